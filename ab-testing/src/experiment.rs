@@ -0,0 +1,179 @@
+//! Sticky assignment of visitors to the variants of an experiment.
+//!
+//! Every visitor gets a random id in a long lived cookie. The variant is a
+//! hash of that id and the experiment name, so it is the same on every
+//! request and every worker without storing anything, and a visitor of two
+//! experiments lands in their buckets independently. The hash has to stay
+//! the same across releases too, it is FNV-1a and not the std `Hasher`,
+//! whose output may change between Rust versions.
+use std::task::{Context, Poll};
+
+use cookie::{Cookie, SameSite};
+use futures::future::{ok, ready, LocalBoxFuture, Ready};
+use ntex::http::{HttpMessage, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, error, Error, ErrorRenderer, FromRequest, HttpRequest};
+use ntex::{Service, Transform};
+
+pub const COOKIE: &str = "visitor_id";
+
+/// An experiment and its variants, with their weights
+pub struct Experiment {
+    name: String,
+    variants: Vec<(String, u32)>,
+    /// the sum of the weights
+    total: u32,
+}
+
+impl Experiment {
+    pub fn new(name: &str) -> Self {
+        Experiment {
+            name: name.to_owned(),
+            variants: Vec::new(),
+            total: 0,
+        }
+    }
+
+    /// Adds a variant, it gets `weight` out of the sum of all weights of
+    /// the visitors
+    pub fn variant(mut self, name: &str, weight: u32) -> Self {
+        self.total = self
+            .total
+            .checked_add(weight)
+            .expect("the weights of an experiment add up to more than u32::MAX");
+        self.variants.push((name.to_owned(), weight));
+        self
+    }
+
+    /// The variant of a visitor, always the same one for the same id
+    pub fn assign(&self, visitor: &str) -> &str {
+        // `AbTesting::new` made sure it isn't 0
+        let mut bucket =
+            (fnv1a(&[&self.name, ":", visitor]) % u64::from(self.total)) as u32;
+        for (name, weight) in &self.variants {
+            if bucket < *weight {
+                return name;
+            }
+            bucket -= weight;
+        }
+        unreachable!("the buckets cover all weights")
+    }
+}
+
+fn fnv1a(parts: &[&str]) -> u64 {
+    parts
+        .iter()
+        .flat_map(|part| part.bytes())
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// The variant of the current visitor, set by `AbTesting`
+#[derive(Clone, Debug)]
+pub struct Variant(pub String);
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Variant {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(req.extensions().get::<Variant>().cloned().ok_or_else(|| {
+            error::ErrorInternalServerError("AbTesting is not enabled").into()
+        }))
+    }
+}
+
+/// Assigns every request to a variant of `experiment`, and gives new
+/// visitors their id cookie
+#[derive(Clone)]
+pub struct AbTesting {
+    experiment: web::types::Data<Experiment>,
+}
+
+impl AbTesting {
+    /// Panics if the weights of `experiment` add up to 0, at startup rather
+    /// than on the first request
+    pub fn new(experiment: web::types::Data<Experiment>) -> Self {
+        assert!(
+            experiment.total > 0,
+            "experiment {} has no variant with a weight",
+            experiment.name
+        );
+        AbTesting { experiment }
+    }
+}
+
+impl<S, Err> Transform<S> for AbTesting
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AbTestingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AbTestingMiddleware {
+            service,
+            experiment: self.experiment.clone(),
+        })
+    }
+}
+
+pub struct AbTestingMiddleware<S> {
+    service: S,
+    experiment: web::types::Data<Experiment>,
+}
+
+impl<S, Err> Service for AbTestingMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let (visitor, new_visitor) = match req.cookie(COOKIE) {
+            Some(cookie) => (cookie.value().to_owned(), false),
+            None => (uuid::Uuid::new_v4().to_string(), true),
+        };
+        let variant = self.experiment.assign(&visitor).to_owned();
+        if new_visitor {
+            log::info!(
+                "visitor {} assigned to `{}` of `{}`",
+                visitor,
+                variant,
+                self.experiment.name
+            );
+        } else {
+            log::debug!("returning visitor {} is in `{}`", visitor, variant);
+        }
+        req.extensions_mut().insert(Variant(variant));
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            if new_visitor {
+                let cookie = Cookie::build(COOKIE, visitor)
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .permanent()
+                    .finish();
+                res.response_mut().add_cookie(&cookie)?;
+            }
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,120 @@
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod experiment;
+
+use experiment::{AbTesting, Experiment, Variant};
+
+/// The same route in two versions, the handler branches on the variant
+async fn pricing(variant: Variant) -> HttpResponse {
+    let body = match variant.0.as_str() {
+        "annual" => serde_json::json!({
+            "variant": variant.0,
+            "headline": "Save 20% with yearly billing",
+            "price": "$96 / year",
+        }),
+        _ => serde_json::json!({
+            "variant": variant.0,
+            "headline": "Simple pricing",
+            "price": "$10 / month",
+        }),
+    };
+    HttpResponse::Ok().json(&body)
+}
+
+fn experiment() -> Experiment {
+    Experiment::new("pricing-page")
+        .variant("control", 70)
+        .variant("annual", 30)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ab_testing=info");
+    env_logger::init();
+
+    let experiment = web::types::Data::new(experiment());
+    // checks the experiment here, not in every worker
+    let ab_testing = AbTesting::new(experiment.clone());
+
+    web::server(move || {
+        App::new()
+            .app_data(experiment.clone())
+            .wrap(ab_testing.clone())
+            .wrap(middleware::Logger::default())
+            .route("/pricing", web::get().to(pricing))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use experiment::COOKIE;
+    use ntex::http::header;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_returning_visitor_keeps_variant() {
+        let experiment = web::types::Data::new(experiment());
+        let app = test::init_service(
+            App::new()
+                .wrap(AbTesting::new(experiment))
+                .route("/pricing", web::get().to(pricing)),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/pricing").to_request();
+        let resp = test::call_service(&app, req).await;
+        let cookie = resp
+            .response()
+            .cookies()
+            .find(|c| c.name() == COOKIE)
+            .unwrap()
+            .into_owned();
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let variant = body["variant"].clone();
+
+        for _ in 0..5 {
+            let req = test::TestRequest::with_uri("/pricing")
+                .cookie(cookie.clone())
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            // no new id for a known visitor
+            assert!(resp.headers().get(header::SET_COOKIE).is_none());
+            let body: serde_json::Value =
+                serde_json::from_slice(&test::read_body(resp).await).unwrap();
+            assert_eq!(body["variant"], variant);
+        }
+    }
+
+    #[test]
+    fn test_distribution() {
+        let experiment = experiment();
+        let visitors = 10_000;
+        let annual = (0..visitors)
+            .filter(|_| experiment.assign(&uuid::Uuid::new_v4().to_string()) == "annual")
+            .count();
+
+        // 30% of the weight, give or take
+        let share = annual as f64 / visitors as f64;
+        assert!((share - 0.3).abs() < 0.03, "annual got {}", share);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no variant with a weight")]
+    fn test_zero_weights() {
+        let experiment = Experiment::new("off").variant("control", 0);
+        AbTesting::new(web::types::Data::new(experiment));
+    }
+
+    #[test]
+    #[should_panic(expected = "more than u32::MAX")]
+    fn test_too_much_weight() {
+        Experiment::new("big")
+            .variant("control", u32::MAX)
+            .variant("annual", 1);
+    }
+}
@@ -0,0 +1,70 @@
+//! Keeps `/work` busy and prints the limit once a second.
+//!
+//! ```bash
+//! cargo run --bin load -- 64
+//! ```
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use ntex::http::client::Client;
+
+const SERVER: &str = "http://127.0.0.1:8080";
+
+#[derive(Default)]
+struct Counts {
+    ok: Cell<u64>,
+    shed: Cell<u64>,
+}
+
+#[ntex::main]
+async fn main() {
+    let clients: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(64);
+    let counts = Rc::new(Counts::default());
+
+    for _ in 0..clients {
+        let counts = counts.clone();
+        ntex::rt::spawn(async move {
+            let client = Client::default();
+            loop {
+                match client.get(format!("{}/work", SERVER)).send().await {
+                    Ok(res) if res.status().is_success() => {
+                        counts.ok.set(counts.ok.get() + 1)
+                    }
+                    Ok(_) => {
+                        counts.shed.set(counts.shed.get() + 1);
+                        // as a well behaved client would, after a 503
+                        ntex::rt::time::delay_for(Duration::from_millis(50)).await;
+                    }
+                    Err(e) => {
+                        eprintln!("is the server running? {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        });
+    }
+
+    let client = Client::default();
+    loop {
+        ntex::rt::time::delay_for(Duration::from_secs(1)).await;
+        let stats: serde_json::Value = client
+            .get(format!("{}/admin/limit", SERVER))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        println!(
+            "ok {:>5}/s  shed {:>5}/s  limit {:>3}  floor {:>6.1}ms",
+            counts.ok.replace(0),
+            counts.shed.replace(0),
+            stats["limit"],
+            stats["floor_ms"].as_f64().unwrap_or_default(),
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! A concurrency limit that follows the latency of what it protects.
+//!
+//! A fixed limit is either too low while the downstream is fast, or far too
+//! high once it slows down and every request waits in its queue. The
+//! limiter estimates the limit from the latency of the requests it lets
+//! through, in the manner of Netflix's gradient limiter:
+//!
+//! * the floor is the lowest recent latency, the downstream without a queue
+//! * samples are averaged over a window as long as the floor, one round
+//!   trip
+//! * every window moves the limit toward `limit * gradient + sqrt(limit)`,
+//!   the gradient is `tolerance * floor / latency` between 0.5 and 1
+//!
+//! While latency stays within `tolerance` times the floor the gradient is 1
+//! and the limit grows by its square root, past that it shrinks, down to
+//! half per window. A downstream that got slower for good would keep the
+//! limit near its bottom, so the floor rises toward the current latency over
+//! `horizon`, and the limit grows again until latency pushes back. Requests
+//! beyond the limit are answered with `503` right away.
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{ok, Either, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, HttpResponse};
+use ntex::{Service, Transform};
+use serde::Serialize;
+
+#[derive(Clone, Copy)]
+pub struct Gradient {
+    pub initial: usize,
+    pub min: usize,
+    pub max: usize,
+    /// How many times the floor latency may get before the limit shrinks
+    pub tolerance: f64,
+    /// How far each window moves the limit toward its estimate
+    pub smoothing: f64,
+    /// How long a slower downstream takes to become the new floor, about.
+    /// Too short, and a queue that builds up slowly passes as one
+    pub horizon: Duration,
+}
+
+impl Default for Gradient {
+    fn default() -> Self {
+        Gradient {
+            initial: 10,
+            min: 2,
+            max: 200,
+            tolerance: 2.0,
+            smoothing: 0.2,
+            horizon: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The samples since the last update
+#[derive(Default)]
+struct Window {
+    started: Option<Instant>,
+    total: Duration,
+    samples: u32,
+    /// The most requests any of them started with
+    in_flight: usize,
+}
+
+struct Estimate {
+    limit: f64,
+    /// Seconds, also how long a window is
+    floor: f64,
+    updated: Option<Instant>,
+    window: Window,
+    /// The limit last logged, changes under 10% aren't
+    logged: usize,
+}
+
+#[derive(Serialize)]
+pub struct Stats {
+    pub limit: usize,
+    pub in_flight: usize,
+    pub floor_ms: f64,
+    pub shed: u64,
+}
+
+pub struct Limiter {
+    config: Gradient,
+    estimate: Mutex<Estimate>,
+    /// The estimate rounded down, read on every request without the lock
+    limit: AtomicUsize,
+    in_flight: AtomicUsize,
+    shed: AtomicU64,
+}
+
+impl Limiter {
+    pub fn new(config: Gradient) -> Self {
+        Limiter {
+            config,
+            estimate: Mutex::new(Estimate {
+                limit: config.initial as f64,
+                floor: 0.0,
+                updated: None,
+                window: Window::default(),
+                logged: config.initial,
+            }),
+            limit: AtomicUsize::new(config.initial),
+            in_flight: AtomicUsize::new(0),
+            shed: AtomicU64::new(0),
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            limit: self.limit(),
+            in_flight: self.in_flight.load(Ordering::Relaxed),
+            floor_ms: self.estimate.lock().unwrap().floor * 1000.0,
+            shed: self.shed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// A slot for one request, or `None` if the limit is reached
+    fn acquire(limiter: &web::types::Data<Limiter>) -> Option<Permit> {
+        let in_flight = limiter.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        let permit = Permit {
+            limiter: limiter.clone(),
+            in_flight,
+            start: Instant::now(),
+        };
+        if in_flight > limiter.limit() {
+            limiter.shed.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        Some(permit)
+    }
+
+    /// Updates the limit from the latency of a request that started with
+    /// `in_flight` requests running, itself included
+    pub fn record(&self, latency: Duration, in_flight: usize) {
+        self.update(latency, in_flight, Instant::now())
+    }
+
+    fn update(&self, latency: Duration, in_flight: usize, now: Instant) {
+        let Gradient {
+            min,
+            max,
+            tolerance,
+            smoothing,
+            horizon,
+            ..
+        } = self.config;
+        let mut estimate = self.estimate.lock().unwrap();
+        let window = &mut estimate.window;
+        window.total += latency;
+        window.samples += 1;
+        window.in_flight = window.in_flight.max(in_flight);
+
+        // a request's latency shows the limit from one round trip ago,
+        // updating every sample would overshoot by a round trip's worth
+        let started = *window.started.get_or_insert(now);
+        let length = Duration::from_secs_f64(estimate.floor);
+        if now.saturating_duration_since(started) < length {
+            return;
+        }
+        let window = std::mem::replace(
+            &mut estimate.window,
+            Window {
+                started: Some(now),
+                ..Window::default()
+            },
+        );
+        let sample = (window.total / window.samples).as_secs_f64().max(1e-6);
+
+        match estimate.updated {
+            Some(_) if sample < estimate.floor => estimate.floor = sample,
+            Some(updated) => {
+                let elapsed = now.saturating_duration_since(updated).as_secs_f64();
+                let rise = (elapsed / horizon.as_secs_f64()).min(1.0);
+                estimate.floor += (sample - estimate.floor) * rise;
+            }
+            None => estimate.floor = sample,
+        }
+        estimate.updated = Some(now);
+        let gradient = (tolerance * estimate.floor / sample).clamp(0.5, 1.0);
+        let target = estimate.limit * gradient + estimate.limit.sqrt();
+        let mut limit = estimate.limit * (1.0 - smoothing) + target * smoothing;
+        // a limit that isn't used says nothing about whether it's too low
+        if (window.in_flight as f64) < estimate.limit / 2.0 {
+            limit = limit.min(estimate.limit);
+        }
+        estimate.limit = limit.clamp(min as f64, max as f64);
+
+        let limit = estimate.limit as usize;
+        self.limit.store(limit, Ordering::Relaxed);
+        let logged = estimate.logged;
+        if limit != logged && (limit * 10 >= logged * 11 || limit * 10 <= logged * 9) {
+            log::info!(
+                "limit {} -> {}, latency {:.1?}, floor {:.1?}",
+                logged,
+                limit,
+                Duration::from_secs_f64(sample),
+                Duration::from_secs_f64(estimate.floor)
+            );
+            estimate.logged = limit;
+        }
+    }
+}
+
+/// Gives the slot back when the request is done, or dropped
+struct Permit {
+    limiter: web::types::Data<Limiter>,
+    in_flight: usize,
+    start: Instant,
+}
+
+impl Permit {
+    fn complete(self) {
+        self.limiter.record(self.start.elapsed(), self.in_flight);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+pub struct AdaptiveLimit {
+    limiter: web::types::Data<Limiter>,
+}
+
+impl AdaptiveLimit {
+    pub fn new(limiter: web::types::Data<Limiter>) -> Self {
+        AdaptiveLimit { limiter }
+    }
+}
+
+impl<S, Err> Transform<S> for AdaptiveLimit
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = AdaptiveLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AdaptiveLimitMiddleware {
+            service,
+            limiter: self.limiter.clone(),
+        })
+    }
+}
+
+pub struct AdaptiveLimitMiddleware<S> {
+    service: S,
+    limiter: web::types::Data<Limiter>,
+}
+
+impl<S, Err> Service for AdaptiveLimitMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type Future = Either<
+        Ready<Result<WebResponse, S::Error>>,
+        LocalBoxFuture<'static, Result<WebResponse, S::Error>>,
+    >;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let permit = match Limiter::acquire(&self.limiter) {
+            Some(permit) => permit,
+            None => {
+                let res = HttpResponse::ServiceUnavailable()
+                    .header(header::RETRY_AFTER, "1")
+                    .json(&serde_json::json!({ "error": "concurrency limit reached" }));
+                return Either::Left(ok(req.into_response(res.into_body())));
+            }
+        };
+        let fut = self.service.call(req);
+        Either::Right(
+            async move {
+                let res = fut.await;
+                // failures are often fast, they would pull the baseline down
+                if res
+                    .as_ref()
+                    .is_ok_and(|res| !res.status().is_server_error())
+                {
+                    permit.complete();
+                }
+                res
+            }
+            .boxed_local(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `windows` requests of `latency_ms`, one window apart, with the limit
+    /// in use as under load
+    fn feed(
+        limiter: &Limiter,
+        now: &mut Instant,
+        latency_ms: u64,
+        windows: usize,
+    ) -> usize {
+        let latency = Duration::from_millis(latency_ms);
+        for _ in 0..windows {
+            *now += latency;
+            limiter.update(latency, limiter.limit(), *now);
+        }
+        limiter.limit()
+    }
+
+    #[test]
+    fn test_limit_follows_latency() {
+        // the floor stays put, see below for a downstream that stays slow
+        let limiter = Limiter::new(Gradient {
+            horizon: Duration::from_secs(3600),
+            ..Gradient::default()
+        });
+        let mut now = Instant::now();
+
+        let fast = feed(&limiter, &mut now, 10, 200);
+        assert_eq!(fast, 200);
+        // within the tolerance it keeps growing
+        assert_eq!(feed(&limiter, &mut now, 18, 50), 200);
+
+        let slow = feed(&limiter, &mut now, 100, 20);
+        assert!(slow < fast / 4, "{} after slowing down", slow);
+        // where halving and the `sqrt(limit)` of queue allowance meet
+        let settled = feed(&limiter, &mut now, 100, 50);
+        assert!(settled <= 5, "{} while slow", settled);
+
+        let recovered = feed(&limiter, &mut now, 10, 50);
+        assert!(recovered > 20, "{} after recovering", recovered);
+        assert_eq!(feed(&limiter, &mut now, 10, 200), 200);
+    }
+
+    #[test]
+    fn test_floor_rises_to_a_slower_downstream() {
+        let limiter = Limiter::new(Gradient::default());
+        let mut now = Instant::now();
+        feed(&limiter, &mut now, 10, 200);
+
+        // a few horizons later 100ms is the new normal
+        let mut lowest = usize::MAX;
+        for _ in 0..2000 {
+            lowest = lowest.min(feed(&limiter, &mut now, 100, 1));
+        }
+        assert!(lowest < 50, "{}", lowest);
+        assert!(limiter.stats().floor_ms > 90.0);
+        assert_eq!(limiter.limit(), 200);
+    }
+
+    #[test]
+    fn test_samples_are_averaged_over_a_window() {
+        let limiter = Limiter::new(Gradient::default());
+        let mut now = Instant::now();
+        assert_eq!(feed(&limiter, &mut now, 10, 200), 200);
+        for _ in 0..5 {
+            now += Duration::from_millis(1);
+            limiter.update(Duration::from_millis(100), limiter.limit(), now);
+        }
+        // still inside the 10ms window
+        assert_eq!(limiter.limit(), 200);
+        now += Duration::from_millis(5);
+        limiter.update(Duration::from_millis(100), limiter.limit(), now);
+        assert_eq!(limiter.limit(), 182);
+    }
+
+    #[test]
+    fn test_unused_limit_only_shrinks() {
+        let limiter = Limiter::new(Gradient::default());
+        let mut now = Instant::now();
+        for _ in 0..100 {
+            now += Duration::from_millis(10);
+            limiter.update(Duration::from_millis(10), 1, now);
+        }
+        assert_eq!(limiter.limit(), Gradient::default().initial);
+
+        now += Duration::from_millis(100);
+        limiter.update(Duration::from_millis(100), 1, now);
+        assert!(limiter.limit() < Gradient::default().initial);
+    }
+}
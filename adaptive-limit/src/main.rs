@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod limiter;
+
+use limiter::{AdaptiveLimit, Gradient, Limiter};
+
+/// Stands in for a database or service with a fixed capacity: beyond
+/// `capacity` concurrent calls they queue, and each takes that much longer
+struct Downstream {
+    latency_ms: AtomicU64,
+    capacity: usize,
+    active: AtomicUsize,
+}
+
+impl Downstream {
+    async fn call(&self) {
+        let active = self.active.fetch_add(1, Ordering::Relaxed) + 1;
+        let queued = (active as f64 / self.capacity as f64).max(1.0);
+        let latency = self.latency_ms.load(Ordering::Relaxed) as f64 * queued;
+        ntex::rt::time::delay_for(Duration::from_millis(latency as u64)).await;
+        self.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+async fn work(downstream: web::types::Data<Downstream>) -> HttpResponse {
+    downstream.call().await;
+    HttpResponse::Ok().finish()
+}
+
+async fn stats(limiter: web::types::Data<Limiter>) -> HttpResponse {
+    HttpResponse::Ok().json(&limiter.stats())
+}
+
+#[derive(Deserialize)]
+struct Latency {
+    latency_ms: u64,
+}
+
+/// Slows the downstream down, or speeds it up
+async fn set_latency(
+    latency: web::types::Json<Latency>,
+    downstream: web::types::Data<Downstream>,
+) -> HttpResponse {
+    downstream
+        .latency_ms
+        .store(latency.latency_ms, Ordering::Relaxed);
+    log::info!("downstream latency is now {}ms", latency.latency_ms);
+    HttpResponse::NoContent().finish()
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,adaptive_limit=info");
+    env_logger::init();
+
+    // one limit for all workers
+    let limiter = web::types::Data::new(Limiter::new(Gradient::default()));
+    let downstream = web::types::Data::new(Downstream {
+        latency_ms: AtomicU64::new(20),
+        capacity: 16,
+        active: AtomicUsize::new(0),
+    });
+
+    web::server(move || {
+        App::new()
+            .app_data(limiter.clone())
+            .app_data(downstream.clone())
+            .wrap(middleware::Logger::default())
+            .service(
+                web::resource("/work")
+                    .wrap(AdaptiveLimit::new(limiter.clone()))
+                    .route(web::get().to(work)),
+            )
+            .route("/admin/limit", web::get().to(stats))
+            .route("/admin/downstream", web::put().to(set_latency))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_requests_beyond_the_limit_are_shed() {
+        let limiter = web::types::Data::new(Limiter::new(Gradient {
+            initial: 3,
+            min: 3,
+            ..Gradient::default()
+        }));
+        let app = test::init_service(
+            App::new().service(
+                web::resource("/slow")
+                    .wrap(AdaptiveLimit::new(limiter.clone()))
+                    .to(|| async {
+                        ntex::rt::time::delay_for(Duration::from_millis(100)).await;
+                        HttpResponse::Ok().finish()
+                    }),
+            ),
+        )
+        .await;
+        let get = || test::TestRequest::get().uri("/slow").to_request();
+
+        let responses = join_all((0..8).map(|_| test::call_service(&app, get()))).await;
+        let ok = responses
+            .iter()
+            .filter(|res| res.status() == StatusCode::OK)
+            .count();
+        assert_eq!(ok, 3);
+        let stats = limiter.stats();
+        assert_eq!((stats.shed, stats.in_flight), (5, 0));
+    }
+}
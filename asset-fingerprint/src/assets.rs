@@ -0,0 +1,181 @@
+//! Static assets under URLs that change with their content.
+//!
+//! At startup every file in the asset directory is read and hashed, and gets
+//! a fingerprinted name with the first 8 hex digits of its sha256:
+//! `app.js` becomes `app.3f2a9c1e.js`. The URL of a file changes whenever
+//! the file does, a response for it never goes stale and can be cached
+//! forever. Templates ask `Assets::url` for the current URL.
+//!
+//! A thread looks at the files every second and rebuilds the map when one
+//! was added, removed or modified. URLs of the old contents are gone then.
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use std::{fs, io, thread};
+
+use sha2::{Digest, Sha256};
+
+/// A file, ready to be served
+pub struct Asset {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+#[derive(Default)]
+struct Manifest {
+    /// `app.js` -> `app.3f2a9c1e.js`
+    names: HashMap<String, String>,
+    /// `app.3f2a9c1e.js` -> the file
+    files: HashMap<String, Arc<Asset>>,
+}
+
+/// What the files looked like when the manifest was built
+type Snapshot = Vec<(PathBuf, SystemTime, u64)>;
+
+pub struct Assets {
+    dir: PathBuf,
+    /// The URL path the assets are served under
+    prefix: &'static str,
+    manifest: RwLock<Manifest>,
+}
+
+impl Assets {
+    pub fn new(dir: impl Into<PathBuf>, prefix: &'static str) -> io::Result<Arc<Self>> {
+        let dir = dir.into();
+        let manifest = build(&dir)?;
+        Ok(Arc::new(Assets {
+            dir,
+            prefix,
+            manifest: RwLock::new(manifest),
+        }))
+    }
+
+    /// The URL of the current version of `name`, a path relative to the
+    /// asset directory
+    pub fn url(&self, name: &str) -> Option<String> {
+        let manifest = self.manifest.read().unwrap();
+        let fingerprinted = manifest.names.get(name)?;
+        Some(format!("{}/{}", self.prefix, fingerprinted))
+    }
+
+    /// The file behind a fingerprinted name. Any other name, old versions
+    /// included, is unknown
+    pub fn get(&self, fingerprinted: &str) -> Option<Arc<Asset>> {
+        self.manifest
+            .read()
+            .unwrap()
+            .files
+            .get(fingerprinted)
+            .cloned()
+    }
+
+    /// Rebuilds the manifest whenever the files change
+    pub fn watch(self: &Arc<Self>, interval: Duration) {
+        let assets = self.clone();
+        thread::spawn(move || {
+            let mut last = snapshot(&assets.dir).unwrap_or_default();
+            loop {
+                thread::sleep(interval);
+                let current = match snapshot(&assets.dir) {
+                    Ok(current) => current,
+                    Err(e) => {
+                        log::error!("can not read {}: {}", assets.dir.display(), e);
+                        continue;
+                    }
+                };
+                if current == last {
+                    continue;
+                }
+                match build(&assets.dir) {
+                    Ok(manifest) => {
+                        let mut names: Vec<_> = manifest.names.values().collect();
+                        names.sort();
+                        log::info!("assets changed, now serving {:?}", names);
+                        *assets.manifest.write().unwrap() = manifest;
+                        last = current;
+                    }
+                    // a file that is being written, the next look will tell
+                    Err(e) => log::warn!("assets changed, not rebuilt: {}", e),
+                }
+            }
+        });
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `img/logo.png` with hash `3f2a9c1e` is `img/logo.3f2a9c1e.png`
+fn fingerprint(name: &str, body: &[u8]) -> String {
+    let hash: String = Sha256::digest(body)[..4]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let file_start = name.rfind('/').map_or(0, |i| i + 1);
+    match name[file_start..].rfind('.') {
+        Some(dot) => {
+            let dot = file_start + dot;
+            format!("{}.{}{}", &name[..dot], hash, &name[dot..])
+        }
+        None => format!("{}.{}", name, hash),
+    }
+}
+
+/// Every file below `dir`, as paths relative to it
+fn files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        for entry in fs::read_dir(&current)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                dirs.push(path);
+            } else {
+                files.push(path.strip_prefix(dir).unwrap().to_path_buf());
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+fn snapshot(dir: &Path) -> io::Result<Snapshot> {
+    files(dir)?
+        .into_iter()
+        .map(|file| {
+            let meta = fs::metadata(dir.join(&file))?;
+            Ok((file, meta.modified()?, meta.len()))
+        })
+        .collect()
+}
+
+fn build(dir: &Path) -> io::Result<Manifest> {
+    let mut manifest = Manifest::default();
+    for file in files(dir)? {
+        // URLs use forward slashes on every platform
+        let name = file
+            .iter()
+            .map(|part| part.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let body = fs::read(dir.join(&file))?;
+        let fingerprinted = fingerprint(&name, &body);
+        let asset = Asset {
+            content_type: content_type(&file),
+            body,
+        };
+        manifest
+            .files
+            .insert(fingerprinted.clone(), Arc::new(asset));
+        manifest.names.insert(name, fingerprinted);
+    }
+    Ok(manifest)
+}
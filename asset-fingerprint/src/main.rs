@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ntex::http::header;
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+use tera::Tera;
+
+mod assets;
+
+use assets::Assets;
+
+/// How often the asset directory is checked for changes
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+async fn index(tmpl: web::types::Data<Tera>) -> Result<HttpResponse, Error> {
+    let body = tmpl
+        .render("index.html", &tera::Context::new())
+        .map_err(|e| error::ErrorInternalServerError(e.to_string()))?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        // the page is what points to the current asset URLs, it must not be
+        // cached itself
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(body))
+}
+
+async fn asset(
+    name: web::types::Path<String>,
+    assets: web::types::Data<Arc<Assets>>,
+) -> HttpResponse {
+    match assets.get(&name) {
+        Some(asset) => HttpResponse::Ok()
+            .content_type(asset.content_type)
+            // the content behind this URL never changes
+            .header(header::CACHE_CONTROL, "public, max-age=31536000, immutable")
+            .body(asset.body.clone()),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+/// `{{ asset_url(name="app.js") }}` in a template
+fn asset_url(assets: Arc<Assets>) -> impl tera::Function {
+    move |args: &HashMap<String, tera::Value>| {
+        let name = args
+            .get("name")
+            .and_then(|name| name.as_str())
+            .ok_or_else(|| tera::Error::msg("asset_url needs a `name`"))?;
+        assets
+            .url(name)
+            .map(tera::Value::from)
+            .ok_or_else(|| tera::Error::msg(format!("there is no asset {}", name)))
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,asset_fingerprint=info");
+    env_logger::init();
+
+    let assets = Assets::new(concat!(env!("CARGO_MANIFEST_DIR"), "/static"), "/static")?;
+    assets.watch(WATCH_INTERVAL);
+
+    web::server(move || {
+        let mut tera =
+            Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*")).unwrap();
+        tera.register_function("asset_url", asset_url(assets.clone()));
+
+        App::new()
+            .data(tera)
+            .data(assets.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/static/{name:.*}", web::get().to(asset))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
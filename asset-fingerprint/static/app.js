@@ -0,0 +1,2 @@
+document.getElementById("message").textContent =
+  "app.js is loaded from " + document.currentScript.src;
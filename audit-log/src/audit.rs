@@ -0,0 +1,183 @@
+//! Audit records for every request that changes something.
+//!
+//! The `Audit` middleware records `POST`, `PUT`, `PATCH` and `DELETE`
+//! requests once they are answered: who made it, what it was, when, and how
+//! it went. The user comes from the `Identity` the authentication left in
+//! the request extensions, requests without one are recorded too, an
+//! unauthorized attempt is worth knowing about.
+//!
+//! Records go to a channel, the response never waits for the disk. A
+//! thread appends them to the log file as json lines, the file is opened
+//! for appending only.
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::task::{Context, Poll};
+use std::thread;
+
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::future::{ok, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use ntex::http::Method;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error};
+use ntex::{Service, Transform};
+use serde::Serialize;
+
+use crate::auth::Identity;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct AuditRecord {
+    pub at: DateTime<Utc>,
+    /// `None` when the request had no valid token
+    pub who: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub remote: Option<String>,
+    pub status: u16,
+    pub outcome: Outcome,
+}
+
+/// Where the middleware sends the records
+pub struct AuditLog(mpsc::UnboundedSender<AuditRecord>);
+
+impl AuditLog {
+    /// The log, and the receiving end for whatever stores the records
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<AuditRecord>) {
+        let (tx, rx) = mpsc::unbounded();
+        (AuditLog(tx), rx)
+    }
+
+    /// Never blocks
+    pub fn record(&self, record: AuditRecord) {
+        if let Err(e) = self.0.unbounded_send(record) {
+            // the writer is gone, the record is all that is left of it
+            log::error!("audit record lost: {:?}", e.into_inner());
+        }
+    }
+}
+
+/// Appends every record to the file at `path`, on a thread of its own
+pub fn write_to_file(
+    mut records: mpsc::UnboundedReceiver<AuditRecord>,
+    path: &str,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    thread::spawn(move || {
+        futures::executor::block_on(async {
+            while let Some(record) = records.next().await {
+                let mut line = serde_json::to_vec(&record).unwrap();
+                line.push(b'\n');
+                // one write per record, a crash can't leave half a line
+                // followed by the next record
+                if let Err(e) = file.write_all(&line).and_then(|_| file.sync_data()) {
+                    log::error!("can not write audit record {:?}: {}", record, e);
+                }
+            }
+        })
+    });
+    Ok(())
+}
+
+/// Records mutating requests in the `AuditLog`
+pub struct Audit {
+    log: web::types::Data<AuditLog>,
+}
+
+impl Audit {
+    pub fn new(log: web::types::Data<AuditLog>) -> Self {
+        Audit { log }
+    }
+}
+
+impl<S, Err> Transform<S> for Audit
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuditMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuditMiddleware {
+            service,
+            log: self.log.clone(),
+        })
+    }
+}
+
+pub struct AuditMiddleware<S> {
+    service: S,
+    log: web::types::Data<AuditLog>,
+}
+
+fn mutating(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::POST | Method::PUT | Method::PATCH | Method::DELETE
+    )
+}
+
+impl<S, Err> Service for AuditMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        if !mutating(req.method()) {
+            return Box::pin(self.service.call(req));
+        }
+        let mut record = AuditRecord {
+            at: Utc::now(),
+            who: None,
+            method: req.method().to_string(),
+            path: req.path().to_owned(),
+            remote: req.peer_addr().map(|addr| addr.ip().to_string()),
+            status: 0,
+            outcome: Outcome::Failure,
+        };
+        let log = self.log.clone();
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let res = fut.await;
+            match &res {
+                Ok(res) => {
+                    // set by the authentication while the request was handled
+                    record.who = res
+                        .request()
+                        .extensions()
+                        .get::<Identity>()
+                        .map(|identity| identity.user.clone());
+                    record.status = res.status().as_u16();
+                    if res.status().is_success() || res.status().is_redirection() {
+                        record.outcome = Outcome::Success;
+                    }
+                }
+                Err(_) => record.status = 500,
+            }
+            log.record(record);
+            res
+        })
+    }
+}
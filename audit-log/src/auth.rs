@@ -0,0 +1,117 @@
+//! Who is making the request, from a bearer token.
+//!
+//! The middleware only identifies the user, it rejects nothing. Handlers
+//! that need a user take `Identity`, which answers `401` without one, so a
+//! request without a valid token still reaches the audit log.
+use std::collections::HashMap;
+use std::task::{Context, Poll};
+
+use derive_more::Display;
+use futures::future::{err, ok, Ready};
+use ntex::http::{header, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    Error, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+
+#[derive(Clone, Debug)]
+pub struct Identity {
+    pub user: String,
+    pub admin: bool,
+}
+
+/// Tokens and the users they belong to
+pub struct Authenticate(HashMap<&'static str, Identity>);
+
+impl Authenticate {
+    pub fn example() -> Self {
+        let mut tokens = HashMap::new();
+        let alice = Identity {
+            user: "alice".to_owned(),
+            admin: true,
+        };
+        let bob = Identity {
+            user: "bob".to_owned(),
+            admin: false,
+        };
+        tokens.insert("alice-token", alice);
+        tokens.insert("bob-token", bob);
+        Authenticate(tokens)
+    }
+}
+
+impl<S, Err> Transform<S> for Authenticate
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthenticateMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthenticateMiddleware {
+            service,
+            tokens: self.0.clone(),
+        })
+    }
+}
+
+pub struct AuthenticateMiddleware<S> {
+    service: S,
+    tokens: HashMap<&'static str, Identity>,
+}
+
+impl<S, Err> Service for AuthenticateMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = S::Future;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let identity = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .and_then(|token| self.tokens.get(token))
+            .cloned();
+        if let Some(identity) = identity {
+            req.extensions_mut().insert(identity);
+        }
+        self.service.call(req)
+    }
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "a valid bearer token is required")]
+pub struct Unauthorized;
+
+impl WebResponseError for Unauthorized {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::Unauthorized()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Identity {
+    type Error = Unauthorized;
+    type Future = Ready<Result<Self, Unauthorized>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Identity>() {
+            Some(identity) => ok(identity.clone()),
+            None => err(Unauthorized),
+        }
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use derive_more::Display;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use serde::{Deserialize, Serialize};
+
+mod audit;
+mod auth;
+
+use audit::{Audit, AuditLog};
+use auth::{Authenticate, Identity};
+
+#[derive(Clone, Serialize)]
+struct Document {
+    id: u64,
+    title: String,
+    owner: String,
+}
+
+#[derive(Default)]
+struct Documents {
+    docs: Mutex<HashMap<u64, Document>>,
+    ids: AtomicU64,
+}
+
+#[derive(Debug, Display)]
+enum DocError {
+    #[display(fmt = "document not found")]
+    NotFound,
+    #[display(fmt = "only the owner or an admin can do that")]
+    Forbidden,
+}
+
+impl WebResponseError for DocError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = serde_json::json!({ "error": self.to_string() });
+        match self {
+            DocError::NotFound => HttpResponse::NotFound().json(&body),
+            DocError::Forbidden => HttpResponse::Forbidden().json(&body),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct NewDocument {
+    title: String,
+}
+
+async fn list(docs: web::types::Data<Documents>) -> HttpResponse {
+    let docs: Vec<_> = docs.docs.lock().unwrap().values().cloned().collect();
+    HttpResponse::Ok().json(&docs)
+}
+
+async fn create(
+    identity: Identity,
+    new: web::types::Json<NewDocument>,
+    docs: web::types::Data<Documents>,
+) -> HttpResponse {
+    let doc = Document {
+        id: docs.ids.fetch_add(1, Ordering::Relaxed) + 1,
+        title: new.into_inner().title,
+        owner: identity.user,
+    };
+    docs.docs.lock().unwrap().insert(doc.id, doc.clone());
+    HttpResponse::Created().json(&doc)
+}
+
+async fn rename(
+    identity: Identity,
+    id: web::types::Path<u64>,
+    new: web::types::Json<NewDocument>,
+    docs: web::types::Data<Documents>,
+) -> Result<HttpResponse, DocError> {
+    let mut docs = docs.docs.lock().unwrap();
+    let doc = docs.get_mut(&id).ok_or(DocError::NotFound)?;
+    if doc.owner != identity.user && !identity.admin {
+        return Err(DocError::Forbidden);
+    }
+    doc.title = new.into_inner().title;
+    Ok(HttpResponse::Ok().json(&*doc))
+}
+
+async fn delete(
+    identity: Identity,
+    id: web::types::Path<u64>,
+    docs: web::types::Data<Documents>,
+) -> Result<HttpResponse, DocError> {
+    let mut docs = docs.docs.lock().unwrap();
+    let doc = docs.get(&id).ok_or(DocError::NotFound)?;
+    if doc.owner != identity.user && !identity.admin {
+        return Err(DocError::Forbidden);
+    }
+    docs.remove(&id);
+    Ok(HttpResponse::NoContent().finish())
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .service(
+            web::resource("/documents")
+                .route(web::get().to(list))
+                .route(web::post().to(create)),
+        )
+        .service(
+            web::resource("/documents/{id}")
+                .route(web::put().to(rename))
+                .route(web::delete().to(delete)),
+        );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,audit_log=info");
+    env_logger::init();
+
+    let (audit_log, records) = AuditLog::new();
+    audit::write_to_file(records, "audit.log")?;
+    let audit_log = web::types::Data::new(audit_log);
+    let docs = web::types::Data::new(Documents::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(docs.clone())
+            // the audit sees the response of everything inside it, the
+            // authentication's `Identity` included
+            .wrap(Authenticate::example())
+            .wrap(Audit::new(audit_log.clone()))
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audit::Outcome;
+    use ntex::http::{header, StatusCode};
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_mutations_are_audited() {
+        let (audit_log, mut records) = AuditLog::new();
+        let app = test::init_service(
+            App::new()
+                .app_data(web::types::Data::new(Documents::default()))
+                .wrap(Authenticate::example())
+                .wrap(Audit::new(web::types::Data::new(audit_log)))
+                .configure(app_config),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/documents")
+            .header(header::AUTHORIZATION, "Bearer bob-token")
+            .set_json(&serde_json::json!({ "title": "plans" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let record = records.try_recv().unwrap();
+        assert_eq!(record.who.as_deref(), Some("bob"));
+        assert_eq!(record.method, "POST");
+        assert_eq!(record.path, "/documents");
+        assert_eq!(record.status, 201);
+        assert_eq!(record.outcome, Outcome::Success);
+
+        // reads are not audited
+        let req = test::TestRequest::with_uri("/documents").to_request();
+        test::call_service(&app, req).await;
+        assert!(records.try_recv().is_err());
+
+        // failures are recorded too, also without a user
+        let req = test::TestRequest::delete().uri("/documents/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        let record = records.try_recv().unwrap();
+        assert_eq!(record.who, None);
+        assert_eq!(record.status, 401);
+        assert_eq!(record.outcome, Outcome::Failure);
+    }
+}
@@ -0,0 +1,227 @@
+//! Bearer tokens signed with HS256.
+//!
+//! `JwtAuth` checks the signature and the expiry of the token before the
+//! request gets to a handler, a request without a valid one is answered
+//! with `401` and a `WWW-Authenticate` challenge. The claims of a valid
+//! token are put in the request's extensions, handlers take them as a
+//! `Claims` argument.
+//!
+//! Only HS256 is accepted, whatever the token's header says, a token can't
+//! pick the algorithm it is checked with.
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use derive_more::Display;
+use futures::future::{err, ok, FutureExt, LocalBoxFuture, Ready};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use ntex::http::{header, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    self, Error, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Claims {
+    pub sub: String,
+    pub role: Role,
+    /// Seconds since the epoch
+    pub iat: u64,
+    pub exp: u64,
+}
+
+impl Claims {
+    /// `403` for other roles
+    pub fn require(&self, role: Role) -> Result<(), AuthError> {
+        if self.role == role {
+            Ok(())
+        } else {
+            Err(AuthError::Forbidden)
+        }
+    }
+}
+
+#[derive(Debug, Display)]
+pub enum AuthError {
+    #[display(fmt = "the token doesn't allow this")]
+    Forbidden,
+    #[display(fmt = "the request has no claims, JwtAuth is missing")]
+    NoClaims,
+}
+
+impl WebResponseError for AuthError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            AuthError::Forbidden => HttpResponse::Forbidden(),
+            AuthError::NoClaims => HttpResponse::InternalServerError(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Claims {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, AuthError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Claims>() {
+            Some(claims) => ok(claims.clone()),
+            None => err(AuthError::NoClaims),
+        }
+    }
+}
+
+/// The signing key, shared by all workers
+pub struct JwtKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey<'static>,
+    validation: Validation,
+    ttl: Duration,
+}
+
+impl JwtKeys {
+    /// Tokens from `mint` are valid for `ttl`
+    pub fn new(secret: &[u8], ttl: Duration) -> Self {
+        JwtKeys {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret).into_static(),
+            validation: Validation::new(Algorithm::HS256),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn mint(&self, sub: &str, role: Role) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let claims = Claims {
+            sub: sub.to_owned(),
+            role,
+            iat: now,
+            exp: now + self.ttl.as_secs(),
+        };
+        jsonwebtoken::encode(&Header::new(Algorithm::HS256), &claims, &self.encoding)
+            .expect("HS256 signing can't fail")
+    }
+
+    fn verify(&self, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+        jsonwebtoken::decode::<Claims>(token, &self.decoding, &self.validation)
+            .map(|data| data.claims)
+    }
+}
+
+pub struct JwtAuth {
+    keys: web::types::Data<JwtKeys>,
+}
+
+impl JwtAuth {
+    pub fn new(keys: web::types::Data<JwtKeys>) -> Self {
+        JwtAuth { keys }
+    }
+}
+
+impl<S, Err> Transform<S> for JwtAuth
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = JwtAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(JwtAuthMiddleware {
+            service: Rc::new(service),
+            keys: self.keys.clone(),
+        })
+    }
+}
+
+pub struct JwtAuthMiddleware<S> {
+    service: Rc<S>,
+    keys: web::types::Data<JwtKeys>,
+}
+
+impl<S, Err> Service for JwtAuthMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let token = match bearer_token(&req) {
+            Some(token) => token,
+            None => return ok(req.into_response(unauthorized(None))).boxed_local(),
+        };
+        let claims = match self.keys.verify(&token) {
+            Ok(claims) => claims,
+            Err(e) => {
+                log::info!("{} {}: rejected token, {}", req.method(), req.path(), e);
+                // the details are for the log, not for the client
+                let description = match e.kind() {
+                    ErrorKind::ExpiredSignature => "the token has expired",
+                    _ => "the token is not valid",
+                };
+                let res = unauthorized(Some(description));
+                return ok(req.into_response(res)).boxed_local();
+            }
+        };
+
+        req.extensions_mut().insert(claims);
+        self.service.call(req).boxed_local()
+    }
+}
+
+fn bearer_token<Err>(req: &WebRequest<Err>) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            Some(token.trim().to_owned())
+        }
+        _ => None,
+    }
+}
+
+/// Without a token only the scheme, RFC 6750
+fn unauthorized(description: Option<&'static str>) -> ntex::http::Response {
+    let challenge = match description {
+        Some(description) => format!(
+            "Bearer error=\"invalid_token\", error_description=\"{}\"",
+            description
+        ),
+        None => "Bearer".to_owned(),
+    };
+    HttpResponse::Unauthorized()
+        .header(header::WWW_AUTHENTICATE, challenge)
+        .finish()
+        .into_body()
+}
@@ -0,0 +1,93 @@
+//! `POST /login` mints a token, everything under `/api` needs one, see
+//! `jwt`. `JWT_SECRET` sets the signing key, without it a random one is
+//! made at startup.
+use std::time::Duration;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use rand::Rng;
+use serde::Deserialize;
+
+mod jwt;
+
+use jwt::{AuthError, Claims, JwtAuth, JwtKeys, Role};
+
+/// How long a token is valid
+const TOKEN_TTL: Duration = Duration::from_secs(15 * 60);
+
+/// The demo's accounts, a real one would check a password hash
+const USERS: &[(&str, &str, Role)] = &[
+    ("alice", "wonderland", Role::User),
+    ("root", "hunter2", Role::Admin),
+];
+
+#[derive(Deserialize)]
+struct Login {
+    username: String,
+    password: String,
+}
+
+async fn login(
+    login: web::types::Json<Login>,
+    keys: web::types::Data<JwtKeys>,
+) -> HttpResponse {
+    let user = USERS.iter().find(|(name, password, _)| {
+        *name == login.username && *password == login.password
+    });
+    let role = match user {
+        Some((_, _, role)) => *role,
+        None => {
+            log::info!("failed login for {}", login.username);
+            return HttpResponse::Unauthorized()
+                .json(&serde_json::json!({ "error": "wrong username or password" }));
+        }
+    };
+
+    HttpResponse::Ok().json(&serde_json::json!({
+        "access_token": keys.mint(&login.username, role),
+        "token_type": "Bearer",
+        "expires_in": keys.ttl().as_secs(),
+    }))
+}
+
+async fn me(claims: Claims) -> HttpResponse {
+    HttpResponse::Ok().json(&claims)
+}
+
+async fn admin(claims: Claims) -> Result<HttpResponse, AuthError> {
+    claims.require(Role::Admin)?;
+    Ok(HttpResponse::Ok().json(&serde_json::json!({
+        "users": USERS.len(),
+        "requested_by": claims.sub,
+    })))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,auth_jwt=info");
+    env_logger::init();
+
+    let secret = match std::env::var("JWT_SECRET") {
+        Ok(secret) => secret.into_bytes(),
+        Err(_) => {
+            log::warn!("JWT_SECRET is not set, tokens are only valid until a restart");
+            rand::thread_rng().gen::<[u8; 32]>().to_vec()
+        }
+    };
+    let keys = web::types::Data::new(JwtKeys::new(&secret, TOKEN_TTL));
+
+    web::server(move || {
+        App::new()
+            .app_data(keys.clone())
+            .wrap(middleware::Logger::default())
+            .route("/login", web::post().to(login))
+            .service(
+                web::scope("/api")
+                    .wrap(JwtAuth::new(keys.clone()))
+                    .route("/me", web::get().to(me))
+                    .route("/admin", web::get().to(admin)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
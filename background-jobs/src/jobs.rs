@@ -0,0 +1,129 @@
+//! The job store, shared by the workers' apps and the background tasks.
+//!
+//! Handlers add jobs and read their status, the runner in `main` takes
+//! their ids from the queue and reports back. Everything is behind one
+//! `Mutex` that is never held across an `.await`.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Task {
+    pub name: String,
+    /// How long the job pretends to work
+    pub seconds: u64,
+    /// Makes the job fail, to see how that looks
+    #[serde(default)]
+    pub fail: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "state")]
+pub enum Status {
+    Queued,
+    Running,
+    Done { result: String },
+    Failed { error: String },
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Job {
+    pub id: u64,
+    pub task: Task,
+    #[serde(flatten)]
+    pub status: Status,
+    /// When the job was done or failed, nothing in the JSON
+    #[serde(skip)]
+    finished: Option<Instant>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: u64,
+    jobs: HashMap<u64, Job>,
+}
+
+pub struct Jobs {
+    state: Mutex<State>,
+    queue: mpsc::UnboundedSender<u64>,
+}
+
+impl Jobs {
+    /// The store, and the receiving end of its queue for the runner
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<u64>) {
+        let (queue, rx) = mpsc::unbounded();
+        let jobs = Jobs {
+            state: Mutex::new(State::default()),
+            queue,
+        };
+        (jobs, rx)
+    }
+
+    pub fn enqueue(&self, task: Task) -> Job {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let job = Job {
+            id: state.next_id,
+            task,
+            status: Status::Queued,
+            finished: None,
+        };
+        state.jobs.insert(job.id, job.clone());
+        // the receiver lives as long as the process
+        let _ = self.queue.unbounded_send(job.id);
+        job
+    }
+
+    pub fn get(&self, id: u64) -> Option<Job> {
+        self.state.lock().unwrap().jobs.get(&id).cloned()
+    }
+
+    /// The task of a job, and marks it running
+    pub fn start(&self, id: u64) -> Option<Task> {
+        let mut state = self.state.lock().unwrap();
+        let job = state.jobs.get_mut(&id)?;
+        job.status = Status::Running;
+        Some(job.task.clone())
+    }
+
+    pub fn finish(&self, id: u64, result: Result<String, String>) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(job) = state.jobs.get_mut(&id) {
+            job.status = match result {
+                Ok(result) => Status::Done { result },
+                Err(error) => Status::Failed { error },
+            };
+            job.finished = Some(Instant::now());
+        }
+    }
+
+    /// Number of jobs in each state
+    pub fn counts(&self) -> HashMap<&'static str, usize> {
+        let state = self.state.lock().unwrap();
+        let mut counts = HashMap::new();
+        for job in state.jobs.values() {
+            let name = match job.status {
+                Status::Queued => "queued",
+                Status::Running => "running",
+                Status::Done { .. } => "done",
+                Status::Failed { .. } => "failed",
+            };
+            *counts.entry(name).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    /// Drops the jobs that finished more than `retention` ago, returns how
+    /// many
+    pub fn compact(&self, retention: Duration) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let before = state.jobs.len();
+        state
+            .jobs
+            .retain(|_, job| job.finished.is_none_or(|at| at.elapsed() <= retention));
+        before - state.jobs.len()
+    }
+}
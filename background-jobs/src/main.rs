@@ -0,0 +1,117 @@
+//! Background tasks next to the web server, sharing a store with it.
+//!
+//! `main` creates the `Jobs` store and spawns two tasks on its own runtime
+//! before it starts the server: the runner works off the queued jobs, two
+//! at a time, and the compactor drops finished jobs every 10 seconds. The
+//! workers run their apps on threads of their own, they all get the same
+//! store in `Data` and only enqueue and read jobs, the tasks keep running
+//! no matter which worker a request went to.
+use std::time::Duration;
+
+use futures::StreamExt;
+use ntex::rt::time::{delay_for, interval};
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod jobs;
+
+use jobs::{Jobs, Task};
+
+/// Jobs run at the same time
+const CONCURRENCY: usize = 2;
+/// How long finished jobs can be looked up
+const RETENTION: Duration = Duration::from_secs(60);
+/// Longest a job can take, it holds one of the `CONCURRENCY` slots
+const MAX_SECONDS: u64 = 3600;
+
+async fn enqueue(
+    task: web::types::Json<Task>,
+    jobs: web::types::Data<Jobs>,
+) -> HttpResponse {
+    if task.seconds > MAX_SECONDS {
+        return HttpResponse::UnprocessableEntity().json(&serde_json::json!({
+            "error": format!("seconds must be at most {}", MAX_SECONDS)
+        }));
+    }
+    let job = jobs.enqueue(task.into_inner());
+    HttpResponse::Accepted()
+        .header("location", format!("/jobs/{}", job.id))
+        .json(&job)
+}
+
+async fn status(
+    id: web::types::Path<u64>,
+    jobs: web::types::Data<Jobs>,
+) -> HttpResponse {
+    match jobs.get(*id) {
+        Some(job) => HttpResponse::Ok().json(&job),
+        None => HttpResponse::NotFound()
+            .json(&serde_json::json!({ "error": format!("no job {}", id) })),
+    }
+}
+
+async fn stats(jobs: web::types::Data<Jobs>) -> HttpResponse {
+    HttpResponse::Ok().json(&jobs.counts())
+}
+
+/// Stands in for real work, an upload to process, a report to build
+async fn run(task: Task) -> Result<String, String> {
+    delay_for(Duration::from_secs(task.seconds)).await;
+    if task.fail {
+        Err(format!("{} failed after {}s", task.name, task.seconds))
+    } else {
+        Ok(format!("{} done in {}s", task.name, task.seconds))
+    }
+}
+
+async fn runner(
+    jobs: web::types::Data<Jobs>,
+    queue: futures::channel::mpsc::UnboundedReceiver<u64>,
+) {
+    queue
+        .for_each_concurrent(CONCURRENCY, |id| {
+            let jobs = jobs.clone();
+            async move {
+                if let Some(task) = jobs.start(id) {
+                    log::info!("job {} started: {}", id, task.name);
+                    let result = run(task).await;
+                    log::info!("job {} finished: {:?}", id, result);
+                    jobs.finish(id, result);
+                }
+            }
+        })
+        .await
+}
+
+async fn compactor(jobs: web::types::Data<Jobs>) {
+    let mut interval = interval(Duration::from_secs(10));
+    loop {
+        interval.tick().await;
+        let removed = jobs.compact(RETENTION);
+        if removed > 0 {
+            log::info!("compacted {} finished jobs", removed);
+        }
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,background_jobs=info");
+    env_logger::init();
+
+    let (jobs, queue) = Jobs::new();
+    let jobs = web::types::Data::new(jobs);
+    ntex::rt::spawn(runner(jobs.clone(), queue));
+    ntex::rt::spawn(compactor(jobs.clone()));
+
+    web::server(move || {
+        App::new()
+            .app_data(jobs.clone())
+            .wrap(middleware::Logger::default())
+            .route("/jobs", web::post().to(enqueue))
+            .route("/jobs", web::get().to(stats))
+            .route("/jobs/{id}", web::get().to(status))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,178 @@
+//! Work goes through a bounded queue, see `queue`, and requests that find
+//! it full are answered with `503` right away.
+use std::time::Duration;
+
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+
+mod queue;
+
+use queue::{QueueError, WorkQueue};
+
+/// Defaults for `QUEUE_BOUND`, `WORKERS` and `JOB_MS`
+const QUEUE_BOUND: usize = 16;
+const WORKERS: usize = 2;
+const JOB_MS: u64 = 200;
+/// The sum of squares up to here still fits into a `u64`
+const MAX_N: u64 = 1_000_000;
+
+impl WebResponseError for QueueError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = serde_json::json!({ "error": self.to_string() });
+        match self {
+            QueueError::Full(_) => HttpResponse::ServiceUnavailable()
+                .header(header::RETRY_AFTER, "1")
+                .json(&body),
+            QueueError::Closed | QueueError::Panicked => {
+                HttpResponse::InternalServerError().json(&body)
+            }
+        }
+    }
+}
+
+struct JobTime(Duration);
+
+/// Something slow, done by a worker thread
+async fn work(
+    n: web::types::Path<u64>,
+    queue: web::types::Data<WorkQueue>,
+    job_time: web::types::Data<JobTime>,
+) -> Result<HttpResponse, QueueError> {
+    let (n, job_time) = (*n, job_time.0);
+    if n > MAX_N {
+        return Ok(HttpResponse::BadRequest().json(&serde_json::json!({
+            "error": format!("n must be at most {}", MAX_N)
+        })));
+    }
+    let done = queue
+        .submit(Box::new(move || {
+            std::thread::sleep(job_time);
+            (1..=n).map(|i| i * i).sum()
+        }))
+        .await
+        .map_err(|e| {
+            log::warn!("shedding /work/{}: {}", n, e);
+            e
+        })?;
+    Ok(HttpResponse::Ok().json(&serde_json::json!({
+        "sum_of_squares": done.result,
+        "queued_ms": done.waited.as_millis() as u64,
+    })))
+}
+
+async fn stats(queue: web::types::Data<WorkQueue>) -> HttpResponse {
+    HttpResponse::Ok().json(&queue.stats())
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/work/{n}", web::get().to(work))
+        .route("/stats", web::get().to(stats));
+}
+
+fn env<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,backpressure=info");
+    env_logger::init();
+
+    let bound = env("QUEUE_BOUND", QUEUE_BOUND);
+    let workers = env("WORKERS", WORKERS);
+    let job_time = Duration::from_millis(env("JOB_MS", JOB_MS));
+    log::info!(
+        "queue of {} jobs, {} workers, {:?} per job",
+        bound,
+        workers,
+        job_time
+    );
+    let queue = web::types::Data::new(WorkQueue::start(bound, workers));
+    let job_time = web::types::Data::new(JobTime(job_time));
+
+    web::server(move || {
+        App::new()
+            .app_data(queue.clone())
+            .app_data(job_time.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_saturated_queue_sheds() {
+        let queue = web::types::Data::new(WorkQueue::start(2, 1));
+        let app = test::init_service(
+            App::new()
+                .app_data(queue.clone())
+                .data(JobTime(Duration::from_millis(200)))
+                .configure(app),
+        )
+        .await;
+        let get = || test::TestRequest::get().uri("/work/3").to_request();
+
+        let responses = join_all((0..10).map(|_| test::call_service(&app, get()))).await;
+        let ok = responses
+            .iter()
+            .filter(|res| res.status() == StatusCode::OK)
+            .count();
+        let shed: Vec<_> = responses
+            .iter()
+            .filter(|res| res.status() == StatusCode::SERVICE_UNAVAILABLE)
+            .collect();
+        // two queued, and one more if the worker took the first in time
+        assert!(ok == 2 || ok == 3, "{} accepted", ok);
+        assert_eq!(ok + shed.len(), 10);
+        assert_eq!(shed[0].headers().get(header::RETRY_AFTER).unwrap(), "1");
+
+        let stats = queue.stats();
+        assert_eq!(
+            (stats.accepted, stats.shed, stats.queued),
+            (ok as u64, 10 - ok as u64, 0)
+        );
+
+        // drained, there is room again
+        let res = test::call_service(&app, get()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[ntex::test]
+    async fn test_panicking_job() {
+        let queue = WorkQueue::start(2, 1);
+        let res = queue.submit(Box::new(|| panic!("job failed"))).await;
+        assert!(matches!(res, Err(QueueError::Panicked)));
+
+        // the only worker is still there
+        let done = queue.submit(Box::new(|| 42)).await.unwrap();
+        assert_eq!(done.result, 42);
+    }
+
+    #[ntex::test]
+    async fn test_n_is_bounded() {
+        let app = test::init_service(
+            App::new()
+                .data(WorkQueue::start(2, 1))
+                .data(JobTime(Duration::from_millis(0)))
+                .configure(app),
+        )
+        .await;
+        let req = test::TestRequest::get()
+            .uri(&format!("/work/{}", MAX_N + 1))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,145 @@
+//! A bounded work queue, drained by a few worker threads.
+//!
+//! `submit` never waits for room: when the queue is full the job is
+//! refused right away, the caller sheds the request. Overload then costs a
+//! quick `503` instead of memory for a queue that only grows, and clients
+//! learn about it while they can still go elsewhere.
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use derive_more::Display;
+use futures::channel::oneshot;
+use serde::Serialize;
+
+pub type Job = Box<dyn FnOnce() -> u64 + Send>;
+
+#[derive(Debug, Display)]
+pub enum QueueError {
+    #[display(fmt = "work queue is full ({} jobs)", _0)]
+    Full(usize),
+    #[display(fmt = "the workers are gone")]
+    Closed,
+    #[display(fmt = "the job failed")]
+    Panicked,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub capacity: usize,
+    /// Waiting for a worker
+    pub queued: usize,
+    pub accepted: u64,
+    pub shed: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    accepted: AtomicU64,
+    shed: AtomicU64,
+}
+
+struct Task {
+    job: Job,
+    /// `None` if the job panicked
+    done: oneshot::Sender<Option<Done>>,
+    queued_at: Instant,
+}
+
+pub struct Done {
+    pub result: u64,
+    /// Time spent in the queue
+    pub waited: Duration,
+}
+
+pub struct WorkQueue {
+    tx: SyncSender<Task>,
+    capacity: usize,
+    counters: Arc<Counters>,
+}
+
+impl WorkQueue {
+    /// Starts `workers` threads taking jobs from a queue of `capacity`
+    pub fn start(capacity: usize, workers: usize) -> Self {
+        let (tx, rx) = mpsc::sync_channel::<Task>(capacity);
+        let rx = Arc::new(Mutex::new(rx));
+        let counters = Arc::new(Counters::default());
+
+        for n in 0..workers {
+            let rx = rx.clone();
+            let counters = counters.clone();
+            thread::Builder::new()
+                .name(format!("worker-{}", n))
+                .spawn(move || loop {
+                    let task = match rx.lock().unwrap().recv() {
+                        Ok(task) => task,
+                        Err(_) => return,
+                    };
+                    counters.queued.fetch_sub(1, Ordering::Relaxed);
+                    let waited = task.queued_at.elapsed();
+                    // a panicking job fails its own request, the worker
+                    // goes on with the next one
+                    let done = match panic::catch_unwind(AssertUnwindSafe(task.job)) {
+                        Ok(result) => Some(Done { result, waited }),
+                        Err(_) => {
+                            log::error!("a job panicked on worker-{}", n);
+                            None
+                        }
+                    };
+                    // the request may have gone away in the meantime
+                    let _ = task.done.send(done);
+                })
+                .unwrap();
+        }
+        WorkQueue {
+            tx,
+            capacity,
+            counters,
+        }
+    }
+
+    /// Queues `job` if there is room, and waits for it to be done
+    pub async fn submit(&self, job: Job) -> Result<Done, QueueError> {
+        let (done, result) = oneshot::channel();
+        let task = Task {
+            job,
+            done,
+            queued_at: Instant::now(),
+        };
+        // counted before it is sent, a worker may take it right away
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        match self.tx.try_send(task) {
+            Ok(()) => {
+                self.counters.accepted.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+                return Err(match e {
+                    TrySendError::Full(_) => {
+                        self.counters.shed.fetch_add(1, Ordering::Relaxed);
+                        QueueError::Full(self.capacity)
+                    }
+                    TrySendError::Disconnected(_) => QueueError::Closed,
+                });
+            }
+        }
+        match result.await {
+            Ok(Some(done)) => Ok(done),
+            Ok(None) => Err(QueueError::Panicked),
+            Err(_) => Err(QueueError::Closed),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            capacity: self.capacity,
+            queued: self.counters.queued.load(Ordering::Relaxed),
+            accepted: self.counters.accepted.load(Ordering::Relaxed),
+            shed: self.counters.shed.load(Ordering::Relaxed),
+        }
+    }
+}
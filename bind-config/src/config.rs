@@ -0,0 +1,63 @@
+//! Where to listen, from the environment:
+//!
+//! * `BIND_ADDRS`, comma separated socket addresses, `0.0.0.0:8080,[::]:8080`
+//!   when unset
+//! * `BIND_UNIX`, the path of a unix socket to listen on as well
+//! * `BIND_REQUIRE_ALL`, `true` to refuse to start when any address can't be
+//!   bound. By default the server starts on the ones that can, as long as
+//!   there is one
+//!
+//! A value that doesn't parse is an error, not a reason to fall back to the
+//! default: a typo would otherwise go unnoticed.
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use derive_more::Display;
+
+const DEFAULT_ADDRS: &str = "0.0.0.0:8080,[::]:8080";
+
+#[derive(Debug, Display)]
+pub enum ConfigError {
+    #[display(fmt = "BIND_ADDRS: `{}` is not a socket address", _0)]
+    Addr(String),
+    #[display(fmt = "BIND_ADDRS: no addresses")]
+    NoAddrs,
+    #[display(fmt = "BIND_REQUIRE_ALL: `{}` is neither true nor false", _0)]
+    RequireAll(String),
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug)]
+pub struct BindConfig {
+    pub tcp: Vec<SocketAddr>,
+    pub unix: Option<PathBuf>,
+    pub require_all: bool,
+}
+
+impl BindConfig {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        let addrs =
+            std::env::var("BIND_ADDRS").unwrap_or_else(|_| DEFAULT_ADDRS.to_owned());
+        let tcp = addrs
+            .split(',')
+            .map(str::trim)
+            .filter(|addr| !addr.is_empty())
+            .map(|addr| addr.parse().map_err(|_| ConfigError::Addr(addr.to_owned())))
+            .collect::<Result<Vec<_>, _>>()?;
+        let unix = std::env::var_os("BIND_UNIX").map(PathBuf::from);
+        if tcp.is_empty() && unix.is_none() {
+            return Err(ConfigError::NoAddrs);
+        }
+
+        let require_all = match std::env::var("BIND_REQUIRE_ALL") {
+            Err(_) => false,
+            Ok(value) => value.parse().map_err(|_| ConfigError::RequireAll(value))?,
+        };
+        Ok(BindConfig {
+            tcp,
+            unix,
+            require_all,
+        })
+    }
+}
@@ -0,0 +1,118 @@
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use socket2::{Domain, Socket, Type};
+
+mod config;
+
+use config::BindConfig;
+
+const BACKLOG: i32 = 1024;
+
+/// Like `bind`, but an IPv6 socket only takes IPv6 connections. By default
+/// `[::]:8080` takes IPv4 ones as well, and `0.0.0.0:8080` next to it fails
+/// with "address in use"
+fn tcp_listener(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+fn unix_listener(path: &Path) -> io::Result<UnixListener> {
+    // left behind by an earlier run, nobody listens on it anymore
+    if path.exists() && std::os::unix::net::UnixStream::connect(path).is_err() {
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// The listener is told by the address it is bound to. Unix socket
+/// connections have no peer address, ntex gives them a made up local one
+async fn index(req: HttpRequest) -> HttpResponse {
+    let (listener, peer) = match req.peer_addr() {
+        Some(peer) => (
+            format!("tcp {}", req.app_config().local_addr()),
+            Some(peer.to_string()),
+        ),
+        None => ("unix".to_owned(), None),
+    };
+    HttpResponse::Ok().json(&serde_json::json!({ "listener": listener, "peer": peer }))
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,bind_config=info");
+    env_logger::init();
+
+    let config = BindConfig::from_env()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    let mut server = web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+    });
+    let mut listening = Vec::new();
+    let mut failed = Vec::new();
+
+    for addr in &config.tcp {
+        let name = format!("tcp  {}", addr);
+        match tcp_listener(*addr) {
+            Ok(lst) => {
+                log::info!("bound {}", name);
+                server = server.listen(lst)?;
+                listening.push(name);
+            }
+            Err(e) => {
+                log::error!("can not bind {}: {}", name, e);
+                failed.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+    if let Some(path) = &config.unix {
+        let name = format!("unix {}", path.display());
+        match unix_listener(path) {
+            Ok(lst) => {
+                log::info!("bound {}", name);
+                server = server.listen_uds(lst)?;
+                listening.push(name);
+            }
+            Err(e) => {
+                log::error!("can not bind {}: {}", name, e);
+                failed.push(format!("{}: {}", name, e));
+            }
+        }
+    }
+
+    let mut summary = format!("listening on {} addresses:", listening.len());
+    for name in &listening {
+        summary += &format!("\n  {}", name);
+    }
+    if !failed.is_empty() {
+        summary += &format!("\nnot listening on {}:", failed.len());
+        for failure in &failed {
+            summary += &format!("\n  {}", failure);
+        }
+    }
+    log::info!("{}", summary);
+
+    let refuse = if listening.is_empty() {
+        Some("no address could be bound")
+    } else if config.require_all && !failed.is_empty() {
+        Some("BIND_REQUIRE_ALL is set, and not every address could be bound")
+    } else {
+        None
+    };
+    if let Some(reason) = refuse {
+        return Err(io::Error::new(io::ErrorKind::AddrNotAvailable, reason));
+    }
+    server.run().await
+}
@@ -0,0 +1,76 @@
+//! Moves the traffic to green in steps, and shows who gets which version.
+//!
+//! ```bash
+//! cargo run --bin shift
+//! ```
+//!
+//! Twenty users keep their session cookies across all steps, 200 new
+//! sessions arrive at each step.
+use std::time::Duration;
+
+use ntex::http::client::Client;
+use ntex::http::header;
+
+const SERVER: &str = "http://127.0.0.1:8080";
+const STEPS: &[u8] = &[0, 10, 25, 50, 75, 100];
+const NEW_SESSIONS: usize = 200;
+
+/// The version served, and the cookie to send from now on
+async fn checkout(client: &Client, cookie: Option<&str>) -> (String, Option<String>) {
+    let mut req = client.get(format!("{}/checkout", SERVER));
+    if let Some(cookie) = cookie {
+        req = req.header(header::COOKIE, cookie);
+    }
+    let res = req.send().await.expect("is the server running?");
+    let set = res
+        .headers()
+        .get(header::SET_COOKIE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .map(str::to_owned);
+    let version = res.headers().get("x-deployment").unwrap();
+    (version.to_str().unwrap().to_owned(), set)
+}
+
+#[ntex::main]
+async fn main() {
+    let client = Client::default();
+    let mut users: Vec<(Option<String>, String)> = Vec::new();
+    for _ in 0..20 {
+        let (version, cookie) = checkout(&client, None).await;
+        users.push((cookie, version));
+    }
+
+    for &percent in STEPS {
+        client
+            .put(format!("{}/admin/rollout", SERVER))
+            .send_json(&serde_json::json!({ "green_percent": percent }))
+            .await
+            .unwrap();
+
+        let mut green = 0;
+        for _ in 0..NEW_SESSIONS {
+            green += (checkout(&client, None).await.0 == "green") as usize;
+        }
+        let mut flipped = 0;
+        for (cookie, version) in users.iter_mut() {
+            let (now, set) = checkout(&client, cookie.as_deref()).await;
+            if now != *version {
+                flipped += 1;
+                *version = now;
+            }
+            if set.is_some() {
+                *cookie = set;
+            }
+        }
+        let green_users = users.iter().filter(|(_, v)| v == "green").count();
+        println!(
+            "green {:>3}%: new sessions {:>3}% green, users {:>2}/20 green, {} flipped",
+            percent,
+            green * 100 / NEW_SESSIONS,
+            green_users,
+            flipped
+        );
+        ntex::rt::time::delay_for(Duration::from_millis(500)).await;
+    }
+}
@@ -0,0 +1,172 @@
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod rollout;
+
+use rollout::{Color, Rollout, Split};
+
+/// The current checkout
+async fn checkout_blue() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "version": "blue",
+        "steps": ["cart", "address", "shipping", "payment", "confirm"],
+    }))
+}
+
+/// The new one, being rolled out
+async fn checkout_green() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "version": "green",
+        "steps": ["cart", "checkout", "confirm"],
+    }))
+}
+
+#[derive(Deserialize)]
+struct Weight {
+    green_percent: u8,
+}
+
+async fn get_rollout(rollout: web::types::Data<Rollout>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "green_percent": rollout.green() }))
+}
+
+async fn set_rollout(
+    weight: web::types::Json<Weight>,
+    rollout: web::types::Data<Rollout>,
+) -> HttpResponse {
+    match rollout.set_green(weight.green_percent) {
+        Some(previous) => {
+            log::info!("green {}% -> {}%", previous, weight.green_percent);
+            HttpResponse::Ok()
+                .json(&serde_json::json!({ "green_percent": weight.green_percent }))
+        }
+        None => HttpResponse::BadRequest()
+            .json(&serde_json::json!({ "error": "green_percent is 0 to 100" })),
+    }
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/checkout")
+            .route(web::get().guard(Color::Green).to(checkout_green))
+            .route(web::get().guard(Color::Blue).to(checkout_blue)),
+    )
+    .service(
+        web::resource("/admin/rollout")
+            .route(web::get().to(get_rollout))
+            .route(web::put().to(set_rollout)),
+    );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,blue_green=info");
+    env_logger::init();
+
+    let rollout = web::types::Data::new(Rollout::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(rollout.clone())
+            .wrap(Split::new(rollout.clone()))
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::header;
+    use ntex::web::test;
+
+    macro_rules! service {
+        ($rollout:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data($rollout.clone())
+                    .wrap(Split::new($rollout.clone()))
+                    .configure(app),
+            )
+            .await
+        };
+    }
+
+    /// The color served, and the cookie set, if one was
+    async fn checkout(
+        app: &impl ntex::Service<
+            Request = ntex::http::Request,
+            Response = ntex::web::dev::WebResponse,
+            Error = ntex::web::Error,
+        >,
+        cookie: Option<&str>,
+    ) -> (String, Option<String>) {
+        let mut req = test::TestRequest::with_uri("/checkout");
+        if let Some(cookie) = cookie {
+            req = req.header(header::COOKIE, format!("deployment={}", cookie));
+        }
+        let res = test::call_service(app, req.to_request()).await;
+        let set = res
+            .response()
+            .cookies()
+            .find(|c| c.name() == rollout::COOKIE)
+            .map(|c| c.value().to_owned());
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(res).await).unwrap();
+        (body["version"].as_str().unwrap().to_owned(), set)
+    }
+
+    #[ntex::test]
+    async fn test_sessions_keep_their_color() {
+        let rollout = web::types::Data::new(Rollout::default());
+        let app = service!(rollout);
+
+        assert_eq!(
+            checkout(&app, None).await,
+            ("blue".into(), Some("blue".into()))
+        );
+
+        rollout.set_green(30);
+        let mut green = 0;
+        for _ in 0..1000 {
+            let (served, set) = checkout(&app, None).await;
+            assert_eq!(Some(&served), set.as_ref());
+            green += (served == "green") as usize;
+        }
+        assert!((200..400).contains(&green), "{} of 1000 green", green);
+
+        // no flipping for a session, whatever the share
+        for percent in &[1, 50, 99] {
+            rollout.set_green(*percent);
+            for _ in 0..20 {
+                assert_eq!(checkout(&app, Some("blue")).await, ("blue".into(), None));
+                assert_eq!(checkout(&app, Some("green")).await, ("green".into(), None));
+            }
+        }
+    }
+
+    #[ntex::test]
+    async fn test_the_ends_override_sessions() {
+        let rollout = web::types::Data::new(Rollout::default());
+        let app = service!(rollout);
+
+        rollout.set_green(100);
+        let finished = checkout(&app, Some("blue")).await;
+        assert_eq!(finished, ("green".into(), Some("green".into())));
+
+        rollout.set_green(0);
+        let rolled_back = checkout(&app, Some("green")).await;
+        assert_eq!(rolled_back, ("blue".into(), Some("blue".into())));
+
+        let req = test::TestRequest::put()
+            .uri("/admin/rollout")
+            .set_json(&serde_json::json!({ "green_percent": 101 }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), ntex::http::StatusCode::BAD_REQUEST);
+        assert_eq!(rollout.green(), 0);
+    }
+}
@@ -0,0 +1,164 @@
+//! Splits traffic between two implementations of the same routes.
+//!
+//! `Split` runs before routing and picks a `Color` for every request, the
+//! routes have a handler for each, with `Color` as the guard. The share of
+//! green is in `Rollout`, in `Data`, and can change at any time.
+//!
+//! A session keeps its color: the first response sets it in a cookie, and
+//! later requests are routed by the cookie, not by chance. A new share only
+//! decides for new sessions. Except at the ends, at 0% everyone is sent to
+//! blue, a rollback has to be a rollback, and at 100% everyone to green,
+//! that finishes the rollout.
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::task::{Context, Poll};
+
+use cookie::{Cookie, SameSite};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::{HttpMessage, RequestHead};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, guard::Guard, Error};
+use ntex::{Service, Transform};
+use rand::Rng;
+
+pub const COOKIE: &str = "deployment";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Color {
+    Blue,
+    Green,
+}
+
+impl Color {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Color::Blue => "blue",
+            Color::Green => "green",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "blue" => Some(Color::Blue),
+            "green" => Some(Color::Green),
+            _ => None,
+        }
+    }
+}
+
+/// Matches the requests `Split` gave this color
+impl Guard for Color {
+    fn check(&self, req: &RequestHead) -> bool {
+        req.extensions().get::<Color>() == Some(self)
+    }
+}
+
+/// The share of green, in percent
+#[derive(Default)]
+pub struct Rollout {
+    green: AtomicU8,
+}
+
+impl Rollout {
+    pub fn green(&self) -> u8 {
+        self.green.load(Ordering::Relaxed)
+    }
+
+    /// `None` for more than 100
+    pub fn set_green(&self, percent: u8) -> Option<u8> {
+        if percent > 100 {
+            return None;
+        }
+        Some(self.green.swap(percent, Ordering::Relaxed))
+    }
+
+    /// The color of a request, `pinned` is the one of its session
+    fn pick(&self, pinned: Option<Color>) -> Color {
+        match (self.green(), pinned) {
+            (0, _) => Color::Blue,
+            (100, _) => Color::Green,
+            (_, Some(color)) => color,
+            (green, None) if rand::thread_rng().gen_range(0, 100) < green => {
+                Color::Green
+            }
+            (_, None) => Color::Blue,
+        }
+    }
+}
+
+pub struct Split {
+    rollout: web::types::Data<Rollout>,
+}
+
+impl Split {
+    pub fn new(rollout: web::types::Data<Rollout>) -> Self {
+        Split { rollout }
+    }
+}
+
+impl<S, Err> Transform<S> for Split
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SplitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SplitMiddleware {
+            service,
+            rollout: self.rollout.clone(),
+        })
+    }
+}
+
+pub struct SplitMiddleware<S> {
+    service: S,
+    rollout: web::types::Data<Rollout>,
+}
+
+impl<S, Err> Service for SplitMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let pinned = req
+            .cookie(COOKIE)
+            .and_then(|cookie| Color::parse(cookie.value()));
+        let color = self.rollout.pick(pinned);
+        req.extensions_mut().insert(color);
+
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            if pinned != Some(color) {
+                // a session cookie, a new browser session is a new session
+                let cookie = Cookie::build(COOKIE, color.as_str())
+                    .path("/")
+                    .http_only(true)
+                    .same_site(SameSite::Lax)
+                    .finish();
+                res.response_mut().add_cookie(&cookie)?;
+            }
+            res.headers_mut().insert(
+                ntex::http::header::HeaderName::from_static("x-deployment"),
+                ntex::http::header::HeaderValue::from_static(color.as_str()),
+            );
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
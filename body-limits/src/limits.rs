@@ -0,0 +1,162 @@
+//! Request body limits and decompression.
+//!
+//! `BodyLimits` turns away a request whose `Content-Length` is over the
+//! limit with a `413` before any of it is read, and counts the bytes of the
+//! ones without, a chunked body just fails with `413` once it gets too
+//! large. A body sent with `Content-Encoding: gzip`, `deflate` or `br` is
+//! decompressed on the way to the handler, which sees the plain bytes and
+//! no `Content-Encoding`, any other encoding is a `415`.
+//!
+//! A few kilobytes of gzip can decompress to gigabytes, so the decompressed
+//! bytes have a limit of their own. Nothing is buffered, the payload is
+//! replaced with one that checks and decodes as the handler reads it.
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::future::{ok, Either, Ready};
+use futures::{Stream, StreamExt};
+use ntex::http::error::PayloadError;
+use ntex::http::header::{self, ContentEncoding};
+use ntex::http::{encoding::Decoder, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, HttpResponse};
+use ntex::{Service, Transform};
+
+#[derive(Clone, Copy)]
+pub struct BodyLimits {
+    limit: u64,
+    decoded_limit: u64,
+}
+
+impl Default for BodyLimits {
+    /// 1 MiB as sent, 10 MiB decompressed
+    fn default() -> Self {
+        BodyLimits {
+            limit: 1024 * 1024,
+            decoded_limit: 10 * 1024 * 1024,
+        }
+    }
+}
+
+impl BodyLimits {
+    /// Bytes of a body as it is sent
+    pub fn limit(mut self, limit: u64) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Bytes of a compressed body once it is decompressed
+    pub fn decoded_limit(mut self, limit: u64) -> Self {
+        self.decoded_limit = limit;
+        self
+    }
+}
+
+impl<S, Err> Transform<S> for BodyLimits
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BodyLimitsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BodyLimitsMiddleware {
+            service,
+            limits: *self,
+        })
+    }
+}
+
+pub struct BodyLimitsMiddleware<S> {
+    service: S,
+    limits: BodyLimits,
+}
+
+impl<S, Err> Service for BodyLimitsMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<WebResponse, Error>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: WebRequest<Err>) -> Self::Future {
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok());
+        if length.is_some_and(|length| length > self.limits.limit) {
+            let res = error(HttpResponse::PayloadTooLarge(), "request body too large");
+            return Either::Right(ok(req.into_response(res)));
+        }
+
+        let encoding = match req.headers().get(header::CONTENT_ENCODING) {
+            None => None,
+            Some(value) => match value.to_str().map(str::trim) {
+                Ok("identity") => None,
+                Ok("gzip") | Ok("x-gzip") => Some(ContentEncoding::Gzip),
+                Ok("deflate") => Some(ContentEncoding::Deflate),
+                Ok("br") => Some(ContentEncoding::Br),
+                _ => {
+                    let res = error(
+                        HttpResponse::UnsupportedMediaType(),
+                        "unsupported content-encoding",
+                    );
+                    return Either::Right(ok(req.into_response(res)));
+                }
+            },
+        };
+
+        let payload = limited(req.take_payload(), self.limits.limit);
+        let payload: Payload = match encoding {
+            None => Payload::Stream(payload.boxed_local()),
+            Some(encoding) => {
+                // the handler gets the decoded body, the headers must not
+                // claim anything else
+                let headers = req.headers_mut();
+                headers.remove(header::CONTENT_ENCODING);
+                headers.remove(header::CONTENT_LENGTH);
+                let decoded = Decoder::new(payload, encoding);
+                Payload::Stream(
+                    limited(decoded, self.limits.decoded_limit).boxed_local(),
+                )
+            }
+        };
+        req.set_payload(payload);
+
+        Either::Left(self.service.call(req))
+    }
+}
+
+/// Fails with `Overflow`, a `413`, once more than `limit` bytes came through
+fn limited<S>(stream: S, limit: u64) -> impl Stream<Item = Result<Bytes, PayloadError>>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>>,
+{
+    let mut seen = 0;
+    stream.map(move |chunk| {
+        let chunk = chunk?;
+        seen += chunk.len() as u64;
+        if seen > limit {
+            Err(PayloadError::Overflow)
+        } else {
+            Ok(chunk)
+        }
+    })
+}
+
+fn error(mut res: ntex::web::HttpResponseBuilder, message: &str) -> HttpResponse {
+    res.json(&serde_json::json!({ "error": message }))
+}
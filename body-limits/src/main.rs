@@ -0,0 +1,59 @@
+//! Request bodies limited and decompressed by `limits`, responses
+//! compressed by `middleware::Compress` for clients that accept it.
+use futures::StreamExt;
+use ntex::web::{self, middleware, App, Error, HttpResponse};
+
+mod limits;
+
+use limits::BodyLimits;
+
+/// Reads the body as it comes in, a body over the limit fails on the way
+async fn upload(mut body: web::types::Payload) -> Result<HttpResponse, Error> {
+    let mut bytes = 0;
+    let mut lines = 0;
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        bytes += chunk.len();
+        lines += chunk.iter().filter(|b| **b == b'\n').count();
+    }
+    Ok(HttpResponse::Ok().json(&serde_json::json!({ "bytes": bytes, "lines": lines })))
+}
+
+async fn echo(value: web::types::Json<serde_json::Value>) -> HttpResponse {
+    HttpResponse::Ok().json(&value.into_inner())
+}
+
+/// Large enough to be worth compressing
+async fn report() -> HttpResponse {
+    let body = (0..1000)
+        .map(|i| format!("line {}: the quick brown fox jumps over the lazy dog\n", i))
+        .collect::<String>();
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(body)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,body_limits=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            // the body is checked by `BodyLimits`, `Json` may take all of it
+            .app_data(web::types::JsonConfig::default().limit(10 * 1024 * 1024))
+            .wrap(
+                BodyLimits::default()
+                    .limit(64 * 1024)
+                    .decoded_limit(1024 * 1024),
+            )
+            .wrap(middleware::Compress::default())
+            .wrap(middleware::Logger::default())
+            .route("/upload", web::post().to(upload))
+            .route("/echo", web::post().to(echo))
+            .route("/report", web::get().to(report))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,33 @@
+//! Encrypts stdin for `Content-Encoding: x-aes256gcm`, with the key in
+//! `BODY_KEY`, and writes the nonce and ciphertext to stdout.
+use std::io::{self, Read, Write};
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+fn main() -> io::Result<()> {
+    let key = std::env::var("BODY_KEY").expect("BODY_KEY is not set");
+    let key = base64::decode(key.trim()).expect("BODY_KEY is not base64");
+    let key = LessSafeKey::new(
+        UnboundKey::new(&AES_256_GCM, &key).expect("BODY_KEY is not 32 bytes"),
+    );
+
+    let mut data = Vec::new();
+    io::stdin().read_to_end(&mut data)?;
+
+    // a nonce must never repeat for the same key, random ones are fine at
+    // this volume
+    let mut nonce = [0; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce).unwrap();
+    key.seal_in_place_append_tag(
+        Nonce::assume_unique_for_key(nonce),
+        Aad::empty(),
+        &mut data,
+    )
+    .unwrap();
+
+    let mut out = io::stdout();
+    out.write_all(&nonce)?;
+    out.write_all(&data)?;
+    out.flush()
+}
@@ -0,0 +1,68 @@
+use bytes::Bytes;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod transform;
+
+use transform::{BodyKey, BodyTransform};
+
+/// Largest body on the wire
+const MAX_ENCODED: usize = 1024 * 1024;
+/// Largest body after decoding
+const MAX_DECODED: usize = 8 * 1024 * 1024;
+
+/// Gets the body as it was before the client encoded it
+async fn echo(body: Bytes) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(body)
+}
+
+#[derive(Deserialize)]
+struct Event {
+    name: String,
+    count: u32,
+}
+
+/// Extractors work on the decoded body too
+async fn events(events: web::types::Json<Vec<Event>>) -> HttpResponse {
+    // in u64, a few u32 counts can add up to more than u32::MAX
+    let total: u64 = events.iter().map(|e| u64::from(e.count)).sum();
+    let names: Vec<_> = events.iter().map(|e| e.name.as_str()).collect();
+    HttpResponse::Ok().json(&serde_json::json!({
+        "events": events.len(),
+        "total": total,
+        "names": names,
+    }))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,body_transform=info");
+    env_logger::init();
+
+    // encrypted bodies are only accepted with a key
+    let key = std::env::var("BODY_KEY").ok().map(|key| {
+        let key = base64::decode(key.trim()).expect("BODY_KEY is not base64");
+        web::types::Data::new(BodyKey::new(&key).expect("BODY_KEY is not 32 bytes"))
+    });
+    if key.is_none() {
+        log::info!("BODY_KEY is not set, encrypted bodies are refused");
+    }
+
+    web::server(move || {
+        let mut app = App::new();
+        if let Some(ref key) = key {
+            app = app.app_data(key.clone());
+        }
+        app.wrap(BodyTransform::new(MAX_ENCODED, MAX_DECODED))
+            .wrap(middleware::Logger::default())
+            .app_data(web::types::PayloadConfig::new(MAX_DECODED))
+            .app_data(web::types::JsonConfig::default().limit(MAX_DECODED))
+            .route("/echo", web::post().to(echo))
+            .route("/events", web::post().to(events))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,248 @@
+//! Decodes request bodies before the handlers see them.
+//!
+//! `Content-Encoding` lists the codings in the order the client applied
+//! them, they are undone from last to first:
+//!
+//! * `gzip`, inflated
+//! * `x-aes256gcm`, the body is a 12 byte nonce followed by the AES-256-GCM
+//!   ciphertext and tag, decrypted with the `BodyKey` in `Data`
+//!
+//! The decoded body replaces the payload, `Content-Encoding` is removed and
+//! `Content-Length` set to the new size, so extractors can't tell the
+//! difference. A body that doesn't decode is answered with `400`.
+//!
+//! A few kilobytes of gzip can inflate to gigabytes. The encoded body is
+//! read up to `max_encoded`, and inflating stops at `max_decoded`, the
+//! output is never held in memory beyond that.
+use std::io::Read;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use bytes::{Bytes, BytesMut};
+use derive_more::Display;
+use flate2::read::GzDecoder;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use ntex::http::h1;
+use ntex::http::header::{self, HeaderValue};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, DefaultError, Error, HttpResponse};
+use ntex::{Service, Transform};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+/// The key for `x-aes256gcm` bodies, without it they are refused
+pub struct BodyKey(LessSafeKey);
+
+impl BodyKey {
+    pub fn new(key: &[u8]) -> Option<Self> {
+        let key = UnboundKey::new(&AES_256_GCM, key).ok()?;
+        Some(BodyKey(LessSafeKey::new(key)))
+    }
+
+    fn decrypt(&self, body: Bytes) -> Result<Bytes, DecodeError> {
+        if body.len() < NONCE_LEN {
+            return Err(DecodeError::Decrypt);
+        }
+        let nonce = Nonce::try_assume_unique_for_key(&body[..NONCE_LEN])
+            .map_err(|_| DecodeError::Decrypt)?;
+        let mut data = body[NONCE_LEN..].to_vec();
+        let len = self
+            .0
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| DecodeError::Decrypt)?
+            .len();
+        data.truncate(len);
+        Ok(data.into())
+    }
+}
+
+#[derive(Debug, Display)]
+enum DecodeError {
+    #[display(fmt = "unsupported content encoding `{}`", _0)]
+    Unsupported(String),
+    #[display(fmt = "encoded body is larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[display(fmt = "decoded body is larger than {} bytes", _0)]
+    Bomb(usize),
+    #[display(fmt = "body is not valid gzip")]
+    Gzip,
+    #[display(fmt = "body can not be decrypted")]
+    Decrypt,
+    #[display(fmt = "encrypted bodies are not accepted")]
+    NoKey,
+}
+
+enum Coding {
+    Gzip,
+    Aes256Gcm,
+}
+
+/// The codings to undo, in the order to undo them
+fn codings(value: &HeaderValue) -> Result<Vec<Coding>, DecodeError> {
+    let value = value
+        .to_str()
+        .map_err(|_| DecodeError::Unsupported(String::new()))?;
+    let mut codings = value
+        .split(',')
+        .map(|c| c.trim().to_ascii_lowercase())
+        .filter(|c| !c.is_empty() && c != "identity")
+        .map(|c| match c.as_str() {
+            "gzip" | "x-gzip" => Ok(Coding::Gzip),
+            "x-aes256gcm" => Ok(Coding::Aes256Gcm),
+            _ => Err(DecodeError::Unsupported(c)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    codings.reverse();
+    Ok(codings)
+}
+
+fn inflate(body: &[u8], max: usize) -> Result<Bytes, DecodeError> {
+    let mut out = Vec::new();
+    // one byte more than allowed tells a full body from a bomb
+    GzDecoder::new(body)
+        .take(max as u64 + 1)
+        .read_to_end(&mut out)
+        .map_err(|_| DecodeError::Gzip)?;
+    if out.len() > max {
+        return Err(DecodeError::Bomb(max));
+    }
+    Ok(out.into())
+}
+
+/// Inflating and decrypting are cpu bound, they run on the thread pool
+fn decode(
+    mut body: Bytes,
+    codings: Vec<Coding>,
+    key: Option<web::types::Data<BodyKey>>,
+    max_decoded: usize,
+) -> Result<Bytes, DecodeError> {
+    for coding in codings {
+        body = match coding {
+            Coding::Gzip => inflate(&body, max_decoded)?,
+            Coding::Aes256Gcm => {
+                key.as_ref().ok_or(DecodeError::NoKey)?.decrypt(body)?
+            }
+        };
+    }
+    Ok(body)
+}
+
+#[derive(Clone)]
+pub struct BodyTransform {
+    max_encoded: usize,
+    max_decoded: usize,
+}
+
+impl BodyTransform {
+    /// Accepts encoded bodies up to `max_encoded` bytes, that decode to at
+    /// most `max_decoded` bytes
+    pub fn new(max_encoded: usize, max_decoded: usize) -> Self {
+        BodyTransform {
+            max_encoded,
+            max_decoded,
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for BodyTransform
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = BodyTransformMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(BodyTransformMiddleware {
+            service: Rc::new(service),
+            config: self.clone(),
+        })
+    }
+}
+
+pub struct BodyTransformMiddleware<S> {
+    service: Rc<S>,
+    config: BodyTransform,
+}
+
+fn bad_request<Err>(req: WebRequest<Err>, err: DecodeError) -> WebResponse {
+    log::warn!("{} {}: {}", req.method(), req.path(), err);
+    req.into_response(
+        HttpResponse::BadRequest()
+            .json(&serde_json::json!({ "error": err.to_string() }))
+            .into_body(),
+    )
+}
+
+impl<S, Err> Service for BodyTransformMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: WebRequest<Err>) -> Self::Future {
+        let codings = match req.headers().get(header::CONTENT_ENCODING).map(codings) {
+            None => return self.service.call(req).boxed_local(),
+            Some(Ok(codings)) => codings,
+            Some(Err(e)) => return ok(bad_request(req, e)).boxed_local(),
+        };
+        let key = req.app_data::<web::types::Data<BodyKey>>().cloned();
+        let svc = self.service.clone();
+        let BodyTransform {
+            max_encoded,
+            max_decoded,
+        } = self.config;
+
+        async move {
+            let mut body = BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk?;
+                if body.len() + chunk.len() > max_encoded {
+                    return Ok(bad_request(req, DecodeError::TooLarge(max_encoded)));
+                }
+                body.extend_from_slice(&chunk);
+            }
+
+            let body =
+                web::block(move || decode(body.freeze(), codings, key, max_decoded))
+                    .await;
+            let body = match body {
+                Ok(body) => body,
+                Err(web::error::BlockingError::Error(e)) => {
+                    return Ok(bad_request(req, e))
+                }
+                Err(web::error::BlockingError::Canceled) => {
+                    let err = web::error::ErrorInternalServerError::<_, DefaultError>(
+                        "thread pool is gone",
+                    );
+                    return Err(err.into());
+                }
+            };
+
+            let headers = req.headers_mut();
+            headers.remove(header::CONTENT_ENCODING);
+            headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+            let (mut sender, decoded) = h1::Payload::create(false);
+            sender.feed_data(body);
+            sender.feed_eof();
+            req.set_payload(decoded.into());
+            svc.call(req).await
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,56 @@
+//! Captures the build metadata, `src/main.rs` reads it with `env!`
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, Utc};
+
+/// Output of a command, if it could be run
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+fn main() {
+    // outside of a git checkout, e.g. when built from a source tarball
+    let commit = output("git", &["rev-parse", "--short=12", "HEAD"])
+        .unwrap_or_else(|| "unknown".to_owned());
+    // build scripts run in the package directory, `.` is this example, edits
+    // to the other examples in the repository don't make it dirty
+    let dirty = output("git", &["status", "--porcelain", "--", "."])
+        .map(|status| !status.is_empty())
+        .unwrap_or(false);
+    println!(
+        "cargo:rustc-env=GIT_COMMIT={}{}",
+        commit,
+        if dirty { "-dirty" } else { "" }
+    );
+
+    // reproducible builds set a fixed time
+    let built_at = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => UNIX_EPOCH + Duration::from_secs(epoch.parse().unwrap()),
+        Err(_) => SystemTime::now(),
+    };
+    println!(
+        "cargo:rustc-env=BUILD_TIMESTAMP={}",
+        DateTime::<Utc>::from(built_at).format("%Y-%m-%dT%H:%M:%SZ")
+    );
+
+    // the compiler cargo uses, not necessarily the one on the PATH
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned());
+    let rustc_version =
+        output(&rustc, &["--version"]).unwrap_or_else(|| "unknown".to_owned());
+    println!("cargo:rustc-env=RUSTC_VERSION={}", rustc_version);
+
+    // a new commit changes HEAD or the branch it points to, staging or
+    // committing changes the index, the repository is the one this example
+    // lives in. Without any rerun-if line cargo would rerun this on every
+    // change in the package
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs/heads");
+    println!("cargo:rerun-if-changed=../.git/index");
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
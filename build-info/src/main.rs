@@ -0,0 +1,53 @@
+use std::time::Instant;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Serialize;
+
+/// Set by `build.rs`, fixed when the binary is compiled
+const GIT_COMMIT: &str = env!("GIT_COMMIT");
+const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+const RUSTC_VERSION: &str = env!("RUSTC_VERSION");
+
+/// When the process started
+struct Started(Instant);
+
+#[derive(Serialize)]
+struct Version {
+    name: &'static str,
+    /// set by cargo from `Cargo.toml`
+    version: &'static str,
+    git_commit: &'static str,
+    build_timestamp: &'static str,
+    rustc_version: &'static str,
+    uptime_secs: u64,
+}
+
+async fn version(started: web::types::Data<Started>) -> HttpResponse {
+    HttpResponse::Ok().json(&Version {
+        name: env!("CARGO_PKG_NAME"),
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: GIT_COMMIT,
+        build_timestamp: BUILD_TIMESTAMP,
+        rustc_version: RUSTC_VERSION,
+        uptime_secs: started.0.elapsed().as_secs(),
+    })
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    // shared by all workers, so every worker reports the same uptime
+    let started = web::types::Data::new(Started(Instant::now()));
+
+    web::server(move || {
+        App::new()
+            .app_data(started.clone())
+            .wrap(middleware::Logger::default())
+            .route("/version", web::get().to(version))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,165 @@
+//! Inserting a large JSON array in batches.
+//!
+//! Every batch of `BATCH_SIZE` items is one transaction, a failing batch is
+//! rolled back on its own and the ones after it are still inserted. Within
+//! a batch the rows go in with multi-row `INSERT`s, one statement for many
+//! rows instead of one per row.
+use std::io;
+
+use ntex::http::StatusCode;
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::types::ToSql;
+use serde::{Deserialize, Serialize};
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// Items per transaction
+const BATCH_SIZE: usize = 500;
+/// Sqlite allows at most 999 parameters per statement by default
+const ROWS_PER_INSERT: usize = 999 / 3;
+/// Largest accepted body
+const MAX_BODY: usize = 16 * 1024 * 1024;
+
+#[derive(Deserialize)]
+struct Item {
+    sku: String,
+    name: String,
+    price_cents: i64,
+}
+
+/// A batch that was rolled back, `from..to` are indexes into the request
+#[derive(Serialize)]
+struct FailedBatch {
+    batch: usize,
+    from: usize,
+    to: usize,
+    error: String,
+}
+
+#[derive(Serialize)]
+struct Report {
+    inserted: usize,
+    failed: Vec<FailedBatch>,
+}
+
+fn insert_values(rows: usize) -> String {
+    let values = vec!["(?, ?, ?)"; rows].join(", ");
+    format!(
+        "INSERT INTO items (sku, name, price_cents) VALUES {}",
+        values
+    )
+}
+
+/// Inserts all items of a batch or none of them
+fn insert_batch(
+    conn: &mut rusqlite::Connection,
+    items: &[Item],
+) -> rusqlite::Result<()> {
+    // rolled back when dropped without commit
+    let tx = conn.transaction()?;
+    for rows in items.chunks(ROWS_PER_INSERT) {
+        let params: Vec<&dyn ToSql> = rows
+            .iter()
+            .flat_map(|item| {
+                vec![&item.sku as &dyn ToSql, &item.name, &item.price_cents]
+            })
+            .collect();
+        tx.prepare_cached(&insert_values(rows.len()))?
+            .execute(&params)?;
+    }
+    tx.commit()
+}
+
+fn insert_all(pool: &Pool, items: &[Item]) -> Result<Report, r2d2::Error> {
+    let mut conn = pool.get()?;
+    let mut report = Report {
+        inserted: 0,
+        failed: Vec::new(),
+    };
+
+    for (batch, items) in items.chunks(BATCH_SIZE).enumerate() {
+        match insert_batch(&mut conn, items) {
+            Ok(()) => report.inserted += items.len(),
+            Err(e) => {
+                let from = batch * BATCH_SIZE;
+                log::warn!("batch {} rolled back: {}", batch, e);
+                report.failed.push(FailedBatch {
+                    batch,
+                    from,
+                    to: from + items.len(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// `200 OK` when everything was inserted, `207 Multi-Status` when some
+/// batches failed
+async fn bulk_insert(
+    items: web::types::Json<Vec<Item>>,
+    pool: web::types::Data<Pool>,
+) -> Result<HttpResponse, Error> {
+    let items = items.into_inner();
+    let report = web::block(move || insert_all(&pool, &items))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    let status = if report.failed.is_empty() {
+        StatusCode::OK
+    } else {
+        StatusCode::MULTI_STATUS
+    };
+    Ok(HttpResponse::build(status).json(&report))
+}
+
+fn count_items(pool: &Pool) -> Result<i64, Box<dyn std::error::Error + Send + Sync>> {
+    let conn = pool.get()?;
+    Ok(
+        conn.query_row("SELECT COUNT(*) FROM items", rusqlite::NO_PARAMS, |row| {
+            row.get(0)
+        })?,
+    )
+}
+
+async fn count(pool: web::types::Data<Pool>) -> Result<HttpResponse, Error> {
+    let count = web::block(move || count_items(&pool))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(&serde_json::json!({ "count": count })))
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,bulk_insert=info");
+    env_logger::init();
+
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file("items.db")).unwrap();
+    pool.get()
+        .unwrap()
+        .execute_batch(
+            "CREATE TABLE IF NOT EXISTS items (
+                sku TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                price_cents INTEGER NOT NULL CHECK (price_cents >= 0)
+            )",
+        )
+        .unwrap();
+
+    web::server(move || {
+        App::new()
+            .data(pool.clone())
+            .wrap(middleware::Logger::default())
+            .service(
+                web::resource("/items/bulk")
+                    .app_data(web::types::JsonConfig::default().limit(MAX_BODY))
+                    .route(web::post().to(bulk_insert)),
+            )
+            .route("/items/count", web::get().to(count))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
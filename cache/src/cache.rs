@@ -0,0 +1,231 @@
+//! In-memory response cache.
+//!
+//! `ResponseCache` answers `GET` requests from a `Cache` held in `Data`, an
+//! LRU keyed by path and query, and stores what the handlers return. A
+//! response is kept for its `Cache-Control: max-age`, or the cache's default
+//! TTL if it has none, and is not kept at all if it is not a `200`, sets a
+//! cookie, says `no-store` or `private`, or has a streaming body or one
+//! bigger than `MAX_BODY`.
+//!
+//! To store a body it has to be read out of the `WebResponse` in full, then
+//! the response goes on with a body made of the bytes read. Streaming bodies
+//! are left alone, reading them would hold the whole stream back.
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use futures::future::{ok, poll_fn, FutureExt, LocalBoxFuture, Ready};
+use lru::LruCache;
+use ntex::http::body::{Body, BodySize, MessageBody, ResponseBody};
+use ntex::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use ntex::http::{Method, StatusCode};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error, HttpResponse};
+use ntex::{Service, Transform};
+
+const X_CACHE: &str = "x-cache";
+/// Bigger bodies are not cached
+const MAX_BODY: u64 = 1024 * 1024;
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+    ttl: Duration,
+}
+
+impl Entry {
+    fn response(&self) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status);
+        for (name, value) in self.headers.iter() {
+            res.header(name.clone(), value.clone());
+        }
+        res.header(header::AGE, self.stored.elapsed().as_secs().to_string())
+            .header(X_CACHE, "HIT")
+            .body(self.body.clone())
+    }
+}
+
+/// Cached responses by path and query, shared by all workers
+pub struct Cache {
+    entries: Mutex<LruCache<String, Entry>>,
+    ttl: Duration,
+}
+
+impl Cache {
+    /// Holds up to `capacity` responses, the least recently used goes first,
+    /// for `ttl` unless they say otherwise
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Cache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<HttpResponse> {
+        // lru 0.6 can only be looked up by the key type itself
+        let key = key.to_owned();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key)?;
+        if entry.stored.elapsed() < entry.ttl {
+            return Some(entry.response());
+        }
+        entries.pop(&key);
+        None
+    }
+
+    fn put(&self, key: String, res: &Response, ttl: Duration, body: Bytes) {
+        let mut headers = res.headers().clone();
+        headers.remove(header::CONTENT_LENGTH);
+        let entry = Entry {
+            status: res.status(),
+            headers,
+            body,
+            stored: Instant::now(),
+            ttl,
+        };
+        self.entries.lock().unwrap().put(key, entry);
+    }
+
+    /// Drops every entry whose key starts with `prefix`, returns how many
+    pub fn invalidate(&self, prefix: &str) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let keys: Vec<String> = entries
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        for key in &keys {
+            entries.pop(key);
+        }
+        keys.len()
+    }
+
+    /// How long `res` may be kept, `None` if it must not be
+    fn ttl(&self, res: &Response) -> Option<Duration> {
+        if res.status() != StatusCode::OK
+            || res.headers().contains_key(header::SET_COOKIE)
+        {
+            return None;
+        }
+        match res.body().size() {
+            BodySize::Empty => (),
+            BodySize::Sized(n) if n <= MAX_BODY => (),
+            _ => return None,
+        }
+
+        let mut ttl = self.ttl;
+        let cache_control = res.headers().get_all(header::CACHE_CONTROL);
+        for value in cache_control.filter_map(|v| v.to_str().ok()) {
+            for directive in value.split(',').map(str::trim) {
+                match directive {
+                    "no-store" | "private" => return None,
+                    _ => {
+                        if let Some(max_age) = directive.strip_prefix("max-age=") {
+                            ttl = Duration::from_secs(max_age.parse().ok()?);
+                        }
+                    }
+                }
+            }
+        }
+        Some(ttl)
+    }
+}
+
+type Response = ntex::http::Response<Body>;
+
+pub struct ResponseCache {
+    cache: web::types::Data<Cache>,
+}
+
+impl ResponseCache {
+    pub fn new(cache: web::types::Data<Cache>) -> Self {
+        ResponseCache { cache }
+    }
+}
+
+impl<S, Err> Transform<S> for ResponseCache
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ResponseCacheMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ResponseCacheMiddleware {
+            service,
+            cache: self.cache.clone(),
+        })
+    }
+}
+
+pub struct ResponseCacheMiddleware<S> {
+    service: S,
+    cache: web::types::Data<Cache>,
+}
+
+impl<S, Err> Service for ResponseCacheMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        if req.method() != Method::GET {
+            return self.service.call(req).boxed_local();
+        }
+
+        let key = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.path().to_owned(), |pq| pq.as_str().to_owned());
+        if let Some(res) = self.cache.get(&key) {
+            return ok(req.into_response(res)).boxed_local();
+        }
+
+        let cache = self.cache.clone();
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            if let Some(ttl) = cache.ttl(res.response()) {
+                let body = read_body(res.take_body())
+                    .await
+                    .map_err(|e| web::error::ErrorInternalServerError(e.to_string()))?;
+                cache.put(key, res.response(), ttl, body.clone());
+                res = res.map_body(|_, _| ResponseBody::Body(Body::Bytes(body)));
+            }
+            res.headers_mut().insert(
+                HeaderName::from_static(X_CACHE),
+                HeaderValue::from_static("MISS"),
+            );
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+async fn read_body(
+    mut body: ResponseBody<Body>,
+) -> Result<Bytes, Box<dyn std::error::Error>> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = poll_fn(|cx| body.poll_next_chunk(cx)).await {
+        buf.extend_from_slice(&chunk?);
+    }
+    Ok(buf.freeze())
+}
@@ -0,0 +1,105 @@
+//! GET responses cached in memory, see `cache`.
+//!
+//! `/products/{id}` takes half a second to render, a cached copy is served
+//! at once with `X-Cache: HIT`. `PUT /products/{id}` changes a price and
+//! drops what is cached for the product, `DELETE /cache/{prefix}` drops
+//! everything under a path.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::stream;
+use ntex::http::header;
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod cache;
+
+use cache::{Cache, ResponseCache};
+
+type Prices = Mutex<HashMap<u32, u32>>;
+
+async fn product(
+    id: web::types::Path<u32>,
+    prices: web::types::Data<Prices>,
+) -> HttpResponse {
+    delay_for(Duration::from_millis(500)).await;
+    match prices.lock().unwrap().get(&id) {
+        Some(price) => {
+            HttpResponse::Ok().json(&serde_json::json!({ "id": *id, "price": price }))
+        }
+        None => HttpResponse::NotFound()
+            .json(&serde_json::json!({ "error": format!("no product {}", id) })),
+    }
+}
+
+async fn set_price(
+    id: web::types::Path<u32>,
+    price: web::types::Json<u32>,
+    prices: web::types::Data<Prices>,
+    cache: web::types::Data<Cache>,
+) -> HttpResponse {
+    prices.lock().unwrap().insert(*id, *price);
+    cache.invalidate(&format!("/products/{}", id));
+    HttpResponse::NoContent().finish()
+}
+
+/// Kept for 5 seconds instead of the default
+async fn time() -> HttpResponse {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    HttpResponse::Ok()
+        .header(header::CACHE_CONTROL, "max-age=5")
+        .body(format!("{}\n", now))
+}
+
+/// Different for everyone, never cached
+async fn me() -> HttpResponse {
+    HttpResponse::Ok()
+        .header(header::CACHE_CONTROL, "private")
+        .body("your account\n")
+}
+
+/// A streaming body, passed through and never cached
+async fn feed() -> HttpResponse {
+    let lines =
+        (1..=3).map(|n| Ok::<_, std::io::Error>(format!("entry {}\n", n).into()));
+    HttpResponse::Ok().streaming(stream::iter(lines))
+}
+
+async fn invalidate(
+    prefix: web::types::Path<String>,
+    cache: web::types::Data<Cache>,
+) -> HttpResponse {
+    let removed = cache.invalidate(&format!("/{}", prefix));
+    HttpResponse::Ok().json(&serde_json::json!({ "invalidated": removed }))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,cache=info");
+    env_logger::init();
+
+    let cache = web::types::Data::new(Cache::new(1000, Duration::from_secs(60)));
+    let prices: HashMap<u32, u32> = (1..=10).map(|id| (id, id * 100)).collect();
+    let prices = web::types::Data::new(Mutex::new(prices));
+
+    web::server(move || {
+        App::new()
+            .app_data(cache.clone())
+            .app_data(prices.clone())
+            .wrap(ResponseCache::new(cache.clone()))
+            .wrap(middleware::Logger::default())
+            .route("/products/{id}", web::get().to(product))
+            .route("/products/{id}", web::put().to(set_price))
+            .route("/time", web::get().to(time))
+            .route("/me", web::get().to(me))
+            .route("/feed", web::get().to(feed))
+            .route("/cache/{prefix:.*}", web::delete().to(invalidate))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
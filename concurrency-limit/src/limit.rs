@@ -0,0 +1,122 @@
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error, HttpResponse};
+use ntex::{Service, Transform};
+use tokio::sync::Semaphore;
+
+/// What to do with a request when all permits are taken
+#[derive(Clone, Copy, Debug)]
+pub enum WhenFull {
+    /// respond with `503` right away
+    Reject,
+    /// wait for a permit, but no longer than this
+    Queue(Duration),
+}
+
+/// Permits for one endpoint, shared by all workers
+pub struct Limiter {
+    permits: Arc<Semaphore>,
+    when_full: WhenFull,
+}
+
+impl Limiter {
+    pub fn new(limit: usize, when_full: WhenFull) -> Self {
+        Limiter {
+            permits: Arc::new(Semaphore::new(limit)),
+            when_full,
+        }
+    }
+}
+
+/// Caps the number of requests the wrapped service handles at the same time.
+///
+/// The permit is held until the response is ready, a streaming body is not
+/// counted.
+pub struct ConcurrencyLimit {
+    limiter: web::types::Data<Limiter>,
+}
+
+impl ConcurrencyLimit {
+    pub fn new(limiter: web::types::Data<Limiter>) -> Self {
+        ConcurrencyLimit { limiter }
+    }
+}
+
+impl<S, Err> Transform<S> for ConcurrencyLimit
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ConcurrencyLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ConcurrencyLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        })
+    }
+}
+
+pub struct ConcurrencyLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: web::types::Data<Limiter>,
+}
+
+impl<S, Err> Service for ConcurrencyLimitMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let service = self.service.clone();
+        let permits = self.limiter.permits.clone();
+        let when_full = self.limiter.when_full;
+
+        async move {
+            let permit = match when_full {
+                WhenFull::Reject => permits.try_acquire_owned().ok(),
+                WhenFull::Queue(max_wait) => {
+                    ntex::rt::time::timeout(max_wait, permits.acquire_owned())
+                        .await
+                        .ok()
+                }
+            };
+
+            match permit {
+                Some(_permit) => service.call(req).await,
+                None => {
+                    log::warn!("{} is at its concurrency limit", req.path());
+                    Ok(req.into_response(
+                        HttpResponse::ServiceUnavailable()
+                            .header(header::RETRY_AFTER, "1")
+                            .body("too many concurrent requests, try again later")
+                            .into_body(),
+                    ))
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,167 @@
+//! Per-endpoint concurrency limit.
+//!
+//! `/report` talks to a slow downstream resource that can only handle a few
+//! requests at a time. `ConcurrencyLimit` lets at most `LIMIT` requests
+//! through, the rest wait in line (`LIMIT_MODE=queue`, the default) or get a
+//! `503` (`LIMIT_MODE=reject`). `/` is not limited.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Serialize;
+
+mod limit;
+
+use limit::{ConcurrencyLimit, Limiter, WhenFull};
+
+/// Stand-in for a database or service that falls over under load
+struct Downstream {
+    latency: Duration,
+    in_flight: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl Downstream {
+    fn new(latency: Duration) -> Self {
+        Downstream {
+            latency,
+            in_flight: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+        }
+    }
+
+    async fn query(&self) -> usize {
+        let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.peak.fetch_max(now, Ordering::SeqCst);
+        ntex::rt::time::delay_for(self.latency).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+        now
+    }
+}
+
+#[derive(Serialize)]
+struct Stats {
+    in_flight: usize,
+    peak: usize,
+}
+
+async fn report(downstream: web::types::Data<Downstream>) -> HttpResponse {
+    let concurrent = downstream.query().await;
+    HttpResponse::Ok().body(format!("report done, {} in flight\n", concurrent))
+}
+
+async fn stats(downstream: web::types::Data<Downstream>) -> HttpResponse {
+    HttpResponse::Ok().json(&Stats {
+        in_flight: downstream.in_flight.load(Ordering::SeqCst),
+        peak: downstream.peak.load(Ordering::SeqCst),
+    })
+}
+
+fn app_config(
+    limiter: web::types::Data<Limiter>,
+) -> impl FnOnce(&mut web::ServiceConfig) {
+    move |cfg| {
+        cfg.route("/", web::get().to(stats)).service(
+            web::resource("/report")
+                .wrap(ConcurrencyLimit::new(limiter))
+                .route(web::get().to(report)),
+        );
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,concurrency_limit=info");
+    env_logger::init();
+
+    let limit = std::env::var("LIMIT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(4);
+    let when_full = match std::env::var("LIMIT_MODE").as_deref() {
+        Ok("reject") => WhenFull::Reject,
+        _ => WhenFull::Queue(Duration::from_secs(5)),
+    };
+    log::info!(
+        "/report allows {} concurrent requests, {:?}",
+        limit,
+        when_full
+    );
+
+    // both are shared, the limit applies to the whole server, not per worker
+    let limiter = web::types::Data::new(Limiter::new(limit, when_full));
+    let downstream = web::types::Data::new(Downstream::new(Duration::from_secs(1)));
+
+    web::server(move || {
+        App::new()
+            .app_data(downstream.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config(limiter.clone()))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::join_all;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    /// Send 5 concurrent requests to an endpoint limited to 2
+    async fn burst(when_full: WhenFull) -> (Vec<StatusCode>, usize) {
+        let downstream =
+            web::types::Data::new(Downstream::new(Duration::from_millis(100)));
+        let limiter = web::types::Data::new(Limiter::new(2, when_full));
+        let app = test::init_service(
+            App::new()
+                .app_data(downstream.clone())
+                .configure(app_config(limiter)),
+        )
+        .await;
+
+        let requests = (0..5).map(|_| {
+            let req = test::TestRequest::get().uri("/report").to_request();
+            test::call_service(&app, req)
+        });
+        let statuses = join_all(requests)
+            .await
+            .iter()
+            .map(|resp| resp.status())
+            .collect();
+        (statuses, downstream.peak.load(Ordering::SeqCst))
+    }
+
+    #[ntex::test]
+    async fn test_reject_when_full() {
+        let (statuses, peak) = burst(WhenFull::Reject).await;
+
+        let ok = statuses.iter().filter(|s| **s == StatusCode::OK).count();
+        let rejected = statuses
+            .iter()
+            .filter(|s| **s == StatusCode::SERVICE_UNAVAILABLE)
+            .count();
+        assert_eq!((ok, rejected), (2, 3));
+        assert_eq!(peak, 2);
+    }
+
+    #[ntex::test]
+    async fn test_queue_when_full() {
+        let (statuses, peak) = burst(WhenFull::Queue(Duration::from_secs(5))).await;
+
+        assert!(statuses.iter().all(|s| *s == StatusCode::OK));
+        assert_eq!(peak, 2);
+    }
+
+    #[ntex::test]
+    async fn test_queue_timeout() {
+        // 3 rounds of 100ms are needed, but requests only wait 150ms
+        let (statuses, peak) = burst(WhenFull::Queue(Duration::from_millis(150))).await;
+
+        let ok = statuses.iter().filter(|s| **s == StatusCode::OK).count();
+        assert_eq!(ok, 4);
+        assert_eq!(peak, 2);
+    }
+}
@@ -0,0 +1,132 @@
+//! Typed settings loaded in layers at startup, see `settings`. They pick the
+//! address, the number of workers and the log level, and the handlers get
+//! them as `Data<Settings>`.
+use std::io;
+use std::path::PathBuf;
+
+use clap::Arg;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod settings;
+
+use settings::{Overrides, Settings};
+
+async fn index(settings: web::types::Data<Settings>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "name": settings.app.name,
+        "env": settings.env,
+    }))
+}
+
+/// The settings as they ended up after all the layers
+async fn show_settings(settings: web::types::Data<Settings>) -> HttpResponse {
+    HttpResponse::Ok().json(settings.get_ref())
+}
+
+#[derive(Deserialize)]
+struct Page {
+    page: Option<usize>,
+}
+
+async fn items(
+    settings: web::types::Data<Settings>,
+    query: web::types::Query<Page>,
+) -> HttpResponse {
+    let size = settings.app.page_size;
+    let page = query.page.unwrap_or(1).max(1);
+    // the items of the last page still have to fit into a usize
+    let start = match (page - 1)
+        .checked_mul(size)
+        .filter(|start| start.checked_add(size + 1).is_some())
+    {
+        Some(start) => start + 1,
+        None => {
+            return HttpResponse::BadRequest()
+                .json(&serde_json::json!({ "error": "page is out of range" }))
+        }
+    };
+    let items: Vec<usize> = (start..start + size).collect();
+    HttpResponse::Ok().json(&serde_json::json!({ "page": page, "items": items }))
+}
+
+fn overrides() -> Overrides {
+    let matches = clap::App::new("config")
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("the base settings file, config.toml by default"),
+        )
+        .arg(
+            Arg::with_name("env")
+                .long("env")
+                .takes_value(true)
+                .possible_values(&["dev", "prod"]),
+        )
+        .arg(
+            Arg::with_name("bind")
+                .long("bind")
+                .takes_value(true)
+                .value_name("ADDR"),
+        )
+        .arg(
+            Arg::with_name("workers")
+                .long("workers")
+                .takes_value(true)
+                .value_name("N"),
+        )
+        .arg(
+            Arg::with_name("log-level")
+                .long("log-level")
+                .takes_value(true)
+                .value_name("LEVEL"),
+        )
+        .get_matches();
+
+    let value = |name| matches.value_of(name).map(str::to_owned);
+    Overrides {
+        config: matches.value_of_os("config").map(PathBuf::from),
+        env: value("env"),
+        bind: value("bind"),
+        workers: value("workers"),
+        log_level: value("log-level"),
+    }
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    let settings = Settings::load(overrides())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+
+    std::env::set_var(
+        "RUST_LOG",
+        format!("ntex={0},config={0}", settings.log.level),
+    );
+    env_logger::init();
+    log::info!(
+        "{} settings: {}, {} workers, log level {}",
+        settings.env.as_str(),
+        settings.server.bind,
+        settings.server.workers,
+        settings.log.level
+    );
+
+    let bind = settings.server.bind;
+    let workers = settings.server.workers;
+    let settings = web::types::Data::new(settings);
+
+    web::server(move || {
+        App::new()
+            .app_data(settings.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/settings", web::get().to(show_settings))
+            .route("/items", web::get().to(items))
+    })
+    .workers(workers)
+    .bind(bind)?
+    .run()
+    .await
+}
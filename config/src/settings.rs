@@ -0,0 +1,166 @@
+//! The settings, in layers, each overriding the one before:
+//!
+//! * `config.toml`, or the file given with `--config`
+//! * `config.<env>.toml` next to it, if there is one, `<env>` is `dev` or
+//!   `prod`
+//! * `APP_` environment variables, `__` between the parts of a key, so
+//!   `APP_SERVER__WORKERS=4` is `server.workers`
+//! * command line flags
+//!
+//! The environment is picked before anything is loaded, from `--env`, then
+//! `APP_ENV`, `dev` when neither is set. The result is checked once at
+//! startup, a server with bad settings doesn't start.
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+use config::{Config, ConfigError, Environment, File, FileFormat, FileSourceFile};
+use derive_more::Display;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Display)]
+pub enum SettingsError {
+    #[display(fmt = "{}", _0)]
+    Load(ConfigError),
+    #[display(fmt = "APP_ENV: `{}` is neither dev nor prod", _0)]
+    Env(String),
+    #[display(fmt = "server.workers: {} is not between 1 and 64", _0)]
+    Workers(usize),
+    #[display(fmt = "log.level: `{}` is not a log level", _0)]
+    LogLevel(String),
+    #[display(fmt = "app.page_size: {} is not between 1 and 100", _0)]
+    PageSize(usize),
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<ConfigError> for SettingsError {
+    fn from(e: ConfigError) -> Self {
+        SettingsError::Load(e)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Env {
+    Dev,
+    Prod,
+}
+
+impl Env {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Env::Dev => "dev",
+            Env::Prod => "prod",
+        }
+    }
+}
+
+impl std::str::FromStr for Env {
+    type Err = SettingsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dev" => Ok(Env::Dev),
+            "prod" => Ok(Env::Prod),
+            _ => Err(SettingsError::Env(s.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Server {
+    pub bind: SocketAddr,
+    pub workers: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Log {
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct App {
+    pub name: String,
+    /// Items per page of `/items`
+    pub page_size: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Settings {
+    pub env: Env,
+    pub server: Server,
+    pub log: Log,
+    pub app: App,
+}
+
+/// What the command line has to say, `None` for a flag that isn't given
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub config: Option<PathBuf>,
+    pub env: Option<String>,
+    pub bind: Option<String>,
+    pub workers: Option<String>,
+    pub log_level: Option<String>,
+}
+
+impl Settings {
+    pub fn load(overrides: Overrides) -> Result<Self, SettingsError> {
+        let env: Env = match overrides.env {
+            Some(env) => env.parse()?,
+            None => match std::env::var("APP_ENV") {
+                Ok(env) => env.parse()?,
+                Err(_) => Env::Dev,
+            },
+        };
+        let base = overrides
+            .config
+            .unwrap_or_else(|| PathBuf::from("config.toml"));
+
+        let mut cfg = Config::new();
+        cfg.merge(toml_file(&base).required(true))?;
+        cfg.merge(toml_file(&env_file(&base, env)).required(false))?;
+        cfg.merge(Environment::with_prefix("APP").separator("__"))?;
+
+        // the flags win over everything, the environment as picked above
+        // over `APP_ENV` and the files
+        cfg.set("env", env.as_str())?;
+        if let Some(bind) = overrides.bind {
+            cfg.set("server.bind", bind)?;
+        }
+        if let Some(workers) = overrides.workers {
+            cfg.set("server.workers", workers)?;
+        }
+        if let Some(level) = overrides.log_level {
+            cfg.set("log.level", level)?;
+        }
+
+        let settings: Settings = cfg.try_into()?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// What deserializing doesn't check
+    fn validate(&self) -> Result<(), SettingsError> {
+        if !(1..=64).contains(&self.server.workers) {
+            return Err(SettingsError::Workers(self.server.workers));
+        }
+        if self.log.level.parse::<log::LevelFilter>().is_err() {
+            return Err(SettingsError::LogLevel(self.log.level.clone()));
+        }
+        if !(1..=100).contains(&self.app.page_size) {
+            return Err(SettingsError::PageSize(self.app.page_size));
+        }
+        Ok(())
+    }
+}
+
+fn toml_file(path: &Path) -> File<FileSourceFile> {
+    File::from(path).format(FileFormat::Toml)
+}
+
+/// `config.toml` gives `config.prod.toml` in the same directory
+fn env_file(base: &Path, env: Env) -> PathBuf {
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    base.with_file_name(format!("{}.{}.toml", stem, env.as_str()))
+}
@@ -0,0 +1,186 @@
+//! Typed access to the request cookies, and setting several at once.
+//!
+//! `CookieJar` parses the `Cookie` headers itself. ntex's `cookies()` gives
+//! up on all of them at the first malformed pair, here a malformed pair or a
+//! header that isn't utf-8 is skipped and counted, the rest are still read.
+//! Values are percent-decoded.
+//!
+//! `CookieWriter` holds the attributes once and gives them to every cookie
+//! it sets or clears. A browser only clears a cookie whose path and domain
+//! match the ones it was set with, sharing them makes that hard to get wrong.
+use std::convert::Infallible;
+use std::str::FromStr;
+
+use cookie::{Cookie, SameSite};
+use futures::future::{ok, Ready};
+use ntex::http::header::{self, HeaderMap};
+use ntex::http::{Payload, ResponseBuilder};
+use ntex::web::{FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+#[derive(Debug, Default)]
+pub struct CookieJar {
+    /// In header order, a browser sends the cookie with the longest path first
+    cookies: Vec<(String, String)>,
+    malformed: usize,
+}
+
+impl CookieJar {
+    pub fn parse(headers: &HeaderMap) -> Self {
+        let mut jar = CookieJar::default();
+        for value in headers.get_all(header::COOKIE) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                Err(_) => {
+                    jar.malformed += 1;
+                    continue;
+                }
+            };
+            for pair in value.split(';').map(str::trim).filter(|p| !p.is_empty()) {
+                match Cookie::parse_encoded(pair) {
+                    Ok(cookie) => jar
+                        .cookies
+                        .push((cookie.name().to_owned(), cookie.value().to_owned())),
+                    Err(e) => {
+                        log::debug!("skipping cookie `{}`: {}", pair, e);
+                        jar.malformed += 1;
+                    }
+                }
+            }
+        }
+        jar
+    }
+
+    /// The first cookie called `name`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// `None` if it is missing or doesn't parse
+    pub fn get_as<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.get(name)?.parse().ok()
+    }
+
+    /// `None` if it is missing or isn't valid json for `T`
+    pub fn get_json<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        serde_json::from_str(self.get(name)?).ok()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.cookies.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Pairs and headers that were skipped
+    pub fn malformed(&self) -> usize {
+        self.malformed
+    }
+}
+
+impl<Err> FromRequest<Err> for CookieJar {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Infallible>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ok(CookieJar::parse(req.headers()))
+    }
+}
+
+#[derive(Clone)]
+pub struct CookieWriter {
+    path: String,
+    secure: bool,
+    http_only: bool,
+    same_site: SameSite,
+    max_age: Option<time::Duration>,
+}
+
+impl Default for CookieWriter {
+    fn default() -> Self {
+        CookieWriter {
+            path: "/".to_owned(),
+            secure: false,
+            http_only: true,
+            same_site: SameSite::Lax,
+            max_age: None,
+        }
+    }
+}
+
+impl CookieWriter {
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    /// Without one they are session cookies, gone when the browser closes
+    pub fn max_age(mut self, max_age: time::Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Starts a batch of cookies for one response
+    pub fn batch(&self) -> CookieBatch {
+        CookieBatch {
+            writer: self.clone(),
+            cookies: Vec::new(),
+        }
+    }
+
+    fn build(&self, name: &str, value: String) -> Cookie<'static> {
+        let mut cookie = Cookie::build(name.to_owned(), value)
+            .path(self.path.clone())
+            .secure(self.secure)
+            .http_only(self.http_only)
+            .same_site(self.same_site)
+            .finish();
+        if let Some(max_age) = self.max_age {
+            cookie.set_max_age(max_age);
+        }
+        cookie
+    }
+}
+
+/// Cookies to set and clear, written by `apply`
+pub struct CookieBatch {
+    writer: CookieWriter,
+    cookies: Vec<Cookie<'static>>,
+}
+
+impl CookieBatch {
+    pub fn set(mut self, name: &str, value: impl ToString) -> Self {
+        self.cookies
+            .push(self.writer.build(name, value.to_string()));
+        self
+    }
+
+    pub fn set_json<T: Serialize>(self, name: &str, value: &T) -> Self {
+        let value = serde_json::to_string(value).unwrap();
+        self.set(name, value)
+    }
+
+    /// An empty cookie that has already expired
+    pub fn clear(mut self, name: &str) -> Self {
+        let mut cookie = self.writer.build(name, String::new());
+        cookie.set_max_age(time::Duration::zero());
+        cookie.set_expires(time::OffsetDateTime::unix_epoch());
+        self.cookies.push(cookie);
+        self
+    }
+
+    /// Adds a `Set-Cookie` header for each. The builder's own `cookie()`
+    /// doesn't percent-encode, a value with `;` or `"` would break the header
+    pub fn apply(self, res: &mut ResponseBuilder) {
+        for cookie in self.cookies {
+            res.header(header::SET_COOKIE, cookie.encoded().to_string());
+        }
+    }
+}
@@ -0,0 +1,204 @@
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod jar;
+
+use jar::{CookieJar, CookieWriter};
+
+const PREFERENCES: &str = "preferences";
+const VISITS: &str = "visits";
+const THEME: &str = "theme";
+
+#[derive(Debug, Deserialize, PartialEq, Serialize)]
+#[serde(default)]
+struct Preferences {
+    theme: String,
+    language: String,
+    page_size: u32,
+}
+
+impl Default for Preferences {
+    fn default() -> Self {
+        Preferences {
+            theme: "light".to_owned(),
+            language: "en".to_owned(),
+            page_size: 20,
+        }
+    }
+}
+
+/// `Secure` cookies aren't sent over plain http, set `SECURE_COOKIES` when
+/// it runs behind https
+fn cookies() -> CookieWriter {
+    CookieWriter::default()
+        .secure(std::env::var_os("SECURE_COOKIES").is_some())
+        .max_age(time::Duration::days(365))
+}
+
+/// Counts the visit, and shows what the jar made of the request's cookies
+async fn index(jar: CookieJar) -> HttpResponse {
+    // a cookie from an older version, or edited by hand, falls back too
+    let preferences: Preferences = jar.get_json(PREFERENCES).unwrap_or_default();
+    let visits = jar.get_as::<u32>(VISITS).unwrap_or(0).saturating_add(1);
+
+    let mut res = HttpResponse::Ok();
+    cookies().batch().set(VISITS, visits).apply(&mut res);
+    res.json(&serde_json::json!({
+        "preferences": preferences,
+        "visits": visits,
+        "cookies": jar.names().collect::<Vec<_>>(),
+        "malformed": jar.malformed(),
+    }))
+}
+
+/// Stores the preferences, and the theme on its own where scripts can read
+/// it before the page is drawn
+async fn save_preferences(preferences: web::types::Json<Preferences>) -> HttpResponse {
+    let mut res = HttpResponse::Ok();
+    let writer = cookies();
+    writer
+        .batch()
+        .set_json(PREFERENCES, &*preferences)
+        .apply(&mut res);
+    writer
+        .http_only(false)
+        .batch()
+        .set(THEME, &preferences.theme)
+        .apply(&mut res);
+    res.json(&*preferences)
+}
+
+/// Clears every cookie the request came with
+async fn forget(jar: CookieJar) -> HttpResponse {
+    let mut batch = cookies().batch();
+    for name in jar.names() {
+        batch = batch.clear(name);
+    }
+    let mut res = HttpResponse::NoContent();
+    batch.apply(&mut res);
+    res.finish()
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/preferences", web::put().to(save_preferences))
+        .route("/cookies", web::delete().to(forget));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,cookie_jar=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use ntex::http::header::{self, HeaderValue};
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    /// `Set-Cookie` headers by cookie name
+    fn set_cookies(res: &ntex::web::dev::WebResponse) -> HashMap<String, String> {
+        res.headers()
+            .get_all(header::SET_COOKIE)
+            .map(|v| {
+                let v = v.to_str().unwrap();
+                (v.split('=').next().unwrap().to_owned(), v.to_owned())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_malformed_cookies_are_skipped() {
+        let mut headers = header::HeaderMap::new();
+        headers.append(
+            header::COOKIE,
+            HeaderValue::from_static("visits=3; garbage; =empty; theme=dark"),
+        );
+        headers.append(header::COOKIE, HeaderValue::from_bytes(b"x=\xff").unwrap());
+        headers.append(header::COOKIE, HeaderValue::from_static("lang=d%C3%A9"));
+
+        let jar = CookieJar::parse(&headers);
+        assert_eq!(jar.names().collect::<Vec<_>>(), ["visits", "theme", "lang"]);
+        assert_eq!(jar.malformed(), 3);
+        assert_eq!(jar.get_as::<u32>("visits"), Some(3));
+        assert_eq!(jar.get_as::<u32>("theme"), None);
+        assert_eq!(jar.get("lang"), Some("dé"));
+    }
+
+    #[ntex::test]
+    async fn test_preferences_round_trip() {
+        let app = test::init_service(App::new().configure(app)).await;
+
+        let preferences = Preferences {
+            theme: "dark".to_owned(),
+            language: "de".to_owned(),
+            page_size: 50,
+        };
+        let req = test::TestRequest::put()
+            .uri("/preferences")
+            .set_json(&preferences)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let set = set_cookies(&res);
+        assert_eq!(set.len(), 2);
+        assert!(set["preferences"].starts_with("preferences=%7B%22theme%22"));
+        assert!(set["preferences"].contains("HttpOnly"));
+        assert!(set["theme"].starts_with("theme=dark;"));
+        assert!(!set["theme"].contains("HttpOnly"));
+        for cookie in set.values() {
+            assert!(cookie.contains("Path=/") && cookie.contains("Max-Age=31536000"));
+        }
+
+        // sent back like a browser would, with a broken pair in between
+        let preferences_cookie = set["preferences"].split(';').next().unwrap();
+        let req = test::TestRequest::get()
+            .header(
+                header::COOKIE,
+                format!("{}; broken; visits=41", preferences_cookie),
+            )
+            .to_request();
+        let res: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(res["preferences"], serde_json::json!(preferences));
+        assert_eq!(res["visits"], 42);
+        assert_eq!(res["malformed"], 1);
+
+        let req = test::TestRequest::get()
+            .header(header::COOKIE, "preferences=not-json")
+            .to_request();
+        let res: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(
+            res["preferences"],
+            serde_json::json!(Preferences::default())
+        );
+    }
+
+    #[ntex::test]
+    async fn test_forget_clears_every_cookie() {
+        let app = test::init_service(App::new().configure(app)).await;
+        let req = test::TestRequest::delete()
+            .uri("/cookies")
+            .header(header::COOKIE, "visits=2; theme=dark")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        let set = set_cookies(&res);
+        assert!(set["visits"].starts_with("visits=;"));
+        assert!(set["theme"].starts_with("theme=;"));
+        for cookie in set.values() {
+            assert!(cookie.contains("Max-Age=0") && cookie.contains("Path=/"));
+        }
+    }
+}
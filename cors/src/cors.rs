@@ -0,0 +1,270 @@
+//! Cross-origin resource sharing.
+//!
+//! `Cors` is the policy, built like the other middlewares of these examples
+//! and given to `wrap`. A preflight, an `OPTIONS` request with `Origin` and
+//! `Access-Control-Request-Method`, is answered by the middleware itself and
+//! never reaches the inner service, there is no route for it: `204` with the
+//! allowed methods and headers if the policy allows the request, `403`
+//! without any CORS headers if it does not. Other requests are passed on,
+//! and get `Access-Control-Allow-Origin` on the way back if their origin is
+//! allowed. Requests without `Origin` are not cross-origin, nothing changes
+//! for them.
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header::{self, HeaderName, HeaderValue};
+use ntex::http::Method;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, HttpResponse};
+use ntex::{Service, Transform};
+
+#[derive(Clone)]
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+#[derive(Clone)]
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    expose_headers: Vec<HeaderName>,
+    max_age: Option<u64>,
+    credentials: bool,
+}
+
+impl Default for Cors {
+    /// No origin is allowed, `GET`, `HEAD` and `POST` once one is
+    fn default() -> Self {
+        Cors {
+            origins: Origins::List(Vec::new()),
+            methods: vec![Method::GET, Method::HEAD, Method::POST],
+            headers: Vec::new(),
+            expose_headers: Vec::new(),
+            max_age: None,
+            credentials: false,
+        }
+    }
+}
+
+impl Cors {
+    /// Adds an origin, `https://app.example.com`, scheme and port included
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        match self.origins {
+            Origins::Any => (),
+            Origins::List(ref mut list) => list.push(origin.to_owned()),
+        }
+        self
+    }
+
+    pub fn allow_any_origin(mut self) -> Self {
+        self.origins = Origins::Any;
+        self
+    }
+
+    /// Replaces the methods allowed
+    pub fn allow_methods(mut self, methods: &[Method]) -> Self {
+        self.methods = methods.to_vec();
+        self
+    }
+
+    /// Request headers the browser may send other than the ones it always
+    /// may, `content-type: application/json` needs `content-type` here
+    pub fn allow_headers(mut self, headers: &[HeaderName]) -> Self {
+        self.headers = headers.to_vec();
+        self
+    }
+
+    /// Response headers scripts may read other than the simple ones
+    pub fn expose_headers(mut self, headers: &[HeaderName]) -> Self {
+        self.expose_headers = headers.to_vec();
+        self
+    }
+
+    /// How long browsers may keep a preflight's answer, in seconds
+    pub fn max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    /// Lets requests carry cookies and `Authorization`. The response then
+    /// names the origin, `*` is not accepted with credentials.
+    pub fn supports_credentials(mut self) -> Self {
+        self.credentials = true;
+        self
+    }
+
+    fn allows(&self, origin: &HeaderValue) -> bool {
+        match &self.origins {
+            Origins::Any => true,
+            Origins::List(list) => list.iter().any(|allowed| origin == allowed.as_str()),
+        }
+    }
+
+    /// `Access-Control-Allow-Origin` for an allowed origin
+    fn allow_origin_value(&self, origin: &HeaderValue) -> HeaderValue {
+        match self.origins {
+            Origins::Any if !self.credentials => HeaderValue::from_static("*"),
+            _ => origin.clone(),
+        }
+    }
+
+    /// Why a preflight is rejected, if it is
+    fn check_preflight(&self, req: &WebRequest<impl Sized>) -> Result<(), String> {
+        let method = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|v| Method::from_bytes(v.as_bytes()).ok());
+        match method {
+            Some(method) if self.methods.contains(&method) => (),
+            Some(method) => return Err(format!("method {} not allowed", method)),
+            None => return Err("invalid access-control-request-method".to_owned()),
+        }
+
+        let requested = req
+            .headers()
+            .get(header::ACCESS_CONTROL_REQUEST_HEADERS)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("");
+        for name in requested
+            .split(',')
+            .map(str::trim)
+            .filter(|n| !n.is_empty())
+        {
+            let name = name.to_ascii_lowercase();
+            if !self.headers.iter().any(|allowed| allowed.as_str() == name) {
+                return Err(format!("header {} not allowed", name));
+            }
+        }
+        Ok(())
+    }
+
+    fn preflight(&self, origin: &HeaderValue) -> HttpResponse {
+        let methods: Vec<&str> = self.methods.iter().map(Method::as_str).collect();
+        let headers: Vec<&str> = self.headers.iter().map(HeaderName::as_str).collect();
+
+        let mut res = HttpResponse::NoContent();
+        res.header(
+            header::ACCESS_CONTROL_ALLOW_ORIGIN,
+            self.allow_origin_value(origin),
+        )
+        .header(header::ACCESS_CONTROL_ALLOW_METHODS, methods.join(", "))
+        .header(
+            header::VARY,
+            "origin, access-control-request-method, access-control-request-headers",
+        );
+        if !headers.is_empty() {
+            res.header(header::ACCESS_CONTROL_ALLOW_HEADERS, headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            res.header(header::ACCESS_CONTROL_MAX_AGE, max_age.to_string());
+        }
+        if self.credentials {
+            res.header(header::ACCESS_CONTROL_ALLOW_CREDENTIALS, "true");
+        }
+        res.finish()
+    }
+}
+
+impl<S, Err> Transform<S> for Cors
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CorsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CorsMiddleware {
+            service,
+            cors: Rc::new(self.clone()),
+        })
+    }
+}
+
+pub struct CorsMiddleware<S> {
+    service: S,
+    cors: Rc<Cors>,
+}
+
+impl<S, Err> Service for CorsMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) => origin.clone(),
+            None => return self.service.call(req).boxed_local(),
+        };
+
+        let preflight = req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+        if preflight {
+            let checked = if self.cors.allows(&origin) {
+                self.cors.check_preflight(&req)
+            } else {
+                Err("origin not allowed".to_owned())
+            };
+            let res = match checked {
+                Ok(()) => self.cors.preflight(&origin),
+                Err(reason) => {
+                    log::info!("preflight from {:?} rejected: {}", origin, reason);
+                    HttpResponse::Forbidden()
+                        .body(format!("CORS preflight rejected: {}", reason))
+                }
+            };
+            return ok(req.into_response(res)).boxed_local();
+        }
+
+        let cors = self.cors.clone();
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            let headers = res.headers_mut();
+            // caches must not hand this response to another origin
+            headers.append(header::VARY, HeaderValue::from_static("origin"));
+            if !cors.allows(&origin) {
+                return Ok(res);
+            }
+
+            headers.insert(
+                header::ACCESS_CONTROL_ALLOW_ORIGIN,
+                cors.allow_origin_value(&origin),
+            );
+            if cors.credentials {
+                headers.insert(
+                    header::ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                    HeaderValue::from_static("true"),
+                );
+            }
+            if !cors.expose_headers.is_empty() {
+                let names: Vec<&str> =
+                    cors.expose_headers.iter().map(HeaderName::as_str).collect();
+                headers.insert(
+                    header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                    HeaderValue::from_str(&names.join(", ")).unwrap(),
+                );
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,73 @@
+//! Two CORS policies, see `cors`: `/api` is for the two front ends named in
+//! `api_cors`, with cookies, `/public` is for anyone.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ntex::http::header::{self, HeaderName};
+use ntex::http::Method;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod cors;
+
+use cors::Cors;
+
+async fn items() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!([{ "id": 1, "name": "first" }]))
+}
+
+async fn create_item(
+    item: web::types::Json<serde_json::Value>,
+    ids: web::types::Data<AtomicU64>,
+) -> HttpResponse {
+    let id = ids.fetch_add(1, Ordering::Relaxed) + 1;
+    HttpResponse::Created()
+        .header("x-request-id", id.to_string())
+        .json(&serde_json::json!({ "id": id, "item": item.into_inner() }))
+}
+
+async fn delete_item(_: web::types::Path<u64>) -> HttpResponse {
+    HttpResponse::NoContent().finish()
+}
+
+async fn status() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "status": "ok" }))
+}
+
+fn api_cors() -> Cors {
+    Cors::default()
+        .allow_origin("http://localhost:3000")
+        .allow_origin("https://app.example.com")
+        .allow_methods(&[Method::GET, Method::POST, Method::DELETE])
+        .allow_headers(&[header::CONTENT_TYPE, header::AUTHORIZATION])
+        .expose_headers(&[HeaderName::from_static("x-request-id")])
+        .max_age(3600)
+        .supports_credentials()
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,cors=info");
+    env_logger::init();
+
+    let ids = web::types::Data::new(AtomicU64::new(1));
+
+    web::server(move || {
+        App::new()
+            .app_data(ids.clone())
+            .wrap(middleware::Logger::default())
+            .service(
+                web::scope("/api")
+                    .wrap(api_cors())
+                    .route("/items", web::get().to(items))
+                    .route("/items", web::post().to(create_item))
+                    .route("/items/{id}", web::delete().to(delete_item)),
+            )
+            .service(
+                web::scope("/public")
+                    .wrap(Cors::default().allow_any_origin().max_age(86400))
+                    .route("/status", web::get().to(status)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
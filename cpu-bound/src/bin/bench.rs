@@ -0,0 +1,74 @@
+//! Measures `/ping` latency while logins are running.
+//!
+//! ```bash
+//! cargo run --release --bin bench
+//! ```
+use std::time::{Duration, Instant};
+
+use ntex::http::client::Client;
+
+const SERVER: &str = "http://127.0.0.1:8080";
+/// Concurrent login requests
+const LOGINS: usize = 4;
+const PINGS: usize = 200;
+
+#[ntex::main]
+async fn main() {
+    let client = Client::default();
+    let user = serde_json::json!({ "username": "bench", "password": "hunter2" });
+
+    let res = client
+        .post(format!("{}/register", SERVER))
+        .send_json(&user)
+        .await
+        .expect("is the server running? start it with `cargo run --release`");
+    assert!(res.status().is_success());
+
+    println!("no logins   {}", measure("", &user).await);
+    println!("offloaded   {}", measure("/login", &user).await);
+    println!("inline      {}", measure("/login-inline", &user).await);
+}
+
+/// Ping latency percentiles while `LOGINS` clients hammer `login`
+async fn measure(login: &'static str, user: &serde_json::Value) -> String {
+    let running = std::rc::Rc::new(std::cell::Cell::new(true));
+
+    if !login.is_empty() {
+        for _ in 0..LOGINS {
+            let running = running.clone();
+            let user = user.clone();
+            ntex::rt::spawn(async move {
+                let client = Client::default();
+                while running.get() {
+                    let _ = client
+                        .post(format!("{}{}", SERVER, login))
+                        .send_json(&user)
+                        .await;
+                }
+            });
+        }
+        // let the logins get going
+        ntex::rt::time::delay_for(Duration::from_millis(200)).await;
+    }
+
+    let client = Client::default();
+    let mut latencies = Vec::with_capacity(PINGS);
+    for _ in 0..PINGS {
+        let start = Instant::now();
+        let _ = client.get(format!("{}/ping", SERVER)).send().await;
+        latencies.push(start.elapsed());
+        ntex::rt::time::delay_for(Duration::from_millis(5)).await;
+    }
+    running.set(false);
+    // wait for the in-flight logins before the next round
+    ntex::rt::time::delay_for(Duration::from_millis(500)).await;
+
+    latencies.sort();
+    let pct = |p: usize| latencies[(latencies.len() * p / 100).min(latencies.len() - 1)];
+    format!(
+        "p50 {:>8.2?}  p99 {:>8.2?}  max {:>8.2?}",
+        pct(50),
+        pct(99),
+        latencies[latencies.len() - 1]
+    )
+}
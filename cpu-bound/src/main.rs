@@ -0,0 +1,121 @@
+//! Keeping cpu heavy work off the event loop.
+//!
+//! Password hashing with argon2 takes tens of milliseconds on purpose. Done
+//! inside a handler it blocks the worker, and every other request on that
+//! worker, ping included, waits. `web::block` runs it on the thread pool
+//! instead, so the worker keeps serving light requests.
+//!
+//! `POST /login` verifies on the thread pool, `POST /login-inline` on the
+//! worker thread. Run `cargo run --release --bin bench` against both.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+use rand::RngCore;
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+struct Credentials {
+    username: String,
+    password: String,
+}
+
+/// Password hashes by user name
+#[derive(Default)]
+struct Users(Mutex<HashMap<String, String>>);
+
+impl Users {
+    fn hash(&self, username: &str) -> Option<String> {
+        self.0.lock().unwrap().get(username).cloned()
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, argon2::Error> {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+    argon2::hash_encoded(password.as_bytes(), &salt, &argon2::Config::default())
+}
+
+fn verify_password(hash: Option<&str>, password: &str) -> Result<bool, argon2::Error> {
+    match hash {
+        Some(hash) => argon2::verify_encoded(hash, password.as_bytes()),
+        // unknown user, don't answer faster than for a wrong password
+        None => hash_password(password).map(|_| false),
+    }
+}
+
+fn login_response(valid: bool) -> HttpResponse {
+    if valid {
+        HttpResponse::Ok().body("welcome")
+    } else {
+        HttpResponse::Unauthorized().body("invalid user name or password")
+    }
+}
+
+async fn register(
+    users: web::types::Data<Users>,
+    creds: web::types::Json<Credentials>,
+) -> Result<HttpResponse, Error> {
+    let creds = creds.into_inner();
+    let password = creds.password;
+    let hash = web::block(move || hash_password(&password))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+
+    users.0.lock().unwrap().insert(creds.username, hash);
+    Ok(HttpResponse::Created().finish())
+}
+
+/// Verify on the thread pool, the worker is free while argon2 runs
+async fn login(
+    users: web::types::Data<Users>,
+    creds: web::types::Json<Credentials>,
+) -> Result<HttpResponse, Error> {
+    let hash = users.hash(&creds.username);
+    let password = creds.into_inner().password;
+
+    let valid = web::block(move || verify_password(hash.as_deref(), &password))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(login_response(valid))
+}
+
+/// Don't do this: verifies on the worker thread and stalls it
+async fn login_inline(
+    users: web::types::Data<Users>,
+    creds: web::types::Json<Credentials>,
+) -> Result<HttpResponse, Error> {
+    let hash = users.hash(&creds.username);
+
+    let valid = verify_password(hash.as_deref(), &creds.password)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(login_response(valid))
+}
+
+async fn ping() -> &'static str {
+    "pong"
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    let users = web::types::Data::new(Users::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(users.clone())
+            .wrap(middleware::Logger::default())
+            .route("/register", web::post().to(register))
+            .route("/login", web::post().to(login))
+            .route("/login-inline", web::post().to(login_inline))
+            .route("/ping", web::get().to(ping))
+    })
+    // a single worker makes the effect easy to see, with more workers a
+    // blocked one still delays every request that lands on it
+    .workers(1)
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,215 @@
+//! Double submit cookie CSRF protection.
+//!
+//! A safe request, `GET`, `HEAD`, `OPTIONS` or `TRACE`, without a valid
+//! token cookie gets one, `<random>.<hmac of random>`. A mutating request
+//! has to send the same token twice, in the cookie and in the
+//! `X-CSRF-Token` header, it is answered with `403` otherwise.
+//!
+//! Another site can make the browser send the cookie along, it can't read
+//! the cookie and so can't put it in a header. The signature keeps out
+//! tokens that weren't made here, one planted in the cookie from a sibling
+//! subdomain, say. The cookie is not `HttpOnly`, the page's own script reads
+//! it, or `CsrfToken` puts it into the page.
+use std::convert::Infallible;
+use std::task::{Context, Poll};
+
+use cookie::{Cookie, SameSite};
+use futures::future::{ok, Either, FutureExt, LocalBoxFuture, Ready};
+use hmac::{Hmac, Mac, NewMac};
+use ntex::http::{HttpMessage, Method, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, FromRequest, HttpRequest, HttpResponse};
+use ntex::{Service, Transform};
+use rand::Rng;
+use sha2::Sha256;
+
+pub const COOKIE: &str = "csrf-token";
+pub const HEADER: &str = "x-csrf-token";
+
+fn mac(key: &[u8], nonce: &[u8]) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("any key length works");
+    mac.update(nonce);
+    mac
+}
+
+fn issue(key: &[u8]) -> String {
+    let nonce: [u8; 16] = rand::thread_rng().gen();
+    let code = mac(key, &nonce).finalize().into_bytes();
+    format!("{}.{}", hex::encode(nonce), hex::encode(code))
+}
+
+fn is_signed(key: &[u8], token: &str) -> bool {
+    let mut parts = token.splitn(2, '.');
+    match (parts.next().map(hex::decode), parts.next().map(hex::decode)) {
+        (Some(Ok(nonce)), Some(Ok(code))) => mac(key, &nonce).verify(&code).is_ok(),
+        _ => false,
+    }
+}
+
+/// Doesn't stop at the first difference, how long it takes tells nothing
+/// about how much of the header was right
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE
+    )
+}
+
+fn cookie(token: String) -> Cookie<'static> {
+    Cookie::build(COOKIE, token)
+        .path("/")
+        .http_only(false)
+        .same_site(SameSite::Strict)
+        .finish()
+}
+
+/// The token of the current request, to put into a page, in a `<meta>`
+/// tag or a hidden field, for its script to send back in the header
+#[derive(Clone)]
+pub struct CsrfToken(pub String);
+
+impl<Err> FromRequest<Err> for CsrfToken {
+    type Error = Infallible;
+    type Future = Ready<Result<Self, Infallible>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // set by `Csrf`, the empty token matches nothing
+        ok(req
+            .extensions()
+            .get::<CsrfToken>()
+            .cloned()
+            .unwrap_or_else(|| CsrfToken(String::new())))
+    }
+}
+
+pub struct Csrf {
+    key: Vec<u8>,
+}
+
+impl Csrf {
+    /// The key signs the tokens, all workers need the same one
+    pub fn new(key: &[u8]) -> Self {
+        Csrf { key: key.to_vec() }
+    }
+}
+
+impl<S, Err> Transform<S> for Csrf
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CsrfMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CsrfMiddleware {
+            service,
+            key: self.key.clone(),
+        })
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: S,
+    key: Vec<u8>,
+}
+
+impl<S, Err> Service for CsrfMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Either<
+        LocalBoxFuture<'static, Result<WebResponse, Error>>,
+        Ready<Result<WebResponse, Error>>,
+    >;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let token = req
+            .cookie(COOKIE)
+            .map(|cookie| cookie.value().to_owned())
+            .filter(|token| is_signed(&self.key, token));
+
+        let (token, issued) = if is_safe(req.method()) {
+            match token {
+                Some(token) => (token, false),
+                None => (issue(&self.key), true),
+            }
+        } else {
+            let header = req.headers().get(HEADER).and_then(|v| v.to_str().ok());
+            let refused = match (&token, header) {
+                (None, _) => Some("no valid csrf cookie"),
+                (Some(_), None) => Some("no x-csrf-token header"),
+                (Some(token), Some(header)) if !same(token, header) => {
+                    Some("x-csrf-token does not match the csrf cookie")
+                }
+                _ => None,
+            };
+            if let Some(reason) = refused {
+                log::warn!("{} {} refused: {}", req.method(), req.path(), reason);
+                return Either::Right(ok(req.into_response(
+                    HttpResponse::Forbidden()
+                        .json(&serde_json::json!({ "error": reason })),
+                )));
+            }
+            (token.unwrap(), false)
+        };
+
+        req.extensions_mut().insert(CsrfToken(token.clone()));
+        let fut = self.service.call(req);
+
+        Either::Left(
+            async move {
+                let mut res = fut.await?;
+                if issued {
+                    res.response_mut().add_cookie(&cookie(token))?;
+                }
+                Ok(res)
+            }
+            .boxed_local(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signature() {
+        let token = issue(b"key");
+        assert!(is_signed(b"key", &token));
+        assert!(!is_signed(b"other key", &token));
+
+        let (nonce, code) = token.split_at(token.find('.').unwrap());
+        assert!(!is_signed(b"key", nonce));
+        assert!(!is_signed(b"key", &format!("{}{}", "00".repeat(16), code)));
+        assert!(!is_signed(b"key", "not.hex"));
+    }
+
+    #[test]
+    fn test_same() {
+        assert!(same("abc", "abc"));
+        assert!(!same("abc", "abd"));
+        assert!(!same("abc", "abcd"));
+    }
+}
@@ -0,0 +1,153 @@
+//! A form protected by `Csrf`, see `csrf`.
+//!
+//! `GET /` sets the token cookie and puts the same token into the page, the
+//! page's script sends it back in `X-CSRF-Token` when the form is
+//! submitted. A request from anywhere else, a form on another site or a
+//! plain `curl`, has the cookie at best and gets a `403`.
+use ntex::web::{self, middleware, App, HttpResponse};
+use rand::Rng;
+use serde::Deserialize;
+
+mod csrf;
+
+use csrf::{Csrf, CsrfToken};
+
+const PAGE: &str = include_str!("../static/index.html");
+
+async fn index(token: CsrfToken) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(PAGE.replace("{{token}}", &token.0))
+}
+
+#[derive(Deserialize)]
+struct Transfer {
+    to: String,
+    amount: u32,
+}
+
+async fn transfer(form: web::types::Form<Transfer>) -> HttpResponse {
+    log::info!("sending {} to {}", form.amount, form.to);
+    HttpResponse::Ok().json(&serde_json::json!({ "sent": form.amount, "to": form.to }))
+}
+
+/// `CSRF_KEY` keeps tokens valid across restarts, without it every start
+/// makes a new key and the tokens of browsers that were here before it are
+/// replaced on their next `GET`
+fn key() -> Vec<u8> {
+    match std::env::var("CSRF_KEY") {
+        Ok(key) if !key.is_empty() => key.into_bytes(),
+        _ => {
+            log::warn!("CSRF_KEY is not set, using a random key");
+            rand::thread_rng().gen::<[u8; 32]>().to_vec()
+        }
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,csrf=info");
+    env_logger::init();
+
+    let key = key();
+
+    web::server(move || {
+        App::new()
+            .wrap(Csrf::new(&key))
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/transfer", web::post().to(transfer))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cookie::Cookie;
+    use ntex::http::{header, StatusCode};
+    use ntex::web::test;
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new()
+                    .wrap(Csrf::new(b"test key"))
+                    .route("/", web::get().to(index))
+                    .route("/transfer", web::post().to(transfer)),
+            )
+            .await
+        };
+    }
+
+    fn transfer_req() -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/transfer")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .set_payload("to=bob&amount=10")
+    }
+
+    #[ntex::test]
+    async fn test_round_trip() {
+        let app = app!();
+
+        let res = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let token = res
+            .response()
+            .cookies()
+            .find(|c| c.name() == csrf::COOKIE)
+            .unwrap()
+            .value()
+            .to_owned();
+        let page = test::read_body(res).await;
+        assert!(std::str::from_utf8(&page).unwrap().contains(&token));
+
+        let req = transfer_req()
+            .cookie(Cookie::new(csrf::COOKIE, token.clone()))
+            .header(csrf::HEADER, token.as_str())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // a valid cookie is kept, not replaced
+        let req = test::TestRequest::get()
+            .cookie(Cookie::new(csrf::COOKIE, token))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.response().cookies().count(), 0);
+    }
+
+    #[ntex::test]
+    async fn test_refused() {
+        let app = app!();
+        let res = test::call_service(&app, test::TestRequest::get().to_request()).await;
+        let token = res.response().cookies().next().unwrap().value().to_owned();
+
+        // what a form on another site sends: the cookie, no header
+        let req = transfer_req()
+            .cookie(Cookie::new(csrf::COOKIE, token.clone()))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let req = transfer_req()
+            .cookie(Cookie::new(csrf::COOKIE, token))
+            .header(csrf::HEADER, "something else")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        // cookie and header agree, but the token wasn't made with our key
+        let forged = format!("{}.{}", "00".repeat(16), "11".repeat(32));
+        let req = transfer_req()
+            .cookie(Cookie::new(csrf::COOKIE, forged.clone()))
+            .header(csrf::HEADER, forged.as_str())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        let body = test::read_body(res).await;
+        assert_eq!(body, r#"{"error":"no valid csrf cookie"}"#);
+    }
+}
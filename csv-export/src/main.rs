@@ -0,0 +1,213 @@
+//! Streaming csv export.
+//!
+//! `GET /export.csv` walks a sqlite cursor on a thread pool thread and sends
+//! every row through a bounded channel. The response body is a stream that
+//! turns each row into exactly one csv record, so memory usage stays flat no
+//! matter how big the table is, and a slow client slows down the cursor
+//! instead of filling up a buffer.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::Bytes;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::{executor, SinkExt, Stream};
+use ntex::http::header;
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// How many rows may be waiting for the client before the cursor blocks
+const ROW_BUFFER: usize = 64;
+
+#[derive(Debug, Serialize)]
+struct Item {
+    id: i64,
+    name: String,
+    comment: String,
+    price: f64,
+}
+
+#[derive(Deserialize)]
+struct ExportParams {
+    /// Simulate a database failure after this many rows
+    fail_after: Option<usize>,
+}
+
+async fn export(
+    db: web::types::Data<Pool>,
+    params: web::types::Query<ExportParams>,
+) -> Result<HttpResponse, Error> {
+    let conn = db.get().map_err(error::ErrorInternalServerError)?;
+    let fail_after = params.fail_after;
+    let (mut tx, rx) = channel(ROW_BUFFER);
+
+    // the cursor is blocking, so it runs on the thread pool. `block_on` makes
+    // the thread wait whenever the channel is full, which is our backpressure.
+    ntex::rt::spawn(async move {
+        let _ = web::block(move || {
+            // a query that fails before the first row ends the body the same
+            // way, not with an empty export
+            if let Err(e) = send_rows(&conn, fail_after, &mut tx) {
+                let _ = executor::block_on(tx.send(Err(e)));
+            }
+            Ok::<_, ()>(())
+        })
+        .await;
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .header(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"export.csv\"",
+        )
+        .streaming(CsvStream {
+            rows: rx,
+            headers: true,
+        }))
+}
+
+/// Sends the rows until the client goes away or one of them fails
+fn send_rows(
+    conn: &rusqlite::Connection,
+    fail_after: Option<usize>,
+    tx: &mut Sender<Result<Item, rusqlite::Error>>,
+) -> Result<(), rusqlite::Error> {
+    let mut stmt =
+        conn.prepare("SELECT id, name, comment, price FROM items ORDER BY id")?;
+    let rows = stmt.query_map(NO_PARAMS, |row| {
+        Ok(Item {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            comment: row.get(2)?,
+            price: row.get(3)?,
+        })
+    })?;
+
+    for (idx, row) in rows.enumerate() {
+        let row = match fail_after {
+            Some(n) if idx == n => Err(rusqlite::Error::SqliteFailure(
+                rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_IOERR),
+                Some("simulated failure".to_owned()),
+            )),
+            _ => row,
+        };
+        let is_err = row.is_err();
+        // receiver is gone, client disconnected
+        if executor::block_on(tx.send(row)).is_err() || is_err {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Turns a stream of rows into a stream of csv records
+struct CsvStream {
+    rows: Receiver<Result<Item, rusqlite::Error>>,
+    /// header line is written together with the first record
+    headers: bool,
+}
+
+impl CsvStream {
+    fn encode(&mut self, item: &Item) -> Result<Bytes, csv::Error> {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(self.headers)
+            .from_writer(Vec::new());
+        wtr.serialize(item)?;
+        self.headers = false;
+
+        let buf = wtr.into_inner().map_err(|e| e.into_error())?;
+        Ok(Bytes::from(buf))
+    }
+}
+
+impl Stream for CsvStream {
+    type Item = Result<Bytes, Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.rows).poll_next(cx) {
+            Poll::Ready(Some(Ok(item))) => match self.encode(&item) {
+                Ok(chunk) => Poll::Ready(Some(Ok(chunk))),
+                Err(e) => Poll::Ready(Some(Err(abort(e)))),
+            },
+            // Returning an error from the body stream drops the connection
+            // without the terminating chunk. Every chunk sent so far is a
+            // complete record, and the client can tell the export is
+            // incomplete instead of getting a truncated file that looks valid.
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(abort(e)))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn abort<E: std::fmt::Display>(e: E) -> Error {
+    log::error!("csv export failed: {}", e);
+    error::ErrorInternalServerError(e.to_string()).into()
+}
+
+/// Create the table and fill it with sample rows on the first start
+fn init_db(pool: &Pool) -> Result<(), rusqlite::Error> {
+    let conn = pool.get().expect("Can not get db connection");
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS items (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            comment TEXT NOT NULL,
+            price REAL NOT NULL
+        )",
+        NO_PARAMS,
+    )?;
+
+    let count: i64 =
+        conn.query_row("SELECT COUNT(*) FROM items", NO_PARAMS, |row| row.get(0))?;
+    if count > 0 {
+        return Ok(());
+    }
+
+    conn.execute("BEGIN", NO_PARAMS)?;
+    for id in 1..=10_000i64 {
+        // a few awkward values to show quoting and escaping
+        let (name, comment) = match id % 4 {
+            0 => (format!("Widget, size {}", id), "plain".to_owned()),
+            1 => (format!("Gadget {}", id), "multi\nline\ncomment".to_owned()),
+            2 => (
+                format!("The \"best\" thing {}", id),
+                "has \"quotes\"".to_owned(),
+            ),
+            _ => (format!("Thing {}", id), String::new()),
+        };
+        conn.execute(
+            "INSERT INTO items (id, name, comment, price) VALUES (?1, ?2, ?3, ?4)",
+            params![id, name, comment, id as f64 * 0.25],
+        )?;
+    }
+    conn.execute("COMMIT", NO_PARAMS)?;
+    Ok(())
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,csv_export=info");
+    env_logger::init();
+
+    let manager = SqliteConnectionManager::file("export.db");
+    let pool = r2d2::Pool::new(manager).unwrap();
+    init_db(&pool).expect("Can not initialize database");
+
+    web::server(move || {
+        App::new()
+            .data(pool.clone())
+            .wrap(middleware::Logger::default())
+            .route("/export.csv", web::get().to(export))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
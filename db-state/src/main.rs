@@ -0,0 +1,217 @@
+//! A `sqlx::PgPool` in `web::types::Data`, the `state` example with a
+//! database instead of counters.
+//!
+//! `POOL_MODE` decides where the pool lives:
+//!
+//! * `global`, the default: one pool is made before the server starts and
+//!   every worker gets a clone of the `Data` with `.app_data()`. The
+//!   connections go to whichever worker needs one
+//! * `per-worker`: the factory closure makes a pool for each worker and
+//!   attaches it with `.data()`. Nothing is shared between threads, but a
+//!   busy worker can't borrow the idle connections of another one
+//!
+//! The handlers don't know the difference, they take a `Data<PgPool>`
+//! either way. Migrations run once at startup, before any worker.
+//!
+//! sqlx uses its async-std runtime, it brings its own reactor. The tokio
+//! runtime of sqlx is tokio 1, ntex runs on tokio 0.2.
+use derive_more::Display;
+use ntex::http::StatusCode;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+
+/// Connections in total, split between the workers in `per-worker` mode
+const MAX_CONNECTIONS: u32 = 16;
+/// Postgres' code for a unique constraint violation
+const UNIQUE_VIOLATION: &str = "23505";
+
+#[derive(Debug, Display)]
+enum UserError {
+    #[display(fmt = "user not found")]
+    NotFound,
+    #[display(fmt = "a user with this email exists")]
+    EmailTaken,
+    #[display(fmt = "{}", _0)]
+    Invalid(&'static str),
+    #[display(fmt = "database error")]
+    Database(sqlx::Error),
+}
+
+impl From<sqlx::Error> for UserError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => UserError::NotFound,
+            sqlx::Error::Database(ref db)
+                if db.code().as_deref() == Some(UNIQUE_VIOLATION) =>
+            {
+                UserError::EmailTaken
+            }
+            e => UserError::Database(e),
+        }
+    }
+}
+
+impl WebResponseError for UserError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UserError::NotFound => StatusCode::NOT_FOUND,
+            UserError::EmailTaken => StatusCode::CONFLICT,
+            UserError::Invalid(_) => StatusCode::BAD_REQUEST,
+            UserError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The details of a database error stay in the log
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        if let UserError::Database(e) = self {
+            log::error!("{}", e);
+        }
+        HttpResponse::build(self.status_code())
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+struct User {
+    id: i64,
+    email: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    email: String,
+    name: String,
+}
+
+async fn list_users(pool: web::types::Data<PgPool>) -> Result<HttpResponse, UserError> {
+    let users =
+        sqlx::query_as::<_, User>("SELECT id, email, name FROM users ORDER BY id")
+            .fetch_all(pool.get_ref())
+            .await?;
+    Ok(HttpResponse::Ok().json(&users))
+}
+
+async fn create_user(
+    user: web::types::Json<NewUser>,
+    pool: web::types::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    let NewUser { email, name } = user.into_inner();
+    if !email.contains('@') {
+        return Err(UserError::Invalid("email is not valid"));
+    }
+    if name.trim().is_empty() {
+        return Err(UserError::Invalid("name is required"));
+    }
+
+    let user = sqlx::query_as::<_, User>(
+        "INSERT INTO users (email, name) VALUES ($1, $2) RETURNING id, email, name",
+    )
+    .bind(email)
+    .bind(name.trim())
+    .fetch_one(pool.get_ref())
+    .await?;
+    Ok(HttpResponse::Created()
+        .header("location", format!("/users/{}", user.id))
+        .json(&user))
+}
+
+async fn get_user(
+    id: web::types::Path<i64>,
+    pool: web::types::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    let user =
+        sqlx::query_as::<_, User>("SELECT id, email, name FROM users WHERE id = $1")
+            .bind(*id)
+            .fetch_one(pool.get_ref())
+            .await?;
+    Ok(HttpResponse::Ok().json(&user))
+}
+
+async fn delete_user(
+    id: web::types::Path<i64>,
+    pool: web::types::Data<PgPool>,
+) -> Result<HttpResponse, UserError> {
+    let deleted = sqlx::query("DELETE FROM users WHERE id = $1")
+        .bind(*id)
+        .execute(pool.get_ref())
+        .await?
+        .rows_affected();
+    if deleted == 0 {
+        return Err(UserError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The pool of the worker that handled the request, or the global one
+async fn pool_stats(pool: web::types::Data<PgPool>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "thread": format!("{:?}", std::thread::current().id()),
+        "connections": pool.size(),
+        "idle": pool.num_idle(),
+    }))
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/users")
+            .route(web::get().to(list_users))
+            .route(web::post().to(create_user)),
+    )
+    .service(
+        web::resource("/users/{id}")
+            .route(web::get().to(get_user))
+            .route(web::delete().to(delete_user)),
+    )
+    .route("/pool", web::get().to(pool_stats));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,db_state=info,sqlx=warn");
+    env_logger::init();
+
+    let url = std::env::var("DATABASE_URL")
+        .unwrap_or_else(|_| "postgres://postgres@localhost/db_state".to_owned());
+    let per_worker = std::env::var("POOL_MODE").is_ok_and(|mode| mode == "per-worker");
+    let workers = std::thread::available_parallelism().map_or(1, |n| n.get());
+
+    let pool = PgPoolOptions::new()
+        .max_connections(MAX_CONNECTIONS)
+        .connect(&url)
+        .await
+        .map_err(|e| std::io::Error::other(format!("can not connect: {}", e)))?;
+    sqlx::migrate!()
+        .run(&pool)
+        .await
+        .map_err(|e| std::io::Error::other(format!("migrations failed: {}", e)))?;
+
+    let global = if per_worker {
+        pool.close().await;
+        log::info!("a pool per worker, {} workers", workers);
+        None
+    } else {
+        log::info!("one pool for all workers");
+        Some(web::types::Data::new(pool))
+    };
+
+    web::server(move || {
+        let application = App::new().wrap(middleware::Logger::default());
+        let application = match &global {
+            Some(pool) => application.app_data(pool.clone()),
+            // connects when a request needs the first connection
+            None => application.data(
+                PgPoolOptions::new()
+                    .max_connections((MAX_CONNECTIONS / workers as u32).max(1))
+                    .connect_lazy(&url)
+                    .expect("DATABASE_URL is not valid"),
+            ),
+        };
+        application.configure(app)
+    })
+    .workers(workers)
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,206 @@
+//! Request deadlines, and passing them on to the services a request calls.
+//!
+//! The caller says when it stops waiting, with `X-Request-Deadline`, a unix
+//! time in milliseconds, or `X-Request-Timeout`, milliseconds from now.
+//! Without either the request gets the default timeout, and no request gets
+//! more than the maximum. The `Deadlines` middleware puts the `Deadline` in
+//! the extensions, or answers `504 Gateway Timeout` right away when it has
+//! passed, nobody is waiting for the answer anymore.
+//!
+//! Outbound calls made with `Deadline::propagate` time out when the request
+//! does, and pass the deadline on, so the services they call don't work for
+//! nothing either. A call that times out ends the request with `504`. A handler can ask `allows` before it starts something
+//! that takes long and that it can do without.
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use derive_more::Display;
+use futures::future::{err, ok, Either, Ready};
+use ntex::http::client::ClientRequest;
+use ntex::http::Payload;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+
+pub const DEADLINE: &str = "x-request-deadline";
+pub const TIMEOUT: &str = "x-request-timeout";
+/// Kept back from outbound calls, to answer when they time out
+const MARGIN: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Display)]
+pub enum DeadlineError {
+    #[display(fmt = "deadline exceeded")]
+    Exceeded,
+    #[display(fmt = "the request has no deadline, Deadlines is missing")]
+    Missing,
+}
+
+impl DeadlineError {
+    fn response(&self) -> HttpResponse {
+        let body = serde_json::json!({ "error": self.to_string() });
+        match self {
+            DeadlineError::Exceeded => HttpResponse::GatewayTimeout().json(&body),
+            DeadlineError::Missing => HttpResponse::InternalServerError().json(&body),
+        }
+    }
+}
+
+impl WebResponseError for DeadlineError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        self.response()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Deadline {
+    at: Instant,
+    /// The same moment on the wall clock, for other hosts
+    wall: SystemTime,
+}
+
+impl Deadline {
+    fn after(timeout: Duration) -> Self {
+        Deadline {
+            at: Instant::now() + timeout,
+            wall: SystemTime::now() + timeout,
+        }
+    }
+
+    fn at_unix_ms(ms: u64) -> Self {
+        let wall = UNIX_EPOCH + Duration::from_millis(ms);
+        let now = SystemTime::now();
+        let at = match wall.duration_since(now) {
+            Ok(remaining) => Instant::now() + remaining,
+            Err(_) => Instant::now(),
+        };
+        Deadline { at, wall }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether there is `needed` left
+    pub fn allows(&self, needed: Duration) -> bool {
+        self.remaining() >= needed
+    }
+
+    /// Times `req` out when the request does, a little before, and sends
+    /// the deadline along
+    pub fn propagate(&self, req: ClientRequest) -> Result<ClientRequest, DeadlineError> {
+        let remaining = self.remaining();
+        if remaining <= MARGIN {
+            return Err(DeadlineError::Exceeded);
+        }
+        let unix_ms = (self.wall - MARGIN)
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis());
+        Ok(req
+            .timeout(remaining - MARGIN)
+            .header(DEADLINE, unix_ms.to_string()))
+    }
+}
+
+fn header_ms<Err>(req: &WebRequest<Err>, name: &str) -> Option<u64> {
+    let value = req.headers().get(name)?;
+    let ms = value.to_str().ok().and_then(|v| v.trim().parse().ok());
+    if ms.is_none() {
+        log::warn!("{} is not a number of milliseconds: {:?}", name, value);
+    }
+    ms
+}
+
+#[derive(Clone, Copy)]
+pub struct Deadlines {
+    default: Duration,
+    max: Duration,
+}
+
+impl Deadlines {
+    pub fn new(default: Duration, max: Duration) -> Self {
+        Deadlines { default, max }
+    }
+
+    /// The earliest of the two headers, the default without them, never
+    /// later than `max`
+    fn deadline<Err>(&self, req: &WebRequest<Err>) -> Deadline {
+        let limit = Deadline::after(self.max);
+        let absolute = header_ms(req, DEADLINE).map(Deadline::at_unix_ms);
+        let relative =
+            header_ms(req, TIMEOUT).map(|ms| Deadline::after(Duration::from_millis(ms)));
+        let asked = match (absolute, relative) {
+            (Some(a), Some(r)) => Some(if a.at < r.at { a } else { r }),
+            (a, r) => a.or(r),
+        };
+        match asked {
+            Some(deadline) if deadline.at < limit.at => deadline,
+            Some(_) => limit,
+            None => Deadline::after(self.default),
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for Deadlines
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = DeadlinesMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(DeadlinesMiddleware {
+            service,
+            config: *self,
+        })
+    }
+}
+
+pub struct DeadlinesMiddleware<S> {
+    service: S,
+    config: Deadlines,
+}
+
+impl<S, Err> Service for DeadlinesMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<WebResponse, S::Error>>, S::Future>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let deadline = self.config.deadline(&req);
+        if deadline.remaining() == Duration::from_secs(0) {
+            log::warn!("{} arrived after its deadline", req.path());
+            let res = DeadlineError::Exceeded.response();
+            return Either::Left(ok(req.into_response(res.into_body())));
+        }
+        req.extensions_mut().insert(deadline);
+        Either::Right(self.service.call(req))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Deadline {
+    type Error = DeadlineError;
+    type Future = Ready<Result<Self, DeadlineError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Deadline>() {
+            Some(deadline) => ok(*deadline),
+            None => err(DeadlineError::Missing),
+        }
+    }
+}
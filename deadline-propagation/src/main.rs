@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use derive_more::{Display, From};
+use ntex::http::client::error::SendRequestError;
+use ntex::http::client::Client;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+
+mod deadline;
+mod upstream;
+
+use deadline::{Deadline, DeadlineError, Deadlines};
+
+const UPSTREAM: &str = "http://127.0.0.1:8081";
+/// For requests that don't say
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+const MAX_TIMEOUT: Duration = Duration::from_secs(10);
+/// Recommendations usually take 300ms, they are only asked for with this
+/// much time left
+const RECOMMENDATIONS_BUDGET: Duration = Duration::from_millis(350);
+
+#[derive(Debug, Display, From)]
+enum ApiError {
+    #[display(fmt = "{}", _0)]
+    Deadline(DeadlineError),
+    #[display(fmt = "upstream failed: {}", _0)]
+    Upstream(String),
+}
+
+impl WebResponseError for ApiError {
+    fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+        match self {
+            ApiError::Deadline(e) => e.error_response(req),
+            ApiError::Upstream(_) => HttpResponse::BadGateway()
+                .json(&serde_json::json!({ "error": self.to_string() })),
+        }
+    }
+}
+
+async fn call(
+    client: &Client,
+    deadline: &Deadline,
+    path: &str,
+) -> Result<serde_json::Value, ApiError> {
+    let req = deadline.propagate(client.get(format!("{}{}", UPSTREAM, path)))?;
+    let mut res = req.send().await.map_err(|e| match e {
+        SendRequestError::Timeout => ApiError::Deadline(DeadlineError::Exceeded),
+        e => ApiError::Upstream(e.to_string()),
+    })?;
+    res.json()
+        .await
+        .map_err(|e| ApiError::Upstream(e.to_string()))
+}
+
+/// The inventory is needed, recommendations are left out when there is no
+/// time for them
+async fn product(
+    id: web::types::Path<u32>,
+    deadline: Deadline,
+    client: web::types::Data<Client>,
+) -> Result<HttpResponse, ApiError> {
+    let inventory = call(&client, &deadline, &format!("/inventory/{}", id)).await?;
+
+    let mut skipped = None;
+    let recommendations = if deadline.allows(RECOMMENDATIONS_BUDGET) {
+        let path = format!("/recommendations/{}", id);
+        match call(&client, &deadline, &path).await {
+            Ok(recommendations) => Some(recommendations),
+            Err(e) => {
+                log::warn!("no recommendations: {}", e);
+                skipped = Some(e.to_string());
+                None
+            }
+        }
+    } else {
+        log::info!("skipping recommendations, {:?} left", deadline.remaining());
+        skipped = Some("not enough time left".to_owned());
+        None
+    };
+
+    Ok(HttpResponse::Ok().json(&serde_json::json!({
+        "id": *id,
+        "inventory": inventory,
+        "recommendations": recommendations,
+        "recommendations_skipped": skipped,
+    })))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,deadline_propagation=info");
+    env_logger::init();
+
+    let server = web::server(|| {
+        App::new()
+            .data(Client::default())
+            .wrap(Deadlines::new(DEFAULT_TIMEOUT, MAX_TIMEOUT))
+            .wrap(middleware::Logger::default())
+            .route("/products/{id}", web::get().to(product))
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    futures::try_join!(server, upstream::start("127.0.0.1:8081")?).map(|_| ())
+}
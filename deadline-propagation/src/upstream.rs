@@ -0,0 +1,52 @@
+//! Stand-ins for the services the products API calls, on port 8081. The
+//! inventory answers in 100ms, recommendations take 300ms.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ntex::rt::time::delay_for;
+use ntex::server::Server;
+use ntex::web::{self, App, HttpRequest, HttpResponse};
+
+use crate::deadline::DEADLINE;
+
+/// What the caller left us, as it says
+fn log_budget(req: &HttpRequest) {
+    let deadline = req
+        .headers()
+        .get(DEADLINE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u128>().ok());
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis();
+    if let Some(deadline) = deadline {
+        log::info!(
+            "upstream: {} has {}ms",
+            req.path(),
+            deadline.saturating_sub(now)
+        );
+    }
+}
+
+async fn inventory(req: HttpRequest, id: web::types::Path<u32>) -> HttpResponse {
+    log_budget(&req);
+    delay_for(Duration::from_millis(100)).await;
+    HttpResponse::Ok().json(&serde_json::json!({ "in_stock": *id % 3 }))
+}
+
+async fn recommendations(req: HttpRequest, id: web::types::Path<u32>) -> HttpResponse {
+    log_budget(&req);
+    delay_for(Duration::from_millis(300)).await;
+    HttpResponse::Ok().json(&serde_json::json!([*id + 1, *id + 2]))
+}
+
+pub fn start(addr: &str) -> std::io::Result<Server> {
+    Ok(web::server(|| {
+        App::new()
+            .route("/inventory/{id}", web::get().to(inventory))
+            .route("/recommendations/{id}", web::get().to(recommendations))
+    })
+    .workers(1)
+    .bind(addr)?
+    .run())
+}
@@ -0,0 +1,158 @@
+//! JSON `404` and `405` responses.
+//!
+//! The two cases are handled at different levels. A path that no resource
+//! matches ends up in the app's default service: `404 Not Found`. A path
+//! that matches, with a method that none of the resource's routes accepts,
+//! ends up in the resource's default service: `405 Method Not Allowed`,
+//! which has to list the methods that are allowed in `Allow`. Without their
+//! own default services, both are answered with an empty body.
+//!
+//! `HEAD` gets a route of its own next to `GET`, a resource doesn't answer it
+//! otherwise. The server leaves the body out of the response.
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+async fn not_found(req: HttpRequest) -> HttpResponse {
+    HttpResponse::NotFound().json(&serde_json::json!({
+        "error": "not found",
+        "path": req.path(),
+    }))
+}
+
+async fn method_not_allowed(req: HttpRequest, allow: &'static str) -> HttpResponse {
+    HttpResponse::MethodNotAllowed()
+        .header(header::ALLOW, allow)
+        .json(&serde_json::json!({
+            "error": "method not allowed",
+            "method": req.method().as_str(),
+            "allow": allow.split(", ").collect::<Vec<_>>(),
+        }))
+}
+
+/// The default service of a resource, `allow` has to match its routes
+fn allow(allow: &'static str) -> web::Route {
+    web::route().to(move |req: HttpRequest| method_not_allowed(req, allow))
+}
+
+async fn list_users() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!([{ "id": 1, "name": "ferris" }]))
+}
+
+async fn create_user() -> HttpResponse {
+    HttpResponse::Created().json(&serde_json::json!({ "id": 2 }))
+}
+
+async fn get_user(id: web::types::Path<u32>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "id": *id, "name": "ferris" }))
+}
+
+async fn delete_user() -> HttpResponse {
+    HttpResponse::NoContent().finish()
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .service(
+            web::resource("/users")
+                .route(web::get().to(list_users))
+                .route(web::head().to(list_users))
+                .route(web::post().to(create_user))
+                .default_service(allow("GET, HEAD, POST")),
+        )
+        .service(
+            web::resource("/users/{id}")
+                .route(web::get().to(get_user))
+                .route(web::head().to(get_user))
+                .route(web::delete().to(delete_user))
+                .default_service(allow("GET, HEAD, DELETE")),
+        );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+            .default_service(web::route().to(not_found))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{Method, StatusCode};
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_not_found() {
+        let app = test::init_service(
+            App::new()
+                .configure(app_config)
+                .default_service(web::route().to(not_found)),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/nope").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(resp.headers().get(header::ALLOW).is_none());
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["path"], "/nope");
+
+        // with any method
+        let req = test::TestRequest::post().uri("/users/1/posts").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[ntex::test]
+    async fn test_method_not_allowed() {
+        let app = test::init_service(
+            App::new()
+                .configure(app_config)
+                .default_service(web::route().to(not_found)),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/users/1")
+            .method(Method::PUT)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            resp.headers().get(header::ALLOW).unwrap(),
+            "GET, HEAD, DELETE"
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["method"], "PUT");
+        assert_eq!(body["allow"], serde_json::json!(["GET", "HEAD", "DELETE"]));
+
+        let req = test::TestRequest::delete().uri("/users").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(
+            resp.headers().get(header::ALLOW).unwrap(),
+            "GET, HEAD, POST"
+        );
+
+        // the allowed methods still reach their handlers
+        let req = test::TestRequest::post().uri("/users").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let req = test::TestRequest::with_uri("/users/1")
+            .method(Method::HEAD)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
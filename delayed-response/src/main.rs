@@ -0,0 +1,140 @@
+//! Tasks that run after a delay. `POST /tasks` schedules one on the timer
+//! wheel of `scheduler`, `GET /tasks/{id}/result` waits for it to fire and
+//! `DELETE /tasks/{id}` cancels it. Tasks still pending when the server
+//! stops are saved to `pending.json` and scheduled again on the next start.
+use std::path::Path;
+use std::time::Duration;
+
+use futures::future::{select, Either};
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod scheduler;
+mod wheel;
+
+use scheduler::{CancelError, Outcome, Scheduler};
+
+const TICK: Duration = Duration::from_millis(10);
+/// One turn of the wheel is 10 seconds, longer delays take several turns
+const SLOTS: usize = 1000;
+const PENDING_FILE: &str = "pending.json";
+/// Longest a request waits for a result, in seconds
+const MAX_WAIT: u64 = 60;
+/// Longest delay a task can be scheduled with, a week
+const MAX_DELAY_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+
+type Tasks = web::types::Data<std::sync::Arc<Scheduler>>;
+
+#[derive(Deserialize)]
+struct NewTask {
+    message: String,
+    delay_ms: u64,
+}
+
+async fn schedule(body: web::types::Json<NewTask>, scheduler: Tasks) -> HttpResponse {
+    let NewTask { message, delay_ms } = body.into_inner();
+    let task = match Some(delay_ms)
+        .filter(|ms| *ms <= MAX_DELAY_MS)
+        .and_then(|ms| scheduler.schedule(message, Duration::from_millis(ms)))
+    {
+        Some(task) => task,
+        None => {
+            return HttpResponse::BadRequest().json(&serde_json::json!({
+                "error": format!("delay_ms must be at most {}", MAX_DELAY_MS)
+            }))
+        }
+    };
+    log::info!("task {} due in {}ms", task.id, delay_ms);
+    HttpResponse::Created()
+        .header("location", format!("/tasks/{}", task.id))
+        .json(&task)
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(&serde_json::json!({ "error": "no such task" }))
+}
+
+async fn status(id: web::types::Path<u64>, scheduler: Tasks) -> HttpResponse {
+    match scheduler.status(*id) {
+        Some((task, status)) => HttpResponse::Ok()
+            .json(&serde_json::json!({ "task": task, "state": status })),
+        None => not_found(),
+    }
+}
+
+#[derive(Deserialize)]
+struct Wait {
+    /// Seconds
+    wait: Option<u64>,
+}
+
+/// Answers once the task fired, or with `202` if it didn't within `wait`
+async fn result(
+    id: web::types::Path<u64>,
+    params: web::types::Query<Wait>,
+    scheduler: Tasks,
+) -> HttpResponse {
+    let outcome = match scheduler.wait(*id) {
+        Some(outcome) => outcome,
+        None => return not_found(),
+    };
+    let wait = Duration::from_secs(params.wait.unwrap_or(30).min(MAX_WAIT));
+
+    match select(outcome, Box::pin(delay_for(wait))).await {
+        Either::Left((Ok(Outcome::Done(result)), _)) => HttpResponse::Ok().json(&result),
+        Either::Left((Ok(Outcome::Cancelled), _)) => HttpResponse::Gone()
+            .json(&serde_json::json!({ "error": "the task was cancelled" })),
+        Either::Left((Err(_), _)) => HttpResponse::InternalServerError().finish(),
+        Either::Right(_) => HttpResponse::Accepted()
+            .header("retry-after", "0")
+            .json(&serde_json::json!({ "status": "pending" })),
+    }
+}
+
+async fn cancel(id: web::types::Path<u64>, scheduler: Tasks) -> HttpResponse {
+    match scheduler.cancel(*id) {
+        Ok(()) => {
+            log::info!("task {} cancelled", id);
+            HttpResponse::NoContent().finish()
+        }
+        Err(CancelError::NotFound) => not_found(),
+        Err(CancelError::Finished) => HttpResponse::Conflict().json(
+            &serde_json::json!({ "error": "the task already fired or was cancelled" }),
+        ),
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,delayed_response=info");
+    env_logger::init();
+
+    let scheduler = Scheduler::start(TICK, SLOTS);
+    let restored = scheduler.load(Path::new(PENDING_FILE))?;
+    if restored > 0 {
+        log::info!("scheduled {} tasks from {} again", restored, PENDING_FILE);
+    }
+    let tasks = web::types::Data::new(scheduler.clone());
+
+    web::server(move || {
+        App::new()
+            .app_data(tasks.clone())
+            .wrap(middleware::Logger::default())
+            .route("/tasks", web::post().to(schedule))
+            .service(
+                web::resource("/tasks/{id}")
+                    .route(web::get().to(status))
+                    .route(web::delete().to(cancel)),
+            )
+            .route("/tasks/{id}/result", web::get().to(result))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await?;
+
+    // stopped gracefully, by ctrl-c or a signal
+    let saved = scheduler.save(Path::new(PENDING_FILE))?;
+    log::info!("saved {} pending tasks to {}", saved, PENDING_FILE);
+    Ok(())
+}
@@ -0,0 +1,245 @@
+//! Scheduled tasks, on a timer wheel turned by one thread for all workers.
+//!
+//! A task waits on the wheel until it is due, then it runs on the wheel's
+//! thread and its result is kept. Requests waiting for it get the result
+//! through a oneshot channel, they don't poll. A task can be cancelled as
+//! long as it hasn't fired.
+//!
+//! Pending tasks can be saved to a file and loaded again, so they survive a
+//! restart. They keep their due time, a task that came due while the server
+//! was down fires right after it is back.
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::channel::oneshot;
+use serde::{Deserialize, Serialize};
+
+use crate::wheel::Wheel;
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Task {
+    pub id: u64,
+    pub message: String,
+    /// Milliseconds since the unix epoch, the wall clock survives restarts
+    pub scheduled_at: u64,
+    pub due_at: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Fired {
+    pub message: String,
+    pub fired_at: u64,
+    /// How much later than `due_at` it fired
+    pub late_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "status", rename_all = "lowercase")]
+pub enum Status {
+    Pending,
+    Done { result: Fired },
+    Cancelled,
+}
+
+/// What a waiting request learns
+#[derive(Clone, Debug)]
+pub enum Outcome {
+    Done(Fired),
+    Cancelled,
+}
+
+struct Record {
+    task: Task,
+    status: Status,
+    slot: usize,
+    waiters: Vec<oneshot::Sender<Outcome>>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CancelError {
+    NotFound,
+    /// It fired or was cancelled already
+    Finished,
+}
+
+struct Inner {
+    wheel: Wheel,
+    tasks: HashMap<u64, Record>,
+    next_id: u64,
+}
+
+pub struct Scheduler {
+    inner: Mutex<Inner>,
+}
+
+impl Scheduler {
+    /// Starts the thread that turns the wheel
+    pub fn start(tick: Duration, slots: usize) -> Arc<Self> {
+        let scheduler = Arc::new(Scheduler {
+            inner: Mutex::new(Inner {
+                wheel: Wheel::new(tick, slots),
+                tasks: HashMap::new(),
+                next_id: 1,
+            }),
+        });
+        let turning = scheduler.clone();
+        thread::Builder::new()
+            .name("timer-wheel".to_owned())
+            .spawn(move || turning.turn())
+            .unwrap();
+        scheduler
+    }
+
+    /// Ticks are counted from the start, a slow tick doesn't push the
+    /// later ones back
+    fn turn(&self) {
+        let tick = self.inner.lock().unwrap().wheel.tick();
+        let started = Instant::now();
+        let mut ticks = 0;
+        loop {
+            ticks += 1;
+            let next = started + tick * ticks;
+            thread::sleep(next.saturating_duration_since(Instant::now()));
+
+            let mut inner = self.inner.lock().unwrap();
+            for id in inner.wheel.advance() {
+                if let Some(record) = inner.tasks.get_mut(&id) {
+                    fire(record);
+                }
+            }
+        }
+    }
+
+    /// `None` if the task would be due after the end of time, `u64`
+    /// milliseconds that is. Checked before the lock is taken, a panic
+    /// while it is held would poison it for every later request
+    pub fn schedule(&self, message: String, delay: Duration) -> Option<Task> {
+        let now = now_ms();
+        let due_at = u64::try_from(delay.as_millis())
+            .ok()
+            .and_then(|delay| now.checked_add(delay))?;
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let task = Task {
+            id,
+            message,
+            scheduled_at: now,
+            due_at,
+        };
+        add(&mut inner, task.clone());
+        Some(task)
+    }
+
+    pub fn status(&self, id: u64) -> Option<(Task, Status)> {
+        let inner = self.inner.lock().unwrap();
+        let record = inner.tasks.get(&id)?;
+        Some((record.task.clone(), record.status.clone()))
+    }
+
+    /// Resolves when the task has fired or was cancelled, right away if
+    /// that already happened
+    pub fn wait(&self, id: u64) -> Option<oneshot::Receiver<Outcome>> {
+        let mut inner = self.inner.lock().unwrap();
+        let record = inner.tasks.get_mut(&id)?;
+        let (tx, rx) = oneshot::channel();
+        match &record.status {
+            Status::Pending => record.waiters.push(tx),
+            Status::Done { result } => {
+                let _ = tx.send(Outcome::Done(result.clone()));
+            }
+            Status::Cancelled => {
+                let _ = tx.send(Outcome::Cancelled);
+            }
+        }
+        Some(rx)
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<(), CancelError> {
+        let mut inner = self.inner.lock().unwrap();
+        let Inner { wheel, tasks, .. } = &mut *inner;
+        let record = tasks.get_mut(&id).ok_or(CancelError::NotFound)?;
+        if !wheel.cancel(id, record.slot) {
+            return Err(CancelError::Finished);
+        }
+        record.status = Status::Cancelled;
+        for waiter in record.waiters.drain(..) {
+            let _ = waiter.send(Outcome::Cancelled);
+        }
+        Ok(())
+    }
+
+    /// Writes the tasks that haven't fired to `path`, if there are any
+    pub fn save(&self, path: &Path) -> io::Result<usize> {
+        let inner = self.inner.lock().unwrap();
+        let pending: Vec<&Task> = inner
+            .tasks
+            .values()
+            .filter(|r| matches!(r.status, Status::Pending))
+            .map(|r| &r.task)
+            .collect();
+        if !pending.is_empty() {
+            std::fs::write(path, serde_json::to_vec_pretty(&pending)?)?;
+        }
+        Ok(pending.len())
+    }
+
+    /// Schedules the tasks saved in `path` again, if there is such a file
+    pub fn load(&self, path: &Path) -> io::Result<usize> {
+        let data = match std::fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let tasks: Vec<Task> = serde_json::from_slice(&data)?;
+        let mut inner = self.inner.lock().unwrap();
+        let count = tasks.len();
+        for task in tasks {
+            inner.next_id = inner.next_id.max(task.id + 1);
+            add(&mut inner, task);
+        }
+        std::fs::remove_file(path)?;
+        Ok(count)
+    }
+}
+
+fn add(inner: &mut Inner, task: Task) {
+    let delay = Duration::from_millis(task.due_at.saturating_sub(now_ms()));
+    let slot = inner.wheel.insert(task.id, delay);
+    inner.tasks.insert(
+        task.id,
+        Record {
+            task,
+            status: Status::Pending,
+            slot,
+            waiters: Vec::new(),
+        },
+    );
+}
+
+/// The task itself, it only says when it ran
+fn fire(record: &mut Record) {
+    let fired_at = now_ms();
+    let result = Fired {
+        message: record.task.message.clone(),
+        fired_at,
+        late_ms: fired_at.saturating_sub(record.task.due_at),
+    };
+    log::info!("task {} fired: {}", record.task.id, result.message);
+    for waiter in record.waiters.drain(..) {
+        let _ = waiter.send(Outcome::Done(result.clone()));
+    }
+    record.status = Status::Done { result };
+}
@@ -0,0 +1,70 @@
+//! A hashed timer wheel: a ring of slots, one per tick, and a cursor that
+//! moves one slot every tick. A timer goes into the slot the cursor reaches
+//! when it is due, timers further out than one turn count the turns they
+//! still have to wait. Adding, cancelling and every tick only look at one
+//! slot, however many timers there are.
+use std::time::Duration;
+
+struct Entry {
+    id: u64,
+    /// Full turns of the wheel left before it fires
+    rounds: u64,
+}
+
+pub struct Wheel {
+    tick: Duration,
+    slots: Vec<Vec<Entry>>,
+    cursor: usize,
+}
+
+impl Wheel {
+    pub fn new(tick: Duration, slots: usize) -> Self {
+        Wheel {
+            tick,
+            slots: (0..slots).map(|_| Vec::new()).collect(),
+            cursor: 0,
+        }
+    }
+
+    pub fn tick(&self) -> Duration {
+        self.tick
+    }
+
+    /// Fires `id` after `delay`, at most a tick later. Returns the slot, for
+    /// `cancel`
+    pub fn insert(&mut self, id: u64, delay: Duration) -> usize {
+        // the current tick is partly over, one more makes sure it isn't early
+        let ticks = (delay.as_nanos() / self.tick.as_nanos()) as u64 + 1;
+        let len = self.slots.len() as u64;
+        let slot = ((self.cursor as u64 + ticks) % len) as usize;
+        self.slots[slot].push(Entry {
+            id,
+            rounds: (ticks - 1) / len,
+        });
+        slot
+    }
+
+    /// Whether `id` was still waiting in `slot`
+    pub fn cancel(&mut self, id: u64, slot: usize) -> bool {
+        let entries = &mut self.slots[slot];
+        let before = entries.len();
+        entries.retain(|e| e.id != id);
+        entries.len() != before
+    }
+
+    /// Moves one tick ahead, returns the timers that are due
+    pub fn advance(&mut self) -> Vec<u64> {
+        self.cursor = (self.cursor + 1) % self.slots.len();
+        let mut due = Vec::new();
+        self.slots[self.cursor].retain_mut(|entry| {
+            if entry.rounds == 0 {
+                due.push(entry.id);
+                false
+            } else {
+                entry.rounds -= 1;
+                true
+            }
+        });
+        due
+    }
+}
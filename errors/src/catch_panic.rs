@@ -0,0 +1,92 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::{Service, Transform};
+
+use crate::error::{AppError, JsonErrors};
+
+/// Converts a panic in any downstream service into an `AppError::Internal`.
+///
+/// The request moved into the service that panicked, the error leaves the
+/// app without one, `JsonErrors` still renders it as JSON.
+pub struct CatchPanic;
+
+impl<S> Transform<S> for CatchPanic
+where
+    S: Service<
+            Request = WebRequest<JsonErrors>,
+            Response = WebResponse,
+            Error = AppError,
+        > + 'static,
+{
+    type Request = WebRequest<JsonErrors>;
+    type Response = WebResponse;
+    type Error = AppError;
+    type InitError = ();
+    type Transform = CatchPanicMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CatchPanicMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+pub struct CatchPanicMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S> Service for CatchPanicMiddleware<S>
+where
+    S: Service<
+            Request = WebRequest<JsonErrors>,
+            Response = WebResponse,
+            Error = AppError,
+        > + 'static,
+{
+    type Request = WebRequest<JsonErrors>;
+    type Response = WebResponse;
+    type Error = AppError;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, AppError>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<JsonErrors>) -> Self::Future {
+        let path = req.path().to_owned();
+        let svc = self.service.clone();
+
+        // extractors can panic in `call()`, handlers when polled
+        let fut = match std::panic::catch_unwind(AssertUnwindSafe(|| svc.call(req))) {
+            Ok(fut) => fut,
+            Err(panic) => {
+                return async move { Err(recover(&path, panic)) }.boxed_local()
+            }
+        };
+        async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(res) => res,
+                Err(panic) => Err(recover(&path, panic)),
+            }
+        }
+        .boxed_local()
+    }
+}
+
+fn recover(path: &str, panic: Box<dyn Any + Send>) -> AppError {
+    let msg = if let Some(s) = panic.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "<non-string panic payload>".to_owned()
+    };
+    log::error!("handler for {} panicked: {}", path, msg);
+    AppError::Internal(msg)
+}
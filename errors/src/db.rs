@@ -0,0 +1,116 @@
+//! Users in sqlite, the queries run on the thread pool.
+use derive_more::{Display, From};
+use ntex::web;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, ErrorCode, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+#[derive(Debug, Display, From)]
+pub enum DbError {
+    #[display(fmt = "no connection: {}", _0)]
+    Pool(r2d2::Error),
+    #[display(fmt = "{}", _0)]
+    Sqlite(rusqlite::Error),
+}
+
+impl From<r2d2::Error> for AppError {
+    fn from(err: r2d2::Error) -> Self {
+        AppError::Db(err.into())
+    }
+}
+
+impl From<rusqlite::Error> for AppError {
+    fn from(err: rusqlite::Error) -> Self {
+        AppError::Db(err.into())
+    }
+}
+
+#[derive(Serialize)]
+pub struct User {
+    id: i64,
+    name: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct NewUser {
+    pub name: String,
+    pub email: String,
+}
+
+#[derive(Clone)]
+pub struct Db(Pool);
+
+impl Db {
+    pub fn open(path: &str) -> Result<Self, DbError> {
+        let pool = r2d2::Pool::builder()
+            .max_size(4)
+            .build(SqliteConnectionManager::file(path))?;
+        pool.get()?.execute_batch(
+            "CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY,
+                name TEXT NOT NULL,
+                email TEXT NOT NULL UNIQUE
+            )",
+        )?;
+        Ok(Db(pool))
+    }
+
+    pub async fn user(&self, id: i64) -> Result<User, AppError> {
+        let pool = self.0.clone();
+        let user = web::block(move || -> Result<_, AppError> {
+            let user = pool
+                .get()?
+                .query_row(
+                    "SELECT id, name, email FROM users WHERE id = ?",
+                    params![id],
+                    |row| {
+                        Ok(User {
+                            id: row.get(0)?,
+                            name: row.get(1)?,
+                            email: row.get(2)?,
+                        })
+                    },
+                )
+                .optional()?;
+            Ok(user)
+        })
+        .await?;
+        user.ok_or_else(|| AppError::NotFound(format!("user {}", id)))
+    }
+
+    pub async fn create(&self, user: NewUser) -> Result<User, AppError> {
+        let pool = self.0.clone();
+        let user = web::block(move || -> Result<_, AppError> {
+            let conn = pool.get()?;
+            match conn.execute(
+                "INSERT INTO users (name, email) VALUES (?, ?)",
+                params![user.name, user.email],
+            ) {
+                Ok(_) => {}
+                // the database checks uniqueness, without a race between
+                // looking and inserting
+                Err(rusqlite::Error::SqliteFailure(e, _))
+                    if e.code == ErrorCode::ConstraintViolation =>
+                {
+                    return Err(AppError::Validation(format!(
+                        "{} is already taken",
+                        user.email
+                    )));
+                }
+                Err(e) => return Err(e.into()),
+            }
+            Ok(User {
+                id: conn.last_insert_rowid(),
+                name: user.name,
+                email: user.email,
+            })
+        })
+        .await?;
+        Ok(user)
+    }
+}
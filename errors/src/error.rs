@@ -0,0 +1,135 @@
+//! One error type for the whole application.
+//!
+//! Handlers return `Result<_, AppError>` and use `?`, whatever failed is
+//! converted into one of four variants, each with its own status code. The
+//! body is always the same, `{"error": {"code": "...", "message": "..."}}`.
+//!
+//! `JsonErrors` makes `AppError` the error type of the app itself, instead of
+//! `web::Error`. Failed extractors are converted to it too, and an error that
+//! leaves the app without a request, like a panic `CatchPanic` caught, is
+//! rendered the same way and not as plain text.
+use std::io;
+
+use derive_more::Display;
+use ntex::http::{ResponseError, StatusCode};
+use ntex::web::error::{
+    BlockingError, DataExtractorError, ErrorContainer, ErrorRenderer, JsonPayloadError,
+    PathError,
+};
+use ntex::web::{HttpRequest, HttpResponse, WebResponseError};
+
+use crate::db::DbError;
+
+/// The error renderer of the app, `App::with(JsonErrors)`
+pub struct JsonErrors;
+
+impl ErrorRenderer for JsonErrors {
+    type Container = AppError;
+}
+
+#[derive(Debug, Display)]
+pub enum AppError {
+    #[display(fmt = "{} not found", _0)]
+    NotFound(String),
+    #[display(fmt = "{}", _0)]
+    Validation(String),
+    /// The cause is logged when it is rendered, clients only learn that the
+    /// database failed
+    #[display(fmt = "database error")]
+    Db(DbError),
+    /// Logged the same way
+    #[display(fmt = "internal server error")]
+    Internal(String),
+}
+
+impl AppError {
+    fn code(&self) -> &'static str {
+        match self {
+            AppError::NotFound(_) => "not_found",
+            AppError::Validation(_) => "validation",
+            AppError::Db(_) => "database",
+            AppError::Internal(_) => "internal",
+        }
+    }
+
+    fn render(&self) -> HttpResponse {
+        match self {
+            AppError::Db(err) => log::error!("database error: {}", err),
+            AppError::Internal(err) => log::error!("internal server error: {}", err),
+            AppError::NotFound(_) | AppError::Validation(_) => (),
+        }
+        HttpResponse::build(WebResponseError::status_code(self)).json(
+            &serde_json::json!({
+                "error": { "code": self.code(), "message": self.to_string() }
+            }),
+        )
+    }
+}
+
+impl WebResponseError<JsonErrors> for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Db(_) | AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        self.render()
+    }
+}
+
+impl ErrorContainer for AppError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        self.render()
+    }
+}
+
+/// Used when there is no request left, the response is the same
+impl ResponseError for AppError {
+    fn error_response(&self) -> HttpResponse {
+        self.render()
+    }
+}
+
+impl From<DbError> for AppError {
+    fn from(err: DbError) -> Self {
+        AppError::Db(err)
+    }
+}
+
+impl From<io::Error> for AppError {
+    fn from(err: io::Error) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
+
+impl From<BlockingError<AppError>> for AppError {
+    fn from(err: BlockingError<AppError>) -> Self {
+        match err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => {
+                AppError::Internal("thread pool is gone".to_owned())
+            }
+        }
+    }
+}
+
+impl From<JsonPayloadError> for AppError {
+    fn from(err: JsonPayloadError) -> Self {
+        AppError::Validation(err.to_string())
+    }
+}
+
+impl From<PathError> for AppError {
+    fn from(err: PathError) -> Self {
+        AppError::Validation(err.to_string())
+    }
+}
+
+impl From<DataExtractorError> for AppError {
+    fn from(err: DataExtractorError) -> Self {
+        AppError::Internal(err.to_string())
+    }
+}
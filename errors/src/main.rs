@@ -0,0 +1,87 @@
+//! Handlers that fail with `AppError`, see `error`.
+//!
+//! Every handler returns `Result<_, AppError>` and uses `?`, database,
+//! io and extractor errors are converted on the way. `CatchPanic` turns a
+//! panic into the same JSON `500`.
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+mod catch_panic;
+mod db;
+mod error;
+
+use catch_panic::CatchPanic;
+use db::{Db, NewUser};
+use error::{AppError, JsonErrors};
+
+async fn get_user(
+    id: web::types::Path<i64>,
+    db: web::types::Data<Db>,
+) -> Result<HttpResponse, AppError> {
+    let user = db.user(*id).await?;
+    Ok(HttpResponse::Ok().json(&user))
+}
+
+async fn create_user(
+    user: web::types::Json<NewUser>,
+    db: web::types::Data<Db>,
+) -> Result<HttpResponse, AppError> {
+    let user = user.into_inner();
+    if user.name.trim().is_empty() {
+        return Err(AppError::Validation("name must not be empty".to_owned()));
+    }
+    if !user.email.contains('@') {
+        return Err(AppError::Validation(format!(
+            "{} is not an email address",
+            user.email
+        )));
+    }
+    let user = db.create(user).await?;
+    Ok(HttpResponse::Created().json(&user))
+}
+
+/// Fails with `Internal` until there is a `motd.txt`
+async fn motd() -> Result<HttpResponse, AppError> {
+    let motd = web::block(|| std::fs::read_to_string("motd.txt")).await;
+    let motd = motd.map_err(|e| match e {
+        web::error::BlockingError::Error(e) => AppError::from(e),
+        web::error::BlockingError::Canceled => {
+            AppError::Internal("thread pool is gone".to_owned())
+        }
+    })?;
+    Ok(HttpResponse::Ok().body(motd))
+}
+
+async fn panic() -> HttpResponse {
+    panic!("something went terribly wrong");
+}
+
+async fn not_found(req: HttpRequest) -> Result<HttpResponse, AppError> {
+    Err(AppError::NotFound(format!(
+        "route {} {}",
+        req.method(),
+        req.path()
+    )))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,errors=info");
+    env_logger::init();
+
+    let db = Db::open("users.db").map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    web::server(move || {
+        App::with(JsonErrors)
+            .data(db.clone())
+            .wrap(CatchPanic)
+            .wrap(middleware::Logger::default())
+            .route("/users", web::post().to(create_user))
+            .route("/users/{id}", web::get().to(get_user))
+            .route("/motd", web::get().to(motd))
+            .route("/panic", web::get().to(panic))
+            .default_service(web::route().to(not_found))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
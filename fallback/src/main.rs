@@ -0,0 +1,215 @@
+//! Serving the last known value when the database is down.
+//!
+//! Every successful read is also written to a cache in `Data`. When the
+//! database fails, the handler answers from the cache instead, marked with
+//! a `Warning` header and `X-Served-From: cache`, and only gives up with
+//! `503` when there is nothing cached.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use derive_more::Display;
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use serde::Serialize;
+
+const SERVED_FROM: &str = "x-served-from";
+
+#[derive(Clone, Serialize)]
+struct Product {
+    id: u32,
+    name: String,
+    stock: u32,
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "database unavailable")]
+struct DbError;
+
+/// Stands in for the database, `POST /db/fail` makes every query fail
+struct Db {
+    products: Mutex<HashMap<u32, Product>>,
+    failing: AtomicBool,
+}
+
+impl Db {
+    fn new() -> Self {
+        let products = (1..=3)
+            .map(|id| {
+                let product = Product {
+                    id,
+                    name: format!("product {}", id),
+                    stock: id * 10,
+                };
+                (id, product)
+            })
+            .collect();
+        Db {
+            products: Mutex::new(products),
+            failing: AtomicBool::new(false),
+        }
+    }
+
+    async fn product(&self, id: u32) -> Result<Option<Product>, DbError> {
+        if self.failing.load(Ordering::Relaxed) {
+            return Err(DbError);
+        }
+        Ok(self.products.lock().unwrap().get(&id).cloned())
+    }
+}
+
+/// The last value read from the database, and when
+#[derive(Default)]
+struct Cache(Mutex<HashMap<u32, (Product, Instant)>>);
+
+#[derive(Debug, Display)]
+enum ProductError {
+    #[display(fmt = "product not found")]
+    NotFound,
+    #[display(fmt = "{}, and no cached copy", _0)]
+    Unavailable(DbError),
+}
+
+impl WebResponseError for ProductError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            ProductError::NotFound => HttpResponse::NotFound(),
+            ProductError::Unavailable(_) => {
+                let mut res = HttpResponse::ServiceUnavailable();
+                res.header(header::RETRY_AFTER, "10");
+                res
+            }
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+async fn product(
+    id: web::types::Path<u32>,
+    db: web::types::Data<Db>,
+    cache: web::types::Data<Cache>,
+) -> Result<HttpResponse, ProductError> {
+    let id = id.into_inner();
+    let err = match db.product(id).await {
+        Ok(Some(product)) => {
+            let mut cache = cache.0.lock().unwrap();
+            cache.insert(id, (product.clone(), Instant::now()));
+            return Ok(HttpResponse::Ok().header(SERVED_FROM, "db").json(&product));
+        }
+        // not an outage, a product that doesn't exist isn't served from
+        // the cache either
+        Ok(None) => return Err(ProductError::NotFound),
+        Err(err) => err,
+    };
+
+    let cached = cache.0.lock().unwrap().get(&id).cloned();
+    match cached {
+        Some((product, stored)) => {
+            log::warn!("{}, serving product {} from the cache", err, id);
+            Ok(HttpResponse::Ok()
+                .header(SERVED_FROM, "cache")
+                .header(header::WARNING, "111 - \"Revalidation Failed\"")
+                .header(header::AGE, stored.elapsed().as_secs().to_string())
+                .json(&product))
+        }
+        None => Err(ProductError::Unavailable(err)),
+    }
+}
+
+/// `POST /db/fail` and `POST /db/recover` switch the outage on and off
+async fn set_failing(req: HttpRequest, db: web::types::Data<Db>) -> HttpResponse {
+    let failing = req.match_info().query("state") == "fail";
+    db.failing.store(failing, Ordering::Relaxed);
+    log::info!("database is {}", if failing { "down" } else { "up" });
+    HttpResponse::NoContent().finish()
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/products/{id}", web::get().to(product))
+        .route("/db/{state:fail|recover}", web::post().to(set_failing));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,fallback=info");
+    env_logger::init();
+
+    let db = web::types::Data::new(Db::new());
+    let cache = web::types::Data::new(Cache::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(db.clone())
+            .app_data(cache.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_cached_fallback() {
+        let app = test::init_service(
+            App::new()
+                .data(Db::new())
+                .data(Cache::default())
+                .configure(app_config),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/products/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(SERVED_FROM).unwrap(), "db");
+        assert!(resp.headers().get(header::WARNING).is_none());
+
+        let req = test::TestRequest::post().uri("/db/fail").to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::with_uri("/products/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(SERVED_FROM).unwrap(), "cache");
+        assert!(resp.headers().get(header::WARNING).is_some());
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["name"], "product 1");
+    }
+
+    #[ntex::test]
+    async fn test_no_cached_value() {
+        let app = test::init_service(
+            App::new()
+                .data(Db::new())
+                .data(Cache::default())
+                .configure(app_config),
+        )
+        .await;
+
+        let req = test::TestRequest::post().uri("/db/fail").to_request();
+        test::call_service(&app, req).await;
+
+        // never read while the database was up
+        let req = test::TestRequest::with_uri("/products/2").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(resp.headers().get(SERVED_FROM).is_none());
+
+        let req = test::TestRequest::post().uri("/db/recover").to_request();
+        test::call_service(&app, req).await;
+
+        let req = test::TestRequest::with_uri("/products/2").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(SERVED_FROM).unwrap(), "db");
+    }
+}
@@ -0,0 +1,188 @@
+//! `GET /dashboard` calls every upstream at once and puts their answers
+//! together. Each upstream has its own timeout, one that is slow or down
+//! leaves its field `null`, with the reason in `errors`, and the dashboard
+//! is marked `partial` instead of failing as a whole.
+use std::time::Duration;
+
+use futures::future::join_all;
+use ntex::http::client::Client;
+use ntex::http::StatusCode;
+use ntex::rt::time::timeout;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde_json::{Map, Value};
+
+mod upstream;
+
+struct Upstream {
+    /// The field of the dashboard it fills
+    name: &'static str,
+    url: String,
+    timeout: Duration,
+}
+
+impl Upstream {
+    fn new(name: &'static str, url: String, timeout: Duration) -> Self {
+        Upstream { name, url, timeout }
+    }
+}
+
+struct Upstreams(Vec<Upstream>);
+
+/// The timeout covers the whole call, the body included
+async fn fetch(client: &Client, upstream: &Upstream) -> Result<Value, String> {
+    let call = async {
+        let mut res = client
+            .get(&upstream.url)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if res.status() != StatusCode::OK {
+            return Err(format!("responded {}", res.status()));
+        }
+        res.json::<Value>().await.map_err(|e| e.to_string())
+    };
+    match timeout(upstream.timeout, call).await {
+        Ok(result) => result,
+        Err(_) => Err(format!(
+            "timed out after {}ms",
+            upstream.timeout.as_millis()
+        )),
+    }
+}
+
+async fn dashboard(
+    upstreams: web::types::Data<Upstreams>,
+    client: web::types::Data<Client>,
+) -> HttpResponse {
+    let results = join_all(upstreams.0.iter().map(|u| fetch(&client, u))).await;
+
+    let mut body = Map::new();
+    let mut errors = Map::new();
+    for (upstream, result) in upstreams.0.iter().zip(results) {
+        let value = result.unwrap_or_else(|e| {
+            log::warn!("{} left out: {}", upstream.name, e);
+            errors.insert(upstream.name.to_owned(), e.into());
+            Value::Null
+        });
+        body.insert(upstream.name.to_owned(), value);
+    }
+    body.insert("partial".to_owned(), (!errors.is_empty()).into());
+    body.insert("errors".to_owned(), errors.into());
+    HttpResponse::Ok().json(&body)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,fanout=info");
+    env_logger::init();
+
+    let url = |path| format!("http://127.0.0.1:8081{}", path);
+    let upstreams = web::types::Data::new(Upstreams(vec![
+        Upstream::new("profile", url("/profile"), Duration::from_millis(300)),
+        Upstream::new("orders", url("/orders"), Duration::from_millis(500)),
+        Upstream::new(
+            "recommendations",
+            url("/recommendations"),
+            Duration::from_secs(1),
+        ),
+    ]));
+
+    let server = web::server(move || {
+        App::new()
+            .app_data(upstreams.clone())
+            .data(Client::default())
+            .wrap(middleware::Logger::default())
+            .route("/dashboard", web::get().to(dashboard))
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    futures::try_join!(server, upstream::start("127.0.0.1:8081")?).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use ntex::rt::time::delay_for;
+    use ntex::web::test;
+
+    /// A fast upstream, a slow one and a broken one
+    fn upstreams() -> test::TestServer {
+        test::server(|| {
+            App::new()
+                .route(
+                    "/fast",
+                    web::get().to(|| async { HttpResponse::Ok().json(&[1, 2, 3]) }),
+                )
+                .route(
+                    "/slow",
+                    web::get().to(|| async {
+                        delay_for(Duration::from_secs(2)).await;
+                        HttpResponse::Ok().json(&"too late")
+                    }),
+                )
+                .route(
+                    "/broken",
+                    web::get()
+                        .to(|| async { HttpResponse::InternalServerError().finish() }),
+                )
+        })
+    }
+
+    #[ntex::test]
+    async fn test_slow_upstream_degrades() {
+        let srv = upstreams();
+        let timeout = Duration::from_millis(200);
+        let app = test::init_service(
+            App::new()
+                .data(Upstreams(vec![
+                    Upstream::new("fast", srv.url("/fast"), timeout),
+                    Upstream::new("slow", srv.url("/slow"), timeout),
+                    Upstream::new("broken", srv.url("/broken"), timeout),
+                ]))
+                .data(Client::default())
+                .route("/dashboard", web::get().to(dashboard)),
+        )
+        .await;
+
+        let started = Instant::now();
+        let req = test::TestRequest::get().uri("/dashboard").to_request();
+        let body: Value = test::read_response_json(&app, req).await;
+        // waited for the slow upstream's timeout, not for the upstream
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        assert_eq!(body["fast"], serde_json::json!([1, 2, 3]));
+        assert_eq!(body["slow"], Value::Null);
+        assert_eq!(body["broken"], Value::Null);
+        assert_eq!(body["partial"], true);
+        assert_eq!(body["errors"]["slow"], "timed out after 200ms");
+        assert_eq!(
+            body["errors"]["broken"],
+            "responded 500 Internal Server Error"
+        );
+        assert!(body["errors"].get("fast").is_none());
+    }
+
+    #[ntex::test]
+    async fn test_complete_dashboard() {
+        let srv = upstreams();
+        let app = test::init_service(
+            App::new()
+                .data(Upstreams(vec![Upstream::new(
+                    "fast",
+                    srv.url("/fast"),
+                    Duration::from_millis(200),
+                )]))
+                .data(Client::default())
+                .route("/dashboard", web::get().to(dashboard)),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/dashboard").to_request();
+        let body: Value = test::read_response_json(&app, req).await;
+        assert_eq!(body["partial"], false);
+        assert_eq!(body["errors"], serde_json::json!({}));
+    }
+}
@@ -0,0 +1,36 @@
+//! Stand-ins for the services behind the dashboard, on port 8081.
+//! Recommendations take three seconds, longer than the dashboard waits.
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::server::Server;
+use ntex::web::{self, App, HttpResponse};
+
+async fn profile() -> HttpResponse {
+    delay_for(Duration::from_millis(20)).await;
+    HttpResponse::Ok().json(&serde_json::json!({ "name": "Alice", "tier": "gold" }))
+}
+
+async fn orders() -> HttpResponse {
+    delay_for(Duration::from_millis(80)).await;
+    HttpResponse::Ok().json(&serde_json::json!([
+        { "id": 1041, "status": "shipped" },
+        { "id": 1042, "status": "processing" },
+    ]))
+}
+
+async fn recommendations() -> HttpResponse {
+    delay_for(Duration::from_secs(3)).await;
+    HttpResponse::Ok().json(&serde_json::json!(["tent", "stove"]))
+}
+
+pub fn start(addr: &str) -> std::io::Result<Server> {
+    Ok(web::server(|| {
+        App::new()
+            .route("/profile", web::get().to(profile))
+            .route("/orders", web::get().to(orders))
+            .route("/recommendations", web::get().to(recommendations))
+    })
+    .bind(addr)?
+    .run())
+}
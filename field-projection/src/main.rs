@@ -0,0 +1,190 @@
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod projection;
+
+use projection::{Fields, UnknownField};
+
+/// Every field path of a book that `?fields=` may name
+const BOOK_FIELDS: &[&str] = &[
+    "id",
+    "title",
+    "year",
+    "tags",
+    "author",
+    "author.id",
+    "author.name",
+    "author.country",
+];
+
+#[derive(Clone, Serialize)]
+struct Author {
+    id: u32,
+    name: &'static str,
+    country: &'static str,
+}
+
+#[derive(Clone, Serialize)]
+struct Book {
+    id: u32,
+    title: &'static str,
+    year: u16,
+    tags: &'static [&'static str],
+    author: Author,
+}
+
+fn books() -> Vec<Book> {
+    let le_guin = Author {
+        id: 1,
+        name: "Ursula K. Le Guin",
+        country: "US",
+    };
+    let lem = Author {
+        id: 2,
+        name: "Stanisław Lem",
+        country: "PL",
+    };
+    vec![
+        Book {
+            id: 1,
+            title: "The Dispossessed",
+            year: 1974,
+            tags: &["anarchism", "space"],
+            author: le_guin.clone(),
+        },
+        Book {
+            id: 2,
+            title: "The Left Hand of Darkness",
+            year: 1969,
+            tags: &["gender", "winter"],
+            author: le_guin,
+        },
+        Book {
+            id: 3,
+            title: "Solaris",
+            year: 1961,
+            tags: &["ocean", "contact"],
+            author: lem,
+        },
+    ]
+}
+
+#[derive(Deserialize)]
+struct Projection {
+    fields: Option<String>,
+}
+
+/// Serializes `value` and keeps the fields the query asked for
+fn respond<T: Serialize>(
+    value: &T,
+    query: &Projection,
+    allowed: &[&str],
+) -> Result<HttpResponse, UnknownField> {
+    let fields = Fields::parse(query.fields.as_deref(), allowed)?;
+    let value = serde_json::to_value(value).unwrap();
+    Ok(HttpResponse::Ok().json(&fields.project(value)))
+}
+
+async fn list_books(
+    query: web::types::Query<Projection>,
+) -> Result<HttpResponse, UnknownField> {
+    respond(&books(), &query, BOOK_FIELDS)
+}
+
+async fn get_book(
+    id: web::types::Path<u32>,
+    query: web::types::Query<Projection>,
+) -> Result<HttpResponse, UnknownField> {
+    match books().into_iter().find(|b| b.id == *id) {
+        Some(book) => respond(&book, &query, BOOK_FIELDS),
+        None => Ok(HttpResponse::NotFound()
+            .json(&serde_json::json!({ "error": "no such book" }))),
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/books", web::get().to(list_books))
+            .route("/books/{id}", web::get().to(get_book))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+    use serde_json::json;
+
+    #[ntex::test]
+    async fn test_projection() {
+        let app = test::init_service(
+            App::new()
+                .route("/books", web::get().to(list_books))
+                .route("/books/{id}", web::get().to(get_book)),
+        )
+        .await;
+
+        let req =
+            test::TestRequest::with_uri("/books/3?fields=id,author.name").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body, json!({"id": 3, "author": {"name": "Stanisław Lem"}}));
+
+        // every item of a list
+        let req = test::TestRequest::with_uri("/books?fields=title").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(
+            body,
+            json!([
+                {"title": "The Dispossessed"},
+                {"title": "The Left Hand of Darkness"},
+                {"title": "Solaris"},
+            ])
+        );
+
+        // a whole object wins over some of its fields
+        let req = test::TestRequest::with_uri("/books/3?fields=author.name,author")
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(
+            body,
+            json!({"author": {"id": 2, "name": "Stanisław Lem", "country": "PL"}})
+        );
+
+        // no `fields`, everything
+        let req = test::TestRequest::with_uri("/books/1").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body["tags"], json!(["anarchism", "space"]));
+        assert_eq!(body["author"]["country"], "US");
+    }
+
+    #[ntex::test]
+    async fn test_unknown_field() {
+        let app =
+            test::init_service(App::new().route("/books/{id}", web::get().to(get_book)))
+                .await;
+
+        for fields in &["id,isbn", "author.email", "title.length"] {
+            let req =
+                test::TestRequest::with_uri(&format!("/books/1?fields={}", fields))
+                    .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", fields);
+        }
+
+        let req = test::TestRequest::with_uri("/books/1?fields=id,isbn").to_request();
+        let resp = test::call_service(&app, req).await;
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["error"], "unknown field `isbn`");
+    }
+}
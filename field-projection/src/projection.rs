@@ -0,0 +1,87 @@
+//! Sparse fieldsets: `?fields=id,title,author.name` keeps only those fields
+//! of a response.
+//!
+//! The resource is serialized to a `serde_json::Value` as usual and then
+//! filtered. Every resource lists the field paths clients may ask for, so a
+//! typo is an error instead of a silently empty response.
+use std::collections::BTreeMap;
+
+use derive_more::Display;
+use ntex::web::{HttpRequest, HttpResponse, WebResponseError};
+use serde_json::{Map, Value};
+
+#[derive(Debug, Display)]
+#[display(fmt = "unknown field `{}`", _0)]
+pub struct UnknownField(String);
+
+impl WebResponseError for UnknownField {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// The selected part of a value
+#[derive(Debug)]
+pub enum Fields {
+    /// The whole value
+    All,
+    /// Only these fields of an object, and of every object of an array
+    Some(BTreeMap<String, Fields>),
+}
+
+impl Fields {
+    /// Parses a comma separated list of dotted paths. A missing or empty
+    /// list selects everything, every path has to be one of `allowed`
+    pub fn parse(list: Option<&str>, allowed: &[&str]) -> Result<Fields, UnknownField> {
+        let list = match list {
+            Some(list) if !list.trim().is_empty() => list,
+            _ => return Ok(Fields::All),
+        };
+
+        let mut fields = Fields::Some(BTreeMap::new());
+        for path in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            if !allowed.contains(&path) {
+                return Err(UnknownField(path.to_owned()));
+            }
+            fields.insert(path.split('.'));
+        }
+        Ok(fields)
+    }
+
+    fn insert<'a>(&mut self, mut path: impl Iterator<Item = &'a str>) {
+        // a parent that is selected whole already has every child
+        if let Fields::Some(children) = self {
+            match path.next() {
+                Some(name) => children
+                    .entry(name.to_owned())
+                    .or_insert_with(|| Fields::Some(BTreeMap::new()))
+                    .insert(path),
+                None => *self = Fields::All,
+            }
+        }
+    }
+
+    /// Keeps the selected fields of `value`
+    pub fn project(&self, value: Value) -> Value {
+        let children = match self {
+            Fields::All => return value,
+            Fields::Some(children) => children,
+        };
+        match value {
+            Value::Object(mut obj) => {
+                let mut projected = Map::new();
+                for (name, fields) in children {
+                    if let Some(value) = obj.remove(name) {
+                        projected.insert(name.clone(), fields.project(value));
+                    }
+                }
+                Value::Object(projected)
+            }
+            Value::Array(items) => {
+                Value::Array(items.into_iter().map(|v| self.project(v)).collect())
+            }
+            value => value,
+        }
+    }
+}
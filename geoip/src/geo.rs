@@ -0,0 +1,181 @@
+//! Where requests come from.
+//!
+//! The `GeoIp` middleware looks up the client address in the `GeoIpDb` in
+//! `Data` and stores the `Location` in the request extensions, handlers take
+//! it as an extractor. Private, loopback and unlisted addresses have an
+//! unknown location, the same as a request without a database: handlers
+//! always get a `Location`, nothing about it is guaranteed.
+//!
+//! Behind a proxy the peer is the proxy. A loopback peer is trusted to be
+//! one, the client is the last address of its `X-Forwarded-For`, the one
+//! the proxy added. Those before it came from the client, who can send
+//! whatever it likes.
+use std::net::IpAddr;
+use std::path::Path;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Ready};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use ntex::http::{header::HeaderName, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, ErrorRenderer, FromRequest, HttpRequest};
+use ntex::{Service, Transform};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Location {
+    /// ISO 3166-1 country code, `DE`
+    pub country: Option<String>,
+    /// ISO 3166-2 subdivision code without the country, `BY` for Bavaria
+    pub region: Option<String>,
+}
+
+/// Anything that can tell where an address is
+pub trait Lookup: Send + Sync {
+    fn lookup(&self, ip: IpAddr) -> Option<Location>;
+}
+
+/// A MaxMind GeoIP2 or GeoLite2 City or Country database
+pub struct MaxMind(Reader<Vec<u8>>);
+
+impl MaxMind {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, MaxMindDBError> {
+        Ok(MaxMind(Reader::open_readfile(path)?))
+    }
+}
+
+impl Lookup for MaxMind {
+    fn lookup(&self, ip: IpAddr) -> Option<Location> {
+        // country databases have no subdivisions, they read as a city
+        // without them
+        let city: geoip2::City = self.0.lookup(ip).ok()?;
+        let region = city
+            .subdivisions
+            .as_ref()
+            .and_then(|subdivisions| subdivisions.first())
+            .and_then(|subdivision| subdivision.iso_code);
+        Some(Location {
+            country: city.country.and_then(|c| c.iso_code).map(str::to_owned),
+            region: region.map(str::to_owned),
+        })
+    }
+}
+
+pub struct GeoIpDb(Box<dyn Lookup>);
+
+impl GeoIpDb {
+    pub fn new(lookup: impl Lookup + 'static) -> Self {
+        GeoIpDb(Box::new(lookup))
+    }
+
+    /// Addresses that are not on the internet are not looked up
+    pub fn locate(&self, ip: IpAddr) -> Location {
+        if !is_global(ip) {
+            return Location::default();
+        }
+        self.0.lookup(ip).unwrap_or_default()
+    }
+}
+
+fn is_global(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // 100.64.0.0/10, carrier-grade NAT
+                || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_global(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // fc00::/7 unique local and fe80::/10 link local
+                    || first & 0xfe00 == 0xfc00
+                    || first & 0xffc0 == 0xfe80)
+            }
+        },
+    }
+}
+
+fn client_ip<Err>(req: &WebRequest<Err>) -> Option<IpAddr> {
+    let peer = req.peer_addr()?.ip();
+    if !peer.is_loopback() {
+        return Some(peer);
+    }
+    let forwarded = req
+        .headers()
+        .get(HeaderName::from_static("x-forwarded-for"))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok());
+    Some(forwarded.unwrap_or(peer))
+}
+
+pub struct GeoIp;
+
+impl<S, Err> Transform<S> for GeoIp
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = GeoIpMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(GeoIpMiddleware { service })
+    }
+}
+
+pub struct GeoIpMiddleware<S> {
+    service: S,
+}
+
+impl<S, Err> Service for GeoIpMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        // the database is in memory, a lookup takes microseconds and
+        // doesn't need the thread pool
+        let location =
+            match (req.app_data::<web::types::Data<GeoIpDb>>(), client_ip(&req)) {
+                (Some(db), Some(ip)) => db.locate(ip),
+                _ => Location::default(),
+            };
+        log::debug!("{} is from {:?}", req.path(), location);
+        req.extensions_mut().insert(location);
+        self.service.call(req)
+    }
+}
+
+/// Unknown without the `GeoIp` middleware
+impl<Err: ErrorRenderer> FromRequest<Err> for Location {
+    type Error = Err::Container;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ok(req
+            .extensions()
+            .get::<Location>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
@@ -0,0 +1,184 @@
+use derive_more::Display;
+use ntex::http::StatusCode;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+
+mod geo;
+
+use geo::{GeoIp, GeoIpDb, Location, MaxMind};
+
+/// Where the final isn't licensed: countries, or `country-region`
+const BLOCKED: &[&str] = &["GB", "US-NY"];
+
+#[derive(Debug, Display)]
+enum GeoError {
+    #[display(fmt = "this content is not available in {}", _0)]
+    Blocked(&'static str),
+}
+
+impl WebResponseError for GeoError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS)
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// The entry of `blocked` that covers `location`. A location that isn't
+/// known is let through
+fn restricted(location: &Location, blocked: &[&'static str]) -> Option<&'static str> {
+    let country = location.country.as_deref()?;
+    let region = location
+        .region
+        .as_deref()
+        .map(|r| format!("{}-{}", country, r));
+    blocked
+        .iter()
+        .copied()
+        .find(|&b| b == country || Some(b) == region.as_deref())
+}
+
+async fn final_match(location: Location) -> Result<HttpResponse, GeoError> {
+    if let Some(blocked) = restricted(&location, BLOCKED) {
+        return Err(GeoError::Blocked(blocked));
+    }
+    Ok(HttpResponse::Ok().json(&serde_json::json!({
+        "stream": "rtmp://streams.example.com/final",
+    })))
+}
+
+async fn greeting(location: Location) -> HttpResponse {
+    let greeting = match location.country.as_deref() {
+        Some("DE") | Some("AT") => "Hallo",
+        Some("FR") => "Bonjour",
+        Some("ES") | Some("MX") | Some("AR") => "Hola",
+        Some("IT") => "Ciao",
+        Some("JP") => "こんにちは",
+        _ => "Hello",
+    };
+    HttpResponse::Ok().json(&serde_json::json!({
+        "greeting": greeting,
+        "country": location.country,
+        "region": location.region,
+    }))
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/greeting", web::get().to(greeting))
+        .route("/final", web::get().to(final_match));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,geoip=info");
+    env_logger::init();
+
+    let path = std::env::var("GEOIP_DB").unwrap_or_else(|_| "GeoLite2-City.mmdb".into());
+    // without a database every location is unknown, the app still works
+    let db = match MaxMind::open(&path) {
+        Ok(db) => Some(web::types::Data::new(GeoIpDb::new(db))),
+        Err(e) => {
+            log::warn!("no geoip database at {}: {}", path, e);
+            None
+        }
+    };
+
+    web::server(move || {
+        let mut app = App::new();
+        if let Some(db) = &db {
+            app = app.app_data(db.clone());
+        }
+        app.wrap(GeoIp)
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+
+    use ntex::web::test;
+
+    use super::*;
+    use crate::geo::Lookup;
+
+    struct Mock(HashMap<IpAddr, Location>);
+
+    impl Lookup for Mock {
+        fn lookup(&self, ip: IpAddr) -> Option<Location> {
+            self.0.get(&ip).cloned()
+        }
+    }
+
+    fn location(country: &str, region: &str) -> Location {
+        Location {
+            country: Some(country.into()),
+            region: Some(region.into()),
+        }
+    }
+
+    async fn get(path: &str, peer: &str, forwarded_for: Option<&str>) -> (u16, Vec<u8>) {
+        let mock = Mock(
+            vec![
+                ("81.2.69.160".parse().unwrap(), location("GB", "ENG")),
+                ("2.125.160.216".parse().unwrap(), location("FR", "IDF")),
+                ("216.160.83.56".parse().unwrap(), location("US", "NY")),
+                ("216.160.83.57".parse().unwrap(), location("US", "WA")),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        let app = test::init_service(
+            App::new()
+                .app_data(web::types::Data::new(GeoIpDb::new(mock)))
+                .wrap(GeoIp)
+                .configure(app_config),
+        )
+        .await;
+        let mut req = test::TestRequest::with_uri(path).peer_addr(peer.parse().unwrap());
+        if let Some(client) = forwarded_for {
+            req = req.header("x-forwarded-for", client);
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        let status = res.status().as_u16();
+        (status, test::read_body(res).await.to_vec())
+    }
+
+    #[ntex::test]
+    async fn test_localizes_and_restricts() {
+        let (status, body) = get("/greeting", "2.125.160.216:4000", None).await;
+        assert_eq!(status, 200);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["greeting"], "Bonjour");
+        assert_eq!(body["region"], "IDF");
+
+        assert_eq!(get("/final", "81.2.69.160:4000", None).await.0, 451);
+        assert_eq!(get("/final", "216.160.83.56:4000", None).await.0, 451);
+        assert_eq!(get("/final", "216.160.83.57:4000", None).await.0, 200);
+        // through a proxy on the same host, the client put in an address
+        // of its own before the one the proxy added
+        let forwarded = Some("216.160.83.57, 81.2.69.160");
+        assert_eq!(get("/final", "127.0.0.1:4000", forwarded).await.0, 451);
+    }
+
+    #[ntex::test]
+    async fn test_unknown_addresses_are_let_through() {
+        // private, not in the database, and a forwarded header from a peer
+        // that isn't a trusted proxy
+        for (peer, forwarded, country) in [
+            ("10.1.2.3:4000", None, serde_json::Value::Null),
+            ("198.51.100.7:4000", None, serde_json::Value::Null),
+            ("216.160.83.57:4000", Some("81.2.69.160"), "US".into()),
+        ] {
+            let (status, body) = get("/greeting", peer, forwarded).await;
+            assert_eq!(status, 200);
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["country"], country, "{}", peer);
+            assert_eq!(body["greeting"], "Hello");
+        }
+        assert_eq!(get("/final", "10.1.2.3:4000", None).await.0, 200);
+    }
+}
@@ -0,0 +1,141 @@
+//! async-graphql on ntex, the schema is in `schema`.
+//!
+//! The `Schema` is built once and kept in `Data`. `POST /graphql` runs
+//! queries and mutations, batches too, `GET /graphiql` is the playground,
+//! and subscriptions run over a websocket at `/graphql/ws`, in the
+//! `graphql-transport-ws` protocol or the older `graphql-ws`, whichever the
+//! client asks for.
+//!
+//! `ws::start` doesn't answer with a `Sec-WebSocket-Protocol`, which
+//! browsers insist on when they asked for one, so the websocket is set up by
+//! hand: the handshake response gets the header, frames from the client are
+//! decoded into the text messages async-graphql reads, and its messages are
+//! encoded into the response body.
+use std::error::Error as StdError;
+
+use async_graphql::http::{GraphiQLSource, WebSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{BatchRequest, BatchResponse};
+use futures::{future, SinkExt, StreamExt};
+use ntex::channel::mpsc;
+use ntex::http::body::{Body, BoxedBodyStream};
+use ntex::http::header;
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+
+mod schema;
+
+use schema::LibrarySchema;
+
+async fn graphql(
+    schema: web::types::Data<LibrarySchema>,
+    req: web::types::Json<BatchRequest>,
+) -> web::types::Json<BatchResponse> {
+    web::types::Json(schema.execute_batch(req.into_inner()).await)
+}
+
+async fn graphiql() -> HttpResponse {
+    let page = GraphiQLSource::build()
+        .endpoint("/graphql")
+        .subscription_endpoint("/graphql/ws")
+        .finish();
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(page)
+}
+
+async fn subscriptions(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    schema: web::types::Data<LibrarySchema>,
+) -> Result<HttpResponse, Error> {
+    let protocol = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(',')
+                .find_map(|p| p.trim().parse::<WebSocketProtocols>().ok())
+        })
+        .ok_or_else(|| {
+            web::error::ErrorBadRequest(
+                "sec-websocket-protocol must be graphql-transport-ws or graphql-ws",
+            )
+        })?;
+
+    let mut res = ntex::http::ws::handshake(req.head())?;
+    res.header(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        protocol.sec_websocket_protocol(),
+    );
+
+    let (tx, rx) = mpsc::channel::<Result<_, Box<dyn StdError>>>();
+    let sink = ntex::ws::StreamEncoder::new(tx);
+    ntex::rt::spawn(connection(
+        schema.get_ref().clone(),
+        payload,
+        sink,
+        protocol,
+    ));
+    Ok(res.body(Body::from_message(BoxedBodyStream::new(rx))))
+}
+
+/// Runs one websocket until either side closes it
+async fn connection(
+    schema: LibrarySchema,
+    payload: web::types::Payload,
+    mut sink: ws::WebSocketsSink,
+    protocol: WebSocketProtocols,
+) {
+    let pongs = sink.clone();
+    let messages = ntex::ws::StreamDecoder::new(payload)
+        .take_while(|frame| {
+            future::ready(matches!(frame, Ok(f) if !matches!(f, ws::Frame::Close(_))))
+        })
+        .filter_map(move |frame| {
+            let mut pongs = pongs.clone();
+            async move {
+                match frame {
+                    Ok(ws::Frame::Text(text)) => Some(text),
+                    Ok(ws::Frame::Ping(msg)) => {
+                        let _ = pongs.send(Ok(ws::Message::Pong(msg))).await;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+        });
+
+    let mut replies = Box::pin(WebSocket::new(schema, messages, protocol));
+    while let Some(reply) = replies.next().await {
+        let msg = match reply {
+            WsMessage::Text(text) => ws::Message::Text(text),
+            WsMessage::Close(code, reason) => {
+                ws::Message::Close(Some(ws::CloseReason::from((code.into(), reason))))
+            }
+        };
+        if sink.send(Ok(msg)).await.is_err() {
+            return;
+        }
+    }
+    let _ = sink.send(Ok(ws::Message::Close(None))).await;
+    let _ = sink.close().await;
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,graphql=info");
+    env_logger::init();
+
+    let schema = web::types::Data::new(schema::schema());
+
+    web::server(move || {
+        App::new()
+            .app_data(schema.clone())
+            .wrap(middleware::Logger::default())
+            .route("/graphql", web::post().to(graphql))
+            .route("/graphql/ws", web::get().to(subscriptions))
+            .route("/graphiql", web::get().to(graphiql))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,119 @@
+//! A library of books: query them, add one, and be told about the ones
+//! added.
+//!
+//! The books live in `Library`, part of the schema's data, all workers share
+//! it through the schema. Every `bookAdded` subscription has a channel of
+//! its own, `addBook` sends the new book to each of them.
+use std::sync::Mutex;
+
+use async_graphql::{Context, Object, Schema, SimpleObject, Subscription, ID};
+use futures::channel::mpsc;
+use futures::{future, Stream, StreamExt};
+
+pub type LibrarySchema = Schema<Query, Mutation, Subscription>;
+
+#[derive(Clone, SimpleObject)]
+pub struct Book {
+    id: ID,
+    title: String,
+    author: String,
+}
+
+#[derive(Default)]
+pub struct Library {
+    books: Mutex<Vec<Book>>,
+    subscribers: Mutex<Vec<mpsc::UnboundedSender<Book>>>,
+}
+
+impl Library {
+    fn add(&self, title: String, author: String) -> Book {
+        let book = {
+            let mut books = self.books.lock().unwrap();
+            let book = Book {
+                id: ID::from(books.len() + 1),
+                title,
+                author,
+            };
+            books.push(book.clone());
+            book
+        };
+        // a subscription that has ended has dropped its receiver
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.unbounded_send(book.clone()).is_ok());
+        book
+    }
+
+    fn subscribe(&self) -> mpsc::UnboundedReceiver<Book> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// All books, or the ones by `author`
+    async fn books(&self, ctx: &Context<'_>, author: Option<String>) -> Vec<Book> {
+        let books = ctx.data_unchecked::<Library>().books.lock().unwrap();
+        books
+            .iter()
+            .filter(|book| author.as_ref().is_none_or(|author| book.author == *author))
+            .cloned()
+            .collect()
+    }
+
+    async fn book(&self, ctx: &Context<'_>, id: ID) -> Option<Book> {
+        let books = ctx.data_unchecked::<Library>().books.lock().unwrap();
+        books.iter().find(|book| book.id == id).cloned()
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    async fn add_book(&self, ctx: &Context<'_>, title: String, author: String) -> Book {
+        ctx.data_unchecked::<Library>().add(title, author)
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Every book added from now on, or the ones by `author`
+    async fn book_added(
+        &self,
+        ctx: &Context<'_>,
+        author: Option<String>,
+    ) -> impl Stream<Item = Book> {
+        ctx.data_unchecked::<Library>()
+            .subscribe()
+            .filter(move |book| {
+                future::ready(
+                    author.as_ref().is_none_or(|author| book.author == *author),
+                )
+            })
+    }
+}
+
+pub fn schema() -> LibrarySchema {
+    let library = Library::default();
+    library.add(
+        "The Left Hand of Darkness".to_owned(),
+        "Ursula K. Le Guin".to_owned(),
+    );
+    library.add(
+        "The Dispossessed".to_owned(),
+        "Ursula K. Le Guin".to_owned(),
+    );
+    library.add("Solaris".to_owned(), "Stanislaw Lem".to_owned());
+
+    Schema::build(Query, Mutation, Subscription)
+        .data(library)
+        .finish()
+}
@@ -0,0 +1,45 @@
+//! The gRPC service behind the gateway, a plain tonic server.
+use std::net::SocketAddr;
+
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+pub mod hello_world {
+    tonic::include_proto!("helloworld");
+}
+
+use hello_world::greeter_server::{Greeter, GreeterServer};
+use hello_world::{HelloReply, HelloRequest};
+
+#[derive(Default)]
+pub struct MyGreeter;
+
+#[tonic::async_trait]
+impl Greeter for MyGreeter {
+    async fn say_hello(
+        &self,
+        request: Request<HelloRequest>,
+    ) -> Result<Response<HelloReply>, Status> {
+        let name = request.into_inner().name;
+        if name.trim().is_empty() {
+            return Err(Status::invalid_argument("name must not be empty"));
+        }
+        Ok(Response::new(HelloReply {
+            message: format!("Hello {}!", name),
+        }))
+    }
+}
+
+/// Run the service on its own thread and runtime, it stands in for a
+/// service that runs somewhere else
+pub fn start(addr: SocketAddr) {
+    std::thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let server = Server::builder()
+            .add_service(GreeterServer::new(MyGreeter))
+            .serve(addr);
+        if let Err(e) = rt.block_on(server) {
+            log::error!("gRPC backend failed: {}", e);
+        }
+    });
+}
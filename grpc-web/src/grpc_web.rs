@@ -0,0 +1,258 @@
+//! gRPC-web to gRPC translation.
+//!
+//! gRPC relies on HTTP/2 trailers for the call status, and browsers can't
+//! read trailers. gRPC-web keeps the length-prefixed message frames of gRPC
+//! and moves the trailers into the body, as a last frame with the `0x80`
+//! flag set. `application/grpc-web-text` also base64 encodes the body, for
+//! clients that can only handle text.
+//!
+//! Messages are passed through as they are, the gateway doesn't need to know
+//! the services behind it.
+use std::fmt::Write;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use ntex::http::header;
+use ntex::web::{self, HttpRequest, HttpResponse, HttpResponseBuilder};
+
+/// Flag of the frame that carries the trailers
+const TRAILERS_FLAG: u8 = 0x80;
+
+/// Headers a gRPC-web client sends, and the ones it has to read
+const ALLOW_HEADERS: &str =
+    "content-type, x-grpc-web, x-user-agent, grpc-timeout, authorization";
+const EXPOSE_HEADERS: &str = "grpc-status, grpc-message, grpc-status-details-bin";
+
+/// Request headers that are not gRPC metadata and are not forwarded
+const NOT_FORWARDED: &[&str] = &[
+    "accept",
+    "accept-encoding",
+    "accept-language",
+    "connection",
+    "content-length",
+    "content-type",
+    "cookie",
+    "host",
+    "origin",
+    "referer",
+    "te",
+    "user-agent",
+    "x-grpc-web",
+    "x-user-agent",
+];
+
+/// gRPC status codes used by the gateway itself
+const INVALID_ARGUMENT: u16 = 3;
+const INTERNAL: u16 = 13;
+const UNAVAILABLE: u16 = 14;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Encoding {
+    /// `application/grpc-web`
+    Binary,
+    /// `application/grpc-web-text`, base64
+    Text,
+}
+
+impl Encoding {
+    fn from_request(req: &HttpRequest) -> Option<Self> {
+        let content_type = req.headers().get(header::CONTENT_TYPE)?.to_str().ok()?;
+        match content_type.split(';').next().unwrap_or("").trim() {
+            "application/grpc-web" | "application/grpc-web+proto" => {
+                Some(Encoding::Binary)
+            }
+            "application/grpc-web-text" | "application/grpc-web-text+proto" => {
+                Some(Encoding::Text)
+            }
+            _ => None,
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Binary => "application/grpc-web+proto",
+            Encoding::Text => "application/grpc-web-text+proto",
+        }
+    }
+}
+
+/// Where calls are sent to, and which origins may call
+pub struct Gateway {
+    backend: String,
+    allowed_origins: Vec<String>,
+    client: hyper::Client<HttpConnector>,
+}
+
+impl Gateway {
+    pub fn new(backend: &str, allowed_origins: Vec<String>) -> Self {
+        Gateway {
+            backend: backend.trim_end_matches('/').to_owned(),
+            allowed_origins,
+            // gRPC is HTTP/2 only, without TLS here
+            client: hyper::Client::builder().http2_only(true).build_http(),
+        }
+    }
+
+    /// `Access-Control-Allow-Origin` for allowed origins
+    fn cors(&self, req: &HttpRequest, res: &mut HttpResponseBuilder) {
+        res.header(header::VARY, "origin");
+
+        let origin = match req.headers().get(header::ORIGIN) {
+            Some(origin) => origin,
+            None => return,
+        };
+        let allowed = self
+            .allowed_origins
+            .iter()
+            .any(|allowed| allowed == "*" || origin == allowed.as_str());
+        if allowed {
+            res.header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone())
+                .header(header::ACCESS_CONTROL_EXPOSE_HEADERS, EXPOSE_HEADERS);
+        }
+    }
+}
+
+/// Encode a `grpc-message` value, which is percent-encoded
+pub fn encode_message(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for b in message.bytes() {
+        if (0x20..=0x7e).contains(&b) && b != b'%' {
+            encoded.push(b as char);
+        } else {
+            let _ = write!(encoded, "%{:02X}", b);
+        }
+    }
+    encoded
+}
+
+/// The trailers as a gRPC-web frame, `name: value` lines with lowercase
+/// names
+pub fn trailers_frame(trailers: &hyper::HeaderMap) -> Bytes {
+    let mut block = Vec::new();
+    for (name, value) in trailers {
+        block.extend_from_slice(name.as_str().as_bytes());
+        block.extend_from_slice(b": ");
+        block.extend_from_slice(value.as_bytes());
+        block.extend_from_slice(b"\r\n");
+    }
+
+    let mut frame = BytesMut::with_capacity(5 + block.len());
+    frame.put_u8(TRAILERS_FLAG);
+    frame.put_u32(block.len() as u32);
+    frame.extend_from_slice(&block);
+    frame.freeze()
+}
+
+/// A call that failed in the gateway, as a trailers-only response: no
+/// messages, the status is sent with the headers
+fn error(
+    gateway: &Gateway,
+    req: &HttpRequest,
+    encoding: Encoding,
+    status: u16,
+    message: &str,
+) -> HttpResponse {
+    log::warn!("{} failed in the gateway: {}", req.path(), message);
+
+    let mut res = HttpResponse::Ok();
+    gateway.cors(req, &mut res);
+    res.content_type(encoding.content_type())
+        .header("grpc-status", status.to_string())
+        .header("grpc-message", encode_message(message))
+        .finish()
+}
+
+/// Answers the CORS preflight a browser sends before each call
+pub async fn preflight(
+    req: HttpRequest,
+    gateway: web::types::Data<Gateway>,
+) -> HttpResponse {
+    let mut res = HttpResponse::NoContent();
+    gateway.cors(&req, &mut res);
+    res.header(header::ACCESS_CONTROL_ALLOW_METHODS, "POST, OPTIONS")
+        .header(header::ACCESS_CONTROL_ALLOW_HEADERS, ALLOW_HEADERS)
+        .header(header::ACCESS_CONTROL_MAX_AGE, "86400")
+        .finish()
+}
+
+/// Forwards a gRPC-web call to the backend, `/{package.Service}/{Method}`
+pub async fn call(
+    req: HttpRequest,
+    body: Bytes,
+    gateway: web::types::Data<Gateway>,
+) -> HttpResponse {
+    let encoding = match Encoding::from_request(&req) {
+        Some(encoding) => encoding,
+        None => {
+            let mut res = HttpResponse::UnsupportedMediaType();
+            gateway.cors(&req, &mut res);
+            return res.body("expected a gRPC-web content type");
+        }
+    };
+    let frames = match encoding {
+        Encoding::Binary => body,
+        Encoding::Text => match base64::decode(&body) {
+            Ok(frames) => Bytes::from(frames),
+            Err(_) => {
+                let msg = "request body is not valid base64";
+                return error(&gateway, &req, encoding, INVALID_ARGUMENT, msg);
+            }
+        },
+    };
+
+    let mut call = hyper::Request::post(format!("{}{}", gateway.backend, req.path()))
+        .header(header::CONTENT_TYPE, "application/grpc")
+        .header(header::TE, "trailers");
+    for (name, value) in req.headers() {
+        if !NOT_FORWARDED.contains(&name.as_str()) {
+            call = call.header(name.clone(), value.clone());
+        }
+    }
+    let call = match call.body(hyper::Body::from(frames)) {
+        Ok(call) => call,
+        Err(e) => return error(&gateway, &req, encoding, INTERNAL, &e.to_string()),
+    };
+
+    let res = match gateway.client.request(call).await {
+        Ok(res) => res,
+        Err(e) => {
+            log::warn!("backend request failed: {}", e);
+            let msg = "backend is unavailable";
+            return error(&gateway, &req, encoding, UNAVAILABLE, msg);
+        }
+    };
+    if res.status() != hyper::StatusCode::OK {
+        let msg = format!("backend answered with {}", res.status());
+        return error(&gateway, &req, encoding, UNAVAILABLE, &msg);
+    }
+
+    let (parts, mut res_body) = res.into_parts();
+    let mut frames = BytesMut::new();
+    while let Some(chunk) = res_body.data().await {
+        match chunk {
+            Ok(chunk) => frames.extend_from_slice(&chunk),
+            Err(e) => return error(&gateway, &req, encoding, INTERNAL, &e.to_string()),
+        }
+    }
+    // a trailers-only response has no trailers, its status is in the headers
+    // and is passed on as it is
+    match res_body.trailers().await {
+        Ok(Some(trailers)) => frames.extend_from_slice(&trailers_frame(&trailers)),
+        Ok(None) => (),
+        Err(e) => return error(&gateway, &req, encoding, INTERNAL, &e.to_string()),
+    }
+
+    let mut res = HttpResponse::Ok();
+    gateway.cors(&req, &mut res);
+    for (name, value) in &parts.headers {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            res.header(name.clone(), value.clone());
+        }
+    }
+    res.content_type(encoding.content_type());
+    match encoding {
+        Encoding::Binary => res.body(frames.freeze()),
+        Encoding::Text => res.body(base64::encode(&frames)),
+    }
+}
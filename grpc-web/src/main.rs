@@ -0,0 +1,252 @@
+use ntex::http::Method;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod backend;
+mod grpc_web;
+
+/// Makes a unary call from the browser, like a gRPC-web client library would
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config.route("/", web::get().to(index)).service(
+        web::resource("/{service}/{method}")
+            .route(web::post().to(grpc_web::call))
+            .route(web::method(Method::OPTIONS).to(grpc_web::preflight)),
+    );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,grpc_web=info");
+    env_logger::init();
+
+    // without a backend, start the example one
+    let backend = std::env::var("GRPC_BACKEND").unwrap_or_else(|_| {
+        backend::start("127.0.0.1:50051".parse().unwrap());
+        "http://127.0.0.1:50051".to_owned()
+    });
+    let allowed_origins = std::env::var("ALLOWED_ORIGINS")
+        .unwrap_or_else(|_| "http://localhost:3000".to_owned())
+        .split(',')
+        .map(|origin| origin.trim().to_owned())
+        .collect();
+    log::info!("forwarding gRPC-web calls to {}", backend);
+
+    let gateway =
+        web::types::Data::new(grpc_web::Gateway::new(&backend, allowed_origins));
+
+    web::server(move || {
+        App::new()
+            .app_data(gateway.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use bytes::Bytes;
+    use ntex::http::header;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+    use prost::Message;
+
+    use backend::hello_world::{HelloReply, HelloRequest};
+
+    /// Start a backend on a free port
+    fn start_backend() -> String {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        backend::start(addr);
+        for _ in 0..50 {
+            if std::net::TcpStream::connect(addr).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        format!("http://{}", addr)
+    }
+
+    fn gateway(backend: &str) -> web::types::Data<grpc_web::Gateway> {
+        let origins = vec!["http://localhost:3000".to_owned()];
+        web::types::Data::new(grpc_web::Gateway::new(backend, origins))
+    }
+
+    /// A browser-style unary call, base64 encoded
+    fn say_hello(name: &str) -> test::TestRequest {
+        let mut msg = Vec::new();
+        HelloRequest {
+            name: name.to_owned(),
+        }
+        .encode(&mut msg)
+        .unwrap();
+        let mut frame = vec![0];
+        frame.extend_from_slice(&(msg.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&msg);
+
+        test::TestRequest::post()
+            .uri("/helloworld.Greeter/SayHello")
+            .header(header::CONTENT_TYPE, "application/grpc-web-text")
+            .header("x-grpc-web", "1")
+            .header(header::ORIGIN, "http://localhost:3000")
+            .set_payload(base64::encode(&frame))
+    }
+
+    /// Messages and the `grpc-status` and `grpc-message` of a response, from
+    /// the headers or the trailers frame
+    fn decode(
+        headers: &ntex::http::HeaderMap,
+        body: &[u8],
+    ) -> (Vec<Bytes>, String, String) {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default()
+        };
+        let body = base64::decode(body).unwrap();
+        let mut messages = Vec::new();
+        let (mut status, mut message) = (header("grpc-status"), header("grpc-message"));
+
+        let mut rest = &body[..];
+        while rest.len() >= 5 {
+            let len = u32::from_be_bytes([rest[1], rest[2], rest[3], rest[4]]) as usize;
+            let frame = &rest[5..5 + len];
+            if rest[0] & 0x80 == 0 {
+                messages.push(Bytes::copy_from_slice(frame));
+            } else {
+                for line in String::from_utf8_lossy(frame).split("\r\n") {
+                    let mut kv = line.splitn(2, ": ");
+                    match (kv.next(), kv.next()) {
+                        (Some("grpc-status"), Some(v)) => status = v.to_owned(),
+                        (Some("grpc-message"), Some(v)) => message = v.to_owned(),
+                        _ => (),
+                    }
+                }
+            }
+            rest = &rest[5 + len..];
+        }
+        (messages, status, message)
+    }
+
+    #[ntex::test]
+    async fn test_unary_call() {
+        let gateway = gateway(&start_backend());
+        let app =
+            test::init_service(App::new().app_data(gateway).configure(app_config)).await;
+
+        let resp = test::call_service(&app, say_hello("world").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/grpc-web-text+proto"
+        );
+        assert_eq!(
+            resp.headers()
+                .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+                .unwrap(),
+            "http://localhost:3000"
+        );
+        let headers = resp.headers().clone();
+        let (messages, status, _) = decode(&headers, &test::read_body(resp).await);
+        assert_eq!(status, "0");
+        let reply = HelloReply::decode(messages[0].clone()).unwrap();
+        assert_eq!(reply.message, "Hello world!");
+
+        // the backend rejects empty names
+        let resp = test::call_service(&app, say_hello("").to_request()).await;
+        let headers = resp.headers().clone();
+        let (messages, status, message) = decode(&headers, &test::read_body(resp).await);
+        assert!(messages.is_empty());
+        assert_eq!(status, "3");
+        // passed on as tonic encoded it, clients percent-decode it
+        assert_eq!(message, "name%20must%20not%20be%20empty");
+    }
+
+    #[ntex::test]
+    async fn test_gateway_errors() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let gateway = gateway(&format!("http://{}", closed));
+        let app =
+            test::init_service(App::new().app_data(gateway).configure(app_config)).await;
+
+        let resp = test::call_service(&app, say_hello("world").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("grpc-status").unwrap(), "14");
+        assert_eq!(
+            resp.headers().get("grpc-message").unwrap(),
+            "backend is unavailable"
+        );
+
+        let req = say_hello("world").set_payload("not base64!").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("grpc-status").unwrap(), "3");
+
+        let req = say_hello("world")
+            .header(header::CONTENT_TYPE, "application/json")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[ntex::test]
+    async fn test_cors_preflight() {
+        let app = test::init_service(
+            App::new()
+                .app_data(gateway("http://127.0.0.1:1"))
+                .configure(app_config),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/helloworld.Greeter/SayHello")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "http://localhost:3000")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NO_CONTENT);
+        let headers = resp.headers();
+        assert_eq!(
+            headers.get(header::ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(),
+            "http://localhost:3000"
+        );
+        let allowed = headers.get(header::ACCESS_CONTROL_ALLOW_HEADERS).unwrap();
+        assert!(allowed.to_str().unwrap().contains("x-grpc-web"));
+        let exposed = headers.get(header::ACCESS_CONTROL_EXPOSE_HEADERS).unwrap();
+        assert!(exposed.to_str().unwrap().contains("grpc-status"));
+
+        let req = test::TestRequest::with_uri("/helloworld.Greeter/SayHello")
+            .method(Method::OPTIONS)
+            .header(header::ORIGIN, "http://evil.example")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    #[test]
+    fn test_encode_message() {
+        assert_eq!(grpc_web::encode_message("not found"), "not found");
+        assert_eq!(
+            grpc_web::encode_message("50% off\nnow: 😀"),
+            "50%25 off%0Anow: %F0%9F%98%80"
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! Calls the gRPC side of the example server:
+//!
+//! ```bash
+//! cargo run --bin client -- get widget
+//! cargo run --bin client -- adjust widget -3
+//! ```
+pub mod inventory {
+    tonic::include_proto!("inventory");
+}
+
+use inventory::inventory_client::InventoryClient;
+use inventory::{AdjustStockRequest, GetItemRequest};
+
+const USAGE: &str = "usage: client get <sku> | client adjust <sku> <delta>";
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let addr = std::env::var("GRPC_ADDR")
+        .unwrap_or_else(|_| "http://127.0.0.1:50051".to_owned());
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut client = InventoryClient::connect(addr).await?;
+
+    let response = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["get", sku] => {
+            client
+                .get_item(GetItemRequest {
+                    sku: sku.to_owned(),
+                })
+                .await
+        }
+        ["adjust", sku, delta] => {
+            client
+                .adjust_stock(AdjustStockRequest {
+                    sku: sku.to_owned(),
+                    delta: delta.parse()?,
+                })
+                .await
+        }
+        _ => return Err(USAGE.into()),
+    };
+    match response {
+        Ok(item) => println!("{:?}", item.into_inner()),
+        Err(status) => println!("{:?}: {}", status.code(), status.message()),
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! One stock, two protocols: JSON over HTTP on 8080, gRPC on 50051. Both
+//! sides share one `Data<Store>`, stock taken out over gRPC is gone for
+//! the web handlers as well. `src/bin/client.rs` makes the gRPC calls.
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod rpc;
+mod store;
+
+use store::{StockError, Store};
+
+const GRPC_ADDR: &str = "127.0.0.1:50051";
+
+fn error_response(e: StockError) -> HttpResponse {
+    let body = serde_json::json!({ "error": e.to_string() });
+    match e {
+        StockError::NotFound(_) => HttpResponse::NotFound().json(&body),
+        StockError::Insufficient { .. } => HttpResponse::Conflict().json(&body),
+    }
+}
+
+async fn list_items(store: web::types::Data<Store>) -> HttpResponse {
+    HttpResponse::Ok().json(&store.list())
+}
+
+async fn get_item(
+    store: web::types::Data<Store>,
+    sku: web::types::Path<String>,
+) -> HttpResponse {
+    match store.get(&sku) {
+        Ok(item) => HttpResponse::Ok().json(&item),
+        Err(e) => error_response(e),
+    }
+}
+
+#[derive(Deserialize)]
+struct Adjust {
+    delta: i32,
+}
+
+async fn adjust_stock(
+    store: web::types::Data<Store>,
+    sku: web::types::Path<String>,
+    body: web::types::Json<Adjust>,
+) -> HttpResponse {
+    match store.adjust(&sku, body.delta) {
+        Ok(item) => {
+            log::info!("http: stock of {} is {} now", item.sku, item.stock);
+            HttpResponse::Ok().json(&item)
+        }
+        Err(e) => error_response(e),
+    }
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/items", web::get().to(list_items))
+        .route("/items/{sku}", web::get().to(get_item))
+        .route("/items/{sku}/stock", web::post().to(adjust_stock));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,grpc=info");
+    env_logger::init();
+
+    let store = web::types::Data::new(Store::new(&[
+        ("widget", "Widget", 10),
+        ("gadget", "Gadget", 3),
+    ]));
+    rpc::start(GRPC_ADDR.parse().unwrap(), store.clone());
+    log::info!("gRPC on {}", GRPC_ADDR);
+
+    web::server(move || {
+        App::new()
+            .app_data(store.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    use rpc::inventory::inventory_client::InventoryClient;
+    use rpc::inventory::{AdjustStockRequest, GetItemRequest};
+
+    /// Start the gRPC server on a free port
+    fn start_grpc(store: web::types::Data<Store>) -> String {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        rpc::start(addr, store);
+        for _ in 0..50 {
+            if std::net::TcpStream::connect(addr).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        format!("http://{}", addr)
+    }
+
+    #[ntex::test]
+    async fn test_shared_store() {
+        let store = web::types::Data::new(Store::new(&[("widget", "Widget", 10)]));
+        let url = start_grpc(store.clone());
+        let app =
+            test::init_service(App::new().app_data(store.clone()).configure(app_config))
+                .await;
+
+        // taken out over gRPC, seen over HTTP
+        let mut client = InventoryClient::connect(url).await.unwrap();
+        let item = client
+            .adjust_stock(AdjustStockRequest {
+                sku: "widget".to_owned(),
+                delta: -4,
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(item.stock, 6);
+
+        let req = test::TestRequest::with_uri("/items/widget").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body["stock"], 6);
+
+        // and the other way round
+        let req = test::TestRequest::post()
+            .uri("/items/widget/stock")
+            .set_json(&serde_json::json!({"delta": 1}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let item = client
+            .get_item(GetItemRequest {
+                sku: "widget".to_owned(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(item.stock, 7);
+    }
+
+    #[ntex::test]
+    async fn test_errors() {
+        let store = web::types::Data::new(Store::new(&[("widget", "Widget", 1)]));
+        let url = start_grpc(store.clone());
+        let mut client = InventoryClient::connect(url).await.unwrap();
+
+        let status = client
+            .get_item(GetItemRequest {
+                sku: "nope".to_owned(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::NotFound);
+
+        let status = client
+            .adjust_stock(AdjustStockRequest {
+                sku: "widget".to_owned(),
+                delta: -2,
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+        assert_eq!(status.message(), "only 1 of widget in stock");
+
+        let app =
+            test::init_service(App::new().app_data(store).configure(app_config)).await;
+        let req = test::TestRequest::post()
+            .uri("/items/widget/stock")
+            .set_json(&serde_json::json!({"delta": -2}))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+    }
+}
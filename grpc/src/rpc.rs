@@ -0,0 +1,76 @@
+//! The gRPC side, a tonic server for `proto/inventory.proto` on a port of
+//! its own. It holds the same `Data<Store>` as the web app.
+use std::net::SocketAddr;
+
+use ntex::web::types::Data;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+
+use crate::store::{self, StockError, Store};
+
+pub mod inventory {
+    tonic::include_proto!("inventory");
+}
+
+use inventory::inventory_server::{Inventory, InventoryServer};
+use inventory::{AdjustStockRequest, GetItemRequest, Item};
+
+impl From<store::Item> for Item {
+    fn from(item: store::Item) -> Self {
+        Item {
+            sku: item.sku,
+            name: item.name,
+            stock: item.stock,
+        }
+    }
+}
+
+impl From<StockError> for Status {
+    fn from(e: StockError) -> Self {
+        match e {
+            StockError::NotFound(_) => Status::not_found(e.to_string()),
+            StockError::Insufficient { .. } => {
+                Status::failed_precondition(e.to_string())
+            }
+        }
+    }
+}
+
+pub struct InventoryService {
+    store: Data<Store>,
+}
+
+#[tonic::async_trait]
+impl Inventory for InventoryService {
+    async fn get_item(
+        &self,
+        request: Request<GetItemRequest>,
+    ) -> Result<Response<Item>, Status> {
+        let item = self.store.get(&request.into_inner().sku)?;
+        Ok(Response::new(item.into()))
+    }
+
+    async fn adjust_stock(
+        &self,
+        request: Request<AdjustStockRequest>,
+    ) -> Result<Response<Item>, Status> {
+        let request = request.into_inner();
+        let item = self.store.adjust(&request.sku, request.delta)?;
+        log::info!("grpc: stock of {} is {} now", item.sku, item.stock);
+        Ok(Response::new(item.into()))
+    }
+}
+
+/// Runs the gRPC server on its own thread and runtime, next to the web
+/// workers
+pub fn start(addr: SocketAddr, store: Data<Store>) {
+    std::thread::spawn(move || {
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let server = Server::builder()
+            .add_service(InventoryServer::new(InventoryService { store }))
+            .serve(addr);
+        if let Err(e) = rt.block_on(server) {
+            log::error!("gRPC server failed: {}", e);
+        }
+    });
+}
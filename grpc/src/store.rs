@@ -0,0 +1,85 @@
+//! The stock both sides work on, shared as one `Data<Store>`.
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+#[derive(Clone, Serialize)]
+pub struct Item {
+    pub sku: String,
+    pub name: String,
+    pub stock: u32,
+}
+
+#[derive(Debug)]
+pub enum StockError {
+    NotFound(String),
+    /// Asked to take out more than there are
+    Insufficient {
+        sku: String,
+        stock: u32,
+    },
+}
+
+impl fmt::Display for StockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StockError::NotFound(sku) => write!(f, "no item {}", sku),
+            StockError::Insufficient { sku, stock } => {
+                write!(f, "only {} of {} in stock", stock, sku)
+            }
+        }
+    }
+}
+
+pub struct Store {
+    items: Mutex<BTreeMap<String, Item>>,
+}
+
+impl Store {
+    pub fn new(items: &[(&str, &str, u32)]) -> Self {
+        let items = items
+            .iter()
+            .map(|&(sku, name, stock)| {
+                let item = Item {
+                    sku: sku.to_owned(),
+                    name: name.to_owned(),
+                    stock,
+                };
+                (sku.to_owned(), item)
+            })
+            .collect();
+        Store {
+            items: Mutex::new(items),
+        }
+    }
+
+    pub fn list(&self) -> Vec<Item> {
+        self.items.lock().unwrap().values().cloned().collect()
+    }
+
+    pub fn get(&self, sku: &str) -> Result<Item, StockError> {
+        let items = self.items.lock().unwrap();
+        items
+            .get(sku)
+            .cloned()
+            .ok_or_else(|| StockError::NotFound(sku.to_owned()))
+    }
+
+    pub fn adjust(&self, sku: &str, delta: i32) -> Result<Item, StockError> {
+        let mut items = self.items.lock().unwrap();
+        let item = items
+            .get_mut(sku)
+            .ok_or_else(|| StockError::NotFound(sku.to_owned()))?;
+        let stock = i64::from(item.stock) + i64::from(delta);
+        if stock < 0 {
+            return Err(StockError::Insufficient {
+                sku: sku.to_owned(),
+                stock: item.stock,
+            });
+        }
+        item.stock = stock.min(i64::from(u32::MAX)) as u32;
+        Ok(item.clone())
+    }
+}
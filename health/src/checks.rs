@@ -0,0 +1,74 @@
+//! The checks the subsystems of `main` register.
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ntex::web;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use crate::health::Check;
+
+pub type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// A connection from the pool answers a query
+pub struct Database(pub Pool);
+
+#[async_trait::async_trait(?Send)]
+impl Check for Database {
+    async fn check(&self) -> Result<(), String> {
+        let pool = self.0.clone();
+        web::block(move || {
+            let conn = pool.get().map_err(|e| e.to_string())?;
+            conn.query_row("SELECT 1", rusqlite::NO_PARAMS, |row| row.get::<_, i64>(0))
+                .map_err(|e| e.to_string())
+        })
+        .await
+        .map(drop)
+        .map_err(|e| e.to_string())
+    }
+}
+
+/// Something we send requests to takes connections
+pub struct Upstream(pub String);
+
+#[async_trait::async_trait(?Send)]
+impl Check for Upstream {
+    async fn check(&self) -> Result<(), String> {
+        ntex::rt::net::TcpStream::connect(self.0.as_str())
+            .await
+            .map(drop)
+            .map_err(|e| format!("{}: {}", self.0, e))
+    }
+}
+
+/// Written to by a loop every time it goes round, a loop that is stuck
+/// stops writing
+#[derive(Clone)]
+pub struct Heartbeat {
+    last: Arc<Mutex<Instant>>,
+    max_age: Duration,
+}
+
+impl Heartbeat {
+    pub fn new(max_age: Duration) -> Self {
+        Heartbeat {
+            last: Arc::new(Mutex::new(Instant::now())),
+            max_age,
+        }
+    }
+
+    pub fn beat(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+impl Check for Heartbeat {
+    async fn check(&self) -> Result<(), String> {
+        let age = self.last.lock().unwrap().elapsed();
+        if age > self.max_age {
+            Err(format!("no heartbeat for {}s", age.as_secs()))
+        } else {
+            Ok(())
+        }
+    }
+}
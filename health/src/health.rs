@@ -0,0 +1,139 @@
+//! `HealthRegistry`, where subsystems put their checks.
+//!
+//! A check is for one of two probes. Liveness asks whether the process
+//! still works at all, a failing one gets it restarted, so only what a
+//! restart fixes belongs there. Readiness asks whether it should get
+//! traffic now; a database that is down fails it, and the process is left
+//! alone until the database is back.
+//!
+//! The checks of a probe run concurrently, each gets `CHECK_TIMEOUT`.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use serde::Serialize;
+
+pub const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[async_trait::async_trait(?Send)]
+pub trait Check: Send + Sync {
+    /// `Err` tells what is wrong
+    async fn check(&self) -> Result<(), String>;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Probe {
+    Liveness,
+    Readiness,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failing,
+    Draining,
+}
+
+#[derive(Serialize)]
+pub struct CheckResult {
+    pub ok: bool,
+    pub ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, CheckResult>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        matches!(self.status, Status::Ok)
+    }
+}
+
+struct Entry {
+    name: &'static str,
+    probe: Probe,
+    check: Arc<dyn Check>,
+}
+
+#[derive(Default)]
+pub struct HealthRegistry {
+    checks: Mutex<Vec<Entry>>,
+    draining: AtomicBool,
+}
+
+impl HealthRegistry {
+    pub fn register<C: Check + 'static>(
+        &self,
+        name: &'static str,
+        probe: Probe,
+        check: C,
+    ) {
+        self.checks.lock().unwrap().push(Entry {
+            name,
+            probe,
+            check: Arc::new(check),
+        });
+    }
+
+    /// From now on the process is not ready, whatever the checks say
+    pub fn set_draining(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    pub async fn run(&self, probe: Probe) -> Report {
+        if probe == Probe::Readiness && self.is_draining() {
+            return Report {
+                status: Status::Draining,
+                checks: BTreeMap::new(),
+            };
+        }
+
+        // the lock isn't held while the checks run
+        let checks: Vec<_> = self
+            .checks
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|entry| entry.probe == probe)
+            .map(|entry| (entry.name, entry.check.clone()))
+            .collect();
+        let results = join_all(checks.into_iter().map(|(name, check)| async move {
+            let start = Instant::now();
+            let result =
+                match ntex::rt::time::timeout(CHECK_TIMEOUT, check.check()).await {
+                    Ok(result) => result,
+                    Err(_) => Err(format!("no answer in {:?}", CHECK_TIMEOUT)),
+                };
+            let result = CheckResult {
+                ok: result.is_ok(),
+                ms: start.elapsed().as_millis() as u64,
+                error: result.err(),
+            };
+            (name, result)
+        }))
+        .await;
+
+        let status = if results.iter().all(|(_, result)| result.ok) {
+            Status::Ok
+        } else {
+            Status::Failing
+        };
+        Report {
+            status,
+            checks: results.into_iter().collect(),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! `/healthz` and `/readyz`, answered by the checks the subsystems put in
+//! a `HealthRegistry`, see `health`.
+//!
+//! On the first SIGTERM or SIGINT the process says it isn't ready any more
+//! and goes on serving for `UNREADY_SECS`, long enough for a load balancer
+//! to see that and send the traffic elsewhere. Then the server stops
+//! gracefully, requests in flight get `DRAIN_SECS`. A second signal exits
+//! right away.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use ntex::server::Server;
+use ntex::web::{self, middleware, App, HttpResponse};
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::Deserialize;
+use tokio::signal::unix::{signal, SignalKind};
+
+mod checks;
+mod health;
+
+use checks::{Database, Heartbeat, Upstream};
+use health::{HealthRegistry, Probe, Report};
+
+const UNREADY_SECS: u64 = 5;
+const DRAIN_SECS: u64 = 10;
+/// The jobs loop goes round every second, it is stuck after this long
+const HEARTBEAT_MAX_AGE: Duration = Duration::from_secs(5);
+
+/// Seconds the jobs loop is to hang on its next round, to see liveness fail
+#[derive(Default)]
+struct Stall(AtomicU64);
+/// Longest stall `/jobs/stall` takes
+const MAX_STALL_SECS: u64 = 60;
+
+async fn index() -> &'static str {
+    "Hello world!"
+}
+
+fn probe_response(report: Report) -> HttpResponse {
+    if report.is_ok() {
+        HttpResponse::Ok().json(&report)
+    } else {
+        HttpResponse::ServiceUnavailable().json(&report)
+    }
+}
+
+async fn healthz(registry: web::types::Data<HealthRegistry>) -> HttpResponse {
+    probe_response(registry.run(Probe::Liveness).await)
+}
+
+async fn readyz(registry: web::types::Data<HealthRegistry>) -> HttpResponse {
+    probe_response(registry.run(Probe::Readiness).await)
+}
+
+#[derive(Deserialize)]
+struct StallQuery {
+    secs: u64,
+}
+
+async fn stall_jobs(
+    stall: web::types::Data<Stall>,
+    query: web::types::Query<StallQuery>,
+) -> HttpResponse {
+    if query.secs > MAX_STALL_SECS {
+        return HttpResponse::BadRequest().json(&serde_json::json!({
+            "error": format!("secs must be at most {}", MAX_STALL_SECS)
+        }));
+    }
+    stall.0.store(query.secs, Ordering::SeqCst);
+    HttpResponse::Accepted().finish()
+}
+
+/// Stands in for background work, beats once a round
+async fn run_jobs(heartbeat: Heartbeat, stall: web::types::Data<Stall>) {
+    let mut interval = ntex::rt::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let secs = stall.0.swap(0, Ordering::SeqCst);
+        if secs > 0 {
+            log::warn!("jobs are stuck for {}s", secs);
+            ntex::rt::time::delay_for(Duration::from_secs(secs)).await;
+        }
+        heartbeat.beat();
+    }
+}
+
+async fn stop_on_signal(server: Server, registry: web::types::Data<HealthRegistry>) {
+    let mut term = signal(SignalKind::terminate()).expect("can not listen for SIGTERM");
+    let mut int = signal(SignalKind::interrupt()).expect("can not listen for SIGINT");
+    let mut graceful = true;
+    loop {
+        futures::future::select(Box::pin(term.recv()), Box::pin(int.recv())).await;
+        if graceful {
+            registry.set_draining();
+            log::info!("not ready, stopping in {}s", UNREADY_SECS);
+            let server = server.clone();
+            // runs on its own, the next signal has to get through
+            ntex::rt::spawn(async move {
+                ntex::rt::time::delay_for(Duration::from_secs(UNREADY_SECS)).await;
+                log::info!("stopping, requests in flight get {}s", DRAIN_SECS);
+                server.stop(true).await;
+            });
+            graceful = false;
+        } else {
+            log::warn!("exiting now");
+            std::process::exit(1);
+        }
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,health=info");
+    env_logger::init();
+
+    let registry = web::types::Data::new(HealthRegistry::default());
+
+    // every connection of an in-memory pool has a database of its own,
+    // enough for `SELECT 1`
+    let pool = r2d2::Pool::new(SqliteConnectionManager::memory())
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+    registry.register("database", Probe::Readiness, Database(pool));
+
+    match std::env::var("UPSTREAM") {
+        Ok(addr) => registry.register("upstream", Probe::Readiness, Upstream(addr)),
+        Err(_) => log::info!("UPSTREAM is not set, there is no upstream to check"),
+    }
+
+    let heartbeat = Heartbeat::new(HEARTBEAT_MAX_AGE);
+    let stall = web::types::Data::new(Stall::default());
+    registry.register("jobs", Probe::Liveness, heartbeat.clone());
+    ntex::rt::spawn(run_jobs(heartbeat, stall.clone()));
+
+    let server = {
+        let registry = registry.clone();
+        web::server(move || {
+            App::new()
+                .app_data(registry.clone())
+                .app_data(stall.clone())
+                .wrap(middleware::Logger::default())
+                .route("/", web::get().to(index))
+                .route("/healthz", web::get().to(healthz))
+                .route("/readyz", web::get().to(readyz))
+                .route("/jobs/stall", web::post().to(stall_jobs))
+        })
+        .disable_signals()
+        .shutdown_timeout(DRAIN_SECS)
+        .bind("127.0.0.1:8080")?
+        .run()
+    };
+    ntex::rt::spawn(stop_on_signal(server.clone(), registry));
+
+    server.await
+}
@@ -1,48 +1,183 @@
+//! A reverse proxy, every request is forwarded to the upstream with
+//! `ntex::http::client::Client` and the upstream's response is sent back.
+//!
+//! Bodies are streamed in both directions, a large upload or download never
+//! sits in memory. Hop-by-hop headers describe one connection, not the
+//! message, they are dropped both ways, everything else is passed through.
+use std::error::Error as StdError;
 use std::net::ToSocketAddrs;
+use std::time::Duration;
 
-use bytes::Bytes;
 use clap::{value_t, Arg};
-use ntex::http::client::Client;
-use ntex::web::{self, middleware, App, Error, HttpRequest, HttpResponse};
+use derive_more::Display;
+use futures::TryStreamExt;
+use ntex::http::body::{Body, SizedStream};
+use ntex::http::client::error::{ConnectError, SendRequestError};
+use ntex::http::client::{Client, ClientRequest};
+use ntex::http::header::{self, HeaderMap, HeaderName};
+use ntex::http::{Method, StatusCode};
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
 use url::Url;
 
+/// RFC 7230 section 6.1, `Expect` is answered by this server, not the upstream
+const HOP_BY_HOP: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "expect",
+];
+
+#[derive(Debug, Display)]
+enum ProxyError {
+    #[display(fmt = "upstream did not respond in time")]
+    Timeout,
+    #[display(fmt = "upstream failed: {}", _0)]
+    Upstream(SendRequestError),
+}
+
+impl From<SendRequestError> for ProxyError {
+    fn from(err: SendRequestError) -> Self {
+        match err {
+            SendRequestError::Timeout
+            | SendRequestError::Connect(ConnectError::Timeout) => ProxyError::Timeout,
+            err => ProxyError::Upstream(err),
+        }
+    }
+}
+
+impl WebResponseError for ProxyError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            ProxyError::Timeout => HttpResponse::GatewayTimeout(),
+            ProxyError::Upstream(_) => HttpResponse::BadGateway(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// The hop-by-hop headers, and the ones `Connection` names as such
+fn hop_by_hop(headers: &HeaderMap) -> Vec<String> {
+    let mut names: Vec<String> =
+        HOP_BY_HOP.iter().map(|&name| name.to_owned()).collect();
+    for value in headers.get_all(header::CONNECTION) {
+        if let Ok(value) = value.to_str() {
+            names.extend(
+                value
+                    .split(',')
+                    .map(|name| name.trim().to_ascii_lowercase()),
+            );
+        }
+    }
+    names
+}
+
+/// The end to end headers of `headers`
+fn end_to_end(
+    headers: &HeaderMap,
+) -> impl Iterator<Item = (&HeaderName, &header::HeaderValue)> {
+    let skip = hop_by_hop(headers);
+    headers
+        .iter()
+        .filter(move |(name, _)| !skip.iter().any(|skip| skip == name.as_str()))
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+fn boxed<E: StdError + 'static>(err: E) -> Box<dyn StdError> {
+    Box::new(err)
+}
+
+/// Appends the client to `X-Forwarded-For`, after the proxies it came through
+fn forwarded_for(req: &HttpRequest, forwarded: ClientRequest) -> ClientRequest {
+    let ip = match req.peer_addr() {
+        Some(addr) => addr.ip().to_string(),
+        None => return forwarded,
+    };
+    let chain = match req
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(chain) => format!("{}, {}", chain, ip),
+        None => ip,
+    };
+    forwarded.set_header("x-forwarded-for", chain)
+}
+
 async fn forward(
     req: HttpRequest,
-    body: Bytes,
+    payload: web::types::Payload,
     url: web::types::Data<Url>,
     client: web::types::Data<Client>,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ProxyError> {
     let mut new_url = url.get_ref().clone();
     new_url.set_path(req.uri().path());
     new_url.set_query(req.uri().query());
 
     // TODO: This forwarded implementation is incomplete as it only handles the inofficial
     // X-Forwarded-For header but not the official Forwarded one.
-    let forwarded_req = client
-        .request_from(new_url.as_str(), req.head())
+    let mut forwarded = client
+        .request(req.method().clone(), new_url.as_str())
         .no_decompress();
-    let forwarded_req = if let Some(addr) = req.head().peer_addr {
-        forwarded_req.header("x-forwarded-for", format!("{}", addr.ip()))
-    } else {
-        forwarded_req
-    };
+    for (name, value) in end_to_end(req.headers()) {
+        // the length is set from the body below
+        if name != header::CONTENT_LENGTH {
+            forwarded = forwarded.header(name.clone(), value.clone());
+        }
+    }
+    let forwarded = forwarded_for(&req, forwarded);
 
-    let mut res = forwarded_req.send_body(body).await.map_err(Error::from)?;
+    // a sized body stays sized, a chunked one is sent chunked again
+    let chunked = req.headers().contains_key(header::TRANSFER_ENCODING);
+    let res = match content_length(req.headers()) {
+        Some(len) => {
+            let payload = SizedStream::new(len, payload.map_err(boxed));
+            forwarded.send_body(Body::from_message(payload)).await
+        }
+        None if chunked => forwarded.send_stream(payload).await,
+        None => forwarded.send().await,
+    };
+    let res = res.map_err(|e| {
+        log::warn!("{} {}: {}", req.method(), new_url, e);
+        ProxyError::from(e)
+    })?;
 
     let mut client_resp = HttpResponse::build(res.status());
-    // Remove `Connection` as per
-    // https://developer.mozilla.org/en-US/docs/Web/HTTP/Headers/Connection#Directives
-    for (header_name, header_value) in
-        res.headers().iter().filter(|(h, _)| *h != "connection")
-    {
-        client_resp.header(header_name.clone(), header_value.clone());
+    for (name, value) in end_to_end(res.headers()) {
+        client_resp.header(name.clone(), value.clone());
     }
-
-    Ok(client_resp.body(res.body().await?))
+    // the upstream's `Content-Length` is passed through, it is written as is
+    // instead of being replaced with chunked encoding
+    let sized = res.headers().contains_key(header::CONTENT_LENGTH);
+    if sized {
+        client_resp.no_chunking();
+    }
+    let bodyless = req.method() == Method::HEAD
+        || res.status() == StatusCode::NO_CONTENT
+        || res.status() == StatusCode::NOT_MODIFIED;
+    if bodyless && !sized {
+        return Ok(client_resp.body(Body::None));
+    }
+    Ok(client_resp.streaming(res))
 }
 
 #[ntex::main]
 async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,http_proxy=info");
+    env_logger::init();
+
     let matches = clap::App::new("HTTP Proxy")
         .arg(
             Arg::with_name("listen_addr")
@@ -93,7 +228,8 @@ async fn main() -> std::io::Result<()> {
 
     web::server(move || {
         App::new()
-            .data(Client::new())
+            // the timeout is until the response head, bodies may take longer
+            .data(Client::build().timeout(Duration::from_secs(30)).finish())
             .data(forward_url.clone())
             .wrap(middleware::Logger::default())
             .default_service(web::route().to(forward))
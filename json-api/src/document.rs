@@ -0,0 +1,295 @@
+//! Building blocks for JSON:API documents.
+//!
+//! Models implement `ToResource`, and `Compound` collects the primary data and
+//! the included resources into a `Document`, applying sparse fieldsets on the
+//! way.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+use ntex::http::StatusCode;
+use ntex::web::{HttpRequest, HttpResponse, WebResponseError};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+pub const CONTENT_TYPE: &str = "application/vnd.api+json";
+
+/// `type` and `id` pair that identifies a resource
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize)]
+pub struct Identifier {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Linkage {
+    ToOne(Option<Identifier>),
+    ToMany(Vec<Identifier>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct Relationship {
+    pub data: Linkage,
+    pub links: Links,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Links {
+    #[serde(rename = "self")]
+    pub self_link: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related: Option<String>,
+}
+
+impl Links {
+    pub fn to(self_link: String) -> Self {
+        Links {
+            self_link,
+            related: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Resource {
+    #[serde(flatten)]
+    pub identifier: Identifier,
+    #[serde(skip_serializing_if = "Map::is_empty")]
+    pub attributes: Map<String, Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub relationships: BTreeMap<&'static str, Relationship>,
+    pub links: Links,
+}
+
+/// A model that can be rendered as a resource object
+pub trait ToResource {
+    const TYPE: &'static str;
+
+    fn id(&self) -> String;
+
+    fn attributes(&self) -> Map<String, Value>;
+
+    fn relationships(&self) -> BTreeMap<&'static str, Relationship> {
+        BTreeMap::new()
+    }
+
+    fn identifier(&self) -> Identifier {
+        Identifier {
+            kind: Self::TYPE,
+            id: self.id(),
+        }
+    }
+
+    fn self_link(&self) -> String {
+        format!("/{}/{}", Self::TYPE, self.id())
+    }
+
+    /// Resource object restricted to the requested fieldset
+    fn to_resource(&self, fields: &Fieldsets) -> Resource {
+        let mut attributes = self.attributes();
+        let mut relationships = self.relationships();
+        if let Some(allowed) = fields.get(Self::TYPE) {
+            attributes.retain(|name, _| allowed.contains(name));
+            relationships.retain(|name, _| allowed.contains(*name));
+        }
+        Resource {
+            identifier: self.identifier(),
+            attributes,
+            relationships,
+            links: Links::to(self.self_link()),
+        }
+    }
+}
+
+/// Sparse fieldsets, `fields[articles]=title,author` maps `articles` to
+/// the set of field names that should be rendered
+#[derive(Debug, Default)]
+pub struct Fieldsets(HashMap<String, HashSet<String>>);
+
+impl Fieldsets {
+    pub fn get(&self, kind: &str) -> Option<&HashSet<String>> {
+        self.0.get(kind)
+    }
+}
+
+/// The `include` and `fields[...]` query parameters
+#[derive(Debug, Default)]
+pub struct QueryParams {
+    pub include: Vec<String>,
+    pub fields: Fieldsets,
+}
+
+impl QueryParams {
+    pub fn parse(query: &HashMap<String, String>) -> Self {
+        let list = |v: &str| {
+            v.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        let mut params = QueryParams::default();
+        for (key, value) in query {
+            if key == "include" {
+                params.include = list(value);
+            } else if let Some(kind) = key
+                .strip_prefix("fields[")
+                .and_then(|k| k.strip_suffix(']'))
+            {
+                params
+                    .fields
+                    .0
+                    .insert(kind.to_owned(), list(value).into_iter().collect());
+            }
+        }
+        params
+    }
+
+    /// Reject include paths the endpoint doesn't know, as the spec requires
+    pub fn check_include(&self, supported: &[&str]) -> Result<(), ApiError> {
+        match self
+            .include
+            .iter()
+            .find(|i| !supported.contains(&i.as_str()))
+        {
+            Some(path) => Err(ApiError::bad_request(format!(
+                "relationship path `{}` can not be included",
+                path
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    pub fn includes(&self, path: &str) -> bool {
+        self.include.iter().any(|i| i == path)
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum PrimaryData {
+    One(Resource),
+    Many(Vec<Resource>),
+}
+
+#[derive(Debug, Serialize)]
+pub struct Document {
+    pub data: PrimaryData,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub included: Vec<Resource>,
+    pub links: Links,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
+}
+
+impl Document {
+    pub fn into_response(self) -> HttpResponse {
+        HttpResponse::Ok()
+            .content_type(CONTENT_TYPE)
+            .body(serde_json::to_string(&self).unwrap())
+    }
+}
+
+/// Collects included resources for a compound document.
+///
+/// Every resource is included at most once, no matter how many primary
+/// resources point to it.
+pub struct Compound<'a> {
+    fields: &'a Fieldsets,
+    seen: HashSet<Identifier>,
+    included: Vec<Resource>,
+}
+
+impl<'a> Compound<'a> {
+    pub fn new(fields: &'a Fieldsets) -> Self {
+        Compound {
+            fields,
+            seen: HashSet::new(),
+            included: Vec::new(),
+        }
+    }
+
+    pub fn include<T: ToResource>(&mut self, item: &T) {
+        if self.seen.insert(item.identifier()) {
+            self.included.push(item.to_resource(self.fields));
+        }
+    }
+
+    pub fn single<T: ToResource>(self, item: &T) -> Document {
+        Document {
+            links: Links::to(item.self_link()),
+            data: PrimaryData::One(item.to_resource(self.fields)),
+            included: self.included,
+            meta: None,
+        }
+    }
+
+    pub fn collection<T: ToResource>(
+        self,
+        items: &[T],
+        self_link: &str,
+        meta: Option<Value>,
+    ) -> Document {
+        Document {
+            links: Links::to(self_link.to_owned()),
+            data: PrimaryData::Many(
+                items.iter().map(|i| i.to_resource(self.fields)).collect(),
+            ),
+            included: self.included,
+            meta,
+        }
+    }
+}
+
+/// Error rendered as a JSON:API error document
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    title: &'static str,
+    detail: String,
+}
+
+impl ApiError {
+    pub fn bad_request(detail: String) -> Self {
+        ApiError {
+            status: StatusCode::BAD_REQUEST,
+            title: "Invalid query parameter",
+            detail,
+        }
+    }
+
+    pub fn not_found(detail: String) -> Self {
+        ApiError {
+            status: StatusCode::NOT_FOUND,
+            title: "Resource not found",
+            detail,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.title, self.detail)
+    }
+}
+
+impl WebResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = json!({
+            "errors": [{
+                "status": self.status.as_str(),
+                "title": self.title,
+                "detail": self.detail,
+            }]
+        });
+        HttpResponse::build(self.status)
+            .content_type(CONTENT_TYPE)
+            .body(body.to_string())
+    }
+}
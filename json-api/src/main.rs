@@ -0,0 +1,341 @@
+//! JSON:API formatted responses.
+//!
+//! `GET /articles` and `GET /articles/{id}` return articles with `author` and
+//! `comments` relationships. Related resources can be added to the document
+//! with `include=author,comments`, and `fields[type]=a,b` limits the fields
+//! rendered for a resource type.
+use std::collections::{BTreeMap, HashMap};
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde_json::{json, Map, Value};
+
+mod document;
+
+use document::{
+    ApiError, Compound, Identifier, Linkage, Links, QueryParams, Relationship,
+    ToResource,
+};
+
+struct Person {
+    id: u32,
+    name: &'static str,
+    twitter: &'static str,
+}
+
+struct Comment {
+    id: u32,
+    body: &'static str,
+    author: u32,
+}
+
+struct Article {
+    id: u32,
+    title: &'static str,
+    body: &'static str,
+    author: u32,
+    comments: Vec<u32>,
+}
+
+impl ToResource for Person {
+    const TYPE: &'static str = "people";
+
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn attributes(&self) -> Map<String, Value> {
+        let mut attrs = Map::new();
+        attrs.insert("name".into(), self.name.into());
+        attrs.insert("twitter".into(), self.twitter.into());
+        attrs
+    }
+}
+
+impl ToResource for Comment {
+    const TYPE: &'static str = "comments";
+
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn attributes(&self) -> Map<String, Value> {
+        let mut attrs = Map::new();
+        attrs.insert("body".into(), self.body.into());
+        attrs
+    }
+
+    fn relationships(&self) -> BTreeMap<&'static str, Relationship> {
+        let mut rels = BTreeMap::new();
+        rels.insert("author", to_one::<Person>(self, "author", self.author));
+        rels
+    }
+}
+
+impl ToResource for Article {
+    const TYPE: &'static str = "articles";
+
+    fn id(&self) -> String {
+        self.id.to_string()
+    }
+
+    fn attributes(&self) -> Map<String, Value> {
+        let mut attrs = Map::new();
+        attrs.insert("title".into(), self.title.into());
+        attrs.insert("body".into(), self.body.into());
+        attrs
+    }
+
+    fn relationships(&self) -> BTreeMap<&'static str, Relationship> {
+        let mut rels = BTreeMap::new();
+        rels.insert("author", to_one::<Person>(self, "author", self.author));
+        rels.insert(
+            "comments",
+            Relationship {
+                data: Linkage::ToMany(
+                    self.comments
+                        .iter()
+                        .map(|id| Identifier {
+                            kind: Comment::TYPE,
+                            id: id.to_string(),
+                        })
+                        .collect(),
+                ),
+                links: Links::to(format!("{}/comments", self.self_link())),
+            },
+        );
+        rels
+    }
+}
+
+/// The `name` relationship from `owner` to the resource `T` with `id`
+fn to_one<T: ToResource>(owner: &impl ToResource, name: &str, id: u32) -> Relationship {
+    Relationship {
+        data: Linkage::ToOne(Some(Identifier {
+            kind: T::TYPE,
+            id: id.to_string(),
+        })),
+        links: Links {
+            self_link: format!("{}/relationships/{}", owner.self_link(), name),
+            related: Some(format!("/{}/{}", T::TYPE, id)),
+        },
+    }
+}
+
+/// In-memory data set
+struct Store {
+    people: Vec<Person>,
+    comments: Vec<Comment>,
+    articles: Vec<Article>,
+}
+
+impl Store {
+    fn new() -> Self {
+        Store {
+            people: vec![
+                Person {
+                    id: 9,
+                    name: "Dan Gebhardt",
+                    twitter: "dgeb",
+                },
+                Person {
+                    id: 2,
+                    name: "Yehuda Katz",
+                    twitter: "wycats",
+                },
+            ],
+            comments: vec![
+                Comment {
+                    id: 5,
+                    body: "First!",
+                    author: 2,
+                },
+                Comment {
+                    id: 12,
+                    body: "I like XML better",
+                    author: 9,
+                },
+            ],
+            articles: vec![
+                Article {
+                    id: 1,
+                    title: "JSON:API paints my bikeshed!",
+                    body: "The shortest article. Ever.",
+                    author: 9,
+                    comments: vec![5, 12],
+                },
+                Article {
+                    id: 2,
+                    title: "Rails is Omakase",
+                    body: "There are a lot of a-la-carte software environments.",
+                    author: 9,
+                    comments: vec![],
+                },
+            ],
+        }
+    }
+
+    fn person(&self, id: u32) -> Option<&Person> {
+        self.people.iter().find(|p| p.id == id)
+    }
+
+    fn comment(&self, id: u32) -> Option<&Comment> {
+        self.comments.iter().find(|c| c.id == id)
+    }
+
+    /// Add the requested relationships of `article` to the document
+    fn include(&self, article: &Article, params: &QueryParams, doc: &mut Compound) {
+        if params.includes("author") {
+            if let Some(author) = self.person(article.author) {
+                doc.include(author);
+            }
+        }
+        if params.includes("comments") {
+            for comment in article.comments.iter().filter_map(|id| self.comment(*id)) {
+                doc.include(comment);
+            }
+        }
+    }
+}
+
+const ARTICLE_INCLUDES: &[&str] = &["author", "comments"];
+
+async fn articles(
+    store: web::types::Data<Store>,
+    query: web::types::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, ApiError> {
+    let params = QueryParams::parse(&query);
+    params.check_include(ARTICLE_INCLUDES)?;
+
+    let mut doc = Compound::new(&params.fields);
+    for article in &store.articles {
+        store.include(article, &params, &mut doc);
+    }
+    let meta = json!({ "total": store.articles.len() });
+
+    Ok(doc
+        .collection(&store.articles, "/articles", Some(meta))
+        .into_response())
+}
+
+async fn article(
+    store: web::types::Data<Store>,
+    id: web::types::Path<u32>,
+    query: web::types::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, ApiError> {
+    let params = QueryParams::parse(&query);
+    params.check_include(ARTICLE_INCLUDES)?;
+
+    let article =
+        store.articles.iter().find(|a| a.id == *id).ok_or_else(|| {
+            ApiError::not_found(format!("article {} does not exist", id))
+        })?;
+
+    let mut doc = Compound::new(&params.fields);
+    store.include(article, &params, &mut doc);
+    Ok(doc.single(article).into_response())
+}
+
+async fn person(
+    store: web::types::Data<Store>,
+    id: web::types::Path<u32>,
+    query: web::types::Query<HashMap<String, String>>,
+) -> Result<HttpResponse, ApiError> {
+    let params = QueryParams::parse(&query);
+    params.check_include(&[])?;
+
+    let person = store
+        .person(*id)
+        .ok_or_else(|| ApiError::not_found(format!("person {} does not exist", id)))?;
+    Ok(Compound::new(&params.fields).single(person).into_response())
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/articles", web::get().to(articles))
+        .route("/articles/{id}", web::get().to(article))
+        .route("/people/{id}", web::get().to(person));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    let store = web::types::Data::new(Store::new());
+
+    web::server(move || {
+        App::new()
+            .app_data(store.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{header, StatusCode};
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_include_author() {
+        let app =
+            test::init_service(App::new().data(Store::new()).configure(app_config))
+                .await;
+
+        let req = test::TestRequest::get()
+            .uri("/articles/1?include=author&fields[articles]=title,author")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/vnd.api+json"
+        );
+
+        let body = test::read_body(resp).await;
+        let doc: Value = serde_json::from_slice(&body).unwrap();
+
+        // primary data, restricted to the sparse fieldset
+        let data = &doc["data"];
+        assert_eq!(data["type"], "articles");
+        assert_eq!(data["id"], "1");
+        assert_eq!(
+            data["attributes"],
+            json!({ "title": "JSON:API paints my bikeshed!" })
+        );
+        assert!(data["relationships"].get("comments").is_none());
+        assert_eq!(
+            data["relationships"]["author"]["data"],
+            json!({ "type": "people", "id": "9" })
+        );
+        assert_eq!(doc["links"]["self"], "/articles/1");
+
+        // the author is included once, with full linkage to the primary data
+        let included = doc["included"].as_array().unwrap();
+        assert_eq!(included.len(), 1);
+        assert_eq!(included[0]["type"], "people");
+        assert_eq!(included[0]["id"], "9");
+        assert_eq!(included[0]["attributes"]["name"], "Dan Gebhardt");
+        assert_eq!(included[0]["links"]["self"], "/people/9");
+    }
+
+    #[ntex::test]
+    async fn test_unknown_include() {
+        let app =
+            test::init_service(App::new().data(Store::new()).configure(app_config))
+                .await;
+
+        let req = test::TestRequest::get()
+            .uri("/articles?include=publisher")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let body = test::read_body(resp).await;
+        let doc: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(doc["errors"][0]["status"], "400");
+    }
+}
@@ -0,0 +1,144 @@
+//! Request bodies and query strings checked with `validator`, see `valid`.
+//!
+//! `POST /orders` takes a JSON order, nested items included, `GET /products`
+//! takes paging and sorting from the query string, with defaults for what
+//! is left out.
+use std::borrow::Cow;
+use std::convert::TryFrom;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+mod valid;
+
+use valid::{ValidJson, ValidQuery};
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+struct Item {
+    #[validate(length(min = 1, max = 16, message = "must be 1 to 16 characters"))]
+    sku: String,
+    #[validate(range(min = 1, max = 99, message = "must be between 1 and 99"))]
+    quantity: u32,
+}
+
+#[derive(Debug, Deserialize, Serialize, Validate)]
+struct Order {
+    #[validate(email(message = "must be an email address"))]
+    email: String,
+    #[validate(length(min = 1, message = "must not be empty"))]
+    #[validate]
+    items: Vec<Item>,
+    #[validate(length(max = 200, message = "must be at most 200 characters"))]
+    note: Option<String>,
+}
+
+const SORT_FIELDS: [&str; 3] = ["name", "price", "-price"];
+
+fn sort_field(sort: &str) -> Result<(), ValidationError> {
+    if SORT_FIELDS.contains(&sort) {
+        return Ok(());
+    }
+    let mut err = ValidationError::new("sort");
+    err.message = Some(Cow::from(format!("must be one of {:?}", SORT_FIELDS)));
+    Err(err)
+}
+
+/// Everything left out of the query string gets its default
+#[derive(Debug, Deserialize, Validate)]
+#[serde(default)]
+struct ListParams {
+    #[validate(range(min = 1, message = "must be at least 1"))]
+    page: u32,
+    #[validate(range(min = 1, max = 100, message = "must be between 1 and 100"))]
+    per_page: u32,
+    #[validate(custom = "sort_field")]
+    sort: String,
+}
+
+impl Default for ListParams {
+    fn default() -> Self {
+        ListParams {
+            page: 1,
+            per_page: 20,
+            sort: "name".to_owned(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize)]
+struct Product {
+    sku: &'static str,
+    name: &'static str,
+    price: u32,
+}
+
+const PRODUCTS: [Product; 4] = [
+    Product {
+        sku: "tea",
+        name: "Green tea",
+        price: 450,
+    },
+    Product {
+        sku: "mug",
+        name: "Mug",
+        price: 1200,
+    },
+    Product {
+        sku: "pot",
+        name: "Teapot",
+        price: 3900,
+    },
+    Product {
+        sku: "cozy",
+        name: "Tea cozy",
+        price: 1500,
+    },
+];
+
+async fn products(params: ValidQuery<ListParams>) -> HttpResponse {
+    let mut products = PRODUCTS.to_vec();
+    match params.sort.as_str() {
+        "price" => products.sort_by_key(|p| p.price),
+        "-price" => products.sort_by_key(|p| std::cmp::Reverse(p.price)),
+        _ => products.sort_by_key(|p| p.name),
+    }
+    // both are u32, the product fits into a u64. Past the end is an empty page
+    let start = (u64::from(params.page) - 1) * u64::from(params.per_page);
+    let start = usize::try_from(start).unwrap_or(usize::MAX);
+    let page: Vec<_> = products
+        .into_iter()
+        .skip(start)
+        .take(params.per_page as usize)
+        .collect();
+    HttpResponse::Ok().json(&serde_json::json!({
+        "page": params.page,
+        "per_page": params.per_page,
+        "sort": params.sort,
+        "products": page,
+    }))
+}
+
+async fn create_order(order: ValidJson<Order>) -> HttpResponse {
+    let order = order.into_inner();
+    log::info!("order of {} items for {}", order.items.len(), order.email);
+    HttpResponse::Created().json(&order)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,json_validation=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            // read by `ValidJson` through `Json`
+            .app_data(web::types::JsonConfig::default().limit(4096))
+            .wrap(middleware::Logger::default())
+            .route("/products", web::get().to(products))
+            .route("/orders", web::post().to(create_order))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,231 @@
+//! Extractors that validate what they extract.
+//!
+//! `ValidJson<T>` and `ValidQuery<T>` extract like `Json<T>` and `Query<T>`,
+//! then run the `validator` checks of `T`. Whatever fails is answered with
+//! one response that lists every failed field:
+//!
+//! ```json
+//! {"error": "invalid request", "errors": [
+//!     {"field": "items[1].quantity", "code": "range", "message": "..."}
+//! ]}
+//! ```
+//!
+//! A body that isn't JSON at all is a `400`, one with the wrong types a
+//! `422`, the same as a failed check. Those stop at the first problem, serde
+//! doesn't go on after it.
+use std::ops::Deref;
+
+use futures::future::{FutureExt, LocalBoxFuture};
+use ntex::http::{Payload, StatusCode};
+use ntex::web::error::{JsonPayloadError, QueryPayloadError};
+use ntex::web::{
+    self, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use validator::{Validate, ValidationErrors, ValidationErrorsKind};
+
+/// One failed check, `field` is the path from the top of the value
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    field: String,
+    code: String,
+    message: String,
+}
+
+#[derive(Debug)]
+pub struct InvalidRequest {
+    status: StatusCode,
+    errors: Vec<FieldError>,
+}
+
+impl std::fmt::Display for InvalidRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "validation failed for {} field(s)", self.errors.len())
+    }
+}
+
+impl WebResponseError for InvalidRequest {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(self.status).json(&serde_json::json!({
+            "error": "invalid request",
+            "errors": self.errors,
+        }))
+    }
+}
+
+impl InvalidRequest {
+    fn single(status: StatusCode, field: &str, code: &str, message: String) -> Self {
+        InvalidRequest {
+            status,
+            errors: vec![FieldError {
+                field: field.to_owned(),
+                code: code.to_owned(),
+                message,
+            }],
+        }
+    }
+}
+
+impl From<ValidationErrors> for InvalidRequest {
+    fn from(errors: ValidationErrors) -> Self {
+        let mut flat = Vec::new();
+        flatten("", &errors, &mut flat);
+        // the checks are kept in hash maps, sorted the order is stable
+        flat.sort_by(|a, b| a.field.cmp(&b.field));
+        InvalidRequest {
+            status: StatusCode::UNPROCESSABLE_ENTITY,
+            errors: flat,
+        }
+    }
+}
+
+impl From<JsonPayloadError> for InvalidRequest {
+    fn from(err: JsonPayloadError) -> Self {
+        match err {
+            JsonPayloadError::Deserialize(e) if e.is_data() => InvalidRequest::single(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "",
+                "invalid_type",
+                e.to_string(),
+            ),
+            JsonPayloadError::Deserialize(e) => InvalidRequest::single(
+                StatusCode::BAD_REQUEST,
+                "",
+                "invalid_json",
+                e.to_string(),
+            ),
+            JsonPayloadError::ContentType => InvalidRequest::single(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "",
+                "content_type",
+                "the body has to be application/json".to_owned(),
+            ),
+            JsonPayloadError::Overflow => InvalidRequest::single(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "",
+                "too_large",
+                err.to_string(),
+            ),
+            JsonPayloadError::Payload(e) => InvalidRequest::single(
+                StatusCode::BAD_REQUEST,
+                "",
+                "payload",
+                e.to_string(),
+            ),
+        }
+    }
+}
+
+impl From<QueryPayloadError> for InvalidRequest {
+    fn from(err: QueryPayloadError) -> Self {
+        let QueryPayloadError::Deserialize(e) = err;
+        InvalidRequest::single(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "",
+            "invalid_query",
+            e.to_string(),
+        )
+    }
+}
+
+/// Every error of `errors` and the values nested in it, with its path
+fn flatten(prefix: &str, errors: &ValidationErrors, out: &mut Vec<FieldError>) {
+    let path = |field: &str| {
+        if prefix.is_empty() {
+            field.to_owned()
+        } else {
+            format!("{}.{}", prefix, field)
+        }
+    };
+    for (field, kind) in errors.errors() {
+        match kind {
+            ValidationErrorsKind::Field(errors) => out.extend(errors.iter().map(|e| {
+                FieldError {
+                    field: path(field),
+                    code: e.code.to_string(),
+                    message: e
+                        .message
+                        .as_ref()
+                        .map_or_else(|| e.code.to_string(), |m| m.to_string()),
+                }
+            })),
+            ValidationErrorsKind::Struct(errors) => flatten(&path(field), errors, out),
+            ValidationErrorsKind::List(items) => {
+                for (index, errors) in items {
+                    flatten(&format!("{}[{}]", path(field), index), errors, out);
+                }
+            }
+        }
+    }
+}
+
+/// A JSON body that passed its checks
+pub struct ValidJson<T>(pub T);
+
+impl<T> ValidJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T, Err: ErrorRenderer> FromRequest<Err> for ValidJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = InvalidRequest;
+    type Future = LocalBoxFuture<'static, Result<Self, InvalidRequest>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        // `Json` reads the body, with the limit of the `JsonConfig`
+        let json = <web::types::Json<T> as FromRequest<Err>>::from_request(req, payload);
+        async move {
+            let value = json.await?.into_inner();
+            value.validate()?;
+            Ok(ValidJson(value))
+        }
+        .boxed_local()
+    }
+}
+
+/// A query string that passed its checks
+pub struct ValidQuery<T>(pub T);
+
+impl<T> Deref for ValidQuery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T, Err: ErrorRenderer> FromRequest<Err> for ValidQuery<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = InvalidRequest;
+    type Future = futures::future::Ready<Result<Self, InvalidRequest>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let value = web::types::Query::<T>::from_query(req.query_string())
+            .map_err(InvalidRequest::from)
+            .and_then(|query| {
+                let value = query.into_inner();
+                value.validate()?;
+                Ok(ValidQuery(value))
+            });
+        futures::future::ready(value)
+    }
+}
@@ -0,0 +1,21 @@
+#!/usr/bin/env bash
+# Exercise connection reuse and pipelining against the keepalive-tuning example.
+HOST=${HOST:-127.0.0.1}
+PORT=${PORT:-8080}
+
+echo "== three sequential requests, curl reuses one connection"
+curl -s "http://$HOST:$PORT/a" "http://$HOST:$PORT/b" "http://$HOST:$PORT/c"
+
+echo
+echo "== three pipelined requests written at once on a single connection"
+# bash's /dev/tcp gives us a raw socket, so no netcat is needed
+exec 3<>"/dev/tcp/$HOST/$PORT"
+printf 'GET /one HTTP/1.1\r\nHost: %s\r\n\r\nGET /two HTTP/1.1\r\nHost: %s\r\n\r\nGET /three HTTP/1.1\r\nHost: %s\r\nConnection: close\r\n\r\n' \
+    "$HOST" "$HOST" "$HOST" >&3
+grep -a "^connection #" <&3
+exec 3<&-
+
+echo
+echo "== two separate curl invocations, always two connections"
+curl -s "http://$HOST:$PORT/x"
+curl -s "http://$HOST:$PORT/y"
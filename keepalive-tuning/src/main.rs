@@ -0,0 +1,94 @@
+//! Keep-alive and pipelining.
+//!
+//! The server is assembled from `HttpService` by hand instead of using
+//! `web::server()`, because that gives access to the `on_connect` callback.
+//! It runs once per accepted connection and its result is stored in the
+//! extensions of every request received on that connection, so handlers can
+//! tell which connection a request arrived on.
+//!
+//! Keep-alive is configured with the `KEEP_ALIVE` env var:
+//!
+//! * `KEEP_ALIVE=5` - idle connections are closed after 5 seconds (default)
+//! * `KEEP_ALIVE=os` - rely on the OS to close idle connections
+//! * `KEEP_ALIVE=off` - every response closes the connection
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ntex::http::{HttpService, KeepAlive};
+use ntex::rt::net::TcpStream;
+use ntex::service::map_config;
+use ntex::web::dev::AppConfig;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+/// Global connection counter shared by all workers
+static NEXT_CONNECTION: AtomicUsize = AtomicUsize::new(1);
+
+/// Per-connection data, created by the `on_connect` callback
+#[derive(Clone)]
+struct Connection {
+    id: usize,
+    /// number of requests served on this connection so far
+    requests: Rc<Cell<usize>>,
+}
+
+async fn index(req: HttpRequest) -> HttpResponse {
+    let conn = req.extensions().get::<Connection>().cloned().unwrap();
+    conn.requests.set(conn.requests.get() + 1);
+
+    log::info!(
+        "connection #{} ({:?}): request #{} {}",
+        conn.id,
+        req.peer_addr(),
+        conn.requests.get(),
+        req.path()
+    );
+
+    HttpResponse::Ok().body(format!(
+        "connection #{}, request #{} on this connection\n",
+        conn.id,
+        conn.requests.get()
+    ))
+}
+
+fn keep_alive_from_env() -> KeepAlive {
+    match std::env::var("KEEP_ALIVE").as_ref().map(|s| s.as_str()) {
+        Ok("off") => KeepAlive::Disabled,
+        Ok("os") => KeepAlive::Os,
+        Ok(secs) => {
+            KeepAlive::Timeout(secs.parse().expect("KEEP_ALIVE is not a number"))
+        }
+        Err(_) => KeepAlive::Timeout(5),
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,keepalive_tuning=info");
+    env_logger::init();
+
+    let keep_alive = keep_alive_from_env();
+    log::info!("keep-alive: {:?}", keep_alive);
+
+    ntex::server::build()
+        .workers(2)
+        .bind("keepalive-tuning", "127.0.0.1:8080", move || {
+            HttpService::build()
+                .keep_alive(keep_alive)
+                // time to receive the first request on a new connection, ms
+                .client_timeout(5000)
+                .on_connect(|_: &TcpStream| Connection {
+                    id: NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed),
+                    requests: Rc::new(Cell::new(0)),
+                })
+                .h1(map_config(
+                    App::new()
+                        .wrap(middleware::Logger::default())
+                        .route("/{tail}*", web::get().to(index)),
+                    |_| AppConfig::default(),
+                ))
+                .tcp()
+        })?
+        .run()
+        .await
+}
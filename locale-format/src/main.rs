@@ -0,0 +1,266 @@
+//! Locale aware formatting.
+//!
+//! The locale is negotiated from the `Accept-Language` header against the
+//! catalog of supported locales held in `Data`. Numbers are formatted with
+//! `num-format`, dates with chrono's localized formatting, and currency with a
+//! per-locale pattern. Unsupported locales fall back to `en-US`.
+use chrono::{NaiveDate, Utc};
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use num_format::{Locale, ToFormattedString};
+use serde::Serialize;
+
+/// Formatting rules for one locale
+struct LocaleFormat {
+    tag: &'static str,
+    numbers: Locale,
+    dates: chrono::Locale,
+    date_pattern: &'static str,
+    /// `{}` is replaced with the formatted amount
+    currency_pattern: &'static str,
+}
+
+/// All supported locales, the first one is the default
+struct Catalog {
+    locales: Vec<LocaleFormat>,
+}
+
+impl Catalog {
+    fn new() -> Self {
+        Catalog {
+            locales: vec![
+                LocaleFormat {
+                    tag: "en-US",
+                    numbers: Locale::en,
+                    dates: chrono::Locale::en_US,
+                    date_pattern: "%B %-d, %Y",
+                    currency_pattern: "${}",
+                },
+                LocaleFormat {
+                    tag: "de-DE",
+                    numbers: Locale::de,
+                    dates: chrono::Locale::de_DE,
+                    date_pattern: "%-d. %B %Y",
+                    currency_pattern: "{} €",
+                },
+                LocaleFormat {
+                    tag: "fr-FR",
+                    numbers: Locale::fr,
+                    dates: chrono::Locale::fr_FR,
+                    date_pattern: "%-d %B %Y",
+                    currency_pattern: "{} €",
+                },
+            ],
+        }
+    }
+
+    fn default_locale(&self) -> &LocaleFormat {
+        &self.locales[0]
+    }
+
+    /// Pick the best supported locale for an `Accept-Language` header value.
+    ///
+    /// Tags are tried in order of their quality value. An exact match wins,
+    /// otherwise the primary language is matched (`de` or `de-AT` -> `de-DE`).
+    fn negotiate(&self, accept_language: Option<&str>) -> &LocaleFormat {
+        let header = match accept_language {
+            Some(h) => h,
+            None => return self.default_locale(),
+        };
+
+        let mut ranges: Vec<(&str, f32)> = header
+            .split(',')
+            .filter_map(|item| {
+                let mut parts = item.trim().split(';');
+                let tag = parts.next()?.trim();
+                let q = parts
+                    .find_map(|p| p.trim().strip_prefix("q="))
+                    .map(|q| q.parse::<f32>().unwrap_or(0.0))
+                    .unwrap_or(1.0);
+                // `q=NaN` isn't `<= 0.0`, and can't be ordered either
+                if tag.is_empty() || !q.is_finite() || q <= 0.0 {
+                    None
+                } else {
+                    Some((tag, q))
+                }
+            })
+            .collect();
+        // stable sort keeps header order for equal weights
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        for (tag, _) in ranges {
+            if let Some(l) = self
+                .locales
+                .iter()
+                .find(|l| l.tag.eq_ignore_ascii_case(tag))
+            {
+                return l;
+            }
+            let lang = tag.split('-').next().unwrap_or(tag);
+            if let Some(l) = self
+                .locales
+                .iter()
+                .find(|l| l.tag.split('-').next().unwrap().eq_ignore_ascii_case(lang))
+            {
+                return l;
+            }
+        }
+        self.default_locale()
+    }
+}
+
+impl LocaleFormat {
+    fn integer(&self, n: i64) -> String {
+        n.to_formatted_string(&self.numbers)
+    }
+
+    /// Format with two fraction digits, using the locale's separators
+    fn decimal(&self, n: f64) -> String {
+        let cents = (n * 100.0).round() as i64;
+        // -0.50 has no integer part to carry the sign
+        let sign = if cents < 0 {
+            self.numbers.minus_sign()
+        } else {
+            ""
+        };
+        let cents = cents.unsigned_abs();
+        format!(
+            "{}{}{}{:02}",
+            sign,
+            (cents / 100).to_formatted_string(&self.numbers),
+            self.numbers.decimal(),
+            cents % 100
+        )
+    }
+
+    fn currency(&self, n: f64) -> String {
+        self.currency_pattern.replace("{}", &self.decimal(n))
+    }
+
+    fn date(&self, date: NaiveDate) -> String {
+        date.format_localized(self.date_pattern, self.dates)
+            .to_string()
+    }
+}
+
+#[derive(Serialize)]
+struct Report {
+    locale: &'static str,
+    visitors: String,
+    revenue: String,
+    average_order: String,
+    report_date: String,
+    generated: String,
+}
+
+/// The same data, rendered for the negotiated locale
+async fn report(catalog: web::types::Data<Catalog>, req: HttpRequest) -> HttpResponse {
+    let accept = req
+        .headers()
+        .get(header::ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok());
+    let locale = catalog.negotiate(accept);
+
+    let visitors = 1_234_567;
+    let revenue = 9_876_543.21;
+    let report_date = NaiveDate::from_ymd_opt(2020, 3, 1).unwrap();
+
+    HttpResponse::Ok()
+        .header(header::CONTENT_LANGUAGE, locale.tag)
+        .header(header::VARY, "Accept-Language")
+        .json(&Report {
+            locale: locale.tag,
+            visitors: locale.integer(visitors),
+            revenue: locale.currency(revenue),
+            average_order: locale.currency(revenue / visitors as f64),
+            report_date: locale.date(report_date),
+            generated: locale.date(Utc::now().naive_utc().date()),
+        })
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    // the catalog is read-only, create it once and share it between workers
+    let catalog = web::types::Data::new(Catalog::new());
+
+    web::server(move || {
+        App::new()
+            .app_data(catalog.clone())
+            .wrap(middleware::Logger::default())
+            .route("/report", web::get().to(report))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::web::test;
+    use serde_json::Value;
+
+    async fn report_for(accept_language: Option<&str>) -> Value {
+        let app = test::init_service(
+            App::new()
+                .data(Catalog::new())
+                .route("/report", web::get().to(report)),
+        )
+        .await;
+
+        let mut req = test::TestRequest::get().uri("/report");
+        if let Some(value) = accept_language {
+            req = req.header(header::ACCEPT_LANGUAGE, value);
+        }
+        test::read_response_json(&app, req.to_request()).await
+    }
+
+    #[ntex::test]
+    async fn test_en_us_vs_de_de() {
+        let en = report_for(Some("en-US")).await;
+        let de = report_for(Some("de-DE,de;q=0.9")).await;
+
+        assert_eq!(en["locale"], "en-US");
+        assert_eq!(en["visitors"], "1,234,567");
+        assert_eq!(en["revenue"], "$9,876,543.21");
+        assert_eq!(en["average_order"], "$8.00");
+        assert_eq!(en["report_date"], "March 1, 2020");
+
+        assert_eq!(de["locale"], "de-DE");
+        assert_eq!(de["visitors"], "1.234.567");
+        assert_eq!(de["revenue"], "9.876.543,21 €");
+        assert_eq!(de["average_order"], "8,00 €");
+        assert_eq!(de["report_date"], "1. März 2020");
+    }
+
+    #[ntex::test]
+    async fn test_negotiation() {
+        // quality values win over header order
+        let fr = report_for(Some("de;q=0.5, fr-CA;q=0.8")).await;
+        assert_eq!(fr["locale"], "fr-FR");
+
+        // unsupported locales and a missing header fall back to the default
+        let unsupported = report_for(Some("ja-JP, zh;q=0.8")).await;
+        assert_eq!(unsupported["locale"], "en-US");
+        assert_eq!(unsupported["visitors"], "1,234,567");
+
+        let missing = report_for(None).await;
+        assert_eq!(missing["locale"], "en-US");
+
+        // not a number, dropped instead of sorted
+        let nan = report_for(Some("en;q=NaN, de;q=0.5")).await;
+        assert_eq!(nan["locale"], "de-DE");
+    }
+
+    #[test]
+    fn test_negative_decimal() {
+        let catalog = Catalog::new();
+        let en = catalog.negotiate(Some("en-US"));
+        assert_eq!(en.decimal(-0.5), "-0.50");
+        assert_eq!(en.decimal(-1234.5), "-1,234.50");
+        assert_eq!(en.decimal(0.5), "0.50");
+    }
+}
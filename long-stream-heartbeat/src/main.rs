@@ -0,0 +1,113 @@
+//! A long-lived event stream with heartbeats.
+//!
+//! Proxies and load balancers close connections that carry no data for a
+//! while, nginx after 60 seconds by default, many cloud load balancers even
+//! sooner. A stream with long quiet periods between events gets cut, and the
+//! client has to reconnect. Sending a comment line every few seconds keeps
+//! the connection busy, SSE clients ignore comments.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{ready, select, Either};
+use futures::{stream, SinkExt, Stream, StreamExt};
+use ntex::http::header;
+use ntex::rt::time::{delay_for, interval_at, Instant};
+use ntex::web::{self, middleware, App, Error, HttpResponse};
+use serde::Deserialize;
+
+mod proxy;
+
+/// Default time between heartbeats, well below common proxy timeouts
+const HEARTBEAT_INTERVAL: u64 = 5;
+/// Time between two events, longer than the proxy's idle timeout
+const EVENT_INTERVAL: Duration = Duration::from_secs(20);
+
+#[derive(Deserialize)]
+struct StreamParams {
+    /// Seconds between heartbeats, `0` disables them
+    heartbeat: Option<u64>,
+}
+
+/// Produces an event now and then, until the client is gone. That is
+/// noticed right away, not only when the next event can't be sent
+async fn produce(mut tx: mpsc::Sender<Bytes>, mut gone: oneshot::Receiver<()>) {
+    for n in 1.. {
+        let event = format!("id: {}\nevent: tick\ndata: event {}\n\n", n, n);
+        if tx.send(Bytes::from(event)).await.is_err() {
+            break;
+        }
+        if let Either::Right(_) = select(delay_for(EVENT_INTERVAL), &mut gone).await {
+            log::info!("client is gone, stopped producing after {} events", n);
+            break;
+        }
+    }
+}
+
+/// The response body. ntex drops it when the client disconnects, which
+/// drops the heartbeat interval and tells the producer to stop
+struct EventStream<S> {
+    body: S,
+    _gone: oneshot::Sender<()>,
+}
+
+impl<S: Stream + Unpin> Stream for EventStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<S::Item>> {
+        Pin::new(&mut self.body).poll_next(cx)
+    }
+}
+
+async fn events(params: web::types::Query<StreamParams>) -> HttpResponse {
+    let (tx, rx) = mpsc::channel(16);
+    let (gone_tx, gone) = oneshot::channel();
+    ntex::rt::spawn(produce(tx, gone));
+
+    let heartbeats = match params.heartbeat.unwrap_or(HEARTBEAT_INTERVAL) {
+        0 => stream::pending().left_stream(),
+        secs => {
+            let period = Duration::from_secs(secs);
+            interval_at(Instant::now() + period, period)
+                .map(|_| Bytes::from_static(b": heartbeat\n\n"))
+                .right_stream()
+        }
+    };
+    let body = stream::once(ready(Bytes::from_static(b"retry: 3000\n\n")))
+        .chain(stream::select(rx, heartbeats))
+        .map(Ok::<_, Error>);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        // nginx buffers responses, which would hold back events and
+        // heartbeats alike
+        .header("x-accel-buffering", "no")
+        .streaming(EventStream {
+            body,
+            _gone: gone_tx,
+        })
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,long_stream_heartbeat=info");
+    env_logger::init();
+
+    let server = web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/events", web::get().to(events))
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    // a proxy in front of it, like in production
+    let proxy = proxy::start("127.0.0.1:8081", "http://127.0.0.1:8080")?;
+    futures::try_join!(server, proxy).map(|_| ())
+}
@@ -0,0 +1,61 @@
+//! A reverse proxy that closes idle streams.
+//!
+//! Like nginx's `proxy_read_timeout`, an upstream response is cut off when
+//! no data arrived for `IDLE_TIMEOUT`. It is here to show what heartbeats
+//! are good for, without setting up a real proxy.
+use std::time::Duration;
+
+use futures::{stream, StreamExt};
+use ntex::http::client::Client;
+use ntex::rt::time::timeout;
+use ntex::server::Server;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Upstream(String);
+
+async fn forward(
+    req: HttpRequest,
+    upstream: web::types::Data<Upstream>,
+    client: web::types::Data<Client>,
+) -> HttpResponse {
+    let url = format!("{}{}", upstream.0, req.uri());
+    let res = match client.request_from(url.as_str(), req.head()).send().await {
+        Ok(res) => res,
+        Err(e) => return HttpResponse::BadGateway().body(e.to_string()),
+    };
+
+    let mut builder = HttpResponse::build(res.status());
+    for (name, value) in res.headers() {
+        builder.header(name.clone(), value.clone());
+    }
+    let body = stream::unfold(res, |mut res| async move {
+        match timeout(IDLE_TIMEOUT, res.next()).await {
+            Ok(Some(chunk)) => Some((chunk, res)),
+            Ok(None) => None,
+            Err(_) => {
+                log::warn!("proxy: no data for {:?}, closing the stream", IDLE_TIMEOUT);
+                None
+            }
+        }
+    });
+    builder.streaming(body.boxed_local())
+}
+
+/// Starts the proxy on `addr`, it forwards everything to `upstream`
+pub fn start(addr: &str, upstream: &str) -> std::io::Result<Server> {
+    let upstream = web::types::Data::new(Upstream(upstream.to_owned()));
+
+    Ok(web::server(move || {
+        App::new()
+            .app_data(upstream.clone())
+            // the response head has to arrive in time, the body may take as
+            // long as it likes
+            .data(Client::build().timeout(Duration::from_secs(5)).finish())
+            .wrap(middleware::Logger::new("proxy: %r %s %Dms"))
+            .default_service(web::route().to(forward))
+    })
+    .bind(addr)?
+    .run())
+}
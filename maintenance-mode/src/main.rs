@@ -0,0 +1,139 @@
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod maintenance;
+
+use maintenance::{Maintenance, MaintenanceMode};
+
+async fn index() -> &'static str {
+    "Hello world!"
+}
+
+async fn health() -> &'static str {
+    "ok"
+}
+
+#[derive(Deserialize, Serialize)]
+struct Status {
+    enabled: bool,
+}
+
+async fn get_status(flag: web::types::Data<Maintenance>) -> HttpResponse {
+    HttpResponse::Ok().json(&Status {
+        enabled: flag.is_enabled(),
+    })
+}
+
+/// Switch maintenance mode on or off.
+///
+/// In a real application this endpoint must be protected, see the readme.
+async fn set_status(
+    flag: web::types::Data<Maintenance>,
+    status: web::types::Json<Status>,
+) -> HttpResponse {
+    flag.set(status.enabled);
+    let state = if status.enabled {
+        "enabled"
+    } else {
+        "disabled"
+    };
+    log::warn!("maintenance mode {}", state);
+    HttpResponse::Ok().json(&*status)
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/health", web::get().to(health))
+        .service(
+            web::resource("/admin/maintenance")
+                .route(web::get().to(get_status))
+                .route(web::put().to(set_status)),
+        );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,maintenance_mode=info");
+    env_logger::init();
+
+    // one flag for all workers, clients are asked to retry after 2 minutes
+    let flag = web::types::Data::new(Maintenance::new(120));
+
+    web::server(move || {
+        App::new()
+            .app_data(flag.clone())
+            .wrap(
+                MaintenanceMode::new(flag.clone())
+                    .allow("/health")
+                    .allow("/admin/"),
+            )
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{header, StatusCode};
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_toggle_maintenance() {
+        let flag = web::types::Data::new(Maintenance::new(120));
+        let app = test::init_service(
+            App::new()
+                .app_data(flag.clone())
+                .wrap(
+                    MaintenanceMode::new(flag.clone())
+                        .allow("/health")
+                        .allow("/admin/"),
+                )
+                .configure(app_config),
+        )
+        .await;
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        let toggle = |enabled: bool| {
+            test::TestRequest::put()
+                .uri("/admin/maintenance")
+                .set_json(&Status { enabled })
+                .to_request()
+        };
+
+        let resp = test::call_service(&app, get("/")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // switch on, regular routes get the maintenance page
+        let resp = test::call_service(&app, toggle(true)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&app, get("/")).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get(header::RETRY_AFTER).unwrap(), "120");
+        let body = test::read_body(resp).await;
+        assert!(std::str::from_utf8(&body)
+            .unwrap()
+            .contains("Down for maintenance"));
+
+        // the allowlist keeps working
+        let resp = test::call_service(&app, get("/health")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        // only whole segments are allowed
+        let resp = test::call_service(&app, get("/healthz")).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let status: Status =
+            test::read_response_json(&app, get("/admin/maintenance")).await;
+        assert!(status.enabled);
+
+        // and off again
+        let resp = test::call_service(&app, toggle(false)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        let resp = test::call_service(&app, get("/")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+}
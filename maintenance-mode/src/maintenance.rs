@@ -0,0 +1,119 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Either, Ready};
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error, HttpResponse};
+use ntex::{Service, Transform};
+
+/// Maintenance flag, shared by all workers
+pub struct Maintenance {
+    enabled: AtomicBool,
+    /// seconds sent in `Retry-After`
+    retry_after: u32,
+}
+
+impl Maintenance {
+    pub fn new(retry_after: u32) -> Self {
+        Maintenance {
+            enabled: AtomicBool::new(false),
+            retry_after,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+}
+
+/// Serves the maintenance page while the flag is set.
+///
+/// Paths under one of the allowed prefixes are always passed through, whole
+/// segments only: `/health` allows `/health` and `/health/db`, not
+/// `/healthz`.
+pub struct MaintenanceMode {
+    flag: web::types::Data<Maintenance>,
+    allow: Vec<String>,
+}
+
+impl MaintenanceMode {
+    pub fn new(flag: web::types::Data<Maintenance>) -> Self {
+        MaintenanceMode {
+            flag,
+            allow: Vec::new(),
+        }
+    }
+
+    /// Keep serving `prefix` and the paths under it
+    pub fn allow(mut self, prefix: &str) -> Self {
+        self.allow.push(prefix.trim_end_matches('/').to_owned());
+        self
+    }
+}
+
+fn is_under(path: &str, prefix: &str) -> bool {
+    path.strip_prefix(prefix)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+impl<S, Err> Transform<S> for MaintenanceMode
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MaintenanceModeMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MaintenanceModeMiddleware {
+            service,
+            flag: self.flag.clone(),
+            allow: self.allow.clone(),
+        })
+    }
+}
+
+pub struct MaintenanceModeMiddleware<S> {
+    service: S,
+    flag: web::types::Data<Maintenance>,
+    allow: Vec<String>,
+}
+
+impl<S, Err> Service for MaintenanceModeMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let allowed = self.allow.iter().any(|prefix| is_under(req.path(), prefix));
+
+        if allowed || !self.flag.is_enabled() {
+            Either::Left(self.service.call(req))
+        } else {
+            Either::Right(ok(req.into_response(
+                HttpResponse::ServiceUnavailable()
+                    .header(header::RETRY_AFTER, self.flag.retry_after.to_string())
+                    .header(header::CACHE_CONTROL, "no-store")
+                    .content_type("text/html; charset=utf-8")
+                    .body(include_str!("maintenance.html"))
+                    .into_body(),
+            )))
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Serialize;
+
+mod version;
+
+use version::ApiVersion;
+
+/// The resource as it is stored, every version is a view of it
+struct User {
+    id: u32,
+    first_name: &'static str,
+    last_name: &'static str,
+    email: &'static str,
+    created_at: &'static str,
+}
+
+/// Version 1, a flat object with the full name
+#[derive(Serialize)]
+struct UserV1 {
+    id: u32,
+    name: String,
+    email: &'static str,
+}
+
+#[derive(Serialize)]
+struct NameV2 {
+    first: &'static str,
+    last: &'static str,
+}
+
+#[derive(Serialize)]
+struct ContactV2 {
+    email: &'static str,
+}
+
+/// Version 2, the name split up and the contact details grouped
+#[derive(Serialize)]
+struct UserV2 {
+    id: u32,
+    name: NameV2,
+    contact: ContactV2,
+    created_at: &'static str,
+}
+
+impl From<&User> for UserV1 {
+    fn from(user: &User) -> Self {
+        UserV1 {
+            id: user.id,
+            name: format!("{} {}", user.first_name, user.last_name),
+            email: user.email,
+        }
+    }
+}
+
+impl From<&User> for UserV2 {
+    fn from(user: &User) -> Self {
+        UserV2 {
+            id: user.id,
+            name: NameV2 {
+                first: user.first_name,
+                last: user.last_name,
+            },
+            contact: ContactV2 { email: user.email },
+            created_at: user.created_at,
+        }
+    }
+}
+
+const USERS: &[User] = &[
+    User {
+        id: 1,
+        first_name: "Ada",
+        last_name: "Lovelace",
+        email: "ada@example.com",
+        created_at: "2020-01-12T09:30:00Z",
+    },
+    User {
+        id: 2,
+        first_name: "Alan",
+        last_name: "Turing",
+        email: "alan@example.com",
+        created_at: "2020-03-02T14:05:00Z",
+    },
+];
+
+/// Answers with the serializer of the requested version
+fn versioned<T: Serialize>(version: ApiVersion, body: &T) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(version.media_type())
+        // caches must keep the versions apart
+        .header(header::VARY, "Accept")
+        .json(body)
+}
+
+/// An unsupported version fails in the extractor, the handler always has
+/// one it knows
+async fn get_user(id: web::types::Path<u32>, version: ApiVersion) -> HttpResponse {
+    let user = match USERS.iter().find(|u| u.id == *id) {
+        Some(user) => user,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    match version {
+        ApiVersion::V1 => versioned(version, &UserV1::from(user)),
+        ApiVersion::V2 => versioned(version, &UserV2::from(user)),
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/users/{id}", web::get().to(get_user))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,134 @@
+//! The API version a client asked for with the `Accept` header.
+//!
+//! `Accept: application/vnd.myapp.v1+json` asks for version 1. A request
+//! without a vendor type, `application/json`, `*/*` or no `Accept` at all,
+//! gets the latest version. Media ranges are tried by their `q` value, the
+//! first one that names a known version, or any json, wins. When none does,
+//! for example `application/vnd.myapp.v9+json` alone, the request fails with
+//! `406 Not Acceptable`.
+use derive_more::Display;
+use futures::future::{ready, Ready};
+use ntex::http::{header, Payload};
+use ntex::web::{
+    ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+
+const VENDOR_PREFIX: &str = "application/vnd.myapp.v";
+const VENDOR_SUFFIX: &str = "+json";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+    const ALL: &'static [ApiVersion] = &[ApiVersion::V1, ApiVersion::V2];
+
+    fn number(self) -> u32 {
+        match self {
+            ApiVersion::V1 => 1,
+            ApiVersion::V2 => 2,
+        }
+    }
+
+    /// The response content type of this version
+    pub fn media_type(self) -> String {
+        format!("{}{}{}", VENDOR_PREFIX, self.number(), VENDOR_SUFFIX)
+    }
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "none of the accepted media types is supported")]
+pub struct NotAcceptable;
+
+impl WebResponseError for NotAcceptable {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let supported: Vec<_> = ApiVersion::ALL.iter().map(|v| v.media_type()).collect();
+        HttpResponse::NotAcceptable().json(&serde_json::json!({
+            "error": self.to_string(),
+            "supported": supported,
+        }))
+    }
+}
+
+/// What one media range of `Accept` asks for
+enum Wanted {
+    Version(ApiVersion),
+    /// A version this server doesn't have
+    Unknown,
+    /// Any json, or anything at all
+    Latest,
+    /// Not json, html for example
+    Other,
+}
+
+fn wanted(media_type: &str) -> Wanted {
+    let media_type = media_type.to_ascii_lowercase();
+    if let Some(number) = media_type
+        .strip_prefix(VENDOR_PREFIX)
+        .and_then(|rest| rest.strip_suffix(VENDOR_SUFFIX))
+    {
+        return ApiVersion::ALL
+            .iter()
+            .find(|v| number == v.number().to_string())
+            .map(|v| Wanted::Version(*v))
+            .unwrap_or(Wanted::Unknown);
+    }
+    match media_type.as_str() {
+        "application/json" | "application/*" | "*/*" => Wanted::Latest,
+        _ => Wanted::Other,
+    }
+}
+
+/// Parses `Accept` into media types, best `q` first. Ranges with the same
+/// `q` keep their order
+fn media_ranges(accept: &str) -> Vec<&str> {
+    let mut ranges: Vec<(&str, f32)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';').map(str::trim);
+            let media_type = parts.next().filter(|t| !t.is_empty())?;
+            let q = parts
+                .filter_map(|p| p.strip_prefix("q="))
+                .find_map(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            Some((media_type, q))
+        })
+        .filter(|(_, q)| *q > 0.0)
+        .collect();
+    ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    ranges
+        .into_iter()
+        .map(|(media_type, _)| media_type)
+        .collect()
+}
+
+fn negotiate(accept: Option<&str>) -> Result<ApiVersion, NotAcceptable> {
+    let accept = match accept {
+        Some(accept) if !accept.trim().is_empty() => accept,
+        _ => return Ok(ApiVersion::LATEST),
+    };
+    for media_type in media_ranges(accept) {
+        match wanted(media_type) {
+            Wanted::Version(version) => return Ok(version),
+            Wanted::Latest => return Ok(ApiVersion::LATEST),
+            Wanted::Unknown | Wanted::Other => {}
+        }
+    }
+    Err(NotAcceptable)
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for ApiVersion {
+    type Error = NotAcceptable;
+    type Future = Ready<Result<Self, NotAcceptable>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let accept = req
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok());
+        ready(negotiate(accept))
+    }
+}
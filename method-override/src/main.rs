@@ -0,0 +1,190 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod method_override;
+
+use method_override::MethodOverride;
+
+struct Notes(Mutex<BTreeMap<u32, String>>);
+
+#[derive(Deserialize)]
+struct NoteForm {
+    text: String,
+}
+
+fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+/// Every note with a form to edit it and one to delete it, both `POST`
+async fn list(notes: web::types::Data<Notes>) -> HttpResponse {
+    let mut html = String::from("<h1>Notes</h1>\n");
+    for (id, text) in notes.0.lock().unwrap().iter() {
+        html.push_str(&format!(
+            r#"<form method="post" action="/notes/{id}">
+  <input type="hidden" name="_method" value="PUT">
+  <input name="text" value="{text}">
+  <button>Save</button>
+</form>
+<form method="post" action="/notes/{id}">
+  <input type="hidden" name="_method" value="DELETE">
+  <button>Delete</button>
+</form>
+"#,
+            id = id,
+            text = text
+                .replace('&', "&amp;")
+                .replace('"', "&quot;")
+                .replace('<', "&lt;"),
+        ));
+    }
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+async fn update(
+    id: web::types::Path<u32>,
+    form: web::types::Form<NoteForm>,
+    notes: web::types::Data<Notes>,
+) -> HttpResponse {
+    match notes.0.lock().unwrap().get_mut(&id) {
+        Some(text) => {
+            *text = form.into_inner().text;
+            see_other("/notes")
+        }
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn delete(
+    id: web::types::Path<u32>,
+    notes: web::types::Data<Notes>,
+) -> HttpResponse {
+    match notes.0.lock().unwrap().remove(&id) {
+        Some(_) => see_other("/notes"),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/notes", web::get().to(list)).service(
+        web::resource("/notes/{id}")
+            .route(web::put().to(update))
+            .route(web::delete().to(delete)),
+    );
+}
+
+fn notes() -> Notes {
+    let notes = vec![(1, "buy milk"), (2, "call mom"), (3, "water the plants")];
+    Notes(Mutex::new(
+        notes
+            .into_iter()
+            .map(|(id, t)| (id, t.to_owned()))
+            .collect(),
+    ))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,method_override=info");
+    env_logger::init();
+
+    let notes = web::types::Data::new(notes());
+
+    web::server(move || {
+        App::new()
+            .app_data(notes.clone())
+            .wrap(MethodOverride::default())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{Method, StatusCode};
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_form_override() {
+        let notes = web::types::Data::new(notes());
+        let app = test::init_service(
+            App::new()
+                .app_data(notes.clone())
+                .wrap(MethodOverride::default())
+                .configure(app_config),
+        )
+        .await;
+
+        // a form deleting a note reaches the DELETE handler
+        let req = test::TestRequest::post()
+            .uri("/notes/1")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .set_payload("_method=DELETE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert!(!notes.0.lock().unwrap().contains_key(&1));
+
+        // the handler still gets the whole form
+        let req = test::TestRequest::post()
+            .uri("/notes/2")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .set_payload("_method=put&text=call+dad")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        assert_eq!(notes.0.lock().unwrap()[&2], "call dad");
+
+        let req = test::TestRequest::post()
+            .uri("/notes/3")
+            .header(method_override::HEADER, "DELETE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::SEE_OTHER);
+        let left: Vec<_> = notes.0.lock().unwrap().keys().copied().collect();
+        assert_eq!(left, vec![2]);
+    }
+
+    #[ntex::test]
+    async fn test_restricted_override() {
+        let notes = web::types::Data::new(notes());
+        let app = test::init_service(
+            App::new()
+                .app_data(notes.clone())
+                .wrap(MethodOverride::new(&[Method::PUT]))
+                .configure(app_config),
+        )
+        .await;
+
+        // not in the allow list
+        let req = test::TestRequest::post()
+            .uri("/notes/1")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .set_payload("_method=DELETE")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        // only POST is overridden
+        let req = test::TestRequest::get()
+            .uri("/notes/1")
+            .header(method_override::HEADER, "PUT")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        assert_eq!(notes.0.lock().unwrap().len(), 3);
+    }
+}
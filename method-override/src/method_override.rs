@@ -0,0 +1,170 @@
+//! Lets HTML forms send `PUT`, `PATCH` and `DELETE` requests.
+//!
+//! Forms can only `GET` and `POST`. A `POST` with an `X-HTTP-Method-Override`
+//! header, or a urlencoded body with a `_method` field, is routed as if it
+//! had been sent with that method. The middleware has to wrap the app, so
+//! the method is changed before routing.
+//!
+//! Only `POST` is overridden, a `GET` link or image could otherwise delete
+//! things, and only to the methods in the allow list. For the form field
+//! the body is read, and handed on to the handler unchanged.
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use bytes::BytesMut;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use ntex::http::{h1, header, Method};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, HttpResponse};
+use ntex::{Service, Transform};
+
+pub const HEADER: &str = "x-http-method-override";
+pub const FIELD: &str = "_method";
+
+/// Largest form body that is searched for `_method`
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+#[derive(Clone)]
+pub struct MethodOverride {
+    allowed: Rc<Vec<Method>>,
+}
+
+impl Default for MethodOverride {
+    /// Allows `PUT`, `PATCH` and `DELETE`
+    fn default() -> Self {
+        MethodOverride::new(&[Method::PUT, Method::PATCH, Method::DELETE])
+    }
+}
+
+impl MethodOverride {
+    pub fn new(allowed: &[Method]) -> Self {
+        MethodOverride {
+            allowed: Rc::new(allowed.to_vec()),
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for MethodOverride
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MethodOverrideMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MethodOverrideMiddleware {
+            service: Rc::new(service),
+            allowed: self.allowed.clone(),
+        })
+    }
+}
+
+pub struct MethodOverrideMiddleware<S> {
+    service: Rc<S>,
+    allowed: Rc<Vec<Method>>,
+}
+
+/// The method a request asked for, `Err` with it if it isn't allowed
+fn parse(value: &str, allowed: &[Method]) -> Result<Method, String> {
+    let value = value.trim().to_ascii_uppercase();
+    match value.parse::<Method>() {
+        Ok(method) if allowed.contains(&method) => Ok(method),
+        _ => Err(value),
+    }
+}
+
+fn is_form<Err>(req: &WebRequest<Err>) -> bool {
+    req.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false)
+}
+
+fn rejected<Err>(req: WebRequest<Err>, method: &str) -> WebResponse {
+    log::warn!("refusing to override POST {} with {}", req.path(), method);
+    req.into_response(
+        HttpResponse::BadRequest()
+            .body(format!("method override to `{}` is not allowed\n", method))
+            .into_body(),
+    )
+}
+
+impl<S, Err> Service for MethodOverrideMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: WebRequest<Err>) -> Self::Future {
+        if req.method() != Method::POST {
+            return self.service.call(req).boxed_local();
+        }
+
+        // the header wins, it doesn't need the body
+        if let Some(value) = req.headers().get(HEADER) {
+            let value = value.to_str().unwrap_or("").to_owned();
+            return match parse(&value, &self.allowed) {
+                Ok(method) => {
+                    req.head_mut().method = method;
+                    self.service.call(req).boxed_local()
+                }
+                Err(method) => ok(rejected(req, &method)).boxed_local(),
+            };
+        }
+        if !is_form(&req) {
+            return self.service.call(req).boxed_local();
+        }
+
+        let svc = self.service.clone();
+        let allowed = self.allowed.clone();
+        async move {
+            let mut body = BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                let chunk = chunk?;
+                if body.len() + chunk.len() > MAX_FORM_SIZE {
+                    return Ok(req.into_response(
+                        HttpResponse::PayloadTooLarge().finish().into_body(),
+                    ));
+                }
+                body.extend_from_slice(&chunk);
+            }
+            let body = body.freeze();
+
+            let field = serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+                .ok()
+                .and_then(|fields| fields.into_iter().find(|(k, _)| k == FIELD))
+                .map(|(_, v)| v);
+            if let Some(value) = field {
+                match parse(&value, &allowed) {
+                    Ok(method) => req.head_mut().method = method,
+                    Err(method) => return Ok(rejected(req, &method)),
+                }
+            }
+
+            let (mut sender, restored) = h1::Payload::create(false);
+            sender.feed_data(body);
+            sender.feed_eof();
+            req.set_payload(restored.into());
+            svc.call(req).await
+        }
+        .boxed_local()
+    }
+}
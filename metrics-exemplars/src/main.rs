@@ -0,0 +1,189 @@
+//! Request latencies in `/metrics`, with the trace id of a request in the
+//! bucket it landed in, see `metrics`.
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::web::{self, App, HttpResponse};
+use rand::Rng;
+
+mod metrics;
+
+use metrics::{Histogram, Metrics};
+
+/// Usually quick
+async fn search() -> HttpResponse {
+    let ms = rand::thread_rng().gen_range(5, 30);
+    delay_for(Duration::from_millis(ms)).await;
+    HttpResponse::Ok().json(&["first result", "second result"])
+}
+
+/// Always slow, the one to find in the traces
+async fn report() -> HttpResponse {
+    delay_for(Duration::from_millis(600)).await;
+    HttpResponse::Ok().json(&["monthly totals"])
+}
+
+async fn exposition(histogram: web::types::Data<Histogram>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type(metrics::CONTENT_TYPE)
+        .body(histogram.render())
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/search", web::get().to(search))
+        .route("/report", web::get().to(report))
+        .route("/metrics", web::get().to(exposition));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,metrics_exemplars=info");
+    env_logger::init();
+
+    let histogram = web::types::Data::new(Histogram::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(histogram.clone())
+            .wrap(Metrics::new(histogram.clone()))
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use ntex::http::header::{self, HeaderMap, HeaderValue};
+    use ntex::web::test;
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+
+    #[derive(Debug)]
+    struct Bucket {
+        count: u64,
+        exemplar: Option<(String, f64)>,
+    }
+
+    /// The `_bucket` samples by route and `le`, checking the framing on the way
+    fn parse(exposition: &str) -> HashMap<(String, String), Bucket> {
+        assert!(exposition.ends_with("# EOF\n"));
+        let mut buckets = HashMap::new();
+        for line in exposition.lines().filter(|l| !l.starts_with('#')) {
+            let (sample, exemplar) = match line.find(" # ") {
+                Some(at) => (&line[..at], Some(&line[at + 3..])),
+                None => (line, None),
+            };
+            let (series, value) = sample.rsplit_once(' ').unwrap();
+            let value: f64 = value.parse().unwrap();
+            let (name, labels) = series.split_once('{').unwrap();
+            let labels: HashMap<&str, &str> = labels
+                .trim_end_matches('}')
+                .split(',')
+                .map(|l| {
+                    let (k, v) = l.split_once('=').unwrap();
+                    (k, v.trim_matches('"'))
+                })
+                .collect();
+
+            if name != "http_request_duration_seconds_bucket" {
+                assert!(exemplar.is_none(), "{}", line);
+                continue;
+            }
+            let exemplar = exemplar.map(|e| {
+                // {trace_id="..."} value timestamp
+                let (labels, rest) = e.split_once("} ").unwrap();
+                let trace_id = labels.strip_prefix("{trace_id=\"").unwrap();
+                let mut rest = rest.split(' ');
+                let value = rest.next().unwrap().parse().unwrap();
+                let _timestamp: f64 = rest.next().unwrap().parse().unwrap();
+                (trace_id.trim_end_matches('"').to_owned(), value)
+            });
+            let key = (labels["route"].to_owned(), labels["le"].to_owned());
+            let bucket = Bucket {
+                count: value as u64,
+                exemplar,
+            };
+            buckets.insert(key, bucket);
+        }
+        buckets
+    }
+
+    #[ntex::test]
+    async fn test_slow_traced_request_has_an_exemplar() {
+        let histogram = web::types::Data::new(Histogram::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(histogram.clone())
+                .wrap(Metrics::new(histogram.clone()))
+                .configure(app),
+        )
+        .await;
+
+        let traceparent = format!("00-{}-00f067aa0ba902b7-01", TRACE_ID);
+        let req = test::TestRequest::with_uri("/report")
+            .header("traceparent", traceparent)
+            .to_request();
+        test::call_service(&app, req).await;
+        for _ in 0..3 {
+            let req = test::TestRequest::with_uri("/search").to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let req = test::TestRequest::with_uri("/metrics").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(
+            res.headers().get(header::CONTENT_TYPE).unwrap(),
+            metrics::CONTENT_TYPE
+        );
+        let body = test::read_body(res).await;
+        let buckets = parse(std::str::from_utf8(&body).unwrap());
+
+        let bucket =
+            |route: &str, le: &str| &buckets[&(route.to_owned(), le.to_owned())];
+        // 600ms is in the 1.0 bucket, and in the cumulative ones above it
+        assert_eq!(bucket("/report", "0.5").count, 0);
+        assert_eq!(bucket("/report", "1.0").count, 1);
+        assert_eq!(bucket("/report", "+Inf").count, 1);
+        let (trace_id, value) = bucket("/report", "1.0").exemplar.clone().unwrap();
+        assert_eq!(trace_id, TRACE_ID);
+        assert!((0.5..=1.0).contains(&value));
+        let exemplars = buckets.values().filter(|b| b.exemplar.is_some()).count();
+        assert_eq!(exemplars, 1);
+
+        // counted, without exemplars
+        assert_eq!(bucket("/search", "+Inf").count, 3);
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let trace_id = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::HeaderName::from_static("traceparent"),
+                HeaderValue::from_static(value),
+            );
+            metrics::trace_id(&headers)
+        };
+        let sampled = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        assert_eq!(trace_id(sampled).as_deref(), Some(TRACE_ID));
+
+        for value in &[
+            // not sampled
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6-00f067aa0ba902b7-01",
+            "garbage",
+        ] {
+            assert_eq!(trace_id(value), None, "{}", value);
+        }
+        assert_eq!(metrics::trace_id(&HeaderMap::new()), None);
+    }
+}
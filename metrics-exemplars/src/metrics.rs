@@ -0,0 +1,241 @@
+//! A request latency histogram in the OpenMetrics text format, with
+//! exemplars.
+//!
+//! An exemplar ties a bucket to one request that landed in it, by its
+//! trace id. A dashboard showing a spike in the slow buckets links straight
+//! to the trace of a slow request. The trace id comes from the W3C
+//! `traceparent` header, and is only used when the trace is sampled, an
+//! unsampled one was never recorded, there is nothing to link to.
+//! Requests without one are counted as usual, they just leave the
+//! exemplars alone.
+//!
+//! Each bucket keeps the exemplar of the last traced request that landed in
+//! it, not in a bucket below.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header::HeaderMap;
+use ntex::http::StatusCode;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error};
+use ntex::{Service, Transform};
+
+pub const CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+const NAME: &str = "http_request_duration_seconds";
+
+/// Upper bounds, the `le` labels spelled the way OpenMetrics wants them
+const BUCKETS: &[(f64, &str)] = &[
+    (0.005, "0.005"),
+    (0.01, "0.01"),
+    (0.025, "0.025"),
+    (0.05, "0.05"),
+    (0.1, "0.1"),
+    (0.25, "0.25"),
+    (0.5, "0.5"),
+    (1.0, "1.0"),
+    (2.5, "2.5"),
+    (5.0, "5.0"),
+    (10.0, "10.0"),
+];
+
+#[derive(Clone)]
+struct Exemplar {
+    trace_id: String,
+    value: f64,
+    /// Seconds since the epoch
+    timestamp: f64,
+}
+
+/// One route's observations, the last bucket is `+Inf`
+#[derive(Default)]
+struct Series {
+    counts: [u64; BUCKETS.len() + 1],
+    exemplars: [Option<Exemplar>; BUCKETS.len() + 1],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+pub struct Histogram {
+    series: Mutex<BTreeMap<String, Series>>,
+}
+
+impl Histogram {
+    pub fn observe(&self, route: &str, seconds: f64, trace_id: Option<String>) {
+        let bucket = BUCKETS
+            .iter()
+            .position(|(le, _)| seconds <= *le)
+            .unwrap_or(BUCKETS.len());
+
+        let mut series = self.series.lock().unwrap();
+        let series = series.entry(route.to_owned()).or_default();
+        series.counts[bucket] += 1;
+        series.count += 1;
+        series.sum += seconds;
+        if let Some(trace_id) = trace_id {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            series.exemplars[bucket] = Some(Exemplar {
+                trace_id,
+                value: seconds,
+                timestamp,
+            });
+        }
+    }
+
+    /// The exposition, ending with the `# EOF` OpenMetrics requires
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# TYPE {} histogram", NAME);
+        let _ = writeln!(out, "# UNIT {} seconds", NAME);
+        let _ = writeln!(out, "# HELP {} Time to the response head.", NAME);
+
+        for (route, series) in self.series.lock().unwrap().iter() {
+            let route = escape(route);
+            let bounds = BUCKETS.iter().map(|(_, le)| *le).chain(Some("+Inf"));
+            let mut cumulative = 0;
+            for (i, le) in bounds.enumerate() {
+                // the counts of a bucket include every bucket below it
+                cumulative += series.counts[i];
+                let _ = write!(
+                    out,
+                    "{}_bucket{{route=\"{}\",le=\"{}\"}} {}",
+                    NAME, route, le, cumulative
+                );
+                if let Some(e) = &series.exemplars[i] {
+                    let _ = write!(
+                        out,
+                        " # {{trace_id=\"{}\"}} {} {:.3}",
+                        e.trace_id, e.value, e.timestamp
+                    );
+                }
+                out.push('\n');
+            }
+            let _ = writeln!(
+                out,
+                "{}_count{{route=\"{}\"}} {}",
+                NAME, route, series.count
+            );
+            let _ = writeln!(out, "{}_sum{{route=\"{}\"}} {}", NAME, route, series.sum);
+        }
+        out.push_str("# EOF\n");
+        out
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// The trace id of a sampled `traceparent`, like
+/// `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`
+pub fn trace_id(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get("traceparent")?.to_str().ok()?;
+    let mut parts = value.trim().split('-');
+    let (version, trace_id, parent_id, flags) =
+        (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+
+    let hex = |s: &str, len| {
+        s.len() == len && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    };
+    // version 00 has exactly four parts, later ones may add more
+    if !hex(version, 2) || version == "ff" || (version == "00" && parts.next().is_some())
+    {
+        return None;
+    }
+    if !hex(trace_id, 32) || !hex(parent_id, 16) || !hex(flags, 2) {
+        return None;
+    }
+    if trace_id.bytes().all(|b| b == b'0') || parent_id.bytes().all(|b| b == b'0') {
+        return None;
+    }
+    let sampled = u8::from_str_radix(flags, 16).ok()? & 1 == 1;
+    if sampled {
+        Some(trace_id.to_owned())
+    } else {
+        None
+    }
+}
+
+/// Observes the latency of every request, by path. Responses with `404`
+/// count as `unmatched`, made up paths would add a series each
+pub struct Metrics {
+    histogram: web::types::Data<Histogram>,
+}
+
+impl Metrics {
+    pub fn new(histogram: web::types::Data<Histogram>) -> Self {
+        Metrics { histogram }
+    }
+}
+
+impl<S, Err> Transform<S> for Metrics
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware {
+            service,
+            histogram: self.histogram.clone(),
+        })
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: S,
+    histogram: web::types::Data<Histogram>,
+}
+
+impl<S, Err> Service for MetricsMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let started = Instant::now();
+        let path = req.path().to_owned();
+        let trace_id = trace_id(req.headers());
+        let histogram = self.histogram.clone();
+
+        self.service
+            .call(req)
+            .map(move |res| {
+                let route = match &res {
+                    Ok(res) if res.status() == StatusCode::NOT_FOUND => "unmatched",
+                    _ => path.as_str(),
+                };
+                let seconds = started.elapsed().as_secs_f64();
+                histogram.observe(route, seconds, trace_id);
+                res
+            })
+            .boxed_local()
+    }
+}
@@ -0,0 +1,77 @@
+//! Prometheus metrics for every request, see `metrics`, scraped from
+//! `GET /metrics`. The handlers take a random while, so the histogram has
+//! something to show, and the orders count is a metric of the app's own in
+//! the same registry.
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, HttpResponse};
+use prometheus::IntCounter;
+use rand::Rng;
+
+mod metrics;
+
+use metrics::{HttpMetrics, Metrics};
+
+async fn random_delay(max_ms: u64) {
+    let ms = rand::thread_rng().gen_range(0, max_ms);
+    delay_for(Duration::from_millis(ms)).await;
+}
+
+async fn index() -> HttpResponse {
+    random_delay(20).await;
+    HttpResponse::Ok().body("Hello world!")
+}
+
+async fn user(id: web::types::Path<u32>) -> HttpResponse {
+    random_delay(100).await;
+    HttpResponse::Ok().json(&serde_json::json!({ "id": *id }))
+}
+
+async fn order(orders: web::types::Data<IntCounter>) -> HttpResponse {
+    random_delay(300).await;
+    orders.inc();
+    HttpResponse::Created().finish()
+}
+
+async fn metrics(metrics: web::types::Data<HttpMetrics>) -> HttpResponse {
+    match metrics.render() {
+        Ok((content_type, body)) => {
+            HttpResponse::Ok().content_type(content_type).body(body)
+        }
+        Err(e) => {
+            log::error!("can not render metrics: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,metrics=info");
+    env_logger::init();
+
+    let to_io = |e: prometheus::Error| std::io::Error::other(e.to_string());
+    let http_metrics = web::types::Data::new(HttpMetrics::new().map_err(to_io)?);
+    let orders = IntCounter::new("orders_total", "Orders placed").map_err(to_io)?;
+    http_metrics
+        .registry()
+        .register(Box::new(orders.clone()))
+        .map_err(to_io)?;
+    let orders = web::types::Data::new(orders);
+
+    web::server(move || {
+        App::new()
+            .app_data(http_metrics.clone())
+            .app_data(orders.clone())
+            .wrap(Metrics::new(http_metrics.clone()))
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/users/{id}", web::get().to(user))
+            .route("/orders", web::post().to(order))
+            .route("/metrics", web::get().to(metrics))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,269 @@
+//! Request metrics in a `prometheus::Registry`.
+//!
+//! `Metrics` counts every request and observes its latency, labeled by
+//! method, path and status, and keeps a gauge of the requests in flight.
+//! `HttpMetrics` holds the registry, it lives in `Data` and is shared by all
+//! workers, `GET /metrics` renders it.
+//!
+//! Every label value is a series of its own. The path is the pattern of the
+//! resource that matched, `/users/{id}` for `/users/42`, a `404` counts as
+//! `unmatched` and a method that isn't a standard one as `other`, or each
+//! id, each made up path and each made up method would add one.
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::{Method, StatusCode, Uri};
+use ntex::router::Path;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error};
+use ntex::{Service, Transform};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+
+pub struct HttpMetrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    duration: HistogramVec,
+    in_flight: IntGaugeVec,
+}
+
+impl HttpMetrics {
+    pub fn new() -> prometheus::Result<Self> {
+        let labels = ["method", "path", "status"];
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Requests handled"),
+            &labels,
+        )?;
+        let duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Time from the request to the response",
+            ),
+            &labels,
+        )?;
+        // neither the status nor whether the path matched is known yet
+        let in_flight = IntGaugeVec::new(
+            Opts::new("http_requests_in_flight", "Requests being handled"),
+            &["method"],
+        )?;
+
+        let registry = Registry::new();
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(duration.clone()))?;
+        registry.register(Box::new(in_flight.clone()))?;
+        Ok(HttpMetrics {
+            registry,
+            requests,
+            duration,
+            in_flight,
+        })
+    }
+
+    /// The registry, for the metrics of the application itself
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Everything in the registry, in the text format
+    pub fn render(&self) -> prometheus::Result<(String, Vec<u8>)> {
+        let encoder = TextEncoder::new();
+        let mut buf = Vec::new();
+        encoder.encode(&self.registry.gather(), &mut buf)?;
+        Ok((encoder.format_type().to_owned(), buf))
+    }
+}
+
+/// The path with the values the router matched put back as `{name}`,
+/// `/users/42/orders` matched by `/users/{id}/orders` is the pattern again.
+/// ntex doesn't keep the pattern itself in the request.
+fn path_label(path: &Path<Uri>) -> String {
+    let full = path.get_ref().path();
+    let mut label = String::new();
+    let mut end = 0;
+    for (name, value) in path.iter() {
+        // most values are slices of the path, where they start tells where
+        // they are in it
+        let offset = (value.as_ptr() as usize).wrapping_sub(full.as_ptr() as usize);
+        let range = match offset.checked_add(value.len()) {
+            Some(stop) if offset >= end && stop <= full.len() => offset..stop,
+            // a value unescaped from a segment with a `%` in it is a copy,
+            // it stands for the next such segment
+            _ => match full[end..]
+                .split('/')
+                .scan(end, |start, segment| {
+                    let range = *start..*start + segment.len();
+                    *start = range.end + 1;
+                    Some(range)
+                })
+                .find(|range| full[range.clone()].contains('%'))
+            {
+                Some(range) => range,
+                None => continue,
+            },
+        };
+        label.push_str(&full[end..range.start]);
+        label.push_str(&format!("{{{}}}", name));
+        end = range.end;
+    }
+    label.push_str(&full[end..]);
+    label
+}
+
+fn method_label(method: &Method) -> &str {
+    match *method {
+        Method::GET
+        | Method::HEAD
+        | Method::POST
+        | Method::PUT
+        | Method::DELETE
+        | Method::CONNECT
+        | Method::OPTIONS
+        | Method::TRACE
+        | Method::PATCH => method.as_str(),
+        _ => "other",
+    }
+}
+
+/// Decrements the gauge however the request ends, a client that goes away
+/// drops the future before it completes
+struct InFlight(prometheus::IntGauge);
+
+impl InFlight {
+    fn start(gauge: prometheus::IntGauge) -> Self {
+        gauge.inc();
+        InFlight(gauge)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+pub struct Metrics {
+    metrics: web::types::Data<HttpMetrics>,
+}
+
+impl Metrics {
+    pub fn new(metrics: web::types::Data<HttpMetrics>) -> Self {
+        Metrics { metrics }
+    }
+}
+
+impl<S, Err> Transform<S> for Metrics
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = MetricsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(MetricsMiddleware {
+            service,
+            metrics: self.metrics.clone(),
+        })
+    }
+}
+
+pub struct MetricsMiddleware<S> {
+    service: S,
+    metrics: web::types::Data<HttpMetrics>,
+}
+
+impl<S, Err> Service for MetricsMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let started = Instant::now();
+        let method = method_label(req.method()).to_owned();
+        let metrics = self.metrics.clone();
+        let in_flight =
+            InFlight::start(metrics.in_flight.with_label_values(&[method.as_str()]));
+
+        let fut = self.service.call(req);
+        async move {
+            let res = fut.await;
+            drop(in_flight);
+            // routing is done, the request knows what matched. An error
+            // has no request left, no resource answered it
+            let (status, path) = match &res {
+                Ok(res) if res.status() != StatusCode::NOT_FOUND => {
+                    (res.status(), path_label(res.request().match_info()))
+                }
+                Ok(res) => (res.status(), "unmatched".to_owned()),
+                Err(e) => (e.as_response_error().status_code(), "unmatched".to_owned()),
+            };
+            let labels = [method.as_str(), path.as_str(), status.as_str()];
+            metrics.requests.with_label_values(&labels).inc();
+            metrics
+                .duration
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+            res
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::web::{test, App, HttpResponse};
+
+    async fn ok() -> HttpResponse {
+        HttpResponse::Ok().finish()
+    }
+
+    #[ntex::test]
+    async fn test_labels() {
+        let metrics = web::types::Data::new(HttpMetrics::new().unwrap());
+        let app = test::init_service(
+            App::new()
+                .wrap(Metrics::new(metrics.clone()))
+                .route("/users/{id}/files/{name}", web::get().to(ok))
+                .route("/orders", web::to(ok)),
+        )
+        .await;
+        for (method, uri) in [
+            ("GET", "/users/42/files/a%20b"),
+            ("GET", "/users/7/files/notes"),
+            ("GET", "/users/42"),
+            ("FOO", "/orders"),
+        ] {
+            let req = test::TestRequest::with_uri(uri)
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .to_request();
+            test::call_service(&app, req).await;
+        }
+
+        let (_, text) = metrics.render().unwrap();
+        let text = String::from_utf8(text).unwrap();
+        for series in [
+            r#"http_requests_total{method="GET",path="/users/{id}/files/{name}",status="200"} 2"#,
+            r#"http_requests_total{method="GET",path="unmatched",status="404"} 1"#,
+            r#"http_requests_total{method="other",path="/orders",status="200"} 1"#,
+        ] {
+            assert!(text.contains(series), "{} in {}", series, text);
+        }
+    }
+}
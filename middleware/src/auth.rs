@@ -0,0 +1,79 @@
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Either, Ready};
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, HttpResponse};
+use ntex::{Service, Transform};
+
+// A stand-in for real authentication: requests need an
+// `Authorization: Bearer <token>` header with the one token it knows,
+// everything else is answered with `401` right here, the service behind it
+// never sees the request.
+pub struct Auth {
+    token: &'static str,
+}
+
+impl Auth {
+    pub fn new(token: &'static str) -> Self {
+        Auth { token }
+    }
+}
+
+impl<S, Err> Transform<S> for Auth
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthMiddleware {
+            service,
+            token: self.token,
+        })
+    }
+}
+
+pub struct AuthMiddleware<S> {
+    service: S,
+    token: &'static str,
+}
+
+impl<S, Err> Service for AuthMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let authorized = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|token| token == self.token);
+
+        if authorized {
+            Either::Left(self.service.call(req))
+        } else {
+            println!("Auth: no valid token for {}", req.path());
+            Either::Right(ok(req.into_response(
+                HttpResponse::Unauthorized()
+                    .header(header::WWW_AUTHENTICATE, "Bearer")
+                    .json(&serde_json::json!({"error": "a valid bearer token is required"})),
+            )))
+        }
+    }
+}
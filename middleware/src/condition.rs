@@ -0,0 +1,111 @@
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+
+use futures::future::{Either, FutureExt, LocalBoxFuture};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::types::Data;
+use ntex::web::Error;
+use ntex::{Service, Transform};
+
+// A switch, shared by all workers in `Data`, that can be flipped while the
+// server runs.
+#[derive(Default)]
+pub struct Flag(AtomicBool);
+
+impl Flag {
+    pub fn is_on(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, on: bool) {
+        self.0.store(on, Ordering::Relaxed)
+    }
+}
+
+// Runs the wrapped middleware only while the flag is on, and goes straight
+// to the next service otherwise.
+//
+// Both ways are built at startup: the next service is put into an `Rc`,
+// and the middleware gets a clone of it. The flag is looked at for every
+// request.
+pub struct Condition<T> {
+    flag: Data<Flag>,
+    transform: T,
+}
+
+impl<T> Condition<T> {
+    pub fn new(flag: Data<Flag>, transform: T) -> Self {
+        Condition { flag, transform }
+    }
+}
+
+impl<S, T, Err> Transform<S> for Condition<T>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    T: Transform<
+        Rc<S>,
+        Request = WebRequest<Err>,
+        Response = WebResponse,
+        Error = Error,
+        InitError = (),
+    >,
+    T::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ConditionMiddleware<S, T::Transform>;
+    type Future = LocalBoxFuture<'static, Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let service = Rc::new(service);
+        let wrapped = self.transform.new_transform(service.clone());
+        let flag = self.flag.clone();
+
+        async move {
+            Ok(ConditionMiddleware {
+                service,
+                wrapped: wrapped.await?,
+                flag,
+            })
+        }
+        .boxed_local()
+    }
+}
+
+pub struct ConditionMiddleware<S, W> {
+    service: Rc<S>,
+    // the wrapped middleware, around the same `service`
+    wrapped: W,
+    flag: Data<Flag>,
+}
+
+impl<S, W, Err> Service for ConditionMiddleware<S, W>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    W: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Either<S::Future, W::Future>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if self.flag.is_on() {
+            self.wrapped.poll_ready(cx)
+        } else {
+            self.service.poll_ready(cx)
+        }
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        if self.flag.is_on() {
+            Either::Right(self.wrapped.call(req))
+        } else {
+            Either::Left(self.service.call(req))
+        }
+    }
+}
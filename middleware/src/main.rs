@@ -1,38 +1,89 @@
-#![allow(dead_code, clippy::type_complexity)]
+#![allow(clippy::type_complexity)]
 
 use futures::future::FutureExt;
-use ntex::{web, Service};
+use ntex::web::{self, middleware, App, HttpResponse};
+use ntex::Service;
+use serde::Deserialize;
 
+mod auth;
+mod condition;
 mod read_request_body;
 mod read_response_body;
 mod redirect;
 mod simple;
+mod timing;
+mod trace;
+
+use condition::{Condition, Flag};
+
+const TOKEN: &str = "secret";
+
+#[derive(Deserialize)]
+struct Toggle {
+    enabled: bool,
+}
+
+/// Turns `SayHi` on or off, for all workers and without a restart
+async fn set_say_hi(
+    flag: web::types::Data<Flag>,
+    body: web::types::Json<Toggle>,
+) -> HttpResponse {
+    flag.set(body.enabled);
+    HttpResponse::Ok().json(&serde_json::json!({ "say_hi": flag.is_on() }))
+}
 
 #[ntex::main]
 async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "actix_web=debug");
+    std::env::set_var("RUST_LOG", "ntex=info");
     env_logger::init();
 
-    web::server(|| {
-        web::App::new()
-            .wrap(redirect::CheckLogin)
-            .wrap(read_request_body::Logging)
-            .wrap(read_response_body::Logging)
-            .wrap(simple::SayHi)
-            .wrap_fn(|req, srv| {
-                println!("Hi from start. You requested: {}", req.path());
-
-                srv.call(req).map(|res| {
-                    println!("Hi from response");
-                    res
-                })
-            })
-            .service(web::resource("/login").to(|| async {
-                "You are on /login. Go to src/redirect.rs to change this behavior."
-            }))
+    let say_hi = web::types::Data::new(Flag::default());
+
+    web::server(move || {
+        // The last `wrap` is the outermost: a request goes through `Logger`,
+        // `Trace("app")`, `Timing` and `SayHi`, and then through the
+        // middlewares of its scope, the response the other way round
+        App::new()
+            .app_data(say_hi.clone())
+            .wrap(Condition::new(say_hi.clone(), simple::SayHi))
+            .wrap(timing::Timing)
+            .wrap(trace::Trace("app"))
+            .wrap(middleware::Logger::default())
             .service(web::resource("/").to(|| async {
                 "Hello, middleware! Check the console where the server is run."
             }))
+            .service(web::resource("/login").to(|| async {
+                "You are on /login. Go to src/redirect.rs to change this behavior."
+            }))
+            // only a token gets in, `Trace("admin")` sees every request,
+            // with a token or without
+            .service(
+                web::scope("/admin")
+                    .wrap(auth::Auth::new(TOKEN))
+                    .wrap(trace::Trace("admin"))
+                    .route("/say-hi", web::put().to(set_say_hi)),
+            )
+            // not logged in, sent to /login
+            .service(
+                web::scope("/account")
+                    .wrap(redirect::CheckLogin)
+                    .route("", web::get().to(|| async { "Your account" })),
+            )
+            // bodies printed on the way in and out
+            .service(
+                web::scope("/echo")
+                    .wrap(read_request_body::Logging)
+                    .wrap(read_response_body::Logging)
+                    .wrap_fn(|req, srv| {
+                        println!("Hi from start. You requested: {}", req.path());
+
+                        srv.call(req).map(|res| {
+                            println!("Hi from response");
+                            res
+                        })
+                    })
+                    .route("", web::post().to(|body: String| async move { body })),
+            )
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -5,6 +5,7 @@ use std::task::{Context, Poll};
 use bytes::BytesMut;
 use futures::future::{ok, Future, Ready};
 use futures::stream::StreamExt;
+use ntex::http::h1;
 use ntex::web::dev::{WebRequest, WebResponse};
 use ntex::web::{Error, ErrorRenderer};
 use ntex::{Service, Transform};
@@ -63,6 +64,11 @@ where
             }
 
             println!("request body: {:?}", body);
+            // the body is read, the service behind gets a copy
+            let (mut sender, payload) = h1::Payload::create(false);
+            sender.feed_data(body.freeze());
+            sender.feed_eof();
+            req.set_payload(payload.into());
             let res = svc.call(req).await?;
 
             println!("response: {:?}", res.headers());
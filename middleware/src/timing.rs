@@ -0,0 +1,67 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::future::{ok, Ready};
+use futures::Future;
+use ntex::http::header::{HeaderName, HeaderValue};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::Error;
+use ntex::{Service, Transform};
+
+// Measures how long everything inside of it takes, and tells in an
+// `x-response-time` header. Whatever wraps it is not counted.
+pub struct Timing;
+
+impl<S, Err> Transform<S> for Timing
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TimingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TimingMiddleware { service })
+    }
+}
+
+pub struct TimingMiddleware<S> {
+    service: S,
+}
+
+impl<S, Err> Service for TimingMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let start = Instant::now();
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let mut res = fut.await?;
+
+            let took = start.elapsed().as_micros();
+            println!("Timing: {}us", took);
+            res.headers_mut().insert(
+                HeaderName::from_static("x-response-time"),
+                HeaderValue::from_str(&format!("{}us", took)).unwrap(),
+            );
+            Ok(res)
+        })
+    }
+}
@@ -0,0 +1,64 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Ready};
+use futures::Future;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::Error;
+use ntex::{Service, Transform};
+
+// Prints its name on the way in and on the way out, to see in which order
+// the middlewares run.
+pub struct Trace(pub &'static str);
+
+impl<S, Err> Transform<S> for Trace
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = TraceMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TraceMiddleware {
+            service,
+            name: self.0,
+        })
+    }
+}
+
+pub struct TraceMiddleware<S> {
+    service: S,
+    name: &'static str,
+}
+
+impl<S, Err> Service for TraceMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let name = self.name;
+        println!("-> {} {}", name, req.path());
+        let fut = self.service.call(req);
+
+        Box::pin(async move {
+            let res = fut.await?;
+            println!("<- {} {}", name, res.status());
+            Ok(res)
+        })
+    }
+}
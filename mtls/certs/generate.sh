@@ -0,0 +1,36 @@
+#!/usr/bin/env bash
+# Generate a local CA, a server certificate and client certificates signed by
+# it, and one client certificate the CA didn't sign.
+set -e
+cd "$(dirname "$0")"
+
+# the certificate authority that vouches for servers and clients
+openssl req -x509 -newkey rsa:2048 -nodes -days 3650 \
+    -keyout ca.key.pem -out ca.cert.pem -subj "/CN=mtls example CA" 2>/dev/null
+echo "generated ca.cert.pem and ca.key.pem"
+
+# sign <name> <subject> <extensions>
+sign() {
+    openssl req -newkey rsa:2048 -nodes -keyout "$1.rsa.pem" -out "$1.csr" \
+        -subj "$2" 2>/dev/null
+    openssl x509 -req -in "$1.csr" -CA ca.cert.pem -CAkey ca.key.pem \
+        -CAcreateserial -days 3650 -out "$1.cert.pem" -extfile <(printf "$3") \
+        2>/dev/null
+    # rustls wants pkcs8 keys
+    openssl pkcs8 -topk8 -nocrypt -in "$1.rsa.pem" -out "$1.key.pem"
+    rm "$1.rsa.pem" "$1.csr"
+    echo "generated $1.cert.pem and $1.key.pem"
+}
+
+sign server "/CN=localhost" \
+    "subjectAltName=DNS:localhost,IP:127.0.0.1\nextendedKeyUsage=serverAuth"
+for client in alice bob; do
+    sign "$client" "/CN=$client/O=mtls example" "extendedKeyUsage=clientAuth"
+done
+rm -f ca.cert.srl
+
+# not signed by the CA, the server rejects it
+openssl req -x509 -newkey rsa:2048 -nodes -days 3650 \
+    -keyout mallory.key.pem -out mallory.cert.pem -subj "/CN=mallory" \
+    -addext "extendedKeyUsage=clientAuth" 2>/dev/null
+echo "generated mallory.cert.pem and mallory.key.pem"
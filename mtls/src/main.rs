@@ -0,0 +1,184 @@
+//! Client certificate authentication.
+//!
+//! The server only accepts clients that present a certificate signed by
+//! `certs/ca.cert.pem`, rustls checks that during the handshake and drops
+//! every other connection. The `on_connect` callback reads the verified
+//! certificate from the TLS session, and the `ClientCert` extractor hands
+//! its subject to the handlers, which decide what the client may do.
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use derive_more::Display;
+use futures::future::{ready, Ready};
+use ntex::http::{HttpService, Payload};
+use ntex::rt::net::TcpStream;
+use ntex::server::rustls::TlsStream;
+use ntex::service::map_config;
+use ntex::web::dev::AppConfig;
+use ntex::web::{self, middleware, App, ErrorRenderer, FromRequest, HttpRequest};
+use ntex::web::{HttpResponse, WebResponseError};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+use rustls::{
+    AllowAnyAnonymousOrAuthenticatedClient, AllowAnyAuthenticatedClient, Certificate,
+    RootCertStore, ServerConfig, Session,
+};
+
+/// Client names that may use `/admin`
+const ADMINS: &[&str] = &["alice"];
+
+/// The subject of a verified client certificate
+#[derive(Clone, Debug)]
+struct ClientCert {
+    common_name: String,
+    subject: String,
+    serial: String,
+}
+
+impl ClientCert {
+    /// Reads the leaf certificate, rustls has verified the chain already
+    fn parse(chain: &[Certificate]) -> Option<Self> {
+        let (_, cert) = x509_parser::parse_x509_certificate(&chain.first()?.0).ok()?;
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|cn| cn.as_str().ok())?
+            .to_owned();
+        Some(ClientCert {
+            common_name,
+            subject: cert.subject().to_string(),
+            serial: cert.tbs_certificate.raw_serial_as_string(),
+        })
+    }
+}
+
+/// What `on_connect` found, `None` for connections without a certificate
+#[derive(Clone)]
+struct PeerCert(Option<ClientCert>);
+
+#[derive(Debug, Display)]
+enum AuthError {
+    #[display(fmt = "a client certificate is required")]
+    NoCertificate,
+    #[display(fmt = "`{}` may not do this", _0)]
+    Forbidden(String),
+}
+
+impl WebResponseError for AuthError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            AuthError::NoCertificate => HttpResponse::Unauthorized(),
+            AuthError::Forbidden(_) => HttpResponse::Forbidden(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for ClientCert {
+    type Error = AuthError;
+    type Future = Ready<Result<Self, AuthError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let cert = req
+            .extensions()
+            .get::<PeerCert>()
+            .and_then(|peer| peer.0.clone());
+        ready(cert.ok_or(AuthError::NoCertificate))
+    }
+}
+
+async fn whoami(cert: ClientCert) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "common_name": cert.common_name,
+        "subject": cert.subject,
+        "serial": cert.serial,
+    }))
+}
+
+/// Authenticated by the certificate, authorized by its name
+async fn admin(cert: ClientCert) -> Result<HttpResponse, AuthError> {
+    if !ADMINS.contains(&cert.common_name.as_str()) {
+        return Err(AuthError::Forbidden(cert.common_name));
+    }
+    Ok(HttpResponse::Ok().body(format!("welcome, {}\n", cert.common_name)))
+}
+
+fn load_certs(path: &str) -> io::Result<Vec<Certificate>> {
+    certs(&mut BufReader::new(File::open(path)?)).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: invalid certificate", path),
+        )
+    })
+}
+
+fn server_config(verify: &str) -> io::Result<ServerConfig> {
+    let mut roots = RootCertStore::empty();
+    for ca in load_certs("certs/ca.cert.pem")? {
+        roots
+            .add(&ca)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    }
+    let mut config = match verify {
+        // connections without a certificate are let in, handlers answer
+        // them with `401`. A certificate that is sent has to be valid
+        "optional" => {
+            ServerConfig::new(AllowAnyAnonymousOrAuthenticatedClient::new(roots))
+        }
+        _ => ServerConfig::new(AllowAnyAuthenticatedClient::new(roots)),
+    };
+
+    let chain = load_certs("certs/server.cert.pem")?;
+    let key =
+        pkcs8_private_keys(&mut BufReader::new(File::open("certs/server.key.pem")?))
+            .ok()
+            .and_then(|mut keys| keys.pop())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "certs/server.key.pem: no pkcs8 key",
+                )
+            })?;
+    config
+        .set_single_cert(chain, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(config)
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,mtls=info");
+    env_logger::init();
+
+    let verify = std::env::var("CLIENT_CERT").unwrap_or_else(|_| "required".to_owned());
+    let config = server_config(&verify)?;
+    log::info!("client certificates are {}", verify);
+
+    // `web::server().bind_rustls()` hides the tls stream, build the http
+    // service by hand to get at the session in `on_connect`
+    ntex::server::build()
+        .bind("mtls", "127.0.0.1:8443", move || {
+            HttpService::build()
+                .on_connect(|io: &TlsStream<TcpStream>| {
+                    let cert = io
+                        .get_ref()
+                        .1
+                        .get_peer_certificates()
+                        .and_then(|chain| ClientCert::parse(&chain));
+                    if let Some(ref cert) = cert {
+                        log::info!("client `{}` connected", cert.common_name);
+                    }
+                    PeerCert(cert)
+                })
+                .finish(map_config(
+                    App::new()
+                        .wrap(middleware::Logger::default())
+                        .route("/whoami", web::get().to(whoami))
+                        .route("/admin", web::get().to(admin)),
+                    |_| AppConfig::default(),
+                ))
+                .rustls(config.clone())
+        })?
+        .run()
+        .await
+}
@@ -0,0 +1,37 @@
+//! Sends a request over the server's unix socket:
+//!
+//! ```bash
+//! cargo run --bin client
+//! cargo run --bin client -- /tmp/multi-bind.sock /
+//! ```
+//!
+//! curl does the same with `--unix-socket`. This one writes the request
+//! itself, http/1.1 is all the socket speaks, there is no tls on it.
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+
+fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .unwrap_or_else(|| "/tmp/multi-bind.sock".to_owned());
+    let uri = args.next().unwrap_or_else(|| "/".to_owned());
+
+    let mut stream = UnixStream::connect(&path).map_err(|e| {
+        std::io::Error::new(e.kind(), format!("{}: {}, is the server running?", path, e))
+    })?;
+    // the host header is required, any name does
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+        uri
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let (head, body) =
+        response.split_at(response.find("\r\n\r\n").map_or(response.len(), |i| i + 4));
+    println!("{}", head.lines().next().unwrap_or_default());
+    println!("{}", body);
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Which listeners to open, from the environment:
+//!
+//! * `HTTP_ADDR`, plain http over tcp, `127.0.0.1:8080` by default
+//! * `UDS_PATH`, plain http over a unix domain socket,
+//!   `/tmp/multi-bind.sock` by default
+//! * `HTTPS_ADDR`, https with `TLS_CERT` and `TLS_KEY`, off by default
+//!
+//! Setting one to `off`, or to nothing, leaves that listener out. At least
+//! one has to be left.
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+
+#[derive(Debug, PartialEq)]
+pub struct Tls {
+    pub addr: String,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Listeners {
+    pub http: Option<String>,
+    pub uds: Option<PathBuf>,
+    pub https: Option<Tls>,
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, msg)
+}
+
+impl Listeners {
+    pub fn from_env() -> io::Result<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// `var` looks a variable up, the tests pass their own
+    pub fn from_vars(var: impl Fn(&str) -> Option<String>) -> io::Result<Self> {
+        let setting =
+            |name: &str, default: Option<&str>| match var(name).as_deref().or(default) {
+                None | Some("") | Some("off") => None,
+                Some(value) => Some(value.to_owned()),
+            };
+        let path = |name: &str, default: &str| {
+            PathBuf::from(var(name).unwrap_or_else(|| default.to_owned()))
+        };
+
+        let listeners = Listeners {
+            http: setting("HTTP_ADDR", Some("127.0.0.1:8080")),
+            uds: setting("UDS_PATH", Some("/tmp/multi-bind.sock")).map(PathBuf::from),
+            https: setting("HTTPS_ADDR", None).map(|addr| Tls {
+                addr,
+                cert: path("TLS_CERT", "certs/cert.pem"),
+                key: path("TLS_KEY", "certs/key.pem"),
+            }),
+        };
+        if listeners.http.is_none()
+            && listeners.uds.is_none()
+            && listeners.https.is_none()
+        {
+            return Err(invalid(
+                "HTTP_ADDR, UDS_PATH and HTTPS_ADDR are all off, nothing to listen on"
+                    .to_owned(),
+            ));
+        }
+        Ok(listeners)
+    }
+}
+
+/// A socket file stays behind when the server is killed, and binding to it
+/// fails with "address in use". It is removed, unless a server still
+/// answers on it, or the path is something other than a socket.
+pub fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let meta = match std::fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !meta.file_type().is_socket() {
+        return Err(invalid(format!(
+            "{}: exists and is not a socket",
+            path.display()
+        )));
+    }
+    if UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{}: another server is listening on it", path.display()),
+        ));
+    }
+    log::info!("removing stale socket {}", path.display());
+    std::fs::remove_file(path)
+}
+
+fn open(path: &Path) -> io::Result<BufReader<File>> {
+    File::open(path).map(BufReader::new).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!(
+                "{}: {}, run certs/generate.sh to create it",
+                path.display(),
+                e
+            ),
+        )
+    })
+}
+
+pub fn tls_config(tls: &Tls) -> io::Result<ServerConfig> {
+    let chain = certs(&mut open(&tls.cert)?)
+        .map_err(|_| invalid(format!("{}: invalid certificate", tls.cert.display())))?;
+    let key = pkcs8_private_keys(&mut open(&tls.key)?)
+        .unwrap_or_default()
+        .pop()
+        .ok_or_else(|| {
+            invalid(format!("{}: no pkcs8 private key", tls.key.display()))
+        })?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key)
+        .map_err(|e| invalid(format!("{}: {}", tls.key.display(), e)))?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from(vars: &[(&str, &str)]) -> io::Result<Listeners> {
+        let vars: HashMap<_, _> = vars.iter().cloned().collect();
+        Listeners::from_vars(|name| vars.get(name).map(|v| v.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let listeners = from(&[]).unwrap();
+        assert_eq!(listeners.http.as_deref(), Some("127.0.0.1:8080"));
+        assert_eq!(listeners.uds, Some(PathBuf::from("/tmp/multi-bind.sock")));
+        assert_eq!(listeners.https, None);
+    }
+
+    #[test]
+    fn test_selection() {
+        let listeners = from(&[
+            ("HTTP_ADDR", "off"),
+            ("UDS_PATH", ""),
+            ("HTTPS_ADDR", "127.0.0.1:8443"),
+        ])
+        .unwrap();
+        assert_eq!(listeners.http, None);
+        assert_eq!(listeners.uds, None);
+        assert_eq!(
+            listeners.https.unwrap().cert,
+            PathBuf::from("certs/cert.pem")
+        );
+
+        assert!(from(&[("HTTP_ADDR", "off"), ("UDS_PATH", "off")]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! One app factory behind several listeners, see `listeners` for how they
+//! are picked.
+//!
+//! Each `bind` adds a listener to the same server, the workers serve all
+//! of them with one app per worker, the factory closure isn't run again per
+//! listener. `/` tells which listener a request came in on.
+//!
+//! A request over the unix socket has no peer address, and its
+//! `app_config().local_addr()` is `127.0.0.1:8080` whatever `HTTP_ADDR`
+//! is, ntex fills one in for unix sockets. Don't build urls from it there.
+use std::io;
+
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+mod listeners;
+
+use listeners::Listeners;
+
+fn listener(req: &HttpRequest) -> &'static str {
+    if req.app_config().secure() {
+        "https"
+    } else if req.peer_addr().is_none() {
+        "uds"
+    } else {
+        "http"
+    }
+}
+
+async fn index(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "listener": listener(&req),
+        "peer": req.peer_addr().map(|addr| addr.to_string()),
+    }))
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,multi_bind=info");
+    env_logger::init();
+
+    let listeners = Listeners::from_env()?;
+
+    let mut server = web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+    });
+    if let Some(addr) = &listeners.http {
+        log::info!("http on {}", addr);
+        server = server.bind(addr)?;
+    }
+    if let Some(path) = &listeners.uds {
+        listeners::remove_stale_socket(path)?;
+        log::info!("http on unix socket {}", path.display());
+        server = server.bind_uds(path)?;
+    }
+    if let Some(tls) = &listeners.https {
+        let config = listeners::tls_config(tls)?;
+        log::info!("https on {}", tls.addr);
+        server = server.bind_rustls(&tls.addr, config)?;
+    }
+
+    server.run().await?;
+
+    // the socket file outlives the listener
+    if let Some(path) = &listeners.uds {
+        let _ = std::fs::remove_file(path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    #[ntex::test]
+    async fn test_uds_and_tcp() {
+        let path =
+            std::env::temp_dir().join(format!("multi-bind-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let tcp = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = tcp.local_addr().unwrap();
+
+        let srv = web::server(|| App::new().route("/", web::get().to(index)))
+            .workers(1)
+            .listen(tcp)
+            .unwrap()
+            .bind_uds(&path)
+            .unwrap()
+            .run();
+
+        // the server's workers run on threads of their own, blocking here is fine
+        let get = |mut stream: Box<dyn ReadWrite>| {
+            stream
+                .write_all(
+                    b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n",
+                )
+                .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let uds = get(Box::new(UnixStream::connect(&path).unwrap()));
+        assert!(uds.starts_with("HTTP/1.1 200 OK"));
+        assert!(uds.ends_with(r#"{"listener":"uds","peer":null}"#));
+
+        let tcp = get(Box::new(std::net::TcpStream::connect(addr).unwrap()));
+        assert!(tcp.contains(r#""listener":"http""#));
+
+        srv.stop(true).await;
+        let _ = std::fs::remove_file(&path);
+    }
+
+    trait ReadWrite: Read + Write {}
+    impl<T: Read + Write> ReadWrite for T {}
+}
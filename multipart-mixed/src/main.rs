@@ -0,0 +1,405 @@
+//! Multipart upload with a JSON metadata part and several file parts.
+//!
+//! The `metadata` part comes first and describes the files that follow, by
+//! the name of their part. Files are streamed to disk as they arrive, then the
+//! upload is checked against the metadata: every described file has to be
+//! present, and no other parts are accepted.
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bytes::BytesMut;
+use derive_more::Display;
+use futures::{StreamExt, TryStreamExt};
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use ntex_multipart::{Field, Multipart};
+use serde::{Deserialize, Serialize};
+
+/// Limit for the JSON part, files are streamed and not limited here
+const METADATA_LIMIT: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct Metadata {
+    title: String,
+    files: Vec<FileMetadata>,
+}
+
+#[derive(Deserialize)]
+struct FileMetadata {
+    /// name of the multipart field holding the file
+    field: String,
+    caption: String,
+}
+
+#[derive(Debug, Serialize)]
+struct StoredFile {
+    field: String,
+    caption: String,
+    filename: String,
+    content_type: String,
+    size: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Upload {
+    id: String,
+    title: String,
+    files: Vec<StoredFile>,
+}
+
+#[derive(Debug, Display)]
+enum UploadError {
+    #[display(fmt = "the first part must be the `metadata` JSON part")]
+    MissingMetadata,
+    #[display(fmt = "metadata is not valid: {}", _0)]
+    InvalidMetadata(String),
+    #[display(fmt = "metadata is larger than {} bytes", METADATA_LIMIT)]
+    MetadataTooLarge,
+    #[display(fmt = "file `{}` is described in the metadata but missing", _0)]
+    MissingFile(String),
+    #[display(fmt = "part `{}` is not described in the metadata", _0)]
+    UnexpectedPart(String),
+    #[display(fmt = "malformed multipart body: {}", _0)]
+    Multipart(String),
+    #[display(fmt = "could not store the upload")]
+    Storage,
+}
+
+impl UploadError {
+    fn code(&self) -> &'static str {
+        match self {
+            UploadError::MissingMetadata => "missing_metadata",
+            UploadError::InvalidMetadata(_) => "invalid_metadata",
+            UploadError::MetadataTooLarge => "metadata_too_large",
+            UploadError::MissingFile(_) => "missing_file",
+            UploadError::UnexpectedPart(_) => "unexpected_part",
+            UploadError::Multipart(_) => "malformed_multipart",
+            UploadError::Storage => "storage_error",
+        }
+    }
+}
+
+impl WebResponseError for UploadError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = serde_json::json!({
+            "error": { "code": self.code(), "message": self.to_string() }
+        });
+        match self {
+            UploadError::Storage => HttpResponse::InternalServerError().json(&body),
+            _ => HttpResponse::BadRequest().json(&body),
+        }
+    }
+}
+
+impl From<ntex_multipart::MultipartError> for UploadError {
+    fn from(e: ntex_multipart::MultipartError) -> Self {
+        UploadError::Multipart(e.to_string())
+    }
+}
+
+/// Where uploads are stored, one directory per upload
+struct Storage {
+    root: PathBuf,
+}
+
+/// The field name of a part and its file name, if it has one. The field name
+/// says which part of the metadata it is, an unnamed part matches none
+fn names(field: &Field) -> (String, Option<String>) {
+    let (mut name, mut filename) = (String::new(), None);
+    let disposition = field
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    for param in disposition.split(';').skip(1) {
+        match param.trim().split_once('=') {
+            Some(("name", value)) => name = value.trim_matches('"').to_owned(),
+            Some(("filename", value)) => {
+                filename = Some(value.trim_matches('"').to_owned())
+            }
+            _ => (),
+        }
+    }
+    (name, filename)
+}
+
+/// Only keep the last path component, and nothing that could escape the
+/// upload directory
+fn sanitize(filename: &str) -> String {
+    let name = filename.rsplit(&['/', '\\'][..]).next().unwrap_or("");
+    let name: String = name
+        .chars()
+        .filter(|c| c.is_alphanumeric() || "._-".contains(*c))
+        .collect();
+    match name.trim_start_matches('.') {
+        "" => "file".to_owned(),
+        name => name.to_owned(),
+    }
+}
+
+async fn read_metadata(field: &mut Field) -> Result<Metadata, UploadError> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk?;
+        if buf.len() + chunk.len() > METADATA_LIMIT {
+            return Err(UploadError::MetadataTooLarge);
+        }
+        buf.extend_from_slice(&chunk);
+    }
+    serde_json::from_slice(&buf).map_err(|e| UploadError::InvalidMetadata(e.to_string()))
+}
+
+async fn save_file(field: &mut Field, path: PathBuf) -> Result<u64, UploadError> {
+    let mut f = web::block(|| std::fs::File::create(path))
+        .await
+        .map_err(|_| UploadError::Storage)?;
+    let mut size = 0;
+    while let Some(chunk) = field.next().await {
+        let data = chunk?;
+        size += data.len() as u64;
+        f = web::block(move || f.write_all(&data).map(|_| f))
+            .await
+            .map_err(|_| UploadError::Storage)?;
+    }
+    Ok(size)
+}
+
+async fn receive(
+    mut payload: Multipart,
+    id: String,
+    dir: &Path,
+) -> Result<Upload, UploadError> {
+    let metadata = match payload.try_next().await? {
+        Some(mut field) if names(&field).0 == "metadata" => {
+            read_metadata(&mut field).await?
+        }
+        _ => return Err(UploadError::MissingMetadata),
+    };
+
+    let Metadata { title, files } = metadata;
+    let mut expected: HashMap<String, FileMetadata> =
+        files.into_iter().map(|f| (f.field.clone(), f)).collect();
+    let dir_path = dir.to_owned();
+    web::block(move || std::fs::create_dir_all(dir_path))
+        .await
+        .map_err(|_| UploadError::Storage)?;
+
+    let mut stored = Vec::new();
+    while let Some(mut field) = payload.try_next().await? {
+        let (name, filename) = names(&field);
+        let file = expected
+            .remove(&name)
+            .ok_or_else(|| UploadError::UnexpectedPart(name.clone()))?;
+
+        let filename = filename.unwrap_or_else(|| name.clone());
+        // prefixed with the field name, two files may have the same name
+        let stored_name = format!("{}-{}", sanitize(&name), sanitize(&filename));
+        let size = save_file(&mut field, dir.join(&stored_name)).await?;
+
+        stored.push(StoredFile {
+            field: file.field,
+            caption: file.caption,
+            filename: stored_name,
+            content_type: field.content_type().to_string(),
+            size,
+        });
+    }
+
+    if let Some(missing) = expected.keys().min() {
+        return Err(UploadError::MissingFile(missing.clone()));
+    }
+
+    Ok(Upload {
+        id,
+        title,
+        files: stored,
+    })
+}
+
+async fn upload(
+    payload: Multipart,
+    storage: web::types::Data<Storage>,
+) -> Result<HttpResponse, UploadError> {
+    let id = uuid::Uuid::new_v4().to_string();
+    let dir = storage.root.join(&id);
+
+    match receive(payload, id, &dir).await {
+        Ok(upload) => Ok(HttpResponse::Created().json(&upload)),
+        Err(e) => {
+            // don't keep half an upload around
+            let _ = web::block(move || std::fs::remove_dir_all(dir)).await;
+            Err(e)
+        }
+    }
+}
+
+async fn index() -> HttpResponse {
+    let html = r#"<html>
+        <head><title>Upload Test</title></head>
+        <body>
+            <form method="post" action="/upload" enctype="multipart/form-data">
+                <textarea name="metadata" rows="6" cols="60">{"title": "Holiday", "files": [{"field": "photo", "caption": "at the beach"}]}</textarea><br/>
+                <input type="file" name="photo"/>
+                <input type="submit" value="Submit"/>
+            </form>
+        </body>
+    </html>"#;
+
+    HttpResponse::Ok().body(html)
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/upload", web::post().to(upload));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .data(Storage {
+                root: PathBuf::from("./uploads"),
+            })
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+    use serde_json::Value;
+
+    const BOUNDARY: &str = "------------------------boundary42";
+
+    /// `(name, filename, content type, body)` parts as a multipart body
+    fn multipart(parts: &[(&str, Option<&str>, &str, &str)]) -> String {
+        let mut body = String::new();
+        for (name, filename, content_type, data) in parts {
+            body.push_str(&format!("--{}\r\n", BOUNDARY));
+            match filename {
+                Some(filename) => body.push_str(&format!(
+                    "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n",
+                    name, filename
+                )),
+                None => body.push_str(&format!(
+                    "Content-Disposition: form-data; name=\"{}\"\r\n",
+                    name
+                )),
+            }
+            body.push_str(&format!(
+                "Content-Type: {}\r\n\r\n{}\r\n",
+                content_type, data
+            ));
+        }
+        body.push_str(&format!("--{}--\r\n", BOUNDARY));
+        body
+    }
+
+    async fn post(root: &Path, body: String) -> (StatusCode, Value) {
+        let app = test::init_service(
+            App::new()
+                .data(Storage {
+                    root: root.to_owned(),
+                })
+                .configure(app_config),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/upload")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", BOUNDARY),
+            )
+            .set_payload(body)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status();
+        let body = test::read_body(resp).await;
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[ntex::test]
+    async fn test_mixed_upload() {
+        let root = std::env::temp_dir().join(format!("mixed-{}", uuid::Uuid::new_v4()));
+        let metadata = r#"{"title": "Holiday", "files": [
+            {"field": "photo", "caption": "at the beach"},
+            {"field": "notes", "caption": "what we did"}
+        ]}"#;
+        let body = multipart(&[
+            ("metadata", None, "application/json", metadata),
+            (
+                "photo",
+                Some("../beach.png"),
+                "image/png",
+                "not really a png",
+            ),
+            ("notes", Some("notes.txt"), "text/plain", "swimming"),
+        ]);
+
+        let (status, upload) = post(&root, body).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(upload["title"], "Holiday");
+
+        let files = upload["files"].as_array().unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0]["field"], "photo");
+        assert_eq!(files[0]["caption"], "at the beach");
+        assert_eq!(files[0]["filename"], "photo-beach.png");
+        assert_eq!(files[0]["content_type"], "image/png");
+        assert_eq!(files[0]["size"], 16);
+        assert_eq!(files[1]["field"], "notes");
+
+        let stored = root
+            .join(upload["id"].as_str().unwrap())
+            .join("notes-notes.txt");
+        assert_eq!(std::fs::read_to_string(stored).unwrap(), "swimming");
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[ntex::test]
+    async fn test_invalid_upload() {
+        let root = std::env::temp_dir().join(format!("mixed-{}", uuid::Uuid::new_v4()));
+
+        // malformed JSON
+        let body = multipart(&[
+            (
+                "metadata",
+                None,
+                "application/json",
+                r#"{"title": "Holiday", "#,
+            ),
+            ("photo", Some("beach.png"), "image/png", "png"),
+        ]);
+        let (status, err) = post(&root, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(err["error"]["code"], "invalid_metadata");
+
+        // a described file is missing
+        let metadata =
+            r#"{"title": "Holiday", "files": [{"field": "photo", "caption": ""}]}"#;
+        let body = multipart(&[("metadata", None, "application/json", metadata)]);
+        let (status, err) = post(&root, body).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(err["error"]["code"], "missing_file");
+
+        // no metadata at all
+        let body = multipart(&[("photo", Some("beach.png"), "image/png", "png")]);
+        let (_, err) = post(&root, body).await;
+        assert_eq!(err["error"]["code"], "missing_metadata");
+
+        // nothing is left behind by the failed uploads
+        let leftovers = std::fs::read_dir(&root).map_or(0, |dir| dir.count());
+        assert_eq!(leftovers, 0);
+    }
+}
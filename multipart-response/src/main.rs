@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+
+use futures::{stream, StreamExt};
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod multipart;
+
+use multipart::{Part, PartError};
+
+struct Files(PathBuf);
+
+#[derive(Deserialize)]
+struct BundleParams {
+    /// Comma separated file names, every file when missing
+    files: Option<String>,
+}
+
+#[derive(Serialize)]
+struct Manifest {
+    /// The `Content-ID`s of the parts that follow, in order
+    parts: Vec<String>,
+}
+
+/// A name of a file in the directory that can go into the part headers as
+/// it is, in a quoted `filename` and in `<...>`
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name
+            .chars()
+            .any(|c| c.is_control() || matches!(c, '/' | '\\' | '"' | '<' | '>'))
+}
+
+fn content_type(name: &str) -> &'static str {
+    match Path::new(name).extension().and_then(|ext| ext.to_str()) {
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("json") => "application/json",
+        Some("png") => "image/png",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Read only when the response gets to it
+async fn file_part(dir: PathBuf, name: String) -> Result<Part, PartError> {
+    let path = dir.join(&name);
+    match web::block(move || std::fs::read(path)).await {
+        Ok(body) => Ok(Part::new(content_type(&name), body)
+            .header("Content-ID", format!("<{}>", name))
+            .header(
+                "Content-Disposition",
+                format!("attachment; filename=\"{}\"", name),
+            )),
+        Err(web::error::BlockingError::Error(e)) => Err(PartError {
+            id: name,
+            reason: e.to_string(),
+        }),
+        Err(web::error::BlockingError::Canceled) => Err(PartError {
+            id: name,
+            reason: "thread pool is gone".to_owned(),
+        }),
+    }
+}
+
+/// A JSON manifest, then one part per file
+async fn bundle(
+    params: web::types::Query<BundleParams>,
+    files: web::types::Data<Files>,
+) -> Result<HttpResponse, web::Error> {
+    let names: Vec<String> = match &params.files {
+        Some(names) => names.split(',').map(str::to_owned).collect(),
+        None => {
+            let mut names = std::fs::read_dir(&files.0)?
+                .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+                .collect::<Vec<_>>();
+            names.sort();
+            names
+        }
+    };
+    if let Some(name) = names.iter().find(|name| !valid_name(name)) {
+        return Ok(HttpResponse::BadRequest().json(
+            &serde_json::json!({ "error": format!("invalid file name `{}`", name) }),
+        ));
+    }
+
+    let manifest = Manifest {
+        parts: names.clone(),
+    };
+    let manifest = Part::new("application/json", serde_json::to_vec(&manifest).unwrap())
+        .header("Content-ID", "<manifest>");
+    let dir = files.0.clone();
+    let parts = stream::iter(Some(Ok(manifest)))
+        .chain(stream::iter(names).then(move |name| file_part(dir.clone(), name)));
+
+    let boundary = multipart::boundary();
+    Ok(HttpResponse::Ok()
+        .content_type(multipart::content_type(&boundary))
+        .streaming(Box::pin(multipart::encode(boundary, parts))))
+}
+
+fn create_demo_files(dir: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    std::fs::write(
+        dir.join("readme.txt"),
+        "Two files, bundled in one response.\n",
+    )?;
+    let pixels: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.join("pixels.bin"), pixels)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,multipart_response=info");
+    env_logger::init();
+
+    let dir = PathBuf::from("files");
+    create_demo_files(&dir)?;
+
+    web::server(move || {
+        App::new()
+            .data(Files(dir.clone()))
+            .wrap(middleware::Logger::default())
+            .route("/bundle", web::get().to(bundle))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ntex::http::header;
+    use ntex::web::test;
+
+    use super::*;
+
+    struct ParsedPart {
+        headers: HashMap<String, String>,
+        body: Vec<u8>,
+    }
+
+    fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+        haystack[from..]
+            .windows(needle.len())
+            .position(|w| w == needle)
+            .map(|i| from + i)
+    }
+
+    /// Every part up to the closing delimiter, which has to be there
+    fn parse(body: &[u8], boundary: &str) -> Vec<ParsedPart> {
+        let delimiter = format!("--{}", boundary).into_bytes();
+        let mut pos = find(body, &delimiter, 0).expect("no first delimiter");
+        let mut parts = Vec::new();
+        loop {
+            pos += delimiter.len();
+            if body[pos..].starts_with(b"--") {
+                return parts;
+            }
+            assert!(body[pos..].starts_with(b"\r\n"));
+            let headers_end = find(body, b"\r\n\r\n", pos).expect("no end of headers");
+            let headers = std::str::from_utf8(&body[pos + 2..headers_end])
+                .unwrap()
+                .split("\r\n")
+                .map(|line| {
+                    let (name, value) = line.split_at(line.find(": ").unwrap());
+                    (name.to_ascii_lowercase(), value[2..].to_owned())
+                })
+                .collect::<HashMap<_, _>>();
+
+            let mut next = delimiter.clone();
+            next.splice(0..0, b"\r\n".iter().copied());
+            let end = find(body, &next, headers_end).expect("no closing delimiter");
+            parts.push(ParsedPart {
+                headers,
+                body: body[headers_end + 4..end].to_vec(),
+            });
+            pos = end + 2;
+        }
+    }
+
+    #[ntex::test]
+    async fn parts_read_back() {
+        let dir = std::env::temp_dir().join("multipart-response-test");
+        create_demo_files(&dir).unwrap();
+        let app = test::init_service(
+            App::new()
+                .data(Files(dir.clone()))
+                .route("/bundle", web::get().to(bundle)),
+        )
+        .await;
+
+        let req =
+            test::TestRequest::with_uri("/bundle?files=readme.txt,gone.bin,pixels.bin")
+                .to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.status().is_success());
+        let content_type = res
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let boundary = content_type.split("boundary=").nth(1).unwrap().to_owned();
+        let body = test::read_body(res).await;
+        let parts = parse(&body, &boundary);
+
+        let ids: Vec<_> = parts
+            .iter()
+            .map(|p| p.headers["content-id"].as_str())
+            .collect();
+        assert_eq!(
+            ids,
+            ["<manifest>", "<readme.txt>", "<gone.bin>", "<pixels.bin>"]
+        );
+        let manifest: serde_json::Value =
+            serde_json::from_slice(&parts[0].body).unwrap();
+        assert_eq!(
+            manifest["parts"],
+            serde_json::json!(["readme.txt", "gone.bin", "pixels.bin"])
+        );
+
+        assert_eq!(
+            parts[1].body,
+            std::fs::read(dir.join("readme.txt")).unwrap()
+        );
+        assert_eq!(
+            parts[1].headers["content-type"],
+            "text/plain; charset=utf-8"
+        );
+        // the failed part doesn't stop the rest
+        assert_eq!(parts[2].headers["x-part-status"], "failed");
+        assert!(!parts[3].headers.contains_key("x-part-status"));
+        let pixels = std::fs::read(dir.join("pixels.bin")).unwrap();
+        assert_eq!(parts[3].body, pixels);
+        assert_eq!(parts[3].headers["content-length"], pixels.len().to_string());
+    }
+}
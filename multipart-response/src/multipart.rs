@@ -0,0 +1,116 @@
+//! `multipart/mixed` response bodies, RFC 2046.
+//!
+//! Parts are taken from a stream and written out as they come, nothing is
+//! buffered beyond the part that is being sent:
+//!
+//! ```text
+//! --boundary\r\n
+//! Content-Type: application/json\r\n
+//! \r\n
+//! {...}\r\n
+//! --boundary\r\n
+//! ...
+//! --boundary--\r\n
+//! ```
+//!
+//! Once the first part is out the status can't change anymore. A part that
+//! fails is replaced by an error part with the same `Content-ID` and an
+//! `X-Part-Status: failed` header, and the parts after it are still sent. A
+//! body without the closing delimiter was cut off.
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt};
+
+pub struct Part {
+    headers: Vec<(&'static str, String)>,
+    body: Bytes,
+}
+
+impl Part {
+    pub fn new(content_type: &str, body: impl Into<Bytes>) -> Self {
+        Part {
+            headers: vec![("Content-Type", content_type.to_owned())],
+            body: body.into(),
+        }
+    }
+
+    pub fn header(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.headers.push((name, value.into()));
+        self
+    }
+}
+
+/// A part that could not be produced
+pub struct PartError {
+    /// The `Content-ID` the part would have had
+    pub id: String,
+    pub reason: String,
+}
+
+impl From<PartError> for Part {
+    fn from(err: PartError) -> Self {
+        let body = serde_json::json!({ "error": err.reason }).to_string();
+        Part::new("application/json", body)
+            .header("Content-ID", format!("<{}>", err.id))
+            .header("X-Part-Status", "failed")
+    }
+}
+
+/// A fresh boundary, 32 random hex digits can't turn up in a part by chance
+pub fn boundary() -> String {
+    format!("part-{}", uuid::Uuid::new_v4().to_simple())
+}
+
+pub fn content_type(boundary: &str) -> String {
+    format!("multipart/mixed; boundary={}", boundary)
+}
+
+fn frame(boundary: &str, part: Part) -> Bytes {
+    let headers: usize = part
+        .headers
+        .iter()
+        .map(|(n, v)| n.len() + v.len() + 4)
+        .sum();
+    let mut buf =
+        BytesMut::with_capacity(boundary.len() + headers + part.body.len() + 32);
+    buf.put_slice(b"--");
+    buf.put_slice(boundary.as_bytes());
+    buf.put_slice(b"\r\n");
+    for (name, value) in &part.headers {
+        buf.put_slice(name.as_bytes());
+        buf.put_slice(b": ");
+        buf.put_slice(value.as_bytes());
+        buf.put_slice(b"\r\n");
+    }
+    buf.put_slice(b"Content-Length: ");
+    buf.put_slice(part.body.len().to_string().as_bytes());
+    buf.put_slice(b"\r\n\r\n");
+    buf.put_slice(&part.body);
+    // strictly this line break belongs to the next delimiter, it is not
+    // part of the body
+    buf.put_slice(b"\r\n");
+    buf.freeze()
+}
+
+/// The response body for `parts`, framed with `boundary`
+pub fn encode<S>(
+    boundary: String,
+    parts: S,
+) -> impl Stream<Item = Result<Bytes, Infallible>>
+where
+    S: Stream<Item = Result<Part, PartError>>,
+{
+    let boundary = Rc::new(boundary);
+    let close = Bytes::from(format!("--{}--\r\n", boundary));
+    parts
+        .map(move |part| {
+            let part = part.unwrap_or_else(|err| {
+                log::warn!("part {} failed: {}", err.id, err.reason);
+                err.into()
+            });
+            Ok(frame(&boundary, part))
+        })
+        .chain(stream::iter(Some(Ok(close))))
+}
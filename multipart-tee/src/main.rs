@@ -0,0 +1,211 @@
+//! Streams every uploaded file to two sinks at once, the disk and a sha256
+//! hasher, reading it only once.
+//!
+//! The chunks of a field are forwarded into `fanout` of two bounded
+//! channels, every chunk goes to both, and each sink reads its own channel.
+//! The upload only moves on as fast as the slower sink. When a sink fails
+//! it drops its channel, the fanout stops accepting chunks, and reading the
+//! field stops too.
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use derive_more::Display;
+use futures::channel::mpsc;
+use futures::{SinkExt, StreamExt, TryStreamExt};
+use ntex::http::header;
+use ntex::web::error::BlockingError;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use ntex_multipart::{Field, Multipart, MultipartError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Chunks a sink may fall behind the other
+const CHANNEL_SIZE: usize = 4;
+/// Default for `MAX_FILE_SIZE`
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+struct Storage {
+    dir: PathBuf,
+    /// The disk sink fails on bigger files
+    max_file_size: u64,
+}
+
+#[derive(Debug, Display)]
+enum UploadError {
+    #[display(fmt = "{}", _0)]
+    Multipart(MultipartError),
+    #[display(fmt = "storing `{}` failed: {}", _0, _1)]
+    Disk(String, io::Error),
+}
+
+impl WebResponseError for UploadError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            UploadError::Multipart(_) => HttpResponse::BadRequest(),
+            UploadError::Disk(..) => HttpResponse::InternalServerError(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// Why reading a field stopped early
+enum ReadError {
+    Multipart(MultipartError),
+    /// A sink closed its channel, its own result has the reason
+    SinkClosed,
+}
+
+#[derive(Serialize)]
+struct Stored {
+    name: String,
+    size: u64,
+    sha256: String,
+    path: String,
+}
+
+fn blocking(err: BlockingError<io::Error>) -> io::Error {
+    match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => io::Error::other("thread pool is gone"),
+    }
+}
+
+/// The disk sink, writes the chunks to `path`
+async fn write_file(
+    path: PathBuf,
+    mut chunks: mpsc::Receiver<Bytes>,
+    max_size: u64,
+) -> io::Result<u64> {
+    let mut file = web::block(move || File::create(path))
+        .await
+        .map_err(blocking)?;
+    let mut size = 0;
+
+    while let Some(chunk) = chunks.next().await {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(io::Error::other(format!("larger than {} bytes", max_size)));
+        }
+        file = web::block(move || file.write_all(&chunk).map(|_| file))
+            .await
+            .map_err(blocking)?;
+    }
+    web::block(move || file.sync_all())
+        .await
+        .map_err(blocking)?;
+    Ok(size)
+}
+
+/// The hash sink
+async fn sha256(mut chunks: mpsc::Receiver<Bytes>) -> String {
+    let mut hasher = Sha256::new();
+    while let Some(chunk) = chunks.next().await {
+        hasher.update(&chunk);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// The form field a part belongs to, only used to report errors, the file
+/// is stored under its hash
+fn field_name(field: &Field) -> String {
+    field
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| {
+            v.split(';')
+                .map(str::trim)
+                .find_map(|param| param.strip_prefix("name="))
+        })
+        .map(|name| name.trim_matches('"').to_owned())
+        .unwrap_or_default()
+}
+
+/// Tees one field into both sinks and waits for all of them. The file is
+/// written under a temporary name and renamed after its hash once every
+/// sink succeeded, or removed otherwise
+async fn store_field(field: Field, storage: &Storage) -> Result<Stored, UploadError> {
+    let name = field_name(&field);
+    let tmp = storage.dir.join(format!("{}.part", uuid::Uuid::new_v4()));
+
+    let (disk_tx, disk_rx) = mpsc::channel(CHANNEL_SIZE);
+    let (hash_tx, hash_rx) = mpsc::channel(CHANNEL_SIZE);
+    let sinks = disk_tx
+        .fanout(hash_tx)
+        .sink_map_err(|_| ReadError::SinkClosed);
+    let read = field.map_err(ReadError::Multipart).forward(sinks);
+
+    let (read, written, sha256) = futures::join!(
+        read,
+        write_file(tmp.clone(), disk_rx, storage.max_file_size),
+        sha256(hash_rx),
+    );
+
+    // a sink error is the cause of `SinkClosed`, report it first
+    let result = match (written, read) {
+        (Err(e), _) => Err(UploadError::Disk(name.clone(), e)),
+        (Ok(_), Err(ReadError::Multipart(e))) => Err(UploadError::Multipart(e)),
+        (Ok(_), Err(ReadError::SinkClosed)) => unreachable!("the sinks read to the end"),
+        (Ok(size), Ok(())) => Ok(size),
+    };
+    let size = match result {
+        Ok(size) => size,
+        Err(e) => {
+            log::warn!("{}, removing {}", e, tmp.display());
+            let _ = web::block(move || std::fs::remove_file(tmp)).await;
+            return Err(e);
+        }
+    };
+
+    let path = storage.dir.join(&sha256);
+    let target = path.clone();
+    web::block(move || std::fs::rename(tmp, target))
+        .await
+        .map_err(|e| UploadError::Disk(name.clone(), blocking(e)))?;
+    Ok(Stored {
+        name,
+        size,
+        sha256,
+        path: path.display().to_string(),
+    })
+}
+
+async fn upload(
+    mut payload: Multipart,
+    storage: web::types::Data<Storage>,
+) -> Result<HttpResponse, UploadError> {
+    let mut stored = Vec::new();
+    while let Some(field) = payload.try_next().await.map_err(UploadError::Multipart)? {
+        // files stored before a failing one are kept
+        stored.push(store_field(field, &storage).await?);
+    }
+    Ok(HttpResponse::Created().json(&stored))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,multipart_tee=info");
+    env_logger::init();
+
+    let max_file_size = std::env::var("MAX_FILE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(MAX_FILE_SIZE);
+    let storage = web::types::Data::new(Storage {
+        dir: PathBuf::from("uploads"),
+        max_file_size,
+    });
+    std::fs::create_dir_all(&storage.dir)?;
+
+    web::server(move || {
+        App::new()
+            .app_data(storage.clone())
+            .wrap(middleware::Logger::default())
+            .route("/upload", web::post().to(upload))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,27 @@
+//! What a file is, going by its first bytes rather than its name or the
+//! content type the client declared.
+
+/// Enough for every signature below
+pub const SNIFF_LEN: usize = 8;
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"%PDF-", "application/pdf"),
+    (b"MZ", "application/x-msdownload"),
+    (b"PK\x03\x04", "application/zip"),
+];
+
+/// The content types uploads may declare
+pub const ACCEPTED: &[&str] =
+    &["image/png", "image/jpeg", "image/gif", "application/pdf"];
+
+/// The content type of a file starting with `prefix`, if it is known
+pub fn sniff(prefix: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| prefix.starts_with(magic))
+        .map(|(_, content_type)| *content_type)
+}
@@ -0,0 +1,353 @@
+//! Checks every uploaded file while it streams in. Its first bytes have to
+//! match the content type it was declared with, see `magic`, before
+//! anything is written to disk. On a mismatch the rest of the upload is
+//! not read, the answer is `415` and the connection is closed.
+//!
+//! An upload is all or nothing: files are written as `.part` and renamed
+//! once the whole request succeeded. When it fails, or the client goes
+//! away, `PartFiles` removes what was written.
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use bytes::{Bytes, BytesMut};
+use derive_more::Display;
+use futures::{StreamExt, TryStreamExt};
+use ntex::http::header;
+use ntex::web::error::BlockingError;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use ntex_multipart::{Field, Multipart, MultipartError};
+use serde::Serialize;
+
+mod magic;
+
+struct Storage {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Display)]
+enum UploadError {
+    #[display(fmt = "{}", _0)]
+    Multipart(MultipartError),
+    #[display(fmt = "`{}` has no filename", _0)]
+    NotAFile(String),
+    #[display(fmt = "`{}`: {} is not accepted", _0, _1)]
+    NotAccepted(String, String),
+    #[display(
+        fmt = "`{}` was declared as {}, but it is {}",
+        name,
+        declared,
+        detected
+    )]
+    Mismatch {
+        name: String,
+        declared: String,
+        detected: &'static str,
+    },
+    #[display(fmt = "storing `{}` failed: {}", _0, _1)]
+    Disk(String, io::Error),
+}
+
+impl WebResponseError for UploadError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            UploadError::Multipart(_) | UploadError::NotAFile(_) => {
+                HttpResponse::BadRequest()
+            }
+            // the rest of the body is not read, the connection can't be
+            // used for another request
+            UploadError::NotAccepted(..) | UploadError::Mismatch { .. } => {
+                let mut res = HttpResponse::UnsupportedMediaType();
+                res.force_close();
+                res
+            }
+            UploadError::Disk(..) => HttpResponse::InternalServerError(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+fn blocking(err: BlockingError<io::Error>) -> io::Error {
+    match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => io::Error::other("thread pool is gone"),
+    }
+}
+
+/// The `.part` files of one request, removed on drop unless the request
+/// succeeded and they were renamed
+#[derive(Default)]
+struct PartFiles(Vec<(PathBuf, PathBuf)>);
+
+impl PartFiles {
+    /// Renames every file to its final name. When one can't be renamed,
+    /// the ones renamed before it are removed again, and `drop` removes the
+    /// rest
+    fn commit(mut self) -> io::Result<Vec<PathBuf>> {
+        let mut committed: Vec<PathBuf> = Vec::new();
+        while let Some((part, path)) = self.0.first() {
+            if let Err(e) = std::fs::rename(part, path) {
+                for path in &committed {
+                    log::info!("removing {}", path.display());
+                    let _ = std::fs::remove_file(path);
+                }
+                return Err(e);
+            }
+            committed.push(self.0.remove(0).1);
+        }
+        Ok(committed)
+    }
+}
+
+impl Drop for PartFiles {
+    fn drop(&mut self) {
+        for (part, _) in &self.0 {
+            log::info!("removing {}", part.display());
+            let _ = std::fs::remove_file(part);
+        }
+    }
+}
+
+/// The file name of a part. Every part has to be a file, one without a file
+/// name is refused with its field name
+fn file_name(field: &Field) -> Result<String, UploadError> {
+    let (mut name, mut filename) = (String::new(), None);
+    let disposition = field
+        .headers()
+        .get(header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    for param in disposition.split(';').skip(1) {
+        match param.trim().split_once('=') {
+            Some(("name", value)) => name = value.trim_matches('"').to_owned(),
+            Some(("filename", value)) => {
+                filename = Some(value.trim_matches('"').to_owned())
+            }
+            _ => (),
+        }
+    }
+    match filename {
+        Some(filename) => Ok(filename),
+        None => Err(UploadError::NotAFile(name)),
+    }
+}
+
+/// Reads until there are enough bytes to tell what the file is, or the
+/// field ends
+async fn read_prefix(field: &mut Field) -> Result<BytesMut, UploadError> {
+    let mut prefix = BytesMut::new();
+    while prefix.len() < magic::SNIFF_LEN {
+        match field.try_next().await.map_err(UploadError::Multipart)? {
+            Some(chunk) => prefix.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    Ok(prefix)
+}
+
+/// Checks the field and writes it to a `.part` file in `parts`
+async fn store_field(
+    mut field: Field,
+    storage: &Storage,
+    parts: &mut PartFiles,
+) -> Result<Stored, UploadError> {
+    let filename = file_name(&field)?;
+    let declared = field.content_type().essence_str().to_owned();
+    if !magic::ACCEPTED.contains(&declared.as_str()) {
+        return Err(UploadError::NotAccepted(filename, declared));
+    }
+
+    let prefix = read_prefix(&mut field).await?;
+    let detected = magic::sniff(&prefix).unwrap_or("application/octet-stream");
+    if detected != declared {
+        return Err(UploadError::Mismatch {
+            name: filename,
+            declared,
+            detected,
+        });
+    }
+    log::info!("`{}` starts like {}, storing it", filename, detected);
+
+    let id = uuid::Uuid::new_v4();
+    let part = storage.dir.join(format!("{}.part", id));
+    let path = storage.dir.join(id.to_string());
+    // registered before it exists, so it is removed whatever happens next
+    parts.0.push((part.clone(), path));
+    let disk = |e| UploadError::Disk(filename.clone(), blocking(e));
+
+    let mut file = web::block(move || File::create(part)).await.map_err(disk)?;
+    let mut size = 0;
+    let mut chunks = futures::stream::once(async { Ok(prefix.freeze()) })
+        .chain(field)
+        .boxed_local();
+    while let Some(chunk) = chunks.next().await {
+        let chunk: Bytes = chunk.map_err(UploadError::Multipart)?;
+        size += chunk.len() as u64;
+        file = web::block(move || file.write_all(&chunk).map(|_| file))
+            .await
+            .map_err(disk)?;
+    }
+    web::block(move || file.sync_all()).await.map_err(disk)?;
+
+    Ok(Stored {
+        name: filename,
+        content_type: detected,
+        size,
+        id: id.to_string(),
+    })
+}
+
+#[derive(Serialize)]
+struct Stored {
+    name: String,
+    content_type: &'static str,
+    size: u64,
+    id: String,
+}
+
+async fn upload(
+    mut payload: Multipart,
+    storage: web::types::Data<Storage>,
+) -> Result<HttpResponse, UploadError> {
+    let mut parts = PartFiles::default();
+    let mut stored = Vec::new();
+    while let Some(field) = payload.try_next().await.map_err(UploadError::Multipart)? {
+        match store_field(field, &storage, &mut parts).await {
+            Ok(file) => stored.push(file),
+            Err(e) => {
+                log::warn!("upload refused: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    web::block(move || parts.commit())
+        .await
+        .map_err(|e| UploadError::Disk("upload".to_owned(), blocking(e)))?;
+    Ok(HttpResponse::Created().json(&stored))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,multipart_validate=info");
+    env_logger::init();
+
+    let storage = web::types::Data::new(Storage {
+        dir: PathBuf::from("uploads"),
+    });
+    std::fs::create_dir_all(&storage.dir)?;
+
+    web::server(move || {
+        App::new()
+            .app_data(storage.clone())
+            .wrap(middleware::Logger::default())
+            .route("/upload", web::post().to(upload))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    const BOUNDARY: &str = "X-BOUNDARY";
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR";
+    /// `MZ` and a DOS stub, for a file renamed from `.exe`
+    const EXE: &[u8] = b"MZ\x90\0\x03\0\0\0\x04\0\0\0\xff\xff";
+
+    fn multipart(files: &[(&str, &str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (filename, content_type, data) in files {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: form-data; name=\"file\"; \
+                     filename=\"{}\"\r\nContent-Type: {}\r\n\r\n",
+                    BOUNDARY, filename, content_type
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+        body
+    }
+
+    fn files(dir: &std::path::Path) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[ntex::test]
+    async fn test_renamed_exe_is_rejected() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let app = test::init_service(
+            App::new()
+                .data(Storage { dir: dir.clone() })
+                .route("/upload", web::post().to(upload)),
+        )
+        .await;
+        let request = |body: Vec<u8>| {
+            test::TestRequest::post()
+                .uri("/upload")
+                .header(
+                    header::CONTENT_TYPE,
+                    format!("multipart/form-data; boundary={}", BOUNDARY),
+                )
+                .set_payload(body)
+                .to_request()
+        };
+
+        // a good file before the bad one, it is not kept either
+        let body = multipart(&[
+            ("cat.png", "image/png", PNG),
+            ("cat2.png", "image/png", EXE),
+        ]);
+        let res = test::call_service(&app, request(body)).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(!res.response().keep_alive());
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(res).await).unwrap();
+        assert_eq!(
+            body["error"],
+            "`cat2.png` was declared as image/png, but it is application/x-msdownload"
+        );
+        assert!(files(&dir).is_empty());
+
+        let body = multipart(&[("cat.png", "image/png", PNG)]);
+        let res = test::call_service(&app, request(body)).await;
+        assert_eq!(res.status(), StatusCode::CREATED);
+        let stored: serde_json::Value =
+            serde_json::from_slice(&test::read_body(res).await).unwrap();
+        assert_eq!(stored[0]["size"], PNG.len());
+        let id = stored[0]["id"].as_str().unwrap().to_owned();
+        assert_eq!(files(&dir), vec![id.clone()]);
+        assert_eq!(std::fs::read(dir.join(id)).unwrap(), PNG);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_commit_is_all_or_nothing() {
+        let dir = std::env::temp_dir().join(uuid::Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&dir).unwrap();
+        let part = |name: &str| (dir.join(format!("{}.part", name)), dir.join(name));
+        std::fs::write(dir.join("a.part"), "a").unwrap();
+        std::fs::write(dir.join("c.part"), "c").unwrap();
+
+        // `b.part` is gone, `a` was renamed already and `c` wasn't yet
+        let parts = PartFiles(vec![part("a"), part("b"), part("c")]);
+        assert!(parts.commit().is_err());
+        assert!(files(&dir).is_empty());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+//! Routes and spec from one list.
+//!
+//! A handler that is routed but not in the spec, or in the spec at a path
+//! it isn't routed at, is easy to end up with when the two are written out
+//! separately. `api!` takes the handlers once. `#[utoipa::path]` puts the
+//! method and path of each one on a `__path_<handler>` type, `configure`
+//! routes the handler there and `openapi` adds the same type to the spec, a
+//! path changed in the attribute changes both.
+//!
+//! The schemas the operations refer to are still listed by hand in
+//! `ApiDoc`.
+use ntex::http::Method;
+use ntex::web::{self, HttpResponse};
+use utoipa::openapi::path::{HttpMethod, PathsBuilder};
+use utoipa::OpenApi;
+
+use crate::todos::{self, ErrorResponse, NewTodo, Todo, TodoUpdate};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Todos", description = "A todo list, routed and documented from one list"),
+    components(schemas(Todo, NewTodo, TodoUpdate, ErrorResponse)),
+    tags((name = "todos", description = "Todo items"))
+)]
+struct ApiDoc;
+
+fn method(method: HttpMethod) -> Method {
+    match method {
+        HttpMethod::Get => Method::GET,
+        HttpMethod::Post => Method::POST,
+        HttpMethod::Put => Method::PUT,
+        HttpMethod::Delete => Method::DELETE,
+        HttpMethod::Options => Method::OPTIONS,
+        HttpMethod::Head => Method::HEAD,
+        HttpMethod::Patch => Method::PATCH,
+        HttpMethod::Trace => Method::TRACE,
+    }
+}
+
+macro_rules! api {
+    ($($module:ident :: $handler:ident),* $(,)?) => {
+        paste::paste! {
+            /// Routes every handler of the list where its `#[utoipa::path]`
+            /// says
+            pub fn configure(cfg: &mut web::ServiceConfig) {
+                $(
+                    let path = <$module:: [<__path_ $handler>] as utoipa::Path>::path();
+                    for m in <$module:: [<__path_ $handler>] as utoipa::Path>::methods() {
+                        cfg.route(&path, web::method(method(m)).to($module:: $handler));
+                    }
+                )*
+            }
+
+            /// The spec, with an operation for every handler of the list
+            pub fn openapi() -> utoipa::openapi::OpenApi {
+                let mut doc = ApiDoc::openapi();
+                doc.paths = PathsBuilder::new()
+                    $(.path_from::<$module:: [<__path_ $handler>]>())*
+                    .build();
+                doc
+            }
+        }
+    };
+}
+
+api! {
+    todos::list_todos,
+    todos::create_todo,
+    todos::get_todo,
+    todos::update_todo,
+    todos::delete_todo,
+}
+
+/// The spec, rendered once at startup
+pub async fn openapi_json(spec: web::types::Data<String>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(spec.get_ref().clone())
+}
+
+/// Swagger UI from a CDN, pointed at `/openapi.json`
+pub async fn swagger_ui() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(SWAGGER_UI)
+}
+
+const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html>
+<head>
+  <title>Todos API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css">
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    SwaggerUIBundle({ url: "/openapi.json", dom_id: "#swagger-ui" });
+  </script>
+</body>
+</html>
+"##;
@@ -0,0 +1,32 @@
+//! A todo API documented with utoipa, see `todos` for the handlers and
+//! `api` for how routes and spec are kept in step. The spec is served at
+//! `/openapi.json`, Swagger UI at `/docs`.
+use ntex::web::{self, middleware, App};
+
+mod api;
+mod todos;
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,openapi=info");
+    env_logger::init();
+
+    let spec = api::openapi()
+        .to_pretty_json()
+        .map_err(std::io::Error::other)?;
+    let spec = web::types::Data::new(spec);
+    let store = web::types::Data::new(todos::Store::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(spec.clone())
+            .app_data(store.clone())
+            .wrap(middleware::Logger::default())
+            .configure(api::configure)
+            .route("/openapi.json", web::get().to(api::openapi_json))
+            .route("/docs", web::get().to(api::swagger_ui))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,177 @@
+//! The todo API, each handler documented with `#[utoipa::path]` and each
+//! type it takes or returns with `ToSchema`.
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+use ntex::web::{self, HttpResponse};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+
+#[derive(Default)]
+pub struct Store {
+    next_id: Mutex<u64>,
+    todos: Mutex<BTreeMap<u64, Todo>>,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct Todo {
+    #[schema(example = 1)]
+    id: u64,
+    #[schema(example = "Buy milk")]
+    title: String,
+    done: bool,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewTodo {
+    #[schema(example = "Buy milk")]
+    title: String,
+}
+
+/// Fields left out stay as they are
+#[derive(Deserialize, ToSchema)]
+pub struct TodoUpdate {
+    title: Option<String>,
+    done: Option<bool>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    /// Only the todos that are done, or the ones that are not
+    done: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    #[schema(example = "no todo 7")]
+    error: String,
+}
+
+fn not_found(id: u64) -> HttpResponse {
+    HttpResponse::NotFound().json(&ErrorResponse {
+        error: format!("no todo {}", id),
+    })
+}
+
+/// List todos
+#[utoipa::path(
+    get,
+    path = "/todos",
+    tag = "todos",
+    params(ListQuery),
+    responses((status = 200, description = "Todos, oldest first", body = [Todo]))
+)]
+pub async fn list_todos(
+    query: web::types::Query<ListQuery>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    let todos = store.todos.lock().unwrap();
+    let todos: Vec<&Todo> = todos
+        .values()
+        .filter(|todo| query.done.is_none_or(|done| todo.done == done))
+        .collect();
+    HttpResponse::Ok().json(&todos)
+}
+
+/// Create a todo
+#[utoipa::path(
+    post,
+    path = "/todos",
+    tag = "todos",
+    request_body = NewTodo,
+    responses((status = 201, description = "The new todo", body = Todo))
+)]
+pub async fn create_todo(
+    todo: web::types::Json<NewTodo>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    let id = {
+        let mut next_id = store.next_id.lock().unwrap();
+        *next_id += 1;
+        *next_id
+    };
+    let todo = Todo {
+        id,
+        title: todo.into_inner().title,
+        done: false,
+    };
+    store.todos.lock().unwrap().insert(id, todo.clone());
+    HttpResponse::Created()
+        .header("location", format!("/todos/{}", id))
+        .json(&todo)
+}
+
+/// Get a todo
+#[utoipa::path(
+    get,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(("id" = u64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "The todo", body = Todo),
+        (status = 404, description = "No such todo", body = ErrorResponse)
+    )
+)]
+pub async fn get_todo(
+    id: web::types::Path<u64>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    match store.todos.lock().unwrap().get(&id) {
+        Some(todo) => HttpResponse::Ok().json(todo),
+        None => not_found(*id),
+    }
+}
+
+/// Change a todo
+#[utoipa::path(
+    put,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(("id" = u64, Path, description = "Todo id")),
+    request_body = TodoUpdate,
+    responses(
+        (status = 200, description = "The changed todo", body = Todo),
+        (status = 404, description = "No such todo", body = ErrorResponse)
+    )
+)]
+pub async fn update_todo(
+    id: web::types::Path<u64>,
+    update: web::types::Json<TodoUpdate>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    let mut todos = store.todos.lock().unwrap();
+    let todo = match todos.get_mut(&id) {
+        Some(todo) => todo,
+        None => return not_found(*id),
+    };
+    let update = update.into_inner();
+    if let Some(title) = update.title {
+        todo.title = title;
+    }
+    if let Some(done) = update.done {
+        todo.done = done;
+    }
+    HttpResponse::Ok().json(todo)
+}
+
+/// Delete a todo
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(("id" = u64, Path, description = "Todo id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 404, description = "No such todo", body = ErrorResponse)
+    )
+)]
+pub async fn delete_todo(
+    id: web::types::Path<u64>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    match store.todos.lock().unwrap().remove(&id) {
+        Some(_) => HttpResponse::NoContent().finish(),
+        None => not_found(*id),
+    }
+}
@@ -0,0 +1,175 @@
+use derive_more::Display;
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+
+mod store;
+
+use store::{Document, IfMatch, Store, UpdateError, Versioned};
+
+#[derive(Debug, Display)]
+enum ApiError {
+    #[display(fmt = "document not found")]
+    NotFound,
+    #[display(
+        fmt = "updates need an If-Match header with the ETag of the version they are based on"
+    )]
+    IfMatchRequired,
+    #[display(fmt = "If-Match is not a list of entity tags")]
+    InvalidIfMatch,
+    /// The current ETag
+    #[display(
+        fmt = "the document was changed by someone else, get it again and reapply your change"
+    )]
+    Stale(String),
+}
+
+impl WebResponseError for ApiError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = serde_json::json!({ "error": self.to_string() });
+        match self {
+            ApiError::NotFound => HttpResponse::NotFound().json(&body),
+            ApiError::IfMatchRequired => {
+                HttpResponse::PreconditionRequired().json(&body)
+            }
+            ApiError::InvalidIfMatch => HttpResponse::BadRequest().json(&body),
+            ApiError::Stale(etag) => HttpResponse::PreconditionFailed()
+                .header(header::ETAG, etag.as_str())
+                .json(&body),
+        }
+    }
+}
+
+fn respond(mut res: web::HttpResponseBuilder, doc: &Versioned) -> HttpResponse {
+    res.header(header::ETAG, doc.etag()).json(doc)
+}
+
+async fn create(
+    doc: web::types::Json<Document>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    let doc = store.create(doc.into_inner());
+    let mut res = HttpResponse::Created();
+    res.header(header::LOCATION, format!("/documents/{}", doc.id));
+    respond(res, &doc)
+}
+
+async fn get(
+    id: web::types::Path<u64>,
+    store: web::types::Data<Store>,
+) -> Result<HttpResponse, ApiError> {
+    let doc = store.get(*id).ok_or(ApiError::NotFound)?;
+    Ok(respond(HttpResponse::Ok(), &doc))
+}
+
+async fn update(
+    req: HttpRequest,
+    id: web::types::Path<u64>,
+    doc: web::types::Json<Document>,
+    store: web::types::Data<Store>,
+) -> Result<HttpResponse, ApiError> {
+    let if_match = req
+        .headers()
+        .get(header::IF_MATCH)
+        .ok_or(ApiError::IfMatchRequired)?;
+    let if_match = if_match
+        .to_str()
+        .ok()
+        .and_then(IfMatch::parse)
+        .ok_or(ApiError::InvalidIfMatch)?;
+
+    match store.update(*id, &if_match, doc.into_inner()) {
+        Ok(doc) => Ok(respond(HttpResponse::Ok(), &doc)),
+        Err(UpdateError::NotFound) => Err(ApiError::NotFound),
+        Err(UpdateError::Stale(current)) => Err(ApiError::Stale(current.etag())),
+    }
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/documents", web::post().to(create)).service(
+        web::resource("/documents/{id}")
+            .route(web::get().to(get))
+            .route(web::put().to(update)),
+    );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    let store = web::types::Data::new(Store::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(store.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::{Method, StatusCode};
+    use ntex::web::test;
+
+    use super::*;
+
+    fn put(etag: Option<&str>, title: &str) -> test::TestRequest {
+        let mut req = test::TestRequest::with_uri("/documents/1")
+            .method(Method::PUT)
+            .set_json(&serde_json::json!({ "title": title, "body": "" }));
+        if let Some(etag) = etag {
+            req = req.header(header::IF_MATCH, etag);
+        }
+        req
+    }
+
+    #[ntex::test]
+    async fn second_update_of_a_version_is_rejected() {
+        let store = web::types::Data::new(Store::default());
+        let app =
+            test::init_service(App::new().app_data(store.clone()).configure(app_config))
+                .await;
+        store.create(Document {
+            title: "draft".into(),
+            body: "".into(),
+        });
+
+        // both read version 1
+        let req = test::TestRequest::with_uri("/documents/1").to_request();
+        let res = test::call_service(&app, req).await;
+        let etag = res
+            .headers()
+            .get(header::ETAG)
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_owned();
+        assert_eq!(etag, "\"1\"");
+
+        let alice =
+            test::call_service(&app, put(Some(&etag), "alice").to_request()).await;
+        assert_eq!(alice.status(), StatusCode::OK);
+        assert_eq!(alice.headers().get(header::ETAG).unwrap(), "\"2\"");
+
+        let bob = test::call_service(&app, put(Some(&etag), "bob").to_request()).await;
+        assert_eq!(bob.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(bob.headers().get(header::ETAG).unwrap(), "\"2\"");
+        // alice's update is not lost
+        assert_eq!(store.get(1).unwrap().document.title, "alice");
+
+        // bob gets the new version and tries again
+        let bob = test::call_service(&app, put(Some("\"2\""), "bob").to_request()).await;
+        assert_eq!(bob.status(), StatusCode::OK);
+
+        let res = test::call_service(&app, put(None, "carol").to_request()).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_REQUIRED);
+        let res =
+            test::call_service(&app, put(Some("W/\"3\""), "carol").to_request()).await;
+        assert_eq!(res.status(), StatusCode::PRECONDITION_FAILED);
+        assert_eq!(store.get(1).unwrap().version, 3);
+    }
+}
@@ -0,0 +1,115 @@
+//! Versioned documents.
+//!
+//! Every document has a version, starting at 1 and incremented by every
+//! update, its ETag is `"<version>"`. An update names the versions it was
+//! based on with `If-Match`. It is checked and applied under the same
+//! lock, two clients that read the same version can't both update it: the
+//! first one wins, the second one is told its copy is stale.
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Document {
+    pub title: String,
+    pub body: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Versioned {
+    pub id: u64,
+    pub version: u64,
+    #[serde(flatten)]
+    pub document: Document,
+}
+
+impl Versioned {
+    pub fn etag(&self) -> String {
+        format!("\"{}\"", self.version)
+    }
+}
+
+/// `If-Match`
+#[derive(Debug, PartialEq)]
+pub enum IfMatch {
+    /// `*`, the document exists in any version
+    Any,
+    Tags(Vec<String>),
+}
+
+impl IfMatch {
+    pub fn parse(value: &str) -> Option<Self> {
+        if value.trim() == "*" {
+            return Some(IfMatch::Any);
+        }
+        let tags = value
+            .split(',')
+            .map(|tag| {
+                let tag = tag.trim();
+                // `If-Match` compares strongly, weak tags never match and
+                // are left out
+                if tag.starts_with("W/\"") && tag.len() > 3 && tag.ends_with('"') {
+                    Some(None)
+                } else if tag.len() >= 2 && tag.starts_with('"') && tag.ends_with('"') {
+                    Some(Some(tag.to_owned()))
+                } else {
+                    None
+                }
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(IfMatch::Tags(tags.into_iter().flatten().collect()))
+    }
+
+    fn matches(&self, etag: &str) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => tags.iter().any(|tag| tag == etag),
+        }
+    }
+}
+
+pub enum UpdateError {
+    NotFound,
+    /// The document changed since the client read it, this is its version now
+    Stale(Versioned),
+}
+
+#[derive(Default)]
+pub struct Store {
+    documents: Mutex<HashMap<u64, Versioned>>,
+}
+
+impl Store {
+    pub fn create(&self, document: Document) -> Versioned {
+        let mut documents = self.documents.lock().unwrap();
+        let doc = Versioned {
+            id: documents.len() as u64 + 1,
+            version: 1,
+            document,
+        };
+        documents.insert(doc.id, doc.clone());
+        doc
+    }
+
+    pub fn get(&self, id: u64) -> Option<Versioned> {
+        self.documents.lock().unwrap().get(&id).cloned()
+    }
+
+    /// Replaces the document if its current version is one of `if_match`
+    pub fn update(
+        &self,
+        id: u64,
+        if_match: &IfMatch,
+        document: Document,
+    ) -> Result<Versioned, UpdateError> {
+        let mut documents = self.documents.lock().unwrap();
+        let current = documents.get_mut(&id).ok_or(UpdateError::NotFound)?;
+        if !if_match.matches(&current.etag()) {
+            return Err(UpdateError::Stale(current.clone()));
+        }
+        current.version += 1;
+        current.document = document;
+        Ok(current.clone())
+    }
+}
@@ -0,0 +1,116 @@
+use std::time::Duration;
+
+use derive_more::Display;
+use ntex::http::client::Client;
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+
+mod provider;
+mod throttle;
+
+use throttle::{QueueFull, Throttle};
+
+/// Default for `MAX_QUEUE_WAIT`, in milliseconds
+const MAX_QUEUE_WAIT: u64 = 2000;
+
+struct Provider {
+    url: String,
+    throttle: Throttle,
+}
+
+#[derive(Debug, Display)]
+enum QuoteError {
+    #[display(fmt = "{}", _0)]
+    Throttled(QueueFull),
+    #[display(fmt = "provider failed: {}", _0)]
+    Provider(String),
+}
+
+impl WebResponseError for QuoteError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = serde_json::json!({ "error": self.to_string() });
+        match self {
+            QuoteError::Throttled(QueueFull(wait)) => HttpResponse::ServiceUnavailable()
+                .header(header::RETRY_AFTER, (wait.as_secs() + 1).to_string())
+                .json(&body),
+            QuoteError::Provider(_) => HttpResponse::BadGateway().json(&body),
+        }
+    }
+}
+
+/// Every worker and every request shares the provider's throttle, so the
+/// app as a whole stays within the quota
+async fn quote(
+    symbol: web::types::Path<String>,
+    provider: web::types::Data<Provider>,
+    client: web::types::Data<Client>,
+) -> Result<HttpResponse, QuoteError> {
+    provider.throttle.acquire().await.map_err(|e| {
+        log::warn!("not calling the provider: {}", e);
+        QuoteError::Throttled(e)
+    })?;
+
+    let url = format!("{}/v1/quote/{}", provider.url, symbol);
+    let mut res = client
+        .get(url.as_str())
+        .send()
+        .await
+        .map_err(|e| QuoteError::Provider(e.to_string()))?;
+    if res.status() != StatusCode::OK {
+        return Err(QuoteError::Provider(res.status().to_string()));
+    }
+    let body = res
+        .body()
+        .await
+        .map_err(|e| QuoteError::Provider(e.to_string()))?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/json")
+        .body(body))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,outbound_throttle=info");
+    env_logger::init();
+
+    // a little below the quota, requests that queue up at the client can
+    // still arrive closer together at the provider
+    // `Throttle` divides a second by it
+    let rate = match std::env::var("RATE_LIMIT") {
+        Ok(v) => match v.parse::<u32>() {
+            Ok(rate) if rate > 0 => rate,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("RATE_LIMIT must be at least 1, not {}", v),
+                ))
+            }
+        },
+        Err(_) => provider::QUOTA - 1,
+    };
+    let max_wait = std::env::var("MAX_QUEUE_WAIT")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(MAX_QUEUE_WAIT);
+    let provider = web::types::Data::new(Provider {
+        url: "http://127.0.0.1:8081".to_owned(),
+        throttle: Throttle::new(rate, 1, Duration::from_millis(max_wait)),
+    });
+    log::info!(
+        "calling the provider {} times per second, queueing up to {}ms",
+        rate,
+        max_wait
+    );
+
+    let server = web::server(move || {
+        App::new()
+            .app_data(provider.clone())
+            .data(Client::default())
+            .wrap(middleware::Logger::default())
+            .route("/quote/{symbol}", web::get().to(quote))
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    futures::try_join!(server, provider::start("127.0.0.1:8081")?).map(|_| ())
+}
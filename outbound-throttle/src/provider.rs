@@ -0,0 +1,56 @@
+//! A stand-in for the third-party API.
+//!
+//! It allows `QUOTA` requests per second and answers everything over it with
+//! `429 Too Many Requests`, like a provider that counts per second would.
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use ntex::server::Server;
+use ntex::web::{self, App, HttpResponse};
+
+pub const QUOTA: u32 = 5;
+
+/// Requests of the current second
+struct Window {
+    started: Instant,
+    requests: u32,
+}
+
+async fn quote(
+    symbol: web::types::Path<String>,
+    window: web::types::Data<Mutex<Window>>,
+) -> HttpResponse {
+    {
+        let mut window = window.lock().unwrap();
+        if window.started.elapsed() >= Duration::from_secs(1) {
+            window.started = Instant::now();
+            window.requests = 0;
+        }
+        window.requests += 1;
+        if window.requests > QUOTA {
+            log::warn!("provider: quota exceeded");
+            return HttpResponse::TooManyRequests().finish();
+        }
+    }
+
+    let cents = symbol.bytes().map(u64::from).sum::<u64>() * 7;
+    HttpResponse::Ok().json(&serde_json::json!({
+        "symbol": symbol.into_inner(),
+        "price": cents as f64 / 100.0,
+    }))
+}
+
+pub fn start(addr: &str) -> std::io::Result<Server> {
+    let window = web::types::Data::new(Mutex::new(Window {
+        started: Instant::now(),
+        requests: 0,
+    }));
+
+    Ok(web::server(move || {
+        App::new()
+            .app_data(window.clone())
+            .route("/v1/quote/{symbol}", web::get().to(quote))
+    })
+    .bind(addr)?
+    .run())
+}
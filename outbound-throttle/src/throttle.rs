@@ -0,0 +1,58 @@
+//! A rate limiter for outbound calls that queues instead of failing.
+//!
+//! Every call reserves the next free slot, one every `1 / rate` seconds, and
+//! sleeps until its slot comes. Slots are handed out in the order calls ask
+//! for them, so the queue is fair, and nobody polls. Up to `burst` calls may
+//! go out at once after a quiet period. A call whose slot is further away
+//! than `max_wait` doesn't queue at all and gets an error right away, it
+//! doesn't take a slot from anyone behind it.
+use std::sync::Mutex;
+use std::time::Duration;
+
+use derive_more::Display;
+use ntex::rt::time::{delay_until, Instant};
+
+#[derive(Debug, Display)]
+#[display(fmt = "the queue is full, the next slot is {:?} away", _0)]
+pub struct QueueFull(pub Duration);
+
+pub struct Throttle {
+    interval: Duration,
+    /// How far ahead of their slot calls may go out
+    tolerance: Duration,
+    max_wait: Duration,
+    /// When the next slot is due, if calls came in at exactly the rate
+    next_slot: Mutex<Instant>,
+}
+
+impl Throttle {
+    /// At most `rate` calls per second, waiting no longer than `max_wait`.
+    /// `rate` can't be 0
+    pub fn new(rate: u32, burst: u32, max_wait: Duration) -> Self {
+        let interval = Duration::from_secs(1) / rate;
+        Throttle {
+            interval,
+            tolerance: interval * burst.saturating_sub(1),
+            max_wait,
+            next_slot: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Waits for a slot, call right before the outbound request
+    pub async fn acquire(&self) -> Result<(), QueueFull> {
+        let at = {
+            let mut next_slot = self.next_slot.lock().unwrap();
+            let now = Instant::now();
+            // after a quiet period the schedule starts again from now
+            let slot = (*next_slot).max(now);
+            let at = slot.checked_sub(self.tolerance).unwrap_or(now).max(now);
+            if at - now > self.max_wait {
+                return Err(QueueFull(at - now));
+            }
+            *next_slot = slot + self.interval;
+            at
+        };
+        delay_until(at).await;
+        Ok(())
+    }
+}
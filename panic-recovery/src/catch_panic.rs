@@ -0,0 +1,91 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Future, FutureExt, Ready};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{error, Error};
+use ntex::{Service, Transform};
+
+/// Converts a panic in any downstream service into a `500` response.
+pub struct CatchPanic;
+
+impl<S, Err> Transform<S> for CatchPanic
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = CatchPanicMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CatchPanicMiddleware {
+            service: Rc::new(service),
+        })
+    }
+}
+
+pub struct CatchPanicMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, Err> Service for CatchPanicMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let path = req.path().to_owned();
+        let svc = self.service.clone();
+
+        // Extractors and non-async code run inside `call()` itself, the
+        // handler body runs when the returned future is polled. Both can panic,
+        // so both are wrapped.
+        let fut = match std::panic::catch_unwind(AssertUnwindSafe(|| svc.call(req))) {
+            Ok(fut) => fut,
+            Err(panic) => return Box::pin(async move { recover(&path, panic) }),
+        };
+
+        Box::pin(async move {
+            match AssertUnwindSafe(fut).catch_unwind().await {
+                Ok(res) => res,
+                Err(panic) => recover(&path, panic),
+            }
+        })
+    }
+}
+
+/// Log the panic and turn it into an error.
+///
+/// The request has been moved into the panicked service, so there is no
+/// request left to build a `WebResponse` from. Returning an `Error` instead
+/// lets ntex render it as a plain `500 Internal Server Error`.
+fn recover(path: &str, panic: Box<dyn Any + Send>) -> Result<WebResponse, Error> {
+    let msg = if let Some(s) = panic.downcast_ref::<&str>() {
+        (*s).to_owned()
+    } else if let Some(s) = panic.downcast_ref::<String>() {
+        s.clone()
+    } else {
+        "<non-string panic payload>".to_owned()
+    };
+    log::error!("handler for {} panicked: {}", path, msg);
+
+    Err(error::ErrorInternalServerError("Internal Server Error").into())
+}
@@ -0,0 +1,52 @@
+//! Panic recovery middleware.
+//!
+//! `CatchPanic` wraps the downstream service in `catch_unwind`, so a handler
+//! that panics produces a logged `500` response instead of killing the
+//! connection. The server runs a single worker to make it obvious that the
+//! same worker keeps serving requests after a panic.
+use std::cell::Cell;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod catch_panic;
+
+async fn index(counter: web::types::Data<Cell<usize>>) -> HttpResponse {
+    counter.set(counter.get() + 1);
+    HttpResponse::Ok().body(format!(
+        "worker is alive, served {} requests",
+        counter.get()
+    ))
+}
+
+async fn boom() -> HttpResponse {
+    let items: Vec<u32> = Vec::new();
+    // index out of bounds
+    HttpResponse::Ok().body(format!("first item: {}", items[0]))
+}
+
+async fn explicit() -> HttpResponse {
+    panic!("something went terribly wrong");
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,panic_recovery=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            // thread-local state, survives panics in other requests
+            .data(Cell::new(0usize))
+            .wrap(catch_panic::CatchPanic)
+            .wrap(middleware::Logger::default())
+            .service((
+                web::resource("/").route(web::get().to(index)),
+                web::resource("/panic").route(web::get().to(explicit)),
+                web::resource("/boom").route(web::get().to(boom)),
+            ))
+    })
+    .workers(1)
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,89 @@
+//! A stand-in for a database server with a connection limit, like
+//! `max_connections` in Postgres. Connecting past the limit fails, the way
+//! a real server answers `too many clients already`.
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use derive_more::Display;
+
+#[derive(Debug, Display)]
+pub enum DbError {
+    #[display(fmt = "too many connections, the limit is {}", _0)]
+    TooManyConnections(u32),
+}
+
+impl std::error::Error for DbError {}
+
+pub struct Database {
+    max_connections: u32,
+    open: AtomicU32,
+}
+
+impl Database {
+    pub fn new(max_connections: u32) -> Arc<Self> {
+        Arc::new(Database {
+            max_connections,
+            open: AtomicU32::new(0),
+        })
+    }
+
+    pub fn max_connections(&self) -> u32 {
+        self.max_connections
+    }
+
+    /// Connections open right now, from all pools
+    pub fn open_connections(&self) -> u32 {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn connect(self: &Arc<Self>) -> Result<Connection, DbError> {
+        let max = self.max_connections;
+        self.open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                if open < max {
+                    Some(open + 1)
+                } else {
+                    None
+                }
+            })
+            .map_err(|_| DbError::TooManyConnections(max))?;
+        Ok(Connection(self.clone()))
+    }
+}
+
+pub struct Connection(Arc<Database>);
+
+impl Connection {
+    /// Blocks for the duration of the query
+    pub fn query(&self) -> u32 {
+        thread::sleep(Duration::from_millis(20));
+        42
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+pub struct Manager(pub Arc<Database>);
+
+impl r2d2::ManageConnection for Manager {
+    type Connection = Connection;
+    type Error = DbError;
+
+    fn connect(&self) -> Result<Connection, DbError> {
+        self.0.connect()
+    }
+
+    fn is_valid(&self, _: &mut Connection) -> Result<(), DbError> {
+        Ok(())
+    }
+
+    fn has_broken(&self, _: &mut Connection) -> bool {
+        false
+    }
+}
@@ -0,0 +1,184 @@
+//! Sizing database pools against the database's connection limit.
+//!
+//! As in the `state` example, the factory closure passed to `web::server`
+//! runs once per worker. A pool built inside it with `.data(pool)` is a
+//! pool per worker, a pool built before it and attached with
+//! `.app_data(pool.clone())` is one pool shared by all workers. Both work,
+//! they spend the database's connections differently:
+//!
+//! * per worker, `POOL_MODE=per-worker`: the database sees
+//!   workers × `POOL_SIZE` connections. Workers don't share anything, but a
+//!   busy worker can't borrow the idle connections of a quiet one, and more
+//!   workers, on a bigger machine or with `WORKERS`, silently mean more
+//!   connections
+//! * shared, `POOL_MODE=shared`: the database sees `POOL_SIZE`
+//!   connections whatever the number of workers, any worker can use any of
+//!   them. All workers take connections from the same pool, the lock is
+//!   held for a moment only, which hardly matters next to a query
+//!
+//! Either way the connections have to fit under the database's limit,
+//! `DB_MAX_CONNECTIONS`, leaving `RESERVED_CONNECTIONS` for migrations, an
+//! admin shell and other services that share the database. The server
+//! refuses to start when they don't: past the limit the pools don't fail at
+//! startup, they fail under load, when the last workers try to connect.
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use ntex::web::{self, error, middleware, App, HttpResponse};
+
+mod db;
+
+use db::{Database, Manager};
+
+type Pool = r2d2::Pool<Manager>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    PerWorker,
+    Shared,
+}
+
+struct Config {
+    mode: Mode,
+    workers: u32,
+    pool_size: u32,
+    reserved: u32,
+    db_max: u32,
+}
+
+fn env_var<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+impl Config {
+    fn from_env() -> Self {
+        let mode = match std::env::var("POOL_MODE").as_deref() {
+            Ok("shared") => Mode::Shared,
+            _ => Mode::PerWorker,
+        };
+        let workers = env_var("WORKERS", num_cpus::get() as u32).max(1);
+        let db_max: u32 = env_var("DB_MAX_CONNECTIONS", 100);
+        let reserved: u32 = env_var("RESERVED_CONNECTIONS", 5);
+        let default_size = match mode {
+            // an even share of what is left, for every worker
+            Mode::PerWorker => (db_max.saturating_sub(reserved) / workers).max(1),
+            Mode::Shared => db_max.saturating_sub(reserved).max(1),
+        };
+        Config {
+            mode,
+            workers,
+            pool_size: env_var("POOL_SIZE", default_size),
+            reserved,
+            db_max,
+        }
+    }
+
+    /// The connections the pools open at most, if they fit under the limit
+    fn total_connections(&self) -> Result<u32, String> {
+        // r2d2 panics on a pool of 0
+        if self.pool_size == 0 {
+            return Err("POOL_SIZE must be at least 1".to_owned());
+        }
+        let (pools, explain) = match self.mode {
+            Mode::PerWorker => (
+                self.workers.saturating_mul(self.pool_size),
+                format!("{} workers × {} connections", self.workers, self.pool_size),
+            ),
+            Mode::Shared => (
+                self.pool_size,
+                format!("{} shared connections", self.pool_size),
+            ),
+        };
+        let total = pools.saturating_add(self.reserved);
+        if total > self.db_max {
+            return Err(format!(
+                "{} + {} reserved = {}, over the database limit of {}",
+                explain, self.reserved, total, self.db_max
+            ));
+        }
+        log::info!(
+            "{} + {} reserved = {} of {} database connections",
+            explain,
+            self.reserved,
+            total,
+            self.db_max
+        );
+        Ok(pools)
+    }
+}
+
+fn pool(db: &Arc<Database>, size: u32) -> Pool {
+    // the pool connects in the background, the factory closure can't wait
+    // for it or fail
+    r2d2::Pool::builder()
+        .max_size(size)
+        .min_idle(Some(size))
+        .connection_timeout(Duration::from_secs(2))
+        .build_unchecked(Manager(db.clone()))
+}
+
+async fn query(pool: web::types::Data<Pool>) -> Result<HttpResponse, web::Error> {
+    let answer =
+        web::block(move || -> Result<u32, r2d2::Error> { Ok(pool.get()?.query()) })
+            .await
+            .map_err(error::ErrorServiceUnavailable)?;
+    Ok(HttpResponse::Ok().json(&serde_json::json!({ "answer": answer })))
+}
+
+async fn stats(
+    pool: web::types::Data<Pool>,
+    db: web::types::Data<Arc<Database>>,
+) -> HttpResponse {
+    let state = pool.state();
+    HttpResponse::Ok().json(&serde_json::json!({
+        "db_max_connections": db.max_connections(),
+        "db_open_connections": db.open_connections(),
+        // the pool of the worker that answers, or the shared one
+        "pool": {
+            "max_size": pool.max_size(),
+            "connections": state.connections,
+            "idle": state.idle_connections,
+        },
+    }))
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,pool_per_worker=info");
+    env_logger::init();
+
+    let config = Config::from_env();
+    config
+        .total_connections()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+    let db = Database::new(config.db_max);
+    let shared = match config.mode {
+        Mode::Shared => Some(web::types::Data::new(pool(&db, config.pool_size))),
+        Mode::PerWorker => None,
+    };
+    let pool_size = config.pool_size;
+
+    web::server(move || {
+        let app = App::new()
+            .data(db.clone())
+            .wrap(middleware::Logger::default())
+            .route("/query", web::get().to(query))
+            .route("/stats", web::get().to(stats));
+        match &shared {
+            Some(shared) => app.app_data(shared.clone()),
+            None => {
+                log::info!("worker pool of {} connections", pool_size);
+                app.data(pool(&db, pool_size))
+            }
+        }
+    })
+    .workers(config.workers as usize)
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,159 @@
+//! The pools in this example, both report through `PoolStats`.
+//!
+//! Neither pool counts waiting callers on its own terms, so each checkout
+//! first tries to get a connection without waiting. When that fails the
+//! caller counts as waiting until it gets one.
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use r2d2_sqlite::SqliteConnectionManager;
+
+use crate::stats::{PoolStats, Stats};
+
+#[derive(Default)]
+struct Waits {
+    waiting: AtomicUsize,
+    total: AtomicU64,
+}
+
+impl Waits {
+    fn start(&self) -> Waiting<'_> {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        self.waiting.fetch_add(1, Ordering::Relaxed);
+        Waiting(self)
+    }
+}
+
+/// Stops counting a caller as waiting, also when its future is dropped
+/// because the client went away
+struct Waiting<'a>(&'a Waits);
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.waiting.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A blocking r2d2 pool of sqlite connections
+pub struct SqlitePool {
+    pool: r2d2::Pool<SqliteConnectionManager>,
+    waits: Waits,
+}
+
+impl SqlitePool {
+    pub fn new(path: &str, max_size: u32) -> Result<Self, r2d2::Error> {
+        let pool = r2d2::Pool::builder()
+            .max_size(max_size)
+            .build(SqliteConnectionManager::file(path))?;
+        Ok(SqlitePool {
+            pool,
+            waits: Waits::default(),
+        })
+    }
+
+    /// Blocks, run it with `web::block`
+    pub fn get(
+        &self,
+    ) -> Result<r2d2::PooledConnection<SqliteConnectionManager>, r2d2::Error> {
+        if let Some(conn) = self.pool.try_get() {
+            return Ok(conn);
+        }
+        let _waiting = self.waits.start();
+        self.pool.get()
+    }
+}
+
+impl PoolStats for SqlitePool {
+    fn backend(&self) -> &'static str {
+        "r2d2"
+    }
+
+    fn stats(&self) -> Stats {
+        let state = self.pool.state();
+        Stats {
+            max_size: self.pool.max_size() as usize,
+            size: state.connections as usize,
+            idle: state.idle_connections as usize,
+            in_use: (state.connections - state.idle_connections) as usize,
+            waiting: self.waits.waiting.load(Ordering::Relaxed),
+            wait_count: self.waits.total.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A connection to an upstream service, only pretends
+pub struct Upstream {
+    pub id: u64,
+}
+
+pub struct UpstreamManager {
+    ids: AtomicU64,
+}
+
+#[async_trait::async_trait]
+impl deadpool::managed::Manager<Upstream, std::io::Error> for UpstreamManager {
+    async fn create(&self) -> Result<Upstream, std::io::Error> {
+        // connecting takes a moment
+        delay_for(Duration::from_millis(20)).await;
+        let id = self.ids.fetch_add(1, Ordering::Relaxed) + 1;
+        log::info!("upstream connection {} opened", id);
+        Ok(Upstream { id })
+    }
+
+    async fn recycle(
+        &self,
+        _: &mut Upstream,
+    ) -> deadpool::managed::RecycleResult<std::io::Error> {
+        Ok(())
+    }
+}
+
+pub type UpstreamConnection = deadpool::managed::Object<Upstream, std::io::Error>;
+type UpstreamError = deadpool::managed::PoolError<std::io::Error>;
+
+/// An async deadpool pool of upstream connections
+pub struct UpstreamPool {
+    pool: deadpool::managed::Pool<Upstream, std::io::Error>,
+    waits: Waits,
+}
+
+impl UpstreamPool {
+    pub fn new(max_size: usize) -> Self {
+        let manager = UpstreamManager {
+            ids: AtomicU64::new(0),
+        };
+        UpstreamPool {
+            pool: deadpool::managed::Pool::new(manager, max_size),
+            waits: Waits::default(),
+        }
+    }
+
+    pub async fn get(&self) -> Result<UpstreamConnection, UpstreamError> {
+        if let Ok(conn) = self.pool.try_get().await {
+            return Ok(conn);
+        }
+        let _waiting = self.waits.start();
+        self.pool.get().await
+    }
+}
+
+impl PoolStats for UpstreamPool {
+    fn backend(&self) -> &'static str {
+        "deadpool"
+    }
+
+    fn stats(&self) -> Stats {
+        // `available` goes negative by the number of waiting callers
+        let status = self.pool.status();
+        let idle = status.available.max(0) as usize;
+        Stats {
+            max_size: status.max_size,
+            size: status.size,
+            idle,
+            in_use: status.size.saturating_sub(idle),
+            waiting: self.waits.waiting.load(Ordering::Relaxed),
+            wait_count: self.waits.total.load(Ordering::Relaxed),
+        }
+    }
+}
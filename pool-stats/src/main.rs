@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+
+mod backends;
+mod stats;
+
+use backends::{SqlitePool, UpstreamPool};
+use stats::Pools;
+
+/// How long a request holds its connection, long enough to pile up
+const WORK: Duration = Duration::from_millis(500);
+/// How often the monitor checks the pools
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Checks in a row a pool has to be saturated before the monitor warns
+const SATURATED_CHECKS: u32 = 3;
+
+/// Statistics of every pool, the handler doesn't know what backs them
+async fn pool(pools: web::types::Data<Pools>) -> HttpResponse {
+    HttpResponse::Ok().json(&pools.report())
+}
+
+/// A slow query, holds a sqlite connection for a while
+async fn db(pool: web::types::Data<SqlitePool>) -> Result<HttpResponse, Error> {
+    let answer = web::block(move || {
+        let conn = pool.get().map_err(|e| e.to_string())?;
+        std::thread::sleep(WORK);
+        conn.query_row("SELECT 6 * 7", rusqlite::NO_PARAMS, |row| {
+            row.get::<_, i64>(0)
+        })
+        .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(&serde_json::json!({ "answer": answer })))
+}
+
+/// A slow call upstream, holds an upstream connection for a while
+async fn upstream(pool: web::types::Data<UpstreamPool>) -> Result<HttpResponse, Error> {
+    let conn = pool
+        .get()
+        .await
+        .map_err(|e| error::ErrorServiceUnavailable(format!("{:?}", e)))?;
+    delay_for(WORK).await;
+    Ok(HttpResponse::Ok().json(&serde_json::json!({ "connection": conn.id })))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,pool_stats=info");
+    env_logger::init();
+
+    let sqlite = web::types::Data::new(SqlitePool::new("pool.db", 4).unwrap());
+    let upstream_pool = web::types::Data::new(UpstreamPool::new(4));
+    let pools = web::types::Data::new(
+        Pools::default()
+            .add("db", sqlite.clone().into_inner())
+            .add("upstream", upstream_pool.clone().into_inner()),
+    );
+
+    ntex::rt::spawn(stats::monitor(
+        pools.clone().into_inner(),
+        CHECK_INTERVAL,
+        SATURATED_CHECKS,
+    ));
+
+    web::server(move || {
+        App::new()
+            .app_data(pools.clone())
+            .app_data(sqlite.clone())
+            .app_data(upstream_pool.clone())
+            .wrap(middleware::Logger::default())
+            .route("/pool", web::get().to(pool))
+            .route("/db", web::get().to(db))
+            .route("/upstream", web::get().to(upstream))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,95 @@
+//! Pool statistics, whatever the pool.
+//!
+//! Every pool reports the same `Stats` through the `PoolStats` trait, the
+//! endpoint and the saturation monitor only know the trait. A new backend
+//! needs an implementation, nothing else changes.
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Stats {
+    pub max_size: usize,
+    /// Connections open right now, idle or in use
+    pub size: usize,
+    pub idle: usize,
+    pub in_use: usize,
+    /// Callers waiting for a connection right now
+    pub waiting: usize,
+    /// Checkouts that had to wait since the start
+    pub wait_count: u64,
+}
+
+impl Stats {
+    /// Every connection is in use, the next caller has to wait
+    pub fn saturated(&self) -> bool {
+        self.in_use >= self.max_size || self.waiting > 0
+    }
+}
+
+pub trait PoolStats: Send + Sync {
+    /// The kind of pool, for display
+    fn backend(&self) -> &'static str;
+
+    fn stats(&self) -> Stats;
+}
+
+/// The pools to report on, by name
+#[derive(Default)]
+pub struct Pools(BTreeMap<&'static str, Arc<dyn PoolStats>>);
+
+impl Pools {
+    pub fn add(mut self, name: &'static str, pool: Arc<dyn PoolStats>) -> Self {
+        self.0.insert(name, pool);
+        self
+    }
+
+    pub fn report(&self) -> serde_json::Value {
+        let pools: serde_json::Map<_, _> = self
+            .0
+            .iter()
+            .map(|(name, pool)| {
+                let mut stats = serde_json::to_value(pool.stats()).unwrap();
+                stats["backend"] = pool.backend().into();
+                (name.to_string(), stats)
+            })
+            .collect();
+        pools.into()
+    }
+}
+
+/// Checks every pool each `interval`, and warns about a pool that stayed
+/// saturated for `threshold` checks in a row. A single busy moment is
+/// normal, a pool that stays saturated is too small, or connections are
+/// held too long
+pub async fn monitor(pools: Arc<Pools>, interval: Duration, threshold: u32) {
+    let mut checks = ntex::rt::time::interval(interval);
+    let mut saturated_for: BTreeMap<&'static str, u32> = BTreeMap::new();
+    loop {
+        checks.tick().await;
+        for (name, pool) in &pools.0 {
+            let stats = pool.stats();
+            let count = saturated_for.entry(name).or_default();
+            if stats.saturated() {
+                *count += 1;
+                if *count == threshold {
+                    log::warn!(
+                        "pool {} saturated for {:?}: {} of {} in use, {} waiting",
+                        name,
+                        interval * threshold,
+                        stats.in_use,
+                        stats.max_size,
+                        stats.waiting
+                    );
+                }
+            } else {
+                if *count >= threshold {
+                    log::info!("pool {} recovered", name);
+                }
+                *count = 0;
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod problem;
+
+use problem::{ApiError, FieldError};
+
+#[derive(Clone, Serialize)]
+struct Book {
+    id: u64,
+    title: String,
+    year: i32,
+    isbn: String,
+}
+
+#[derive(Default)]
+struct Library {
+    books: Mutex<BTreeMap<u64, Book>>,
+    ids: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct NewBook {
+    title: String,
+    year: i32,
+    isbn: String,
+}
+
+impl NewBook {
+    fn validate(&self) -> Result<(), ApiError> {
+        let mut errors = Vec::new();
+        if self.title.trim().is_empty() {
+            errors.push(FieldError {
+                field: "title",
+                message: "must not be empty".to_owned(),
+            });
+        }
+        if !(1450..=2100).contains(&self.year) {
+            errors.push(FieldError {
+                field: "year",
+                message: "must be between 1450 and 2100".to_owned(),
+            });
+        }
+        let digits = self.isbn.chars().filter(|c| *c != '-').count();
+        if digits != 13 || !self.isbn.chars().all(|c| c.is_ascii_digit() || c == '-') {
+            errors.push(FieldError {
+                field: "isbn",
+                message: "must be 13 digits, dashes allowed".to_owned(),
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError::Validation(errors))
+        }
+    }
+}
+
+async fn get_book(
+    id: web::types::Path<u64>,
+    library: web::types::Data<Library>,
+) -> Result<HttpResponse, ApiError> {
+    let books = library.books.lock().unwrap();
+    let book = books.get(&id).ok_or(ApiError::NotFound("book", *id))?;
+    Ok(HttpResponse::Ok().json(book))
+}
+
+/// The body is parsed here rather than by the `Json` extractor, so a
+/// malformed one is a problem document too
+async fn add_book(
+    body: Bytes,
+    library: web::types::Data<Library>,
+) -> Result<HttpResponse, ApiError> {
+    let new: NewBook = serde_json::from_slice(&body)
+        .map_err(|e| ApiError::MalformedBody(e.to_string()))?;
+    new.validate()?;
+
+    let book = Book {
+        id: library.ids.fetch_add(1, Ordering::Relaxed) + 1,
+        title: new.title,
+        year: new.year,
+        isbn: new.isbn,
+    };
+    library.books.lock().unwrap().insert(book.id, book.clone());
+    Ok(HttpResponse::Created().json(&book))
+}
+
+async fn no_route() -> Result<HttpResponse, ApiError> {
+    Err(ApiError::NoRoute)
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .route("/books", web::post().to(add_book))
+        .route("/books/{id}", web::get().to(get_book));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,problem_json=info");
+    env_logger::init();
+
+    let library = web::types::Data::new(Library::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(library.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+            .default_service(web::to(no_route))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{header, StatusCode};
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_problem_documents() {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::types::Data::new(Library::default()))
+                .configure(app_config)
+                .default_service(web::to(no_route)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/books")
+            .set_json(&serde_json::json!({ "title": " ", "year": 2020, "isbn": "12-3" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            problem::CONTENT_TYPE
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "https://example.com/problems/validation-failed",
+                "title": "Validation failed",
+                "status": 422,
+                "detail": "2 field(s) are invalid",
+                "instance": "/books",
+                "errors": [
+                    { "field": "title", "message": "must not be empty" },
+                    { "field": "isbn", "message": "must be 13 digits, dashes allowed" },
+                ],
+            })
+        );
+
+        let req = test::TestRequest::with_uri("/books/42").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_TYPE).unwrap(),
+            problem::CONTENT_TYPE
+        );
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["type"], "https://example.com/problems/not-found");
+        assert_eq!(body["status"], 404);
+        assert_eq!(body["detail"], "there is no book with id 42");
+        assert_eq!(body["instance"], "/books/42");
+        // extension members only where they apply
+        assert!(body.get("errors").is_none());
+    }
+}
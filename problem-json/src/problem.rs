@@ -0,0 +1,93 @@
+//! Errors as RFC 7807 problem documents.
+//!
+//! Every `ApiError` renders as `application/problem+json`:
+//!
+//! * `type`, a URI naming the kind of problem, clients branch on it
+//! * `title`, a short summary, the same for every problem of the type
+//! * `status`, the HTTP status, repeated for clients that lose the headers
+//! * `detail`, what went wrong this time
+//! * `instance`, the request path the problem happened at
+//!
+//! Validation problems carry an `errors` extension member with one entry
+//! per invalid field.
+use derive_more::Display;
+use ntex::http::{header, StatusCode};
+use ntex::web::{HttpRequest, HttpResponse, WebResponseError};
+use serde::Serialize;
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+
+const TYPE_BASE: &str = "https://example.com/problems/";
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldError {
+    /// The field, as the client sent it
+    pub field: &'static str,
+    pub message: String,
+}
+
+#[derive(Serialize)]
+struct Problem<'a> {
+    #[serde(rename = "type")]
+    kind: String,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    instance: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<&'a [FieldError]>,
+}
+
+#[derive(Debug, Display)]
+pub enum ApiError {
+    #[display(fmt = "the request body is not valid json: {}", _0)]
+    MalformedBody(String),
+    #[display(fmt = "{} field(s) are invalid", "_0.len()")]
+    Validation(Vec<FieldError>),
+    #[display(fmt = "there is no {} with id {}", _0, _1)]
+    NotFound(&'static str, u64),
+    #[display(fmt = "nothing is served at this path")]
+    NoRoute,
+}
+
+impl ApiError {
+    /// The last segment of the `type` URI, and the `title`
+    fn kind(&self) -> (&'static str, &'static str) {
+        match self {
+            ApiError::MalformedBody(_) => ("malformed-body", "Malformed request body"),
+            ApiError::Validation(_) => ("validation-failed", "Validation failed"),
+            ApiError::NotFound(..) | ApiError::NoRoute => {
+                ("not-found", "Resource not found")
+            }
+        }
+    }
+}
+
+impl WebResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::MalformedBody(_) => StatusCode::BAD_REQUEST,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::NotFound(..) | ApiError::NoRoute => StatusCode::NOT_FOUND,
+        }
+    }
+
+    fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+        let (kind, title) = self.kind();
+        let status = self.status_code();
+        let problem = Problem {
+            kind: format!("{}{}", TYPE_BASE, kind),
+            title,
+            status: status.as_u16(),
+            detail: self.to_string(),
+            instance: req.path(),
+            errors: match self {
+                ApiError::Validation(errors) => Some(errors),
+                _ => None,
+            },
+        };
+        HttpResponse::build(status)
+            .header(header::CONTENT_TYPE, CONTENT_TYPE)
+            .body(serde_json::to_string(&problem).unwrap())
+    }
+}
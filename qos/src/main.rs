@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod qos;
+
+use qos::{InFlight, Limits, Priority, Qos};
+
+#[derive(Deserialize)]
+struct Work {
+    /// How long the request takes
+    ms: Option<u64>,
+}
+
+/// Most a request can ask to take, it holds its in flight slot all along
+const MAX_MS: u64 = 60_000;
+
+async fn work(params: web::types::Query<Work>) -> HttpResponse {
+    let ms = params.ms.unwrap_or(200);
+    if ms > MAX_MS {
+        return HttpResponse::BadRequest().json(&serde_json::json!({
+            "error": format!("ms must be at most {}", MAX_MS)
+        }));
+    }
+    delay_for(Duration::from_millis(ms)).await;
+    HttpResponse::Ok().json(&serde_json::json!({ "done": true }))
+}
+
+async fn health(in_flight: web::types::Data<InFlight>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "in_flight": in_flight.get() }))
+}
+
+fn qos(limits: Limits) -> Qos {
+    Qos::new(limits)
+        .route("/health", Priority::Critical)
+        .route("/checkout", Priority::High)
+        .route("/reports", Priority::Low)
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/health", web::get().to(health))
+        .route("/checkout", web::post().to(work))
+        .route("/search", web::get().to(work))
+        .route("/reports", web::get().to(work));
+}
+
+fn env_limit(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,qos=info");
+    env_logger::init();
+
+    let limits = Limits {
+        low: env_limit("QOS_LOW_LIMIT", 20),
+        normal: env_limit("QOS_NORMAL_LIMIT", 50),
+        high: env_limit("QOS_HIGH_LIMIT", 80),
+    };
+    log::info!("in flight limits: {:?}", limits);
+    let in_flight = web::types::Data::new(InFlight::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(in_flight.clone())
+            .wrap(qos(limits))
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::join_all;
+    use ntex::http::Method;
+    use ntex::web::test;
+
+    use super::*;
+
+    #[ntex::test]
+    async fn sheds_low_priority_first() {
+        let limits = Limits {
+            low: 2,
+            normal: 3,
+            high: 4,
+        };
+        let in_flight = web::types::Data::new(InFlight::default());
+        let app = test::init_service(
+            App::new()
+                .app_data(in_flight.clone())
+                .wrap(qos(limits))
+                .configure(app_config),
+        )
+        .await;
+
+        // all of them are in flight together, they are admitted in order
+        let requests = vec![
+            test::TestRequest::with_uri("/reports?ms=100"),
+            test::TestRequest::with_uri("/reports?ms=100"),
+            // 2 in flight, the low limit
+            test::TestRequest::with_uri("/reports?ms=100"),
+            // premium makes it high
+            test::TestRequest::with_uri("/reports?ms=100")
+                .header("authorization", "Bearer premium-1"),
+            // 3 in flight, the normal limit
+            test::TestRequest::with_uri("/search?ms=100"),
+            test::TestRequest::with_uri("/checkout?ms=100").method(Method::POST),
+            // 4 in flight, the high limit
+            test::TestRequest::with_uri("/checkout?ms=100").method(Method::POST),
+            test::TestRequest::with_uri("/health"),
+            // lowered by the client
+            test::TestRequest::with_uri("/search?ms=100").header("x-priority", "low"),
+        ];
+        let statuses: Vec<u16> = join_all(requests.into_iter().map(|req| {
+            let app = &app;
+            async move {
+                test::call_service(app, req.to_request())
+                    .await
+                    .status()
+                    .as_u16()
+            }
+        }))
+        .await;
+        assert_eq!(statuses, [200, 200, 503, 200, 503, 200, 503, 200, 503]);
+
+        // and once the load is gone, low priority requests are back
+        assert_eq!(in_flight.get(), 0);
+        let req = test::TestRequest::with_uri("/reports?ms=1").to_request();
+        assert_eq!(test::call_service(&app, req).await.status().as_u16(), 200);
+    }
+
+    #[ntex::test]
+    async fn test_too_long() {
+        let app = test::init_service(App::new().configure(app_config)).await;
+        let req = test::TestRequest::with_uri("/search?ms=18446744073709551615");
+        let res = test::call_service(&app, req.to_request()).await;
+        assert_eq!(res.status().as_u16(), 400);
+    }
+}
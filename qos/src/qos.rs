@@ -0,0 +1,224 @@
+//! Load shedding by priority.
+//!
+//! Every request gets a `Priority`, from the first path prefix rule that
+//! matches, `Normal` without one. Premium customers are at least `High`. A
+//! client can lower its own requests with `X-Priority: low`, a batch job
+//! that can wait, but never raise them.
+//!
+//! The requests being handled are counted in the `InFlight` in `Data`. Each
+//! priority has a limit, a request is only admitted while fewer requests
+//! than that are in flight, the others get `503` right away. Under load
+//! `Low` requests go first, then `Normal`, then `High`. `Critical` requests,
+//! health checks, are always admitted.
+use std::cmp;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Either, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, HttpResponse};
+use ntex::{Service, Transform};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Priority {
+    Low,
+    Normal,
+    High,
+    Critical,
+}
+
+/// Requests being handled right now, by every worker
+#[derive(Default)]
+pub struct InFlight(AtomicUsize);
+
+impl InFlight {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// Counts a request in, unless `limit` are in flight already
+fn admit(in_flight: web::types::Data<InFlight>, limit: usize) -> Option<Admitted> {
+    in_flight
+        .0
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+            if n < limit {
+                Some(n + 1)
+            } else {
+                None
+            }
+        })
+        .ok()
+        .map(|_| Admitted(in_flight))
+}
+
+/// Counts the request out when it is done, or dropped before
+struct Admitted(web::types::Data<InFlight>);
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        (self.0).0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// How many requests may be in flight for a priority to be admitted
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub low: usize,
+    pub normal: usize,
+    pub high: usize,
+}
+
+impl Limits {
+    fn of(&self, priority: Priority) -> usize {
+        match priority {
+            Priority::Low => self.low,
+            Priority::Normal => self.normal,
+            Priority::High => self.high,
+            Priority::Critical => usize::MAX,
+        }
+    }
+}
+
+struct Inner {
+    limits: Limits,
+    rules: Vec<(String, Priority)>,
+}
+
+#[derive(Clone)]
+pub struct Qos(Rc<Inner>);
+
+impl Qos {
+    pub fn new(limits: Limits) -> Self {
+        Qos(Rc::new(Inner {
+            limits,
+            rules: Vec::new(),
+        }))
+    }
+
+    /// Requests below `prefix` have `priority`, the first matching rule
+    /// wins
+    pub fn route(mut self, prefix: &str, priority: Priority) -> Self {
+        Rc::get_mut(&mut self.0)
+            .expect("rules are added before the app is built")
+            .rules
+            .push((prefix.to_owned(), priority));
+        self
+    }
+
+    fn classify<Err>(&self, req: &WebRequest<Err>) -> Priority {
+        let by_path = self
+            .0
+            .rules
+            .iter()
+            .find(|(prefix, _)| req.path().starts_with(prefix.as_str()))
+            .map_or(Priority::Normal, |(_, priority)| *priority);
+        let premium = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("Bearer premium-"));
+        let priority = if premium {
+            cmp::max(by_path, Priority::High)
+        } else {
+            by_path
+        };
+        let lowered = req
+            .headers()
+            .get("x-priority")
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"low"));
+        if lowered {
+            cmp::min(priority, Priority::Low)
+        } else {
+            priority
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for Qos
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = web::Error>,
+    S::Future: 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = web::Error;
+    type InitError = ();
+    type Transform = QosMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(QosMiddleware {
+            service,
+            qos: self.clone(),
+        })
+    }
+}
+
+pub struct QosMiddleware<S> {
+    service: S,
+    qos: Qos,
+}
+
+impl<S, Err> Service for QosMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = web::Error>,
+    S::Future: 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = web::Error;
+    type Future = Either<
+        Ready<Result<WebResponse, web::Error>>,
+        LocalBoxFuture<'static, Result<WebResponse, web::Error>>,
+    >;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let in_flight = match req.app_data::<web::types::Data<InFlight>>() {
+            Some(in_flight) => in_flight.clone(),
+            None => {
+                log::error!("no InFlight in the app data, nothing is shed");
+                return Either::Right(self.service.call(req).boxed_local());
+            }
+        };
+        let priority = self.qos.classify(&req);
+        let limit = self.qos.0.limits.of(priority);
+
+        // counted in right away and not when the future is polled, a
+        // request counts from the moment it is accepted
+        let admitted = match admit(in_flight.clone(), limit) {
+            Some(admitted) => admitted,
+            None => {
+                log::warn!(
+                    "shedding {:?} {} with {} requests in flight",
+                    priority,
+                    req.path(),
+                    in_flight.get()
+                );
+                let res = HttpResponse::ServiceUnavailable()
+                    .header(header::RETRY_AFTER, "1")
+                    .json(
+                        &serde_json::json!({ "error": "overloaded, try again later" }),
+                    );
+                return Either::Left(ok(req.into_response(res.into_body())));
+            }
+        };
+        let fut = self.service.call(req);
+        Either::Right(
+            async move {
+                let res = fut.await;
+                drop(admitted);
+                res
+            }
+            .boxed_local(),
+        )
+    }
+}
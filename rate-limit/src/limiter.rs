@@ -0,0 +1,190 @@
+//! A token bucket per client address.
+//!
+//! Every client starts with `burst` tokens, a request takes one, and they
+//! come back at `per_second`. Without a token the request is answered
+//! with `429 Too Many Requests` and a `Retry-After` of the seconds until
+//! the next one.
+//!
+//! The buckets are in `Buckets`, in `Data`, shared by all workers. A bucket
+//! that has filled up again is no different from no bucket,
+//! `Buckets::purge` removes those, so clients that went away don't stay
+//! in memory.
+//!
+//! IPv6 clients usually get a whole `/64`, they are limited by that, not
+//! by single addresses.
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready, TryFutureExt};
+use ntex::http::header::{self, HeaderName, HeaderValue};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, HttpResponse};
+use ntex::{Service, Transform};
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When it is full again, if nothing is taken
+    full_at: Instant,
+}
+
+#[derive(Default)]
+pub struct Buckets {
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl Buckets {
+    /// Takes a token, `Err` with the time until the next one otherwise
+    fn take(
+        &self,
+        client: IpAddr,
+        quota: &Quota,
+        now: Instant,
+    ) -> Result<u32, Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: quota.burst,
+            updated: now,
+            full_at: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * quota.per_second).min(quota.burst);
+        bucket.updated = now;
+
+        if bucket.tokens < 1.0 {
+            let wait = (1.0 - bucket.tokens) / quota.per_second;
+            return Err(Duration::from_secs_f64(wait));
+        }
+        bucket.tokens -= 1.0;
+        let refill = (quota.burst - bucket.tokens) / quota.per_second;
+        bucket.full_at = now + Duration::from_secs_f64(refill);
+        Ok(bucket.tokens as u32)
+    }
+
+    /// Removes the buckets that are full again, returns how many are left
+    pub fn purge(&self) -> usize {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+        buckets.retain(|_, bucket| bucket.full_at > now);
+        buckets.len()
+    }
+}
+
+/// The bucket a peer belongs to
+fn client(peer: Option<IpAddr>) -> IpAddr {
+    match peer {
+        Some(IpAddr::V6(ip)) => {
+            let s = ip.segments();
+            IpAddr::V6(Ipv6Addr::new(s[0], s[1], s[2], s[3], 0, 0, 0, 0))
+        }
+        Some(ip) => ip,
+        // no address, a unix socket, they share one bucket
+        None => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+    }
+}
+
+/// Tokens left after this request
+const REMAINING: &str = "x-ratelimit-remaining";
+
+#[derive(Clone, Copy)]
+struct Quota {
+    burst: f64,
+    per_second: f64,
+}
+
+pub struct RateLimit {
+    buckets: ntex::web::types::Data<Buckets>,
+    quota: Quota,
+}
+
+impl RateLimit {
+    /// Allows bursts of `burst` requests, and `per_second` requests a
+    /// second after that
+    pub fn new(
+        buckets: ntex::web::types::Data<Buckets>,
+        burst: u32,
+        per_second: f64,
+    ) -> Self {
+        assert!(burst > 0 && per_second > 0.0, "the limits must be positive");
+        RateLimit {
+            buckets,
+            quota: Quota {
+                burst: f64::from(burst),
+                per_second,
+            },
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for RateLimit
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimitMiddleware {
+            service,
+            buckets: self.buckets.clone(),
+            quota: self.quota,
+        })
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: S,
+    buckets: ntex::web::types::Data<Buckets>,
+    quota: Quota,
+}
+
+impl<S, Err> Service for RateLimitMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let client = client(req.peer_addr().map(|addr| addr.ip()));
+        let remaining = match self.buckets.take(client, &self.quota, Instant::now()) {
+            Ok(remaining) => remaining,
+            Err(wait) => {
+                // whole seconds, rounded up, never 0
+                let retry_after = wait.as_secs() + 1;
+                log::warn!("{} is over the limit, retry in {}s", client, retry_after);
+                let res = HttpResponse::TooManyRequests()
+                    .header(header::RETRY_AFTER, retry_after)
+                    .header(REMAINING, 0)
+                    .json(&serde_json::json!({ "error": "too many requests" }));
+                return ok(req.into_response(res.into_body())).boxed_local();
+            }
+        };
+
+        self.service
+            .call(req)
+            .map_ok(move |mut res| {
+                res.headers_mut().insert(
+                    HeaderName::from_static(REMAINING),
+                    HeaderValue::from(remaining),
+                );
+                res
+            })
+            .boxed_local()
+    }
+}
@@ -0,0 +1,57 @@
+//! Limits the requests of each client, see `limiter`. The api allows bursts
+//! of 10 and 2 requests a second after that, logins 3 a minute, a bucket
+//! store each.
+use std::time::Duration;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod limiter;
+
+use limiter::{Buckets, RateLimit};
+
+async fn items() -> HttpResponse {
+    HttpResponse::Ok().json(&["first", "second"])
+}
+
+async fn login() -> HttpResponse {
+    HttpResponse::Unauthorized().json(&serde_json::json!({ "error": "wrong password" }))
+}
+
+/// Drops the buckets of clients that have been quiet long enough
+async fn purge(buckets: &[web::types::Data<Buckets>]) {
+    let mut interval = ntex::rt::time::interval(Duration::from_secs(60));
+    loop {
+        interval.tick().await;
+        let left: usize = buckets.iter().map(|b| b.purge()).sum();
+        log::info!("{} clients are rate limited", left);
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,rate_limit=info");
+    env_logger::init();
+
+    let api = web::types::Data::new(Buckets::default());
+    let logins = web::types::Data::new(Buckets::default());
+    let stores = vec![api.clone(), logins.clone()];
+    ntex::rt::spawn(async move { purge(&stores).await });
+
+    web::server(move || {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .service(
+                web::scope("/api")
+                    .wrap(RateLimit::new(api.clone(), 10, 2.0))
+                    .route("/items", web::get().to(items)),
+            )
+            .service(
+                web::resource("/login")
+                    .wrap(RateLimit::new(logins.clone(), 3, 3.0 / 60.0))
+                    .route(web::post().to(login)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
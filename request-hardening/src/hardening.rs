@@ -0,0 +1,176 @@
+//! Rejects requests whose framing is ambiguous, before they are routed.
+//!
+//! Request smuggling needs two servers, a proxy and this one, that disagree
+//! on where a request ends. ntex itself lets `Transfer-Encoding: chunked`
+//! win over `Content-Length`, reads no body for `gzip, chunked`, and takes
+//! `+5` for a length. Any of these can make it see a different request than
+//! the proxy in front of it saw. `Hardening` answers them with `400`:
+//!
+//! * `Content-Length` and `Transfer-Encoding` together
+//! * more than one `Content-Length`, or one that is not just digits
+//! * a `Transfer-Encoding` other than a single `chunked`, or any on HTTP/1.0
+//!
+//! Header blocks with too many headers or too many bytes get `431`. The
+//! connection is closed after every rejection, nothing after the rejected
+//! request on it can be trusted.
+use std::task::{Context, Poll};
+
+use derive_more::Display;
+use futures::future::{ok, Either, Ready};
+use ntex::http::header::{self, HeaderMap};
+use ntex::http::{StatusCode, Version};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::HttpResponse;
+use ntex::{Service, Transform};
+
+#[derive(Debug, Display, PartialEq)]
+pub enum Violation {
+    #[display(fmt = "more than {} headers", _0)]
+    TooManyHeaders(usize),
+    #[display(fmt = "headers larger than {} bytes", _0)]
+    HeadersTooLarge(usize),
+    #[display(fmt = "both Content-Length and Transfer-Encoding")]
+    ConflictingFraming,
+    #[display(fmt = "more than one Content-Length")]
+    MultipleContentLength,
+    #[display(fmt = "invalid Content-Length")]
+    InvalidContentLength,
+    #[display(fmt = "unsupported Transfer-Encoding")]
+    UnsupportedTransferEncoding,
+    #[display(fmt = "Transfer-Encoding on HTTP/1.0")]
+    TransferEncodingOnHttp10,
+}
+
+impl Violation {
+    fn status(&self) -> StatusCode {
+        match self {
+            Violation::TooManyHeaders(_) | Violation::HeadersTooLarge(_) => {
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+            }
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct Hardening {
+    max_headers: usize,
+    max_header_bytes: usize,
+}
+
+impl Default for Hardening {
+    fn default() -> Self {
+        Hardening {
+            max_headers: 64,
+            max_header_bytes: 8 * 1024,
+        }
+    }
+}
+
+impl Hardening {
+    pub fn max_headers(mut self, max: usize) -> Self {
+        self.max_headers = max;
+        self
+    }
+
+    /// Names and values, with `: ` and the line end, like on the wire
+    pub fn max_header_bytes(mut self, max: usize) -> Self {
+        self.max_header_bytes = max;
+        self
+    }
+
+    pub fn check(&self, version: Version, headers: &HeaderMap) -> Result<(), Violation> {
+        if headers.len() > self.max_headers {
+            return Err(Violation::TooManyHeaders(self.max_headers));
+        }
+        let bytes: usize = headers
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len() + 4)
+            .sum();
+        if bytes > self.max_header_bytes {
+            return Err(Violation::HeadersTooLarge(self.max_header_bytes));
+        }
+
+        let lengths: Vec<_> = headers.get_all(header::CONTENT_LENGTH).collect();
+        let codings: Vec<_> = headers.get_all(header::TRANSFER_ENCODING).collect();
+        if !lengths.is_empty() && !codings.is_empty() {
+            return Err(Violation::ConflictingFraming);
+        }
+        match &lengths[..] {
+            [] => (),
+            [length] => {
+                let digits = length.as_bytes();
+                if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+                    return Err(Violation::InvalidContentLength);
+                }
+            }
+            _ => return Err(Violation::MultipleContentLength),
+        }
+        match &codings[..] {
+            [] => (),
+            _ if version == Version::HTTP_10 => {
+                return Err(Violation::TransferEncodingOnHttp10)
+            }
+            [coding] if coding.as_bytes().eq_ignore_ascii_case(b"chunked") => (),
+            _ => return Err(Violation::UnsupportedTransferEncoding),
+        }
+        Ok(())
+    }
+}
+
+impl<S, Err> Transform<S> for Hardening
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type InitError = ();
+    type Transform = HardeningMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(HardeningMiddleware {
+            service,
+            config: *self,
+        })
+    }
+}
+
+pub struct HardeningMiddleware<S> {
+    service: S,
+    config: Hardening,
+}
+
+impl<S, Err> Service for HardeningMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<WebResponse, S::Error>>, S::Future>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        match self.config.check(req.version(), req.headers()) {
+            Ok(()) => Either::Right(self.service.call(req)),
+            Err(violation) => {
+                log::warn!(
+                    "rejecting {} {} from {:?}: {}",
+                    req.method(),
+                    req.path(),
+                    req.peer_addr(),
+                    violation
+                );
+                let res = HttpResponse::build(violation.status())
+                    .force_close()
+                    .json(&serde_json::json!({ "error": violation.to_string() }));
+                Either::Left(ok(req.into_response(res.into_body())))
+            }
+        }
+    }
+}
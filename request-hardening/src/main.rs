@@ -0,0 +1,160 @@
+//! Checks the framing and size of every request before routing, see
+//! `hardening`.
+use bytes::Bytes;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod hardening;
+
+use hardening::Hardening;
+
+async fn echo(body: Bytes) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "received": body.len() }))
+}
+
+fn hardening() -> Hardening {
+    Hardening::default().max_headers(32).max_header_bytes(4096)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,request_hardening=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            // registered last, so it runs first, even unknown routes are checked
+            .wrap(middleware::Logger::default())
+            .wrap(hardening())
+            .route("/echo", web::post().to(echo))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{header, StatusCode, Version};
+    use ntex::web::test;
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new()
+                    .wrap(hardening())
+                    .route("/echo", web::post().to(echo)),
+            )
+            .await
+        };
+    }
+
+    async fn error(
+        app: &impl ntex::Service<
+            Request = ntex::http::Request,
+            Response = ntex::web::dev::WebResponse,
+            Error = ntex::web::Error,
+        >,
+        req: ntex::http::Request,
+        status: StatusCode,
+    ) -> String {
+        let res = test::call_service(app, req).await;
+        assert_eq!(res.status(), status);
+        assert!(!res.response().keep_alive());
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(res).await).unwrap();
+        body["error"].as_str().unwrap().to_owned()
+    }
+
+    #[ntex::test]
+    async fn test_conflicting_framing() {
+        let app = app!();
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .header(header::CONTENT_LENGTH, "5")
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .to_request();
+        let msg = error(&app, req, StatusCode::BAD_REQUEST).await;
+        assert_eq!(msg, "both Content-Length and Transfer-Encoding");
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .header(header::CONTENT_LENGTH, "5")
+            .header(header::CONTENT_LENGTH, "6")
+            .to_request();
+        let msg = error(&app, req, StatusCode::BAD_REQUEST).await;
+        assert_eq!(msg, "more than one Content-Length");
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .header(header::CONTENT_LENGTH, "+5")
+            .to_request();
+        let msg = error(&app, req, StatusCode::BAD_REQUEST).await;
+        assert_eq!(msg, "invalid Content-Length");
+
+        for coding in &["gzip, chunked", "chunked, identity", "xchunked"] {
+            let req = test::TestRequest::post()
+                .uri("/echo")
+                .header(header::TRANSFER_ENCODING, *coding)
+                .to_request();
+            let msg = error(&app, req, StatusCode::BAD_REQUEST).await;
+            assert_eq!(msg, "unsupported Transfer-Encoding");
+        }
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .version(Version::HTTP_10)
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .to_request();
+        let msg = error(&app, req, StatusCode::BAD_REQUEST).await;
+        assert_eq!(msg, "Transfer-Encoding on HTTP/1.0");
+
+        // checked before routing, unknown paths too
+        let req = test::TestRequest::get()
+            .uri("/nowhere")
+            .header(header::CONTENT_LENGTH, "1")
+            .header(header::TRANSFER_ENCODING, "chunked")
+            .to_request();
+        error(&app, req, StatusCode::BAD_REQUEST).await;
+    }
+
+    #[ntex::test]
+    async fn test_oversized_headers() {
+        let app = app!();
+        let too_large = StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE;
+
+        let mut req = test::TestRequest::post().uri("/echo");
+        for n in 0..33 {
+            req = req.header(format!("x-padding-{}", n).as_str(), "1");
+        }
+        let msg = error(&app, req.to_request(), too_large).await;
+        assert_eq!(msg, "more than 32 headers");
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .header("x-padding", "a".repeat(5000))
+            .to_request();
+        let msg = error(&app, req, too_large).await;
+        assert_eq!(msg, "headers larger than 4096 bytes");
+    }
+
+    #[ntex::test]
+    async fn test_well_formed_requests_pass() {
+        let app = app!();
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .header(header::CONTENT_LENGTH, "5")
+            .set_payload("hello")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let req = test::TestRequest::post()
+            .uri("/echo")
+            .header(header::TRANSFER_ENCODING, "Chunked")
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
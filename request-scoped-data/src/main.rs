@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use derive_more::Display;
+use futures::future::{FutureExt, LocalBoxFuture};
+use ntex::http::Payload;
+use ntex::web::{self, middleware, App, ErrorRenderer, FromRequest, HttpRequest};
+use ntex::web::{HttpResponse, WebResponseError};
+
+mod scoped;
+
+/// Users and their permissions, counting how often they are loaded
+#[derive(Default)]
+struct Db {
+    users: HashMap<String, (String, Vec<&'static str>)>,
+    loads: AtomicUsize,
+    /// Shared with every `UserContext`, counts the dropped ones
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Db {
+    fn example() -> Self {
+        let mut users = HashMap::new();
+        users.insert("1".to_owned(), ("ferris".to_owned(), vec!["read", "write"]));
+        users.insert("2".to_owned(), ("corro".to_owned(), vec!["read"]));
+        Db {
+            users,
+            ..Db::default()
+        }
+    }
+
+    /// An expensive lookup, it should happen once per request at most
+    async fn load(&self, id: &str) -> Option<UserContext> {
+        self.loads.fetch_add(1, Ordering::SeqCst);
+        ntex::rt::time::delay_for(Duration::from_millis(10)).await;
+
+        let (name, permissions) = self.users.get(id)?.clone();
+        Some(UserContext {
+            id: id.to_owned(),
+            name,
+            permissions,
+            dropped: self.dropped.clone(),
+        })
+    }
+}
+
+/// Everything known about the user of a request
+struct UserContext {
+    id: String,
+    name: String,
+    permissions: Vec<&'static str>,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Drop for UserContext {
+    fn drop(&mut self) {
+        log::debug!("request done, dropping the context of user {}", self.id);
+        self.dropped.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Clone, Debug, Display)]
+enum AuthError {
+    #[display(fmt = "missing header `x-user-id`")]
+    Missing,
+    #[display(fmt = "unknown user")]
+    Unknown,
+}
+
+impl WebResponseError for AuthError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::Unauthorized()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// The context of the request's user, loaded on first use
+fn user_context(
+    req: &HttpRequest,
+) -> impl std::future::Future<Output = Result<Rc<UserContext>, AuthError>> {
+    let id = req
+        .headers()
+        .get("x-user-id")
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.to_owned());
+    let db = req.app_data::<web::types::Data<Db>>().unwrap().clone();
+
+    scoped::get_or_init(req, || async move {
+        let id = id.ok_or(AuthError::Missing)?;
+        let user = db.load(&id).await.ok_or(AuthError::Unknown)?;
+        Ok(Rc::new(user))
+    })
+}
+
+/// Who is asking
+struct CurrentUser(Rc<UserContext>);
+
+impl<Err: ErrorRenderer> FromRequest<Err> for CurrentUser {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, AuthError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        user_context(req)
+            .map(|ctx| ctx.map(CurrentUser))
+            .boxed_local()
+    }
+}
+
+/// What they may do, from the same context
+struct Permissions(Rc<UserContext>);
+
+impl Permissions {
+    fn allows(&self, permission: &str) -> bool {
+        self.0.permissions.contains(&permission)
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Permissions {
+    type Error = AuthError;
+    type Future = LocalBoxFuture<'static, Result<Self, AuthError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        user_context(req)
+            .map(|ctx| ctx.map(Permissions))
+            .boxed_local()
+    }
+}
+
+async fn dashboard(user: CurrentUser, permissions: Permissions) -> HttpResponse {
+    // one context, shared by both extractors
+    debug_assert!(Rc::ptr_eq(&user.0, &permissions.0));
+
+    HttpResponse::Ok().json(&serde_json::json!({
+        "user": user.0.name,
+        "can_write": permissions.allows("write"),
+    }))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,request_scoped_data=debug");
+    env_logger::init();
+
+    let db = web::types::Data::new(Db::example());
+
+    web::server(move || {
+        App::new()
+            .app_data(db.clone())
+            .wrap(middleware::Logger::default())
+            .route("/dashboard", web::get().to(dashboard))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_single_initialization() {
+        let db = web::types::Data::new(Db::example());
+        let app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/dashboard", web::get().to(dashboard)),
+        )
+        .await;
+
+        for (n, user) in ["1", "2"].iter().enumerate() {
+            let req = test::TestRequest::with_uri("/dashboard")
+                .header("x-user-id", *user)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            // two extractors, one load
+            assert_eq!(db.loads.load(Ordering::SeqCst), n + 1);
+            assert_eq!(db.dropped.load(Ordering::SeqCst), n);
+
+            // the response holds the request, the context goes with it
+            drop(resp);
+            assert_eq!(db.dropped.load(Ordering::SeqCst), n + 1);
+        }
+    }
+
+    #[ntex::test]
+    async fn test_failed_initialization() {
+        let db = web::types::Data::new(Db::example());
+        let app = test::init_service(
+            App::new()
+                .app_data(db.clone())
+                .route("/dashboard", web::get().to(dashboard)),
+        )
+        .await;
+
+        // a failure is shared too, it isn't retried by the second extractor
+        let req = test::TestRequest::with_uri("/dashboard")
+            .header("x-user-id", "42")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(db.loads.load(Ordering::SeqCst), 1);
+
+        let req = test::TestRequest::with_uri("/dashboard").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(db.loads.load(Ordering::SeqCst), 1);
+    }
+}
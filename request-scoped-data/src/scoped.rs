@@ -0,0 +1,37 @@
+//! Values that live as long as one request.
+//!
+//! The value is kept in the request's extensions, the first extractor that
+//! asks for it starts the initialization and every later one gets the same
+//! value. ntex creates the futures of all extractors of a handler before it
+//! polls any of them, so it isn't enough to check for a finished value: the
+//! initialization itself is stored, as a shared future, and is run once.
+//!
+//! Extensions are cleared when the request is done, which drops the value.
+use std::future::Future;
+
+use futures::future::{FutureExt, LocalBoxFuture, Shared};
+use ntex::web::HttpRequest;
+
+struct Scoped<T>(Shared<LocalBoxFuture<'static, T>>);
+
+/// The value of type `T` for this request, created by `init` on first use.
+///
+/// `T` is cloned for every caller, wrap it in an `Rc` if it is expensive to
+/// clone or has to be the same instance.
+pub fn get_or_init<T, F, Fut>(
+    req: &HttpRequest,
+    init: F,
+) -> Shared<LocalBoxFuture<'static, T>>
+where
+    T: Clone + 'static,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = T> + 'static,
+{
+    if let Some(scoped) = req.extensions().get::<Scoped<T>>() {
+        return scoped.0.clone();
+    }
+
+    let value = init().boxed_local().shared();
+    req.extensions_mut().insert(Scoped(value.clone()));
+    value
+}
@@ -0,0 +1,108 @@
+//! Downloads a file, resuming where an interrupted run stopped.
+//!
+//! ```text
+//! download <url> <file> [--stop-after <bytes>]
+//! ```
+//!
+//! The data goes to `<file>.part`, the `ETag` of the download next to it in
+//! `<file>.part.etag`. A later run asks for the rest with `Range`, and with
+//! the `ETag` in `If-Range`, so a file that changed in between is
+//! downloaded again from the start. `--stop-after` interrupts the download
+//! on purpose.
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+
+use futures::StreamExt;
+use ntex::http::client::Client;
+use ntex::http::{header, StatusCode};
+
+fn usage() -> ! {
+    eprintln!("usage: download <url> <file> [--stop-after <bytes>]");
+    std::process::exit(2)
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (url, target, stop_after) = match args.as_slice() {
+        [url, target] => (url, target, None),
+        [url, target, flag, n] if flag == "--stop-after" => (
+            url,
+            target,
+            Some(n.parse::<u64>().unwrap_or_else(|_| usage())),
+        ),
+        _ => usage(),
+    };
+    let part = format!("{}.part", target);
+    let etag_file = format!("{}.part.etag", target);
+
+    // without the etag a partial file can't be resumed safely
+    let resume = match (fs::metadata(&part), fs::read_to_string(&etag_file)) {
+        (Ok(meta), Ok(etag)) if meta.len() > 0 => Some((meta.len(), etag)),
+        _ => None,
+    };
+
+    let mut req = Client::new().get(url.as_str());
+    if let Some((offset, etag)) = &resume {
+        println!("resuming at byte {}, if still {}", offset, etag);
+        req = req
+            .header(header::RANGE, format!("bytes={}-", offset))
+            .header(header::IF_RANGE, etag.as_str());
+    }
+    let mut res = req
+        .send()
+        .await
+        .map_err(|e| io::Error::other(e.to_string()))?;
+
+    let (mut file, mut received) = match res.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let (offset, _) = resume.unwrap();
+            println!("file is unchanged, downloading the rest");
+            (OpenOptions::new().append(true).open(&part)?, offset)
+        }
+        StatusCode::OK => {
+            if resume.is_some() {
+                println!("file has changed, downloading it again");
+            }
+            let etag = res
+                .headers()
+                .get(header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or_default()
+                .to_owned();
+            fs::write(&etag_file, etag)?;
+            (File::create(&part)?, 0)
+        }
+        // the part file holds the whole file already
+        StatusCode::RANGE_NOT_SATISFIABLE if resume.is_some() => {
+            let (offset, _) = resume.unwrap();
+            (OpenOptions::new().append(true).open(&part)?, offset)
+        }
+        status => {
+            return Err(io::Error::other(format!("server responded {}", status)));
+        }
+    };
+
+    let mut this_run = 0;
+    while let Some(chunk) = res.next().await {
+        let chunk = chunk.map_err(|e| io::Error::other(e.to_string()))?;
+        let chunk = match stop_after {
+            Some(n) if this_run + chunk.len() as u64 >= n => {
+                let rest = (n - this_run) as usize;
+                file.write_all(&chunk[..rest])?;
+                println!("interrupted after {} bytes", received + rest as u64);
+                return Ok(());
+            }
+            _ => chunk,
+        };
+        file.write_all(&chunk)?;
+        this_run += chunk.len() as u64;
+        received += chunk.len() as u64;
+    }
+    file.sync_all()?;
+
+    fs::rename(&part, target)?;
+    let _ = fs::remove_file(&etag_file);
+    println!("downloaded {} bytes to {}", received, target);
+    Ok(())
+}
@@ -0,0 +1,261 @@
+//! Large file downloads that can be resumed.
+//!
+//! `GET /files/{name}` sends an `ETag` and a `Last-Modified` with every
+//! file. A client that got interrupted asks for the rest with `Range`, and
+//! sends one of the two in `If-Range`. While the file is unchanged it gets
+//! `206 Partial Content` with just the missing bytes, once the file has
+//! changed `200 OK` with all of it.
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use bytes::Bytes;
+use futures::stream;
+use ntex::http::header;
+use ntex::web::error::BlockingError;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+mod range;
+
+use range::{Range, Validators};
+
+/// Bytes read from the file at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Size of the file generated for the demo
+const DEMO_FILE_SIZE: usize = 16 * 1024 * 1024;
+
+/// The directory the files are served from
+struct Files(PathBuf);
+
+/// Runs blocking file io on the thread pool
+async fn blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => io::Error::other("thread pool is gone"),
+    })
+}
+
+/// `len` bytes of the file, starting at its current position
+fn read_chunks(
+    file: File,
+    len: u64,
+) -> impl futures::Stream<Item = io::Result<Bytes>> + Unpin {
+    Box::pin(stream::try_unfold(
+        (file, len),
+        |(mut file, left)| async move {
+            if left == 0 {
+                return Ok(None);
+            }
+            let size = left.min(CHUNK_SIZE as u64) as usize;
+            let (file, chunk) = blocking(move || {
+                let mut chunk = vec![0; size];
+                file.read_exact(&mut chunk)?;
+                Ok((file, chunk))
+            })
+            .await?;
+            Ok(Some((Bytes::from(chunk), (file, left - size as u64))))
+        },
+    ))
+}
+
+/// Only plain file names, nothing that leaves the directory
+fn valid_name(name: &str) -> bool {
+    !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+async fn download(
+    req: HttpRequest,
+    name: web::types::Path<String>,
+    files: web::types::Data<Files>,
+) -> HttpResponse {
+    if !valid_name(&name) {
+        return HttpResponse::NotFound().finish();
+    }
+    let path = files.0.join(name.as_str());
+    // the validators come from the open file, they describe what is sent
+    let opened = blocking(move || {
+        let file = File::open(path)?;
+        let meta = file.metadata()?;
+        Ok((file, meta.len(), meta.modified()?))
+    })
+    .await;
+    let (mut file, len, modified) = match opened {
+        Ok(opened) => opened,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            return HttpResponse::NotFound().finish()
+        }
+        Err(e) => {
+            log::error!("can not open {}: {}", name, e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let validators = Validators::new(len, modified);
+
+    let headers = req.headers();
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        None => Range::Full,
+        Some(range) => match headers.get(header::IF_RANGE).map(|v| v.to_str()) {
+            Some(Ok(if_range)) if !validators.if_range_matches(if_range) => {
+                log::info!("{} changed since {}, sending all of it", name, if_range);
+                Range::Full
+            }
+            Some(Err(_)) => Range::Full,
+            _ => range::parse(range, len),
+        },
+    };
+
+    let mut res = match range {
+        Range::Full => HttpResponse::Ok(),
+        Range::Partial(..) => HttpResponse::PartialContent(),
+        Range::Unsatisfiable => {
+            return HttpResponse::RangeNotSatisfiable()
+                .header(header::CONTENT_RANGE, format!("bytes */{}", len))
+                .finish()
+        }
+    };
+    res.header(header::ACCEPT_RANGES, "bytes")
+        .header(header::ETAG, validators.etag.as_str())
+        .header(header::LAST_MODIFIED, validators.last_modified_header())
+        .content_type("application/octet-stream");
+
+    let (first, last) = match range {
+        Range::Partial(first, last) => {
+            res.header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, len),
+            );
+            (first, last)
+        }
+        _ => (0, len.saturating_sub(1)),
+    };
+    let size = if len == 0 { 0 } else { last - first + 1 };
+    if let Err(e) = file.seek(SeekFrom::Start(first)) {
+        log::error!("can not seek in {}: {}", name, e);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    // the length is known, it is sent as `Content-Length`
+    res.no_chunking()
+        .header(header::CONTENT_LENGTH, size)
+        .streaming(read_chunks(file, size))
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config.service(
+        web::resource("/files/{name}")
+            .route(web::get().to(download))
+            .route(web::head().to(download)),
+    );
+}
+
+/// Some data to download, the same every time
+fn create_demo_file(path: &std::path::Path) -> io::Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let mut file = io::BufWriter::new(File::create(path)?);
+    for i in 0..DEMO_FILE_SIZE / 4 {
+        file.write_all(&(i as u32).to_le_bytes())?;
+    }
+    file.flush()
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,resumable_download=info");
+    env_logger::init();
+
+    std::fs::create_dir_all("files")?;
+    create_demo_file("files/big.bin".as_ref())?;
+    let files = web::types::Data::new(Files(PathBuf::from("files")));
+
+    web::server(move || {
+        App::new()
+            .app_data(files.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    /// A directory with `data.bin` in it, one per test
+    fn files(content: &[u8]) -> web::types::Data<Files> {
+        let dir = std::env::temp_dir().join(format!(
+            "resumable-download-{}-{}",
+            std::process::id(),
+            content.len()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("data.bin"), content).unwrap();
+        web::types::Data::new(Files(dir))
+    }
+
+    #[ntex::test]
+    async fn test_matching_if_range() {
+        let content: Vec<u8> = (0..=255).collect();
+        let app = test::init_service(
+            App::new().app_data(files(&content)).configure(app_config),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/files/data.bin").to_request();
+        let resp = test::call_service(&app, req).await;
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        let modified = resp.headers().get(header::LAST_MODIFIED).unwrap().clone();
+
+        for validator in [etag, modified] {
+            let req = test::TestRequest::with_uri("/files/data.bin")
+                .header(header::RANGE, "bytes=200-")
+                .header(header::IF_RANGE, validator)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_RANGE).unwrap(),
+                "bytes 200-255/256"
+            );
+            assert_eq!(test::read_body(resp).await, content[200..]);
+        }
+    }
+
+    #[ntex::test]
+    async fn test_stale_if_range() {
+        let content: Vec<u8> = (0..100).collect();
+        let app = test::init_service(
+            App::new().app_data(files(&content)).configure(app_config),
+        )
+        .await;
+
+        let stale = [
+            "\"64-0\"",
+            // a weak tag never matches, even the right one
+            "W/\"64-0\"",
+            "Wed, 21 Oct 2015 07:28:00 GMT",
+        ];
+        for validator in stale {
+            let req = test::TestRequest::with_uri("/files/data.bin")
+                .header(header::RANGE, "bytes=50-")
+                .header(header::IF_RANGE, validator)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+            assert!(resp.headers().get(header::CONTENT_RANGE).is_none());
+            assert_eq!(test::read_body(resp).await, content);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! `Range` and `If-Range` for a single byte range.
+//!
+//! A client resuming a download sends the range it is missing and, in
+//! `If-Range`, the validator of the copy it has. The range is only served
+//! if the file still matches that validator, otherwise the client gets the
+//! whole file and starts over. Without the check it would glue the end of
+//! the new file onto the start of the old one.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// What identifies the current version of a file.
+///
+/// The entity tag is made of size and modification time, a file rewritten
+/// with the same size within the same second keeps its tag
+pub struct Validators {
+    pub etag: String,
+    /// Truncated to seconds, like the `Last-Modified` header
+    pub last_modified: SystemTime,
+}
+
+impl Validators {
+    pub fn new(len: u64, modified: SystemTime) -> Self {
+        let secs = modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Validators {
+            etag: format!("\"{:x}-{:x}\"", len, secs),
+            last_modified: UNIX_EPOCH + Duration::from_secs(secs),
+        }
+    }
+
+    pub fn last_modified_header(&self) -> String {
+        httpdate::fmt_http_date(self.last_modified)
+    }
+
+    /// Does the copy the client has, identified by `If-Range`, still match.
+    ///
+    /// An entity tag needs a strong match, weak tags never match. A date
+    /// has to be exactly the `Last-Modified` of the file
+    pub fn if_range_matches(&self, if_range: &str) -> bool {
+        let if_range = if_range.trim();
+        if if_range.starts_with('"') || if_range.starts_with("W/") {
+            return if_range == self.etag;
+        }
+        match httpdate::parse_http_date(if_range) {
+            Ok(date) => date == self.last_modified,
+            Err(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Range {
+    /// No range, or one that is ignored, the whole file is sent
+    Full,
+    /// First and last byte, inclusive
+    Partial(u64, u64),
+    /// The range starts after the end of the file
+    Unsatisfiable,
+}
+
+/// Parses `Range` for a file of `len` bytes.
+///
+/// Only a single range is supported, a client asking for several gets the
+/// whole file, which the spec allows. So does one with a malformed header
+pub fn parse(header: &str, len: u64) -> Range {
+    let spec = match header.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return Range::Full,
+    };
+    let (first, last) = match spec.find('-') {
+        Some(i) => (&spec[..i], &spec[i + 1..]),
+        None => return Range::Full,
+    };
+
+    let (first, last) = match (first.parse::<u64>(), last.parse::<u64>()) {
+        // `bytes=500-999`, the end can be past the end of the file
+        (Ok(first), Ok(last)) if first <= last => {
+            (first, last.min(len.saturating_sub(1)))
+        }
+        // `bytes=500-`
+        (Ok(first), Err(_)) if last.is_empty() => (first, len.saturating_sub(1)),
+        // `bytes=-500`, the last 500 bytes
+        (Err(_), Ok(suffix)) if first.is_empty() => {
+            if suffix == 0 {
+                return Range::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len.saturating_sub(1))
+        }
+        _ => return Range::Full,
+    };
+    if first >= len {
+        return Range::Unsatisfiable;
+    }
+    Range::Partial(first, last)
+}
@@ -0,0 +1,109 @@
+//! Uploads a file, resuming where an interrupted run stopped.
+//!
+//! ```text
+//! upload <server> <file> [--stop-after <bytes>]
+//! ```
+//!
+//! The url of the upload is kept in `<file>.upload`. A later run asks the
+//! server for the offset with `HEAD`, and sends the rest from there.
+//! `--stop-after` breaks the connection on purpose after that many bytes.
+use std::fs;
+use std::io;
+
+use bytes::Bytes;
+use ntex::http::client::Client;
+use ntex::http::{header, StatusCode};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+fn usage() -> ! {
+    eprintln!("usage: upload <server> <file> [--stop-after <bytes>]");
+    std::process::exit(2)
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (server, path, stop_after) = match args.as_slice() {
+        [server, path] => (server, path, None),
+        [server, path, flag, n] if flag == "--stop-after" => (
+            server,
+            path,
+            Some(n.parse::<usize>().unwrap_or_else(|_| usage())),
+        ),
+        _ => usage(),
+    };
+    let data = fs::read(path)?;
+    let state = format!("{}.upload", path);
+    let client = Client::default();
+
+    let resumed = match fs::read_to_string(&state) {
+        Ok(url) => {
+            let res = client.head(&url).send().await.expect("HEAD failed");
+            match res.headers().get("upload-offset") {
+                Some(offset) if res.status() == StatusCode::OK => {
+                    let offset: usize = offset.to_str().unwrap().parse().unwrap();
+                    println!("resuming {} at {} of {} bytes", url, offset, data.len());
+                    Some((url, offset))
+                }
+                // gone, or never belonged to this file
+                _ => None,
+            }
+        }
+        Err(_) => None,
+    };
+    let (url, offset) = match resumed {
+        Some(resumed) => resumed,
+        None => {
+            let res = client
+                .post(format!("{}/uploads", server))
+                .header("upload-length", data.len())
+                .send()
+                .await
+                .expect("is the server running?");
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let location = res.headers().get(header::LOCATION).unwrap();
+            let url = format!("{}{}", server, location.to_str().unwrap());
+            fs::write(&state, &url)?;
+            println!("created {}", url);
+            (url, 0)
+        }
+    };
+
+    let rest = Bytes::from(data).slice(offset..);
+    let mut chunks: Vec<io::Result<Bytes>> = Vec::new();
+    let mut sent = 0;
+    for start in (0..rest.len()).step_by(CHUNK_SIZE) {
+        let chunk = rest.slice(start..rest.len().min(start + CHUNK_SIZE));
+        if stop_after.is_some_and(|n| sent + chunk.len() > n) {
+            chunks.push(Err(io::Error::other("stopped on purpose")));
+            break;
+        }
+        sent += chunk.len();
+        chunks.push(Ok(chunk));
+    }
+
+    let res = client
+        .patch(&url)
+        .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+        .header("upload-offset", offset)
+        .send_stream(futures::stream::iter(chunks))
+        .await;
+    match res {
+        Ok(res) if res.status() == StatusCode::NO_CONTENT => {
+            let offset = res.headers().get("upload-offset").unwrap();
+            println!("done, the server has {} bytes", offset.to_str().unwrap());
+            fs::remove_file(&state)?;
+        }
+        Ok(mut res) => {
+            let body = res.body().await.unwrap_or_default();
+            println!(
+                "refused: {} {}",
+                res.status(),
+                String::from_utf8_lossy(&body)
+            );
+        }
+        Err(e) => println!("interrupted after {} bytes: {}", sent, e),
+    }
+    Ok(())
+}
@@ -0,0 +1,303 @@
+//! A resumable upload protocol, a subset of tus (https://tus.io).
+//!
+//! `POST /uploads` with `Upload-Length` creates an upload and answers with
+//! its url in `Location`. `PATCH` on it appends the body, its
+//! `Upload-Offset` must be the offset the upload is at, or it is refused
+//! with `409` and the right one. `HEAD` tells the offset, a client whose
+//! connection broke asks, and continues from there.
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use futures::StreamExt;
+use ntex::http::header;
+use ntex::rt::time::timeout;
+use ntex::web::error::BlockingError;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+
+mod uploads;
+
+use uploads::{Status, UploadError, Uploads, UPLOAD_LENGTH, UPLOAD_OFFSET};
+
+/// The largest upload that can be created
+const MAX_LENGTH: u64 = 1 << 30;
+/// ntex doesn't end the body of a request whose connection is gone, a body
+/// that stalls this long is taken as broken off
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Runs blocking file io on the thread pool
+async fn blocking<F, T>(f: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    web::block(f).await.map_err(|e| match e {
+        BlockingError::Error(e) => e,
+        BlockingError::Canceled => io::Error::other("thread pool is gone"),
+    })
+}
+
+/// A header that must hold a number
+fn number(req: &HttpRequest, name: &str) -> Option<u64> {
+    req.headers().get(name)?.to_str().ok()?.parse().ok()
+}
+
+fn with_status(res: &mut ntex::http::ResponseBuilder, status: Status) {
+    res.header(UPLOAD_OFFSET, status.offset)
+        .header(UPLOAD_LENGTH, status.length)
+        .header(header::CACHE_CONTROL, "no-store");
+}
+
+async fn create(
+    req: HttpRequest,
+    uploads: web::types::Data<Uploads>,
+) -> Result<HttpResponse, UploadError> {
+    let length = number(&req, UPLOAD_LENGTH)
+        .ok_or(UploadError::BadRequest("Upload-Length is required"))?;
+    if length > MAX_LENGTH {
+        return Err(UploadError::TooLarge(MAX_LENGTH));
+    }
+    let id = {
+        let uploads = uploads.clone();
+        blocking(move || uploads.create(length)).await?
+    };
+    log::info!("upload {} created, {} bytes", id, length);
+    Ok(HttpResponse::Created()
+        .header(header::LOCATION, format!("/uploads/{}", id))
+        .finish())
+}
+
+async fn offset(
+    id: web::types::Path<String>,
+    uploads: web::types::Data<Uploads>,
+) -> Result<HttpResponse, UploadError> {
+    let status = uploads.status(&id).ok_or(UploadError::NotFound)?;
+    let mut res = HttpResponse::Ok();
+    with_status(&mut res, status);
+    Ok(res.finish())
+}
+
+async fn append(
+    req: HttpRequest,
+    id: web::types::Path<String>,
+    mut body: web::types::Payload,
+    uploads: web::types::Data<Uploads>,
+) -> Result<HttpResponse, UploadError> {
+    let content_type = req.headers().get(header::CONTENT_TYPE);
+    if content_type.is_none_or(|ct| ct != "application/offset+octet-stream") {
+        return Err(UploadError::ContentType);
+    }
+    let offset = number(&req, UPLOAD_OFFSET)
+        .ok_or(UploadError::BadRequest("Upload-Offset is required"))?;
+    let lease = Uploads::lease(&uploads, &id, offset)?;
+    let Status { length, .. } = lease.status;
+    // refused before anything is written, when the length is known
+    if number(&req, header::CONTENT_LENGTH.as_str())
+        .is_some_and(|len| offset.checked_add(len).is_none_or(|end| end > length))
+    {
+        return Err(UploadError::TooLarge(length));
+    }
+
+    let (lease, mut file) = blocking(move || {
+        let file = lease.open()?;
+        Ok((lease, file))
+    })
+    .await?;
+    let mut received = offset;
+    let broken = loop {
+        let chunk = match timeout(IDLE_TIMEOUT, body.next()).await {
+            Ok(Some(Ok(chunk))) => chunk,
+            Ok(None) => break None,
+            Ok(Some(Err(e))) => break Some(e.to_string()),
+            Err(_) => break Some(format!("no data for {:?}", IDLE_TIMEOUT)),
+        };
+        received += chunk.len() as u64;
+        if received > length {
+            blocking(move || lease.roll_back(file)).await?;
+            return Err(UploadError::TooLarge(length));
+        }
+        file = blocking(move || {
+            file.write_all(&chunk)?;
+            Ok(file)
+        })
+        .await?;
+    };
+    let status = blocking(move || lease.finish(file)).await?;
+    // what arrived so far is kept, the client resumes after it
+    if let Some(reason) = broken {
+        log::info!("upload {} broken off at {}: {}", id, status.offset, reason);
+        return Err(UploadError::BadRequest("the body is incomplete"));
+    }
+
+    let mut res = HttpResponse::NoContent();
+    with_status(&mut res, status);
+    Ok(res.finish())
+}
+
+/// The finished file, once every byte is in
+async fn download(
+    id: web::types::Path<String>,
+    uploads: web::types::Data<Uploads>,
+) -> Result<HttpResponse, UploadError> {
+    let path = uploads.file(&id).ok_or(UploadError::NotFound)?;
+    let data = blocking(move || std::fs::read(path)).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/octet-stream")
+        .body(data))
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/uploads", web::post().to(create)).service(
+        web::resource("/uploads/{id}")
+            .route(web::head().to(offset))
+            .route(web::patch().to(append))
+            .route(web::get().to(download)),
+    );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,resumable_upload=info");
+    env_logger::init();
+
+    let uploads = web::types::Data::new(Uploads::load(Path::new("uploads"))?);
+
+    web::server(move || {
+        App::new()
+            .app_data(uploads.clone())
+            // the default limit is for small bodies, chunks can be large
+            .app_data(web::types::PayloadConfig::new(MAX_LENGTH as usize))
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::{error::PayloadError, h1, StatusCode};
+    use ntex::web::test;
+
+    /// An empty directory, one per test
+    fn empty_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "resumable-upload-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn patch(url: &str, offset: u64, body: &'static [u8]) -> test::TestRequest {
+        test::TestRequest::with_uri(url)
+            .method(ntex::http::Method::PATCH)
+            .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+            .header(UPLOAD_OFFSET, offset)
+            .set_payload(body)
+    }
+
+    fn offset_of(res: &ntex::web::dev::WebResponse) -> u64 {
+        let offset = res.headers().get(UPLOAD_OFFSET).unwrap();
+        offset.to_str().unwrap().parse().unwrap()
+    }
+
+    macro_rules! create {
+        ($app:expr, $length:expr) => {{
+            let req = test::TestRequest::post()
+                .uri("/uploads")
+                .header(UPLOAD_LENGTH, $length)
+                .to_request();
+            let res = test::call_service(&$app, req).await;
+            assert_eq!(res.status(), StatusCode::CREATED);
+            let url = res.headers().get(header::LOCATION).unwrap();
+            url.to_str().unwrap().to_owned()
+        }};
+    }
+
+    #[ntex::test]
+    async fn test_offset_conflict_and_completion() {
+        let dir = empty_dir("complete");
+        let uploads = web::types::Data::new(Uploads::load(&dir).unwrap());
+        let app =
+            test::init_service(App::new().app_data(uploads.clone()).configure(app))
+                .await;
+        let url = create!(app, 11);
+
+        let res = test::call_service(&app, patch(&url, 0, b"hello ").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(offset_of(&res), 6);
+
+        // a retry of the same chunk, the client missed the response
+        let res = test::call_service(&app, patch(&url, 0, b"hello ").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+        assert_eq!(offset_of(&res), 6);
+
+        let req = patch(&url, 6, b"world and more").to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = test::TestRequest::get().uri(&url).to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+
+        let res = test::call_service(&app, patch(&url, 6, b"world").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+        assert_eq!(offset_of(&res), 11);
+
+        let res = test::call_service(&app, patch(&url, 11, b"").to_request()).await;
+        assert_eq!(res.status(), StatusCode::CONFLICT);
+
+        let req = test::TestRequest::get().uri(&url).to_request();
+        assert_eq!(test::read_response(&app, req).await, "hello world");
+
+        // found again after a restart
+        let id = url.trim_start_matches("/uploads/");
+        let reloaded = Uploads::load(&dir).unwrap();
+        assert_eq!(
+            reloaded.status(id),
+            Some(Status {
+                offset: 11,
+                length: 11
+            })
+        );
+    }
+
+    #[ntex::test]
+    async fn test_resume_after_broken_patch() {
+        let uploads =
+            web::types::Data::new(Uploads::load(&empty_dir("resume")).unwrap());
+        let app =
+            test::init_service(App::new().app_data(uploads.clone()).configure(app))
+                .await;
+        let url = create!(app, 10);
+
+        // the connection breaks after four bytes
+        let (mut sender, payload) = h1::Payload::create(false);
+        sender.feed_data("0123".into());
+        sender.set_error(PayloadError::Incomplete(None));
+        let req = test::TestRequest::with_uri(&url)
+            .method(ntex::http::Method::PATCH)
+            .header(header::CONTENT_TYPE, "application/offset+octet-stream")
+            .header(UPLOAD_OFFSET, 0)
+            .to_request();
+        let (req, _) = req.replace_payload(payload.into());
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::default()
+            .uri(&url)
+            .method(ntex::http::Method::HEAD)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(offset_of(&res), 4);
+
+        let res = test::call_service(&app, patch(&url, 4, b"456789").to_request()).await;
+        assert_eq!(offset_of(&res), 10);
+        let req = test::TestRequest::get().uri(&url).to_request();
+        assert_eq!(test::read_response(&app, req).await, "0123456789");
+    }
+}
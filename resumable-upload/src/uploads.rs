@@ -0,0 +1,248 @@
+//! Uploads in progress, on disk and in `Data`.
+//!
+//! An upload is two files: `<id>.part` with the bytes received so far, and
+//! `<id>.json` with the length it will have. The size of the part file is
+//! the offset, nothing else has to be kept in sync with it. Whatever reached
+//! the disk before a connection broke counts, and the client resumes from
+//! there. Once all bytes are in, the part file is renamed to `<id>`.
+//!
+//! `load` finds the uploads again after a restart. Only one `PATCH` at a
+//! time writes to an upload, `lease` marks it busy until it is done.
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use derive_more::Display;
+use ntex::http::StatusCode;
+use ntex::web::{self, HttpRequest, HttpResponse, WebResponseError};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+pub const UPLOAD_OFFSET: &str = "upload-offset";
+pub const UPLOAD_LENGTH: &str = "upload-length";
+
+#[derive(Debug, Display)]
+pub enum UploadError {
+    #[display(fmt = "no such upload")]
+    NotFound,
+    #[display(fmt = "the upload is at offset {}", _0)]
+    Conflict(u64),
+    #[display(fmt = "another request is writing to the upload")]
+    Busy,
+    #[display(fmt = "the upload is complete")]
+    Complete,
+    #[display(fmt = "more than the {} bytes of the upload", _0)]
+    TooLarge(u64),
+    #[display(fmt = "{}", _0)]
+    BadRequest(&'static str),
+    #[display(fmt = "expected application/offset+octet-stream")]
+    ContentType,
+    #[display(fmt = "storage error")]
+    Io(io::Error),
+}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+impl WebResponseError for UploadError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            UploadError::NotFound => StatusCode::NOT_FOUND,
+            UploadError::Conflict(_) | UploadError::Busy | UploadError::Complete => {
+                StatusCode::CONFLICT
+            }
+            UploadError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            UploadError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            UploadError::ContentType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            UploadError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        if let UploadError::Io(e) = self {
+            log::error!("upload storage: {}", e);
+        }
+        let mut res = HttpResponse::build(self.status_code());
+        // tells the client where to continue
+        if let UploadError::Conflict(offset) = self {
+            res.header(UPLOAD_OFFSET, *offset);
+        }
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Info {
+    length: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    pub offset: u64,
+    pub length: u64,
+}
+
+impl Status {
+    pub fn complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+struct Upload {
+    status: Status,
+    busy: bool,
+}
+
+pub struct Uploads {
+    dir: PathBuf,
+    uploads: Mutex<HashMap<String, Upload>>,
+}
+
+impl Uploads {
+    /// The uploads in `dir`, complete or not. Blocks
+    pub fn load(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut uploads = HashMap::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let id = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(id) => id.to_owned(),
+                None => continue,
+            };
+            let info: Info = serde_json::from_slice(&fs::read(&path)?)?;
+            let offset = match fs::metadata(dir.join(format!("{}.part", id))) {
+                Ok(meta) => meta.len(),
+                Err(_) if dir.join(&id).exists() => info.length,
+                Err(_) => continue,
+            };
+            let status = Status {
+                offset,
+                length: info.length,
+            };
+            uploads.insert(
+                id,
+                Upload {
+                    status,
+                    busy: false,
+                },
+            );
+        }
+        log::info!("{} uploads in {}", uploads.len(), dir.display());
+        Ok(Uploads {
+            dir: dir.to_owned(),
+            uploads: Mutex::new(uploads),
+        })
+    }
+
+    /// A new, empty upload, returns its id. Blocks
+    pub fn create(&self, length: u64) -> io::Result<String> {
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .collect();
+        File::create(self.part(&id))?;
+        let info = serde_json::to_vec(&Info { length })?;
+        fs::write(self.dir.join(format!("{}.json", id)), info)?;
+
+        let status = Status { offset: 0, length };
+        let upload = Upload {
+            status,
+            busy: false,
+        };
+        self.uploads.lock().unwrap().insert(id.clone(), upload);
+        Ok(id)
+    }
+
+    pub fn status(&self, id: &str) -> Option<Status> {
+        Some(self.uploads.lock().unwrap().get(id)?.status)
+    }
+
+    /// The path of the finished file, once it is complete
+    pub fn file(&self, id: &str) -> Option<PathBuf> {
+        self.status(id)
+            .filter(Status::complete)
+            .map(|_| self.dir.join(id))
+    }
+
+    fn part(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.part", id))
+    }
+
+    /// The right to append to `id`, if the client's `offset` is right
+    pub fn lease(
+        uploads: &web::types::Data<Uploads>,
+        id: &str,
+        offset: u64,
+    ) -> Result<Lease, UploadError> {
+        let mut all = uploads.uploads.lock().unwrap();
+        let upload = all.get_mut(id).ok_or(UploadError::NotFound)?;
+        if upload.status.complete() {
+            return Err(UploadError::Complete);
+        }
+        if upload.busy {
+            return Err(UploadError::Busy);
+        }
+        if offset != upload.status.offset {
+            return Err(UploadError::Conflict(upload.status.offset));
+        }
+        upload.busy = true;
+        Ok(Lease {
+            uploads: uploads.clone(),
+            id: id.to_owned(),
+            status: upload.status,
+        })
+    }
+}
+
+/// Holds an upload busy, until it is dropped
+pub struct Lease {
+    uploads: web::types::Data<Uploads>,
+    id: String,
+    pub status: Status,
+}
+
+impl Lease {
+    pub fn open(&self) -> io::Result<File> {
+        OpenOptions::new()
+            .append(true)
+            .open(self.uploads.part(&self.id))
+    }
+
+    /// Takes the offset from the file after a `PATCH`, complete or broken
+    /// off, and moves a complete upload into place. Blocks
+    pub fn finish(mut self, file: File) -> io::Result<Status> {
+        file.sync_all()?;
+        self.status.offset = file.metadata()?.len();
+        drop(file);
+        if self.status.complete() {
+            let part = self.uploads.part(&self.id);
+            fs::rename(part, self.uploads.dir.join(&self.id))?;
+            log::info!("upload {} is complete", self.id);
+        }
+        Ok(self.status)
+    }
+
+    /// Puts the file back to where it was before this `PATCH`. Blocks
+    pub fn roll_back(self, file: File) -> io::Result<()> {
+        file.set_len(self.status.offset)
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut all = self.uploads.uploads.lock().unwrap();
+        if let Some(upload) = all.get_mut(&self.id) {
+            upload.status = self.status;
+            upload.busy = false;
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Response formats, one type per format.
+//!
+//! A handler generic over `Format` serves any `Serialize` type in every
+//! format, the route picks the format: `web::get().to(handler::<Xml>)`.
+use ntex::http::header;
+use ntex::web::{error, DefaultError, Error, HttpResponse};
+use serde::Serialize;
+
+pub type SerializeError = Box<dyn std::error::Error>;
+
+pub trait Format {
+    const CONTENT_TYPE: &'static str;
+
+    fn serialize<T: Serialize>(value: &T) -> Result<String, SerializeError>;
+}
+
+pub struct Json;
+
+impl Format for Json {
+    const CONTENT_TYPE: &'static str = "application/json";
+
+    fn serialize<T: Serialize>(value: &T) -> Result<String, SerializeError> {
+        Ok(serde_json::to_string_pretty(value)?)
+    }
+}
+
+/// The root element is named after the type, `#[serde(rename)]` changes
+/// it. A sequence becomes one element per entry, named after the field
+pub struct Xml;
+
+impl Format for Xml {
+    const CONTENT_TYPE: &'static str = "application/xml; charset=utf-8";
+
+    fn serialize<T: Serialize>(value: &T) -> Result<String, SerializeError> {
+        let mut xml = String::from(r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+        xml.push('\n');
+        let mut ser = quick_xml::se::Serializer::new(&mut xml);
+        ser.indent(' ', 2);
+        value.serialize(ser)?;
+        Ok(xml)
+    }
+}
+
+pub struct Yaml;
+
+impl Format for Yaml {
+    const CONTENT_TYPE: &'static str = "application/yaml; charset=utf-8";
+
+    fn serialize<T: Serialize>(value: &T) -> Result<String, SerializeError> {
+        Ok(serde_yaml::to_string(value)?)
+    }
+}
+
+/// `value` in format `F`, with its content type
+pub fn respond<F: Format, T: Serialize>(value: &T) -> Result<HttpResponse, Error> {
+    let body = F::serialize(value).map_err(|e| {
+        error::ErrorInternalServerError::<_, DefaultError>(e.to_string())
+    })?;
+    Ok(HttpResponse::Ok()
+        .header(header::CONTENT_TYPE, F::CONTENT_TYPE)
+        .body(body))
+}
@@ -0,0 +1,125 @@
+use ntex::web::{self, middleware, App, Error, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod format;
+
+use format::{respond, Format, Json, Xml, Yaml};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "catalog")]
+struct Catalog {
+    name: String,
+    version: u32,
+    products: Vec<Product>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Product {
+    id: u32,
+    name: String,
+    price: f64,
+    in_stock: bool,
+    /// An empty list leaves no element in xml, it has to default
+    #[serde(default)]
+    tags: Vec<String>,
+    /// Skipped rather than sent as an empty xml element, that would come
+    /// back as `Some("")`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+fn catalog() -> Catalog {
+    Catalog {
+        name: "Lamps & Desks".to_owned(),
+        version: 3,
+        products: vec![
+            Product {
+                id: 1,
+                name: "Desk lamp".to_owned(),
+                price: 19.5,
+                in_stock: true,
+                tags: vec!["light".to_owned(), "office".to_owned()],
+                note: None,
+            },
+            Product {
+                id: 2,
+                name: "Standing desk".to_owned(),
+                price: 420.0,
+                in_stock: false,
+                tags: Vec::new(),
+                note: Some("ships in <2> weeks".to_owned()),
+            },
+        ],
+    }
+}
+
+/// The same catalog for every route, in the route's format
+async fn data<F: Format>(
+    catalog: web::types::Data<Catalog>,
+) -> Result<HttpResponse, Error> {
+    respond::<F, _>(catalog.get_ref())
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .route("/data.json", web::get().to(data::<Json>))
+        .route("/data.xml", web::get().to(data::<Xml>))
+        .route("/data.yaml", web::get().to(data::<Yaml>));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,route_serializers=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .data(catalog())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::header;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_formats_are_equivalent() {
+        let app =
+            test::init_service(App::new().data(catalog()).configure(app_config)).await;
+
+        let mut decoded = Vec::new();
+        for (path, content_type) in &[
+            ("/data.json", Json::CONTENT_TYPE),
+            ("/data.xml", Xml::CONTENT_TYPE),
+            ("/data.yaml", Yaml::CONTENT_TYPE),
+        ] {
+            let req = test::TestRequest::with_uri(path).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert!(resp.status().is_success());
+            assert_eq!(
+                resp.headers().get(header::CONTENT_TYPE).unwrap(),
+                content_type
+            );
+
+            let body = test::read_body(resp).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            let catalog: Catalog = match *path {
+                "/data.json" => serde_json::from_str(body).unwrap(),
+                "/data.xml" => quick_xml::de::from_str(body).unwrap(),
+                _ => serde_yaml::from_str(body).unwrap(),
+            };
+            decoded.push(catalog);
+        }
+
+        for catalog in decoded {
+            assert_eq!(catalog, super::catalog());
+        }
+    }
+}
@@ -0,0 +1,40 @@
+//! Guards of our own, anything that can look at the request head and say
+//! yes or no can pick a route.
+use ntex::http::RequestHead;
+use ntex::web::guard::Guard;
+
+/// Version of the API a request without an `api-version` header gets
+pub const DEFAULT_VERSION: u8 = 1;
+
+/// Matches requests for one version of the API, by the `api-version`
+/// header. A value that isn't a number matches no version
+pub struct ApiVersion(pub u8);
+
+impl ApiVersion {
+    pub fn of(req: &RequestHead) -> Option<u8> {
+        match req.headers.get("api-version") {
+            None => Some(DEFAULT_VERSION),
+            Some(value) => value.to_str().ok()?.trim().parse().ok(),
+        }
+    }
+}
+
+impl Guard for ApiVersion {
+    fn check(&self, req: &RequestHead) -> bool {
+        ApiVersion::of(req) == Some(self.0)
+    }
+}
+
+/// Matches requests sent with a JSON body, whatever the parameters of the
+/// content type
+pub struct JsonBody;
+
+impl Guard for JsonBody {
+    fn check(&self, req: &RequestHead) -> bool {
+        req.headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(';').next())
+            .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+    }
+}
@@ -0,0 +1,200 @@
+//! URL dispatch: scopes, typed path parameters, guards of our own and a
+//! default service for what matches nothing.
+use ntex::http::StatusCode;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+mod guards;
+
+use guards::{ApiVersion, JsonBody};
+
+async fn list_users() -> HttpResponse {
+    HttpResponse::Ok().json(&json!([{"id": 1, "name": "ann"}, {"id": 2, "name": "bob"}]))
+}
+
+/// `{id:\d+}` in the pattern, `/users/ann` doesn't get here at all
+async fn get_user(id: web::types::Path<u32>) -> HttpResponse {
+    HttpResponse::Ok().json(&json!({ "id": *id }))
+}
+
+/// One element of the tuple for every parameter, in pattern order
+async fn get_post(path: web::types::Path<(u32, String)>) -> HttpResponse {
+    let (user, slug) = path.into_inner();
+    HttpResponse::Ok().json(&json!({ "user": user, "slug": slug }))
+}
+
+#[derive(Deserialize)]
+struct NewUser {
+    name: String,
+}
+
+async fn create_user(user: web::types::Json<NewUser>) -> HttpResponse {
+    HttpResponse::Created().json(&json!({ "id": 3, "name": user.name }))
+}
+
+/// The `POST` for everything `JsonBody` didn't take
+async fn not_json() -> HttpResponse {
+    HttpResponse::build(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+        .json(&json!({"error": "expected an application/json body"}))
+}
+
+async fn status_v1() -> HttpResponse {
+    HttpResponse::Ok().json(&json!({"status": "ok"}))
+}
+
+/// Version 2 tells a bit more, in another shape
+async fn status_v2() -> HttpResponse {
+    HttpResponse::Ok().json(&json!({
+        "status": {"healthy": true, "version": env!("CARGO_PKG_VERSION")},
+    }))
+}
+
+/// Everything under `/api` no version scope took
+async fn unsupported_version(req: HttpRequest) -> HttpResponse {
+    let version = req
+        .headers()
+        .get("api-version")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    HttpResponse::BadRequest().json(&json!({
+        "error": format!("api-version `{}` is not supported, use 1 or 2", version),
+    }))
+}
+
+async fn not_found(req: HttpRequest) -> HttpResponse {
+    HttpResponse::NotFound().json(&json!({
+        "error": format!("no route for {} {}", req.method(), req.path()),
+    }))
+}
+
+fn app_config(cfg: &mut web::ServiceConfig) {
+    cfg.service((
+        web::scope("/users")
+            .service(
+                web::resource("")
+                    .route(web::get().to(list_users))
+                    .route(web::post().guard(JsonBody).to(create_user))
+                    .route(web::post().to(not_json)),
+            )
+            .route("/{id:\\d+}", web::get().to(get_user))
+            .route("/{id:\\d+}/posts/{slug}", web::get().to(get_post)),
+        // the same prefix three times, the first scope whose guard says yes
+        // takes the request
+        web::scope("/api")
+            .guard(ApiVersion(1))
+            .route("/status", web::get().to(status_v1)),
+        web::scope("/api")
+            .guard(ApiVersion(2))
+            .route("/status", web::get().to(status_v2)),
+        web::scope("/api").default_service(web::route().to(unsupported_version)),
+    ));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,routing=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+            .default_service(web::route().to(not_found))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::header;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_path_params() {
+        let app = test::init_service(App::new().configure(app_config)).await;
+
+        let req = test::TestRequest::with_uri("/users/7/posts/hello").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body, json!({"user": 7, "slug": "hello"}));
+
+        let req = test::TestRequest::with_uri("/users/7").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body, json!({"id": 7}));
+
+        // not a number, no route
+        let req = test::TestRequest::with_uri("/users/ann").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[ntex::test]
+    async fn test_json_body_guard() {
+        let app = test::init_service(App::new().configure(app_config)).await;
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .header(header::CONTENT_TYPE, "application/json; charset=utf-8")
+            .set_payload(r#"{"name": "cy"}"#)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let req = test::TestRequest::post()
+            .uri("/users")
+            .header(header::CONTENT_TYPE, "text/plain")
+            .set_payload("cy")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[ntex::test]
+    async fn test_api_version_guard() {
+        let app = test::init_service(App::new().configure(app_config)).await;
+
+        // no header is version 1
+        let req = test::TestRequest::with_uri("/api/status").to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body, json!({"status": "ok"}));
+
+        let req = test::TestRequest::with_uri("/api/status")
+            .header("api-version", "2")
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body["status"]["healthy"], true);
+
+        for version in &["3", "two"] {
+            let req = test::TestRequest::with_uri("/api/status")
+                .header("api-version", *version)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+
+    #[ntex::test]
+    async fn test_default_service() {
+        let app = test::init_service(
+            App::new()
+                .configure(app_config)
+                .default_service(web::route().to(not_found)),
+        )
+        .await;
+
+        let req = test::TestRequest::delete().uri("/nope").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["error"], "no route for DELETE /nope");
+
+        // an unknown path in a version scope ends up there as well
+        let req = test::TestRequest::with_uri("/api/nope").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+}
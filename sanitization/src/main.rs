@@ -0,0 +1,194 @@
+//! Comments with html formatting, sanitized with `policy` before they are
+//! stored, the page shows them as they are.
+use std::fmt::Write;
+use std::sync::Mutex;
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod policy;
+
+use policy::{Policy, SanitizeError};
+
+/// Largest comment body, in bytes
+const MAX_COMMENT: usize = 16 * 1024;
+/// Largest request, each byte of the body could be a six byte `\u003c`
+const MAX_JSON: usize = MAX_COMMENT * 6 + 1024;
+
+#[derive(Deserialize)]
+struct NewComment {
+    author: String,
+    body: String,
+}
+
+#[derive(Clone, Serialize)]
+struct Comment {
+    id: usize,
+    author: String,
+    /// Sanitized html
+    body: String,
+}
+
+#[derive(Default)]
+struct Comments(Mutex<Vec<Comment>>);
+
+async fn post_comment(
+    comment: web::types::Json<NewComment>,
+    policy: web::types::Data<Policy>,
+    comments: web::types::Data<Comments>,
+) -> Result<HttpResponse, SanitizeError> {
+    let comment = comment.into_inner();
+    let body = policy.clean(&comment.body)?;
+    let sanitized = body != comment.body;
+
+    let mut comments = comments.0.lock().unwrap();
+    let comment = Comment {
+        id: comments.len() + 1,
+        author: comment.author,
+        body,
+    };
+    if sanitized {
+        log::info!("comment {} by {} was sanitized", comment.id, comment.author);
+    }
+    comments.push(comment.clone());
+    Ok(HttpResponse::Created().json(&serde_json::json!({
+        "comment": comment,
+        "sanitized": sanitized,
+    })))
+}
+
+/// The bodies go in as they are, the author is plain text and escaped
+async fn page(comments: web::types::Data<Comments>) -> HttpResponse {
+    let mut html = String::from("<!doctype html>\n<title>Comments</title>\n");
+    for comment in comments.0.lock().unwrap().iter() {
+        let _ = writeln!(
+            html,
+            "<article><h3>{}</h3>{}</article>",
+            ammonia::clean_text(&comment.author),
+            comment.body
+        );
+    }
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(html)
+}
+
+async fn get_policy(policy: web::types::Data<Policy>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "allowlist": policy.allowlist(),
+        "max_bytes": policy.max_bytes(),
+    }))
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/comments")
+            .route(web::post().to(post_comment))
+            .route(web::get().to(page)),
+    )
+    .route("/policy", web::get().to(get_policy));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,sanitization=info");
+    env_logger::init();
+
+    let policy = web::types::Data::new(Policy::new(&policy::COMMENTS, MAX_COMMENT));
+    let comments = web::types::Data::new(Comments::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(policy.clone())
+            .app_data(comments.clone())
+            .app_data(web::types::JsonConfig::default().limit(MAX_JSON))
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    macro_rules! service {
+        () => {
+            test::init_service(
+                App::new()
+                    .app_data(web::types::Data::new(Policy::new(
+                        &policy::COMMENTS,
+                        MAX_COMMENT,
+                    )))
+                    .app_data(web::types::Data::new(Comments::default()))
+                    .app_data(web::types::JsonConfig::default().limit(MAX_JSON))
+                    .configure(app),
+            )
+            .await
+        };
+    }
+
+    fn post(body: &str) -> ntex::http::Request {
+        test::TestRequest::post()
+            .uri("/comments")
+            .set_json(&serde_json::json!({ "author": "<i>mallory</i>", "body": body }))
+            .to_request()
+    }
+
+    #[ntex::test]
+    async fn test_script_is_removed_formatting_kept() {
+        let app = service!();
+
+        let payload = concat!(
+            "<b>great</b> post<script>fetch('/steal?c='+document.cookie)</script>",
+            "<img src=x onerror=alert(1)>",
+            "<a href=\"javascript:alert(1)\" onclick=\"alert(2)\">see</a>",
+            "<p style=\"position:fixed\">end"
+        );
+        let res: serde_json::Value = test::read_response_json(&app, post(payload)).await;
+        assert_eq!(res["sanitized"], true);
+        assert_eq!(
+            res["comment"]["body"],
+            concat!(
+                "<b>great</b> post",
+                "<a rel=\"noopener noreferrer nofollow ugc\">see</a>",
+                "<p>end</p>"
+            )
+        );
+
+        let res = test::call_service(&app, post("<b>bold</b> and <em>em</em>")).await;
+        let res: serde_json::Value =
+            serde_json::from_slice(&test::read_body(res).await).unwrap();
+        assert_eq!(res["sanitized"], false);
+
+        let req = test::TestRequest::with_uri("/comments").to_request();
+        let page = test::read_body(test::call_service(&app, req).await).await;
+        let page = std::str::from_utf8(&page).unwrap();
+        assert!(!page.contains("<script") && !page.contains("onerror"));
+        assert!(page.contains("<h3>&lt;i&gt;mallory") && !page.contains("<i>mallory"));
+        assert!(page.contains("</h3><b>great</b> post"));
+        assert!(page.contains("<b>bold</b> and <em>em</em>"));
+    }
+
+    #[ntex::test]
+    async fn test_size_guard() {
+        let app = service!();
+
+        let res = test::call_service(&app, post(&"a".repeat(MAX_COMMENT + 1))).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // past what the json extractor reads
+        let res = test::call_service(&app, post(&"a".repeat(MAX_JSON + 1))).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let res = test::call_service(&app, post("<script>alert(1)</script>")).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let req = test::TestRequest::with_uri("/comments").to_request();
+        let page = test::read_body(test::call_service(&app, req).await).await;
+        assert!(!std::str::from_utf8(&page).unwrap().contains("<article>"));
+    }
+}
@@ -0,0 +1,119 @@
+//! What user supplied html may contain, everything else is removed before
+//! it is stored.
+//!
+//! The html is parsed the way a browser would parse it, tags and
+//! attributes not on the allowlist are dropped, `<script>` and `<style>`
+//! with their content. Links keep only the allowed schemes, `javascript:`
+//! urls lose their `href`, and get a `rel` that keeps them from passing on
+//! the page. What comes out is serialized again, well formed, what a
+//! browser renders is what was checked.
+use std::collections::{HashMap, HashSet};
+
+use derive_more::Display;
+use ntex::http::StatusCode;
+use ntex::web::{HttpRequest, HttpResponse, WebResponseError};
+use serde::Serialize;
+
+#[derive(Serialize)]
+pub struct Allowlist {
+    pub tags: &'static [&'static str],
+    /// Attributes by the tag they are allowed on
+    pub attributes: &'static [(&'static str, &'static [&'static str])],
+    pub url_schemes: &'static [&'static str],
+}
+
+/// Formatting for comments, no images, no tables, no styles
+pub const COMMENTS: Allowlist = Allowlist {
+    tags: &[
+        "a",
+        "b",
+        "blockquote",
+        "br",
+        "code",
+        "em",
+        "i",
+        "li",
+        "ol",
+        "p",
+        "pre",
+        "s",
+        "strong",
+        "u",
+        "ul",
+    ],
+    attributes: &[("a", &["href", "title"])],
+    url_schemes: &["http", "https", "mailto"],
+};
+
+#[derive(Debug, Display, PartialEq)]
+pub enum SanitizeError {
+    #[display(fmt = "input is larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[display(fmt = "nothing is left once sanitized")]
+    Empty,
+}
+
+impl WebResponseError for SanitizeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            SanitizeError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            SanitizeError::Empty => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(self.status_code())
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// An allowlist ready to use, shared in `Data`
+pub struct Policy {
+    allowlist: &'static Allowlist,
+    max_bytes: usize,
+    cleaner: ammonia::Builder<'static>,
+}
+
+impl Policy {
+    /// `max_bytes` is checked before parsing, the parser's time and memory
+    /// grow with the input
+    pub fn new(allowlist: &'static Allowlist, max_bytes: usize) -> Self {
+        let attributes = allowlist
+            .attributes
+            .iter()
+            .map(|(tag, attributes)| (*tag, attributes.iter().copied().collect()))
+            .collect::<HashMap<_, HashSet<_>>>();
+
+        let mut cleaner = ammonia::Builder::default();
+        cleaner
+            .tags(allowlist.tags.iter().copied().collect())
+            .tag_attributes(attributes)
+            .generic_attributes(HashSet::new())
+            .url_schemes(allowlist.url_schemes.iter().copied().collect())
+            .link_rel(Some("noopener noreferrer nofollow ugc"));
+        Policy {
+            allowlist,
+            max_bytes,
+            cleaner,
+        }
+    }
+
+    pub fn allowlist(&self) -> &'static Allowlist {
+        self.allowlist
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn clean(&self, html: &str) -> Result<String, SanitizeError> {
+        if html.len() > self.max_bytes {
+            return Err(SanitizeError::TooLarge(self.max_bytes));
+        }
+        let clean = self.cleaner.clean(html).to_string();
+        if clean.trim().is_empty() {
+            return Err(SanitizeError::Empty);
+        }
+        Ok(clean)
+    }
+}
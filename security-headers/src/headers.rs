@@ -0,0 +1,193 @@
+//! Security headers for every response, from a preset that can be adjusted.
+//!
+//! The Content-Security-Policy only allows inline scripts and styles that
+//! carry the nonce of the request. The middleware makes a new one for every
+//! request and puts it in the extensions, handlers get it with the
+//! `CspNonce` extractor and hand it to their templates. `{nonce}` in the
+//! policy is replaced with it.
+//!
+//! Headers the handler set itself are left alone.
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use derive_more::Display;
+use futures::future::{err, ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header::{self, HeaderName, HeaderValue};
+use ntex::http::Payload;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    Error, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+
+const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' 'nonce-{nonce}'; \
+                           style-src 'self' 'nonce-{nonce}'; object-src 'none'; \
+                           base-uri 'none'; frame-ancestors 'none'";
+
+/// The nonce of the current request
+#[derive(Clone, Debug)]
+pub struct CspNonce(pub String);
+
+impl CspNonce {
+    fn new() -> Self {
+        let nonce = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(22)
+            .collect();
+        CspNonce(nonce)
+    }
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "the request has no nonce, SecurityHeaders is missing")]
+pub struct NoNonce;
+
+impl WebResponseError for NoNonce {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for CspNonce {
+    type Error = NoNonce;
+    type Future = Ready<Result<Self, NoNonce>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<CspNonce>() {
+            Some(nonce) => ok(nonce.clone()),
+            None => err(NoNonce),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SecurityHeaders {
+    /// Fixed for every response
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// With `{nonce}` in it
+    csp: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    /// The strict preset
+    fn default() -> Self {
+        SecurityHeaders {
+            headers: Vec::new(),
+            csp: None,
+        }
+        .csp(DEFAULT_CSP)
+        .hsts(63_072_000, true)
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .referrer_policy("strict-origin-when-cross-origin")
+        .header(header::X_FRAME_OPTIONS, "DENY")
+        .permissions_policy("camera=(), microphone=(), geolocation=(), payment=()")
+    }
+}
+
+impl SecurityHeaders {
+    /// Sets `name`, replacing the preset's value
+    pub fn header(mut self, name: HeaderName, value: &str) -> Self {
+        self.headers.retain(|(n, _)| *n != name);
+        self.headers
+            .push((name, HeaderValue::from_str(value).unwrap()));
+        self
+    }
+
+    /// Only takes effect over https, browsers ignore it on plain http
+    pub fn hsts(self, max_age: u64, include_subdomains: bool) -> Self {
+        let mut value = format!("max-age={}", max_age);
+        if include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        self.header(header::STRICT_TRANSPORT_SECURITY, &value)
+    }
+
+    pub fn referrer_policy(self, policy: &str) -> Self {
+        self.header(header::REFERRER_POLICY, policy)
+    }
+
+    pub fn permissions_policy(self, policy: &str) -> Self {
+        self.header(HeaderName::from_static("permissions-policy"), policy)
+    }
+
+    /// `{nonce}` is replaced with the nonce of the request
+    pub fn csp(mut self, policy: &str) -> Self {
+        self.csp = Some(policy.to_owned());
+        self
+    }
+}
+
+impl<S, Err> Transform<S> for SecurityHeaders
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SecurityHeadersMiddleware {
+            service,
+            preset: Rc::new(self.clone()),
+        })
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: S,
+    preset: Rc<SecurityHeaders>,
+}
+
+impl<S, Err> Service for SecurityHeadersMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let nonce = CspNonce::new();
+        let csp = self
+            .preset
+            .csp
+            .as_ref()
+            .map(|policy| policy.replace("{nonce}", &nonce.0));
+        req.extensions_mut().insert(nonce);
+        let preset = self.preset.clone();
+
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            let headers = res.headers_mut();
+            for (name, value) in &preset.headers {
+                if !headers.contains_key(name) {
+                    headers.insert(name.clone(), value.clone());
+                }
+            }
+            if let Some(csp) = csp {
+                if !headers.contains_key(header::CONTENT_SECURITY_POLICY) {
+                    headers.insert(
+                        header::CONTENT_SECURITY_POLICY,
+                        HeaderValue::from_str(&csp).unwrap(),
+                    );
+                }
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,114 @@
+//! Every response gets the security headers of `headers`, the page shows
+//! the Content-Security-Policy nonce at work.
+use ntex::web::{self, middleware, App, Error, HttpResponse};
+use tera::Tera;
+
+mod headers;
+
+use headers::{CspNonce, SecurityHeaders};
+
+async fn index(
+    tmpl: web::types::Data<Tera>,
+    nonce: CspNonce,
+) -> Result<HttpResponse, Error> {
+    let mut ctx = tera::Context::new();
+    ctx.insert("nonce", &nonce.0);
+    let body = tmpl
+        .render("index.html", &ctx)
+        .map_err(|e| web::error::ErrorInternalServerError(e.to_string()))?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+async fn status() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "status": "ok" }))
+}
+
+fn templates() -> Tera {
+    Tera::new(concat!(env!("CARGO_MANIFEST_DIR"), "/templates/**/*")).unwrap()
+}
+
+/// The strict preset, with geolocation allowed for pages of our own
+fn preset() -> SecurityHeaders {
+    SecurityHeaders::default()
+        .permissions_policy("camera=(), microphone=(), geolocation=(self), payment=()")
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .route("/status", web::get().to(status));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,security_headers=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .data(templates())
+            .wrap(preset())
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::header;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_headers_and_nonce() {
+        let app = test::init_service(
+            App::new().data(templates()).wrap(preset()).configure(app),
+        )
+        .await;
+
+        let mut nonces = Vec::new();
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri("/").to_request();
+            let res = test::call_service(&app, req).await;
+            let get = |name| {
+                res.headers()
+                    .get(name)
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+                    .to_owned()
+            };
+            assert_eq!(
+                get(header::STRICT_TRANSPORT_SECURITY),
+                "max-age=63072000; includeSubDomains"
+            );
+            assert_eq!(get(header::X_CONTENT_TYPE_OPTIONS), "nosniff");
+            assert_eq!(
+                get(header::REFERRER_POLICY),
+                "strict-origin-when-cross-origin"
+            );
+            assert!(get(header::HeaderName::from_static("permissions-policy"))
+                .contains("geolocation=(self)"));
+
+            let csp = get(header::CONTENT_SECURITY_POLICY);
+            let nonce = csp
+                .split("'nonce-")
+                .nth(1)
+                .and_then(|rest| rest.split('\'').next())
+                .unwrap()
+                .to_owned();
+            // the page uses the nonce of its own response
+            let body = test::read_body(res).await;
+            let body = std::str::from_utf8(&body).unwrap();
+            assert!(body.contains(&format!("<script nonce=\"{}\">", nonce)));
+            nonces.push(nonce);
+        }
+        assert_ne!(nonces[0], nonces[1]);
+
+        let req = test::TestRequest::get().uri("/status").to_request();
+        let res = test::call_service(&app, req).await;
+        assert!(res.headers().contains_key(header::CONTENT_SECURITY_POLICY));
+    }
+}
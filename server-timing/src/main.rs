@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use futures::join;
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod timing;
+
+use timing::{ServerTiming, Timer, Timings};
+
+/// Stands in for a cache that never has the product
+async fn cache_lookup(_id: u32) -> Option<serde_json::Value> {
+    delay_for(Duration::from_millis(2)).await;
+    None
+}
+
+/// Stands in for a database, the queries take a while
+async fn query(timer: Timer, ms: u64) {
+    delay_for(Duration::from_millis(ms)).await;
+    drop(timer);
+}
+
+async fn product(id: web::types::Path<u32>, timings: Timings) -> HttpResponse {
+    let id = id.into_inner();
+
+    let cached = {
+        let _timer = timings.start("cache").desc("in-memory lookup");
+        cache_lookup(id).await
+    };
+
+    let product = match cached {
+        Some(product) => product,
+        None => {
+            let db = timings.start("db");
+            query(db.child("connect"), 5).await;
+            // the two queries run at the same time, both are parts of `db`
+            join!(
+                query(db.child("product").desc("load product"), 20),
+                query(db.child("reviews").desc("load reviews"), 15),
+            );
+            serde_json::json!({ "id": id, "name": "Lamp", "reviews": 3 })
+        }
+    };
+
+    let _timer = timings.start("render");
+    let body = serde_json::to_string_pretty(&product).unwrap();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(body)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,server_timing=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(ServerTiming)
+            .wrap(middleware::Logger::default())
+            .route("/products/{id}", web::get().to(product))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_nested_timings_in_header() {
+        let app = test::init_service(
+            App::new()
+                .wrap(ServerTiming)
+                .route("/products/{id}", web::get().to(product)),
+        )
+        .await;
+
+        let req = test::TestRequest::with_uri("/products/1").to_request();
+        let resp = test::call_service(&app, req).await;
+        let header = resp.headers().get(timing::SERVER_TIMING).unwrap();
+
+        // `name;dur=<ms>[;desc="..."]`, separated by commas
+        let mut names = Vec::new();
+        for metric in header.to_str().unwrap().split(", ") {
+            let mut params = metric.split(';');
+            names.push(params.next().unwrap().to_owned());
+            let dur = params.next().unwrap().strip_prefix("dur=").unwrap();
+            assert!(dur.parse::<f64>().unwrap() >= 0.0);
+            if let Some(desc) = params.next() {
+                assert!(desc.starts_with("desc=\"") && desc.ends_with('"'));
+            }
+            assert_eq!(params.next(), None);
+        }
+        assert_eq!(
+            names,
+            [
+                "cache",
+                "db",
+                "db.connect",
+                "db.product",
+                "db.reviews",
+                "render",
+                "total"
+            ]
+        );
+    }
+}
@@ -0,0 +1,235 @@
+//! `Server-Timing` header from timings recorded during a request.
+//!
+//! The `ServerTiming` middleware puts a `Timings` accumulator into the
+//! request extensions, handlers take it as an extractor and time their work
+//! with `timings.start("db")`. Timing stops when the returned `Timer` is
+//! dropped. `timer.child("query")` starts a timing nested in it, its name
+//! gets the outer name as prefix, `db.query`. The header lists the timings
+//! in the order they were started, followed by the `total` time of the
+//! request:
+//!
+//! ```text
+//! Server-Timing: db;dur=21.3, db.query;dur=20.1;desc="load product", total;dur=23.0
+//! ```
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::future::{ok, LocalBoxFuture, Ready};
+use ntex::http::header::{HeaderName, HeaderValue};
+use ntex::http::Payload;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, ErrorRenderer, FromRequest, HttpRequest};
+use ntex::{Service, Transform};
+
+pub const SERVER_TIMING: &str = "server-timing";
+
+struct Entry {
+    name: String,
+    desc: Option<String>,
+    /// `None` while the timer is running
+    dur: Option<Duration>,
+}
+
+/// The timings of one request
+#[derive(Clone, Default)]
+pub struct Timings(Rc<RefCell<Vec<Entry>>>);
+
+impl Timings {
+    /// Starts timing `name`
+    pub fn start(&self, name: &str) -> Timer {
+        self.start_named(token(name))
+    }
+
+    fn start_named(&self, name: String) -> Timer {
+        let mut entries = self.0.borrow_mut();
+        entries.push(Entry {
+            name,
+            desc: None,
+            dur: None,
+        });
+        Timer {
+            timings: self.clone(),
+            index: entries.len() - 1,
+            started: Instant::now(),
+        }
+    }
+
+    /// Adds a timing measured some other way
+    pub fn record(&self, name: &str, dur: Duration) {
+        self.0.borrow_mut().push(Entry {
+            name: token(name),
+            desc: None,
+            dur: Some(dur),
+        });
+    }
+
+    /// The header value, timers that are still running are left out
+    pub fn header_value(&self) -> String {
+        self.0
+            .borrow()
+            .iter()
+            .filter_map(|e| e.dur.map(|dur| metric(&e.name, e.desc.as_deref(), dur)))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// A running timing, stops when dropped
+pub struct Timer {
+    timings: Timings,
+    index: usize,
+    started: Instant,
+}
+
+impl Timer {
+    /// Adds a description, shown in devtools next to the name
+    pub fn desc(self, desc: &str) -> Self {
+        self.timings.0.borrow_mut()[self.index].desc = Some(desc.to_owned());
+        self
+    }
+
+    /// Starts timing a part of this timing.
+    ///
+    /// Nesting is explicit, two futures that run concurrently can each
+    /// time their parts without ending up in each other
+    pub fn child(&self, name: &str) -> Timer {
+        let name = format!(
+            "{}.{}",
+            self.timings.0.borrow()[self.index].name,
+            token(name)
+        );
+        self.timings.start_named(name)
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.timings.0.borrow_mut()[self.index].dur = Some(self.started.elapsed());
+    }
+}
+
+/// Metric names are tokens, anything else is replaced
+fn token(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c,
+            '!' | '#' | '$' | '%' | '&' | '\'' | '*' | '+' | '-' | '.' | '^' | '_'
+            | '`' | '|' | '~' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+fn metric(name: &str, desc: Option<&str>, dur: Duration) -> String {
+    let mut metric = format!("{};dur={:.1}", name, dur.as_secs_f64() * 1000.0);
+    if let Some(desc) = desc {
+        // a quoted string, header values can only hold visible ascii
+        let desc: String = desc
+            .chars()
+            .map(|c| {
+                if c.is_ascii() && !c.is_ascii_control() {
+                    c
+                } else {
+                    '?'
+                }
+            })
+            .collect();
+        let desc = desc.replace('\\', "\\\\").replace('"', "\\\"");
+        metric.push_str(&format!(";desc=\"{}\"", desc));
+    }
+    metric
+}
+
+/// Without the middleware the timings are recorded, but never sent
+impl<Err: ErrorRenderer> FromRequest<Err> for Timings {
+    type Error = Error;
+    type Future = Ready<Result<Self, Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ok(req
+            .extensions()
+            .get::<Timings>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Sends the timings of every request in a `Server-Timing` header.
+///
+/// Timings tell a lot about the backend, in production only enable it for
+/// trusted clients
+pub struct ServerTiming;
+
+impl<S, Err> Transform<S> for ServerTiming
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ServerTimingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ServerTimingMiddleware { service })
+    }
+}
+
+pub struct ServerTimingMiddleware<S> {
+    service: S,
+}
+
+impl<S, Err> Service for ServerTimingMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let started = Instant::now();
+        let timings = Timings::default();
+        req.extensions_mut().insert(timings.clone());
+
+        let fut = self.service.call(req);
+        Box::pin(async move {
+            let mut res = fut.await?;
+            timings.record("total", started.elapsed());
+            if let Ok(value) = HeaderValue::from_str(&timings.header_value()) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(SERVER_TIMING), value);
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_header_format() {
+        let timings = Timings::default();
+        timings.record("db", Duration::from_micros(12_340));
+        timings.record("cache hit", Duration::from_micros(500));
+        timings.record("total", Duration::from_millis(20));
+        timings.0.borrow_mut()[0].desc = Some("say \"hi\"".to_owned());
+
+        assert_eq!(
+            timings.header_value(),
+            "db;dur=12.3;desc=\"say \\\"hi\\\"\", cache_hit;dur=0.5, total;dur=20.0"
+        );
+    }
+}
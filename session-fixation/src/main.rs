@@ -0,0 +1,186 @@
+//! A shop where the cart survives logging in, but the session id does not.
+//! See `session` for the why.
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod session;
+
+use session::{Session, SessionMiddleware, Sessions};
+
+const USERS: &[(&str, &str)] = &[("alice", "wonderland"), ("bob", "builder")];
+
+async fn add_to_cart(item: web::types::Path<String>, session: Session) -> HttpResponse {
+    let mut cart: Vec<String> = session.get("cart").unwrap_or_default();
+    cart.push(item.into_inner());
+    session.set("cart", &cart);
+    HttpResponse::Ok().json(&cart)
+}
+
+async fn cart(session: Session) -> HttpResponse {
+    let cart: Vec<String> = session.get("cart").unwrap_or_default();
+    HttpResponse::Ok().json(&cart)
+}
+
+async fn clear_cart(session: Session) -> HttpResponse {
+    session.remove("cart");
+    HttpResponse::NoContent().finish()
+}
+
+#[derive(Deserialize, Serialize)]
+struct Login {
+    user: String,
+    password: String,
+}
+
+/// A new id for the logged in session, the cart goes along
+async fn login(form: web::types::Form<Login>, session: Session) -> HttpResponse {
+    let known = USERS
+        .iter()
+        .any(|(user, password)| *user == form.user && *password == form.password);
+    if !known {
+        return HttpResponse::Unauthorized()
+            .json(&serde_json::json!({ "error": "unknown user or wrong password" }));
+    }
+    session.renew();
+    session.set("user", &form.user);
+    log::info!("{} logged in", form.user);
+    HttpResponse::Ok().json(&serde_json::json!({ "user": form.user }))
+}
+
+async fn me(session: Session) -> HttpResponse {
+    match session.get::<String>("user") {
+        Some(user) => HttpResponse::Ok().json(&serde_json::json!({ "user": user })),
+        None => HttpResponse::Unauthorized()
+            .json(&serde_json::json!({ "error": "not logged in" })),
+    }
+}
+
+async fn logout(session: Session) -> HttpResponse {
+    session.purge();
+    HttpResponse::NoContent().finish()
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/cart")
+            .route(web::get().to(cart))
+            .route(web::delete().to(clear_cart)),
+    )
+    .route("/cart/{item}", web::post().to(add_to_cart))
+    .route("/login", web::post().to(login))
+    .route("/me", web::get().to(me))
+    .route("/logout", web::post().to(logout));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,session_fixation=info");
+    env_logger::init();
+
+    let sessions = web::types::Data::new(Sessions::default());
+
+    web::server(move || {
+        App::new()
+            .wrap(SessionMiddleware::new(sessions.clone()))
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cookie::Cookie;
+    use ntex::http::StatusCode;
+    use ntex::web::dev::WebResponse;
+    use ntex::web::test;
+
+    fn session_cookie(res: &WebResponse) -> Option<Cookie<'static>> {
+        res.response()
+            .cookies()
+            .find(|c| c.name() == session::COOKIE)
+            .map(Cookie::into_owned)
+    }
+
+    #[ntex::test]
+    async fn test_login_renews_session_id() {
+        let app = test::init_service(
+            App::new()
+                .wrap(SessionMiddleware::new(web::types::Data::new(
+                    Sessions::default(),
+                )))
+                .configure(app),
+        )
+        .await;
+
+        // anonymous, with something in the cart
+        let req = test::TestRequest::post().uri("/cart/tent").to_request();
+        let res = test::call_service(&app, req).await;
+        let before = session_cookie(&res).unwrap();
+
+        let req = test::TestRequest::post()
+            .uri("/login")
+            .cookie(before.clone())
+            .set_form(&Login {
+                user: "alice".to_owned(),
+                password: "wonderland".to_owned(),
+            })
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let after = session_cookie(&res).unwrap();
+        assert_ne!(after.value(), before.value());
+
+        // the new id is logged in and kept the cart
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(after.clone())
+            .to_request();
+        let body: serde_json::Value = test::read_response_json(&app, req).await;
+        assert_eq!(body["user"], "alice");
+        let req = test::TestRequest::get()
+            .uri("/cart")
+            .cookie(after)
+            .to_request();
+        let cart: Vec<String> = test::read_response_json(&app, req).await;
+        assert_eq!(cart, vec!["tent"]);
+
+        // the old one is gone
+        let req = test::TestRequest::get()
+            .uri("/me")
+            .cookie(before.clone())
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNAUTHORIZED);
+        let req = test::TestRequest::get()
+            .uri("/cart")
+            .cookie(before)
+            .to_request();
+        let cart: Vec<String> = test::read_response_json(&app, req).await;
+        assert!(cart.is_empty());
+    }
+
+    #[ntex::test]
+    async fn test_unknown_id_is_not_adopted() {
+        let app = test::init_service(
+            App::new()
+                .wrap(SessionMiddleware::new(web::types::Data::new(
+                    Sessions::default(),
+                )))
+                .configure(app),
+        )
+        .await;
+
+        let planted = Cookie::new(session::COOKIE, "chosen-by-the-attacker");
+        let req = test::TestRequest::post()
+            .uri("/cart/tent")
+            .cookie(planted)
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        let issued = session_cookie(&res).unwrap();
+        assert_ne!(issued.value(), "chosen-by-the-attacker");
+    }
+}
@@ -0,0 +1,238 @@
+//! Server side sessions, the cookie only holds a random id.
+//!
+//! Session ids are only ever made here. A cookie with an id the store
+//! doesn't know, one an attacker made up, or one that was renewed or
+//! purged, starts a new, empty session under a new id, the one from the
+//! cookie is never adopted.
+//!
+//! An id planted in a victim's browser before login would still work after
+//! it, and belong to the victim's account. `Session::renew` prevents that,
+//! handlers call it whenever the privilege changes: the data moves to a new
+//! id, the old one is removed from the store and resolves to nothing.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+
+use cookie::{Cookie, SameSite};
+use derive_more::Display;
+use futures::future::{err, ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::{HttpMessage, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    self, Error, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+pub const COOKIE: &str = "session";
+
+type Data = HashMap<String, Value>;
+
+#[derive(Default)]
+pub struct Sessions {
+    entries: Mutex<HashMap<String, Data>>,
+}
+
+impl Sessions {
+    fn load(&self, id: &str) -> Option<Data> {
+        self.entries.lock().unwrap().get(id).cloned()
+    }
+
+    fn save(&self, id: &str, data: Data) {
+        self.entries.lock().unwrap().insert(id.to_owned(), data);
+    }
+
+    fn remove(&self, id: &str) {
+        self.entries.lock().unwrap().remove(id);
+    }
+
+    /// Stores `data` under a new id
+    fn create(&self, data: Data) -> String {
+        let id: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .collect();
+        self.save(&id, data);
+        id
+    }
+}
+
+#[derive(Default)]
+struct State {
+    data: Data,
+    changed: bool,
+    renew: bool,
+    purge: bool,
+}
+
+/// The session of the current request
+#[derive(Clone)]
+pub struct Session(Rc<RefCell<State>>);
+
+impl Session {
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.0.borrow().data.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: T) {
+        let mut state = self.0.borrow_mut();
+        state
+            .data
+            .insert(key.to_owned(), serde_json::to_value(value).unwrap());
+        state.changed = true;
+    }
+
+    pub fn remove(&self, key: &str) {
+        let mut state = self.0.borrow_mut();
+        state.changed |= state.data.remove(key).is_some();
+    }
+
+    /// Moves the data to a new id when the response is sent, call it on
+    /// every privilege change
+    pub fn renew(&self) {
+        self.0.borrow_mut().renew = true;
+    }
+
+    /// Removes the session and its cookie
+    pub fn purge(&self) {
+        self.0.borrow_mut().purge = true;
+    }
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "the request has no session, SessionMiddleware is missing")]
+pub struct NoSession;
+
+impl WebResponseError for NoSession {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Session {
+    type Error = NoSession;
+    type Future = Ready<Result<Self, NoSession>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Session>() {
+            Some(session) => ok(session.clone()),
+            None => err(NoSession),
+        }
+    }
+}
+
+fn cookie(id: String) -> Cookie<'static> {
+    Cookie::build(COOKIE, id)
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .finish()
+}
+
+pub struct SessionMiddleware {
+    sessions: web::types::Data<Sessions>,
+}
+
+impl SessionMiddleware {
+    pub fn new(sessions: web::types::Data<Sessions>) -> Self {
+        SessionMiddleware { sessions }
+    }
+}
+
+impl<S, Err> Transform<S> for SessionMiddleware
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SessionService {
+            service,
+            sessions: self.sessions.clone(),
+        })
+    }
+}
+
+pub struct SessionService<S> {
+    service: S,
+    sessions: web::types::Data<Sessions>,
+}
+
+impl<S, Err> Service for SessionService<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let sessions = self.sessions.clone();
+        // an id we don't know is as good as none
+        let known = req.cookie(COOKIE).and_then(|cookie| {
+            let id = cookie.value().to_owned();
+            sessions.load(&id).map(|data| (id, data))
+        });
+        let (id, data) = match known {
+            Some((id, data)) => (Some(id), data),
+            None => (None, Data::new()),
+        };
+        let session = Session(Rc::new(RefCell::new(State {
+            data,
+            ..State::default()
+        })));
+        req.extensions_mut().insert(session.clone());
+
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            let state = std::mem::take(&mut *session.0.borrow_mut());
+
+            if state.purge {
+                if let Some(id) = id {
+                    sessions.remove(&id);
+                }
+                let mut removal = cookie(String::new());
+                removal.set_max_age(time::Duration::zero());
+                res.response_mut().add_cookie(&removal)?;
+            } else if state.renew {
+                if let Some(id) = id {
+                    sessions.remove(&id);
+                }
+                let id = sessions.create(state.data);
+                log::info!("session renewed");
+                res.response_mut().add_cookie(&cookie(id))?;
+            } else if state.changed {
+                match id {
+                    Some(id) => sessions.save(&id, state.data),
+                    None => {
+                        let id = sessions.create(state.data);
+                        res.response_mut().add_cookie(&cookie(id))?;
+                    }
+                }
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,176 @@
+//! Login and logout with sessions, in a signed cookie or in Redis.
+//!
+//! `SESSION_STORE=redis` keeps them in Redis at `REDIS_URL`, anything else
+//! in the cookie itself. The handlers can't tell the difference, both are
+//! a `SessionStore`. Everything under `/account` needs a login,
+//! `RequireLogin` redirects anonymous users to `/login`.
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod session;
+mod store;
+
+use session::{RequireLogin, Session, SessionMiddleware, Sessions, USER};
+use store::{CookieStore, RedisStore};
+
+/// Redis sessions expire after half an hour without a change
+const REDIS_TTL: usize = 30 * 60;
+
+/// user, password
+const USERS: &[(&str, &str)] = &[("alice", "wonderland"), ("bob", "builder")];
+
+fn see_other(location: &str) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .header(header::LOCATION, location)
+        .finish()
+}
+
+#[derive(Deserialize)]
+struct Next {
+    next: Option<String>,
+}
+
+impl Next {
+    /// Only paths on this site, `//evil.example` is another site. Browsers
+    /// read `/\evil.example` the same way, and drop tabs and line breaks
+    /// before they look
+    fn path(&self) -> &str {
+        match self.next.as_deref() {
+            Some(next)
+                if next.starts_with('/')
+                    && !next.starts_with("//")
+                    && !next.chars().any(|c| c == '\\' || c.is_control()) =>
+            {
+                next
+            }
+            _ => "/account",
+        }
+    }
+}
+
+async fn login_form(next: web::types::Query<Next>) -> HttpResponse {
+    let next = escape(next.path());
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            r#"<form method="post" action="/login">
+<input name="user"> <input name="password" type="password">
+<input name="next" type="hidden" value="{}">
+<button>Log in</button>
+</form>"#,
+            next
+        ))
+}
+
+/// Enough escaping for an attribute value
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+}
+
+#[derive(Deserialize)]
+struct Login {
+    user: String,
+    password: String,
+    next: Option<String>,
+}
+
+async fn login(session: Session, form: web::types::Form<Login>) -> HttpResponse {
+    let known = USERS
+        .iter()
+        .any(|(user, password)| *user == form.user && *password == form.password);
+    if !known {
+        log::warn!("failed login for {:?}", form.user);
+        return HttpResponse::Unauthorized()
+            .json(&serde_json::json!({ "error": "wrong user or password" }));
+    }
+    // a new cookie on every login, one planted before it is worthless
+    session.renew();
+    session.set(USER, &form.user);
+    let next = Next {
+        next: form.next.clone(),
+    };
+    see_other(next.path())
+}
+
+async fn logout(session: Session) -> HttpResponse {
+    session.purge();
+    see_other("/login")
+}
+
+async fn index(session: Session) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "user": session.get::<String>(USER) }))
+}
+
+/// Only reached with a user, `RequireLogin` sees to that
+async fn account(session: Session) -> HttpResponse {
+    let visits = session.get::<u32>("visits").unwrap_or(0) + 1;
+    session.set("visits", visits);
+    HttpResponse::Ok().json(&serde_json::json!({
+        "user": session.get::<String>(USER),
+        "visits": visits,
+    }))
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(index))
+        .service(
+            web::resource("/login")
+                .route(web::get().to(login_form))
+                .route(web::post().to(login)),
+        )
+        .route("/logout", web::post().to(logout))
+        .service(
+            web::scope("/account")
+                .wrap(RequireLogin::new("/login"))
+                .route("", web::get().to(account)),
+        );
+}
+
+/// `SESSION_KEY` signs the cookies, at least 32 bytes. Without it a random
+/// key is used, and a restart logs everybody out.
+fn key() -> cookie::Key {
+    match std::env::var("SESSION_KEY") {
+        Ok(key) if key.len() >= 32 => cookie::Key::derive_from(key.as_bytes()),
+        _ => {
+            log::warn!(
+                "SESSION_KEY is missing or shorter than 32 bytes, using a random key"
+            );
+            cookie::Key::generate()
+        }
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,sessions=info");
+    env_logger::init();
+
+    let key = key();
+    let sessions = if std::env::var("SESSION_STORE").as_deref() == Ok("redis") {
+        let url = std::env::var("REDIS_URL")
+            .unwrap_or_else(|_| "redis://127.0.0.1/".to_owned());
+        let store = RedisStore::connect(&url, REDIS_TTL)
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        log::info!("sessions are in redis at {}", url);
+        Sessions::new(store, key)
+    } else {
+        log::info!("sessions are in the cookie");
+        Sessions::new(CookieStore, key)
+    };
+    let sessions = web::types::Data::new(sessions);
+
+    web::server(move || {
+        App::new()
+            .wrap(SessionMiddleware::new(sessions.clone()))
+            .wrap(middleware::Logger::default())
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
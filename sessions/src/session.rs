@@ -0,0 +1,344 @@
+//! Sessions in a signed cookie, kept by a `SessionStore`.
+//!
+//! `SessionMiddleware` checks the signature of the `session` cookie before
+//! the store ever sees it, a forged or altered cookie is the same as none.
+//! Handlers get the `Session` of the request, after the response the
+//! middleware saves it if it changed, and sends the cookie when its value
+//! did. `RequireLogin` sends requests without a user in the session to the
+//! login page.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use cookie::{Cookie, CookieJar, Key, SameSite};
+use derive_more::Display;
+use futures::future::{err, ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header;
+use ntex::http::{HttpMessage, Payload};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    self, Error, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::store::{SessionStore, State, StoreError};
+
+pub const COOKIE: &str = "session";
+/// The key `RequireLogin` looks for
+pub const USER: &str = "user";
+
+/// The store, and the key the cookies are signed with
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    key: Key,
+}
+
+impl Sessions {
+    pub fn new(store: impl SessionStore + 'static, key: Key) -> Self {
+        Sessions {
+            store: Box::new(store),
+            key,
+        }
+    }
+
+    /// The cookie's value, if the signature is right
+    fn verify(&self, cookie: Cookie<'static>) -> Option<String> {
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+        let cookie = jar.signed(&self.key).get(COOKIE)?;
+        Some(cookie.value().to_owned())
+    }
+
+    fn signed(&self, value: String) -> Cookie<'static> {
+        let mut jar = CookieJar::new();
+        jar.signed(&self.key).add(
+            Cookie::build(COOKIE, value)
+                .path("/")
+                .http_only(true)
+                .same_site(SameSite::Lax)
+                .finish(),
+        );
+        jar.get(COOKIE).unwrap().clone()
+    }
+}
+
+#[derive(Default)]
+struct Inner {
+    state: State,
+    changed: bool,
+    renew: bool,
+    purge: bool,
+}
+
+/// The session of the current request
+#[derive(Clone)]
+pub struct Session(Rc<RefCell<Inner>>);
+
+impl Session {
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.0.borrow().state.get(key)?.clone();
+        serde_json::from_value(value).ok()
+    }
+
+    pub fn set<T: Serialize>(&self, key: &str, value: T) {
+        let mut inner = self.0.borrow_mut();
+        inner
+            .state
+            .insert(key.to_owned(), serde_json::to_value(value).unwrap());
+        inner.changed = true;
+    }
+
+    /// Stores the session under a new cookie, call it on login
+    pub fn renew(&self) {
+        self.0.borrow_mut().renew = true;
+    }
+
+    /// Removes the session and its cookie
+    pub fn purge(&self) {
+        self.0.borrow_mut().purge = true;
+    }
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "the request has no session, SessionMiddleware is missing")]
+pub struct NoSession;
+
+impl WebResponseError for NoSession {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Session {
+    type Error = NoSession;
+    type Future = Ready<Result<Self, NoSession>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Session>() {
+            Some(session) => ok(session.clone()),
+            None => err(NoSession),
+        }
+    }
+}
+
+/// A failing store fails the request, the details only go to the log
+fn unavailable(e: StoreError) -> HttpResponse {
+    log::error!("{}", e);
+    HttpResponse::ServiceUnavailable()
+        .json(&serde_json::json!({ "error": "sessions are unavailable" }))
+}
+
+pub struct SessionMiddleware {
+    sessions: web::types::Data<Sessions>,
+}
+
+impl SessionMiddleware {
+    pub fn new(sessions: web::types::Data<Sessions>) -> Self {
+        SessionMiddleware { sessions }
+    }
+}
+
+impl<S, Err> Transform<S> for SessionMiddleware
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SessionService {
+            service: Rc::new(service),
+            sessions: self.sessions.clone(),
+        })
+    }
+}
+
+pub struct SessionService<S> {
+    service: Rc<S>,
+    sessions: web::types::Data<Sessions>,
+}
+
+impl<S, Err> Service for SessionService<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let sessions = self.sessions.clone();
+        let svc = self.service.clone();
+        let cookie = req.cookie(COOKIE).and_then(|c| sessions.verify(c));
+
+        async move {
+            let store = &sessions.store;
+            let loaded = match cookie {
+                Some(cookie) => match store.load(&cookie).await {
+                    Ok(state) => state.map(|state| (cookie, state)),
+                    Err(e) => return Ok(req.into_response(unavailable(e))),
+                },
+                None => None,
+            };
+            let (cookie, state) = match loaded {
+                Some((cookie, state)) => (Some(cookie), state),
+                None => (None, State::new()),
+            };
+            let session = Session(Rc::new(RefCell::new(Inner {
+                state,
+                ..Inner::default()
+            })));
+            req.extensions_mut().insert(session.clone());
+
+            let mut res = svc.call(req).await?;
+            let inner = std::mem::take(&mut *session.0.borrow_mut());
+
+            let saved = if inner.purge {
+                if let Some(cookie) = &cookie {
+                    store.remove(cookie).await
+                } else {
+                    Ok(())
+                }
+                .map(|_| {
+                    let mut removal = Cookie::build(COOKIE, "").path("/").finish();
+                    removal.set_max_age(time::Duration::zero());
+                    Some(removal)
+                })
+            } else if inner.renew || inner.changed {
+                let mut cookie = cookie.as_deref();
+                if inner.renew {
+                    if let Some(old) = cookie.take() {
+                        if let Err(e) = store.remove(old).await {
+                            return Ok(res.into_response(unavailable(e)));
+                        }
+                    }
+                }
+                store
+                    .save(cookie, &inner.state)
+                    .await
+                    .map(|value| match cookie {
+                        Some(old) if old == value => None,
+                        _ => Some(sessions.signed(value)),
+                    })
+            } else {
+                Ok(None)
+            };
+
+            match saved {
+                Ok(Some(cookie)) => {
+                    res.response_mut().add_cookie(&cookie)?;
+                    Ok(res)
+                }
+                Ok(None) => Ok(res),
+                Err(e) => Ok(res.into_response(unavailable(e))),
+            }
+        }
+        .boxed_local()
+    }
+}
+
+/// Redirects to `login` unless the session has a user, with the path in
+/// `next` to get back to
+pub struct RequireLogin {
+    login: &'static str,
+}
+
+impl RequireLogin {
+    pub fn new(login: &'static str) -> Self {
+        RequireLogin { login }
+    }
+}
+
+impl<S, Err> Transform<S> for RequireLogin
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequireLoginService<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequireLoginService {
+            service,
+            login: self.login,
+        })
+    }
+}
+
+pub struct RequireLoginService<S> {
+    service: S,
+    login: &'static str,
+}
+
+impl<S, Err> Service for RequireLoginService<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let user = req
+            .extensions()
+            .get::<Session>()
+            .and_then(|session| session.get::<String>(USER));
+        if user.is_some() {
+            return self.service.call(req).boxed_local();
+        }
+
+        let next = match req.uri().path_and_query() {
+            Some(path) => path.as_str(),
+            None => req.path(),
+        };
+        let location = format!("{}?next={}", self.login, encode(next));
+        let res = HttpResponse::SeeOther()
+            .header(header::LOCATION, location)
+            .finish();
+        ok(req.into_response(res)).boxed_local()
+    }
+}
+
+/// Percent-encodes everything but unreserved characters and `/`
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z'
+            | b'a'..=b'z'
+            | b'0'..=b'9'
+            | b'-'
+            | b'.'
+            | b'_'
+            | b'~'
+            | b'/' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,131 @@
+//! Where sessions are kept, behind `SessionStore`.
+//!
+//! The middleware only knows the trait. It hands a store the value of the
+//! session cookie, after checking its signature, and puts whatever `save`
+//! returns back into the cookie:
+//!
+//! * `CookieStore`, the cookie is the session, nothing is kept on the server
+//! * `RedisStore`, the cookie holds a random id, the session is in Redis
+use std::collections::HashMap;
+
+use derive_more::Display;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+use serde_json::Value;
+
+pub type State = HashMap<String, Value>;
+
+#[derive(Debug, Display)]
+pub enum StoreError {
+    #[display(fmt = "session is larger than {} bytes", _0)]
+    TooLarge(usize),
+    #[display(fmt = "session store failed: {}", _0)]
+    Redis(redis::RedisError),
+}
+
+impl From<redis::RedisError> for StoreError {
+    fn from(err: redis::RedisError) -> Self {
+        StoreError::Redis(err)
+    }
+}
+
+#[async_trait::async_trait(?Send)]
+pub trait SessionStore: Send + Sync {
+    /// The session a cookie points to, `None` if it is unknown or expired
+    async fn load(&self, cookie: &str) -> Result<Option<State>, StoreError>;
+
+    /// Stores the session, `cookie` is `None` for a new one, returns the
+    /// value for the cookie
+    async fn save(
+        &self,
+        cookie: Option<&str>,
+        state: &State,
+    ) -> Result<String, StoreError>;
+
+    async fn remove(&self, cookie: &str) -> Result<(), StoreError>;
+}
+
+/// Browsers keep about 4096 bytes per cookie, name, signature and
+/// attributes included
+const MAX_COOKIE_SESSION: usize = 3072;
+
+/// The session is the cookie, as JSON in base64, a cookie value can't
+/// have quotes or commas.
+///
+/// The signature stops clients from changing it, not from reading it, keep
+/// secrets out. Nothing on the server knows about the session either, a
+/// copy of the cookie still works after logout, until the key changes.
+pub struct CookieStore;
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for CookieStore {
+    async fn load(&self, cookie: &str) -> Result<Option<State>, StoreError> {
+        let json = base64::decode_config(cookie, base64::URL_SAFE_NO_PAD).ok();
+        Ok(json.and_then(|json| serde_json::from_slice(&json).ok()))
+    }
+
+    async fn save(&self, _: Option<&str>, state: &State) -> Result<String, StoreError> {
+        let json = serde_json::to_vec(state).unwrap();
+        let value = base64::encode_config(json, base64::URL_SAFE_NO_PAD);
+        if value.len() > MAX_COOKIE_SESSION {
+            return Err(StoreError::TooLarge(MAX_COOKIE_SESSION));
+        }
+        Ok(value)
+    }
+
+    async fn remove(&self, _: &str) -> Result<(), StoreError> {
+        // the middleware clears the cookie, there is nothing else
+        Ok(())
+    }
+}
+
+/// Sessions in Redis under `session:<id>`, they expire `ttl` seconds after
+/// they were last changed
+pub struct RedisStore {
+    conn: ConnectionManager,
+    ttl: usize,
+}
+
+impl RedisStore {
+    pub async fn connect(url: &str, ttl: usize) -> Result<Self, StoreError> {
+        let client = redis::Client::open(url)?;
+        let conn = client.get_tokio_connection_manager().await?;
+        Ok(RedisStore { conn, ttl })
+    }
+}
+
+fn redis_key(id: &str) -> String {
+    format!("session:{}", id)
+}
+
+#[async_trait::async_trait(?Send)]
+impl SessionStore for RedisStore {
+    async fn load(&self, id: &str) -> Result<Option<State>, StoreError> {
+        let value: Option<String> = self.conn.clone().get(redis_key(id)).await?;
+        Ok(value.and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    async fn save(&self, id: Option<&str>, state: &State) -> Result<String, StoreError> {
+        let id = match id {
+            Some(id) => id.to_owned(),
+            None => rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(32)
+                .collect(),
+        };
+        let value = serde_json::to_string(state).unwrap();
+        let () = self
+            .conn
+            .clone()
+            .set_ex(redis_key(&id), value, self.ttl)
+            .await?;
+        Ok(id)
+    }
+
+    async fn remove(&self, id: &str) -> Result<(), StoreError> {
+        let _: usize = self.conn.clone().del(redis_key(id)).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod shadow;
+
+#[derive(Deserialize, Serialize)]
+struct Order {
+    product: String,
+    quantity: u32,
+}
+
+/// The version in production
+mod v1 {
+    use super::*;
+
+    pub async fn product(id: web::types::Path<u32>) -> HttpResponse {
+        HttpResponse::Ok().json(&serde_json::json!({ "id": *id, "price": 10.5 }))
+    }
+
+    pub async fn order(order: web::types::Json<Order>) -> HttpResponse {
+        let total = 10.5 * order.quantity as f64;
+        HttpResponse::Created()
+            .json(&serde_json::json!({ "order": &*order, "total": total }))
+    }
+}
+
+/// The new version that is tested with real traffic. It is slower, and
+/// prices are in cents now
+mod v2 {
+    use super::*;
+
+    pub async fn product(id: web::types::Path<u32>) -> HttpResponse {
+        ntex::rt::time::delay_for(Duration::from_secs(2)).await;
+        HttpResponse::Ok().json(&serde_json::json!({ "id": *id, "price": 1050 }))
+    }
+
+    pub async fn order(
+        req: HttpRequest,
+        order: web::types::Json<Order>,
+    ) -> HttpResponse {
+        // a shadow request, don't place the order twice
+        if req.headers().contains_key("x-shadow-request") {
+            log::info!("dry run for {} x {}", order.quantity, order.product);
+        }
+        let total = 10.5 * order.quantity as f64;
+        HttpResponse::Created()
+            .json(&serde_json::json!({ "order": &*order, "total": total }))
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,shadow_traffic=info");
+    env_logger::init();
+
+    // without a shadow, start the example one
+    let url = std::env::var("SHADOW_URL").ok();
+    // `gen_bool` panics outside of 0..=1, and every request would
+    let sample_rate = match std::env::var("SHADOW_SAMPLE_RATE") {
+        Ok(v) => match v.parse::<f64>() {
+            Ok(rate) if (0.0..=1.0).contains(&rate) => rate,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("SHADOW_SAMPLE_RATE must be between 0 and 1, not {}", v),
+                ))
+            }
+        },
+        Err(_) => 0.1,
+    };
+    let shadow = shadow::Shadow {
+        url: url
+            .clone()
+            .unwrap_or_else(|| "http://127.0.0.1:8081".to_owned()),
+        sample_rate,
+        timeout: Duration::from_secs(5),
+        max_in_flight: 100,
+    };
+    log::info!(
+        "mirroring {:.0}% of the requests to {}",
+        shadow.sample_rate * 100.0,
+        shadow.url
+    );
+
+    let primary = web::server(move || {
+        App::new()
+            .wrap(shadow.clone())
+            .wrap(middleware::Logger::default())
+            .route("/products/{id}", web::get().to(v1::product))
+            .route("/orders", web::post().to(v1::order))
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    if url.is_some() {
+        return primary.await;
+    }
+    let v2 = web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::new("shadow: %r %s %Dms"))
+            .route("/products/{id}", web::get().to(v2::product))
+            .route("/orders", web::post().to(v2::order))
+    })
+    .bind("127.0.0.1:8081")?
+    .run();
+    futures::try_join!(primary, v2).map(|_| ())
+}
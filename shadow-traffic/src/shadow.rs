@@ -0,0 +1,254 @@
+//! Mirrors a sample of the requests to a shadow upstream.
+//!
+//! The primary response is sent as soon as it is ready, the shadow request
+//! runs in a task of its own and its response is only compared and dropped.
+//! A slow or broken shadow can't delay anything, at most `max_in_flight`
+//! shadow requests wait per worker, further requests are not mirrored until
+//! some of them finished or timed out.
+//!
+//! Shadow requests carry `x-shadow-request: 1`, the shadow must not repeat
+//! side effects like sending mails or charging cards for them.
+use std::cell::Cell;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use ntex::http::body::{Body, ResponseBody};
+use ntex::http::client::Client;
+use ntex::http::{h1, header, HeaderMap, Method, StatusCode};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::Error;
+use ntex::{Service, Transform};
+use rand::Rng;
+
+/// Largest request body that is buffered for the shadow, larger requests are
+/// only sent to the primary
+const MAX_BODY_SIZE: u64 = 256 * 1024;
+/// Largest shadow response that is read for the comparison
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+/// Request headers that belong to the connection and are not copied
+const HOP_BY_HOP: &[header::HeaderName] = &[
+    header::CONNECTION,
+    header::CONTENT_LENGTH,
+    header::HOST,
+    header::TRANSFER_ENCODING,
+];
+
+#[derive(Clone)]
+pub struct Shadow {
+    /// Base url of the shadow, the path and query of the request are added
+    pub url: String,
+    /// Share of the requests that are mirrored, from 0.0 to 1.0
+    pub sample_rate: f64,
+    /// How long to wait for a shadow response
+    pub timeout: Duration,
+    /// Shadow requests per worker that may wait for a response
+    pub max_in_flight: usize,
+}
+
+impl<S, Err> Transform<S> for Shadow
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ShadowMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ShadowMiddleware {
+            service: Rc::new(service),
+            client: Client::build().timeout(self.timeout).finish(),
+            config: self.clone(),
+            in_flight: Rc::new(Cell::new(0)),
+        })
+    }
+}
+
+pub struct ShadowMiddleware<S> {
+    service: Rc<S>,
+    client: Client,
+    config: Shadow,
+    in_flight: Rc<Cell<usize>>,
+}
+
+impl<S> ShadowMiddleware<S> {
+    fn sample<Err>(&self, req: &WebRequest<Err>) -> bool {
+        if !rand::thread_rng().gen_bool(self.config.sample_rate) {
+            return false;
+        }
+        if self.in_flight.get() >= self.config.max_in_flight {
+            log::debug!("shadow is falling behind, not mirroring {}", req.path());
+            return false;
+        }
+        // a streamed body can be of any size, it isn't buffered
+        if req.headers().contains_key(header::TRANSFER_ENCODING) {
+            return false;
+        }
+        let length = req
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .unwrap_or(0);
+        length <= MAX_BODY_SIZE
+    }
+}
+
+impl<S, Err> Service for ShadowMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, mut req: WebRequest<Err>) -> Self::Future {
+        if !self.sample(&req) {
+            return self.service.call(req).boxed_local();
+        }
+
+        let svc = self.service.clone();
+        let client = self.client.clone();
+        let url = format!(
+            "{}{}",
+            self.config.url.trim_end_matches('/'),
+            req.uri()
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or("/")
+        );
+        let in_flight = self.in_flight.clone();
+
+        async move {
+            // read the body once, then hand a copy of it to the handler
+            let mut body = BytesMut::new();
+            let mut payload = req.take_payload();
+            while let Some(chunk) = payload.next().await {
+                body.extend_from_slice(&chunk?);
+            }
+            let body = body.freeze();
+            let (mut sender, restored) = h1::Payload::create(false);
+            sender.feed_data(body.clone());
+            sender.feed_eof();
+            req.set_payload(restored.into());
+
+            let shadow_req = ShadowRequest {
+                method: req.method().clone(),
+                url,
+                headers: req.headers().clone(),
+                body,
+            };
+            let res = svc.call(req).await?;
+
+            // only bodies that are in memory already can be compared, a
+            // streamed response is compared by its status
+            let primary_body = match res.response().body() {
+                ResponseBody::Body(Body::Bytes(body)) => Some(body.clone()),
+                ResponseBody::Body(Body::Empty) => Some(Bytes::new()),
+                _ => None,
+            };
+            let primary = (res.status(), primary_body);
+
+            in_flight.set(in_flight.get() + 1);
+            ntex::rt::spawn(async move {
+                shadow_req.send(&client, primary).await;
+                in_flight.set(in_flight.get() - 1);
+            });
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+struct ShadowRequest {
+    method: Method,
+    url: String,
+    headers: HeaderMap,
+    body: Bytes,
+}
+
+impl ShadowRequest {
+    /// Sends the request and logs how its response differs from `primary`
+    async fn send(self, client: &Client, primary: (StatusCode, Option<Bytes>)) {
+        let mut req = client
+            .request(self.method.clone(), self.url.as_str())
+            .header("x-shadow-request", "1");
+        for (name, value) in self.headers.iter() {
+            if !HOP_BY_HOP.contains(name) {
+                req = req.header(name.clone(), value.clone());
+            }
+        }
+
+        let mut res = match req.send_body(self.body).await {
+            Ok(res) => res,
+            Err(e) => {
+                log::warn!("shadow {} {} failed: {}", self.method, self.url, e);
+                return;
+            }
+        };
+        let body = match res.body().limit(MAX_RESPONSE_SIZE).await {
+            Ok(body) => body,
+            Err(e) => {
+                log::warn!("shadow {} {} failed: {}", self.method, self.url, e);
+                return;
+            }
+        };
+
+        let (status, primary_body) = primary;
+        if status != res.status() {
+            log::warn!(
+                "shadow {} {}: status {} instead of {}",
+                self.method,
+                self.url,
+                res.status(),
+                status
+            );
+        } else if let Some(diff) = primary_body.and_then(|p| diff(&p, &body)) {
+            log::warn!("shadow {} {}: {}", self.method, self.url, diff);
+        } else {
+            log::debug!("shadow {} {}: same response", self.method, self.url);
+        }
+        // the shadow response is dropped here
+    }
+}
+
+/// Where two bodies start to differ, with a bit of context
+fn diff(primary: &[u8], shadow: &[u8]) -> Option<String> {
+    let offset = primary
+        .iter()
+        .zip(shadow)
+        .position(|(a, b)| a != b)
+        .or_else(|| {
+            if primary.len() != shadow.len() {
+                Some(primary.len().min(shadow.len()))
+            } else {
+                None
+            }
+        })?;
+    let context = |body: &[u8]| {
+        let start = offset.saturating_sub(20);
+        let end = (offset + 20).min(body.len());
+        String::from_utf8_lossy(&body[start..end]).into_owned()
+    };
+    Some(format!(
+        "body differs at byte {}: {:?} instead of {:?}",
+        offset,
+        context(shadow),
+        context(primary)
+    ))
+}
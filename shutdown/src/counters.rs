@@ -0,0 +1,107 @@
+//! The counters of the `state` example, kept across restarts.
+//!
+//! `Counters` is shared by all workers, like `state`'s global counters, and
+//! saved to a file once the server has stopped. `WorkerCounter` is per
+//! worker, like `state`'s `Cell`, it is gone with its worker. Its `Drop`
+//! runs while the worker shuts down, before the cleanup in `main`, and adds
+//! what it counted to the shared counters so nothing is lost.
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use ntex::web;
+use serde::{Deserialize, Serialize};
+
+#[derive(Default, Deserialize, Serialize)]
+struct Saved {
+    requests: u64,
+    by_path: BTreeMap<String, u64>,
+    /// Requests counted by workers, added when they stopped
+    by_workers: u64,
+}
+
+#[derive(Default)]
+pub struct Counters {
+    requests: AtomicU64,
+    by_path: Mutex<BTreeMap<String, u64>>,
+    by_workers: AtomicU64,
+}
+
+impl Counters {
+    /// The counters saved by the last run, zero without a file
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let saved: Saved = match std::fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Saved::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(Counters {
+            requests: AtomicU64::new(saved.requests),
+            by_path: Mutex::new(saved.by_path),
+            by_workers: AtomicU64::new(saved.by_workers),
+        })
+    }
+
+    /// Writes a temporary file and renames it, a crash while saving leaves
+    /// the last file intact
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec_pretty(&self.snapshot())?;
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, json)?;
+        std::fs::rename(tmp, path)
+    }
+
+    pub fn count(&self, path: &str) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        *self
+            .by_path
+            .lock()
+            .unwrap()
+            .entry(path.to_owned())
+            .or_default() += 1;
+    }
+
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::to_value(self.snapshot()).unwrap()
+    }
+
+    fn snapshot(&self) -> Saved {
+        Saved {
+            requests: self.requests.load(Ordering::Relaxed),
+            by_path: self.by_path.lock().unwrap().clone(),
+            by_workers: self.by_workers.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Requests one worker handled, added to `Counters` when it stops
+pub struct WorkerCounter {
+    count: Cell<u64>,
+    counters: web::types::Data<Counters>,
+}
+
+impl WorkerCounter {
+    pub fn new(counters: web::types::Data<Counters>) -> Self {
+        WorkerCounter {
+            count: Cell::new(0),
+            counters,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count.set(self.count.get() + 1);
+        self.count.get()
+    }
+}
+
+impl Drop for WorkerCounter {
+    fn drop(&mut self) {
+        log::info!("worker stopped after {} requests", self.count.get());
+        self.counters
+            .by_workers
+            .fetch_add(self.count.get(), Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,153 @@
+//! Stops gracefully, and cleans up once the server has stopped.
+//!
+//! ntex's own signal handling is disabled, `stop_on_signal` does it: the
+//! first SIGTERM or SIGINT stops the server gracefully. The listeners close
+//! at once, requests in flight get up to `DRAIN_SECS` to finish. A second
+//! signal exits right away, the cleanup doesn't run.
+//!
+//! `server.await` returns once every worker has stopped and dropped its
+//! app, the per-worker `Data` with it, see `counters`. Nothing handles
+//! requests any more, the cleanup runs: the last visits are written, the
+//! sqlite log is checkpointed, and the counters are saved.
+use std::path::Path;
+use std::time::Duration;
+
+use ntex::server::Server;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use serde::Deserialize;
+use tokio::signal::unix::{signal, SignalKind};
+
+mod counters;
+mod visits;
+
+use counters::{Counters, WorkerCounter};
+use visits::Visits;
+
+/// How long requests in flight may take after the first signal
+const DRAIN_SECS: u64 = 10;
+/// Longest `/slow` takes
+const MAX_SLOW_SECS: u64 = 60;
+const COUNTERS: &str = "counters.json";
+
+async fn index(
+    req: HttpRequest,
+    counters: web::types::Data<Counters>,
+    worker: web::types::Data<WorkerCounter>,
+    visits: web::types::Data<Visits>,
+) -> HttpResponse {
+    counters.count(req.path());
+    visits.record(req.path());
+    let mut totals = counters.json();
+    totals["this_worker"] = worker.count().into();
+    HttpResponse::Ok().json(&totals)
+}
+
+#[derive(Deserialize)]
+struct Slow {
+    secs: u64,
+}
+
+/// Takes a while, to have something in flight when the signal comes
+async fn slow(
+    req: HttpRequest,
+    query: web::types::Query<Slow>,
+    counters: web::types::Data<Counters>,
+    visits: web::types::Data<Visits>,
+) -> HttpResponse {
+    if query.secs > MAX_SLOW_SECS {
+        return HttpResponse::BadRequest().json(&serde_json::json!({
+            "error": format!("secs must be at most {}", MAX_SLOW_SECS)
+        }));
+    }
+    ntex::rt::time::delay_for(Duration::from_secs(query.secs)).await;
+    counters.count(req.path());
+    visits.record(req.path());
+    HttpResponse::Ok().json(&serde_json::json!({ "slept": query.secs }))
+}
+
+async fn stop_on_signal(server: Server) {
+    let mut term = signal(SignalKind::terminate()).expect("can not listen for SIGTERM");
+    let mut int = signal(SignalKind::interrupt()).expect("can not listen for SIGINT");
+    let mut graceful = true;
+    loop {
+        futures::future::select(Box::pin(term.recv()), Box::pin(int.recv())).await;
+        if graceful {
+            log::info!("stopping, requests in flight get {}s", DRAIN_SECS);
+            // runs on its own, the next signal has to get through
+            ntex::rt::spawn(server.stop(true));
+            graceful = false;
+        } else {
+            // the server handles one stop at a time, a `stop(false)` would
+            // wait for the graceful one
+            log::warn!("exiting now, without the cleanup");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Writes the pending visits every few seconds, while the server runs
+async fn flush_visits(visits: web::types::Data<Visits>) {
+    let mut interval = ntex::rt::time::interval(Duration::from_secs(5));
+    loop {
+        interval.tick().await;
+        match visits.flush().await {
+            Ok(0) => (),
+            Ok(n) => log::info!("wrote {} visits", n),
+            Err(e) => log::error!("can not write visits: {}", e),
+        }
+    }
+}
+
+/// Runs after the server has stopped, in order
+async fn cleanup(counters: &Counters, visits: &Visits) {
+    match visits.flush().await {
+        Ok(n) => log::info!("wrote the last {} visits", n),
+        Err(e) => log::error!("visits are lost: {}", e),
+    }
+    match visits.total() {
+        Ok(total) => log::info!("{} visits stored", total),
+        Err(e) => log::error!("can not count visits: {}", e),
+    }
+    if let Err(e) = visits.checkpoint() {
+        log::error!("can not checkpoint the database: {}", e);
+    }
+    match counters.save(Path::new(COUNTERS)) {
+        Ok(()) => log::info!("counters saved to {}", COUNTERS),
+        Err(e) => log::error!("can not save the counters: {}", e),
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,shutdown=info");
+    env_logger::init();
+
+    let counters = web::types::Data::new(Counters::load(Path::new(COUNTERS))?);
+    let visits = web::types::Data::new(
+        Visits::open("visits.db").map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    ntex::rt::spawn(flush_visits(visits.clone()));
+
+    let server = {
+        let counters = counters.clone();
+        let visits = visits.clone();
+        web::server(move || {
+            App::new()
+                .app_data(counters.clone())
+                .app_data(visits.clone())
+                .data(WorkerCounter::new(counters.clone()))
+                .wrap(middleware::Logger::default())
+                .route("/", web::get().to(index))
+                .route("/slow", web::get().to(slow))
+        })
+        .disable_signals()
+        .shutdown_timeout(DRAIN_SECS)
+        .bind("127.0.0.1:8080")?
+        .run()
+    };
+    ntex::rt::spawn(stop_on_signal(server.clone()));
+
+    server.await?;
+    cleanup(&counters, &visits).await;
+    Ok(())
+}
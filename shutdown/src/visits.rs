@@ -0,0 +1,100 @@
+//! Visits, written to sqlite in batches.
+//!
+//! Requests only add to `pending`, `flush` writes what has piled up in one
+//! transaction. It runs every few seconds, and a last time on shutdown,
+//! after the workers have stopped, when nothing can be added any more.
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use derive_more::{Display, From};
+use ntex::web::{self, error::BlockingError};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::params;
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+#[derive(Debug, Display, From)]
+pub enum DbError {
+    #[display(fmt = "no connection: {}", _0)]
+    Pool(r2d2::Error),
+    #[display(fmt = "{}", _0)]
+    Sqlite(rusqlite::Error),
+    #[display(fmt = "thread pool is gone")]
+    Canceled,
+}
+
+impl From<BlockingError<DbError>> for DbError {
+    fn from(err: BlockingError<DbError>) -> Self {
+        match err {
+            BlockingError::Error(err) => err,
+            BlockingError::Canceled => DbError::Canceled,
+        }
+    }
+}
+
+pub struct Visits {
+    pool: Pool,
+    pending: Mutex<Vec<(String, i64)>>,
+}
+
+impl Visits {
+    pub fn open(path: &str) -> Result<Self, DbError> {
+        // the log keeps readers and the writer apart, `checkpoint` folds it
+        // back into the database file
+        let manager = SqliteConnectionManager::file(path)
+            .with_init(|conn| conn.execute_batch("PRAGMA journal_mode = WAL;"));
+        let pool = r2d2::Pool::builder().max_size(4).build(manager)?;
+        pool.get()?.execute_batch(
+            "CREATE TABLE IF NOT EXISTS visits (path TEXT NOT NULL, at INTEGER NOT NULL)",
+        )?;
+        Ok(Visits {
+            pool,
+            pending: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn record(&self, path: &str) {
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        self.pending.lock().unwrap().push((path.to_owned(), at));
+    }
+
+    /// Writes the pending visits, returns how many
+    pub async fn flush(&self) -> Result<usize, DbError> {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        if pending.is_empty() {
+            return Ok(0);
+        }
+        let pool = self.pool.clone();
+        let written = web::block(move || -> Result<usize, DbError> {
+            let mut conn = pool.get()?;
+            let tx = conn.transaction()?;
+            for (path, at) in &pending {
+                tx.execute(
+                    "INSERT INTO visits (path, at) VALUES (?1, ?2)",
+                    params![path, at],
+                )?;
+            }
+            tx.commit()?;
+            Ok(pending.len())
+        })
+        .await?;
+        Ok(written)
+    }
+
+    /// Blocks, it is only called after the server has stopped
+    pub fn total(&self) -> Result<i64, DbError> {
+        let conn = self.pool.get()?;
+        Ok(conn.query_row("SELECT COUNT(*) FROM visits", params![], |row| row.get(0))?)
+    }
+
+    /// Moves the write-ahead log into the database file, the last thing
+    /// before the pool closes its connections
+    pub fn checkpoint(&self) -> Result<(), DbError> {
+        let conn = self.pool.get()?;
+        conn.execute_batch("PRAGMA wal_checkpoint(TRUNCATE);")?;
+        Ok(())
+    }
+}
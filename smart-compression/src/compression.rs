@@ -0,0 +1,197 @@
+//! Decides which responses are worth compressing.
+//!
+//! Compression trades server CPU time for bandwidth. For large text bodies
+//! that is a good deal: JSON, HTML or CSS shrink to a fraction, which saves
+//! transfer time on slow links and egress costs. It is a bad deal when:
+//!
+//! * the body is tiny. A response of a few hundred bytes fits into the first
+//!   TCP packets either way, so it doesn't arrive any sooner, but every
+//!   response still pays for setting up an encoder, and the gzip framing can
+//!   even make the body larger.
+//! * the body is compressed already, like images, video or archives. The
+//!   CPU time is spent for nothing, the size stays the same.
+//!
+//! `SmartCompression` only picks the encoding, `middleware::Compress`, which
+//! has to wrap it, does the actual work. Everything that is skipped is marked
+//! as `identity`, so `Compress` leaves it alone.
+use std::task::{Context, Poll};
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::body::{BodySize, MessageBody};
+use ntex::http::header::{self, ContentEncoding, HeaderName, HeaderValue};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{BodyEncoding, Error};
+use ntex::{Service, Transform};
+
+const X_COMPRESSED: &str = "x-compressed";
+const NOT_ACCEPTED: &str = "no (not accepted)";
+
+/// Content types that compress well, everything else is assumed to be
+/// compressed already
+const COMPRESSIBLE: &[&str] = &[
+    "application/javascript",
+    "application/json",
+    "application/wasm",
+    "application/xml",
+    "image/svg+xml",
+];
+
+pub struct SmartCompression {
+    threshold: u64,
+}
+
+impl SmartCompression {
+    /// Only compress bodies of at least `threshold` bytes. Around 1 KB is a
+    /// common choice, streamed bodies of unknown size are always compressed
+    pub fn new(threshold: u64) -> Self {
+        SmartCompression { threshold }
+    }
+}
+
+impl<S, Err> Transform<S> for SmartCompression
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SmartCompressionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SmartCompressionMiddleware {
+            service,
+            threshold: self.threshold,
+        })
+    }
+}
+
+pub struct SmartCompressionMiddleware<S> {
+    service: S,
+    threshold: u64,
+}
+
+impl<S, Err> Service for SmartCompressionMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let accepted = req
+            .headers()
+            .get(header::ACCEPT_ENCODING)
+            .and_then(|v| v.to_str().ok())
+            .and_then(negotiate);
+        let threshold = self.threshold;
+        let fut = self.service.call(req);
+
+        async move {
+            let mut res = fut.await?;
+            // the handler knows better
+            if res.response().get_encoding().is_some() {
+                return Ok(res);
+            }
+
+            let decision = decide(&res, accepted, threshold);
+            // from here on the body depends on the request's
+            // `Accept-Encoding`, caches have to know
+            if decision.is_ok() || decision == Err(NOT_ACCEPTED) {
+                res.headers_mut()
+                    .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+            }
+            let (encoding, marker) = match decision {
+                Ok(encoding) => (encoding, encoding.as_str()),
+                Err(reason) => (ContentEncoding::Identity, reason),
+            };
+            res.response_mut().encoding(encoding);
+            res.headers_mut().insert(
+                HeaderName::from_static(X_COMPRESSED),
+                HeaderValue::from_static(marker),
+            );
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+/// The encoding to use, or why the response is sent as it is
+fn decide(
+    res: &WebResponse,
+    accepted: Option<ContentEncoding>,
+    threshold: u64,
+) -> Result<ContentEncoding, &'static str> {
+    if res.headers().contains_key(header::CONTENT_ENCODING) {
+        return Err("no (already encoded)");
+    }
+    let content_type = res
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("");
+    if !compressible(content_type) {
+        return Err("no (not compressible)");
+    }
+    match res.response().body().size() {
+        BodySize::None | BodySize::Empty => return Err("no (empty)"),
+        BodySize::Sized(len) if len < threshold => return Err("no (too small)"),
+        _ => (),
+    }
+    accepted.ok_or(NOT_ACCEPTED)
+}
+
+fn compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || COMPRESSIBLE.contains(&essence.as_str())
+}
+
+/// The encoding with the highest quality in `Accept-Encoding`, on a tie
+/// brotli, which compresses text best, before gzip and deflate
+fn negotiate(accept_encoding: &str) -> Option<ContentEncoding> {
+    let mut best: Option<(f32, u8, ContentEncoding)> = None;
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let (preference, encoding) = match params.next().unwrap_or("").trim() {
+            "br" => (3, ContentEncoding::Br),
+            "gzip" | "*" => (2, ContentEncoding::Gzip),
+            "deflate" => (1, ContentEncoding::Deflate),
+            _ => continue,
+        };
+        let quality = match params.find_map(|p| p.trim().strip_prefix("q=")) {
+            Some(q) => match q.parse::<f32>() {
+                Ok(q) => q,
+                Err(_) => continue,
+            },
+            None => 1.0,
+        };
+        // `q=0` means not acceptable, `q=NaN` isn't `<= 0.0`, and can't be
+        // ordered either
+        if !quality.is_finite() || quality <= 0.0 {
+            continue;
+        }
+        if !matches!(best, Some((q, p, _)) if (q, p) >= (quality, preference)) {
+            best = Some((quality, preference, encoding));
+        }
+    }
+    best.map(|(_, _, encoding)| encoding)
+}
@@ -0,0 +1,152 @@
+use ntex::http::header::ContentEncoding;
+use ntex::web::{self, middleware, App, BodyEncoding, HttpResponse};
+
+mod compression;
+
+use compression::SmartCompression;
+
+/// Default for `COMPRESSION_THRESHOLD`, in bytes.
+///
+/// Below about 1 KB compression rarely pays off: the response fits into a
+/// single packet anyway, and gzip adds close to 20 bytes of framing. Large
+/// text bodies are the opposite, everything up to the ~14 KB of the initial
+/// TCP congestion window arrives in the first round trip, so shrinking a
+/// 50 KB JSON body below that saves whole round trips.
+const COMPRESSION_THRESHOLD: u64 = 1024;
+
+async fn small() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "status": "ok" }))
+}
+
+async fn large() -> HttpResponse {
+    let body = (0..200)
+        .map(|i| format!("line {}: the quick brown fox jumps over the lazy dog\n", i))
+        .collect::<String>();
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(body)
+}
+
+/// PNG data is deflate compressed already, compressing it again costs CPU
+/// and saves nothing
+async fn logo() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("image/png")
+        .body(vec![0u8; 8 * 1024])
+}
+
+/// A handler can still decide on its own. This one is read by clients that
+/// can't decompress
+async fn raw() -> HttpResponse {
+    HttpResponse::Ok()
+        .encoding(ContentEncoding::Identity)
+        .content_type("text/csv")
+        .body("id,name\n".repeat(1000))
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .route("/small", web::get().to(small))
+        .route("/large", web::get().to(large))
+        .route("/logo.png", web::get().to(logo))
+        .route("/raw.csv", web::get().to(raw));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,smart_compression=info");
+    env_logger::init();
+
+    let threshold = std::env::var("COMPRESSION_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(COMPRESSION_THRESHOLD);
+    log::info!("compressing responses of at least {} bytes", threshold);
+
+    web::server(move || {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .wrap(SmartCompression::new(threshold))
+            // `Compress` has to be outside, it does what `SmartCompression`
+            // decided. Bodies over 1 KB are compressed on a thread pool,
+            // which keeps the workers free but still costs CPU per response
+            .wrap(middleware::Compress::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::header;
+    use ntex::web::test;
+
+    async fn get(path: &str, accept_encoding: Option<&str>) -> (String, String, usize) {
+        let app = test::init_service(
+            App::new()
+                .wrap(SmartCompression::new(COMPRESSION_THRESHOLD))
+                .wrap(middleware::Compress::default())
+                .configure(app_config),
+        )
+        .await;
+
+        let mut req = test::TestRequest::with_uri(path);
+        if let Some(accept_encoding) = accept_encoding {
+            req = req.header(header::ACCEPT_ENCODING, accept_encoding);
+        }
+        let resp = test::call_service(&app, req.to_request()).await;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .map(|v| v.to_str().unwrap().to_owned())
+                .unwrap_or_default()
+        };
+        let encoding = header(header::CONTENT_ENCODING);
+        let marker = header(header::HeaderName::from_static("x-compressed"));
+        (encoding, marker, test::read_body(resp).await.len())
+    }
+
+    #[ntex::test]
+    async fn test_compression_threshold() {
+        let (encoding, marker, _) = get("/small", Some("gzip")).await;
+        assert_eq!((encoding.as_str(), marker.as_str()), ("", "no (too small)"));
+
+        let (encoding, marker, len) = get("/large", Some("gzip, deflate")).await;
+        assert_eq!((encoding.as_str(), marker.as_str()), ("gzip", "gzip"));
+        assert!(len < 1024);
+
+        let (encoding, marker, _) = get("/large", None).await;
+        assert_eq!(encoding, "");
+        assert_eq!(marker, "no (not accepted)");
+    }
+
+    #[ntex::test]
+    async fn test_skipped_content() {
+        let (encoding, marker, len) = get("/logo.png", Some("gzip")).await;
+        assert_eq!(encoding, "");
+        assert_eq!(marker, "no (not compressible)");
+        assert_eq!(len, 8 * 1024);
+
+        // left alone, without a marker
+        let (encoding, marker, _) = get("/raw.csv", Some("gzip")).await;
+        assert_eq!((encoding.as_str(), marker.as_str()), ("", ""));
+    }
+
+    #[ntex::test]
+    async fn test_negotiation() {
+        let (encoding, ..) = get("/large", Some("gzip, br")).await;
+        assert_eq!(encoding, "br");
+        let (encoding, ..) = get("/large", Some("br;q=0.5, deflate")).await;
+        assert_eq!(encoding, "deflate");
+        let (encoding, ..) = get("/large", Some("deflate;q=0.5, gzip;q=NaN")).await;
+        assert_eq!(encoding, "deflate");
+        let (encoding, marker, _) = get("/large", Some("gzip;q=0, identity")).await;
+        assert_eq!(
+            (encoding.as_str(), marker.as_str()),
+            ("", "no (not accepted)")
+        );
+    }
+}
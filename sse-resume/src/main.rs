@@ -0,0 +1,267 @@
+//! Server-sent events that resume where the client left off.
+//!
+//! Every event gets the next id of its channel, and the last
+//! `REPLAY_BUFFER` events of each channel are kept in `Data`. A reconnecting
+//! `EventSource` sends the id of the last event it received in the
+//! `Last-Event-ID` header, the server replays everything after it before
+//! the live events continue.
+//!
+//! An id the server doesn't know, from before a restart for example, or one
+//! that is older than the buffer reaches back, can't be resumed. The client
+//! gets a `resync` event instead and has to reload its state, live events
+//! follow right after it.
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{stream, StreamExt};
+use ntex::http::header;
+use ntex::web::{self, middleware, App, Error, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+/// Events kept per channel for replay
+const REPLAY_BUFFER: usize = 100;
+/// Live events queued for a slow client before it is dropped, it can
+/// reconnect and resume
+const CLIENT_QUEUE: usize = 16;
+/// Channels anyone can create, by publishing to or subscribing to a new name
+const MAX_CHANNELS: usize = 1000;
+/// How often the `clock` channel gets an event
+const CLOCK_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Event {
+    id: u64,
+    /// The event, ready to be sent
+    frame: Bytes,
+}
+
+impl Event {
+    fn new(id: u64, kind: Option<&str>, data: &str) -> Self {
+        let mut frame = format!("id: {}\n", id);
+        if let Some(kind) = kind {
+            frame.push_str(&format!("event: {}\n", kind));
+        }
+        // a line break ends a field, every line of the data needs its own.
+        // An event without data is never dispatched by the browser
+        for line in data.split('\n') {
+            frame.push_str(&format!("data: {}\n", line.trim_end_matches('\r')));
+        }
+        frame.push('\n');
+        Event {
+            id,
+            frame: Bytes::from(frame),
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ResyncReason {
+    /// The id is not one this channel has handed out
+    Unknown,
+    /// The events after the id are no longer in the buffer
+    Expired,
+}
+
+#[derive(Default)]
+struct Channel {
+    /// Id of the last published event, `0` before the first
+    last_id: u64,
+    buffer: VecDeque<Event>,
+    subscribers: Vec<mpsc::Sender<Bytes>>,
+}
+
+impl Channel {
+    fn publish(&mut self, data: &str) -> u64 {
+        self.last_id += 1;
+        let event = Event::new(self.last_id, None, data);
+
+        // disconnected and slow clients are removed on the way
+        self.subscribers
+            .retain_mut(|tx| tx.try_send(event.frame.clone()).is_ok());
+        if self.buffer.len() == REPLAY_BUFFER {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back(event);
+        self.last_id
+    }
+
+    /// The events after `last_id`, or why they can't be replayed
+    fn replay(&self, last_id: Option<&str>) -> Result<Vec<Bytes>, ResyncReason> {
+        let last_id = match last_id {
+            None => return Ok(Vec::new()),
+            Some(id) => id.parse::<u64>().map_err(|_| ResyncReason::Unknown)?,
+        };
+        if last_id > self.last_id {
+            return Err(ResyncReason::Unknown);
+        }
+        let oldest = self.buffer.front().map_or(self.last_id + 1, |e| e.id);
+        if last_id + 1 < oldest {
+            return Err(ResyncReason::Expired);
+        }
+        Ok(self
+            .buffer
+            .iter()
+            .filter(|e| e.id > last_id)
+            .map(|e| e.frame.clone())
+            .collect())
+    }
+}
+
+/// There are `MAX_CHANNELS` already, and the name is not one of them
+struct TooManyChannels;
+
+/// All channels, shared by all workers
+#[derive(Default)]
+struct Channels(Mutex<HashMap<String, Channel>>);
+
+/// The channel called `name`, created if there is room for it
+fn channel<'a>(
+    channels: &'a mut HashMap<String, Channel>,
+    name: &str,
+) -> Result<&'a mut Channel, TooManyChannels> {
+    if !channels.contains_key(name) && channels.len() >= MAX_CHANNELS {
+        return Err(TooManyChannels);
+    }
+    Ok(channels.entry(name.to_owned()).or_default())
+}
+
+impl Channels {
+    fn publish(&self, name: &str, data: &str) -> Result<u64, TooManyChannels> {
+        let mut channels = self.0.lock().unwrap();
+        Ok(channel(&mut channels, name)?.publish(data))
+    }
+
+    /// Subscribes a client that has seen everything up to `last_id`.
+    ///
+    /// The replay is collected and the client subscribed under the same
+    /// lock, nothing published in between is missed or sent twice.
+    fn subscribe(
+        &self,
+        name: &str,
+        last_id: Option<&str>,
+    ) -> Result<(Vec<Bytes>, mpsc::Receiver<Bytes>), TooManyChannels> {
+        let mut channels = self.0.lock().unwrap();
+        let channel = channel(&mut channels, name)?;
+
+        let first = match channel.replay(last_id) {
+            Ok(replay) => {
+                log::info!(
+                    "{} after {:?}: replaying {} events",
+                    name,
+                    last_id,
+                    replay.len()
+                );
+                replay
+            }
+            Err(reason) => {
+                log::info!("{} after {:?}: can not be resumed", name, last_id);
+                // with the id of the last event, a later reconnect resumes
+                // from here
+                let data = serde_json::json!({ "reason": reason }).to_string();
+                vec![Event::new(channel.last_id, Some("resync"), &data).frame]
+            }
+        };
+        let (tx, rx) = mpsc::channel(CLIENT_QUEUE);
+        channel.subscribers.push(tx);
+        Ok((first, rx))
+    }
+}
+
+#[derive(Deserialize)]
+struct StreamParams {
+    /// End the stream after this many events, the client reconnects
+    close_after: Option<usize>,
+}
+
+async fn events(
+    req: HttpRequest,
+    channel: web::types::Path<String>,
+    params: web::types::Query<StreamParams>,
+    channels: web::types::Data<Channels>,
+) -> HttpResponse {
+    let last_id = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|v| v.to_str().ok());
+    let (first, live) = match channels.subscribe(&channel, last_id) {
+        Ok(subscription) => subscription,
+        Err(TooManyChannels) => return too_many_channels(),
+    };
+
+    let events = stream::iter(first).chain(live);
+    let events = match params.close_after {
+        Some(n) => events.take(n).left_stream(),
+        None => events.right_stream(),
+    };
+    // reconnect after a second instead of the browser's default of 3
+    let retry = Bytes::from_static(b"retry: 1000\n\n");
+    let body = stream::iter(Some(retry)).chain(events).map(Ok::<_, Error>);
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .no_chunking()
+        .streaming(body)
+}
+
+async fn publish(
+    channel: web::types::Path<String>,
+    body: String,
+    channels: web::types::Data<Channels>,
+) -> HttpResponse {
+    match channels.publish(&channel, &body) {
+        Ok(id) => HttpResponse::Ok().json(&serde_json::json!({ "id": id })),
+        Err(TooManyChannels) => too_many_channels(),
+    }
+}
+
+fn too_many_channels() -> HttpResponse {
+    HttpResponse::ServiceUnavailable().json(&serde_json::json!({
+        "error": format!("there are {} channels already", MAX_CHANNELS)
+    }))
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,sse_resume=info");
+    env_logger::init();
+
+    let channels = web::types::Data::new(Channels::default());
+
+    // something to watch without publishing by hand
+    let clock = channels.clone();
+    ntex::rt::spawn(async move {
+        let mut interval = ntex::rt::time::interval(CLOCK_INTERVAL);
+        let mut n = 0u64;
+        loop {
+            interval.tick().await;
+            n += 1;
+            // the first publish, there is room for the channel
+            let _ = clock.publish("clock", &format!("tick {}", n));
+        }
+    });
+
+    web::server(move || {
+        App::new()
+            .app_data(channels.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .service(
+                web::resource("/events/{channel}")
+                    .route(web::get().to(events))
+                    .route(web::post().to(publish)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
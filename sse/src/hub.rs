@@ -0,0 +1,102 @@
+//! Fans events out to every connected client.
+//!
+//! `Hub::publish` encodes an event once and sends the `Bytes` into a
+//! `broadcast` channel, each client's response body is a receiver of it.
+//!
+//! The channel holds the last `capacity` events. Publishing never waits
+//! for the clients, a client that falls further behind than that, because
+//! it reads slowly or the network is, skips the oldest events and gets a
+//! `lagged` event with the number it missed. One slow client can't hold
+//! up the others, or fill the memory.
+//!
+//! When a client goes away ntex drops its body, and with it the receiver,
+//! the channel stops keeping events for it.
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use futures::Stream;
+use ntex::web::Error;
+use tokio::sync::broadcast::{self, RecvError};
+
+pub struct Hub {
+    sender: broadcast::Sender<Bytes>,
+    ids: AtomicU64,
+    client_ids: AtomicU64,
+}
+
+/// One event in the `text/event-stream` format
+fn encode(id: Option<u64>, event: &str, data: &serde_json::Value) -> Bytes {
+    let mut encoded = String::new();
+    if let Some(id) = id {
+        encoded.push_str(&format!("id: {}\n", id));
+    }
+    // serde_json's compact output has no newlines
+    encoded.push_str(&format!("event: {}\ndata: {}\n\n", event, data));
+    encoded.into()
+}
+
+impl Hub {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Hub {
+            sender,
+            ids: AtomicU64::new(1),
+            client_ids: AtomicU64::new(0),
+        }
+    }
+
+    /// Sends an event to every client, returns how many there are
+    pub fn publish(&self, event: &str, data: &serde_json::Value) -> usize {
+        let id = self.ids.fetch_add(1, Ordering::Relaxed);
+        // an error only means nobody is listening
+        self.sender.send(encode(Some(id), event, data)).unwrap_or(0)
+    }
+
+    pub fn clients(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    /// The events from now on, as a response body
+    pub fn subscribe(
+        &self,
+    ) -> impl Stream<Item = Result<Bytes, Error>> + Unpin + 'static {
+        let client = Client {
+            id: self.client_ids.fetch_add(1, Ordering::Relaxed) + 1,
+            receiver: self.sender.subscribe(),
+        };
+        log::info!("client {} connected, {} now", client.id, self.clients());
+        let hello = encode(None, "hello", &serde_json::json!({ "client": client.id }));
+
+        Box::pin(futures::stream::unfold(
+            (client, Some(hello)),
+            |(mut client, first)| async move {
+                if let Some(first) = first {
+                    return Some((Ok(first), (client, None)));
+                }
+                let event = match client.receiver.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        log::warn!("client {} missed {} events", client.id, missed);
+                        encode(None, "lagged", &serde_json::json!({ "missed": missed }))
+                    }
+                    // the hub is gone, the server is shutting down
+                    Err(RecvError::Closed) => return None,
+                };
+                Some((Ok(event), (client, None)))
+            },
+        ))
+    }
+}
+
+/// A client's receiver, dropped with the response body when the client
+/// disconnects
+struct Client {
+    id: u64,
+    receiver: broadcast::Receiver<Bytes>,
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        log::info!("client {} disconnected", self.id);
+    }
+}
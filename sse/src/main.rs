@@ -0,0 +1,87 @@
+//! A background task publishes a price every second, `GET /events` streams
+//! them to every client as server-sent events, see `hub`. `POST /publish`
+//! adds events of its own.
+use std::time::Duration;
+
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpResponse};
+use rand::Rng;
+use serde::Deserialize;
+
+mod hub;
+
+use hub::Hub;
+
+/// Events kept for clients that are behind
+const CAPACITY: usize = 16;
+
+async fn events(hub: web::types::Data<Hub>) -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .streaming(hub.subscribe())
+}
+
+#[derive(Deserialize)]
+struct Publish {
+    message: String,
+    /// How often to send it, more than `CAPACITY` at once overruns every
+    /// client
+    #[serde(default = "one")]
+    times: usize,
+}
+
+fn one() -> usize {
+    1
+}
+
+async fn publish(
+    hub: web::types::Data<Hub>,
+    publish: web::types::Json<Publish>,
+) -> HttpResponse {
+    let mut clients = 0;
+    for _ in 0..publish.times.min(1000) {
+        clients = hub.publish("message", &serde_json::json!(publish.message));
+    }
+    HttpResponse::Ok().json(&serde_json::json!({ "clients": clients }))
+}
+
+/// A random walk, published whether anybody listens or not
+async fn ticker(hub: web::types::Data<Hub>) {
+    let mut price = 100.0_f64;
+    let mut interval = ntex::rt::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        price = (price + rand::thread_rng().gen_range(-1.0, 1.0)).max(1.0);
+        let price = (price * 100.0).round() / 100.0;
+        hub.publish(
+            "price",
+            &serde_json::json!({ "symbol": "NTX", "price": price }),
+        );
+    }
+}
+
+async fn stats(hub: web::types::Data<Hub>) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "clients": hub.clients() }))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,sse=info");
+    env_logger::init();
+
+    let hub = web::types::Data::new(Hub::new(CAPACITY));
+    ntex::rt::spawn(ticker(hub.clone()));
+
+    web::server(move || {
+        App::new()
+            .app_data(hub.clone())
+            .wrap(middleware::Logger::default())
+            .route("/events", web::get().to(events))
+            .route("/publish", web::post().to(publish))
+            .route("/stats", web::get().to(stats))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,77 @@
+//! The counters of the `state` example, one more time, with the global one
+//! in Redis. A `Mutex` or an atomic is shared by the workers of one process
+//! and starts at zero with it. A Redis counter is shared by every process
+//! that talks to the same Redis, and is still there after a restart.
+//!
+//! `INCR` adds one and returns the new value in one step, two processes
+//! counting at the same time never lose a count.
+//!
+//! All workers share one `ConnectionManager`. A clone is a handle to the
+//! same connection, which is opened again if it drops.
+use std::cell::Cell;
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ntex::web::{self, middleware, App, HttpResponse};
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+
+const KEY: &str = "state-redis:counter";
+
+async fn index(
+    redis: web::types::Data<ConnectionManager>,
+    process: web::types::Data<AtomicUsize>,
+    worker: web::types::Data<Cell<u32>>,
+) -> HttpResponse {
+    let global: u64 = match redis.get_ref().clone().incr(KEY, 1).await {
+        Ok(value) => value,
+        Err(e) => {
+            log::error!("redis: {}", e);
+            return HttpResponse::ServiceUnavailable()
+                .json(&serde_json::json!({"error": format!("redis: {}", e)}));
+        }
+    };
+    let process = process.fetch_add(1, Ordering::SeqCst) + 1;
+    worker.set(worker.get() + 1);
+
+    HttpResponse::Ok().content_type("text/plain").body(format!(
+        "redis counter: {}, process counter: {}, worker counter: {} (pid {})",
+        global,
+        process,
+        worker.get(),
+        std::process::id()
+    ))
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,state_redis=info");
+    env_logger::init();
+
+    let url =
+        std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1/".to_owned());
+    let bind = std::env::var("BIND").unwrap_or_else(|_| "127.0.0.1:8080".to_owned());
+
+    let client = redis::Client::open(url.as_str())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    let conn = client
+        .get_tokio_connection_manager()
+        .await
+        .map_err(|e| io::Error::other(format!("redis at {}: {}", url, e)))?;
+    log::info!("counting in redis at {}", url);
+
+    let redis = web::types::Data::new(conn);
+    let process = web::types::Data::new(AtomicUsize::new(0));
+
+    web::server(move || {
+        App::new()
+            .app_data(redis.clone())
+            .app_data(process.clone())
+            .data(Cell::new(0u32))
+            .wrap(middleware::Logger::default())
+            .service(web::resource("/").to(index))
+    })
+    .bind(bind)?
+    .run()
+    .await
+}
@@ -0,0 +1,44 @@
+//! The handler of the state example and the part of the app that is the
+//! same for every worker, in a library so that other crates, `testing` for
+//! one, can build the same app.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use ntex::web::{self, HttpRequest, HttpResponse};
+
+/// simple handle
+async fn index(
+    counter1: web::types::Data<Mutex<usize>>,
+    counter2: web::types::Data<Cell<u32>>,
+    counter3: web::types::Data<AtomicUsize>,
+    req: HttpRequest,
+) -> HttpResponse {
+    println!("{:?}", req);
+
+    // Increment the counters
+    *counter1.lock().unwrap() += 1;
+    counter2.set(counter2.get() + 1);
+    counter3.fetch_add(1, Ordering::SeqCst);
+
+    let body = format!(
+        "global mutex counter: {}, local counter: {}, global atomic counter: {}",
+        *counter1.lock().unwrap(),
+        counter2.get(),
+        counter3.load(Ordering::SeqCst),
+    );
+    HttpResponse::Ok().body(body)
+}
+
+/// Adds the thread-local state and registers the handler. The global state,
+/// `Data<Mutex<usize>>` and `Data<AtomicUsize>`, has to be added by the
+/// caller.
+pub fn config(cfg: &mut web::ServiceConfig) {
+    // Create some thread-local state
+    let counter2 = Cell::new(0u32);
+
+    cfg.data(counter2) // add thread-local state
+        // register simple handler
+        .service(web::resource("/").to(index));
+}
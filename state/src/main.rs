@@ -18,35 +18,11 @@
 //!
 //! Check [user guide](https://actix.rs/docs/application/#state) for more info.
 
-use std::cell::Cell;
 use std::io;
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::AtomicUsize;
 use std::sync::Mutex;
 
-use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
-
-/// simple handle
-async fn index(
-    counter1: web::types::Data<Mutex<usize>>,
-    counter2: web::types::Data<Cell<u32>>,
-    counter3: web::types::Data<AtomicUsize>,
-    req: HttpRequest,
-) -> HttpResponse {
-    println!("{:?}", req);
-
-    // Increment the counters
-    *counter1.lock().unwrap() += 1;
-    counter2.set(counter2.get() + 1);
-    counter3.fetch_add(1, Ordering::SeqCst);
-
-    let body = format!(
-        "global mutex counter: {}, local counter: {}, global atomic counter: {}",
-        *counter1.lock().unwrap(),
-        counter2.get(),
-        counter3.load(Ordering::SeqCst),
-    );
-    HttpResponse::Ok().body(body)
-}
+use ntex::web::{self, middleware, App};
 
 #[ntex::main]
 async fn main() -> io::Result<()> {
@@ -60,17 +36,13 @@ async fn main() -> io::Result<()> {
 
     // move is necessary to give closure below ownership of counter1
     web::server(move || {
-        // Create some thread-local state
-        let counter2 = Cell::new(0u32);
-
         App::new()
             .app_data(counter1.clone()) // add shared state
             .app_data(counter3.clone()) // add shared state
-            .data(counter2) // add thread-local state
             // enable logger
             .wrap(middleware::Logger::default())
-            // register simple handler
-            .service(web::resource("/").to(index))
+            // thread-local state and the handler, see `lib.rs`
+            .configure(state::config)
     })
     .bind("127.0.0.1:8080")?
     .run()
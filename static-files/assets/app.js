@@ -0,0 +1,27 @@
+// A client side router: every path is served index.html, the path picks
+// what to show.
+const routes = [
+  [/^\/$/, () => "Home"],
+  [/^\/about$/, () => "About this example"],
+  [/^\/orders\/(\d+)$/, (id) => `Order ${id}`],
+];
+
+function render() {
+  const path = location.pathname;
+  const route = routes.find(([pattern]) => pattern.test(path));
+  const view = document.getElementById("view");
+  view.textContent = route
+    ? route[1](...path.match(route[0]).slice(1))
+    : `Nothing at ${path}`;
+}
+
+document.addEventListener("click", (event) => {
+  const link = event.target.closest("a");
+  if (link && link.origin === location.origin) {
+    event.preventDefault();
+    history.pushState(null, "", link.pathname);
+    render();
+  }
+});
+window.addEventListener("popstate", render);
+render();
@@ -0,0 +1,353 @@
+//! A service that serves a directory, registered like `ntex-files`' `Files`.
+//!
+//! * `ETag` and `Last-Modified` on every file, `If-None-Match` and
+//!   `If-Modified-Since` are answered with `304`
+//! * `app.js.gz` next to `app.js` is sent instead, with
+//!   `Content-Encoding: gzip`, to clients that accept gzip. The files are
+//!   compressed once, ahead of time, and each variant has its own `ETag`
+//! * with `spa_fallback`, a path that has no file and no extension gets the
+//!   fallback instead, the client side router takes it from there. A
+//!   missing `.js` or `.png` stays a `404`, a script tag doesn't get html
+use std::fs::{File, Metadata};
+use std::io::{self, Read};
+use std::marker::PhantomData;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header::{self, HeaderMap};
+use ntex::http::{Method, StatusCode};
+use ntex::router::ResourceDef;
+use ntex::web::dev::{WebRequest, WebResponse, WebServiceConfig, WebServiceFactory};
+use ntex::web::error::BlockingError;
+use ntex::web::{self, ErrorRenderer, HttpResponse};
+use ntex::{Service, ServiceFactory};
+
+/// Bytes read from a file at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+struct Config {
+    mount: String,
+    dir: PathBuf,
+    fallback: Option<PathBuf>,
+    max_age: u32,
+}
+
+pub struct StaticFiles {
+    config: Config,
+}
+
+impl StaticFiles {
+    /// Serves `dir` under the path `mount`
+    pub fn new(mount: &str, dir: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            config: Config {
+                mount: mount.trim_end_matches('/').to_owned(),
+                dir: dir.into(),
+                fallback: None,
+                max_age: 3600,
+            },
+        }
+    }
+
+    /// The file, relative to the directory, for paths without a file
+    pub fn spa_fallback(mut self, file: &str) -> Self {
+        self.config.fallback = Some(PathBuf::from(file));
+        self
+    }
+
+    /// How long the files may be cached, html is always revalidated
+    pub fn max_age(mut self, secs: u32) -> Self {
+        self.config.max_age = secs;
+        self
+    }
+}
+
+impl<Err: ErrorRenderer> WebServiceFactory<Err> for StaticFiles {
+    fn register(self, config: &mut WebServiceConfig<Err>) {
+        // everything below the mount point, like a scope, an empty prefix
+        // would only match the mount point itself
+        let rdef = match self.config.mount.as_str() {
+            "" => ResourceDef::prefix("/"),
+            mount => ResourceDef::root_prefix(mount),
+        };
+        let factory = FilesFactory(Arc::new(self.config), PhantomData);
+        config.register_service(rdef, None, factory, None)
+    }
+}
+
+pub struct FilesFactory<Err>(Arc<Config>, PhantomData<Err>);
+
+impl<Err: ErrorRenderer> ServiceFactory for FilesFactory<Err> {
+    type Config = ();
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Err::Container;
+    type InitError = ();
+    type Service = FilesService<Err>;
+    type Future = Ready<Result<Self::Service, ()>>;
+
+    fn new_service(&self, _: ()) -> Self::Future {
+        ok(FilesService(self.0.clone(), PhantomData))
+    }
+}
+
+pub struct FilesService<Err>(Arc<Config>, PhantomData<Err>);
+
+/// The path below the directory, `None` for anything that could leave it
+/// or reach a hidden file
+fn relative_path(path: &str) -> Option<PathBuf> {
+    let decoded = percent_encoding::percent_decode_str(path)
+        .decode_utf8()
+        .ok()?;
+    let mut relative = PathBuf::new();
+    for segment in decoded.split('/').filter(|s| !s.is_empty()) {
+        if segment.starts_with('.') || segment.contains('\\') || segment.contains('\0') {
+            return None;
+        }
+        relative.push(segment);
+    }
+    // on windows a segment like `c:` is a prefix
+    if relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        Some(relative)
+    } else {
+        None
+    }
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "application/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("ico") => "image/x-icon",
+        Some("wasm") => "application/wasm",
+        Some("woff2") => "font/woff2",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn accepts_gzip(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|coding| {
+            let mut parts = coding.split(';').map(str::trim);
+            parts.next() == Some("gzip") && !parts.any(|p| p == "q=0" || p == "q=0.0")
+        })
+}
+
+/// A file to send, with what describes it
+struct Found {
+    file: File,
+    path: PathBuf,
+    len: u64,
+    /// Truncated to seconds, like the `Last-Modified` header
+    modified: SystemTime,
+    gzip: bool,
+}
+
+impl Found {
+    fn etag(&self) -> String {
+        let secs = self.modified.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let suffix = if self.gzip { "-gz" } else { "" };
+        format!("\"{:x}-{:x}{}\"", self.len, secs, suffix)
+    }
+
+    /// Whether the copy the client has is still this one, `If-None-Match`
+    /// wins over `If-Modified-Since`
+    fn not_modified(&self, headers: &HeaderMap) -> bool {
+        if let Some(tags) = headers.get(header::IF_NONE_MATCH) {
+            let etag = self.etag();
+            // the comparison is weak, `W/"x"` matches `"x"`
+            return tags.to_str().is_ok_and(|tags| {
+                tags.split(',')
+                    .map(str::trim)
+                    .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+            });
+        }
+        headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| httpdate::parse_http_date(v.to_str().ok()?).ok())
+            .is_some_and(|since| self.modified <= since)
+    }
+}
+
+fn open(path: &Path) -> io::Result<(File, Metadata)> {
+    let file = File::open(path)?;
+    let meta = file.metadata()?;
+    Ok((file, meta))
+}
+
+/// The file for `relative`, the gzip variant if it exists and can be sent,
+/// `index.html` for a directory, the fallback when there is nothing
+fn find(config: &Config, relative: PathBuf, gzip: bool) -> io::Result<Option<Found>> {
+    let mut path = config.dir.join(&relative);
+    if path.is_dir() {
+        path.push("index.html");
+    }
+    let path = if path.is_file() {
+        path
+    } else {
+        match &config.fallback {
+            Some(fallback) if relative.extension().is_none() => {
+                config.dir.join(fallback)
+            }
+            _ => return Ok(None),
+        }
+    };
+
+    let (file, meta) = match open(&path) {
+        Ok(opened) => opened,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    // a variant older than the file was made from an older version of it
+    let gz = path.with_file_name(format!(
+        "{}.gz",
+        path.file_name().unwrap().to_string_lossy()
+    ));
+    let variant = if gzip { open(&gz).ok() } else { None };
+    let (file, meta, gzip) = match variant {
+        Some((gz_file, gz_meta))
+            if gz_meta.is_file() && gz_meta.modified()? >= meta.modified()? =>
+        {
+            (gz_file, gz_meta, true)
+        }
+        _ => (file, meta, false),
+    };
+    let secs = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(Some(Found {
+        file,
+        path,
+        len: meta.len(),
+        modified: UNIX_EPOCH + Duration::from_secs(secs),
+        gzip,
+    }))
+}
+
+fn read_chunks(
+    file: File,
+    len: u64,
+) -> impl futures::Stream<Item = io::Result<Bytes>> + Unpin {
+    Box::pin(futures::stream::try_unfold(
+        (file, len),
+        |(mut file, left)| async move {
+            if left == 0 {
+                return Ok(None);
+            }
+            let size = left.min(CHUNK_SIZE as u64) as usize;
+            let (file, chunk) = web::block(move || {
+                let mut chunk = vec![0; size];
+                file.read_exact(&mut chunk)?;
+                Ok((file, chunk))
+            })
+            .await
+            .map_err(|e| match e {
+                BlockingError::Error(e) => e,
+                BlockingError::Canceled => io::Error::other("thread pool is gone"),
+            })?;
+            Ok(Some((Bytes::from(chunk), (file, left - size as u64))))
+        },
+    ))
+}
+
+impl<Err: ErrorRenderer> Service for FilesService<Err> {
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Err::Container;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Self::Error>>;
+
+    fn poll_ready(&self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let config = self.0.clone();
+        async move {
+            let res = respond(config, &req).await;
+            Ok(req.into_response(res))
+        }
+        .boxed_local()
+    }
+}
+
+async fn respond<Err>(config: Arc<Config>, req: &WebRequest<Err>) -> HttpResponse {
+    let head = match *req.method() {
+        Method::GET => false,
+        Method::HEAD => true,
+        _ => {
+            return HttpResponse::MethodNotAllowed()
+                .header(header::ALLOW, "GET, HEAD")
+                .finish()
+        }
+    };
+    let relative = match relative_path(req.match_info().unprocessed()) {
+        Some(relative) => relative,
+        None => return HttpResponse::NotFound().finish(),
+    };
+    let gzip = accepts_gzip(req.headers());
+    let found = {
+        let config = config.clone();
+        web::block(move || find(&config, relative, gzip)).await
+    };
+    let found = match found {
+        Ok(Some(found)) => found,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => {
+            log::error!("can not serve {}: {}", req.path(), e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let not_modified = found.not_modified(req.headers());
+    let mut res = if not_modified {
+        HttpResponse::build(StatusCode::NOT_MODIFIED)
+    } else {
+        HttpResponse::build(StatusCode::OK)
+    };
+    // html points to the other files, it has to be checked every time
+    let cache_control = if found.path.extension().is_some_and(|ext| ext == "html") {
+        "no-cache".to_owned()
+    } else {
+        format!("public, max-age={}", config.max_age)
+    };
+    res.header(header::ETAG, found.etag())
+        .header(
+            header::LAST_MODIFIED,
+            httpdate::fmt_http_date(found.modified),
+        )
+        .header(header::CACHE_CONTROL, cache_control)
+        // a cache must not hand the gzip variant to a client without gzip
+        .header(header::VARY, "Accept-Encoding");
+    if not_modified {
+        return res.finish();
+    }
+
+    res.content_type(content_type(&found.path))
+        .no_chunking()
+        .header(header::CONTENT_LENGTH, found.len);
+    if found.gzip {
+        res.header(header::CONTENT_ENCODING, "gzip");
+    }
+    // `finish()` would replace the length with 0, an empty stream keeps it,
+    // the body of a HEAD response is dropped anyway
+    let len = if head { 0 } else { found.len };
+    res.streaming(read_chunks(found.file, len))
+}
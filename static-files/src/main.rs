@@ -0,0 +1,31 @@
+//! Serves the single page app in `assets/` with `StaticFiles`, see `files`.
+//! `/api` is handled before it, every other path is a file or the app.
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod files;
+
+use files::StaticFiles;
+
+async fn status() -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({ "status": "ok" }))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,static_files=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/api/status", web::get().to(status))
+            .service(
+                StaticFiles::new("/", "assets")
+                    .spa_fallback("index.html")
+                    .max_age(24 * 3600),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
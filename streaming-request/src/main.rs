@@ -0,0 +1,266 @@
+//! Processing a request body while it arrives.
+//!
+//! `POST /digest` hashes and counts the body chunk by chunk, every chunk is
+//! dropped as soon as it is processed. The memory used doesn't depend on the
+//! size of the upload, a multi-gigabyte body needs the same few buffers as a
+//! small one, because ntex stops reading from the socket while the handler
+//! is busy with a chunk.
+//!
+//! A client that goes away mid-upload doesn't always end the body stream
+//! with an error, a vanished client or a half-closed connection just stops
+//! sending. Each chunk has to arrive within `idle_timeout`, otherwise the
+//! upload is given up.
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use derive_more::Display;
+use futures::{Stream, StreamExt};
+use ntex::http::error::PayloadError;
+use ntex::http::header;
+use ntex::rt::time::timeout;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Default for `MAX_UPLOAD_SIZE`, 8 GiB
+const MAX_UPLOAD_SIZE: u64 = 8 * 1024 * 1024 * 1024;
+/// How long to wait for the next chunk
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct Config {
+    max_size: u64,
+    idle_timeout: Duration,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct Summary {
+    bytes: u64,
+    /// like `wc -l`, plus a last line without a newline
+    lines: u64,
+    sha256: String,
+}
+
+#[derive(Debug, Display)]
+enum UploadError {
+    #[display(fmt = "body is larger than {} bytes", _0)]
+    TooLarge(u64),
+    #[display(fmt = "body ended after {} bytes: {}", _0, _1)]
+    Aborted(u64, PayloadError),
+    #[display(fmt = "no data for {:?} after {} bytes", _1, _0)]
+    Stalled(u64, Duration),
+}
+
+impl WebResponseError for UploadError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let body = serde_json::json!({ "error": self.to_string() });
+        match self {
+            UploadError::TooLarge(_) => HttpResponse::PayloadTooLarge().json(&body),
+            UploadError::Aborted(..) | UploadError::Stalled(..) => {
+                HttpResponse::BadRequest().json(&body)
+            }
+        }
+    }
+}
+
+/// Running hash and line count
+#[derive(Default)]
+struct Counter {
+    hasher: Sha256,
+    bytes: u64,
+    newlines: u64,
+    ends_with_newline: bool,
+}
+
+impl Counter {
+    fn update(&mut self, chunk: &[u8]) {
+        self.hasher.update(chunk);
+        self.bytes += chunk.len() as u64;
+        self.newlines += chunk.iter().filter(|b| **b == b'\n').count() as u64;
+        if let Some(last) = chunk.last() {
+            self.ends_with_newline = *last == b'\n';
+        }
+    }
+
+    fn finish(self) -> Summary {
+        let unterminated = self.bytes > 0 && !self.ends_with_newline;
+        Summary {
+            bytes: self.bytes,
+            lines: self.newlines + unterminated as u64,
+            sha256: hex::encode(self.hasher.finalize()),
+        }
+    }
+}
+
+/// Consume the body, stopping as soon as it grows over `max_size`
+async fn digest<S>(mut body: S, config: &Config) -> Result<Summary, UploadError>
+where
+    S: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    let mut counter = Counter::default();
+    loop {
+        let chunk = match timeout(config.idle_timeout, body.next()).await {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => break,
+            Err(_) => {
+                return Err(UploadError::Stalled(counter.bytes, config.idle_timeout))
+            }
+        };
+        // an error here usually means the client went away mid-upload
+        let chunk = chunk.map_err(|e| UploadError::Aborted(counter.bytes, e))?;
+        if counter.bytes + chunk.len() as u64 > config.max_size {
+            return Err(UploadError::TooLarge(config.max_size));
+        }
+        counter.update(&chunk);
+    }
+    Ok(counter.finish())
+}
+
+async fn upload(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    config: web::types::Data<Config>,
+) -> Result<HttpResponse, UploadError> {
+    // reject announced oversized bodies before reading anything, chunked
+    // bodies are checked while they are read
+    let length = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u64>().ok());
+    if matches!(length, Some(length) if length > config.max_size) {
+        return Err(UploadError::TooLarge(config.max_size));
+    }
+
+    let started = Instant::now();
+    match digest(payload, &config).await {
+        Ok(summary) => {
+            log::info!(
+                "digested {} bytes in {:.1?}",
+                summary.bytes,
+                started.elapsed()
+            );
+            Ok(HttpResponse::Ok().json(&summary))
+        }
+        Err(e) => {
+            // for an aborted upload nobody is there to read the response
+            log::warn!("upload failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config.route("/digest", web::post().to(upload));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,streaming_request=info");
+    env_logger::init();
+
+    let max_size = std::env::var("MAX_UPLOAD_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(MAX_UPLOAD_SIZE);
+    let config = web::types::Data::new(Config {
+        max_size,
+        idle_timeout: IDLE_TIMEOUT,
+    });
+
+    web::server(move || {
+        App::new()
+            .app_data(config.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    fn config(max_size: u64) -> Config {
+        Config {
+            max_size,
+            idle_timeout: Duration::from_millis(100),
+        }
+    }
+
+    fn chunks(chunks: &[&'static str]) -> Vec<Result<Bytes, PayloadError>> {
+        chunks
+            .iter()
+            .map(|c| Ok(Bytes::from_static(c.as_bytes())))
+            .collect()
+    }
+
+    #[ntex::test]
+    async fn test_digest_chunks() {
+        // lines split across chunks
+        let body = stream::iter(chunks(&["hel", "lo\nwor", "ld\n", "no newline"]));
+        let summary = digest(body, &config(1024)).await.unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                bytes: 22,
+                lines: 3,
+                sha256:
+                    "77b3c6785d14d3136784adf9c43f3689c3425671006c73043f67c1fb0068635e"
+                        .to_owned(),
+            }
+        );
+
+        let summary = digest(stream::iter(Vec::new()), &config(1024))
+            .await
+            .unwrap();
+        assert_eq!((summary.bytes, summary.lines), (0, 0));
+    }
+
+    #[ntex::test]
+    async fn test_aborted_and_too_large() {
+        let mut body = chunks(&["first chunk\n"]);
+        body.push(Err(PayloadError::Incomplete(None)));
+        let err = digest(stream::iter(body), &config(1024)).await.unwrap_err();
+        assert!(matches!(err, UploadError::Aborted(12, _)));
+
+        // stops at the chunk that crosses the limit
+        let body = stream::iter(chunks(&["12345", "67890", "never read"]));
+        let err = digest(body, &config(8)).await.unwrap_err();
+        assert!(matches!(err, UploadError::TooLarge(8)));
+
+        // the client stopped sending, without closing the stream
+        let body = stream::iter(chunks(&["first chunk\n"])).chain(stream::pending());
+        let err = digest(body, &config(1024)).await.unwrap_err();
+        assert!(matches!(err, UploadError::Stalled(12, _)));
+    }
+
+    #[ntex::test]
+    async fn test_upload() {
+        let srv = test::server(|| {
+            App::new()
+                .app_data(web::types::Data::new(config(16)))
+                .configure(app_config)
+        });
+
+        let mut res = srv.post("/digest").send_body("a\nb\n").await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let summary: serde_json::Value =
+            serde_json::from_slice(&res.body().await.unwrap()).unwrap();
+        assert_eq!(summary["lines"], 2);
+
+        // announced by Content-Length
+        let res = srv.post("/digest").send_body("x".repeat(17)).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // chunked, only noticed while reading
+        let body = stream::iter(vec!["0123456789"; 3])
+            .map(|c| Ok::<_, PayloadError>(Bytes::from(c)));
+        let res = srv.post("/digest").send_stream(body).await.unwrap();
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
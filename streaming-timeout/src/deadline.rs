@@ -0,0 +1,78 @@
+//! An overall deadline for a streaming body.
+//!
+//! `Deadline` passes the items of a stream through until the deadline, then
+//! ends it with one last item saying so. It only ever ends the stream
+//! between two items, so as long as every item is a complete record, the
+//! client never gets half of one. A record split over several items could
+//! be cut in the middle, producers have to yield whole records.
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::{Future, Stream};
+use ntex::rt::time::{delay_for, Delay};
+
+pub struct Deadline<S, F> {
+    inner: S,
+    delay: Pin<Box<Delay>>,
+    /// Builds the last item from the number of items sent, taken when the
+    /// deadline passes
+    on_expiry: Option<F>,
+    sent: usize,
+    done: bool,
+}
+
+impl<S, F> Deadline<S, F> {
+    pub fn new(inner: S, deadline: Duration, on_expiry: F) -> Self {
+        Deadline {
+            inner,
+            delay: Box::pin(delay_for(deadline)),
+            on_expiry: Some(on_expiry),
+            sent: 0,
+            done: false,
+        }
+    }
+}
+
+impl<S, F, E> Stream for Deadline<S, F>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    F: FnOnce(usize) -> Bytes + Unpin,
+{
+    type Item = Result<Bytes, E>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+
+        // checked first, a producer that always has something ready is cut
+        // off all the same
+        if this.delay.as_mut().poll(cx).is_ready() {
+            this.done = true;
+            log::warn!(
+                "deadline passed after {} records, ending the stream",
+                this.sent
+            );
+            let last = (this.on_expiry.take().unwrap())(this.sent);
+            return Poll::Ready(Some(Ok(last)));
+        }
+
+        match Pin::new(&mut this.inner).poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                this.sent += 1;
+                Poll::Ready(Some(item))
+            }
+            Poll::Ready(None) => {
+                this.done = true;
+                Poll::Ready(None)
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::stream::{self, StreamExt};
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, Error, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+mod deadline;
+
+use deadline::Deadline;
+
+/// Default for `deadline_ms`
+const DEADLINE: Duration = Duration::from_secs(2);
+/// Most `deadline_ms` and `delay_ms` can be, a timer far enough out
+/// overflows the clock
+const MAX_MS: u64 = 60_000;
+
+#[derive(Deserialize)]
+struct ReportQuery {
+    rows: Option<u32>,
+    /// How long the producer takes per row
+    delay_ms: Option<u64>,
+    deadline_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct Row {
+    id: u32,
+    value: u64,
+}
+
+/// A newline delimited JSON report from a slow producer. Each row is one
+/// chunk, the deadline can only fall between two rows
+async fn report(query: web::types::Query<ReportQuery>) -> HttpResponse {
+    if query
+        .delay_ms
+        .max(query.deadline_ms)
+        .is_some_and(|ms| ms > MAX_MS)
+    {
+        return HttpResponse::BadRequest().json(&serde_json::json!({
+            "error": format!("delay_ms and deadline_ms must be at most {}", MAX_MS)
+        }));
+    }
+    let rows = query.rows.unwrap_or(20);
+    let delay = Duration::from_millis(query.delay_ms.unwrap_or(300));
+    let deadline = query
+        .deadline_ms
+        .map(Duration::from_millis)
+        .unwrap_or(DEADLINE);
+
+    let producer = stream::unfold(0, move |id| async move {
+        if id == rows {
+            return None;
+        }
+        delay_for(delay).await;
+        let row = Row {
+            id,
+            value: u64::from(id) * u64::from(id),
+        };
+        let mut line = serde_json::to_vec(&row).unwrap();
+        line.push(b'\n');
+        Some((Ok::<_, Error>(Bytes::from(line)), id + 1))
+    });
+
+    let body = Deadline::new(producer.boxed_local(), deadline, |sent| {
+        let end = serde_json::json!({ "deadline_exceeded": true, "rows": sent });
+        Bytes::from(format!("{}\n", end))
+    });
+    HttpResponse::Ok()
+        .content_type("application/x-ndjson")
+        .streaming(body)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,streaming_timeout=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/report", web::get().to(report))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    fn lines(body: &[u8]) -> Vec<serde_json::Value> {
+        std::str::from_utf8(body)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[ntex::test]
+    async fn test_cut_off_at_deadline() {
+        let app =
+            test::init_service(App::new().route("/report", web::get().to(report))).await;
+
+        let req =
+            test::TestRequest::with_uri("/report?rows=10&delay_ms=50&deadline_ms=175")
+                .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // every line parses, the last one tells why the report is short
+        let lines = lines(&test::read_body(resp).await);
+        let (end, rows) = lines.split_last().unwrap();
+        assert!(rows.len() < 10);
+        assert_eq!(end["deadline_exceeded"], true);
+        assert_eq!(end["rows"], rows.len());
+        for (id, row) in rows.iter().enumerate() {
+            assert_eq!(row["id"], id);
+        }
+    }
+
+    #[ntex::test]
+    async fn test_within_deadline() {
+        let app =
+            test::init_service(App::new().route("/report", web::get().to(report))).await;
+
+        let req =
+            test::TestRequest::with_uri("/report?rows=3&delay_ms=10&deadline_ms=1000")
+                .to_request();
+        let resp = test::call_service(&app, req).await;
+        let lines = lines(&test::read_body(resp).await);
+        assert_eq!(lines.len(), 3);
+        assert!(lines
+            .iter()
+            .all(|line| line.get("deadline_exceeded").is_none()));
+    }
+
+    #[ntex::test]
+    async fn test_too_long() {
+        let app =
+            test::init_service(App::new().route("/report", web::get().to(report))).await;
+
+        for uri in &[
+            "/report?delay_ms=60001",
+            "/report?deadline_ms=18446744073709551615",
+        ] {
+            let req = test::TestRequest::with_uri(uri).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod swr;
+
+/// Simulated slow upstream
+#[derive(Default)]
+struct Upstream {
+    calls: AtomicUsize,
+    failing: AtomicBool,
+}
+
+/// Expensive to render, may be served from cache for 5 seconds and served
+/// stale for another 30 while it is refreshed
+async fn report(upstream: web::types::Data<Upstream>) -> HttpResponse {
+    ntex::rt::time::delay_for(Duration::from_secs(1)).await;
+
+    if upstream.failing.load(Ordering::Relaxed) {
+        return HttpResponse::BadGateway().body("upstream is down");
+    }
+    let n = upstream.calls.fetch_add(1, Ordering::Relaxed) + 1;
+    HttpResponse::Ok()
+        .header(
+            header::CACHE_CONTROL,
+            "max-age=5, stale-while-revalidate=30",
+        )
+        .body(format!("report #{}\n", n))
+}
+
+/// Toggle upstream failures, to see the stale entry being kept
+async fn toggle_upstream(upstream: web::types::Data<Upstream>) -> String {
+    let failing = !upstream.failing.load(Ordering::Relaxed);
+    upstream.failing.store(failing, Ordering::Relaxed);
+    format!("upstream failing: {}\n", failing)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,swr_cache=info");
+    env_logger::init();
+
+    let cache = web::types::Data::new(swr::Cache::new(1000));
+    let upstream = web::types::Data::new(Upstream::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(upstream.clone())
+            .wrap(swr::SwrCache::new(cache.clone(), "http://127.0.0.1:8080"))
+            .wrap(middleware::Logger::default())
+            .route("/report", web::get().to(report))
+            .route("/upstream/toggle", web::post().to(toggle_upstream))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,403 @@
+//! Response cache with `stale-while-revalidate` support.
+//!
+//! Responses to `GET` requests are cached according to their
+//! `Cache-Control: max-age=N, stale-while-revalidate=M` header. Within `N`
+//! seconds the cached copy is served as is. For the following `M` seconds it
+//! is still served right away, but a background request refreshes the entry.
+//! Only one refresh per entry runs at a time, and a failed refresh keeps the
+//! stale entry until its window is over. Misses are coalesced the same way:
+//! while one request for a key is passed to the handler, the others for it
+//! wait and get what it stored.
+//!
+//! The cache holds a fixed number of responses, the least recently used
+//! goes first, the query string is part of the key and anyone can make new
+//! ones.
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use bytes::{Bytes, BytesMut};
+use futures::channel::oneshot;
+use futures::future::{ok, poll_fn, FutureExt, LocalBoxFuture, Ready};
+use lru::LruCache;
+use ntex::http::body::{Body, MessageBody, ResponseBody};
+use ntex::http::client::Client;
+use ntex::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use ntex::http::{Method, StatusCode};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error, HttpResponse};
+use ntex::{Service, Transform};
+
+/// Marks the background request that refreshes an entry
+const REVALIDATE: &str = "x-swr-revalidate";
+const X_CACHE: &str = "x-cache";
+
+struct Entry {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    stored: Instant,
+    max_age: Duration,
+    stale_while_revalidate: Duration,
+    revalidating: bool,
+}
+
+enum Lookup {
+    Fresh(HttpResponse),
+    /// the flag tells whether this request should start the refresh
+    Stale(HttpResponse, bool),
+    /// This request answers the miss, the flag tells whether the others for
+    /// the key wait for it
+    Miss(bool),
+    /// Another request is answering the miss, `true` once it stored a response
+    Pending(oneshot::Receiver<bool>),
+}
+
+/// Cached responses by path and query, shared by all workers
+pub struct Cache {
+    entries: Mutex<LruCache<String, Entry>>,
+    /// Keys of the misses being answered, with the requests waiting for them
+    loading: Mutex<HashMap<String, Vec<oneshot::Sender<bool>>>>,
+    capacity: usize,
+}
+
+impl Cache {
+    /// Holds up to `capacity` responses, and coalesces up to as many misses
+    pub fn new(capacity: usize) -> Self {
+        Cache {
+            entries: Mutex::new(LruCache::new(capacity)),
+            loading: Mutex::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    fn lookup(&self, key: &str) -> Lookup {
+        // lru 0.6 can only be looked up by the key type itself
+        let key = key.to_owned();
+        let mut entries = self.entries.lock().unwrap();
+        let entry = match entries.get_mut(&key) {
+            Some(entry) => entry,
+            None => return self.miss(&key),
+        };
+
+        let age = entry.stored.elapsed();
+        if age < entry.max_age {
+            Lookup::Fresh(entry.response(age, "HIT"))
+        } else if age < entry.max_age + entry.stale_while_revalidate {
+            let revalidate = !entry.revalidating;
+            entry.revalidating = true;
+            Lookup::Stale(entry.response(age, "STALE"), revalidate)
+        } else {
+            entries.pop(&key);
+            self.miss(&key)
+        }
+    }
+
+    fn miss(&self, key: &str) -> Lookup {
+        let mut loading = self.loading.lock().unwrap();
+        let full = loading.len() >= self.capacity;
+        match loading.get_mut(key) {
+            Some(waiting) => {
+                let (tx, rx) = oneshot::channel();
+                waiting.push(tx);
+                Lookup::Pending(rx)
+            }
+            // too many at once, this one isn't waited for
+            None if full => Lookup::Miss(false),
+            None => {
+                loading.insert(key.to_owned(), Vec::new());
+                Lookup::Miss(true)
+            }
+        }
+    }
+
+    /// What a request that waited for a miss is answered with
+    fn get(&self, key: &str) -> Option<HttpResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get(&key.to_owned())?;
+        let age = entry.stored.elapsed();
+        if age < entry.max_age + entry.stale_while_revalidate {
+            Some(entry.response(age, "HIT"))
+        } else {
+            None
+        }
+    }
+
+    /// `false` when the response can't be cached
+    fn store(&self, key: String, res: &Response, body: Bytes) -> bool {
+        let cache_control = res
+            .headers()
+            .get(header::CACHE_CONTROL)
+            .and_then(|v| v.to_str().ok());
+        let (max_age, stale_while_revalidate) = match cache_control.map(parse) {
+            Some((Some(max_age), swr)) if res.status() == StatusCode::OK => {
+                (max_age, swr.unwrap_or_default())
+            }
+            // not cacheable, whatever is cached stays
+            _ => {
+                self.revalidation_failed(&key);
+                return false;
+            }
+        };
+
+        let mut headers = res.headers().clone();
+        headers.remove(header::CONTENT_LENGTH);
+        let entry = Entry {
+            status: res.status(),
+            headers,
+            body,
+            stored: Instant::now(),
+            max_age,
+            stale_while_revalidate,
+            revalidating: false,
+        };
+        self.entries.lock().unwrap().put(key, entry);
+        true
+    }
+
+    /// Keep the stale entry, the next request in the window tries again
+    fn revalidation_failed(&self, key: &str) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(&key.to_owned()) {
+            entry.revalidating = false;
+        }
+    }
+}
+
+type Response = ntex::http::Response<Body>;
+
+/// A miss being answered. However the request ends, the ones waiting for it
+/// are let go when it is dropped
+struct Loading {
+    cache: web::types::Data<Cache>,
+    key: String,
+    stored: bool,
+}
+
+impl Drop for Loading {
+    fn drop(&mut self) {
+        let waiting = self.cache.loading.lock().unwrap().remove(&self.key);
+        for tx in waiting.into_iter().flatten() {
+            let _ = tx.send(self.stored);
+        }
+    }
+}
+
+impl Entry {
+    fn response(&self, age: Duration, status: &'static str) -> HttpResponse {
+        let mut res = HttpResponse::build(self.status);
+        for (name, value) in self.headers.iter() {
+            res.header(name.clone(), value.clone());
+        }
+        res.header(header::AGE, age.as_secs().to_string())
+            .header(X_CACHE, status)
+            .body(self.body.clone())
+    }
+}
+
+/// `max-age` and `stale-while-revalidate` from a `Cache-Control` value
+fn parse(cache_control: &str) -> (Option<Duration>, Option<Duration>) {
+    let mut max_age = None;
+    let mut swr = None;
+    for directive in cache_control.split(',').map(str::trim) {
+        let mut kv = directive.splitn(2, '=');
+        let key = kv.next().unwrap_or("");
+        let value = kv
+            .next()
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs);
+        match key {
+            "max-age" => max_age = value,
+            "stale-while-revalidate" => swr = value,
+            "no-store" | "no-cache" | "private" => return (None, None),
+            _ => (),
+        }
+    }
+    (max_age, swr)
+}
+
+/// Cache middleware.
+///
+/// `origin` is the address the background refresh is sent to, usually the
+/// server's own address.
+pub struct SwrCache {
+    cache: web::types::Data<Cache>,
+    origin: Rc<String>,
+}
+
+impl SwrCache {
+    pub fn new(cache: web::types::Data<Cache>, origin: &str) -> Self {
+        SwrCache {
+            cache,
+            origin: Rc::new(origin.to_owned()),
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for SwrCache
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SwrCacheMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SwrCacheMiddleware {
+            service: Rc::new(service),
+            inner: Rc::new(Inner {
+                cache: self.cache.clone(),
+                origin: self.origin.clone(),
+                client: Client::build().timeout(Duration::from_secs(10)).finish(),
+            }),
+        })
+    }
+}
+
+struct Inner {
+    cache: web::types::Data<Cache>,
+    origin: Rc<String>,
+    client: Client,
+}
+
+impl Inner {
+    /// Refresh the entry by sending the request again, the middleware on the
+    /// receiving side stores the new response
+    fn revalidate(self: Rc<Self>, key: String) {
+        ntex::rt::spawn(async move {
+            let url = format!("{}{}", self.origin, key);
+            log::info!("revalidating {}", key);
+
+            let res = self
+                .client
+                .get(url.as_str())
+                .header(REVALIDATE, "1")
+                .send()
+                .await;
+            match res {
+                Ok(res) if res.status().is_success() => (),
+                Ok(res) => {
+                    log::warn!("revalidating {} failed: {}", key, res.status());
+                    self.cache.revalidation_failed(&key);
+                }
+                Err(e) => {
+                    log::warn!("revalidating {} failed: {}", key, e);
+                    self.cache.revalidation_failed(&key);
+                }
+            }
+        });
+    }
+}
+
+pub struct SwrCacheMiddleware<S> {
+    service: Rc<S>,
+    inner: Rc<Inner>,
+}
+
+impl<S, Err> Service for SwrCacheMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        if req.method() != Method::GET {
+            return self.service.call(req).boxed_local();
+        }
+
+        let key = req
+            .uri()
+            .path_and_query()
+            .map_or_else(|| req.path().to_owned(), |pq| pq.as_str().to_owned());
+        // only honor refresh requests from this machine
+        let revalidation = req.headers().contains_key(REVALIDATE)
+            && matches!(req.peer_addr(), Some(addr) if addr.ip().is_loopback());
+
+        let service = self.service.clone();
+        let inner = self.inner.clone();
+        let mut loading = None;
+        if !revalidation {
+            match self.inner.cache.lookup(&key) {
+                Lookup::Fresh(res) => return ok(req.into_response(res)).boxed_local(),
+                Lookup::Stale(res, revalidate) => {
+                    if revalidate {
+                        self.inner.clone().revalidate(key);
+                    }
+                    return ok(req.into_response(res)).boxed_local();
+                }
+                Lookup::Miss(false) => (),
+                Lookup::Miss(true) => {
+                    loading = Some(Loading {
+                        cache: self.inner.cache.clone(),
+                        key: key.clone(),
+                        stored: false,
+                    })
+                }
+                Lookup::Pending(stored) => {
+                    return async move {
+                        if let Ok(true) = stored.await {
+                            if let Some(res) = inner.cache.get(&key) {
+                                return Ok(req.into_response(res));
+                            }
+                        }
+                        // not cacheable, or the request went away, this one
+                        // goes to the handler on its own
+                        service.call(req).await
+                    }
+                    .boxed_local();
+                }
+            }
+        }
+
+        async move {
+            let mut res = service.call(req).await?;
+            let body = match read_body(res.take_body()).await {
+                Ok(body) => body,
+                Err(e) => {
+                    inner.cache.revalidation_failed(&key);
+                    return Err(
+                        web::error::ErrorInternalServerError(e.to_string()).into()
+                    );
+                }
+            };
+            let stored = inner.cache.store(key, res.response(), body.clone());
+            if let Some(loading) = &mut loading {
+                loading.stored = stored;
+            }
+            drop(loading);
+
+            let status = if revalidation { "REVALIDATED" } else { "MISS" };
+            res.headers_mut().insert(
+                HeaderName::from_static(X_CACHE),
+                HeaderValue::from_static(status),
+            );
+            Ok(res.map_body(|_, _| ResponseBody::Body(Body::Bytes(body))))
+        }
+        .boxed_local()
+    }
+}
+
+async fn read_body(
+    mut body: ResponseBody<Body>,
+) -> Result<Bytes, Box<dyn std::error::Error>> {
+    let mut buf = BytesMut::new();
+    while let Some(chunk) = poll_fn(|cx| body.poll_next_chunk(cx)).await {
+        buf.extend_from_slice(&chunk?);
+    }
+    Ok(buf.freeze())
+}
@@ -0,0 +1,52 @@
+//! Talks to the binary port with nothing but the standard library:
+//!
+//! ```text
+//! cargo run --bin client -- set greeting hello
+//! cargo run --bin client -- get greeting
+//! cargo run --bin client -- del greeting
+//! ```
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+fn request(op: u8, key: &str, value: &[u8]) -> Vec<u8> {
+    let len = 1 + 2 + key.len() + value.len();
+    let mut frame = Vec::with_capacity(4 + len);
+    frame.extend_from_slice(&(len as u32).to_be_bytes());
+    frame.push(op);
+    frame.extend_from_slice(&(key.len() as u16).to_be_bytes());
+    frame.extend_from_slice(key.as_bytes());
+    frame.extend_from_slice(value);
+    frame
+}
+
+/// The status byte and the rest of the frame
+fn response(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut len = [0; 4];
+    stream.read_exact(&mut len)?;
+    let mut frame = vec![0; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut frame)?;
+    let body = frame.split_off(1);
+    Ok((frame[0], body))
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let frame = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["get", key] => request(1, key, b""),
+        ["set", key, value] => request(2, key, value.as_bytes()),
+        ["del", key] => request(3, key, b""),
+        _ => {
+            eprintln!("usage: client get <key> | set <key> <value> | del <key>");
+            std::process::exit(2);
+        }
+    };
+
+    let mut stream = TcpStream::connect("127.0.0.1:9000")?;
+    stream.write_all(&frame)?;
+    match response(&mut stream)? {
+        (0, value) => println!("ok {}", String::from_utf8_lossy(&value)),
+        (1, _) => println!("not found"),
+        (_, msg) => println!("error: {}", String::from_utf8_lossy(&msg)),
+    }
+    Ok(())
+}
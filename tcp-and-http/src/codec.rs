@@ -0,0 +1,111 @@
+//! The binary protocol of the TCP port.
+//!
+//! Every frame starts with its length, a big endian `u32`, the length of
+//! what follows. A request is an opcode byte, the key length as a big
+//! endian `u16`, the key, and for `SET` the value, everything up to the end
+//! of the frame:
+//!
+//! ```text
+//! | len: u32 | op: u8 | key len: u16 | key | value |
+//! ```
+//!
+//! `GET` is 1, `SET` 2 and `DEL` 3. A response is a status byte followed by
+//! the rest of the frame: 0 and the value, or nothing for `SET` and `DEL`,
+//! 1 when the key is not found, 2 and an error message.
+use std::io;
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use ntex::codec::{Decoder, Encoder};
+
+/// Larger frames are refused before they are buffered
+pub const MAX_FRAME: usize = 1024 * 1024;
+
+const GET: u8 = 1;
+const SET: u8 = 2;
+const DEL: u8 = 3;
+
+const OK: u8 = 0;
+const NOT_FOUND: u8 = 1;
+const ERROR: u8 = 2;
+
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    Get(String),
+    Set(String, Bytes),
+    Delete(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Response {
+    Ok(Bytes),
+    NotFound,
+    Error(String),
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The server side, decodes requests and encodes responses
+pub struct KvCodec;
+
+impl KvCodec {
+    fn request(mut frame: BytesMut) -> io::Result<Request> {
+        if frame.len() < 3 {
+            return Err(invalid("frame too short"));
+        }
+        let op = frame.get_u8();
+        let key_len = frame.get_u16() as usize;
+        if frame.len() < key_len {
+            return Err(invalid("key longer than the frame"));
+        }
+        let key = String::from_utf8(frame.split_to(key_len).to_vec())
+            .map_err(|_| invalid("key is not utf-8"))?;
+        match op {
+            GET if frame.is_empty() => Ok(Request::Get(key)),
+            SET => Ok(Request::Set(key, frame.freeze())),
+            DEL if frame.is_empty() => Ok(Request::Delete(key)),
+            GET | DEL => Err(invalid("unexpected value")),
+            _ => Err(invalid("unknown opcode")),
+        }
+    }
+}
+
+impl Decoder for KvCodec {
+    type Item = Request;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Request>> {
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if len > MAX_FRAME {
+            return Err(invalid("frame too large"));
+        }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        Self::request(src.split_to(len)).map(Some)
+    }
+}
+
+impl Encoder for KvCodec {
+    type Item = Response;
+    type Error = io::Error;
+
+    fn encode(&mut self, res: Response, dst: &mut BytesMut) -> io::Result<()> {
+        let (status, body) = match res {
+            Response::Ok(value) => (OK, value),
+            Response::NotFound => (NOT_FOUND, Bytes::new()),
+            Response::Error(msg) => (ERROR, Bytes::from(msg)),
+        };
+        dst.reserve(5 + body.len());
+        dst.put_u32(1 + body.len() as u32);
+        dst.put_u8(status);
+        dst.put(body);
+        Ok(())
+    }
+}
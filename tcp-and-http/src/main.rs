@@ -0,0 +1,113 @@
+//! A key-value store with two front doors, an HTTP API on port 8080 and a
+//! binary protocol on port 9000, see `codec`. Both servers run in the same
+//! process and share a `Store`.
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use ntex::codec::Framed;
+use ntex::fn_service;
+use ntex::rt::net::TcpStream;
+use ntex::server::Server;
+use ntex::web::{self, middleware, App, HttpResponse};
+
+mod codec;
+mod store;
+
+use codec::{KvCodec, Response};
+use store::Store;
+
+/// One TCP connection, requests are answered in order until the client
+/// hangs up. A broken frame is answered with an error and ends the
+/// connection, there is no telling where the next one starts
+async fn session(io: TcpStream, store: web::types::Data<Store>) -> std::io::Result<()> {
+    let peer = io.peer_addr()?;
+    log::info!("tcp: {} connected", peer);
+    let mut framed = Framed::new(io, KvCodec);
+
+    while let Some(req) = framed.next().await {
+        match req {
+            Ok(req) => {
+                log::info!("tcp: {} {:?}", peer, req);
+                framed.send(store.handle(req)).await?;
+            }
+            Err(e) => {
+                log::warn!("tcp: {} sent a bad frame: {}", peer, e);
+                framed.send(Response::Error(e.to_string())).await?;
+                break;
+            }
+        }
+    }
+    log::info!("tcp: {} disconnected", peer);
+    Ok(())
+}
+
+async fn keys(store: web::types::Data<Store>) -> HttpResponse {
+    HttpResponse::Ok().json(&store.keys())
+}
+
+async fn get(
+    key: web::types::Path<String>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    match store.get(&key) {
+        Some(value) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(value),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+async fn put(
+    key: web::types::Path<String>,
+    body: Bytes,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    store.set(key.into_inner(), body);
+    HttpResponse::NoContent().finish()
+}
+
+async fn delete(
+    key: web::types::Path<String>,
+    store: web::types::Data<Store>,
+) -> HttpResponse {
+    if store.delete(&key) {
+        HttpResponse::NoContent().finish()
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,tcp_and_http=info");
+    env_logger::init();
+
+    // one store for every worker of both servers
+    let store = web::types::Data::new(Store::default());
+
+    let tcp = {
+        let store = store.clone();
+        Server::build()
+            .bind("kv", "127.0.0.1:9000", move || {
+                let store = store.clone();
+                fn_service(move |io: TcpStream| session(io, store.clone()))
+            })?
+            .run()
+    };
+
+    let http = web::server(move || {
+        App::new()
+            .app_data(store.clone())
+            .wrap(middleware::Logger::default())
+            .route("/keys", web::get().to(keys))
+            .service(
+                web::resource("/keys/{key}")
+                    .route(web::get().to(get))
+                    .route(web::put().to(put))
+                    .route(web::delete().to(delete)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    futures::try_join!(http, tcp).map(|_| ())
+}
@@ -0,0 +1,44 @@
+//! The store both servers work on.
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use bytes::Bytes;
+
+use crate::codec::{Request, Response};
+
+#[derive(Default)]
+pub struct Store {
+    values: RwLock<BTreeMap<String, Bytes>>,
+}
+
+impl Store {
+    pub fn get(&self, key: &str) -> Option<Bytes> {
+        self.values.read().unwrap().get(key).cloned()
+    }
+
+    pub fn set(&self, key: String, value: Bytes) {
+        self.values.write().unwrap().insert(key, value);
+    }
+
+    /// Whether there was a value
+    pub fn delete(&self, key: &str) -> bool {
+        self.values.write().unwrap().remove(key).is_some()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.values.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Answers a request of the TCP protocol
+    pub fn handle(&self, req: Request) -> Response {
+        match req {
+            Request::Get(key) => self.get(&key).map_or(Response::NotFound, Response::Ok),
+            Request::Set(key, value) => {
+                self.set(key, value);
+                Response::Ok(Bytes::new())
+            }
+            Request::Delete(key) if self.delete(&key) => Response::Ok(Bytes::new()),
+            Request::Delete(_) => Response::NotFound,
+        }
+    }
+}
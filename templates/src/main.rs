@@ -0,0 +1,122 @@
+//! HTML from tera templates, shared by the workers in `Data<Templates>`. The
+//! sign up form comes back with the errors and what was entered until it is
+//! right, and debug builds pick up edited templates without a restart.
+use ntex::http::StatusCode;
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tera::Context;
+
+mod registry;
+
+use registry::Templates;
+
+const MIN_AGE: u8 = 13;
+
+/// Strings as they were entered, an age that isn't a number goes back
+/// into the form as it is
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default)]
+struct Signup {
+    name: String,
+    email: String,
+    age: String,
+}
+
+impl Signup {
+    fn errors(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.name.trim().is_empty() {
+            errors.push("Name is required".to_owned());
+        }
+        let email = self.email.trim();
+        if !email.contains('@') || email.starts_with('@') || email.ends_with('@') {
+            errors.push("Email must look like name@example.com".to_owned());
+        }
+        match self.age.trim().parse::<u8>() {
+            Ok(age) if age < MIN_AGE => {
+                errors.push(format!("You must be at least {} to sign up", MIN_AGE))
+            }
+            Ok(_) => {}
+            Err(_) => errors.push("Age must be a number".to_owned()),
+        }
+        errors
+    }
+}
+
+fn render(
+    templates: &Templates,
+    status: StatusCode,
+    name: &str,
+    ctx: &Context,
+) -> Result<HttpResponse, Error> {
+    let html = templates.render(name, ctx).map_err(|e| {
+        log::error!("can not render {}: {}", name, registry::describe(&e));
+        error::ErrorInternalServerError("Template error")
+    })?;
+    Ok(HttpResponse::build(status)
+        .content_type("text/html; charset=utf-8")
+        .body(html))
+}
+
+fn signup_page(
+    templates: &Templates,
+    status: StatusCode,
+    form: &Signup,
+    errors: &[String],
+) -> Result<HttpResponse, Error> {
+    let mut ctx = Context::new();
+    ctx.insert("form", form);
+    ctx.insert("errors", errors);
+    render(templates, status, "signup.html", &ctx)
+}
+
+async fn index(templates: web::types::Data<Templates>) -> Result<HttpResponse, Error> {
+    signup_page(&templates, StatusCode::OK, &Signup::default(), &[])
+}
+
+async fn signup(
+    templates: web::types::Data<Templates>,
+    form: web::types::Form<Signup>,
+) -> Result<HttpResponse, Error> {
+    let errors = form.errors();
+    if !errors.is_empty() {
+        return signup_page(
+            &templates,
+            StatusCode::UNPROCESSABLE_ENTITY,
+            &form,
+            &errors,
+        );
+    }
+
+    let mut ctx = Context::new();
+    ctx.insert("name", form.name.trim());
+    ctx.insert("email", form.email.trim());
+    render(&templates, StatusCode::OK, "welcome.html", &ctx)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,templates=info");
+    env_logger::init();
+
+    let templates = Templates::load(concat!(env!("CARGO_MANIFEST_DIR"), "/templates"))
+        .map_err(|e| std::io::Error::other(registry::describe(&e)))?;
+    log::info!("templates loaded from {}", templates.dir().display());
+    let templates = web::types::Data::new(templates);
+    #[cfg(debug_assertions)]
+    registry::watch(templates.clone());
+
+    web::server(move || {
+        App::new()
+            .app_data(templates.clone())
+            .wrap(middleware::Logger::default())
+            .service(
+                web::resource("/")
+                    .route(web::get().to(index))
+                    .route(web::post().to(signup)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,119 @@
+//! The templates of every worker, loaded once and shared through `Data`.
+//! Debug builds watch the directory and load them again when a file
+//! changes, release builds keep what they loaded at startup.
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use tera::{Context, Tera};
+
+pub struct Templates {
+    dir: PathBuf,
+    tera: RwLock<Tera>,
+}
+
+impl Templates {
+    pub fn load(dir: impl Into<PathBuf>) -> tera::Result<Self> {
+        let dir = dir.into();
+        let tera = Tera::new(&glob(&dir))?;
+        Ok(Templates {
+            dir,
+            tera: RwLock::new(tera),
+        })
+    }
+
+    pub fn render(&self, name: &str, ctx: &Context) -> tera::Result<String> {
+        self.tera.read().unwrap().render(name, ctx)
+    }
+
+    /// Loads all the templates again. On an error, a typo in a template
+    /// that is being edited, the old ones stay
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    pub fn reload(&self) -> tera::Result<()> {
+        let tera = Tera::new(&glob(&self.dir))?;
+        *self.tera.write().unwrap() = tera;
+        Ok(())
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// The error with all its causes, tera's own message alone rarely tells
+/// what is wrong
+pub fn describe(e: &tera::Error) -> String {
+    let mut msg = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(e) = source {
+        msg.push_str(": ");
+        msg.push_str(&e.to_string());
+        source = e.source();
+    }
+    msg
+}
+
+fn glob(dir: &Path) -> String {
+    format!("{}/**/*.html", dir.display())
+}
+
+#[cfg(debug_assertions)]
+pub use watch::watch;
+
+#[cfg(debug_assertions)]
+mod watch {
+    use std::path::{Path, PathBuf};
+    use std::time::{Duration, SystemTime};
+
+    use ntex::web::types::Data;
+
+    use super::{describe, Templates};
+
+    const INTERVAL: Duration = Duration::from_millis(500);
+
+    /// A thread that looks at the files every `INTERVAL`, a file that is
+    /// added, removed or written to has the templates loaded again
+    pub fn watch(templates: Data<Templates>) {
+        log::info!("watching the templates for changes");
+        std::thread::spawn(move || {
+            let mut seen = files(templates.dir());
+            loop {
+                std::thread::sleep(INTERVAL);
+                let now = files(templates.dir());
+                if now == seen {
+                    continue;
+                }
+                seen = now;
+                match templates.reload() {
+                    Ok(()) => log::info!("templates reloaded"),
+                    Err(e) => log::error!("keeping the old templates: {}", describe(&e)),
+                }
+            }
+        });
+    }
+
+    /// Every file below `dir` with its modification time, sorted
+    fn files(dir: &Path) -> Vec<(PathBuf, SystemTime)> {
+        let mut files = Vec::new();
+        let mut dirs = vec![dir.to_path_buf()];
+        while let Some(dir) = dirs.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                match entry.metadata() {
+                    Ok(meta) if meta.is_dir() => dirs.push(path),
+                    Ok(meta) => {
+                        if let Ok(modified) = meta.modified() {
+                            files.push((path, modified));
+                        }
+                    }
+                    Err(_) => {}
+                }
+            }
+        }
+        files.sort();
+        files
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::HashMap;
+
+use ntex::web::{self, error, middleware, App, Error, HttpResponse};
+use rusqlite::{params, NO_PARAMS};
+use serde::{Deserialize, Serialize};
+
+mod tenants;
+
+use tenants::{TenantDb, Tenants};
+
+/// Default for `POOL_CACHE_SIZE`, how many tenant pools are open at most
+const POOL_CACHE_SIZE: usize = 2;
+
+#[derive(Serialize)]
+struct Note {
+    id: i64,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct NewNote {
+    text: String,
+}
+
+/// The notes of the tenant, from its own database
+async fn list(db: TenantDb) -> Result<HttpResponse, Error> {
+    let notes = web::block(move || {
+        let conn = db.pool.get()?;
+        let mut stmt = conn.prepare("SELECT id, text FROM notes ORDER BY id")?;
+        let notes = stmt
+            .query_map(NO_PARAMS, |row| {
+                Ok(Note {
+                    id: row.get(0)?,
+                    text: row.get(1)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(notes)
+    })
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(&notes))
+}
+
+async fn add(
+    db: TenantDb,
+    note: web::types::Json<NewNote>,
+) -> Result<HttpResponse, Error> {
+    let tenant = db.tenant.clone();
+    let note = web::block(move || {
+        let conn = db.pool.get()?;
+        conn.execute("INSERT INTO notes (text) VALUES (?1)", params![note.text])?;
+        Ok::<_, Box<dyn std::error::Error + Send + Sync>>(Note {
+            id: conn.last_insert_rowid(),
+            text: note.into_inner().text,
+        })
+    })
+    .await
+    .map_err(error::ErrorInternalServerError)?;
+    log::info!("`{}` added note {}", tenant, note.id);
+    Ok(HttpResponse::Created().json(&note))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,tenant_db_routing=info");
+    env_logger::init();
+
+    // in a real service this comes from a tenant registry
+    std::fs::create_dir_all("data")?;
+    let databases = ["acme", "globex", "initech"]
+        .iter()
+        .map(|tenant| (tenant.to_string(), format!("data/{}.db", tenant).into()))
+        .collect::<HashMap<_, _>>();
+    let capacity = std::env::var("POOL_CACHE_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(POOL_CACHE_SIZE);
+    let tenants = web::types::Data::new(Tenants::new(databases, capacity));
+
+    web::server(move || {
+        App::new()
+            .app_data(tenants.clone())
+            .wrap(middleware::Logger::default())
+            .service(
+                web::resource("/notes")
+                    .route(web::get().to(list))
+                    .route(web::post().to(add)),
+            )
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,168 @@
+//! Routing each request to the database of its tenant.
+//!
+//! Every tenant has a database of its own, here a sqlite file, so one
+//! tenant's data can't leak into another tenant's queries. With many tenants
+//! a pool per tenant doesn't scale, most of them are idle most of the time.
+//! Pools are opened on the first request of a tenant and kept in a cache of
+//! limited size, the least recently used pool is closed when it is full.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use derive_more::Display;
+use futures::future::{FutureExt, LocalBoxFuture};
+use ntex::http::Payload;
+use ntex::web::WebResponseError;
+use ntex::web::{self, ErrorRenderer, FromRequest, HttpRequest, HttpResponse};
+use r2d2_sqlite::SqliteConnectionManager;
+
+pub type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// The header that names the tenant
+const TENANT_HEADER: &str = "x-tenant";
+
+#[derive(Debug, Display)]
+pub enum TenantError {
+    #[display(fmt = "missing header `{}`", TENANT_HEADER)]
+    Missing,
+    #[display(fmt = "unknown tenant `{}`", _0)]
+    Unknown(String),
+    #[display(fmt = "database error: {}", _0)]
+    Database(String),
+}
+
+impl WebResponseError for TenantError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            TenantError::Missing => HttpResponse::BadRequest(),
+            TenantError::Unknown(_) => HttpResponse::NotFound(),
+            TenantError::Database(_) => HttpResponse::InternalServerError(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// Open pools, by tenant, with the time they were last used
+#[derive(Default)]
+struct PoolCache {
+    pools: HashMap<String, (Pool, u64)>,
+    clock: u64,
+}
+
+impl PoolCache {
+    fn get(&mut self, tenant: &str) -> Option<Pool> {
+        self.clock += 1;
+        let clock = self.clock;
+        self.pools.get_mut(tenant).map(|(pool, used)| {
+            *used = clock;
+            pool.clone()
+        })
+    }
+
+    /// Adds a pool, and closes the least recently used ones over `capacity`
+    fn insert(&mut self, tenant: &str, pool: Pool, capacity: usize) -> Pool {
+        // another request of the tenant may have been faster
+        if let Some(pool) = self.get(tenant) {
+            return pool;
+        }
+        while self.pools.len() >= capacity {
+            let oldest = self
+                .pools
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(tenant, _)| tenant.clone());
+            match oldest {
+                Some(oldest) => {
+                    // handlers that still hold a clone keep the pool open
+                    // until they are done
+                    log::info!("closing the pool of `{}`", oldest);
+                    self.pools.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+        self.pools
+            .insert(tenant.to_owned(), (pool.clone(), self.clock));
+        pool
+    }
+}
+
+/// The known tenants and their databases
+pub struct Tenants {
+    databases: HashMap<String, PathBuf>,
+    cache: Mutex<PoolCache>,
+    capacity: usize,
+}
+
+impl Tenants {
+    /// `capacity` is the number of pools that are open at most
+    pub fn new(databases: HashMap<String, PathBuf>, capacity: usize) -> Self {
+        Tenants {
+            databases,
+            cache: Mutex::new(PoolCache::default()),
+            capacity,
+        }
+    }
+
+    /// The pool of `tenant`, opened if it isn't cached
+    pub async fn pool(&self, tenant: &str) -> Result<Pool, TenantError> {
+        let path = match self.databases.get(tenant) {
+            Some(path) => path.clone(),
+            None => return Err(TenantError::Unknown(tenant.to_owned())),
+        };
+        if let Some(pool) = self.cache.lock().unwrap().get(tenant) {
+            return Ok(pool);
+        }
+
+        // opening connections blocks, the lock isn't held meanwhile
+        log::info!("opening the pool of `{}` at {}", tenant, path.display());
+        let pool = web::block(move || open(path))
+            .await
+            .map_err(|e| TenantError::Database(e.to_string()))?;
+        let mut cache = self.cache.lock().unwrap();
+        Ok(cache.insert(tenant, pool, self.capacity))
+    }
+}
+
+/// Opens a small pool and creates the schema on the first use
+fn open(path: PathBuf) -> Result<Pool, r2d2::Error> {
+    let manager = SqliteConnectionManager::file(path).with_init(|conn| {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS notes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                text TEXT NOT NULL
+            )",
+        )
+    });
+    r2d2::Pool::builder().max_size(4).build(manager)
+}
+
+/// The database of the tenant named in `X-Tenant`
+pub struct TenantDb {
+    pub tenant: String,
+    pub pool: Pool,
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for TenantDb {
+    type Error = TenantError;
+    type Future = LocalBoxFuture<'static, Result<Self, TenantError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let tenant = req
+            .headers()
+            .get(TENANT_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_owned());
+        let tenants = req
+            .app_data::<web::types::Data<Tenants>>()
+            .expect("Tenants are not configured")
+            .clone();
+
+        async move {
+            let tenant = tenant.ok_or(TenantError::Missing)?;
+            let pool = tenants.pool(&tenant).await?;
+            Ok(TenantDb { tenant, pool })
+        }
+        .boxed_local()
+    }
+}
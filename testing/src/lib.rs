@@ -0,0 +1,5 @@
+//! `SayHi` of the `middleware` example, compiled from that example's own
+//! file so that the tests in `tests/` test the code the example runs. The
+//! app of the `state` example comes from its library, `state::config`.
+#[path = "../../middleware/src/simple.rs"]
+pub mod middleware;
@@ -0,0 +1,27 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::Mutex;
+
+use ntex::web::{self, middleware, App};
+
+use testing::middleware::SayHi;
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,testing=info");
+    env_logger::init();
+
+    let mutex = web::types::Data::new(Mutex::new(0usize));
+    let atomic = web::types::Data::new(AtomicUsize::new(0usize));
+
+    web::server(move || {
+        App::new()
+            .app_data(mutex.clone())
+            .app_data(atomic.clone())
+            .wrap(SayHi)
+            .wrap(middleware::Logger::default())
+            .configure(state::config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,62 @@
+//! `SayHi` on its own, wrapped around a stand-in service instead of an app,
+//! and then in an app. It only prints, whatever the service answers has to
+//! come out of it as it went in.
+use ntex::http::StatusCode;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, test, App, DefaultError, ErrorContainer, HttpResponse};
+use ntex::{fn_service, Service, Transform};
+
+use testing::middleware::SayHi;
+
+#[ntex::test]
+async fn test_passes_response_through() {
+    let inner = fn_service(|req: WebRequest<DefaultError>| async move {
+        let res = HttpResponse::Created().header("x-inner", "1").body("made");
+        Ok::<_, web::Error>(req.into_response(res))
+    });
+    let srv = SayHi.new_transform(inner).await.unwrap();
+
+    let req = test::TestRequest::with_uri("/anything").to_srv_request();
+    let res = srv.call(req).await.unwrap();
+    assert_eq!(res.status(), StatusCode::CREATED);
+    assert_eq!(res.headers().get("x-inner").unwrap(), "1");
+    assert_eq!(res.headers().len(), 1);
+    assert_eq!(test::read_body(res).await, "made");
+}
+
+/// An error of the inner service is passed on as it is, it only becomes a
+/// response outside of the middleware
+#[ntex::test]
+async fn test_passes_errors_on() {
+    let inner = fn_service(|_: WebRequest<DefaultError>| async {
+        Err::<WebResponse, web::Error>(web::error::ErrorBadRequest("no").into())
+    });
+    let srv = SayHi.new_transform(inner).await.unwrap();
+
+    let err = srv
+        .call(test::TestRequest::default().to_srv_request())
+        .await
+        .err()
+        .unwrap();
+    // rendered the way the app would for the request
+    let res = err.error_response(&test::TestRequest::default().to_http_request());
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+}
+
+#[ntex::test]
+async fn test_in_app() {
+    let app = test::init_service(
+        App::new()
+            .wrap(SayHi)
+            .route("/", web::get().to(|| async { "hello" })),
+    )
+    .await;
+
+    let body = test::read_response(&app, test::TestRequest::get().to_request()).await;
+    assert_eq!(body, "hello");
+
+    // the app's own 404 goes through the middleware as well
+    let req = test::TestRequest::get().uri("/missing").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+}
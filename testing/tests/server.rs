@@ -0,0 +1,49 @@
+//! A real server, bound to a free port by `test::server`, and requests over
+//! TCP with its client. Slower than `init_service`, but it tests what a
+//! client sees: the HTTP on the wire, keep-alive, the headers of the
+//! response.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use ntex::http::{header, StatusCode};
+use ntex::web::{self, test, App};
+
+use testing::middleware::SayHi;
+
+#[ntex::test]
+async fn test_server() {
+    let mutex = web::types::Data::new(Mutex::new(0usize));
+    let atomic = web::types::Data::new(AtomicUsize::new(0usize));
+    let srv = {
+        let atomic = atomic.clone();
+        // the factory is called on the server's thread, once per worker
+        test::server(move || {
+            App::new()
+                .app_data(mutex.clone())
+                .app_data(atomic.clone())
+                .wrap(SayHi)
+                .configure(state::config)
+        })
+    };
+
+    for n in 1..=3 {
+        let mut res = srv.get("/").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get(header::CONTENT_LENGTH).unwrap(), "67");
+        let body = res.body().await.unwrap();
+        assert!(body.starts_with(format!("global mutex counter: {},", n).as_bytes()));
+    }
+    assert_eq!(atomic.load(Ordering::SeqCst), 3);
+
+    let res = srv.head("/").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let res = srv.get("/missing").send().await.unwrap();
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    // a free port is picked for every test server
+    assert_ne!(srv.addr().port(), 0);
+    assert_eq!(
+        srv.url("/"),
+        format!("http://localhost:{}/", srv.addr().port())
+    );
+}
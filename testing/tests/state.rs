@@ -0,0 +1,92 @@
+//! The app run in-process with `test::init_service`, no socket involved:
+//! requests are built with `TestRequest` and handed to the app directly.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use ntex::http::StatusCode;
+use ntex::web::types::Data;
+use ntex::web::{test, App};
+
+/// The global state of the `state` example, the test keeps a clone
+fn counters() -> (Data<Mutex<usize>>, Data<AtomicUsize>) {
+    (Data::new(Mutex::new(0)), Data::new(AtomicUsize::new(0)))
+}
+
+#[ntex::test]
+async fn test_index() {
+    let (mutex, atomic) = counters();
+    let app = test::init_service(
+        App::new()
+            .app_data(mutex.clone())
+            .app_data(atomic.clone())
+            .configure(state::config),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let body = test::read_body(res).await;
+    assert_eq!(
+        body,
+        "global mutex counter: 1, local counter: 1, global atomic counter: 1"
+    );
+
+    // `read_response` calls the app and reads the body in one go
+    let req = test::TestRequest::get().uri("/").to_request();
+    let body = test::read_response(&app, req).await;
+    assert_eq!(
+        body,
+        "global mutex counter: 2, local counter: 2, global atomic counter: 2"
+    );
+
+    // the test holds on to the shared counters as well
+    assert_eq!(*mutex.lock().unwrap(), 2);
+    assert_eq!(atomic.load(Ordering::SeqCst), 2);
+}
+
+/// Two apps stand in for two worker threads
+#[ntex::test]
+async fn test_local_counter_per_app() {
+    let (mutex, atomic) = counters();
+    let first = test::init_service(
+        App::new()
+            .app_data(mutex.clone())
+            .app_data(atomic.clone())
+            .configure(state::config),
+    )
+    .await;
+    let second = test::init_service(
+        App::new()
+            .app_data(mutex.clone())
+            .app_data(atomic.clone())
+            .configure(state::config),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    test::call_service(&first, req).await;
+    let req = test::TestRequest::get().uri("/").to_request();
+    let body = test::read_response(&second, req).await;
+    assert_eq!(
+        body,
+        "global mutex counter: 2, local counter: 1, global atomic counter: 2"
+    );
+}
+
+#[ntex::test]
+async fn test_not_found() {
+    let (mutex, atomic) = counters();
+    let app = test::init_service(
+        App::new()
+            .app_data(mutex.clone())
+            .app_data(atomic.clone())
+            .configure(state::config),
+    )
+    .await;
+
+    let req = test::TestRequest::get().uri("/missing").to_request();
+    let res = test::call_service(&app, req).await;
+    assert_eq!(res.status(), StatusCode::NOT_FOUND);
+    assert_eq!(*mutex.lock().unwrap(), 0);
+}
@@ -0,0 +1,64 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use ntex::web::types::Data;
+use serde::Serialize;
+
+/// Stands in for a database, it only counts what happens to transactions
+#[derive(Default)]
+pub struct Ledger {
+    in_flight: AtomicUsize,
+    committed: AtomicUsize,
+    rolled_back: AtomicUsize,
+}
+
+#[derive(Serialize)]
+pub struct Stats {
+    pub in_flight: usize,
+    pub committed: usize,
+    pub rolled_back: usize,
+}
+
+impl Ledger {
+    pub fn begin(ledger: &Data<Ledger>, id: usize) -> Transaction {
+        ledger.in_flight.fetch_add(1, Ordering::SeqCst);
+        Transaction {
+            ledger: ledger.clone(),
+            id,
+            done: false,
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            in_flight: self.in_flight.load(Ordering::SeqCst),
+            committed: self.committed.load(Ordering::SeqCst),
+            rolled_back: self.rolled_back.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// Rolled back when dropped before `commit`, and that includes the handler
+/// owning it being dropped at an `.await` by `Timeout`
+pub struct Transaction {
+    ledger: Data<Ledger>,
+    id: usize,
+    done: bool,
+}
+
+impl Transaction {
+    pub fn commit(mut self) {
+        self.done = true;
+        self.ledger.committed.fetch_add(1, Ordering::SeqCst);
+        log::info!("transaction {} committed", self.id);
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        self.ledger.in_flight.fetch_sub(1, Ordering::SeqCst);
+        if !self.done {
+            self.ledger.rolled_back.fetch_add(1, Ordering::SeqCst);
+            log::warn!("transaction {} dropped, rolled back", self.id);
+        }
+    }
+}
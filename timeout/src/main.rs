@@ -0,0 +1,151 @@
+//! Handlers with a deadline, see `timeout`.
+//!
+//! `/api` answers within a second or gets a `504`, `/reports` gets five
+//! seconds and a `503`. `work` holds a `Transaction` while it waits, when
+//! its future is dropped the transaction is rolled back, `/stats` shows the
+//! counts.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use ntex::http::StatusCode;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde::Deserialize;
+
+mod ledger;
+mod timeout;
+
+use ledger::Ledger;
+use timeout::{JsonErrors, Timeout};
+
+const MAX_WORK_MS: u64 = 60_000;
+
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+
+#[derive(Deserialize)]
+struct Work {
+    ms: u64,
+}
+
+/// Takes `ms` milliseconds inside a transaction, and commits if it gets
+/// that far
+async fn work(
+    query: web::types::Query<Work>,
+    ledger: web::types::Data<Ledger>,
+) -> HttpResponse {
+    if query.ms > MAX_WORK_MS {
+        return HttpResponse::BadRequest().json(&serde_json::json!({
+            "error": format!("ms must be at most {}", MAX_WORK_MS)
+        }));
+    }
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let tx = Ledger::begin(&ledger, id);
+    log::info!("transaction {} started, {}ms of work", id, query.ms);
+
+    // dropped here, while waiting, when the deadline passes
+    ntex::rt::time::delay_for(Duration::from_millis(query.ms)).await;
+
+    tx.commit();
+    HttpResponse::Ok().json(&serde_json::json!({ "transaction": id, "ms": query.ms }))
+}
+
+async fn stats(ledger: web::types::Data<Ledger>) -> HttpResponse {
+    HttpResponse::Ok().json(&ledger.stats())
+}
+
+fn config(cfg: &mut web::ServiceConfig<JsonErrors>) {
+    cfg.service(
+        web::scope("/api")
+            .wrap(Timeout::new(Duration::from_secs(1)))
+            .route("/work", web::get().to(work)),
+    )
+    .service(
+        web::scope("/reports")
+            .wrap(
+                Timeout::new(Duration::from_secs(5))
+                    .status(StatusCode::SERVICE_UNAVAILABLE),
+            )
+            .route("/work", web::get().to(work)),
+    )
+    .route("/stats", web::get().to(stats));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,timeout=info");
+    env_logger::init();
+
+    let ledger = web::types::Data::new(Ledger::default());
+
+    web::server(move || {
+        App::with(JsonErrors)
+            .app_data(ledger.clone())
+            .wrap(middleware::Logger::default())
+            .configure(config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::web::test;
+
+    fn server() -> (test::TestServer, web::types::Data<Ledger>) {
+        let ledger = web::types::Data::new(Ledger::default());
+        let data = ledger.clone();
+        let srv = test::server(move || {
+            App::with(JsonErrors)
+                .app_data(data.clone())
+                .configure(config)
+        });
+        (srv, ledger)
+    }
+
+    #[ntex::test]
+    async fn test_in_time_commits() {
+        let (srv, ledger) = server();
+
+        let mut res = srv.get("/api/work?ms=10").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["ms"], 10);
+
+        let stats = ledger.stats();
+        assert_eq!(
+            (stats.committed, stats.rolled_back, stats.in_flight),
+            (1, 0, 0)
+        );
+    }
+
+    #[ntex::test]
+    async fn test_expired_rolls_back() {
+        let (srv, ledger) = server();
+
+        let mut res = srv.get("/api/work?ms=3000").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["error"], "no response within 1s");
+
+        // the handler was dropped before the response was sent
+        let stats = ledger.stats();
+        assert_eq!(
+            (stats.committed, stats.rolled_back, stats.in_flight),
+            (0, 1, 0)
+        );
+    }
+
+    #[ntex::test]
+    async fn test_status_per_scope() {
+        let (srv, _) = server();
+
+        let res = srv.get("/reports/work?ms=6000").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let mut res = srv.get("/api/work?ms=99999").send().await.unwrap();
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = res.json().await.unwrap();
+        assert_eq!(body["error"], "ms must be at most 60000");
+    }
+}
@@ -0,0 +1,178 @@
+//! `Timeout` races the service it wraps against a deadline.
+//!
+//! When the deadline passes first, the service's future is dropped, and
+//! with it everything the handler was holding at its current `.await`. A
+//! handler cleans up in `Drop`, as `Transaction` does, it gets no other
+//! notice.
+//!
+//! A client going away is not noticed while the handler runs, the handler
+//! finishes its work anyway. The deadline is what bounds it.
+//!
+//! The request was moved into the service, there is none left to build a
+//! response for, `TimedOut` is returned as an error. ntex renders an error
+//! that leaves the app with the renderer's container, without a request, and
+//! `DefaultError`'s is plain text. Apps that use `JsonErrors` get JSON.
+use std::fmt;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use derive_more::Display;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::{ResponseError, StatusCode};
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::error::{DefaultError, ErrorContainer, ErrorRenderer};
+use ntex::web::{self, HttpRequest, HttpResponse, WebResponseError};
+use ntex::{Service, Transform};
+
+#[derive(Debug, Display)]
+#[display(fmt = "no response within {:?}", after)]
+pub struct TimedOut {
+    after: Duration,
+    status: StatusCode,
+}
+
+impl WebResponseError for TimedOut {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::build(self.status)
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// `DefaultError`, except that errors are rendered as `{"error": ...}` when
+/// there is no request too
+pub struct JsonErrors;
+
+impl ErrorRenderer for JsonErrors {
+    type Container = JsonError;
+}
+
+/// Any error the default renderer takes
+pub struct JsonError(web::Error);
+
+impl<T: WebResponseError<DefaultError>> From<T> for JsonError {
+    fn from(err: T) -> Self {
+        JsonError(err.into())
+    }
+}
+
+impl fmt::Debug for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl ErrorContainer for JsonError {
+    fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+        self.0.as_response_error().error_response(req)
+    }
+}
+
+impl ResponseError for JsonError {
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.0.as_response_error().status_code())
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+pub struct Timeout {
+    after: Duration,
+    status: StatusCode,
+}
+
+impl Timeout {
+    /// Answers with `504 Gateway Timeout` by default
+    pub fn new(after: Duration) -> Self {
+        Timeout {
+            after,
+            status: StatusCode::GATEWAY_TIMEOUT,
+        }
+    }
+
+    /// `503 Service Unavailable`, for instance, tells clients that trying
+    /// again later may work
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+}
+
+impl<S, Err> Transform<S> for Timeout
+where
+    S: Service<
+        Request = WebRequest<Err>,
+        Response = WebResponse,
+        Error = Err::Container,
+    >,
+    S::Future: 'static,
+    Err: ErrorRenderer,
+    TimedOut: Into<Err::Container>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Err::Container;
+    type InitError = ();
+    type Transform = TimeoutMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TimeoutMiddleware {
+            service,
+            after: self.after,
+            status: self.status,
+        })
+    }
+}
+
+pub struct TimeoutMiddleware<S> {
+    service: S,
+    after: Duration,
+    status: StatusCode,
+}
+
+impl<S, Err> Service for TimeoutMiddleware<S>
+where
+    S: Service<
+        Request = WebRequest<Err>,
+        Response = WebResponse,
+        Error = Err::Container,
+    >,
+    S::Future: 'static,
+    Err: ErrorRenderer,
+    TimedOut: Into<Err::Container>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Err::Container;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Err::Container>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let path = req.path().to_owned();
+        let (after, status) = (self.after, self.status);
+        let fut = self.service.call(req);
+
+        async move {
+            match ntex::rt::time::timeout(after, fut).await {
+                Ok(res) => res,
+                Err(_) => {
+                    log::warn!("{} took longer than {:?}, dropped", path, after);
+                    Err(TimedOut { after, status }.into())
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
@@ -0,0 +1,9 @@
+#!/usr/bin/env bash
+# Generate a self-signed certificate and key for localhost.
+set -e
+cd "$(dirname "$0")"
+
+openssl req -x509 -newkey rsa:2048 -nodes -days 3650 \
+    -keyout key.pem -out cert.pem \
+    -subj "/CN=localhost" -addext "subjectAltName=DNS:localhost" 2>/dev/null
+echo "generated cert.pem and key.pem"
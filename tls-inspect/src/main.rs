@@ -0,0 +1,148 @@
+//! Reports what was negotiated in the TLS handshake of a connection.
+//!
+//! The same app is served over TLS on 8443 and over plain http on 8080. The
+//! `on_connect` callback of each listener runs once per connection, right
+//! after the handshake, and its return value, a `Connection`, is added to
+//! the extensions of every request on that connection.
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use futures::future::{ready, Ready};
+use ntex::http::{HttpService, Payload};
+use ntex::rt::net::TcpStream;
+use ntex::server::rustls::TlsStream;
+use ntex::service::map_config;
+use ntex::web::dev::AppConfig;
+use ntex::web::{
+    self, middleware, App, ErrorRenderer, FromRequest, HttpRequest, HttpResponse,
+};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+use rustls::{NoClientAuth, ProtocolVersion, ServerConfig, ServerSession, Session};
+use serde::Serialize;
+
+/// The parameters of a TLS session
+#[derive(Clone, Debug, Serialize)]
+struct TlsInfo {
+    version: String,
+    cipher_suite: String,
+    /// The host name the client asked for, clients don't send it when they
+    /// connect to an ip address
+    sni: Option<String>,
+    /// The application protocol picked with ALPN, `h2` or `http/1.1`
+    alpn: Option<String>,
+}
+
+impl TlsInfo {
+    fn new(session: &ServerSession) -> Self {
+        let version = match session.get_protocol_version() {
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_owned(),
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_owned(),
+            Some(v) => format!("{:?}", v),
+            None => "unknown".to_owned(),
+        };
+        TlsInfo {
+            version,
+            cipher_suite: session
+                .get_negotiated_ciphersuite()
+                .map(|suite| format!("{:?}", suite.suite))
+                .unwrap_or_else(|| "unknown".to_owned()),
+            sni: session.get_sni_hostname().map(str::to_owned),
+            alpn: session
+                .get_alpn_protocol()
+                .map(|proto| String::from_utf8_lossy(proto).into_owned()),
+        }
+    }
+}
+
+/// Per connection data, `tls` is `None` for plain http
+#[derive(Clone)]
+struct Connection {
+    tls: Option<TlsInfo>,
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Connection {
+    type Error = Err::Container;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        // every listener sets it, a request without one can't happen
+        let conn = req.extensions().get::<Connection>().cloned();
+        ready(Ok(conn.unwrap_or(Connection { tls: None })))
+    }
+}
+
+async fn tls_info(conn: Connection) -> HttpResponse {
+    match conn.tls {
+        Some(tls) => HttpResponse::Ok().json(&tls),
+        None => HttpResponse::Ok().json(&serde_json::json!({ "version": "none" })),
+    }
+}
+
+fn config(cfg: &mut web::ServiceConfig) {
+    cfg.route("/tls-info", web::get().to(tls_info));
+}
+
+fn server_config() -> io::Result<ServerConfig> {
+    let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg);
+
+    let chain = certs(&mut BufReader::new(File::open("certs/cert.pem")?))
+        .map_err(|_| invalid("certs/cert.pem: invalid certificate"))?;
+    let key = pkcs8_private_keys(&mut BufReader::new(File::open("certs/key.pem")?))
+        .ok()
+        .and_then(|mut keys| keys.pop())
+        .ok_or_else(|| invalid("certs/key.pem: no pkcs8 key"))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(config)
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,tls_inspect=info");
+    env_logger::init();
+
+    let tls_config = server_config()?;
+
+    // `web::server().bind_rustls()` hides the tls stream, build the http
+    // services by hand to get at the session in `on_connect`
+    ntex::server::build()
+        .bind("https", "127.0.0.1:8443", move || {
+            HttpService::build()
+                .on_connect(|io: &TlsStream<TcpStream>| {
+                    let tls = TlsInfo::new(io.get_ref().1);
+                    let peer = io.get_ref().0.peer_addr();
+                    log::info!(
+                        "{}: {} {}, sni {:?}, alpn {:?}",
+                        peer.map(|addr| addr.to_string()).unwrap_or_default(),
+                        tls.version,
+                        tls.cipher_suite,
+                        tls.sni,
+                        tls.alpn
+                    );
+                    Connection { tls: Some(tls) }
+                })
+                .finish(map_config(
+                    App::new()
+                        .wrap(middleware::Logger::default())
+                        .configure(config),
+                    |_| AppConfig::default(),
+                ))
+                .rustls(tls_config.clone())
+        })?
+        .bind("http", "127.0.0.1:8080", || {
+            HttpService::build()
+                .on_connect(|_: &TcpStream| Connection { tls: None })
+                .finish(map_config(
+                    App::new()
+                        .wrap(middleware::Logger::default())
+                        .configure(config),
+                    |_| AppConfig::default(),
+                ))
+                .tcp()
+        })?
+        .run()
+        .await
+}
@@ -0,0 +1,86 @@
+//! HTTPS with rustls, over HTTP/1.1 and HTTP/2.
+//!
+//! `bind_rustls` advertises `h2` and `http/1.1` with ALPN, each client
+//! picks one in the handshake and `/` reports which. With `HTTP_ADDR` set,
+//! e.g. to `0.0.0.0:80`, plain http is served there too, and redirected to
+//! https, see `redirect`.
+use std::fs::File;
+use std::io::{self, BufReader};
+
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys, rsa_private_keys};
+use rustls::{NoClientAuth, ServerConfig};
+
+mod redirect;
+
+use redirect::RedirectToHttps;
+
+const HTTPS_PORT: u16 = 8443;
+const CERT: &str = "certs/cert.pem";
+const KEY: &str = "certs/key.pem";
+
+async fn index(req: HttpRequest) -> HttpResponse {
+    HttpResponse::Ok().json(&serde_json::json!({
+        "version": format!("{:?}", req.version()),
+        "secure": req.app_config().secure(),
+    }))
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Opens a pem file, pointing at `certs/generate.sh` when it isn't there
+fn open(path: &str) -> io::Result<BufReader<File>> {
+    File::open(path).map(BufReader::new).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("{}: {}, run certs/generate.sh to create it", path, e),
+        )
+    })
+}
+
+/// The certificate chain, leaf first, and its key. Keys can be pkcs8
+/// (`BEGIN PRIVATE KEY`) or pkcs1 (`BEGIN RSA PRIVATE KEY`).
+fn tls_config() -> io::Result<ServerConfig> {
+    let chain = certs(&mut open(CERT)?)
+        .map_err(|_| invalid(format!("{}: invalid certificate", CERT)))?;
+    if chain.is_empty() {
+        return Err(invalid(format!("{}: no certificate found", CERT)));
+    }
+    let mut keys = pkcs8_private_keys(&mut open(KEY)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = rsa_private_keys(&mut open(KEY)?).unwrap_or_default();
+    }
+    let key = keys
+        .pop()
+        .ok_or_else(|| invalid(format!("{}: no pkcs8 or rsa private key found", KEY)))?;
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config
+        .set_single_cert(chain, key)
+        .map_err(|e| invalid(format!("{}: {}", KEY, e)))?;
+    Ok(config)
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,tls_rustls=info");
+    env_logger::init();
+
+    let config = tls_config()?;
+
+    let mut server = web::server(|| {
+        App::new()
+            .wrap(RedirectToHttps::new(HTTPS_PORT))
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+    })
+    .bind_rustls(("127.0.0.1", HTTPS_PORT), config)?;
+
+    if let Ok(addr) = std::env::var("HTTP_ADDR") {
+        log::info!("redirecting http on {} to https", addr);
+        server = server.bind(addr)?;
+    }
+    server.run().await
+}
@@ -0,0 +1,90 @@
+//! Sends requests that came in over plain http to the same url on https.
+//!
+//! Both listeners serve the same app, `AppConfig::secure` tells them apart.
+//! The redirect is a `308`, unlike `301` it keeps the method and body of a
+//! `POST`.
+use std::task::{Context, Poll};
+
+use futures::future::{ok, Either, Ready};
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, HttpResponse};
+use ntex::{Service, Transform};
+
+pub struct RedirectToHttps {
+    port: u16,
+}
+
+impl RedirectToHttps {
+    /// Redirects to the https listener on `port`
+    pub fn new(port: u16) -> Self {
+        RedirectToHttps { port }
+    }
+}
+
+impl<S, Err> Transform<S> for RedirectToHttps
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RedirectToHttpsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RedirectToHttpsMiddleware {
+            service,
+            port: self.port,
+        })
+    }
+}
+
+pub struct RedirectToHttpsMiddleware<S> {
+    service: S,
+    port: u16,
+}
+
+impl<S, Err> Service for RedirectToHttpsMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = Either<S::Future, Ready<Result<WebResponse, Error>>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        if req.app_config().secure() {
+            return Either::Left(self.service.call(req));
+        }
+
+        // the host the client asked for, with the port of the https listener
+        let info = req.connection_info();
+        let host = info.host();
+        let host = match host.rfind(':') {
+            Some(colon) if !host.ends_with(']') => &host[..colon],
+            _ => host,
+        };
+        let path = req
+            .uri()
+            .path_and_query()
+            .map(|path| path.as_str())
+            .unwrap_or("/");
+        let location = match self.port {
+            443 => format!("https://{}{}", host, path),
+            port => format!("https://{}:{}{}", host, port, path),
+        };
+        drop(info);
+
+        let res = HttpResponse::PermanentRedirect()
+            .header(header::LOCATION, location)
+            .finish();
+        Either::Right(ok(req.into_response(res)))
+    }
+}
@@ -0,0 +1,11 @@
+#!/usr/bin/env bash
+# Generate a self-signed certificate and key for every virtual host.
+set -e
+cd "$(dirname "$0")"
+
+for host in one.localhost two.localhost default.localhost; do
+    openssl req -x509 -newkey rsa:2048 -nodes -days 3650 \
+        -keyout "$host.key.pem" -out "$host.cert.pem" \
+        -subj "/CN=$host" -addext "subjectAltName=DNS:$host" 2>/dev/null
+    echo "generated $host.cert.pem and $host.key.pem"
+done
@@ -0,0 +1,145 @@
+//! Several virtual hosts with their own certificates behind one TLS listener.
+//!
+//! `SniResolver` implements rustls' `ResolvesServerCert` and picks the
+//! certificate from the server name the client sent in its `ClientHello`.
+//! The `on_connect` callback copies the negotiated name from the TLS session
+//! into the request extensions, so handlers can report which host was used.
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::sync::Arc;
+
+use ntex::http::HttpService;
+use ntex::rt::net::TcpStream;
+use ntex::server::rustls::TlsStream;
+use ntex::service::map_config;
+use ntex::web::dev::AppConfig;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use rustls::internal::pemfile::{certs, pkcs8_private_keys};
+use rustls::sign::{self, CertifiedKey};
+use rustls::{ClientHello, NoClientAuth, ResolvesServerCert, ServerConfig};
+
+/// What to do with a client asking for a host we have no certificate for
+#[derive(Debug, Clone, Copy)]
+enum UnknownSni {
+    /// present the default certificate, the browser will show a name mismatch
+    Default,
+    /// abort the handshake
+    Reject,
+}
+
+struct SniResolver {
+    hosts: HashMap<String, CertifiedKey>,
+    default: CertifiedKey,
+    unknown: UnknownSni,
+}
+
+impl ResolvesServerCert for SniResolver {
+    fn resolve(&self, client_hello: ClientHello) -> Option<CertifiedKey> {
+        // clients connecting by ip address don't send a server name
+        let name: Option<&str> = client_hello.server_name().map(|name| name.into());
+
+        if let Some(key) = name.and_then(|name| self.hosts.get(name)) {
+            return Some(key.clone());
+        }
+
+        log::warn!("no certificate for server name {:?}", name);
+        match self.unknown {
+            UnknownSni::Default => Some(self.default.clone()),
+            // returning `None` makes rustls fail the handshake
+            UnknownSni::Reject => None,
+        }
+    }
+}
+
+/// Load a pem certificate chain and its pkcs8 private key
+fn load_certified_key(cert_path: &str, key_path: &str) -> io::Result<CertifiedKey> {
+    let invalid = |msg: &str| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", cert_path, msg),
+        )
+    };
+
+    let chain = certs(&mut BufReader::new(File::open(cert_path)?))
+        .map_err(|_| invalid("invalid certificate"))?;
+    let key = pkcs8_private_keys(&mut BufReader::new(File::open(key_path)?))
+        .map_err(|_| invalid("invalid private key"))?
+        .pop()
+        .ok_or_else(|| invalid("no pkcs8 private key found"))?;
+    let key = sign::any_supported_type(&key).map_err(|_| invalid("unsupported key"))?;
+
+    Ok(CertifiedKey::new(chain, Arc::new(key)))
+}
+
+/// Server name negotiated during the TLS handshake
+#[derive(Clone)]
+struct SniHostname(Option<String>);
+
+async fn index(req: HttpRequest) -> HttpResponse {
+    let sni = req
+        .extensions()
+        .get::<SniHostname>()
+        .and_then(|sni| sni.0.clone())
+        .unwrap_or_else(|| "<none>".to_owned());
+    // http/2 requests carry the host in the uri instead of a `Host` header
+    let host = req
+        .headers()
+        .get("host")
+        .and_then(|h| h.to_str().ok())
+        .or_else(|| req.uri().host())
+        .unwrap_or("<none>");
+
+    HttpResponse::Ok().body(format!("sni hostname: {}\nhost header: {}\n", sni, host))
+}
+
+#[ntex::main]
+async fn main() -> io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,tls_sni=info");
+    env_logger::init();
+
+    let unknown = match std::env::var("UNKNOWN_SNI").as_ref().map(|s| s.as_str()) {
+        Ok("reject") => UnknownSni::Reject,
+        _ => UnknownSni::Default,
+    };
+
+    let mut hosts = HashMap::new();
+    for host in &["one.localhost", "two.localhost"] {
+        let key = load_certified_key(
+            &format!("certs/{}.cert.pem", host),
+            &format!("certs/{}.key.pem", host),
+        )?;
+        hosts.insert(host.to_string(), key);
+    }
+    let default = load_certified_key(
+        "certs/default.localhost.cert.pem",
+        "certs/default.localhost.key.pem",
+    )?;
+    log::info!("unknown server names: {:?}", unknown);
+
+    let mut config = ServerConfig::new(NoClientAuth::new());
+    config.cert_resolver = Arc::new(SniResolver {
+        hosts,
+        default,
+        unknown,
+    });
+
+    // `web::server().bind_rustls()` hides the tls stream, build the http
+    // service by hand to get at the session in `on_connect`
+    ntex::server::build()
+        .bind("tls-sni", "127.0.0.1:8443", move || {
+            HttpService::build()
+                .on_connect(|io: &TlsStream<TcpStream>| {
+                    SniHostname(io.get_ref().1.get_sni_hostname().map(String::from))
+                })
+                .finish(map_config(
+                    App::new()
+                        .wrap(middleware::Logger::default())
+                        .route("/", web::get().to(index)),
+                    |_| AppConfig::default(),
+                ))
+                .rustls(config.clone())
+        })?
+        .run()
+        .await
+}
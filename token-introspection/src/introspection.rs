@@ -0,0 +1,259 @@
+//! Bearer token validation through OAuth2 token introspection (RFC 7662).
+//!
+//! Results are kept in a `TokenCache` so the auth server is only asked once
+//! per token and cache period. A cached token never outlives its `exp` claim.
+//! If the auth server can not be reached the request is rejected, nothing is
+//! let through unverified.
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::client::Client;
+use ntex::http::header;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{self, Error, HttpResponse};
+use ntex::{Service, Transform};
+use serde::{Deserialize, Serialize};
+
+/// Introspection response, only the fields we use
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TokenInfo {
+    pub active: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    /// expiry as seconds since the unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exp: Option<u64>,
+}
+
+struct Entry {
+    info: TokenInfo,
+    expires: Instant,
+}
+
+/// Introspection results shared by all workers
+pub struct TokenCache {
+    entries: Mutex<HashMap<String, Entry>>,
+    /// upper bound for caching an active token
+    ttl: Duration,
+    /// how long an inactive token is remembered
+    negative_ttl: Duration,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        TokenCache {
+            entries: Mutex::new(HashMap::new()),
+            ttl,
+            negative_ttl,
+        }
+    }
+
+    fn get(&self, token: &str) -> Option<TokenInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(token) {
+            Some(entry) if entry.expires > Instant::now() => Some(entry.info.clone()),
+            Some(_) => {
+                entries.remove(token);
+                None
+            }
+            None => None,
+        }
+    }
+
+    /// Remember the result, returns the result as it should be used
+    fn insert(&self, token: String, mut info: TokenInfo) -> TokenInfo {
+        let ttl = if info.active {
+            match info.exp.map(seconds_left) {
+                // already expired, the auth server's clock is ahead of ours
+                Some(0) => {
+                    info.active = false;
+                    self.negative_ttl
+                }
+                Some(left) => self.ttl.min(Duration::from_secs(left)),
+                None => self.ttl,
+            }
+        } else {
+            self.negative_ttl
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        // drop expired entries, so old tokens don't pile up
+        let now = Instant::now();
+        entries.retain(|_, e| e.expires > now);
+        entries.insert(
+            token,
+            Entry {
+                info: info.clone(),
+                expires: now + ttl,
+            },
+        );
+        info
+    }
+}
+
+fn seconds_left(exp: u64) -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    exp.saturating_sub(now)
+}
+
+/// Middleware that requires a valid bearer token.
+///
+/// The token's `TokenInfo` is stored in the request extensions.
+pub struct Introspection {
+    endpoint: Rc<String>,
+    cache: web::types::Data<TokenCache>,
+}
+
+impl Introspection {
+    pub fn new(endpoint: &str, cache: web::types::Data<TokenCache>) -> Self {
+        Introspection {
+            endpoint: Rc::new(endpoint.to_owned()),
+            cache,
+        }
+    }
+}
+
+impl<S, Err> Transform<S> for Introspection
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = IntrospectionMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IntrospectionMiddleware {
+            service: Rc::new(service),
+            inner: Rc::new(Inner {
+                client: Client::build().timeout(Duration::from_secs(2)).finish(),
+                endpoint: self.endpoint.clone(),
+                cache: self.cache.clone(),
+            }),
+        })
+    }
+}
+
+struct Inner {
+    client: Client,
+    endpoint: Rc<String>,
+    cache: web::types::Data<TokenCache>,
+}
+
+impl Inner {
+    async fn introspect(&self, token: &str) -> Result<TokenInfo, String> {
+        let mut res = self
+            .client
+            .post(self.endpoint.as_str())
+            .basic_auth("resource-server", Some("secret"))
+            .send_form(&[("token", token), ("token_type_hint", "access_token")])
+            .await
+            .map_err(|e| e.to_string())?;
+
+        if !res.status().is_success() {
+            return Err(format!("auth server responded with {}", res.status()));
+        }
+        res.json::<TokenInfo>().await.map_err(|e| e.to_string())
+    }
+}
+
+pub struct IntrospectionMiddleware<S> {
+    service: Rc<S>,
+    inner: Rc<Inner>,
+}
+
+impl<S, Err> Service for IntrospectionMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>
+        + 'static,
+    Err: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: Self::Request) -> Self::Future {
+        let service = self.service.clone();
+        let inner = self.inner.clone();
+
+        async move {
+            let token = match bearer_token(&req) {
+                Some(token) => token,
+                None => return Ok(req.into_response(unauthorized(None))),
+            };
+
+            let info = match inner.cache.get(&token) {
+                Some(info) => {
+                    log::info!("introspection cache hit");
+                    info
+                }
+                None => {
+                    log::info!("introspection cache miss");
+                    match inner.introspect(&token).await {
+                        Ok(info) => inner.cache.insert(token, info),
+                        // fail closed, and don't cache the failure
+                        Err(e) => {
+                            log::error!("token introspection failed: {}", e);
+                            return Ok(req.into_response(
+                                HttpResponse::ServiceUnavailable()
+                                    .header(header::RETRY_AFTER, "5")
+                                    .finish()
+                                    .into_body(),
+                            ));
+                        }
+                    }
+                }
+            };
+
+            if !info.active {
+                return Ok(req.into_response(unauthorized(Some("invalid_token"))));
+            }
+
+            req.extensions_mut().insert(info);
+            service.call(req).await
+        }
+        .boxed_local()
+    }
+}
+
+fn bearer_token<Err>(req: &WebRequest<Err>) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let mut parts = value.splitn(2, ' ');
+    match (parts.next(), parts.next()) {
+        (Some(scheme), Some(token)) if scheme.eq_ignore_ascii_case("bearer") => {
+            Some(token.trim().to_owned())
+        }
+        _ => None,
+    }
+}
+
+fn unauthorized(error: Option<&str>) -> ntex::http::Response {
+    let challenge = match error {
+        Some(error) => format!("Bearer error=\"{}\"", error),
+        None => "Bearer".to_owned(),
+    };
+    HttpResponse::Unauthorized()
+        .header(header::WWW_AUTHENTICATE, challenge)
+        .finish()
+        .into_body()
+}
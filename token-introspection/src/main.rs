@@ -0,0 +1,213 @@
+//! Opaque bearer tokens validated with a cached introspection call.
+//!
+//! The api listens on 8080, and unless `INTROSPECTION_URL` is set a mock auth
+//! server with a few hard coded tokens is started on 8081.
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse};
+use serde::Deserialize;
+
+mod introspection;
+
+use introspection::{Introspection, TokenCache, TokenInfo};
+
+/// Protected resource, shows what the auth server told us about the token
+async fn me(req: HttpRequest) -> HttpResponse {
+    match req.extensions().get::<TokenInfo>() {
+        Some(info) => HttpResponse::Ok().json(info),
+        None => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+#[derive(Deserialize)]
+struct IntrospectionRequest {
+    token: String,
+}
+
+/// Stand-in for the auth server's introspection endpoint
+async fn mock_introspect(form: web::types::Form<IntrospectionRequest>) -> HttpResponse {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let info = match form.token.as_str() {
+        "valid-token" => active("alice", now + 3600),
+        // expires before the cache ttl runs out
+        "short-lived" => active("bob", now + 10),
+        _ => TokenInfo {
+            active: false,
+            sub: None,
+            scope: None,
+            client_id: None,
+            exp: None,
+        },
+    };
+    log::info!("auth server: introspected token, active: {}", info.active);
+    HttpResponse::Ok().json(&info)
+}
+
+fn active(sub: &str, exp: u64) -> TokenInfo {
+    TokenInfo {
+        active: true,
+        sub: Some(sub.to_owned()),
+        scope: Some("read".to_owned()),
+        client_id: Some("demo-client".to_owned()),
+        exp: Some(exp),
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,token_introspection=info");
+    env_logger::init();
+
+    let endpoint = std::env::var("INTROSPECTION_URL").ok();
+    if endpoint.is_none() {
+        web::server(|| App::new().route("/introspect", web::post().to(mock_introspect)))
+            .bind("127.0.0.1:8081")?
+            .workers(1)
+            .run();
+    }
+    let endpoint =
+        endpoint.unwrap_or_else(|| "http://127.0.0.1:8081/introspect".to_owned());
+
+    // active tokens are cached for at most a minute, unknown ones for 10s
+    let cache = web::types::Data::new(TokenCache::new(
+        Duration::from_secs(60),
+        Duration::from_secs(10),
+    ));
+
+    web::server(move || {
+        App::new()
+            .wrap(Introspection::new(&endpoint, cache.clone()))
+            .wrap(middleware::Logger::default())
+            .route("/me", web::get().to(me))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use ntex::http::{header, StatusCode};
+    use ntex::web::test;
+
+    /// Mock auth server that counts introspection calls.
+    ///
+    /// "valid-token" is active for an hour, "short-lived" for two seconds.
+    fn auth_server(calls: Arc<AtomicUsize>) -> test::TestServer {
+        test::server(move || {
+            let calls = calls.clone();
+            App::new().route(
+                "/introspect",
+                web::post().to(move |form: web::types::Form<IntrospectionRequest>| {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs();
+                    let info = match form.token.as_str() {
+                        "valid-token" => active("alice", now + 3600),
+                        "short-lived" => active("bob", now + 2),
+                        _ => TokenInfo {
+                            active: false,
+                            sub: None,
+                            scope: None,
+                            client_id: None,
+                            exp: None,
+                        },
+                    };
+                    async move { HttpResponse::Ok().json(&info) }
+                }),
+            )
+        })
+    }
+
+    fn request(token: &str) -> ntex::http::Request {
+        test::TestRequest::get()
+            .uri("/me")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .to_request()
+    }
+
+    macro_rules! api {
+        ($endpoint:expr) => {
+            test::init_service(
+                App::new()
+                    .wrap(Introspection::new(
+                        $endpoint,
+                        web::types::Data::new(TokenCache::new(
+                            Duration::from_secs(300),
+                            Duration::from_secs(300),
+                        )),
+                    ))
+                    .route("/me", web::get().to(me)),
+            )
+            .await
+        };
+    }
+
+    #[ntex::test]
+    async fn test_cache_hit_and_miss() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let srv = auth_server(calls.clone());
+        let app = api!(&srv.url("/introspect"));
+
+        // miss, asks the auth server
+        let resp = test::call_service(&app, request("valid-token")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // hit, answered from the cache
+        let info: TokenInfo =
+            test::read_response_json(&app, request("valid-token")).await;
+        assert_eq!(info.sub.as_deref(), Some("alice"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // inactive tokens are rejected, and cached as well
+        for _ in 0..2 {
+            let resp = test::call_service(&app, request("revoked")).await;
+            assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // no token at all never reaches the auth server
+        let req = test::TestRequest::get().uri("/me").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[ntex::test]
+    async fn test_cache_honors_exp() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let srv = auth_server(calls.clone());
+        let app = api!(&srv.url("/introspect"));
+
+        for _ in 0..2 {
+            let resp = test::call_service(&app, request("short-lived")).await;
+            assert_eq!(resp.status(), StatusCode::OK);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // the cache ttl is 5 minutes, but the token expires before that
+        ntex::rt::time::delay_for(Duration::from_millis(2500)).await;
+        test::call_service(&app, request("short-lived")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[ntex::test]
+    async fn test_auth_server_down() {
+        // nothing listens on port 1
+        let app = api!("http://127.0.0.1:1/introspect");
+
+        let resp = test::call_service(&app, request("valid-token")).await;
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,136 @@
+//! Samples a fraction of the requests for detailed traces, see `sampling`.
+//! `SAMPLE_RATE` sets the rate at startup, `PUT /admin/sampling` while
+//! running.
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::web::{self, App, HttpResponse};
+use serde::Deserialize;
+
+mod sampling;
+
+use sampling::{Sampler, Sampling, Trace};
+
+/// Default for `SAMPLE_RATE`
+const SAMPLE_RATE: f64 = 0.1;
+
+/// Pretends to load a user and their orders, order 13 is the one that
+/// always fails
+async fn orders(id: web::types::Path<u32>, trace: Trace) -> HttpResponse {
+    delay_for(Duration::from_millis(5)).await;
+    trace.crumb("loaded user");
+
+    if *id == 13 {
+        trace.crumb("orders query failed");
+        return HttpResponse::InternalServerError()
+            .json(&serde_json::json!({ "error": "orders database unavailable" }));
+    }
+    delay_for(Duration::from_millis(20)).await;
+    let orders: Vec<u32> = (1..=*id % 5).map(|n| *id * 100 + n).collect();
+    trace.note("queried orders", || {
+        format!("{} orders: {:?}", orders.len(), orders)
+    });
+
+    HttpResponse::Ok().json(&orders)
+}
+
+async fn stats(sampler: web::types::Data<Sampler>) -> HttpResponse {
+    HttpResponse::Ok().json(&sampler.stats())
+}
+
+#[derive(Deserialize)]
+struct Rate {
+    rate: f64,
+}
+
+async fn set_rate(
+    rate: web::types::Json<Rate>,
+    sampler: web::types::Data<Sampler>,
+) -> HttpResponse {
+    log::info!("sample rate {} -> {}", sampler.rate(), rate.rate);
+    sampler.set_rate(rate.rate);
+    HttpResponse::Ok().json(&sampler.stats())
+}
+
+fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/orders/{id}", web::get().to(orders)).service(
+        web::resource("/admin/sampling")
+            .route(web::get().to(stats))
+            .route(web::put().to(set_rate)),
+    );
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,trace_sampling=info");
+    env_logger::init();
+
+    let rate = std::env::var("SAMPLE_RATE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(SAMPLE_RATE);
+    let sampler = web::types::Data::new(Sampler::new(rate));
+    log::info!("sampling {}% of the requests", rate * 100.0);
+
+    web::server(move || {
+        App::new()
+            .app_data(sampler.clone())
+            .wrap(Sampling::new(sampler.clone()))
+            .configure(app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+    use sampling::Stats;
+
+    #[ntex::test]
+    async fn test_errors_are_always_sampled() {
+        let sampler = web::types::Data::new(Sampler::new(0.0));
+        let app = test::init_service(
+            App::new()
+                .app_data(sampler.clone())
+                .wrap(Sampling::new(sampler.clone()))
+                .configure(app),
+        )
+        .await;
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        for _ in 0..10 {
+            let res = test::call_service(&app, get("/orders/7")).await;
+            assert_eq!(res.status(), StatusCode::OK);
+        }
+        let res = test::call_service(&app, get("/orders/13")).await;
+        assert_eq!(res.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(
+            sampler.stats(),
+            Stats {
+                rate: 0.0,
+                seen: 11,
+                sampled: 1,
+                errors: 1,
+            }
+        );
+
+        // changed at runtime, every request from now on
+        let req = test::TestRequest::put()
+            .uri("/admin/sampling")
+            .set_json(&serde_json::json!({ "rate": 1.0 }))
+            .to_request();
+        let res = test::call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        for _ in 0..5 {
+            test::call_service(&app, get("/orders/7")).await;
+        }
+        let stats = sampler.stats();
+        assert_eq!(stats.rate, 1.0);
+        // the admin request is sampled at the old rate
+        assert_eq!((stats.seen, stats.sampled, stats.errors), (17, 6, 1));
+    }
+}
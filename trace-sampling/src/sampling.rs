@@ -0,0 +1,266 @@
+//! Detailed traces for a sample of the requests, a counter for the rest.
+//!
+//! The `Sampling` middleware decides when a request arrives, with the rate
+//! the shared `Sampler` has right now, it can be changed while the server
+//! runs. Every request gets a `Trace` in its extensions:
+//!
+//! * `Trace::crumb` records a static label and the time, cheap enough for
+//!   every request
+//! * `Trace::note` takes a closure, it only runs for sampled requests, for
+//!   details that cost something to put together
+//!
+//! Sampled requests log their trail when they complete. So do requests that
+//! fail, whatever the rate, with their crumbs, the notes were skipped. The
+//! trail of the rest is dropped, they are only counted.
+use std::cell::RefCell;
+use std::fmt::Write;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use derive_more::Display;
+use futures::future::{err, ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::Payload;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{
+    self, Error, ErrorRenderer, FromRequest, HttpRequest, HttpResponse, WebResponseError,
+};
+use ntex::{Service, Transform};
+use serde::Serialize;
+
+/// Rates are kept in millionths, to fit an atomic
+const SCALE: u32 = 1_000_000;
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Stats {
+    pub rate: f64,
+    pub seen: u64,
+    pub sampled: u64,
+    /// Sampled because they failed, included in `sampled`
+    pub errors: u64,
+}
+
+/// The sample rate and the counters, shared by every worker
+pub struct Sampler {
+    rate: AtomicU32,
+    seen: AtomicU64,
+    sampled: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Sampler {
+    pub fn new(rate: f64) -> Self {
+        let sampler = Sampler {
+            rate: AtomicU32::new(0),
+            seen: AtomicU64::new(0),
+            sampled: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+        };
+        sampler.set_rate(rate);
+        sampler
+    }
+
+    /// The fraction of requests to sample, from 0 to 1
+    pub fn set_rate(&self, rate: f64) {
+        let rate = (rate.clamp(0.0, 1.0) * f64::from(SCALE)) as u32;
+        self.rate.store(rate, Ordering::Relaxed);
+    }
+
+    pub fn rate(&self) -> f64 {
+        f64::from(self.rate.load(Ordering::Relaxed)) / f64::from(SCALE)
+    }
+
+    fn decide(&self) -> bool {
+        self.seen.fetch_add(1, Ordering::Relaxed);
+        let rate = self.rate.load(Ordering::Relaxed);
+        rate > 0 && rand::random::<u32>() % SCALE < rate
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            rate: self.rate(),
+            seen: self.seen.load(Ordering::Relaxed),
+            sampled: self.sampled.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+struct Crumb {
+    at: Duration,
+    label: &'static str,
+    note: Option<String>,
+}
+
+struct Trail {
+    started: Instant,
+    sampled: bool,
+    crumbs: Vec<Crumb>,
+}
+
+/// The trail of the current request
+#[derive(Clone)]
+pub struct Trace(Rc<RefCell<Trail>>);
+
+impl Trace {
+    fn new(sampled: bool) -> Self {
+        Trace(Rc::new(RefCell::new(Trail {
+            started: Instant::now(),
+            sampled,
+            crumbs: Vec::new(),
+        })))
+    }
+
+    pub fn crumb(&self, label: &'static str) {
+        let mut trail = self.0.borrow_mut();
+        let at = trail.started.elapsed();
+        trail.crumbs.push(Crumb {
+            at,
+            label,
+            note: None,
+        });
+    }
+
+    /// A crumb with details, `note` only runs when the request is sampled
+    pub fn note<F: FnOnce() -> String>(&self, label: &'static str, note: F) {
+        let mut trail = self.0.borrow_mut();
+        let at = trail.started.elapsed();
+        let note = if trail.sampled { Some(note()) } else { None };
+        trail.crumbs.push(Crumb { at, label, note });
+    }
+
+    fn render(&self) -> String {
+        let trail = self.0.borrow();
+        let mut out = String::new();
+        for crumb in &trail.crumbs {
+            let _ = write!(out, " | +{}ms {}", crumb.at.as_millis(), crumb.label);
+            if let Some(note) = &crumb.note {
+                let _ = write!(out, " ({})", note);
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Display)]
+#[display(fmt = "the request has no trace, Sampling is missing")]
+pub struct NoTrace;
+
+impl WebResponseError for NoTrace {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+impl<Err: ErrorRenderer> FromRequest<Err> for Trace {
+    type Error = NoTrace;
+    type Future = Ready<Result<Self, NoTrace>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        match req.extensions().get::<Trace>() {
+            Some(trace) => ok(trace.clone()),
+            None => err(NoTrace),
+        }
+    }
+}
+
+pub struct Sampling {
+    sampler: web::types::Data<Sampler>,
+}
+
+impl Sampling {
+    pub fn new(sampler: web::types::Data<Sampler>) -> Self {
+        Sampling { sampler }
+    }
+}
+
+impl<S, Err> Transform<S> for Sampling
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SamplingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(SamplingMiddleware {
+            service,
+            sampler: self.sampler.clone(),
+        })
+    }
+}
+
+pub struct SamplingMiddleware<S> {
+    service: S,
+    sampler: web::types::Data<Sampler>,
+}
+
+impl<S, Err> Service for SamplingMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let sampled = self.sampler.decide();
+        let trace = Trace::new(sampled);
+        req.extensions_mut().insert(trace.clone());
+        // cheap to clone, the request line is only formatted for a trace
+        let (method, uri) = (req.method().clone(), req.uri().clone());
+        let sampler = self.sampler.clone();
+
+        self.service
+            .call(req)
+            .map(move |res| {
+                let failed = res
+                    .as_ref()
+                    .map_or(true, |res| res.status().is_server_error());
+                if !sampled && !failed {
+                    return res;
+                }
+
+                let request = format!("{} {}", method, uri.path());
+                let status = match &res {
+                    Ok(res) => res.status().as_u16().to_string(),
+                    Err(e) => e.to_string(),
+                };
+
+                sampler.sampled.fetch_add(1, Ordering::Relaxed);
+                let elapsed = trace.0.borrow().started.elapsed().as_millis();
+                if failed {
+                    sampler.errors.fetch_add(1, Ordering::Relaxed);
+                    log::warn!(
+                        "trace {} {} {}ms{}",
+                        request,
+                        status,
+                        elapsed,
+                        trace.render()
+                    );
+                } else {
+                    log::info!(
+                        "trace {} {} {}ms{}",
+                        request,
+                        status,
+                        elapsed,
+                        trace.render()
+                    );
+                }
+                res
+            })
+            .boxed_local()
+    }
+}
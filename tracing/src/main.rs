@@ -0,0 +1,68 @@
+//! Structured logs in JSON, one line per event, every line of a request
+//! with its `request_id`, see `request_span`.
+use std::time::Duration;
+
+use ntex::rt::time::delay_for;
+use ntex::web::{self, App, HttpResponse};
+use tracing::Instrument;
+
+mod request_span;
+
+use request_span::{RequestId, RequestSpan};
+
+/// Pretends to query a database, in a span of its own
+async fn load_order(id: u32) -> Option<serde_json::Value> {
+    async {
+        delay_for(Duration::from_millis(15)).await;
+        tracing::debug!("query done");
+        if id == 0 {
+            None
+        } else {
+            Some(serde_json::json!({ "id": id, "items": id % 4 + 1 }))
+        }
+    }
+    .instrument(tracing::info_span!("db", query = "select_order"))
+    .await
+}
+
+async fn order(id: web::types::Path<u32>) -> HttpResponse {
+    tracing::info!(order_id = *id, "loading order");
+    match load_order(*id).await {
+        Some(order) => HttpResponse::Ok().json(&order),
+        None => {
+            tracing::warn!(order_id = *id, "no such order");
+            HttpResponse::NotFound()
+                .json(&serde_json::json!({ "error": "no such order" }))
+        }
+    }
+}
+
+/// The id goes on to the services this one calls
+async fn checkout(id: RequestId) -> HttpResponse {
+    tracing::info!(downstream = "payments", "calling with x-request-id {}", id);
+    HttpResponse::InternalServerError()
+        .json(&serde_json::json!({ "error": "payments unavailable", "request_id": id.as_str() }))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "info,tracing_example=debug");
+    // ntex logs with `log`, those records end up here too. `spans` lists
+    // every span an event is in, the request's first
+    tracing_subscriber::fmt()
+        .json()
+        .with_current_span(false)
+        .with_span_list(true)
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    web::server(|| {
+        App::new()
+            .wrap(RequestSpan)
+            .route("/orders/{id}", web::get().to(order))
+            .route("/checkout", web::post().to(checkout))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,157 @@
+//! A `tracing` span around every request, with its id.
+//!
+//! The id comes from the request's `X-Request-Id`, when it looks like one, a
+//! proxy in front or the calling service made it, otherwise a new one is
+//! made. It is a field of the span, so every event logged while the request
+//! is handled carries it, and it goes back in the response's
+//! `X-Request-Id`. The span gets the status and the latency when the
+//! response is ready, and logs one `request completed` event.
+//!
+//! Handlers take a `RequestId` argument to pass the id on to other
+//! services.
+use std::fmt;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use ntex::http::header::{HeaderName, HeaderValue};
+use ntex::http::Payload;
+use ntex::web::dev::{WebRequest, WebResponse};
+use ntex::web::{Error, ErrorRenderer, FromRequest, HttpRequest};
+use ntex::{Service, Transform};
+use rand::Rng;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+pub const HEADER: &str = "x-request-id";
+
+#[derive(Clone, Debug)]
+pub struct RequestId(String);
+
+impl RequestId {
+    fn new() -> Self {
+        RequestId(format!("{:032x}", rand::thread_rng().gen::<u128>()))
+    }
+
+    /// Ids from outside are kept short and printable, they end up in logs
+    fn parse(value: &HeaderValue) -> Option<Self> {
+        let value = value.to_str().ok()?;
+        let valid = !value.is_empty()
+            && value.len() <= 64
+            && value.bytes().all(|b| {
+                b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.'
+            });
+        if valid {
+            Some(RequestId(value.to_owned()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// `RequestSpan` always puts one in the extensions, there is nothing to
+/// fail with, a new id for a request that somehow has none
+impl<Err: ErrorRenderer> FromRequest<Err> for RequestId {
+    type Error = std::convert::Infallible;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let id = req.extensions().get::<RequestId>().cloned();
+        ok(id.unwrap_or_else(RequestId::new))
+    }
+}
+
+pub struct RequestSpan;
+
+impl<S, Err> Transform<S> for RequestSpan
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestSpanMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestSpanMiddleware { service })
+    }
+}
+
+pub struct RequestSpanMiddleware<S> {
+    service: S,
+}
+
+impl<S, Err> Service for RequestSpanMiddleware<S>
+where
+    S: Service<Request = WebRequest<Err>, Response = WebResponse, Error = Error>,
+    S::Future: 'static,
+{
+    type Request = WebRequest<Err>;
+    type Response = WebResponse;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<WebResponse, Error>>;
+
+    fn poll_ready(&self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&self, req: WebRequest<Err>) -> Self::Future {
+        let started = Instant::now();
+        let id = req
+            .headers()
+            .get(HEADER)
+            .and_then(RequestId::parse)
+            .unwrap_or_else(RequestId::new);
+        let span = tracing::info_span!(
+            "request",
+            request_id = %id,
+            method = %req.method(),
+            path = %req.path(),
+            status = Empty,
+            latency_ms = Empty,
+        );
+        let header = HeaderValue::from_str(id.as_str()).unwrap();
+        req.extensions_mut().insert(id);
+
+        // the handler runs inside the span, its events carry the id
+        let fut = {
+            let _entered = span.enter();
+            self.service.call(req)
+        };
+        async move {
+            let mut res = fut.await;
+            let span = tracing::Span::current();
+            span.record("latency_ms", started.elapsed().as_millis() as u64);
+            match &mut res {
+                Ok(res) => {
+                    let status = res.status();
+                    span.record("status", status.as_u16());
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(HEADER), header);
+                    if status.is_server_error() {
+                        tracing::error!("request completed");
+                    } else {
+                        tracing::info!("request completed");
+                    }
+                }
+                Err(e) => tracing::error!(error = %e, "request failed"),
+            }
+            res
+        }
+        .instrument(span)
+        .boxed_local()
+    }
+}
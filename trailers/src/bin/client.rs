@@ -0,0 +1,89 @@
+//! Reads a report and the trailers after it, and checks the body against
+//! the checksum in them. The ntex client skips trailers, this one reads the
+//! response from the socket itself.
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+
+use sha2::{Digest, Sha256};
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_line(reader: &mut impl BufRead) -> io::Result<String> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    Ok(line.trim_end().to_owned())
+}
+
+/// Header lines up to an empty one, names in lower case
+fn read_headers(reader: &mut impl BufRead) -> io::Result<Vec<(String, String)>> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        let colon = line.find(':').ok_or_else(|| invalid("bad header"))?;
+        let (name, value) = line.split_at(colon);
+        headers.push((name.to_ascii_lowercase(), value[1..].trim().to_owned()));
+    }
+}
+
+fn get<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+fn main() -> io::Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "/report?rows=20".to_owned());
+    let mut stream = TcpStream::connect("127.0.0.1:8080")?;
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: 127.0.0.1:8080\r\nTE: trailers\r\nConnection: close\r\n\r\n",
+        path
+    )?;
+    let mut reader = BufReader::new(stream);
+    println!("{}", read_line(&mut reader)?);
+    let headers = read_headers(&mut reader)?;
+
+    let mut body = Vec::new();
+    let trailers = if get(&headers, "transfer-encoding") == Some("chunked") {
+        loop {
+            let line = read_line(&mut reader)?;
+            let size = line.split(';').next().unwrap_or_default();
+            let size =
+                usize::from_str_radix(size, 16).map_err(|_| invalid("bad chunk"))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..])?;
+            read_line(&mut reader)?;
+        }
+        read_headers(&mut reader)?
+    } else {
+        // the server didn't send trailers, the checksum is a header
+        reader.read_to_end(&mut body)?;
+        headers.clone()
+    };
+
+    println!("{} bytes", body.len());
+    for (name, value) in &trailers {
+        if name.starts_with("x-") {
+            println!("{}: {}", name, value);
+        }
+    }
+    let checksum = hex::encode(Sha256::digest(&body));
+    match get(&trailers, "x-content-sha256") {
+        Some(expected) if expected == checksum => println!("checksum matches"),
+        Some(_) => println!("checksum does not match"),
+        None => println!("no checksum"),
+    }
+    Ok(())
+}
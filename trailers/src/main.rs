@@ -0,0 +1,199 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use derive_more::Display;
+use futures::{stream, Stream, StreamExt};
+use ntex::rt::time::delay_for;
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+mod trailers;
+
+/// How long a row of the report takes
+const ROW_DELAY: Duration = Duration::from_millis(10);
+const CHECKSUM: &str = "x-content-sha256";
+const STATUS: &str = "x-status";
+const ERROR: &str = "x-error";
+
+#[derive(Debug, Display)]
+enum ReportError {
+    #[display(fmt = "row {} could not be read", _0)]
+    Row(usize),
+}
+
+impl WebResponseError for ReportError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::InternalServerError()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+#[derive(Deserialize)]
+struct ReportParams {
+    rows: Option<usize>,
+    /// The row that fails
+    fail_at: Option<usize>,
+}
+
+/// A CSV report, a row at a time
+fn rows(
+    count: usize,
+    fail_at: Option<usize>,
+) -> impl Stream<Item = Result<Bytes, ReportError>> {
+    let header = stream::iter(Some(Ok(Bytes::from_static(b"id,name,amount\n"))));
+    header.chain(stream::iter(0..count).then(move |i| async move {
+        delay_for(ROW_DELAY).await;
+        if Some(i) == fail_at {
+            return Err(ReportError::Row(i));
+        }
+        Ok(Bytes::from(format!("{},item-{},{}\n", i, i, i * 7 % 100)))
+    }))
+}
+
+/// The checksum of the body and how it ended come after it, in trailers.
+/// For clients without trailers, the report is put together first, and the
+/// checksum is a header
+async fn report(
+    req: HttpRequest,
+    params: web::types::Query<ReportParams>,
+) -> Result<HttpResponse, ReportError> {
+    let rows = Box::pin(rows(params.rows.unwrap_or(100), params.fail_at));
+
+    if trailers::accepted(&req) {
+        let hasher = Rc::new(RefCell::new(Sha256::new()));
+        let body = {
+            let hasher = hasher.clone();
+            rows.inspect(move |row| {
+                if let Ok(row) = row {
+                    hasher.borrow_mut().update(row);
+                }
+            })
+        };
+        let body = trailers::stream(body, move |err: Option<&ReportError>| {
+            let checksum = hex::encode(hasher.borrow_mut().finalize_reset());
+            match err {
+                None => vec![(CHECKSUM, checksum), (STATUS, "ok".to_owned())],
+                // the checksum of what was sent, a client can still check
+                // the part it has
+                Some(e) => vec![
+                    (CHECKSUM, checksum),
+                    (STATUS, "error".to_owned()),
+                    (ERROR, e.to_string()),
+                ],
+            }
+        });
+        let names = [CHECKSUM, STATUS, ERROR];
+        return Ok(trailers::response("text/csv", &names, Box::pin(body)));
+    }
+
+    let mut body = BytesMut::new();
+    let mut rows = rows;
+    while let Some(row) = rows.next().await {
+        body.extend_from_slice(&row?);
+    }
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv")
+        .header(CHECKSUM, hex::encode(Sha256::digest(&body)))
+        .body(body.freeze()))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,trailers=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/report", web::get().to(report))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ntex::http::header;
+    use ntex::web::test;
+
+    use super::*;
+
+    /// The body and the trailers of a chunked body
+    fn dechunk(mut data: &[u8]) -> (Vec<u8>, HashMap<String, String>) {
+        fn line<'a>(data: &mut &'a [u8]) -> &'a str {
+            let end = data.windows(2).position(|w| w == b"\r\n").unwrap();
+            let line = std::str::from_utf8(&data[..end]).unwrap();
+            *data = &data[end + 2..];
+            line
+        }
+
+        let mut body = Vec::new();
+        loop {
+            let size = usize::from_str_radix(line(&mut data), 16).unwrap();
+            if size == 0 {
+                break;
+            }
+            body.extend_from_slice(&data[..size]);
+            data = &data[size..];
+            assert_eq!(line(&mut data), "");
+        }
+        let mut trailers = HashMap::new();
+        loop {
+            let trailer = line(&mut data);
+            if trailer.is_empty() {
+                assert!(data.is_empty());
+                return (body, trailers);
+            }
+            let (name, value) = trailer.split_at(trailer.find(": ").unwrap());
+            trailers.insert(name.to_owned(), value[2..].to_owned());
+        }
+    }
+
+    async fn get(uri: &str, te: Option<&str>) -> (HashMap<String, String>, Vec<u8>) {
+        let app =
+            test::init_service(App::new().route("/report", web::get().to(report))).await;
+        let mut req = test::TestRequest::with_uri(uri);
+        if let Some(te) = te {
+            req = req.header(header::TE, te);
+        }
+        let res = test::call_service(&app, req.to_request()).await;
+        let headers = res
+            .headers()
+            .iter()
+            .map(|(n, v)| (n.to_string(), v.to_str().unwrap().to_owned()))
+            .collect();
+        (headers, test::read_body(res).await.to_vec())
+    }
+
+    #[ntex::test]
+    async fn checksum_trailer() {
+        let (headers, body) = get("/report?rows=5", Some("trailers")).await;
+        assert_eq!(headers["transfer-encoding"], "chunked");
+        assert_eq!(headers["trailer"], "x-content-sha256, x-status, x-error");
+        let (body, trailers) = dechunk(&body);
+        assert!(body.starts_with(b"id,name,amount\n0,item-0,0\n"));
+        assert_eq!(trailers[CHECKSUM], hex::encode(Sha256::digest(&body)));
+        assert_eq!(trailers[STATUS], "ok");
+
+        let (_, body) = get("/report?rows=5&fail_at=3", Some("trailers")).await;
+        let (body, trailers) = dechunk(&body);
+        assert_eq!(body.iter().filter(|&&b| b == b'\n').count(), 4);
+        assert_eq!(trailers[CHECKSUM], hex::encode(Sha256::digest(&body)));
+        assert_eq!(trailers[STATUS], "error");
+        assert_eq!(trailers[ERROR], "row 3 could not be read");
+    }
+
+    #[ntex::test]
+    async fn checksum_header_without_trailers() {
+        let (headers, body) = get("/report?rows=5", None).await;
+        assert!(!headers.contains_key("trailer"));
+        assert_eq!(headers[CHECKSUM], hex::encode(Sha256::digest(&body)));
+        assert!(body.starts_with(b"id,name,amount\n"));
+    }
+}
@@ -0,0 +1,93 @@
+//! Chunked responses with trailer headers.
+//!
+//! ntex frames streamed bodies with `Transfer-Encoding: chunked` itself and
+//! ends them with an empty last chunk, there is no place for trailers.
+//! A response built with `no_chunking()` is written out as it is, so the
+//! body here does its own framing, and the last chunk carries the trailers:
+//!
+//! ```text
+//! 1a\r\n
+//! <26 bytes>\r\n
+//! 0\r\n
+//! x-content-sha256: 9f86...\r\n
+//! \r\n
+//! ```
+//!
+//! Trailers are only for clients that ask for them with `TE: trailers`, an
+//! HTTP/1.0 client or one that would drop them gets a response without.
+use std::convert::Infallible;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{stream, Stream, StreamExt};
+use ntex::http::{header, Version};
+use ntex::web::{HttpRequest, HttpResponse};
+
+pub fn accepted(req: &HttpRequest) -> bool {
+    req.version() == Version::HTTP_11
+        && req
+            .headers()
+            .get_all(header::TE)
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|te| te.trim().eq_ignore_ascii_case("trailers"))
+}
+
+fn chunk(data: &[u8]) -> Bytes {
+    let mut buf = BytesMut::with_capacity(data.len() + 12);
+    buf.put_slice(format!("{:x}\r\n", data.len()).as_bytes());
+    buf.put_slice(data);
+    buf.put_slice(b"\r\n");
+    buf.freeze()
+}
+
+fn last_chunk(trailers: Vec<(&'static str, String)>) -> Bytes {
+    let mut buf = BytesMut::from(&b"0\r\n"[..]);
+    for (name, value) in trailers {
+        buf.put_slice(name.as_bytes());
+        buf.put_slice(b": ");
+        buf.put_slice(value.as_bytes());
+        buf.put_slice(b"\r\n");
+    }
+    buf.put_slice(b"\r\n");
+    buf.freeze()
+}
+
+/// Streams `body`, then the trailers `trailers` returns. They are asked for
+/// when the body is done, or with the error that ended it
+pub fn stream<S, E, F>(
+    body: S,
+    trailers: F,
+) -> impl Stream<Item = Result<Bytes, Infallible>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    F: FnOnce(Option<&E>) -> Vec<(&'static str, String)>,
+{
+    stream::unfold((body, Some(trailers)), |(mut body, trailers)| async move {
+        let trailers = trailers?;
+        loop {
+            let frame = match body.next().await {
+                // an empty chunk would end the body
+                Some(Ok(data)) if data.is_empty() => continue,
+                Some(Ok(data)) => {
+                    return Some((Ok(chunk(&data)), (body, Some(trailers))))
+                }
+                Some(Err(e)) => last_chunk(trailers(Some(&e))),
+                None => last_chunk(trailers(None)),
+            };
+            return Some((Ok(frame), (body, None)));
+        }
+    })
+}
+
+/// The response for `body`, announcing the trailers in `names`
+pub fn response<S>(content_type: &str, names: &[&str], body: S) -> HttpResponse
+where
+    S: Stream<Item = Result<Bytes, Infallible>> + Unpin + 'static,
+{
+    HttpResponse::Ok()
+        .content_type(content_type)
+        .no_chunking()
+        .header(header::TRANSFER_ENCODING, "chunked")
+        .header(header::TRAILER, names.join(", "))
+        .streaming(body)
+}
@@ -0,0 +1,401 @@
+//! Typed headers and the `TypedHeader` extractor.
+//!
+//! ntex hands out raw `HeaderValue`s, every header here is parsed into its own
+//! type instead. A header that is present but malformed is rejected with a
+//! `400 Bad Request` before the handler runs.
+use std::fmt;
+
+use derive_more::Display;
+use futures::future::{ready, Ready};
+use ntex::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use ntex::http::Payload;
+use ntex::web::{ErrorRenderer, WebResponseError};
+use ntex::web::{FromRequest, HttpRequest, HttpResponse, HttpResponseBuilder};
+
+/// A header that can be parsed from and encoded to a header value
+pub trait Header: Sized {
+    fn name() -> HeaderName;
+
+    fn parse(value: &str) -> Result<Self, String>;
+
+    fn encode(&self) -> HeaderValue;
+}
+
+#[derive(Debug, Display)]
+pub enum HeaderError {
+    #[display(fmt = "missing header `{}`", _0)]
+    Missing(HeaderName),
+    #[display(fmt = "malformed header `{}`: {}", _0, _1)]
+    Malformed(HeaderName, String),
+}
+
+impl WebResponseError for HeaderError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::BadRequest()
+            .json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+/// Parse header `H`, if present
+pub fn get<H: Header>(headers: &HeaderMap) -> Result<Option<H>, HeaderError> {
+    let value = match headers.get(H::name()) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    value
+        .to_str()
+        .map_err(|_| "not visible ASCII".to_owned())
+        .and_then(H::parse)
+        .map(Some)
+        .map_err(|e| HeaderError::Malformed(H::name(), e))
+}
+
+/// Extracts a required header, missing or malformed headers are a `400`
+pub struct TypedHeader<H>(pub H);
+
+impl<H: Header, Err: ErrorRenderer> FromRequest<Err> for TypedHeader<H> {
+    type Error = HeaderError;
+    type Future = Ready<Result<Self, HeaderError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(match get(req.headers()) {
+            Ok(Some(h)) => Ok(TypedHeader(h)),
+            Ok(None) => Err(HeaderError::Missing(H::name())),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+/// Extracts an optional header, only a malformed header is a `400`.
+///
+/// `Option<TypedHeader<H>>` would also accept malformed headers, as `None`.
+pub struct OptionalHeader<H>(pub Option<H>);
+
+impl<H: Header, Err: ErrorRenderer> FromRequest<Err> for OptionalHeader<H> {
+    type Error = HeaderError;
+    type Future = Ready<Result<Self, HeaderError>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(get(req.headers()).map(OptionalHeader))
+    }
+}
+
+/// Set typed headers on a response
+pub trait TypedHeaderExt {
+    fn typed_header<H: Header>(&mut self, header: H) -> &mut Self;
+}
+
+impl TypedHeaderExt for HttpResponseBuilder {
+    fn typed_header<H: Header>(&mut self, header: H) -> &mut Self {
+        self.header(H::name(), header.encode())
+    }
+}
+
+/// `Content-Type`
+#[derive(Debug, PartialEq)]
+pub struct ContentType(pub mime::Mime);
+
+impl Header for ContentType {
+    fn name() -> HeaderName {
+        header::CONTENT_TYPE
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        value.parse().map(ContentType).map_err(|e| e.to_string())
+    }
+
+    fn encode(&self) -> HeaderValue {
+        HeaderValue::from_str(self.0.as_ref()).unwrap()
+    }
+}
+
+/// `Authorization`, the bearer scheme only
+#[derive(Debug, PartialEq)]
+pub struct Authorization {
+    pub token: String,
+}
+
+impl Header for Authorization {
+    fn name() -> HeaderName {
+        header::AUTHORIZATION
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let mut parts = value.splitn(2, ' ');
+        let scheme = parts.next().unwrap_or("");
+        if !scheme.eq_ignore_ascii_case("bearer") {
+            return Err(format!("unsupported scheme `{}`", scheme));
+        }
+        match parts.next().map(str::trim) {
+            Some(token) if !token.is_empty() && !token.contains(' ') => {
+                Ok(Authorization {
+                    token: token.to_owned(),
+                })
+            }
+            _ => Err("expected `Bearer <token>`".to_owned()),
+        }
+    }
+
+    fn encode(&self) -> HeaderValue {
+        HeaderValue::from_str(&format!("Bearer {}", self.token)).unwrap()
+    }
+}
+
+/// An entity tag, as used by `ETag` and `If-Match`
+#[derive(Clone, Debug, PartialEq)]
+pub struct EntityTag {
+    pub weak: bool,
+    pub tag: String,
+}
+
+impl EntityTag {
+    pub fn strong(tag: impl Into<String>) -> Self {
+        EntityTag {
+            weak: false,
+            tag: tag.into(),
+        }
+    }
+
+    /// `If-Match` uses the strong comparison, weak tags never match
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+}
+
+impl fmt::Display for EntityTag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+impl std::str::FromStr for EntityTag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (weak, quoted) = match s.strip_prefix("W/") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        match quoted.strip_prefix('"').and_then(|s| s.strip_suffix('"')) {
+            Some(tag) if !tag.contains('"') => Ok(EntityTag {
+                weak,
+                tag: tag.to_owned(),
+            }),
+            _ => Err(format!("invalid entity tag `{}`", s)),
+        }
+    }
+}
+
+/// `ETag`
+#[derive(Debug, PartialEq)]
+pub struct ETag(pub EntityTag);
+
+impl Header for ETag {
+    fn name() -> HeaderName {
+        header::ETAG
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        value.trim().parse().map(ETag)
+    }
+
+    fn encode(&self) -> HeaderValue {
+        HeaderValue::from_str(&self.0.to_string()).unwrap()
+    }
+}
+
+/// `If-Match`
+#[derive(Debug, PartialEq)]
+pub enum IfMatch {
+    Any,
+    Tags(Vec<EntityTag>),
+}
+
+impl IfMatch {
+    pub fn matches(&self, current: &EntityTag) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Tags(tags) => tags.iter().any(|tag| tag.strong_eq(current)),
+        }
+    }
+}
+
+impl Header for IfMatch {
+    fn name() -> HeaderName {
+        header::IF_MATCH
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        if value.trim() == "*" {
+            return Ok(IfMatch::Any);
+        }
+        value
+            .split(',')
+            .map(|tag| tag.trim().parse())
+            .collect::<Result<_, _>>()
+            .map(IfMatch::Tags)
+    }
+
+    fn encode(&self) -> HeaderValue {
+        let value = match self {
+            IfMatch::Any => "*".to_owned(),
+            IfMatch::Tags(tags) => {
+                let tags: Vec<_> = tags.iter().map(EntityTag::to_string).collect();
+                tags.join(", ")
+            }
+        };
+        HeaderValue::from_str(&value).unwrap()
+    }
+}
+
+/// One range of a `Range: bytes=...` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ByteRange {
+    /// `first-last`, inclusive
+    FromTo(u64, u64),
+    /// `first-`
+    From(u64),
+    /// `-n`, the last n bytes
+    Last(u64),
+}
+
+impl ByteRange {
+    /// First and last byte within a body of `len` bytes, `None` if the range
+    /// can not be satisfied
+    pub fn resolve(self, len: u64) -> Option<(u64, u64)> {
+        let (first, last) = match self {
+            ByteRange::FromTo(first, last) => (first, last.min(len.saturating_sub(1))),
+            ByteRange::From(first) => (first, len.saturating_sub(1)),
+            ByteRange::Last(0) => return None,
+            ByteRange::Last(n) => (len.saturating_sub(n), len.saturating_sub(1)),
+        };
+        if first < len {
+            Some((first, last))
+        } else {
+            None
+        }
+    }
+}
+
+/// `Range`, byte ranges only
+#[derive(Debug, PartialEq)]
+pub struct Range(pub Vec<ByteRange>);
+
+impl Header for Range {
+    fn name() -> HeaderName {
+        header::RANGE
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let ranges = value
+            .trim()
+            .strip_prefix("bytes=")
+            .ok_or_else(|| "only byte ranges are supported".to_owned())?;
+
+        let parse = |n: &str| n.trim().parse::<u64>().map_err(|e| e.to_string());
+        ranges
+            .split(',')
+            .map(|range| {
+                let mut bounds = range.splitn(2, '-');
+                let first = bounds.next().unwrap_or("").trim();
+                let last = bounds
+                    .next()
+                    .ok_or_else(|| format!("invalid range `{}`", range))?
+                    .trim();
+                match (first.is_empty(), last.is_empty()) {
+                    (true, false) => Ok(ByteRange::Last(parse(last)?)),
+                    (false, true) => Ok(ByteRange::From(parse(first)?)),
+                    (false, false) => match (parse(first)?, parse(last)?) {
+                        (first, last) if first <= last => {
+                            Ok(ByteRange::FromTo(first, last))
+                        }
+                        _ => Err(format!("invalid range `{}`", range)),
+                    },
+                    (true, true) => Err(format!("invalid range `{}`", range)),
+                }
+            })
+            .collect::<Result<_, _>>()
+            .map(Range)
+    }
+
+    fn encode(&self) -> HeaderValue {
+        let ranges: Vec<_> = self
+            .0
+            .iter()
+            .map(|range| match range {
+                ByteRange::FromTo(first, last) => format!("{}-{}", first, last),
+                ByteRange::From(first) => format!("{}-", first),
+                ByteRange::Last(n) => format!("-{}", n),
+            })
+            .collect();
+        HeaderValue::from_str(&format!("bytes={}", ranges.join(","))).unwrap()
+    }
+}
+
+/// `Content-Range`, `None` for an unsatisfiable range
+#[derive(Debug, PartialEq)]
+pub struct ContentRange {
+    pub range: Option<(u64, u64)>,
+    pub len: u64,
+}
+
+impl Header for ContentRange {
+    fn name() -> HeaderName {
+        header::CONTENT_RANGE
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid content range `{}`", value);
+        let rest = value.trim().strip_prefix("bytes ").ok_or_else(invalid)?;
+        let mut parts = rest.splitn(2, '/');
+        let range = parts.next().ok_or_else(invalid)?;
+        let len = parts
+            .next()
+            .and_then(|len| len.parse().ok())
+            .ok_or_else(invalid)?;
+        let range = if range == "*" {
+            None
+        } else {
+            let mut bounds = range.splitn(2, '-').map(str::parse);
+            match (bounds.next(), bounds.next()) {
+                (Some(Ok(first)), Some(Ok(last))) => Some((first, last)),
+                _ => return Err(invalid()),
+            }
+        };
+        Ok(ContentRange { range, len })
+    }
+
+    fn encode(&self) -> HeaderValue {
+        let value = match self.range {
+            Some((first, last)) => format!("bytes {}-{}/{}", first, last, self.len),
+            None => format!("bytes */{}", self.len),
+        };
+        HeaderValue::from_str(&value).unwrap()
+    }
+}
+
+/// Custom `X-Api-Version` header
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ApiVersion(pub u16);
+
+impl Header for ApiVersion {
+    fn name() -> HeaderName {
+        HeaderName::from_static("x-api-version")
+    }
+
+    fn parse(value: &str) -> Result<Self, String> {
+        value
+            .trim()
+            .parse()
+            .map(ApiVersion)
+            .map_err(|_| "expected a version number".to_owned())
+    }
+
+    fn encode(&self) -> HeaderValue {
+        HeaderValue::from(self.0)
+    }
+}
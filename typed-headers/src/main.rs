@@ -0,0 +1,349 @@
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use ntex::http::header;
+use ntex::web::{self, middleware, App, HttpResponse};
+use serde_json::json;
+
+mod headers;
+
+use headers::{
+    ApiVersion, Authorization, ContentRange, ContentType, ETag, EntityTag, IfMatch,
+    OptionalHeader, Range, TypedHeader, TypedHeaderExt,
+};
+
+const TOKEN: &str = "secret-token";
+const FILE: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+struct Document {
+    version: u64,
+    body: serde_json::Value,
+}
+
+impl Document {
+    fn etag(&self) -> EntityTag {
+        EntityTag::strong(format!("v{}", self.version))
+    }
+}
+
+type Store = Mutex<Document>;
+
+/// Reads the custom `X-Api-Version` header, version 1 if it is missing
+async fn get_document(
+    store: web::types::Data<Store>,
+    OptionalHeader(version): OptionalHeader<ApiVersion>,
+) -> HttpResponse {
+    let doc = store.lock().unwrap();
+    let body = match version.unwrap_or(ApiVersion(1)) {
+        ApiVersion(1) => doc.body.clone(),
+        ApiVersion(2) => json!({ "version": doc.version, "data": doc.body }),
+        ApiVersion(v) => {
+            return HttpResponse::BadRequest().json(
+                &json!({ "error": format!("api version {} is not supported", v) }),
+            )
+        }
+    };
+    HttpResponse::Ok()
+        .typed_header(ETag(doc.etag()))
+        .typed_header(version.unwrap_or(ApiVersion(1)))
+        .json(&body)
+}
+
+/// Replaces the document, only with a JSON body and only if the client has
+/// seen the current version
+async fn put_document(
+    store: web::types::Data<Store>,
+    OptionalHeader(auth): OptionalHeader<Authorization>,
+    TypedHeader(content_type): TypedHeader<ContentType>,
+    OptionalHeader(if_match): OptionalHeader<IfMatch>,
+    body: Bytes,
+) -> HttpResponse {
+    match auth {
+        Some(Authorization { ref token }) if token == TOKEN => (),
+        _ => {
+            return HttpResponse::Unauthorized()
+                .header(header::WWW_AUTHENTICATE, "Bearer")
+                .finish()
+        }
+    }
+    if content_type.0.essence_str() != mime::APPLICATION_JSON.essence_str() {
+        return HttpResponse::UnsupportedMediaType().body("expected application/json");
+    }
+
+    let mut doc = store.lock().unwrap();
+    match if_match {
+        None => {
+            return HttpResponse::PreconditionRequired().body("If-Match is required")
+        }
+        Some(if_match) if !if_match.matches(&doc.etag()) => {
+            return HttpResponse::PreconditionFailed()
+                .typed_header(ETag(doc.etag()))
+                .finish()
+        }
+        Some(_) => (),
+    }
+
+    match serde_json::from_slice(&body) {
+        Ok(body) => {
+            doc.body = body;
+            doc.version += 1;
+            HttpResponse::Ok()
+                .typed_header(ETag(doc.etag()))
+                .json(&doc.body)
+        }
+        Err(e) => HttpResponse::BadRequest().json(&json!({ "error": e.to_string() })),
+    }
+}
+
+/// Serves a single byte range, several ranges are answered with the whole
+/// file
+async fn get_file(OptionalHeader(range): OptionalHeader<Range>) -> HttpResponse {
+    let len = FILE.len() as u64;
+    let range = match range {
+        Some(Range(ranges)) if ranges.len() == 1 => ranges[0],
+        _ => {
+            return HttpResponse::Ok()
+                .header(header::ACCEPT_RANGES, "bytes")
+                .body(FILE)
+        }
+    };
+
+    match range.resolve(len) {
+        Some((first, last)) => HttpResponse::PartialContent()
+            .typed_header(ContentRange {
+                range: Some((first, last)),
+                len,
+            })
+            .body(&FILE[first as usize..=last as usize]),
+        None => HttpResponse::RangeNotSatisfiable()
+            .typed_header(ContentRange { range: None, len })
+            .finish(),
+    }
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .service(
+            web::resource("/document")
+                .route(web::get().to(get_document))
+                .route(web::put().to(put_document)),
+        )
+        .route("/file", web::get().to(get_file));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    let store = web::types::Data::new(Mutex::new(Document {
+        version: 1,
+        body: json!({ "title": "Typed headers" }),
+    }));
+
+    web::server(move || {
+        App::new()
+            .app_data(store.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    fn store() -> web::types::Data<Store> {
+        web::types::Data::new(Mutex::new(Document {
+            version: 1,
+            body: json!({ "title": "test" }),
+        }))
+    }
+
+    fn put(if_match: &str) -> test::TestRequest {
+        test::TestRequest::put()
+            .uri("/document")
+            .header(header::AUTHORIZATION, "Bearer secret-token")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::IF_MATCH, if_match)
+            .set_payload(r#"{"title":"changed"}"#)
+    }
+
+    #[ntex::test]
+    async fn test_custom_header() {
+        let app =
+            test::init_service(App::new().app_data(store()).configure(app_config)).await;
+
+        let req = test::TestRequest::get()
+            .uri("/document")
+            .header("x-api-version", "2")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"v1\"");
+        assert_eq!(resp.headers().get("x-api-version").unwrap(), "2");
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body, json!({ "version": 1, "data": { "title": "test" } }));
+
+        let req = test::TestRequest::get()
+            .uri("/document")
+            .header("x-api-version", "two")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(
+            body["error"],
+            "malformed header `x-api-version`: expected a version number"
+        );
+    }
+
+    #[ntex::test]
+    async fn test_if_match() {
+        let app =
+            test::init_service(App::new().app_data(store()).configure(app_config)).await;
+
+        let resp = test::call_service(&app, put("\"v1\"").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"v2\"");
+
+        // stale, and weak tags never match
+        for if_match in &["\"v1\"", "W/\"v2\""] {
+            let resp = test::call_service(&app, put(if_match).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::PRECONDITION_FAILED);
+            assert_eq!(resp.headers().get(header::ETAG).unwrap(), "\"v2\"");
+        }
+
+        let resp = test::call_service(&app, put("\"x\", \"v2\"").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, put("*").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+
+        // not quoted
+        let resp = test::call_service(&app, put("v4").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::put()
+            .uri("/document")
+            .header(header::AUTHORIZATION, "Bearer secret-token")
+            .header(header::CONTENT_TYPE, "application/json")
+            .set_payload("{}")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PRECONDITION_REQUIRED);
+    }
+
+    #[ntex::test]
+    async fn test_content_type_and_authorization() {
+        let app =
+            test::init_service(App::new().app_data(store()).configure(app_config)).await;
+
+        let cases = vec![
+            (
+                header::CONTENT_TYPE,
+                "text/plain",
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (
+                header::CONTENT_TYPE,
+                "application/json; charset=utf-8",
+                StatusCode::OK,
+            ),
+            (header::CONTENT_TYPE, "json", StatusCode::BAD_REQUEST),
+            (
+                header::AUTHORIZATION,
+                "Bearer wrong",
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                header::AUTHORIZATION,
+                "Basic dXNlcg==",
+                StatusCode::BAD_REQUEST,
+            ),
+            (header::AUTHORIZATION, "Bearer", StatusCode::BAD_REQUEST),
+        ];
+        for (name, value, status) in cases {
+            let req = put("*").header(name.clone(), value).to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{}: {}", name, value);
+        }
+
+        let req = test::TestRequest::put()
+            .uri("/document")
+            .header(header::AUTHORIZATION, "Bearer secret-token")
+            .header(header::IF_MATCH, "*")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        assert_eq!(body["error"], "missing header `content-type`");
+    }
+
+    #[ntex::test]
+    async fn test_range() {
+        let app = test::init_service(App::new().configure(app_config)).await;
+
+        let cases = vec![
+            (
+                "bytes=0-4",
+                StatusCode::PARTIAL_CONTENT,
+                "abcde",
+                "bytes 0-4/26",
+            ),
+            (
+                "bytes=23-",
+                StatusCode::PARTIAL_CONTENT,
+                "xyz",
+                "bytes 23-25/26",
+            ),
+            (
+                "bytes=-2",
+                StatusCode::PARTIAL_CONTENT,
+                "yz",
+                "bytes 24-25/26",
+            ),
+            (
+                "bytes=20-100",
+                StatusCode::PARTIAL_CONTENT,
+                "uvwxyz",
+                "bytes 20-25/26",
+            ),
+            (
+                "bytes=26-",
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                "",
+                "bytes */26",
+            ),
+        ];
+        for (range, status, body, content_range) in cases {
+            let req = test::TestRequest::get()
+                .uri("/file")
+                .header(header::RANGE, range)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{}", range);
+            assert_eq!(
+                resp.headers().get(header::CONTENT_RANGE).unwrap(),
+                content_range
+            );
+            assert_eq!(test::read_body(resp).await, body);
+        }
+
+        for range in &["bytes=4-0", "bytes=a-b", "bytes=-", "items=0-4"] {
+            let req = test::TestRequest::get()
+                .uri("/file")
+                .header(header::RANGE, *range)
+                .to_request();
+            let resp = test::call_service(&app, req).await;
+            assert_eq!(resp.status(), StatusCode::BAD_REQUEST, "{}", range);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Upload progress reported over server-sent events.
+//!
+//! The browser picks a random upload id, opens `GET /progress/{id}` as an
+//! `EventSource` and then posts the file to `POST /upload/{id}`. The upload
+//! handler records the number of bytes received in the shared `Uploads`
+//! registry, the SSE endpoint polls that registry and streams the percentage
+//! until the upload is finished.
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{stream, StreamExt, TryStreamExt};
+use ntex::http::header;
+use ntex::web::{self, middleware, App, Error, HttpRequest, HttpResponse};
+use ntex_multipart::Multipart;
+
+/// How often the progress stream checks for new data
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+/// Close the progress stream if the upload doesn't make progress for this long
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a finished upload is kept for a progress stream that comes late
+const FINISHED_RETENTION: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct Progress {
+    received: u64,
+    /// taken from the request's `Content-Length`, includes multipart framing
+    total: Option<u64>,
+    finished: Option<Instant>,
+}
+
+impl Progress {
+    fn percent(&self) -> u64 {
+        match self.total {
+            _ if self.finished.is_some() => 100,
+            Some(total) if total > 0 => (self.received * 100 / total).min(99),
+            _ => 0,
+        }
+    }
+}
+
+/// Progress of all running uploads, keyed by upload id
+#[derive(Default)]
+struct Uploads(Mutex<HashMap<String, Progress>>);
+
+impl Uploads {
+    fn update<F: FnOnce(&mut Progress)>(&self, id: &str, f: F) {
+        let mut uploads = self.0.lock().unwrap();
+        f(uploads.entry(id.to_owned()).or_default());
+    }
+
+    /// The progress stream removes the upload once it sent `done`, with
+    /// nobody watching it is dropped by a later `finish`
+    fn finish(&self, id: &str) {
+        let mut uploads = self.0.lock().unwrap();
+        uploads.retain(|_, p| {
+            p.finished
+                .is_none_or(|at| at.elapsed() < FINISHED_RETENTION)
+        });
+        uploads.entry(id.to_owned()).or_default().finished = Some(Instant::now());
+    }
+
+    /// A progress stream went away, its entry goes too unless an upload is
+    /// running for it, that upload's `finish` takes care of it then
+    fn unwatch(&self, id: &str) {
+        let mut uploads = self.0.lock().unwrap();
+        let running = uploads.get(id).is_some_and(|p| {
+            p.finished.is_none() && (p.total.is_some() || p.received > 0)
+        });
+        if !running {
+            uploads.remove(id);
+        }
+    }
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+async fn upload(
+    id: web::types::Path<String>,
+    req: HttpRequest,
+    mut payload: Multipart,
+    uploads: web::types::Data<Uploads>,
+) -> Result<HttpResponse, Error> {
+    let id = id.into_inner();
+    let total = req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok());
+    uploads.update(&id, |p| p.total = total);
+
+    // marks the upload as finished even if it failed, or the client went
+    // away and the handler is dropped, this closes the progress stream
+    struct Finish<'a>(&'a str, &'a Uploads);
+
+    impl Drop for Finish<'_> {
+        fn drop(&mut self) {
+            self.1.finish(self.0);
+        }
+    }
+
+    let finish = Finish(&id, &uploads);
+    let result = save_files(&id, &mut payload, &uploads).await;
+    drop(finish);
+    let size = result?;
+
+    Ok(HttpResponse::Ok().body(format!("received {} bytes", size)))
+}
+
+async fn save_files(
+    id: &str,
+    payload: &mut Multipart,
+    uploads: &Uploads,
+) -> Result<u64, Error> {
+    let mut size = 0;
+
+    while let Some(mut field) = payload.try_next().await? {
+        let filepath = format!("./tmp/{}-{}", id, size);
+        let mut f = web::block(|| std::fs::File::create(filepath)).await?;
+
+        while let Some(chunk) = field.next().await {
+            let data = chunk?;
+            size += data.len() as u64;
+            uploads.update(id, |p| p.received += data.len() as u64);
+
+            f = web::block(move || f.write_all(&data).map(|_| f)).await?;
+        }
+    }
+    Ok(size)
+}
+
+/// Stream progress percentages as server-sent events
+async fn progress(
+    id: web::types::Path<String>,
+    uploads: web::types::Data<Uploads>,
+) -> HttpResponse {
+    let id = id.into_inner();
+    // the client subscribes before it starts uploading
+    uploads.update(&id, |_| ());
+
+    struct State {
+        id: String,
+        uploads: web::types::Data<Uploads>,
+        last: Option<u64>,
+        last_change: Instant,
+        finished: bool,
+    }
+
+    // a client that disconnects drops the stream, whatever state it is in
+    impl Drop for State {
+        fn drop(&mut self) {
+            self.uploads.unwatch(&self.id);
+        }
+    }
+
+    let state = State {
+        id,
+        uploads,
+        last: None,
+        last_change: Instant::now(),
+        finished: false,
+    };
+
+    let events = stream::unfold(state, |mut st| async move {
+        if st.finished {
+            return None;
+        }
+
+        loop {
+            let (pct, done) = {
+                let uploads = st.uploads.0.lock().unwrap();
+                match uploads.get(&st.id) {
+                    Some(p) => (p.percent(), p.finished.is_some()),
+                    None => (0, true),
+                }
+            };
+
+            if done {
+                st.finished = true;
+                let msg = format!("data: {}\n\nevent: done\ndata: {}\n\n", pct, pct);
+                return Some((Ok::<_, Error>(Bytes::from(msg)), st));
+            }
+
+            if st.last != Some(pct) {
+                st.last = Some(pct);
+                st.last_change = Instant::now();
+                return Some((Ok(Bytes::from(format!("data: {}\n\n", pct))), st));
+            }
+
+            if st.last_change.elapsed() > IDLE_TIMEOUT {
+                log::warn!("upload {} stalled, closing progress stream", st.id);
+                st.uploads.0.lock().unwrap().remove(&st.id);
+                return None;
+            }
+
+            ntex::rt::time::delay_for(POLL_INTERVAL).await;
+        }
+    });
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .no_chunking()
+        .streaming(Box::pin(events))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,upload_progress=info");
+    env_logger::init();
+    std::fs::create_dir_all("./tmp")?;
+
+    // shared by all workers, the upload and the progress stream may be
+    // handled by different ones
+    let uploads = web::types::Data::new(Uploads::default());
+
+    web::server(move || {
+        App::new()
+            .app_data(uploads.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/upload/{id}", web::post().to(upload))
+            .route("/progress/{id}", web::get().to(progress))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
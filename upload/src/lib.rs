@@ -0,0 +1,210 @@
+//! Streams uploaded files to disk, a chunk at a time.
+//!
+//! Each file of a `multipart/form-data` request goes to `<dir>/<uuid>` while
+//! it arrives, and is hashed on the way, the body is never held in memory.
+//! A file over `Limits::file`, or a request over `Limits::total`, is
+//! refused with `413` as soon as it crosses the limit, the rest of the body
+//! is not read. A refused request keeps none of its files.
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use derive_more::Display;
+use futures::TryStreamExt;
+use ntex::http::header::{self, HeaderValue};
+use ntex::web::error::BlockingError;
+use ntex::web::{self, HttpRequest, HttpResponse, WebResponseError};
+use ntex_multipart::{Field, Multipart, MultipartError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub struct Limits {
+    /// Bytes per file
+    pub file: u64,
+    /// Bytes of all files in a request
+    pub total: u64,
+}
+
+pub struct Storage {
+    dir: PathBuf,
+    limits: Limits,
+}
+
+impl Storage {
+    pub fn new(dir: impl Into<PathBuf>, limits: Limits) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Storage { dir, limits })
+    }
+}
+
+#[derive(Debug, Display)]
+enum UploadError {
+    #[display(fmt = "{}", _0)]
+    Multipart(MultipartError),
+    #[display(fmt = "`{}` is not a file", _0)]
+    NotAFile(String),
+    #[display(fmt = "`{}` is larger than {} bytes", _0, _1)]
+    FileTooLarge(String, u64),
+    #[display(fmt = "the upload is larger than {} bytes", _0)]
+    TooLarge(u64),
+    #[display(fmt = "storing `{}` failed: {}", _0, _1)]
+    Disk(String, io::Error),
+}
+
+impl WebResponseError for UploadError {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut res = match self {
+            UploadError::Multipart(_) | UploadError::NotAFile(_) => {
+                HttpResponse::BadRequest()
+            }
+            // refused in the middle of the body, the connection is closed
+            // instead of reading the rest
+            UploadError::FileTooLarge(..) | UploadError::TooLarge(_) => {
+                let mut res = HttpResponse::PayloadTooLarge();
+                res.force_close();
+                res
+            }
+            UploadError::Disk(..) => HttpResponse::InternalServerError(),
+        };
+        res.json(&serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+fn blocking(err: BlockingError<io::Error>) -> io::Error {
+    match err {
+        BlockingError::Error(err) => err,
+        BlockingError::Canceled => io::Error::other("thread pool is gone"),
+    }
+}
+
+/// The files of one request, removed on drop unless the request succeeded
+#[derive(Default)]
+struct Written(Vec<PathBuf>);
+
+impl Written {
+    fn keep(mut self) {
+        self.0.clear();
+    }
+}
+
+impl Drop for Written {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            log::info!("removing {}", path.display());
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// What a part's `form-data; name="docs"; filename="a.pdf"` says
+#[derive(Default)]
+struct Disposition {
+    name: Option<String>,
+    filename: Option<String>,
+}
+
+impl Disposition {
+    fn parse(value: Option<&HeaderValue>) -> Self {
+        let mut disposition = Disposition::default();
+        let value = value.and_then(|v| v.to_str().ok()).unwrap_or_default();
+        for (key, value) in value.split(';').filter_map(|p| p.split_once('=')) {
+            let value = Some(value.trim().trim_matches('"').to_owned());
+            match key.trim() {
+                "name" => disposition.name = value,
+                "filename" => disposition.filename = value,
+                _ => (),
+            }
+        }
+        disposition
+    }
+}
+
+#[derive(Serialize)]
+struct Stored {
+    field: String,
+    filename: String,
+    size: u64,
+    sha256: String,
+    id: String,
+}
+
+async fn store_field(
+    mut field: Field,
+    storage: &Storage,
+    written: &mut Written,
+    total: &mut u64,
+) -> Result<Stored, UploadError> {
+    let Disposition { name, filename } =
+        Disposition::parse(field.headers().get(header::CONTENT_DISPOSITION));
+    let name = name.unwrap_or_default();
+    let filename = filename.ok_or_else(|| UploadError::NotAFile(name.clone()))?;
+
+    // the name the client sent is only metadata, it never becomes a path
+    let id = uuid::Uuid::new_v4().to_string();
+    let path = storage.dir.join(&id);
+    // in `written` before the file is created, any error from here on
+    // removes it
+    written.0.push(path.clone());
+    let disk = |e| UploadError::Disk(filename.clone(), blocking(e));
+
+    let mut file = web::block(move || File::create(path)).await.map_err(disk)?;
+    let mut hasher = Sha256::new();
+    let mut size = 0;
+    while let Some(chunk) = field.try_next().await.map_err(UploadError::Multipart)? {
+        size += chunk.len() as u64;
+        *total += chunk.len() as u64;
+        if size > storage.limits.file {
+            return Err(UploadError::FileTooLarge(filename, storage.limits.file));
+        }
+        if *total > storage.limits.total {
+            return Err(UploadError::TooLarge(storage.limits.total));
+        }
+        hasher.update(&chunk);
+        file = web::block(move || file.write_all(&chunk).map(|_| file))
+            .await
+            .map_err(disk)?;
+    }
+    web::block(move || file.sync_all()).await.map_err(disk)?;
+    log::info!("stored `{}`, {} bytes, as {}", filename, size, id);
+
+    Ok(Stored {
+        field: name,
+        filename,
+        size,
+        sha256: hex::encode(hasher.finalize()),
+        id,
+    })
+}
+
+async fn upload(
+    mut payload: Multipart,
+    storage: web::types::Data<Storage>,
+) -> Result<HttpResponse, UploadError> {
+    let mut written = Written::default();
+    let mut stored = Vec::new();
+    let mut total = 0;
+    while let Some(field) = payload.try_next().await.map_err(UploadError::Multipart)? {
+        match store_field(field, &storage, &mut written, &mut total).await {
+            Ok(file) => stored.push(file),
+            Err(e) => {
+                log::warn!("upload refused: {}", e);
+                return Err(e);
+            }
+        }
+    }
+    written.keep();
+    Ok(HttpResponse::Created().json(&stored))
+}
+
+async fn form() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("form.html"))
+}
+
+/// The routes, the app needs a `Storage` in `Data`
+pub fn app(cfg: &mut web::ServiceConfig) {
+    cfg.route("/", web::get().to(form))
+        .route("/upload", web::post().to(upload));
+}
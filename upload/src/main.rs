@@ -0,0 +1,27 @@
+use ntex::web::{self, middleware, App};
+
+use upload::{Limits, Storage};
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,upload=info");
+    env_logger::init();
+
+    let storage = web::types::Data::new(Storage::new(
+        "uploads",
+        Limits {
+            file: 10 * 1024 * 1024,
+            total: 50 * 1024 * 1024,
+        },
+    )?);
+
+    web::server(move || {
+        App::new()
+            .app_data(storage.clone())
+            .wrap(middleware::Logger::default())
+            .configure(upload::app)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
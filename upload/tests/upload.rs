@@ -0,0 +1,171 @@
+use ntex::http::header;
+use ntex::http::StatusCode;
+use ntex::web::{self, test, App};
+use sha2::{Digest, Sha256};
+
+use upload::{Limits, Storage};
+
+const BOUNDARY: &str = "X-BOUNDARY";
+
+/// A `multipart/form-data` body with a file per `(filename, data)`
+fn multipart(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut body = Vec::new();
+    for (filename, data) in files {
+        body.extend_from_slice(
+            format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; \
+                 filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+                BOUNDARY, filename
+            )
+            .as_bytes(),
+        );
+        body.extend_from_slice(data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+    body
+}
+
+fn request(body: Vec<u8>) -> ntex::http::Request {
+    test::TestRequest::post()
+        .uri("/upload")
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", BOUNDARY),
+        )
+        .set_payload(body)
+        .to_request()
+}
+
+async fn json(res: ntex::web::dev::WebResponse) -> serde_json::Value {
+    serde_json::from_slice(&test::read_body(res).await).unwrap()
+}
+
+/// `upload-<name>-<pid>` in the temp dir, left over from an earlier run
+/// it is removed first
+fn dir(name: &str) -> std::path::PathBuf {
+    let dir =
+        std::env::temp_dir().join(format!("upload-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Files `Storage` wrote, uploads are named by their id
+fn count_files(dir: &std::path::Path) -> usize {
+    std::fs::read_dir(dir).unwrap().count()
+}
+
+#[ntex::test]
+async fn test_files_are_stored_with_metadata() {
+    let dir = dir("stored");
+    let storage = Storage::new(
+        &dir,
+        Limits {
+            file: 1024,
+            total: 4096,
+        },
+    )
+    .unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::types::Data::new(storage))
+            .configure(upload::app),
+    )
+    .await;
+
+    let notes = b"first line\nsecond line\n".to_vec();
+    // not valid utf-8, and with a name that tries to leave the directory
+    let image: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+    let body = multipart(&[("notes.txt", &notes), ("../../etc/passwd", &image)]);
+    let res = test::call_service(&app, request(body)).await;
+    assert_eq!(res.status(), StatusCode::CREATED);
+    let stored = json(res).await;
+
+    for (stored, (filename, data)) in stored
+        .as_array()
+        .unwrap()
+        .iter()
+        .zip(&[("notes.txt", &notes), ("../../etc/passwd", &image)])
+    {
+        assert_eq!(stored["field"], "file");
+        assert_eq!(stored["filename"], *filename);
+        assert_eq!(stored["size"], data.len());
+        assert_eq!(stored["sha256"], hex::encode(Sha256::digest(data)));
+        // under its id, whatever the filename said
+        let id = stored["id"].as_str().unwrap();
+        assert_eq!(&std::fs::read(dir.join(id)).unwrap(), *data);
+    }
+    assert_eq!(count_files(&dir), 2);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[ntex::test]
+async fn test_limits_keep_nothing() {
+    let dir = dir("limits");
+    let storage = Storage::new(
+        &dir,
+        Limits {
+            file: 100,
+            total: 150,
+        },
+    )
+    .unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::types::Data::new(storage))
+            .configure(upload::app),
+    )
+    .await;
+
+    let res =
+        test::call_service(&app, request(multipart(&[("big.bin", &[0; 101])]))).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(!res.response().keep_alive());
+    let body = json(res).await;
+    assert_eq!(body["error"], "`big.bin` is larger than 100 bytes");
+
+    // each file is fine, together they are not, the first isn't kept either
+    let body = multipart(&[("a.bin", &[1; 80]), ("b.bin", &[2; 80])]);
+    let res = test::call_service(&app, request(body)).await;
+    assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let body = json(res).await;
+    assert_eq!(body["error"], "the upload is larger than 150 bytes");
+    assert_eq!(count_files(&dir), 0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[ntex::test]
+async fn test_fields_must_be_files() {
+    let dir = dir("fields");
+    let storage = Storage::new(
+        &dir,
+        Limits {
+            file: 100,
+            total: 100,
+        },
+    )
+    .unwrap();
+    let app = test::init_service(
+        App::new()
+            .app_data(web::types::Data::new(storage))
+            .configure(upload::app),
+    )
+    .await;
+
+    let body = format!(
+        "--{0}\r\nContent-Disposition: form-data; name=\"comment\"\r\n\r\nhi\r\n--{0}--\r\n",
+        BOUNDARY
+    );
+    let res = test::call_service(&app, request(body.into_bytes())).await;
+    assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+    let req = test::TestRequest::get().uri("/").to_request();
+    let form = test::read_response(&app, req).await;
+    assert!(std::str::from_utf8(&form)
+        .unwrap()
+        .contains("multipart/form-data"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
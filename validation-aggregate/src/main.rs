@@ -0,0 +1,283 @@
+use ntex::web::{self, middleware, App, HttpRequest, HttpResponse, WebResponseError};
+use serde::{Deserialize, Serialize};
+
+/// One failed check, `field` is the path from the top of the body, like
+/// `items[1].quantity`
+#[derive(Debug, Serialize)]
+struct FieldError {
+    field: String,
+    code: &'static str,
+    message: String,
+}
+
+/// Everything that is wrong with a body, answered with
+/// `422 Unprocessable Entity`
+#[derive(Debug)]
+struct ValidationErrors(Vec<FieldError>);
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "validation failed for {} field(s)", self.0.len())
+    }
+}
+
+impl WebResponseError for ValidationErrors {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        HttpResponse::UnprocessableEntity().json(&serde_json::json!({
+            "error": self.to_string(),
+            "errors": self.0,
+        }))
+    }
+}
+
+/// A type that can check itself. Implementations report every problem to the
+/// `Validator` and don't stop at the first one
+trait Validate {
+    fn validate(&self, v: &mut Validator);
+}
+
+/// Collects errors while walking a value, and knows where in it it is
+#[derive(Default)]
+struct Validator {
+    path: Vec<String>,
+    errors: Vec<FieldError>,
+}
+
+impl Validator {
+    /// Records an error for `field` unless `ok`
+    fn check(&mut self, field: &str, ok: bool, code: &'static str, message: &str) {
+        if !ok {
+            let field = self.path_to(field);
+            self.errors.push(FieldError {
+                field,
+                code,
+                message: message.to_owned(),
+            });
+        }
+    }
+
+    fn required(&mut self, field: &str, value: &str) {
+        self.check(
+            field,
+            !value.trim().is_empty(),
+            "required",
+            "must not be empty",
+        );
+    }
+
+    fn range(&mut self, field: &str, value: i64, min: i64, max: i64) {
+        let msg = format!("must be between {} and {}", min, max);
+        self.check(field, value >= min && value <= max, "out_of_range", &msg);
+    }
+
+    /// Validates a nested value, its errors are prefixed with `field`
+    fn nested<T: Validate>(&mut self, field: &str, value: &T) {
+        self.path.push(field.to_owned());
+        value.validate(self);
+        self.path.pop();
+    }
+
+    /// Validates every item, their errors are prefixed with `field[index]`
+    fn each<T: Validate>(&mut self, field: &str, items: &[T]) {
+        for (idx, item) in items.iter().enumerate() {
+            self.nested(&format!("{}[{}]", field, idx), item);
+        }
+    }
+
+    fn path_to(&self, field: &str) -> String {
+        let mut path = self.path.join(".");
+        if !path.is_empty() {
+            path.push('.');
+        }
+        path.push_str(field);
+        path
+    }
+}
+
+/// Runs all checks of `value`, `Err` holds all that failed
+fn validate<T: Validate>(value: &T) -> Result<(), ValidationErrors> {
+    let mut v = Validator::default();
+    value.validate(&mut v);
+    if v.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationErrors(v.errors))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Order {
+    email: String,
+    shipping: Address,
+    items: Vec<LineItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Address {
+    street: String,
+    city: String,
+    postal_code: String,
+    /// ISO 3166-1 alpha-2
+    country: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LineItem {
+    sku: String,
+    quantity: i64,
+    price_cents: i64,
+}
+
+impl Validate for Order {
+    fn validate(&self, v: &mut Validator) {
+        v.check(
+            "email",
+            self.email.contains('@'),
+            "invalid_format",
+            "must be an email address",
+        );
+        v.nested("shipping", &self.shipping);
+        v.check(
+            "items",
+            !self.items.is_empty() && self.items.len() <= 50,
+            "invalid_length",
+            "must have between 1 and 50 items",
+        );
+        v.each("items", &self.items);
+    }
+}
+
+impl Validate for Address {
+    fn validate(&self, v: &mut Validator) {
+        v.required("street", &self.street);
+        v.required("city", &self.city);
+        v.check(
+            "postal_code",
+            self.postal_code.len() == 5
+                && self.postal_code.bytes().all(|b| b.is_ascii_digit()),
+            "invalid_format",
+            "must be 5 digits",
+        );
+        v.check(
+            "country",
+            self.country.len() == 2
+                && self.country.bytes().all(|b| b.is_ascii_uppercase()),
+            "invalid_format",
+            "must be a two letter country code",
+        );
+    }
+}
+
+impl Validate for LineItem {
+    fn validate(&self, v: &mut Validator) {
+        v.required("sku", &self.sku);
+        v.range("quantity", self.quantity, 1, 100);
+        v.range("price_cents", self.price_cents, 1, 1_000_000);
+    }
+}
+
+async fn create_order(
+    order: web::types::Json<Order>,
+) -> Result<HttpResponse, ValidationErrors> {
+    validate(&*order)?;
+
+    let total: i64 = order.items.iter().map(|i| i.quantity * i.price_cents).sum();
+    Ok(HttpResponse::Created().json(&serde_json::json!({
+        "items": order.items.len(),
+        "total_cents": total,
+    })))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/orders", web::post().to(create_order))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::StatusCode;
+    use ntex::web::test;
+
+    #[ntex::test]
+    async fn test_all_errors_reported() {
+        let app = test::init_service(
+            App::new().route("/orders", web::post().to(create_order)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(&serde_json::json!({
+                "email": "nobody",
+                "shipping": {
+                    "street": "1 Main St",
+                    "city": " ",
+                    "postal_code": "1234",
+                    "country": "DE",
+                },
+                "items": [
+                    {"sku": "A-1", "quantity": 2, "price_cents": 500},
+                    {"sku": "", "quantity": 0, "price_cents": 500},
+                    {"sku": "C-3", "quantity": 1, "price_cents": -1},
+                ],
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let body: serde_json::Value =
+            serde_json::from_slice(&test::read_body(resp).await).unwrap();
+        let fields: Vec<_> = body["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| (e["field"].as_str().unwrap(), e["code"].as_str().unwrap()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("email", "invalid_format"),
+                ("shipping.city", "required"),
+                ("shipping.postal_code", "invalid_format"),
+                ("items[1].sku", "required"),
+                ("items[1].quantity", "out_of_range"),
+                ("items[2].price_cents", "out_of_range"),
+            ]
+        );
+    }
+
+    #[ntex::test]
+    async fn test_valid_order() {
+        let app = test::init_service(
+            App::new().route("/orders", web::post().to(create_order)),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/orders")
+            .set_json(&serde_json::json!({
+                "email": "jane@example.com",
+                "shipping": {
+                    "street": "1 Main St",
+                    "city": "Springfield",
+                    "postal_code": "12345",
+                    "country": "US",
+                },
+                "items": [{"sku": "A-1", "quantity": 2, "price_cents": 500}],
+            }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+}
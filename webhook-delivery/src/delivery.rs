@@ -0,0 +1,269 @@
+//! Delivering webhooks, with retries and a dead-letter store.
+//!
+//! Handlers create deliveries and put their ids on a queue, a single
+//! background worker takes them off and sends them, every send in a task of
+//! its own. A failed delivery is put back on the queue after an exponential
+//! backoff with jitter, the worker never waits for it. A delivery that
+//! still fails after `MAX_ATTEMPTS`, or is refused for good with a `4xx`
+//! other than `408` and `429`, goes to the `DeadLetters`.
+//!
+//! Receivers can get a delivery more than once, when their answer is lost,
+//! they tell by `X-Webhook-Id`.
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::StreamExt;
+use ntex::http::client::Client;
+use ntex::http::StatusCode;
+use ntex::rt::time::delay_for;
+use ntex::web::types::Data;
+use rand::Rng;
+use serde::Serialize;
+
+use crate::signature;
+
+pub const MAX_ATTEMPTS: usize = 5;
+/// The wait after the first failure, it doubles with every attempt
+const BASE_DELAY: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Pending,
+    Retrying,
+    Delivered,
+    DeadLettered,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Attempt {
+    pub at: DateTime<Utc>,
+    /// The response status, or why there was none
+    pub result: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub url: String,
+    pub event: String,
+    pub status: Status,
+    pub attempts: Vec<Attempt>,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    body: Bytes,
+    #[serde(skip)]
+    secret: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct DeadLetter {
+    pub delivery_id: u64,
+    pub url: String,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub reason: String,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+pub struct DeadLetters(Mutex<Vec<DeadLetter>>);
+
+impl DeadLetters {
+    pub fn list(&self) -> Vec<DeadLetter> {
+        self.0.lock().unwrap().clone()
+    }
+
+    fn push(&self, letter: DeadLetter) {
+        self.0.lock().unwrap().push(letter);
+    }
+}
+
+pub struct Deliveries {
+    inner: Mutex<HashMap<u64, Delivery>>,
+    queue: mpsc::UnboundedSender<u64>,
+}
+
+/// What happens to a delivery after an attempt
+enum Next {
+    Done,
+    Retry(Duration),
+    DeadLetter(String),
+}
+
+impl Deliveries {
+    /// The deliveries, and the queue for `worker`
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<u64>) {
+        let (tx, rx) = mpsc::unbounded();
+        let deliveries = Deliveries {
+            inner: Mutex::new(HashMap::new()),
+            queue: tx,
+        };
+        (deliveries, rx)
+    }
+
+    /// Queues `body` for `url`, signed with `secret`
+    pub fn enqueue(&self, url: &str, secret: &str, event: &str, body: Bytes) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.len() as u64 + 1;
+        inner.insert(
+            id,
+            Delivery {
+                id,
+                url: url.to_owned(),
+                event: event.to_owned(),
+                status: Status::Pending,
+                attempts: Vec::new(),
+                next_attempt_at: None,
+                body,
+                secret: secret.to_owned(),
+            },
+        );
+        let _ = self.queue.unbounded_send(id);
+        id
+    }
+
+    pub fn get(&self, id: u64) -> Option<Delivery> {
+        self.inner.lock().unwrap().get(&id).cloned()
+    }
+
+    fn record(&self, id: u64, result: Result<(), (String, bool)>) -> (Next, Delivery) {
+        let mut inner = self.inner.lock().unwrap();
+        let delivery = inner.get_mut(&id).unwrap();
+        let (result, next) = match result {
+            Ok(()) => ("delivered".to_owned(), Next::Done),
+            Err((reason, false)) => (reason.clone(), Next::DeadLetter(reason)),
+            Err((reason, true)) if delivery.attempts.len() + 1 >= MAX_ATTEMPTS => {
+                let next = Next::DeadLetter(format!(
+                    "{} attempts, last: {}",
+                    MAX_ATTEMPTS, reason
+                ));
+                (reason, next)
+            }
+            Err((reason, true)) => {
+                (reason, Next::Retry(backoff(delivery.attempts.len() + 1)))
+            }
+        };
+        delivery.attempts.push(Attempt {
+            at: Utc::now(),
+            result,
+        });
+        let (status, next_attempt_at) = match &next {
+            Next::Done => (Status::Delivered, None),
+            Next::Retry(wait) => (
+                Status::Retrying,
+                Some(Utc::now() + chrono::Duration::from_std(*wait).unwrap()),
+            ),
+            Next::DeadLetter(_) => (Status::DeadLettered, None),
+        };
+        delivery.status = status;
+        delivery.next_attempt_at = next_attempt_at;
+        (next, delivery.clone())
+    }
+}
+
+/// After the `attempt`th failure: `BASE_DELAY` doubled for every attempt
+/// before, somewhere between half of that and all of it. Receivers that
+/// failed together don't get their retries together
+fn backoff(attempt: usize) -> Duration {
+    let wait = BASE_DELAY
+        .checked_mul(1 << (attempt - 1).min(16))
+        .map_or(MAX_DELAY, |wait| wait.min(MAX_DELAY));
+    wait / 2 + wait.mul_f64(rand::thread_rng().gen_range(0.0, 0.5))
+}
+
+/// Only these are worth another try, every other `4xx` will be refused
+/// again
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::REQUEST_TIMEOUT
+        || status == StatusCode::TOO_MANY_REQUESTS
+}
+
+async fn attempt(
+    client: Client,
+    delivery: Delivery,
+    deliveries: Data<Deliveries>,
+    dead_letters: Data<DeadLetters>,
+) {
+    let timestamp = Utc::now().timestamp();
+    let res = client
+        .post(delivery.url.as_str())
+        .header("x-webhook-id", delivery.id.to_string())
+        .header("x-webhook-event", delivery.event.as_str())
+        .header(
+            signature::HEADER,
+            signature::sign(delivery.secret.as_bytes(), timestamp, &delivery.body),
+        )
+        .content_type("application/json")
+        .timeout(TIMEOUT)
+        .send_body(delivery.body.clone())
+        .await;
+    let result = match res {
+        Ok(mut res) => {
+            // read to the end, or the connection can't go back to the pool
+            let _ = res.body().await;
+            if res.status().is_success() {
+                Ok(())
+            } else {
+                Err((res.status().to_string(), retryable(res.status())))
+            }
+        }
+        Err(e) => Err((e.to_string(), true)),
+    };
+
+    let (next, delivery) = deliveries.record(delivery.id, result);
+    let attempts = delivery.attempts.len();
+    match next {
+        Next::Done => {
+            log::info!("delivery {} delivered, attempt {}", delivery.id, attempts)
+        }
+        Next::Retry(wait) => {
+            log::warn!(
+                "delivery {} failed, attempt {}: {}, retrying in {:?}",
+                delivery.id,
+                attempts,
+                delivery.attempts[attempts - 1].result,
+                wait
+            );
+            delay_for(wait).await;
+            let _ = deliveries.queue.unbounded_send(delivery.id);
+        }
+        Next::DeadLetter(reason) => {
+            log::error!("delivery {} dead-lettered: {}", delivery.id, reason);
+            dead_letters.push(DeadLetter {
+                delivery_id: delivery.id,
+                payload: serde_json::from_slice(&delivery.body).unwrap_or_default(),
+                url: delivery.url,
+                event: delivery.event,
+                reason,
+                at: Utc::now(),
+            });
+        }
+    }
+}
+
+/// Takes deliveries off the queue until the server stops
+pub async fn worker(
+    mut queue: mpsc::UnboundedReceiver<u64>,
+    deliveries: Data<Deliveries>,
+    dead_letters: Data<DeadLetters>,
+) {
+    let client = Client::default();
+    while let Some(id) = queue.next().await {
+        if let Some(delivery) = deliveries.get(id) {
+            ntex::rt::spawn(attempt(
+                client.clone(),
+                delivery,
+                deliveries.clone(),
+                dead_letters.clone(),
+            ));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use ntex::web::{self, middleware, App, HttpResponse};
+use rand::Rng;
+use serde::Deserialize;
+
+mod delivery;
+mod receiver;
+mod signature;
+
+use delivery::{DeadLetters, Deliveries};
+
+#[derive(Deserialize)]
+struct NewSubscription {
+    url: String,
+    /// A random one when missing
+    secret: Option<String>,
+}
+
+struct Subscription {
+    url: String,
+    secret: String,
+}
+
+#[derive(Default)]
+struct Subscriptions(Mutex<Vec<Subscription>>);
+
+/// The secret is only ever shown here
+async fn subscribe(
+    sub: web::types::Json<NewSubscription>,
+    subscriptions: web::types::Data<Subscriptions>,
+) -> HttpResponse {
+    let sub = sub.into_inner();
+    let secret = sub
+        .secret
+        .unwrap_or_else(|| hex::encode(rand::thread_rng().gen::<[u8; 16]>()));
+    let mut subscriptions = subscriptions.0.lock().unwrap();
+    let body = serde_json::json!({
+        "id": subscriptions.len() + 1,
+        "url": sub.url,
+        "secret": secret,
+    });
+    subscriptions.push(Subscription {
+        url: sub.url,
+        secret,
+    });
+    HttpResponse::Created().json(&body)
+}
+
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    data: serde_json::Value,
+}
+
+/// One delivery for every subscription, sent in the background
+async fn publish(
+    event: web::types::Json<Event>,
+    subscriptions: web::types::Data<Subscriptions>,
+    deliveries: web::types::Data<Deliveries>,
+) -> HttpResponse {
+    let event = event.into_inner();
+    let body = serde_json::json!({
+        "type": event.kind,
+        "data": event.data,
+        "created_at": chrono::Utc::now(),
+    });
+    let body = Bytes::from(serde_json::to_vec(&body).unwrap());
+    let ids: Vec<u64> = subscriptions
+        .0
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|sub| deliveries.enqueue(&sub.url, &sub.secret, &event.kind, body.clone()))
+        .collect();
+    HttpResponse::Accepted().json(&serde_json::json!({ "deliveries": ids }))
+}
+
+async fn delivery(
+    id: web::types::Path<u64>,
+    deliveries: web::types::Data<Deliveries>,
+) -> HttpResponse {
+    match deliveries.get(*id) {
+        Some(delivery) => HttpResponse::Ok().json(&delivery),
+        None => HttpResponse::NotFound()
+            .json(&serde_json::json!({ "error": "no such delivery" })),
+    }
+}
+
+async fn dead_letters(dead_letters: web::types::Data<DeadLetters>) -> HttpResponse {
+    HttpResponse::Ok().json(&dead_letters.list())
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,webhook_delivery=info");
+    env_logger::init();
+
+    let (deliveries, queue) = Deliveries::new();
+    let deliveries = web::types::Data::new(deliveries);
+    let dead = web::types::Data::new(DeadLetters::default());
+    let subscriptions = web::types::Data::new(Subscriptions::default());
+    ntex::rt::spawn(delivery::worker(queue, deliveries.clone(), dead.clone()));
+
+    let server = web::server(move || {
+        App::new()
+            .app_data(deliveries.clone())
+            .app_data(dead.clone())
+            .app_data(subscriptions.clone())
+            .wrap(middleware::Logger::default())
+            .route("/subscriptions", web::post().to(subscribe))
+            .route("/events", web::post().to(publish))
+            .route("/deliveries/{id}", web::get().to(delivery))
+            .route("/dead-letters", web::get().to(dead_letters))
+    })
+    .bind("127.0.0.1:8080")?
+    .run();
+
+    futures::try_join!(server, receiver::start("127.0.0.1:8081")?).map(|_| ())
+}
@@ -0,0 +1,85 @@
+//! Webhook receivers to deliver to, on port 8081:
+//!
+//! * `/ok` checks the signature and accepts
+//! * `/flaky` fails the first two attempts of every delivery with `503`
+//! * `/broken` always fails with `500`
+//! * `/gone` refuses for good with `410`
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use ntex::server::Server;
+use ntex::web::{self, App, HttpRequest, HttpResponse};
+
+use crate::signature;
+
+/// The secret the receivers are subscribed with
+pub const SECRET: &str = "receiver-secret";
+/// Signatures older than this are refused
+const TOLERANCE: u64 = 300;
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> &'a str {
+    req.headers()
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+}
+
+async fn ok(req: HttpRequest, body: Bytes) -> HttpResponse {
+    let now = chrono::Utc::now().timestamp();
+    let signature = header(&req, signature::HEADER);
+    if !signature::verify(SECRET.as_bytes(), signature, &body, now, TOLERANCE) {
+        log::warn!("receiver: bad signature");
+        return HttpResponse::Unauthorized().finish();
+    }
+    log::info!(
+        "receiver: got delivery {}, {}",
+        header(&req, "x-webhook-id"),
+        String::from_utf8_lossy(&body)
+    );
+    HttpResponse::Ok().finish()
+}
+
+// the receivers read the body even when they don't need it, a server that
+// doesn't has to close the connection
+
+async fn flaky(
+    req: HttpRequest,
+    _: Bytes,
+    seen: web::types::Data<Mutex<HashMap<String, u32>>>,
+) -> HttpResponse {
+    let mut seen = seen.lock().unwrap();
+    let attempts = seen
+        .entry(header(&req, "x-webhook-id").to_owned())
+        .or_default();
+    *attempts += 1;
+    if *attempts <= 2 {
+        HttpResponse::ServiceUnavailable().finish()
+    } else {
+        HttpResponse::Ok().finish()
+    }
+}
+
+async fn broken(_: Bytes) -> HttpResponse {
+    HttpResponse::InternalServerError().finish()
+}
+
+async fn gone(_: Bytes) -> HttpResponse {
+    HttpResponse::Gone().finish()
+}
+
+pub fn start(addr: &str) -> std::io::Result<Server> {
+    let seen = web::types::Data::new(Mutex::new(HashMap::<String, u32>::new()));
+
+    Ok(web::server(move || {
+        App::new()
+            .app_data(seen.clone())
+            .route("/ok", web::post().to(ok))
+            .route("/flaky", web::post().to(flaky))
+            .route("/broken", web::post().to(broken))
+            .route("/gone", web::post().to(gone))
+    })
+    .workers(1)
+    .bind(addr)?
+    .run())
+}
@@ -1,245 +1,210 @@
+//! Chat rooms over websockets.
+//!
+//! A `WsSession` is the service that handles the frames of one connection,
+//! the `ChatServer` in `Data` knows every session and which room it is in.
+//! Messages from the server are sent to the client by a task of the
+//! session, with a ping every `HEARTBEAT_INTERVAL`. A client that sends
+//! nothing, pongs included, for `CLIENT_TIMEOUT` is disconnected.
+use std::cell::{Cell, RefCell};
+use std::io;
+use std::rc::Rc;
+use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
-use actix::*;
-use actix_files as fs;
-use actix_web::{web, App, Error, HttpRequest, HttpResponse, HttpServer};
-use actix_web_actors::ws;
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::{ok, Ready};
+use futures::{stream, SinkExt, StreamExt};
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, Service};
 
 mod server;
 
+use server::ChatServer;
+
 /// How often heartbeat pings are sent
 const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
 /// How long before lack of client response causes a timeout
 const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
 
-/// Entry point for our route
-async fn chat_route(
-    req: HttpRequest,
-    stream: web::Payload,
-    srv: web::Data<Addr<server::ChatServer>>,
-) -> Result<HttpResponse, Error> {
-    ws::start(
-        WsChatSession {
-            id: 0,
-            hb: Instant::now(),
-            room: "Main".to_owned(),
-            name: None,
-            addr: srv.get_ref().clone(),
-        },
-        &req,
-        stream,
-    )
+struct WsSession {
+    id: u64,
+    server: web::types::Data<ChatServer>,
+    room: RefCell<String>,
+    name: RefCell<Option<String>>,
+    /// when the client last sent a frame
+    hb: Cell<Instant>,
+    closed: Cell<bool>,
 }
 
-struct WsChatSession {
-    /// unique session id
-    id: usize,
-    /// Client must send ping at least once per 10 seconds (CLIENT_TIMEOUT),
-    /// otherwise we drop connection.
-    hb: Instant,
-    /// joined room
-    room: String,
-    /// peer name
-    name: Option<String>,
-    /// Chat server
-    addr: Addr<server::ChatServer>,
-}
+impl WsSession {
+    /// Leaves the chat server, exactly once
+    fn close(&self, reason: &str) {
+        if !self.closed.replace(true) {
+            log::info!("session {} closed: {}", self.id, reason);
+            self.server.disconnect(self.id);
+        }
+    }
 
-impl Actor for WsChatSession {
-    type Context = ws::WebsocketContext<Self>;
-
-    /// Method is called on actor start.
-    /// We register ws session with ChatServer
-    fn started(&mut self, ctx: &mut Self::Context) {
-        // we'll start heartbeat process on session start.
-        self.hb(ctx);
-
-        // register self in chat server. `AsyncContext::wait` register
-        // future within context, but context waits until this future resolves
-        // before processing any other events.
-        // HttpContext::state() is instance of WsChatSessionState, state is shared
-        // across all routes within application
-        let addr = ctx.address();
-        self.addr
-            .send(server::Connect {
-                addr: addr.recipient(),
-            })
-            .into_actor(self)
-            .then(|res, act, ctx| {
-                match res {
-                    Ok(res) => act.id = res,
-                    // something is wrong with chat server
-                    _ => ctx.stop(),
+    /// `/list`, `/join room`, `/name name`, or a message for the room
+    fn handle_text(&self, text: &str) -> Option<ws::Message> {
+        let text = text.trim();
+        if !text.starts_with('/') {
+            let msg = match &*self.name.borrow() {
+                Some(name) => format!("{}: {}", name, text),
+                None => text.to_owned(),
+            };
+            self.server.message(self.id, &self.room.borrow(), &msg);
+            return None;
+        }
+
+        let mut parts = text.splitn(2, ' ');
+        let (command, arg) = (parts.next().unwrap(), parts.next().map(str::trim));
+        let reply = match (command, arg) {
+            ("/list", _) => {
+                for (room, sessions) in self.server.list_rooms() {
+                    self.server
+                        .reply(self.id, &format!("{} ({})", room, sessions));
                 }
-                fut::ready(())
-            })
-            .wait(ctx);
+                return None;
+            }
+            ("/join", Some(room)) if !room.is_empty() => {
+                self.server.join(self.id, room);
+                *self.room.borrow_mut() = room.to_owned();
+                "joined".to_owned()
+            }
+            ("/join", _) => "!!! room name is required".to_owned(),
+            ("/name", Some(name)) if !name.is_empty() => {
+                *self.name.borrow_mut() = Some(name.to_owned());
+                return None;
+            }
+            ("/name", _) => "!!! name is required".to_owned(),
+            _ => format!("!!! unknown command: {:?}", text),
+        };
+        Some(ws::Message::Text(reply))
     }
+}
 
-    fn stopping(&mut self, _: &mut Self::Context) -> Running {
-        // notify chat server
-        self.addr.do_send(server::Disconnect { id: self.id });
-        Running::Stop
+impl Drop for WsSession {
+    fn drop(&mut self) {
+        self.close("dropped");
     }
 }
 
-/// Handle messages from chat server, we simply send it to peer websocket
-impl Handler<server::Message> for WsChatSession {
-    type Result = ();
+impl Service for WsSession {
+    type Request = ws::Frame;
+    type Response = Option<ws::Message>;
+    type Error = io::Error;
+    type Future = Ready<Result<Option<ws::Message>, io::Error>>;
 
-    fn handle(&mut self, msg: server::Message, ctx: &mut Self::Context) {
-        ctx.text(msg.0);
+    fn poll_ready(&self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
     }
-}
 
-/// WebSocket message handler
-impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsChatSession {
-    fn handle(
-        &mut self,
-        msg: Result<ws::Message, ws::ProtocolError>,
-        ctx: &mut Self::Context,
-    ) {
-        let msg = match msg {
-            Err(_) => {
-                ctx.stop();
-                return;
+    fn call(&self, frame: ws::Frame) -> Self::Future {
+        // any frame shows the client is still there
+        self.hb.set(Instant::now());
+        let item = match frame {
+            ws::Frame::Text(text) => self.handle_text(&String::from_utf8_lossy(&text)),
+            ws::Frame::Ping(msg) => Some(ws::Message::Pong(msg)),
+            ws::Frame::Close(reason) => {
+                self.close("closed by client");
+                Some(ws::Message::Close(reason))
             }
-            Ok(msg) => msg,
+            _ => None,
         };
+        ok(item)
+    }
+}
 
-        println!("WEBSOCKET MESSAGE: {:?}", msg);
-        match msg {
-            ws::Message::Ping(msg) => {
-                self.hb = Instant::now();
-                ctx.pong(&msg);
-            }
-            ws::Message::Pong(_) => {
-                self.hb = Instant::now();
-            }
-            ws::Message::Text(text) => {
-                let m = text.trim();
-                // we check for /sss type of messages
-                if m.starts_with('/') {
-                    let v: Vec<&str> = m.splitn(2, ' ').collect();
-                    match v[0] {
-                        "/list" => {
-                            // Send ListRooms message to chat server and wait for
-                            // response
-                            println!("List rooms");
-                            self.addr
-                                .send(server::ListRooms)
-                                .into_actor(self)
-                                .then(|res, _, ctx| {
-                                    match res {
-                                        Ok(rooms) => {
-                                            for room in rooms {
-                                                ctx.text(room);
-                                            }
-                                        }
-                                        _ => println!("Something is wrong"),
-                                    }
-                                    fut::ready(())
-                                })
-                                .wait(ctx)
-                            // .wait(ctx) pauses all events in context,
-                            // so actor wont receive any new messages until it get list
-                            // of rooms back
-                        }
-                        "/join" => {
-                            if v.len() == 2 {
-                                self.room = v[1].to_owned();
-                                self.addr.do_send(server::Join {
-                                    id: self.id,
-                                    name: self.room.clone(),
-                                });
-
-                                ctx.text("joined");
-                            } else {
-                                ctx.text("!!! room name is required");
-                            }
-                        }
-                        "/name" => {
-                            if v.len() == 2 {
-                                self.name = Some(v[1].to_owned());
-                            } else {
-                                ctx.text("!!! name is required");
-                            }
-                        }
-                        _ => ctx.text(format!("!!! unknown command: {:?}", m)),
-                    }
-                } else {
-                    let msg = if let Some(ref name) = self.name {
-                        format!("{}: {}", name, m)
-                    } else {
-                        m.to_owned()
-                    };
-                    // send message to chat server
-                    self.addr.do_send(server::ClientMessage {
-                        id: self.id,
-                        msg,
-                        room: self.room.clone(),
-                    })
-                }
-            }
-            ws::Message::Binary(_) => println!("Unexpected binary"),
-            ws::Message::Close(_) => {
-                ctx.stop();
-            }
-            ws::Message::Continuation(_) => {
-                ctx.stop();
+enum Outgoing {
+    Message(String),
+    Heartbeat,
+}
+
+/// Sends the server's messages and the pings, until the session is closed
+/// or the client is gone
+async fn forward(
+    mut sink: ws::WebSocketsSink,
+    messages: mpsc::UnboundedReceiver<String>,
+    session: Rc<WsSession>,
+) {
+    let ticks = stream::unfold(
+        ntex::rt::time::interval(HEARTBEAT_INTERVAL),
+        |mut interval| async {
+            interval.tick().await;
+            Some((Outgoing::Heartbeat, interval))
+        },
+    );
+    let mut outgoing = stream::select(messages.map(Outgoing::Message), Box::pin(ticks));
+
+    while let Some(item) = outgoing.next().await {
+        let msg = match item {
+            Outgoing::Message(text) => ws::Message::Text(text),
+            Outgoing::Heartbeat if session.closed.get() => break,
+            Outgoing::Heartbeat if session.hb.get().elapsed() > CLIENT_TIMEOUT => {
+                session.close("heartbeat timed out");
+                let _ = sink.send(Ok(ws::Message::Close(None))).await;
+                break;
             }
-            ws::Message::Nop => (),
+            Outgoing::Heartbeat => ws::Message::Ping(Bytes::new()),
+        };
+        if sink.send(Ok(msg)).await.is_err() {
+            session.close("connection lost");
+            break;
         }
     }
 }
 
-impl WsChatSession {
-    /// helper method that sends ping to client every second.
-    ///
-    /// also this method checks heartbeats from client
-    fn hb(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
-            // check client heartbeats
-            if Instant::now().duration_since(act.hb) > CLIENT_TIMEOUT {
-                // heartbeat timed out
-                println!("Websocket Client heartbeat failed, disconnecting!");
-
-                // notify chat server
-                act.addr.do_send(server::Disconnect { id: act.id });
-
-                // stop actor
-                ctx.stop();
-
-                // don't try to send a ping
-                return;
-            }
+/// Entry point for our route
+async fn chat_route(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    server: web::types::Data<ChatServer>,
+) -> Result<HttpResponse, Error> {
+    let (id, messages) = server.connect();
+    let session = Rc::new(WsSession {
+        id,
+        server: server.clone(),
+        room: RefCell::new(server::MAIN.to_owned()),
+        name: RefCell::new(None),
+        hb: Cell::new(Instant::now()),
+        closed: Cell::new(false),
+    });
+    // the factory is a `Fn`, but it is only called once per websocket
+    let state = RefCell::new(Some((messages, session)));
 
-            ctx.ping(b"");
-        });
-    }
+    ws::start(
+        req,
+        payload,
+        fn_factory_with_config(move |sink: ws::WebSocketsSink| {
+            let (messages, session) = state.borrow_mut().take().unwrap();
+            ntex::rt::spawn(forward(sink, messages, session.clone()));
+            ok::<_, Error>(session)
+        }),
+    )
+    .await
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("../static/websocket.html"))
 }
 
-#[actix_rt::main]
+#[ntex::main]
 async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,websocket_chat=info");
     env_logger::init();
 
-    // Start chat server actor
-    let server = server::ChatServer::default().start();
+    let server = web::types::Data::new(ChatServer::default());
 
-    // Create Http server with websocket support
-    HttpServer::new(move || {
+    web::server(move || {
         App::new()
-            .data(server.clone())
-            // redirect to websocket.html
-            .service(web::resource("/").route(web::get().to(|| {
-                HttpResponse::Found()
-                    .header("LOCATION", "/static/websocket.html")
-                    .finish()
-            })))
-            // websocket
-            .service(web::resource("/ws/").to(chat_route))
-            // static resources
-            .service(fs::Files::new("/static/", "static/"))
+            .app_data(server.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/ws/", web::get().to(chat_route))
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -1,206 +1,121 @@
-//! `ChatServer` is an actor. It maintains list of connection client session.
-//! And manages available rooms. Peers send messages to other peers in same
-//! room through `ChatServer`.
-
-use actix::prelude::*;
-use rand::{self, rngs::ThreadRng, Rng};
-use std::collections::{HashMap, HashSet};
-
-/// Chat server sends this messages to session
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Message(pub String);
-
-/// Message for chat server communications
-
-/// New chat session is created
-#[derive(Message)]
-#[rtype(usize)]
-pub struct Connect {
-    pub addr: Recipient<Message>,
-}
-
-/// Session is disconnected
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Disconnect {
-    pub id: usize,
+//! `ChatServer` keeps the registry of sessions and rooms, shared by all
+//! workers in `Data`. Sessions send messages to the peers in their room
+//! through it, every session has a channel the server writes to.
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use futures::channel::mpsc;
+
+/// The room sessions join when they connect
+pub const MAIN: &str = "Main";
+
+struct Rooms {
+    sessions: HashMap<u64, mpsc::UnboundedSender<String>>,
+    /// session ids by room
+    rooms: BTreeMap<String, HashSet<u64>>,
 }
 
-/// Send message to specific room
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct ClientMessage {
-    /// Id of the client session
-    pub id: usize,
-    /// Peer message
-    pub msg: String,
-    /// Room name
-    pub room: String,
-}
-
-/// List of available rooms
-pub struct ListRooms;
-
-impl actix::Message for ListRooms {
-    type Result = Vec<String>;
-}
+impl Rooms {
+    /// Send message to all sessions in the room, but `skip`
+    fn send(&self, room: &str, message: &str, skip: Option<u64>) {
+        let ids = match self.rooms.get(room) {
+            Some(ids) => ids,
+            None => return,
+        };
+        for id in ids.iter().filter(|id| Some(**id) != skip) {
+            if let Some(tx) = self.sessions.get(id) {
+                let _ = tx.unbounded_send(message.to_owned());
+            }
+        }
+    }
 
-/// Join room, if room does not exists create new one.
-#[derive(Message)]
-#[rtype(result = "()")]
-pub struct Join {
-    /// Client id
-    pub id: usize,
-    /// Room name
-    pub name: String,
+    /// Removes the session from its room, rooms other than `Main` go away
+    /// with the last session
+    fn leave(&mut self, id: u64) -> Option<String> {
+        let room = self
+            .rooms
+            .iter_mut()
+            .find_map(|(room, ids)| if ids.remove(&id) { Some(room) } else { None })
+            .cloned()?;
+        if room != MAIN && self.rooms[&room].is_empty() {
+            self.rooms.remove(&room);
+        }
+        Some(room)
+    }
 }
 
-/// `ChatServer` manages chat rooms and responsible for coordinating chat
-/// session. implementation is super primitive
 pub struct ChatServer {
-    sessions: HashMap<usize, Recipient<Message>>,
-    rooms: HashMap<String, HashSet<usize>>,
-    rng: ThreadRng,
+    rooms: Mutex<Rooms>,
+    ids: AtomicU64,
 }
 
 impl Default for ChatServer {
-    fn default() -> ChatServer {
-        // default room
-        let mut rooms = HashMap::new();
-        rooms.insert("Main".to_owned(), HashSet::new());
-
+    fn default() -> Self {
+        let mut rooms = BTreeMap::new();
+        rooms.insert(MAIN.to_owned(), HashSet::new());
         ChatServer {
-            sessions: HashMap::new(),
-            rooms,
-            rng: rand::thread_rng(),
+            rooms: Mutex::new(Rooms {
+                sessions: HashMap::new(),
+                rooms,
+            }),
+            ids: AtomicU64::new(0),
         }
     }
 }
 
 impl ChatServer {
-    /// Send message to all users in the room
-    fn send_message(&self, room: &str, message: &str, skip_id: usize) {
-        if let Some(sessions) = self.rooms.get(room) {
-            for id in sessions {
-                if *id != skip_id {
-                    if let Some(addr) = self.sessions.get(id) {
-                        let _ = addr.do_send(Message(message.to_owned()));
-                    }
-                }
-            }
-        }
-    }
-}
-
-/// Make actor from `ChatServer`
-impl Actor for ChatServer {
-    /// We are going to use simple Context, we just need ability to communicate
-    /// with other actors.
-    type Context = Context<Self>;
-}
-
-/// Handler for Connect message.
-///
-/// Register new session and assign unique id to this session
-impl Handler<Connect> for ChatServer {
-    type Result = usize;
-
-    fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
-        println!("Someone joined");
-
-        // notify all users in same room
-        self.send_message(&"Main".to_owned(), "Someone joined", 0);
-
-        // register session with random id
-        let id = self.rng.gen::<usize>();
-        self.sessions.insert(id, msg.addr);
-
-        // auto join session to Main room
-        self.rooms
-            .entry("Main".to_owned())
-            .or_insert(HashSet::new())
-            .insert(id);
-
-        // send id back
-        id
+    /// Registers a session in `Main`, returns its id and the receiver for
+    /// the messages to it
+    pub fn connect(&self) -> (u64, mpsc::UnboundedReceiver<String>) {
+        let id = self.ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = mpsc::unbounded();
+        let mut rooms = self.rooms.lock().unwrap();
+        rooms.send(MAIN, "Someone joined", None);
+        rooms.sessions.insert(id, tx);
+        rooms.rooms.entry(MAIN.to_owned()).or_default().insert(id);
+        (id, rx)
     }
-}
-
-/// Handler for Disconnect message.
-impl Handler<Disconnect> for ChatServer {
-    type Result = ();
 
-    fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        println!("Someone disconnected");
-
-        let mut rooms: Vec<String> = Vec::new();
-
-        // remove address
-        if self.sessions.remove(&msg.id).is_some() {
-            // remove session from all rooms
-            for (name, sessions) in &mut self.rooms {
-                if sessions.remove(&msg.id) {
-                    rooms.push(name.to_owned());
-                }
+    /// Ends the session's message stream
+    pub fn disconnect(&self, id: u64) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if rooms.sessions.remove(&id).is_some() {
+            if let Some(room) = rooms.leave(id) {
+                rooms.send(&room, "Someone disconnected", None);
             }
         }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, "Someone disconnected", 0);
-        }
-    }
-}
-
-/// Handler for Message message.
-impl Handler<ClientMessage> for ChatServer {
-    type Result = ();
-
-    fn handle(&mut self, msg: ClientMessage, _: &mut Context<Self>) {
-        self.send_message(&msg.room, msg.msg.as_str(), msg.id);
     }
-}
-
-/// Handler for `ListRooms` message.
-impl Handler<ListRooms> for ChatServer {
-    type Result = MessageResult<ListRooms>;
 
-    fn handle(&mut self, _: ListRooms, _: &mut Context<Self>) -> Self::Result {
-        let mut rooms = Vec::new();
-
-        for key in self.rooms.keys() {
-            rooms.push(key.to_owned())
+    /// Moves the session to `room`, a room that doesn't exist is created
+    pub fn join(&self, id: u64, room: &str) {
+        let mut rooms = self.rooms.lock().unwrap();
+        if let Some(old) = rooms.leave(id) {
+            rooms.send(&old, "Someone disconnected", None);
         }
-
-        MessageResult(rooms)
+        rooms.send(room, "Someone connected", None);
+        rooms.rooms.entry(room.to_owned()).or_default().insert(id);
     }
-}
 
-/// Join room, send disconnect message to old room
-/// send join message to new room
-impl Handler<Join> for ChatServer {
-    type Result = ();
-
-    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
-        let Join { id, name } = msg;
-        let mut rooms = Vec::new();
+    /// Sends to the peers in `room`, not back to the sender
+    pub fn message(&self, id: u64, room: &str, message: &str) {
+        self.rooms.lock().unwrap().send(room, message, Some(id));
+    }
 
-        // remove session from all rooms
-        for (n, sessions) in &mut self.rooms {
-            if sessions.remove(&id) {
-                rooms.push(n.to_owned());
-            }
+    /// Sends to one session only
+    pub fn reply(&self, id: u64, message: &str) {
+        if let Some(tx) = self.rooms.lock().unwrap().sessions.get(&id) {
+            let _ = tx.unbounded_send(message.to_owned());
         }
-        // send message to other users
-        for room in rooms {
-            self.send_message(&room, "Someone disconnected", 0);
-        }
-
-        self.rooms
-            .entry(name.clone())
-            .or_insert(HashSet::new())
-            .insert(id);
+    }
 
-        self.send_message(&name, "Someone connected", id);
+    /// Rooms with the number of sessions in them
+    pub fn list_rooms(&self) -> Vec<(String, usize)> {
+        let rooms = self.rooms.lock().unwrap();
+        rooms
+            .rooms
+            .iter()
+            .map(|(room, ids)| (room.clone(), ids.len()))
+            .collect()
     }
 }
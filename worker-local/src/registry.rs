@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use ntex::web::types::Data;
+use serde::Serialize;
+
+/// All workers' stats, one registry shared by the workers
+#[derive(Default)]
+pub struct Registry {
+    workers: Mutex<Vec<Data<WorkerStats>>>,
+}
+
+/// Written by one worker only, the registry and `/stats` read them
+pub struct WorkerStats {
+    pub index: usize,
+    build: Duration,
+    requests: AtomicUsize,
+    findings: AtomicUsize,
+}
+
+#[derive(Serialize)]
+pub struct WorkerReport {
+    pub worker: usize,
+    pub build_ms: f64,
+    pub requests: usize,
+    pub findings: usize,
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub requests: usize,
+    pub findings: usize,
+    pub workers: Vec<WorkerReport>,
+}
+
+impl Registry {
+    /// Called from the factory closure, once per worker, the index is the
+    /// order in which the workers got there
+    pub fn register(&self, build: Duration) -> Data<WorkerStats> {
+        let mut workers = self.workers.lock().unwrap();
+        let stats = Data::new(WorkerStats {
+            index: workers.len(),
+            build,
+            requests: AtomicUsize::new(0),
+            findings: AtomicUsize::new(0),
+        });
+        workers.push(stats.clone());
+        stats
+    }
+
+    pub fn report(&self) -> Report {
+        let workers: Vec<_> = self
+            .workers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|w| WorkerReport {
+                worker: w.index,
+                build_ms: w.build.as_secs_f64() * 1000.0,
+                requests: w.requests.load(Ordering::Relaxed),
+                findings: w.findings.load(Ordering::Relaxed),
+            })
+            .collect();
+
+        Report {
+            requests: workers.iter().map(|w| w.requests).sum(),
+            findings: workers.iter().map(|w| w.findings).sum(),
+            workers,
+        }
+    }
+}
+
+impl WorkerStats {
+    pub fn record(&self, findings: usize) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.findings.fetch_add(findings, Ordering::Relaxed);
+    }
+}
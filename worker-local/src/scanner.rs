@@ -0,0 +1,71 @@
+use std::time::{Duration, Instant};
+
+use regex::{Regex, RegexSet};
+
+/// What the scanner looks for, by name
+const RULES: &[(&str, &str)] = &[
+    ("email", r"\b[\w.+-]+@[\w-]+(\.[\w-]+)+\b"),
+    (
+        "ipv4",
+        r"\b(25[0-5]|2[0-4]\d|1?\d?\d)(\.(25[0-5]|2[0-4]\d|1?\d?\d)){3}\b",
+    ),
+    ("card", r"\b(\d{4}[ -]?){3}\d{4}\b"),
+    ("aws-key", r"\bAKIA[0-9A-Z]{16}\b"),
+    ("jwt", r"\beyJ[\w-]+\.eyJ[\w-]+\.[\w-]+\b"),
+    ("private-key", r"-----BEGIN ([A-Z]+ )?PRIVATE KEY-----"),
+    ("phone", r"\+\d{1,3}[ -]?\(?\d{2,4}\)?([ -]?\d{2,4}){2,3}\b"),
+];
+
+/// Finds secrets and personal data in a text.
+///
+/// Compiling the rules takes a while. Matching needs scratch space, the
+/// regex crate keeps it for the thread that matched first and hands it to
+/// other threads under a lock, a scanner per worker stays on the fast path.
+pub struct Scanner {
+    set: RegexSet,
+    rules: Vec<(&'static str, Regex)>,
+    built_in: Duration,
+}
+
+#[derive(serde::Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub count: usize,
+}
+
+impl Scanner {
+    pub fn new() -> Self {
+        let started = Instant::now();
+        let set = RegexSet::new(RULES.iter().map(|(_, re)| re)).unwrap();
+        let rules = RULES
+            .iter()
+            .map(|(name, re)| (*name, Regex::new(re).unwrap()))
+            .collect();
+
+        Scanner {
+            set,
+            rules,
+            built_in: started.elapsed(),
+        }
+    }
+
+    pub fn built_in(&self) -> Duration {
+        self.built_in
+    }
+
+    /// The set tells which rules match in one pass, only those are run
+    /// again to count
+    pub fn scan(&self, text: &str) -> Vec<Finding> {
+        self.set
+            .matches(text)
+            .into_iter()
+            .map(|i| {
+                let (rule, re) = &self.rules[i];
+                Finding {
+                    rule,
+                    count: re.find_iter(text).count(),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,162 @@
+//! Coalesces small outbound messages into fewer websocket frames.
+//!
+//! Every frame is a write to the socket, and at tens of thousands of small
+//! messages a second the writes cost more than the messages. The writer of
+//! a connection collects messages in a `Batch` and sends them as one text
+//! frame, one message per line, once the oldest has waited `BATCH_WINDOW`
+//! or the batch reaches `MAX_BATCH_BYTES`.
+//!
+//! Urgent messages skip the batch. They come through a channel of their own
+//! that the writer always looks at first, flush what is batched so far to
+//! keep the order, and go out right away.
+use std::error::Error;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::{FutureExt, SinkExt, StreamExt};
+use ntex::rt::time::{delay_until, Instant};
+use ntex::web::ws;
+
+/// How long a message may wait for others to share its frame
+pub const BATCH_WINDOW: Duration = Duration::from_millis(10);
+/// A batch this large is sent without waiting for the window to end
+pub const MAX_BATCH_BYTES: usize = 32 * 1024;
+
+/// Messages waiting for the next frame
+#[derive(Default)]
+pub struct Batch {
+    buf: String,
+    messages: usize,
+    /// When the oldest message has waited long enough
+    deadline: Option<Instant>,
+}
+
+impl Batch {
+    fn push(&mut self, msg: &str) {
+        if self.messages > 0 {
+            self.buf.push('\n');
+        } else {
+            self.deadline = Some(Instant::now() + BATCH_WINDOW);
+        }
+        self.buf.push_str(msg);
+        self.messages += 1;
+    }
+
+    fn is_full(&self) -> bool {
+        self.buf.len() >= MAX_BATCH_BYTES
+    }
+
+    fn take(&mut self) -> Option<(String, usize)> {
+        if self.messages == 0 {
+            return None;
+        }
+        self.deadline = None;
+        let messages = std::mem::replace(&mut self.messages, 0);
+        Some((std::mem::take(&mut self.buf), messages))
+    }
+}
+
+/// What a connection sent, for the log
+#[derive(Debug, Default)]
+pub struct Sent {
+    pub messages: usize,
+    pub frames: usize,
+}
+
+/// Encoded frames on their way to the response body. A bounded channel,
+/// the writer has to wait when the client reads slowly, and urgent messages
+/// don't end up behind everything sent so far
+pub type Sink = ntex::ws::StreamEncoder<mpsc::Sender<Result<Bytes, Box<dyn Error>>>>;
+
+pub struct Writer {
+    sink: Sink,
+    batch: Batch,
+    batching: bool,
+    pub sent: Sent,
+}
+
+impl Writer {
+    pub fn new(sink: Sink, batching: bool) -> Self {
+        Writer {
+            sink,
+            batch: Batch::default(),
+            batching,
+            sent: Sent::default(),
+        }
+    }
+
+    async fn send(&mut self, text: String, messages: usize) -> Result<(), ()> {
+        self.sent.messages += messages;
+        self.sent.frames += 1;
+        self.sink
+            .send(Ok(ws::Message::Text(text)))
+            .await
+            .map_err(|_| ())
+    }
+
+    async fn flush(&mut self) -> Result<(), ()> {
+        match self.batch.take() {
+            Some((text, messages)) => self.send(text, messages).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn urgent(&mut self, msg: String) -> Result<(), ()> {
+        self.flush().await?;
+        self.send(msg, 1).await
+    }
+
+    /// Sends until a channel is closed or the client is gone
+    pub async fn run(
+        mut self,
+        mut urgent: mpsc::Receiver<String>,
+        mut normal: mpsc::Receiver<String>,
+    ) -> Sent {
+        let _ = self.forward(&mut urgent, &mut normal).await;
+        let _ = self.flush().await;
+        self.sent
+    }
+
+    async fn forward(
+        &mut self,
+        urgent: &mut mpsc::Receiver<String>,
+        normal: &mut mpsc::Receiver<String>,
+    ) -> Result<(), ()> {
+        loop {
+            // the first message of a batch, or an urgent one
+            futures::select_biased! {
+                msg = urgent.next() => self.urgent(msg.ok_or(())?).await?,
+                msg = normal.next() => match msg.ok_or(())? {
+                    msg if self.batching => self.batch.push(&msg),
+                    msg => self.send(msg, 1).await?,
+                },
+            }
+            if self.batch.deadline.is_none() {
+                continue;
+            }
+
+            // more messages join the batch, until its window ends
+            let mut window = delay_until(self.batch.deadline.unwrap()).fuse();
+            loop {
+                futures::select_biased! {
+                    msg = urgent.next() => {
+                        self.urgent(msg.ok_or(())?).await?;
+                        break;
+                    }
+                    msg = normal.next() => {
+                        self.batch.push(&msg.ok_or(())?);
+                        if self.batch.is_full() {
+                            self.flush().await?;
+                            break;
+                        }
+                    }
+                    _ = window => {
+                        self.flush().await?;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
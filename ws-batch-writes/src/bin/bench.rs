@@ -0,0 +1,127 @@
+//! Compares sending with and without batching.
+//!
+//! ```text
+//! bench [messages]
+//! ```
+//!
+//! Throughput: asks the server for a burst of small messages as fast as it
+//! can, once batched and once with a frame per message.
+//!
+//! Latency: asks for a steady stream of 100k messages a second, pings the
+//! server during it, and measures how long ticks and pongs take to arrive.
+//! Batching holds ticks back for up to the batch window, pongs skip it.
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::{SinkExt, StreamExt};
+use ntex::http::client::Client;
+use ntex::web::ws;
+
+/// Pings sent during a run
+const PINGS: usize = 20;
+/// Messages a second of the latency runs
+const RATE: usize = 100_000;
+
+#[derive(Default)]
+struct Run {
+    elapsed: Duration,
+    frames: usize,
+    ticks: Vec<Duration>,
+    pongs: Vec<Duration>,
+}
+
+fn since_epoch(micros: u64) -> Duration {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.checked_sub(Duration::from_micros(micros))
+        .unwrap_or_default()
+}
+
+async fn run(batch: bool, messages: usize, rate: Option<usize>) -> Run {
+    let url = format!("http://127.0.0.1:8080/ws?batch={}", batch as u8);
+    let (_, mut framed) = Client::new()
+        .ws(url)
+        .max_frame_size(1024 * 1024)
+        .connect()
+        .await
+        .expect("is the server running?");
+
+    let started = Instant::now();
+    let burst = match rate {
+        Some(rate) => format!("burst {} {}", messages, rate),
+        None => format!("burst {}", messages),
+    };
+    framed.send(ws::Message::Text(burst)).await.unwrap();
+
+    let mut run = Run::default();
+    let mut pings: Vec<Instant> = Vec::new();
+    let mut received = 0;
+    while received < messages || run.pongs.len() < pings.len() {
+        let text = match framed.next().await {
+            Some(Ok(ws::Frame::Text(text))) => text,
+            Some(Ok(_)) => continue,
+            _ => panic!("connection lost"),
+        };
+        run.frames += 1;
+        for line in text.split(|b| *b == b'\n') {
+            let msg: serde_json::Value = serde_json::from_slice(line).unwrap();
+            if msg["type"] == "pong" {
+                let id: usize = msg["id"].as_str().unwrap().parse().unwrap();
+                run.pongs.push(pings[id].elapsed());
+            } else {
+                received += 1;
+                run.ticks
+                    .push(since_epoch(msg["sent_at"].as_u64().unwrap()));
+            }
+        }
+        // pings spread over the run
+        if pings.len() < PINGS && received >= pings.len() * messages / PINGS {
+            let ping = format!("ping {}", pings.len());
+            pings.push(Instant::now());
+            framed.send(ws::Message::Text(ping)).await.unwrap();
+        }
+    }
+    run.elapsed = started.elapsed();
+    let _ = framed.send(ws::Message::Close(None)).await;
+    run
+}
+
+fn stats(times: &[Duration]) -> String {
+    let avg = times.iter().sum::<Duration>() / times.len().max(1) as u32;
+    let max = times.iter().max().copied().unwrap_or_default();
+    format!(
+        "avg {:>6.2}ms max {:>6.2}ms",
+        avg.as_secs_f64() * 1000.0,
+        max.as_secs_f64() * 1000.0
+    )
+}
+
+#[ntex::main]
+async fn main() {
+    let messages = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(500_000);
+
+    println!("throughput, {} messages as fast as possible", messages);
+    for &batch in &[true, false] {
+        let run = run(batch, messages, None).await;
+        println!(
+            "  {:<10} {:>8} frames {:>10.0} msg/s",
+            if batch { "batched" } else { "unbatched" },
+            run.frames,
+            messages as f64 / run.elapsed.as_secs_f64(),
+        );
+    }
+
+    let messages = RATE * 2;
+    println!("latency, {} messages at {} a second", messages, RATE);
+    for &batch in &[true, false] {
+        let run = run(batch, messages, Some(RATE)).await;
+        println!(
+            "  {:<10} {:>8} frames   ticks {}   pongs {}",
+            if batch { "batched" } else { "unbatched" },
+            run.frames,
+            stats(&run.ticks),
+            stats(&run.pongs),
+        );
+    }
+}
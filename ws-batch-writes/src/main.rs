@@ -0,0 +1,153 @@
+use std::cell::RefCell;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::channel::mpsc;
+use futures::future::{ok, ready};
+use futures::SinkExt;
+use ntex::rt::time::{delay_until, Instant};
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, fn_service};
+use serde::{Deserialize, Serialize};
+
+mod batch;
+
+use batch::Writer;
+
+/// Messages a burst can queue up before it waits for the writer
+const QUEUE_SIZE: usize = 1024;
+/// Frames the writer can queue up before it waits for the client
+const FRAME_QUEUE_SIZE: usize = 4;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Outgoing<'a> {
+    Tick {
+        seq: usize,
+        price: f64,
+        /// Microseconds since the unix epoch, the client on the same machine
+        /// can tell how long the tick took to arrive
+        sent_at: u64,
+    },
+    Pong {
+        id: &'a str,
+    },
+}
+
+fn now_micros() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    now.as_micros() as u64
+}
+
+/// Sends `count` small messages, `rate` a second or as fast as the writer
+/// takes them
+async fn burst(mut tx: mpsc::Sender<String>, count: usize, rate: Option<usize>) {
+    // a timer can't fire much more often than every millisecond, a paced
+    // burst sends a few messages every millisecond
+    let per_ms = rate.map(|rate| (rate / 1000).max(1));
+    let started = Instant::now();
+    for seq in 1..=count {
+        if let Some(per_ms) = per_ms {
+            if seq % per_ms == 0 {
+                delay_until(started + Duration::from_millis((seq / per_ms) as u64))
+                    .await;
+            }
+        }
+        let tick = Outgoing::Tick {
+            seq,
+            price: 100.0 + (seq % 100) as f64 / 100.0,
+            sent_at: now_micros(),
+        };
+        if tx
+            .send(serde_json::to_string(&tick).unwrap())
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct WsParams {
+    /// `0` sends every message in a frame of its own
+    batch: Option<u8>,
+}
+
+/// Commands from the client, one per text frame:
+///
+/// * `burst <n> [rate]`, send `n` ticks, `rate` a second or as fast as
+///   possible
+/// * `ping <id>`, answer with a pong right away
+async fn ws_index(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    params: web::types::Query<WsParams>,
+) -> Result<HttpResponse, Error> {
+    let batching = params.batch != Some(0);
+    let (frames_tx, frames_rx) = mpsc::channel(FRAME_QUEUE_SIZE);
+    ws::start_with(
+        req,
+        payload,
+        frames_tx,
+        frames_rx,
+        fn_factory_with_config(move |sink: batch::Sink| {
+            let (urgent_tx, urgent_rx) = mpsc::channel(16);
+            let (normal_tx, normal_rx) = mpsc::channel(QUEUE_SIZE);
+            ntex::rt::spawn(async move {
+                let sent = Writer::new(sink, batching).run(urgent_rx, normal_rx).await;
+                log::info!(
+                    "connection closed, batching {}: {} messages in {} frames",
+                    if batching { "on" } else { "off" },
+                    sent.messages,
+                    sent.frames
+                );
+            });
+            let urgent = RefCell::new(urgent_tx);
+
+            ok::<_, Error>(fn_service(move |frame| {
+                let item = match frame {
+                    ws::Frame::Text(text) => {
+                        let text = String::from_utf8_lossy(&text);
+                        let mut words = text.split_whitespace();
+                        match (words.next(), words.next()) {
+                            (Some("burst"), Some(n)) => {
+                                let n = n.parse().unwrap_or(0);
+                                let rate = words.next().and_then(|r| r.parse().ok());
+                                ntex::rt::spawn(burst(normal_tx.clone(), n, rate));
+                            }
+                            (Some("ping"), Some(id)) => {
+                                let pong = Outgoing::Pong { id };
+                                let pong = serde_json::to_string(&pong).unwrap();
+                                if urgent.borrow_mut().try_send(pong).is_err() {
+                                    log::warn!("urgent queue is full, pong dropped");
+                                }
+                            }
+                            _ => log::warn!("unknown command {:?}", text),
+                        }
+                        None
+                    }
+                    ws::Frame::Ping(msg) => Some(ws::Message::Pong(msg)),
+                    ws::Frame::Close(reason) => Some(ws::Message::Close(reason)),
+                    _ => None,
+                };
+                ready(Ok::<_, std::io::Error>(item))
+            }))
+        }),
+    )
+    .await
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ws_batch_writes=info");
+    env_logger::init();
+
+    web::server(|| {
+        App::new()
+            .wrap(middleware::Logger::default())
+            .route("/ws", web::get().to(ws_index))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
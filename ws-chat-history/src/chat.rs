@@ -0,0 +1,139 @@
+//! The chat room: stored messages and the connections that receive them.
+//!
+//! Every message is written to sqlite before anyone sees it, its row id is
+//! its sequence number. Storing and broadcasting happen under one lock, so
+//! connections receive messages in sequence order. A joining connection
+//! reads the history and subscribes under the same lock: the history ends
+//! right before the first live message, nothing is missed, nothing comes
+//! twice.
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use futures::channel::mpsc;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, Row};
+use serde::Serialize;
+
+pub type Pool = r2d2::Pool<SqliteConnectionManager>;
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Message {
+    /// Sequence number, increasing in the order messages were sent
+    pub id: i64,
+    pub user: String,
+    pub text: String,
+    /// Milliseconds since the unix epoch, for display. Clocks can go
+    /// backwards, order by `id`
+    pub sent_at: i64,
+}
+
+impl Message {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Message {
+            id: row.get(0)?,
+            user: row.get(1)?,
+            text: row.get(2)?,
+            sent_at: row.get(3)?,
+        })
+    }
+}
+
+pub struct Chat {
+    pool: Pool,
+    subscribers: Mutex<HashMap<u64, mpsc::UnboundedSender<Message>>>,
+    ids: AtomicU64,
+}
+
+impl Chat {
+    pub fn new(pool: Pool) -> Result<Self, Error> {
+        pool.get()?.execute_batch(
+            "CREATE TABLE IF NOT EXISTS messages (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user TEXT NOT NULL,
+                text TEXT NOT NULL,
+                sent_at INTEGER NOT NULL
+            )",
+        )?;
+        Ok(Chat {
+            pool,
+            subscribers: Mutex::new(HashMap::new()),
+            ids: AtomicU64::new(0),
+        })
+    }
+
+    /// Stores a message and sends it to every connection. Blocks, run it
+    /// with `web::block`
+    pub fn post(&self, user: &str, text: &str) -> Result<Message, Error> {
+        let sent_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let conn = self.pool.get()?;
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        conn.execute(
+            "INSERT INTO messages (user, text, sent_at) VALUES (?1, ?2, ?3)",
+            params![user, text, sent_at],
+        )?;
+        let msg = Message {
+            id: conn.last_insert_rowid(),
+            user: user.to_owned(),
+            text: text.to_owned(),
+            sent_at,
+        };
+        // closed connections are removed on the way
+        subscribers.retain(|_, tx| tx.unbounded_send(msg.clone()).is_ok());
+        Ok(msg)
+    }
+
+    /// Subscribes a new connection. Returns its id, the last `history`
+    /// messages, oldest first, and the receiver for everything after them.
+    /// Blocks, run it with `web::block`
+    pub fn join(
+        &self,
+        history: u32,
+    ) -> Result<(u64, Vec<Message>, mpsc::UnboundedReceiver<Message>), Error> {
+        let conn = self.pool.get()?;
+
+        let mut subscribers = self.subscribers.lock().unwrap();
+        let mut messages = page(&conn, None, history)?;
+        messages.reverse();
+        let id = self.ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = mpsc::unbounded();
+        subscribers.insert(id, tx);
+        Ok((id, messages, rx))
+    }
+
+    pub fn leave(&self, id: u64) {
+        self.subscribers.lock().unwrap().remove(&id);
+    }
+
+    /// Up to `limit` messages before `before`, newest first. Blocks, run it
+    /// with `web::block`
+    pub fn history(
+        &self,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<Message>, Error> {
+        let conn = self.pool.get()?;
+        Ok(page(&conn, before, limit)?)
+    }
+}
+
+/// Keyset pagination by sequence number. Unlike an offset, a page stays the
+/// same while new messages arrive
+fn page(
+    conn: &rusqlite::Connection,
+    before: Option<i64>,
+    limit: u32,
+) -> rusqlite::Result<Vec<Message>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, user, text, sent_at FROM messages
+         WHERE id < ?1 ORDER BY id DESC LIMIT ?2",
+    )?;
+    let rows = stmt.query_map(
+        params![before.unwrap_or(i64::MAX), limit],
+        Message::from_row,
+    )?;
+    rows.collect()
+}
@@ -0,0 +1,195 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use futures::channel::mpsc;
+use futures::future::{ok, ready, Either};
+use futures::{SinkExt, StreamExt};
+use ntex::web::{self, error, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, fn_service};
+use r2d2_sqlite::SqliteConnectionManager;
+use serde::{Deserialize, Serialize};
+
+mod chat;
+
+use chat::{Chat, Message};
+
+/// How many messages a new connection gets before the live ones
+const HISTORY_SIZE: u32 = 20;
+/// Largest page of `GET /messages`
+const MAX_PAGE_SIZE: u32 = 100;
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Outgoing {
+    /// First frame on every connection, oldest first
+    History {
+        messages: Vec<Message>,
+    },
+    Message(Message),
+}
+
+/// Unsubscribes a connection, exactly once
+struct Connection {
+    chat: web::types::Data<Chat>,
+    id: u64,
+    closed: Cell<bool>,
+}
+
+impl Connection {
+    fn close(&self, reason: &str) {
+        if !self.closed.replace(true) {
+            log::info!("connection {} closed: {}", self.id, reason);
+            self.chat.leave(self.id);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.close("dropped");
+    }
+}
+
+/// Sends the history, then every new message as it is stored
+async fn forward(
+    mut sink: ws::WebSocketsSink,
+    history: Vec<Message>,
+    messages: mpsc::UnboundedReceiver<Message>,
+    conn: Rc<Connection>,
+) {
+    let history = Outgoing::History { messages: history };
+    let mut outgoing =
+        futures::stream::once(ready(history)).chain(messages.map(Outgoing::Message));
+
+    while let Some(item) = outgoing.next().await {
+        let msg = ws::Message::Text(serde_json::to_string(&item).unwrap());
+        if sink.send(Ok(msg)).await.is_err() {
+            conn.close("connection lost");
+            break;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct JoinParams {
+    name: String,
+}
+
+async fn ws_index(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    params: web::types::Query<JoinParams>,
+    chat: web::types::Data<Chat>,
+) -> Result<HttpResponse, Error> {
+    let name = params.into_inner().name;
+    let (id, history, messages) = {
+        let chat = chat.clone();
+        web::block(move || chat.join(HISTORY_SIZE))
+            .await
+            .map_err(error::ErrorInternalServerError)?
+    };
+    log::info!("{} joined as connection {}", name, id);
+    let conn = Rc::new(Connection {
+        chat: chat.clone(),
+        id,
+        closed: Cell::new(false),
+    });
+    // the factory is a `Fn`, but it is only called once per websocket
+    let state = RefCell::new(Some((history, messages, conn)));
+
+    ws::start(
+        req,
+        payload,
+        fn_factory_with_config(move |sink: ws::WebSocketsSink| {
+            let (history, messages, conn) = state.borrow_mut().take().unwrap();
+            ntex::rt::spawn(forward(sink, history, messages, conn.clone()));
+            let chat = chat.clone();
+            let name = name.clone();
+
+            ok::<_, Error>(fn_service(move |frame| match frame {
+                // the write path: stored first, then broadcast to everyone,
+                // the sender included
+                ws::Frame::Text(text) => {
+                    let chat = chat.clone();
+                    let name = name.clone();
+                    let text = String::from_utf8_lossy(&text).into_owned();
+                    Either::Left(async move {
+                        if let Err(e) = web::block(move || chat.post(&name, &text)).await
+                        {
+                            log::error!("message could not be stored: {}", e);
+                        }
+                        Ok::<_, std::io::Error>(None)
+                    })
+                }
+                ws::Frame::Ping(msg) => Either::Right(ok(Some(ws::Message::Pong(msg)))),
+                ws::Frame::Close(reason) => {
+                    conn.close("closed by client");
+                    Either::Right(ok(Some(ws::Message::Close(reason))))
+                }
+                _ => Either::Right(ok(None)),
+            }))
+        }),
+    )
+    .await
+}
+
+#[derive(Deserialize)]
+struct HistoryParams {
+    /// Only messages before this sequence number, the `next` of the previous
+    /// page
+    before: Option<i64>,
+    limit: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct HistoryPage {
+    /// Oldest first
+    messages: Vec<Message>,
+    /// `before` for the next, older page, if there is one
+    next: Option<i64>,
+}
+
+async fn history(
+    params: web::types::Query<HistoryParams>,
+    chat: web::types::Data<Chat>,
+) -> Result<HttpResponse, Error> {
+    let limit = params.limit.unwrap_or(50).clamp(1, MAX_PAGE_SIZE);
+    let before = params.before;
+    let mut messages = web::block(move || chat.history(before, limit))
+        .await
+        .map_err(error::ErrorInternalServerError)?;
+    messages.reverse();
+
+    let next = match messages.first() {
+        Some(oldest) if messages.len() == limit as usize => Some(oldest.id),
+        _ => None,
+    };
+    Ok(HttpResponse::Ok().json(&HistoryPage { messages, next }))
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ws_chat_history=info");
+    env_logger::init();
+
+    let pool = r2d2::Pool::new(SqliteConnectionManager::file("chat.db")).unwrap();
+    let chat = web::types::Data::new(Chat::new(pool).unwrap());
+
+    web::server(move || {
+        App::new()
+            .app_data(chat.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/messages", web::get().to(history))
+            .route("/ws", web::get().to(ws_index))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,420 @@
+//! Presence tracking over websockets.
+//!
+//! A client connects to `/ws?user=<name>` to appear online, or to `/ws` to
+//! only watch. Every connection first gets the list of online users, then
+//! `join` and `leave` events. A user with several connections (tabs,
+//! devices) joins with the first one and leaves with the last one.
+//!
+//! Connections are pinged, a connection that can't be written to anymore or
+//! that stops answering is dropped. So users also leave when their client
+//! goes away without closing the websocket.
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::ok;
+use futures::{stream, SinkExt, StreamExt};
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, fn_service};
+use serde::{Deserialize, Serialize};
+
+/// How often heartbeat pings are sent
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum PresenceEvent {
+    /// First message on every connection
+    Online {
+        users: Vec<String>,
+    },
+    Join {
+        user: String,
+    },
+    Leave {
+        user: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct OnlineUser {
+    user: String,
+    connections: usize,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// connection ids by user
+    users: BTreeMap<String, Vec<u64>>,
+    /// every connection, watchers included
+    subscribers: HashMap<u64, mpsc::UnboundedSender<PresenceEvent>>,
+}
+
+impl Registry {
+    fn broadcast(&mut self, event: PresenceEvent) {
+        for tx in self.subscribers.values() {
+            let _ = tx.unbounded_send(event.clone());
+        }
+    }
+}
+
+/// Who is online, shared by all workers
+struct Presence {
+    registry: Mutex<Registry>,
+    connections: AtomicU64,
+    /// clients that don't answer for two intervals are dropped
+    heartbeat: Duration,
+}
+
+impl Presence {
+    fn new(heartbeat: Duration) -> Self {
+        Presence {
+            registry: Mutex::new(Registry::default()),
+            connections: AtomicU64::new(0),
+            heartbeat,
+        }
+    }
+
+    /// Register a connection, `user` is `None` for watchers.
+    ///
+    /// Returns the connection id and the receiver for its events.
+    fn connect(
+        &self,
+        user: Option<&str>,
+    ) -> (u64, mpsc::UnboundedReceiver<PresenceEvent>) {
+        let id = self.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = mpsc::unbounded();
+        let mut registry = self.registry.lock().unwrap();
+
+        if let Some(user) = user {
+            let connections = registry.users.entry(user.to_owned()).or_default();
+            connections.push(id);
+            if connections.len() == 1 {
+                log::info!("{} joined", user);
+                registry.broadcast(PresenceEvent::Join {
+                    user: user.to_owned(),
+                });
+            }
+        }
+
+        let users = registry.users.keys().cloned().collect();
+        let _ = tx.unbounded_send(PresenceEvent::Online { users });
+        registry.subscribers.insert(id, tx);
+        (id, rx)
+    }
+
+    fn disconnect(&self, user: Option<&str>, id: u64) {
+        let mut registry = self.registry.lock().unwrap();
+        // ends the connection's event stream
+        registry.subscribers.remove(&id);
+
+        let user = match user {
+            Some(user) => user,
+            None => return,
+        };
+        let last = match registry.users.get_mut(user) {
+            Some(connections) => {
+                connections.retain(|c| *c != id);
+                connections.is_empty()
+            }
+            None => false,
+        };
+        if last {
+            registry.users.remove(user);
+            log::info!("{} left", user);
+            registry.broadcast(PresenceEvent::Leave {
+                user: user.to_owned(),
+            });
+        }
+    }
+
+    fn online(&self) -> Vec<OnlineUser> {
+        let registry = self.registry.lock().unwrap();
+        registry
+            .users
+            .iter()
+            .map(|(user, connections)| OnlineUser {
+                user: user.clone(),
+                connections: connections.len(),
+            })
+            .collect()
+    }
+}
+
+/// Removes a connection from the registry, exactly once
+struct Connection {
+    presence: web::types::Data<Presence>,
+    user: Option<String>,
+    id: u64,
+    closed: Cell<bool>,
+}
+
+impl Connection {
+    fn close(&self, reason: &str) {
+        if !self.closed.replace(true) {
+            log::info!("connection {} closed: {}", self.id, reason);
+            self.presence.disconnect(self.user.as_deref(), self.id);
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.close("dropped");
+    }
+}
+
+enum Outgoing {
+    Event(PresenceEvent),
+    Heartbeat,
+}
+
+/// Sends presence events and pings until the connection is closed, the
+/// client stops answering or a send fails because the client is gone
+async fn forward(
+    mut sink: ws::WebSocketsSink,
+    events: mpsc::UnboundedReceiver<PresenceEvent>,
+    conn: Rc<Connection>,
+    last_seen: Rc<Cell<Instant>>,
+) {
+    let heartbeat = conn.presence.heartbeat;
+    let ticks =
+        stream::unfold(ntex::rt::time::interval(heartbeat), |mut interval| async {
+            interval.tick().await;
+            Some((Outgoing::Heartbeat, interval))
+        });
+    let mut outgoing = stream::select(events.map(Outgoing::Event), Box::pin(ticks));
+
+    while let Some(item) = outgoing.next().await {
+        let msg = match item {
+            Outgoing::Event(event) => {
+                ws::Message::Text(serde_json::to_string(&event).unwrap())
+            }
+            Outgoing::Heartbeat if conn.closed.get() => break,
+            Outgoing::Heartbeat if last_seen.get().elapsed() > heartbeat * 2 => {
+                conn.close("heartbeat timed out");
+                let _ = sink.send(Ok(ws::Message::Close(None))).await;
+                break;
+            }
+            Outgoing::Heartbeat => ws::Message::Ping(Bytes::new()),
+        };
+        if sink.send(Ok(msg)).await.is_err() {
+            conn.close("connection lost");
+            break;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ConnectParams {
+    user: Option<String>,
+}
+
+async fn ws_index(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    params: web::types::Query<ConnectParams>,
+    presence: web::types::Data<Presence>,
+) -> Result<HttpResponse, Error> {
+    let user = params.into_inner().user.filter(|user| !user.is_empty());
+    let (id, events) = presence.connect(user.as_deref());
+    let conn = Rc::new(Connection {
+        presence: presence.clone(),
+        user,
+        id,
+        closed: Cell::new(false),
+    });
+    let last_seen = Rc::new(Cell::new(Instant::now()));
+    // the factory is a `Fn`, but it is only called once per websocket
+    let state = RefCell::new(Some((events, conn, last_seen)));
+
+    ws::start(
+        req,
+        payload,
+        fn_factory_with_config(move |sink: ws::WebSocketsSink| {
+            let (events, conn, last_seen) = state.borrow_mut().take().unwrap();
+            ntex::rt::spawn(forward(sink, events, conn.clone(), last_seen.clone()));
+
+            ok::<_, Error>(fn_service(move |frame| {
+                // any frame, pongs included, shows the client is still there
+                last_seen.set(Instant::now());
+                let item = match frame {
+                    ws::Frame::Ping(msg) => Some(ws::Message::Pong(msg)),
+                    ws::Frame::Close(reason) => {
+                        conn.close("closed by client");
+                        Some(ws::Message::Close(reason))
+                    }
+                    _ => None,
+                };
+                ok::<_, std::io::Error>(item)
+            }))
+        }),
+    )
+    .await
+}
+
+async fn online(presence: web::types::Data<Presence>) -> HttpResponse {
+    HttpResponse::Ok().json(&presence.online())
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+fn app_config(config: &mut web::ServiceConfig) {
+    config
+        .route("/", web::get().to(index))
+        .route("/online", web::get().to(online))
+        .route("/ws", web::get().to(ws_index));
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ws_presence=info");
+    env_logger::init();
+
+    let presence = web::types::Data::new(Presence::new(HEARTBEAT_INTERVAL));
+
+    web::server(move || {
+        App::new()
+            .app_data(presence.clone())
+            .wrap(middleware::Logger::default())
+            .configure(app_config)
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+    use futures::future::select;
+    use ntex::http::client::Client;
+    use ntex::web::test;
+
+    fn join(user: &str) -> PresenceEvent {
+        PresenceEvent::Join {
+            user: user.to_owned(),
+        }
+    }
+
+    fn leave(user: &str) -> PresenceEvent {
+        PresenceEvent::Leave {
+            user: user.to_owned(),
+        }
+    }
+
+    #[ntex::test]
+    async fn test_multiple_connections() {
+        let presence = Presence::new(HEARTBEAT_INTERVAL);
+        let (_, mut watcher) = presence.connect(None);
+        let (tab1, _rx1) = presence.connect(Some("alice"));
+        let (tab2, _rx2) = presence.connect(Some("alice"));
+        let (bob, _rx3) = presence.connect(Some("bob"));
+
+        let online = presence.online();
+        assert_eq!(online[0].connections, 2);
+        assert_eq!(online[1].connections, 1);
+
+        // alice is still online in the second tab
+        presence.disconnect(Some("alice"), tab1);
+        presence.disconnect(Some("bob"), bob);
+        presence.disconnect(Some("alice"), tab2);
+        assert!(presence.online().is_empty());
+
+        watcher.close();
+        let events: Vec<_> = watcher.collect().await;
+        assert_eq!(
+            events,
+            vec![
+                PresenceEvent::Online { users: Vec::new() },
+                join("alice"),
+                join("bob"),
+                leave("bob"),
+                leave("alice"),
+            ]
+        );
+    }
+
+    async fn online_users(srv: &test::TestServer) -> serde_json::Value {
+        let mut res = srv.get("/online").send().await.unwrap();
+        serde_json::from_slice(&res.body().await.unwrap()).unwrap()
+    }
+
+    /// Connect as `user` and answer pings, until the returned sender is
+    /// dropped, which drops the connection without a close frame
+    async fn connect(srv: &test::TestServer, user: &str) -> oneshot::Sender<()> {
+        let url = srv.url(&format!("/ws?user={}", user));
+        let (_, mut framed) = Client::new().ws(url).connect().await.unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+
+        ntex::rt::spawn(async move {
+            let pongs = async {
+                while let Some(Ok(frame)) = framed.next().await {
+                    if let ws::Frame::Ping(msg) = frame {
+                        let _ = framed.send(ws::Message::Pong(msg)).await;
+                    }
+                }
+            };
+            futures::pin_mut!(pongs);
+            select(pongs, stopped).await;
+        });
+        stop
+    }
+
+    fn server() -> test::TestServer {
+        test::server(|| {
+            App::new()
+                .app_data(web::types::Data::new(Presence::new(Duration::from_millis(
+                    100,
+                ))))
+                .configure(app_config)
+        })
+    }
+
+    #[ntex::test]
+    async fn test_ungraceful_disconnect() {
+        let srv = server();
+        let tab1 = connect(&srv, "alice").await;
+        let tab2 = connect(&srv, "alice").await;
+        assert_eq!(
+            online_users(&srv).await,
+            serde_json::json!([{ "user": "alice", "connections": 2 }])
+        );
+
+        drop(tab1);
+        ntex::rt::time::delay_for(Duration::from_millis(300)).await;
+        assert_eq!(
+            online_users(&srv).await,
+            serde_json::json!([{ "user": "alice", "connections": 1 }])
+        );
+
+        drop(tab2);
+        ntex::rt::time::delay_for(Duration::from_millis(300)).await;
+        assert_eq!(online_users(&srv).await, serde_json::json!([]));
+    }
+
+    #[ntex::test]
+    async fn test_heartbeat_timeout() {
+        let srv = server();
+        // connected, but never reads or answers pings
+        let (_, _framed) = Client::new()
+            .ws(srv.url("/ws?user=bob"))
+            .connect()
+            .await
+            .unwrap();
+        assert_eq!(online_users(&srv).await.as_array().unwrap().len(), 1);
+
+        ntex::rt::time::delay_for(Duration::from_millis(400)).await;
+        assert_eq!(online_users(&srv).await, serde_json::json!([]));
+    }
+}
@@ -0,0 +1,275 @@
+//! Limits the message rate of every websocket connection.
+//!
+//! Each connection has its own token bucket in the state of its frame
+//! handler. A message takes a token, tokens come back at `rate` per second,
+//! up to `burst`. A message without a token is dropped or the connection is
+//! closed, depending on the policy.
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Instant;
+
+use futures::future::{ok, ready};
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, fn_service};
+
+/// Frames a client may still send after the close frame
+const MAX_AFTER_CLOSE: u64 = 100;
+
+/// What happens to a message over the limit
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Policy {
+    /// The message is ignored, the connection stays open
+    Drop,
+    /// The connection is closed with `1008 Policy Violation`
+    Close,
+}
+
+#[derive(Clone, Copy)]
+struct Limits {
+    /// Messages per second
+    rate: f64,
+    burst: f64,
+    policy: Policy,
+}
+
+struct TokenBucket {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Starts full, a new connection may send `burst` messages at once
+    fn new(limits: &Limits) -> Self {
+        TokenBucket {
+            rate: limits.rate,
+            burst: limits.burst,
+            tokens: limits.burst,
+            updated: Instant::now(),
+        }
+    }
+
+    fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.updated = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Ends the connection of a client that ignores the close frame
+#[derive(Debug)]
+struct Flooding;
+
+impl std::fmt::Display for Flooding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("client kept sending after the close frame")
+    }
+}
+
+impl std::error::Error for Flooding {}
+
+/// The state of one connection's frame handler
+struct Connection {
+    peer: String,
+    policy: Policy,
+    bucket: RefCell<TokenBucket>,
+    dropped: Cell<u64>,
+    /// Set once the close frame is sent
+    closing: Cell<bool>,
+}
+
+impl Connection {
+    /// Echoes text and binary messages, as long as they come slowly enough
+    fn handle(&self, frame: ws::Frame) -> Result<Option<ws::Message>, Flooding> {
+        // frames that were on the way when the close frame was sent are
+        // discarded, a client that keeps on sending is cut off
+        if self.closing.get() {
+            return match frame {
+                ws::Frame::Close(_) => Ok(None),
+                _ if self.dropped.get() >= MAX_AFTER_CLOSE => Err(Flooding),
+                _ => {
+                    self.dropped.set(self.dropped.get() + 1);
+                    Ok(None)
+                }
+            };
+        }
+
+        let msg = match frame {
+            ws::Frame::Text(text) => {
+                ws::Message::Text(String::from_utf8_lossy(&text).into_owned())
+            }
+            ws::Frame::Binary(bin) => ws::Message::Binary(bin),
+            ws::Frame::Ping(msg) => return Ok(Some(ws::Message::Pong(msg))),
+            ws::Frame::Close(reason) => {
+                if self.dropped.get() > 0 {
+                    log::info!("{} dropped {} messages", self.peer, self.dropped.get());
+                }
+                return Ok(Some(ws::Message::Close(reason)));
+            }
+            _ => return Ok(None),
+        };
+        if self.bucket.borrow_mut().take() {
+            return Ok(Some(msg));
+        }
+
+        match self.policy {
+            Policy::Drop => {
+                self.dropped.set(self.dropped.get() + 1);
+                log::debug!("{} is over the limit, message dropped", self.peer);
+                Ok(None)
+            }
+            Policy::Close => {
+                log::warn!("{} is over the limit, closing", self.peer);
+                self.closing.set(true);
+                Ok(Some(ws::Message::Close(Some(ws::CloseReason {
+                    code: ws::CloseCode::Policy,
+                    description: Some("rate limit exceeded".to_owned()),
+                }))))
+            }
+        }
+    }
+}
+
+async fn ws_index(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    limits: web::types::Data<Limits>,
+) -> Result<HttpResponse, Error> {
+    let conn = Rc::new(Connection {
+        peer: req
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default(),
+        policy: limits.policy,
+        bucket: RefCell::new(TokenBucket::new(&limits)),
+        dropped: Cell::new(0),
+        closing: Cell::new(false),
+    });
+
+    ws::start(
+        req,
+        payload,
+        fn_factory_with_config(move |_: ws::WebSocketsSink| {
+            let conn = conn.clone();
+            ok::<_, Error>(fn_service(move |frame| ready(conn.handle(frame))))
+        }),
+    )
+    .await
+}
+
+fn env<T: std::str::FromStr>(name: &str, default: T) -> T {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ws_rate_limit=info");
+    env_logger::init();
+
+    let policy = match std::env::var("POLICY").as_deref() {
+        Ok("drop") => Policy::Drop,
+        _ => Policy::Close,
+    };
+    let limits = Limits {
+        rate: env("RATE", 5.0),
+        burst: env("BURST", 10.0),
+        policy,
+    };
+    log::info!(
+        "{} messages per second, bursts of {}, {:?} when over",
+        limits.rate,
+        limits.burst,
+        limits.policy
+    );
+
+    web::server(move || {
+        App::new()
+            .data(limits)
+            .wrap(middleware::Logger::default())
+            .route("/ws", web::get().to(ws_index))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use futures::{SinkExt, StreamExt};
+    use ntex::http::client::Client;
+    use ntex::web::test;
+
+    fn server(policy: Policy) -> test::TestServer {
+        test::server(move || {
+            App::new()
+                .data(Limits {
+                    rate: 2.0,
+                    burst: 3.0,
+                    policy,
+                })
+                .route("/ws", web::get().to(ws_index))
+        })
+    }
+
+    #[ntex::test]
+    async fn test_flood_is_closed() {
+        let srv = server(Policy::Close);
+        let (_, mut framed) = Client::new().ws(srv.url("/ws")).connect().await.unwrap();
+
+        for i in 0..10 {
+            let _ = framed.send(ws::Message::Text(i.to_string())).await;
+        }
+
+        // the burst is echoed, then the connection is closed
+        let mut echoed = Vec::new();
+        let reason = loop {
+            match framed.next().await.unwrap().unwrap() {
+                ws::Frame::Text(text) => echoed.push(text),
+                ws::Frame::Close(reason) => break reason.unwrap(),
+                frame => panic!("unexpected {:?}", frame),
+            }
+        };
+        assert_eq!(echoed, vec!["0", "1", "2"]);
+        assert_eq!(reason.code, ws::CloseCode::Policy);
+        assert_eq!(reason.description.as_deref(), Some("rate limit exceeded"));
+    }
+
+    #[ntex::test]
+    async fn test_flood_is_dropped() {
+        let srv = server(Policy::Drop);
+        let (_, mut framed) = Client::new().ws(srv.url("/ws")).connect().await.unwrap();
+
+        for i in 0..10 {
+            framed.send(ws::Message::Text(i.to_string())).await.unwrap();
+        }
+        for expected in &["0", "1", "2"] {
+            match framed.next().await.unwrap().unwrap() {
+                ws::Frame::Text(text) => assert_eq!(text, expected),
+                frame => panic!("unexpected {:?}", frame),
+            }
+        }
+
+        // still open, and a token has come back
+        ntex::rt::time::delay_for(Duration::from_millis(600)).await;
+        framed.send(ws::Message::Text("late".into())).await.unwrap();
+        match framed.next().await.unwrap().unwrap() {
+            ws::Frame::Text(text) => assert_eq!(text, "late"),
+            frame => panic!("unexpected {:?}", frame),
+        }
+    }
+}
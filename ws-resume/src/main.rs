@@ -0,0 +1,296 @@
+//! Resumable websocket sessions.
+//!
+//! A background task publishes an event every second to every session. Each
+//! event gets the session's next sequence number and is kept in a bounded
+//! replay buffer. A client connects to `/ws?session=<token>&last_seq=<n>`,
+//! the server replays everything after `n` and then continues with live
+//! events. Unknown or expired sessions, and sessions whose buffer no longer
+//! reaches back to `n`, start over with a new token.
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::future::ok;
+use futures::{SinkExt, StreamExt};
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, fn_service};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Events kept per session for replay
+const REPLAY_BUFFER: usize = 50;
+/// How long a disconnected session can be resumed
+const SESSION_TTL: Duration = Duration::from_secs(60);
+/// How often an event is published
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMessage {
+    /// First message on every connection
+    Session {
+        session: String,
+        resumed: bool,
+        /// sequence of the first event the client is going to get
+        next_seq: u64,
+    },
+    Event {
+        seq: u64,
+        data: String,
+    },
+}
+
+struct Session {
+    next_seq: u64,
+    buffer: VecDeque<(u64, ServerMessage)>,
+    /// live connection, the `u64` identifies it
+    live: Option<(u64, mpsc::UnboundedSender<ServerMessage>)>,
+    disconnected: Option<Instant>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Session {
+            next_seq: 1,
+            buffer: VecDeque::with_capacity(REPLAY_BUFFER),
+            live: None,
+            disconnected: None,
+        }
+    }
+
+    fn publish(&mut self, data: String) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let msg = ServerMessage::Event { seq, data };
+
+        if self.buffer.len() == REPLAY_BUFFER {
+            self.buffer.pop_front();
+        }
+        self.buffer.push_back((seq, msg.clone()));
+
+        if let Some((_, tx)) = &self.live {
+            let _ = tx.unbounded_send(msg);
+        }
+    }
+
+    /// Can the client continue after `last_seq` without missing anything
+    fn can_resume(&self, last_seq: u64) -> bool {
+        let oldest = self.buffer.front().map_or(self.next_seq, |(seq, _)| *seq);
+        last_seq.checked_add(1).is_some_and(|next| next >= oldest)
+            && last_seq < self.next_seq
+    }
+}
+
+/// All sessions, shared by all workers
+#[derive(Default)]
+struct Sessions {
+    sessions: Mutex<HashMap<String, Session>>,
+    connections: AtomicU64,
+}
+
+impl Sessions {
+    /// Attach a connection to a session, resuming it if possible.
+    ///
+    /// Returns the session token, the connection id and the receiver for the
+    /// connection. Replayed events are already queued in the receiver, so
+    /// they can't interleave with live ones.
+    fn connect(
+        &self,
+        token: Option<&str>,
+        last_seq: u64,
+    ) -> (String, u64, mpsc::UnboundedReceiver<ServerMessage>) {
+        let conn_id = self.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = mpsc::unbounded();
+        let mut sessions = self.sessions.lock().unwrap();
+
+        let resumable = token
+            .and_then(|t| sessions.get(t).map(|s| (t, s)))
+            .filter(|(_, s)| s.can_resume(last_seq))
+            .map(|(t, _)| t.to_owned());
+
+        let (token, resumed) = match resumable {
+            Some(token) => (token, true),
+            None => {
+                if let Some(token) = token {
+                    log::info!("session {} can not be resumed, starting over", token);
+                }
+                let token: String = rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(16)
+                    .collect();
+                sessions.insert(token.clone(), Session::new());
+                (token, false)
+            }
+        };
+
+        let session = sessions.get_mut(&token).unwrap();
+        let replay: Vec<_> = if resumed {
+            session
+                .buffer
+                .iter()
+                .filter(|(seq, _)| *seq > last_seq)
+                .map(|(_, msg)| msg.clone())
+                .collect()
+        } else {
+            Vec::new()
+        };
+        log::info!(
+            "connection {} attached to session {}, replaying {} events",
+            conn_id,
+            token,
+            replay.len()
+        );
+
+        let _ = tx.unbounded_send(ServerMessage::Session {
+            session: token.clone(),
+            resumed,
+            next_seq: if resumed {
+                last_seq.saturating_add(1)
+            } else {
+                session.next_seq
+            },
+        });
+        for msg in replay {
+            let _ = tx.unbounded_send(msg);
+        }
+        // a previous connection of this session is dropped here
+        session.live = Some((conn_id, tx));
+        session.disconnected = None;
+
+        (token, conn_id, rx)
+    }
+
+    fn disconnect(&self, token: &str, conn_id: u64) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if let Some(session) = sessions.get_mut(token) {
+            // the session may already belong to a newer connection
+            if session.live.as_ref().map(|(id, _)| *id) == Some(conn_id) {
+                session.live = None;
+                session.disconnected = Some(Instant::now());
+            }
+        }
+    }
+
+    fn publish(&self, data: &str) {
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.retain(|token, session| match session.disconnected {
+            Some(at) if at.elapsed() > SESSION_TTL => {
+                log::info!("session {} expired", token);
+                false
+            }
+            _ => true,
+        });
+        for session in sessions.values_mut() {
+            session.publish(data.to_owned());
+        }
+    }
+}
+
+/// Detaches the connection from its session once the websocket is gone
+struct Connection {
+    sessions: web::types::Data<Sessions>,
+    token: String,
+    id: u64,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        log::info!("connection {} closed", self.id);
+        self.sessions.disconnect(&self.token, self.id);
+    }
+}
+
+#[derive(Deserialize)]
+struct ResumeParams {
+    session: Option<String>,
+    #[serde(default)]
+    last_seq: u64,
+}
+
+async fn ws_index(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    params: web::types::Query<ResumeParams>,
+    sessions: web::types::Data<Sessions>,
+) -> Result<HttpResponse, Error> {
+    let (token, id, rx) = sessions.connect(params.session.as_deref(), params.last_seq);
+    let conn = Connection {
+        sessions: sessions.clone(),
+        token,
+        id,
+    };
+    // the factory is a `Fn`, but it is only called once per websocket
+    let state = RefCell::new(Some((rx, conn)));
+
+    ws::start(
+        req,
+        payload,
+        fn_factory_with_config(move |mut sink: ws::WebSocketsSink| {
+            let (mut rx, conn) = state.borrow_mut().take().unwrap();
+
+            // Forward replayed and live events to the client. This ends when
+            // the session gets a new connection, or when a send fails because
+            // the client went away, which drops `conn` and detaches it.
+            ntex::rt::spawn(async move {
+                let _conn = conn;
+                while let Some(msg) = rx.next().await {
+                    let text = serde_json::to_string(&msg).unwrap();
+                    if sink.send(Ok(ws::Message::Text(text))).await.is_err() {
+                        break;
+                    }
+                }
+            });
+
+            ok::<_, Error>(fn_service(|frame| {
+                let item = match frame {
+                    ws::Frame::Ping(msg) => Some(ws::Message::Pong(msg)),
+                    ws::Frame::Close(reason) => Some(ws::Message::Close(reason)),
+                    _ => None,
+                };
+                ok::<_, std::io::Error>(item)
+            }))
+        }),
+    )
+    .await
+}
+
+async fn index() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(include_str!("index.html"))
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ws_resume=info");
+    env_logger::init();
+
+    let sessions = web::types::Data::new(Sessions::default());
+
+    // the event source, every session gets every event
+    let publisher = sessions.clone();
+    ntex::rt::spawn(async move {
+        let mut interval = ntex::rt::time::interval(EVENT_INTERVAL);
+        let mut n = 0u64;
+        loop {
+            interval.tick().await;
+            n += 1;
+            publisher.publish(&format!("tick {}", n));
+        }
+    });
+
+    web::server(move || {
+        App::new()
+            .app_data(sessions.clone())
+            .wrap(middleware::Logger::default())
+            .route("/", web::get().to(index))
+            .route("/ws", web::get().to(ws_index))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}
@@ -0,0 +1,41 @@
+//! Subscribes to a topic and prints what arrives:
+//!
+//! ```text
+//! cargo run --bin client -- trades 'price > 100'
+//! cargo run --bin client -- trades 'symbol == "ACME"'
+//! ```
+use futures::{SinkExt, StreamExt};
+use ntex::http::client::Client;
+use ntex::ws;
+
+#[ntex::main]
+async fn main() {
+    let mut args = std::env::args().skip(1);
+    let topic = args.next().unwrap_or_else(|| "trades".to_owned());
+    let filter = args.next();
+
+    let (_, mut framed) = Client::new()
+        .ws("http://127.0.0.1:8080/ws")
+        .connect()
+        .await
+        .expect("the server is not running");
+
+    let subscribe = serde_json::json!({
+        "action": "subscribe",
+        "topic": topic,
+        "filter": filter,
+    });
+    framed
+        .send(ws::Message::Text(subscribe.to_string()))
+        .await
+        .unwrap();
+
+    while let Some(Ok(frame)) = framed.next().await {
+        match frame {
+            ws::Frame::Text(text) => println!("{}", String::from_utf8_lossy(&text)),
+            ws::Frame::Ping(msg) => framed.send(ws::Message::Pong(msg)).await.unwrap(),
+            ws::Frame::Close(_) => break,
+            _ => (),
+        }
+    }
+}
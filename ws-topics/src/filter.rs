@@ -0,0 +1,308 @@
+//! Filter expressions, compiled once per subscription and evaluated against
+//! the data of every event on the topic.
+//!
+//! ```text
+//! price > 100
+//! symbol == "ACME" && (price < 50 || volume >= 10000)
+//! venue.country != "US"
+//! ```
+//!
+//! A field is a dotted path into the event data, compared with a number, a
+//! string, `true` or `false`. `&&` binds tighter than `||`. Numbers and
+//! strings can be ordered, anything can be compared for equality. A field
+//! the event doesn't have, or of another type, doesn't match.
+use std::cmp::Ordering;
+
+use derive_more::Display;
+use serde_json::Value;
+
+/// How deep parentheses may nest. The parser recurses once per level, an
+/// expression nested deeper than this would otherwise run the worker out
+/// of stack
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Display)]
+#[display(fmt = "{} at {}", message, at)]
+pub struct FilterError {
+    message: String,
+    /// Byte offset into the expression
+    at: usize,
+}
+
+fn error<T>(message: impl Into<String>, at: usize) -> Result<T, FilterError> {
+    Err(FilterError {
+        message: message.into(),
+        at,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug)]
+enum Node {
+    Compare(Vec<String>, Op, Value),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+}
+
+/// A compiled filter expression
+#[derive(Debug)]
+pub struct Filter(Node);
+
+impl Filter {
+    pub fn parse(expr: &str) -> Result<Filter, FilterError> {
+        let tokens = tokenize(expr)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            end: expr.len(),
+            depth: 0,
+        };
+        let node = parser.or()?;
+        match parser.peek() {
+            None => Ok(Filter(node)),
+            Some((_, at)) => error("unexpected input", *at),
+        }
+    }
+
+    pub fn matches(&self, data: &Value) -> bool {
+        self.0.matches(data)
+    }
+}
+
+impl Node {
+    fn matches(&self, data: &Value) -> bool {
+        match self {
+            Node::And(a, b) => a.matches(data) && b.matches(data),
+            Node::Or(a, b) => a.matches(data) || b.matches(data),
+            Node::Compare(path, op, expected) => {
+                let value = path.iter().try_fold(data, |value, key| value.get(key));
+                value.is_some_and(|value| compare(value, *op, expected))
+            }
+        }
+    }
+}
+
+fn compare(value: &Value, op: Op, expected: &Value) -> bool {
+    let ordering = match (value, expected) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().partial_cmp(&b.as_f64()),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (a, b) => match op {
+            Op::Eq => return a == b,
+            Op::Ne => return a != b,
+            _ => None,
+        },
+    };
+    match (ordering, op) {
+        (None, _) => false,
+        (Some(o), Op::Eq) => o == Ordering::Equal,
+        (Some(o), Op::Ne) => o != Ordering::Equal,
+        (Some(o), Op::Lt) => o == Ordering::Less,
+        (Some(o), Op::Le) => o != Ordering::Greater,
+        (Some(o), Op::Gt) => o == Ordering::Greater,
+        (Some(o), Op::Ge) => o != Ordering::Less,
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Field(Vec<String>),
+    Literal(Value),
+    Op(Op),
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+fn tokenize(expr: &str) -> Result<Vec<(Token, usize)>, FilterError> {
+    let bytes = expr.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let start = i;
+        let two = expr.get(i..i + 2).unwrap_or("");
+        let token = match bytes[i] {
+            b' ' | b'\t' | b'\n' => {
+                i += 1;
+                continue;
+            }
+            b'(' => Token::Open,
+            b')' => Token::Close,
+            _ if two == "&&" => Token::And,
+            _ if two == "||" => Token::Or,
+            _ if two == "==" => Token::Op(Op::Eq),
+            _ if two == "!=" => Token::Op(Op::Ne),
+            _ if two == "<=" => Token::Op(Op::Le),
+            _ if two == ">=" => Token::Op(Op::Ge),
+            b'<' => Token::Op(Op::Lt),
+            b'>' => Token::Op(Op::Gt),
+            b'"' => {
+                let len = match expr[i + 1..].find('"') {
+                    Some(len) => len,
+                    None => return error("unterminated string", start),
+                };
+                i += len + 2;
+                tokens.push((Token::Literal(expr[start + 1..i - 1].into()), start));
+                continue;
+            }
+            b'-' | b'0'..=b'9' => {
+                i += 1;
+                while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.')
+                {
+                    i += 1;
+                }
+                match expr[start..i].parse::<f64>() {
+                    Ok(n) => tokens.push((Token::Literal(n.into()), start)),
+                    Err(_) => return error("invalid number", start),
+                }
+                continue;
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                while i < bytes.len()
+                    && (bytes[i].is_ascii_alphanumeric()
+                        || bytes[i] == b'_'
+                        || bytes[i] == b'.')
+                {
+                    i += 1;
+                }
+                let word = &expr[start..i];
+                let token = match word {
+                    "true" => Token::Literal(true.into()),
+                    "false" => Token::Literal(false.into()),
+                    _ if word.split('.').any(str::is_empty) => {
+                        return error("invalid field", start)
+                    }
+                    _ => Token::Field(word.split('.').map(str::to_owned).collect()),
+                };
+                tokens.push((token, start));
+                continue;
+            }
+            _ => return error("unexpected character", start),
+        };
+        i += match token {
+            Token::Open | Token::Close => 1,
+            Token::Op(Op::Lt) | Token::Op(Op::Gt) => 1,
+            _ => 2,
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: &'a [(Token, usize)],
+    pos: usize,
+    /// Where errors at the end of the input are reported
+    end: usize,
+    /// Open parentheses around the current position
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&'a (Token, usize)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self, expected: &str) -> Result<&'a (Token, usize), FilterError> {
+        match self.tokens.get(self.pos) {
+            Some(token) => {
+                self.pos += 1;
+                Ok(token)
+            }
+            None => error(format!("expected {}", expected), self.end),
+        }
+    }
+
+    fn or(&mut self) -> Result<Node, FilterError> {
+        let mut filter = self.and()?;
+        while let Some((Token::Or, _)) = self.peek() {
+            self.pos += 1;
+            filter = Node::Or(Box::new(filter), Box::new(self.and()?));
+        }
+        Ok(filter)
+    }
+
+    fn and(&mut self) -> Result<Node, FilterError> {
+        let mut filter = self.comparison()?;
+        while let Some((Token::And, _)) = self.peek() {
+            self.pos += 1;
+            filter = Node::And(Box::new(filter), Box::new(self.comparison()?));
+        }
+        Ok(filter)
+    }
+
+    fn comparison(&mut self) -> Result<Node, FilterError> {
+        match self.next("a field")? {
+            (Token::Open, at) => {
+                if self.depth == MAX_DEPTH {
+                    return error(
+                        format!("parentheses nested deeper than {}", MAX_DEPTH),
+                        *at,
+                    );
+                }
+                self.depth += 1;
+                let filter = self.or()?;
+                self.depth -= 1;
+                match self.next("`)`")? {
+                    (Token::Close, _) => Ok(filter),
+                    (_, at) => error("expected `)`", *at),
+                }
+            }
+            (Token::Field(path), _) => {
+                let op = match self.next("a comparison")? {
+                    (Token::Op(op), _) => *op,
+                    (_, at) => return error("expected a comparison", *at),
+                };
+                match self.next("a value")? {
+                    (Token::Literal(value), _) => {
+                        Ok(Node::Compare(path.clone(), op, value.clone()))
+                    }
+                    (_, at) => error("expected a value", *at),
+                }
+            }
+            (_, at) => error("expected a field", *at),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_matches() {
+        let filter =
+            Filter::parse(r#"symbol == "ACME" && (price < 50 || volume >= 10000)"#)
+                .unwrap();
+        assert!(filter.matches(&json!({ "symbol": "ACME", "price": 40 })));
+        assert!(
+            filter.matches(&json!({ "symbol": "ACME", "price": 60, "volume": 10000 }))
+        );
+        assert!(!filter.matches(&json!({ "symbol": "ACME", "price": 60 })));
+        assert!(!filter.matches(&json!({ "symbol": "INIT", "price": 40 })));
+    }
+
+    #[test]
+    fn test_depth() {
+        let nested = |depth: usize| {
+            format!("{}price > 1{}", "(".repeat(depth), ")".repeat(depth))
+        };
+        assert!(Filter::parse(&nested(MAX_DEPTH)).is_ok());
+
+        let err = Filter::parse(&nested(MAX_DEPTH + 1)).unwrap_err();
+        assert_eq!(err.to_string(), "parentheses nested deeper than 32 at 32");
+
+        // a frame's worth of `(`, no stack overflow
+        assert!(Filter::parse(&"(".repeat(5000)).is_err());
+    }
+}
@@ -0,0 +1,357 @@
+//! Topics over websockets, with a filter per subscription.
+//!
+//! A client subscribes by sending
+//! `{"action": "subscribe", "topic": "trades", "filter": "price > 100"}`,
+//! the filter is optional, see `filter` for what it can say. The
+//! subscriptions of a connection and their compiled filters live with the
+//! connection, the broker only knows which connections want which topic.
+//! Every event is checked against the connection's filter before it is
+//! sent, clients only get what matches.
+//!
+//! An invalid filter or message is answered with an `error` frame, the
+//! connection stays open.
+//!
+//! A connection leaves the broker when it ends, with a close frame or
+//! without one, see `Closer` and `forward`.
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::future::ok;
+use futures::{stream, SinkExt, StreamExt};
+use ntex::web::{self, middleware, ws, App, Error, HttpRequest, HttpResponse};
+use ntex::{fn_factory_with_config, fn_service};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+mod filter;
+
+use filter::Filter;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Serialize)]
+struct Event {
+    topic: String,
+    data: serde_json::Value,
+}
+
+/// Which connections want which topic, shared by all workers
+#[derive(Default)]
+struct Broker {
+    topics: Mutex<HashMap<String, HashSet<u64>>>,
+    connections: Mutex<HashMap<u64, mpsc::UnboundedSender<Arc<Event>>>>,
+    ids: AtomicU64,
+}
+
+impl Broker {
+    fn connect(&self) -> (u64, mpsc::UnboundedReceiver<Arc<Event>>) {
+        let id = self.ids.fetch_add(1, Ordering::Relaxed) + 1;
+        let (tx, rx) = mpsc::unbounded();
+        self.connections.lock().unwrap().insert(id, tx);
+        (id, rx)
+    }
+
+    fn disconnect(&self, id: u64) {
+        self.connections.lock().unwrap().remove(&id);
+        let mut topics = self.topics.lock().unwrap();
+        topics.values_mut().for_each(|ids| {
+            ids.remove(&id);
+        });
+        topics.retain(|_, ids| !ids.is_empty());
+    }
+
+    fn subscribe(&self, id: u64, topic: &str) {
+        let mut topics = self.topics.lock().unwrap();
+        topics.entry(topic.to_owned()).or_default().insert(id);
+    }
+
+    fn unsubscribe(&self, id: u64, topic: &str) {
+        if let Some(ids) = self.topics.lock().unwrap().get_mut(topic) {
+            ids.remove(&id);
+        }
+    }
+
+    /// Sends the event to every subscriber of its topic, returns how many
+    /// there are. Filtering happens on each connection
+    fn publish(&self, event: Event) -> usize {
+        let ids = match self.topics.lock().unwrap().get(&event.topic) {
+            Some(ids) => ids.clone(),
+            None => return 0,
+        };
+        let event = Arc::new(event);
+        let connections = self.connections.lock().unwrap();
+        for id in &ids {
+            if let Some(tx) = connections.get(id) {
+                let _ = tx.unbounded_send(event.clone());
+            }
+        }
+        ids.len()
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+enum Incoming {
+    Subscribe {
+        topic: String,
+        filter: Option<String>,
+    },
+    Unsubscribe {
+        topic: String,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Outgoing<'a> {
+    Subscribed {
+        topic: &'a str,
+        filter: Option<&'a str>,
+    },
+    Unsubscribed {
+        topic: &'a str,
+    },
+    Event(&'a Event),
+    Error {
+        message: String,
+    },
+}
+
+impl Outgoing<'_> {
+    fn message(&self) -> ws::Message {
+        ws::Message::Text(serde_json::to_string(self).unwrap())
+    }
+}
+
+/// The topics of one connection, with their filters
+type Subscriptions = RefCell<HashMap<String, Option<Filter>>>;
+
+/// Leaves the broker, exactly once
+struct Connection {
+    broker: web::types::Data<Broker>,
+    id: u64,
+    subscriptions: Subscriptions,
+    closed: Cell<bool>,
+}
+
+impl Connection {
+    fn close(&self, reason: &str) {
+        if !self.closed.replace(true) {
+            log::info!("connection {} closed: {}", self.id, reason);
+            self.broker.disconnect(self.id);
+        }
+    }
+
+    fn handle(&self, text: &str) -> ws::Message {
+        let msg = match serde_json::from_str(text) {
+            Ok(msg) => msg,
+            Err(e) => {
+                return Outgoing::Error {
+                    message: format!("invalid message: {}", e),
+                }
+                .message()
+            }
+        };
+        match msg {
+            Incoming::Subscribe { topic, filter } => {
+                let compiled = match filter.as_deref().map(Filter::parse).transpose() {
+                    Ok(compiled) => compiled,
+                    Err(e) => {
+                        return Outgoing::Error {
+                            message: format!(
+                                "invalid filter `{}`: {}",
+                                filter.unwrap_or_default(),
+                                e
+                            ),
+                        }
+                        .message()
+                    }
+                };
+                log::info!(
+                    "connection {} subscribed to {} where {}",
+                    self.id,
+                    topic,
+                    filter.as_deref().unwrap_or("true")
+                );
+                self.broker.subscribe(self.id, &topic);
+                self.subscriptions
+                    .borrow_mut()
+                    .insert(topic.clone(), compiled);
+                Outgoing::Subscribed {
+                    topic: &topic,
+                    filter: filter.as_deref(),
+                }
+                .message()
+            }
+            Incoming::Unsubscribe { topic } => {
+                self.broker.unsubscribe(self.id, &topic);
+                self.subscriptions.borrow_mut().remove(&topic);
+                Outgoing::Unsubscribed { topic: &topic }.message()
+            }
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.close("dropped");
+    }
+}
+
+/// Held by the connection's ws service only. `forward` keeps the
+/// `Connection` alive while the broker can still send to it, the service
+/// is dropped when the connection ends, with a close frame or without one
+struct Closer(Rc<Connection>);
+
+impl Drop for Closer {
+    fn drop(&mut self) {
+        self.0.close("connection ended");
+    }
+}
+
+/// What `forward` writes next
+enum Next {
+    Event(Arc<Event>),
+    Heartbeat,
+}
+
+/// Sends the events that pass the connection's filter for their topic, and
+/// a ping every `HEARTBEAT_INTERVAL`. A client that went away without a
+/// close frame is only noticed when something is written to it, the pings
+/// make sure that happens even on topics where nothing matches
+async fn forward(
+    mut sink: ws::WebSocketsSink,
+    events: mpsc::UnboundedReceiver<Arc<Event>>,
+    conn: Rc<Connection>,
+) {
+    let ticks = stream::unfold(
+        ntex::rt::time::interval(HEARTBEAT_INTERVAL),
+        |mut interval| async {
+            interval.tick().await;
+            Some((Next::Heartbeat, interval))
+        },
+    );
+    let mut outgoing = stream::select(events.map(Next::Event), Box::pin(ticks));
+
+    while let Some(item) = outgoing.next().await {
+        let msg = match item {
+            Next::Heartbeat if conn.closed.get() => break,
+            Next::Heartbeat => ws::Message::Ping(Bytes::new()),
+            Next::Event(event) => {
+                let matches = match conn.subscriptions.borrow().get(&event.topic) {
+                    Some(Some(filter)) => filter.matches(&event.data),
+                    Some(None) => true,
+                    // unsubscribed while it was on its way
+                    None => false,
+                };
+                if !matches {
+                    continue;
+                }
+                Outgoing::Event(&event).message()
+            }
+        };
+        if sink.send(Ok(msg)).await.is_err() {
+            conn.close("connection lost");
+            break;
+        }
+    }
+}
+
+async fn ws_index(
+    req: HttpRequest,
+    payload: web::types::Payload,
+    broker: web::types::Data<Broker>,
+) -> Result<HttpResponse, Error> {
+    let (id, events) = broker.connect();
+    let conn = Rc::new(Connection {
+        broker: broker.clone(),
+        id,
+        subscriptions: RefCell::new(HashMap::new()),
+        closed: Cell::new(false),
+    });
+    // the factory is a `Fn`, but it is only called once per websocket
+    let state = RefCell::new(Some((events, conn)));
+
+    ws::start(
+        req,
+        payload,
+        fn_factory_with_config(move |sink: ws::WebSocketsSink| {
+            let (events, conn) = state.borrow_mut().take().unwrap();
+            ntex::rt::spawn(forward(sink, events, conn.clone()));
+            let closer = Rc::new(Closer(conn));
+
+            ok::<_, Error>(fn_service(move |frame| {
+                let conn = &closer.0;
+                let item = match frame {
+                    ws::Frame::Text(text) => {
+                        Some(conn.handle(&String::from_utf8_lossy(&text)))
+                    }
+                    ws::Frame::Ping(msg) => Some(ws::Message::Pong(msg)),
+                    ws::Frame::Close(reason) => {
+                        conn.close("closed by client");
+                        Some(ws::Message::Close(reason))
+                    }
+                    _ => None,
+                };
+                ok::<_, std::io::Error>(item)
+            }))
+        }),
+    )
+    .await
+}
+
+/// Publishes the JSON body to `topic`
+async fn publish(
+    topic: web::types::Path<String>,
+    data: web::types::Json<serde_json::Value>,
+    broker: web::types::Data<Broker>,
+) -> HttpResponse {
+    let subscribers = broker.publish(Event {
+        topic: topic.into_inner(),
+        data: data.into_inner(),
+    });
+    HttpResponse::Ok().json(&serde_json::json!({ "subscribers": subscribers }))
+}
+
+/// Made up trades on `trades`, a few per second
+async fn trades(broker: web::types::Data<Broker>) {
+    let mut interval = ntex::rt::time::interval(Duration::from_millis(500));
+    loop {
+        interval.tick().await;
+        let (symbol, price, volume) = {
+            let mut rng = rand::thread_rng();
+            let symbol = ["ACME", "GLOBEX", "INITECH"][rng.gen_range(0, 3)];
+            (symbol, rng.gen_range(20, 200), rng.gen_range(1, 50) * 100)
+        };
+        broker.publish(Event {
+            topic: "trades".to_owned(),
+            data: serde_json::json!({ "symbol": symbol, "price": price, "volume": volume }),
+        });
+    }
+}
+
+#[ntex::main]
+async fn main() -> std::io::Result<()> {
+    std::env::set_var("RUST_LOG", "ntex=info,ws_topics=info");
+    env_logger::init();
+
+    let broker = web::types::Data::new(Broker::default());
+    ntex::rt::spawn(trades(broker.clone()));
+
+    web::server(move || {
+        App::new()
+            .app_data(broker.clone())
+            .wrap(middleware::Logger::default())
+            .route("/ws", web::get().to(ws_index))
+            .route("/publish/{topic}", web::post().to(publish))
+    })
+    .bind("127.0.0.1:8080")?
+    .run()
+    .await
+}